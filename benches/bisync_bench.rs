@@ -24,6 +24,7 @@ fn make_file_entry(path: &str, size: u64, mtime_secs_ago: u64) -> FileEntry {
         nlink: 1,
         acls: None,
         bsd_flags: None,
+        win_attrs: None,
     }
 }
 
@@ -0,0 +1,81 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sy::delta::Adler32;
+
+fn generate_data(size: usize) -> Vec<u8> {
+    // Cheap LCG so the data isn't trivially compressible or periodic
+    let mut state = 0x2545_F491u32;
+    (0..size)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+/// Byte-at-a-time Adler-32, deferring the modulo over zlib's 5552-byte
+/// chunks: the baseline the vectorized hash is measured against
+fn hash_scalar(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Whole-block hashing (checksum computation, window re-initialization)
+fn bench_block_hash(c: &mut Criterion) {
+    let mut group = c.benchmark_group("adler32_block_hash");
+
+    for size in [4 * 1024, 128 * 1024, 1024 * 1024] {
+        let data = generate_data(size);
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("scalar", size), &data, |b, data| {
+            b.iter(|| hash_scalar(black_box(data)));
+        });
+
+        group.bench_with_input(BenchmarkId::new("vectorized", size), &data, |b, data| {
+            b.iter(|| Adler32::hash(black_box(data)));
+        });
+    }
+
+    group.finish();
+}
+
+/// Sliding the window one byte at a time (delta generation inner loop)
+fn bench_roll(c: &mut Criterion) {
+    let mut group = c.benchmark_group("adler32_roll");
+    let size = 4 * 1024 * 1024;
+    let data = generate_data(size);
+
+    for block_size in [2048usize, 128 * 1024] {
+        group.throughput(Throughput::Bytes((size - block_size) as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(block_size),
+            &block_size,
+            |b, &block_size| {
+                b.iter(|| {
+                    let mut hasher = Adler32::new(block_size);
+                    hasher.update_block(&data[..block_size]);
+                    let mut acc = 0u32;
+                    for (&old, &new) in data.iter().zip(&data[block_size..]) {
+                        hasher.roll(old, new);
+                        acc ^= hasher.digest();
+                    }
+                    black_box(acc)
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_block_hash, bench_roll);
+criterion_main!(benches);
@@ -39,6 +39,7 @@ fn bench_deletion_planning(c: &mut Criterion) {
                         nlink: 1,
                         acls: None,
                         bsd_flags: None,
+                        win_attrs: None,
                     })
                     .collect();
 
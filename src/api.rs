@@ -0,0 +1,530 @@
+//! Programmatic API for embedding sy.
+//!
+//! GUI frontends and other Rust tools can run a sync in-process instead of
+//! shelling out to the binary and parsing its output:
+//!
+//! ```no_run
+//! # async fn example() -> sy::error::Result<()> {
+//! let stats = sy::Sync::builder()
+//!     .source("/data/photos/")
+//!     .dest("/backup/photos")
+//!     .delete(true)
+//!     .on_progress(|p| println!("{}/{} files", p.files_done, p.files_total))
+//!     .run()
+//!     .await?;
+//! println!("{} created, {} updated", stats.files_created, stats.files_updated);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Paths take the same forms as on the command line (`user@host:/path`,
+//! `s3://bucket/key`, ...), including rsync trailing slash semantics. Nothing
+//! is printed; progress goes to the callback and the outcome to the returned
+//! `SyncStats`.
+//!
+//! Async consumers that drive their own rendering can take a stream of
+//! [`SyncEvent`]s instead:
+//!
+//! ```no_run
+//! # async fn example() {
+//! use futures::StreamExt;
+//! use sy::SyncEvent;
+//!
+//! let mut events = sy::Sync::builder()
+//!     .source("/data/photos/")
+//!     .dest("/backup/photos")
+//!     .run_with_events();
+//! while let Some(event) = events.next().await {
+//!     match event {
+//!         SyncEvent::FileDone(p) => println!("{}", p.path.display()),
+//!         SyncEvent::Done(result) => println!("{:?}", result.map(|s| s.files_created)),
+//!         _ => {}
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::cli::SymlinkMode;
+use crate::config::SshSettings;
+use crate::error::{Result, SyncError};
+use crate::filter::{EntryMetadata, FilterCallback, FilterDecision, FilterEngine};
+use crate::integrity::ChecksumType;
+use crate::metrics::{MetricsCallback, MetricsSnapshot};
+use crate::path::SyncPath;
+use crate::retry::RetryConfig;
+use crate::sync::progress::{EventSender, Progress, ProgressCallback, SyncEvent};
+use crate::sync::scanner::ScanOptions;
+use crate::sync::{SyncEngine, SyncStats};
+use crate::transport::router::TransportRouter;
+use futures::Stream;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// Entry point for the programmatic API; see [`Sync::builder`]
+pub struct Sync;
+
+impl Sync {
+    /// Start configuring a sync
+    pub fn builder() -> SyncBuilder {
+        SyncBuilder::default()
+    }
+}
+
+/// Options for a sync run, with the CLI's defaults
+pub struct SyncBuilder {
+    source: Option<String>,
+    dest: Option<String>,
+    delete: bool,
+    delete_threshold: u8,
+    force_delete: bool,
+    dry_run: bool,
+    checksum: bool,
+    verify: bool,
+    parallel: usize,
+    max_errors: usize,
+    excludes: Vec<String>,
+    filter: Option<FilterCallback>,
+    on_progress: Option<ProgressCallback>,
+    on_metrics: Option<MetricsCallback>,
+    cancel: Option<CancellationToken>,
+}
+
+impl Default for SyncBuilder {
+    fn default() -> Self {
+        Self {
+            source: None,
+            dest: None,
+            delete: false,
+            delete_threshold: 50,
+            force_delete: false,
+            dry_run: false,
+            checksum: false,
+            verify: false,
+            parallel: 10,
+            max_errors: 100,
+            excludes: Vec::new(),
+            filter: None,
+            on_progress: None,
+            on_metrics: None,
+            cancel: None,
+        }
+    }
+}
+
+impl SyncBuilder {
+    /// Source path (required)
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Destination path (required)
+    pub fn dest(mut self, dest: impl Into<String>) -> Self {
+        self.dest = Some(dest.into());
+        self
+    }
+
+    /// Delete destination files that aren't in the source (`--delete`)
+    pub fn delete(mut self, delete: bool) -> Self {
+        self.delete = delete;
+        self
+    }
+
+    /// Refuse to delete more than this percentage of destination files
+    /// (`--delete-threshold`, default 50)
+    pub fn delete_threshold(mut self, percent: u8) -> Self {
+        self.delete_threshold = percent.min(100);
+        self
+    }
+
+    /// Skip the deletion safety checks (`--force-delete`)
+    pub fn force_delete(mut self, force: bool) -> Self {
+        self.force_delete = force;
+        self
+    }
+
+    /// Plan the sync without changing anything (`--dry-run`)
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Compare files by checksum instead of size and mtime (`--checksum`)
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Read back and verify each file after writing it (`--verify`)
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Number of files transferred concurrently (`-j`)
+    pub fn parallel(mut self, workers: usize) -> Self {
+        self.parallel = workers.max(1);
+        self
+    }
+
+    /// Abort after this many errors (`--max-errors`, 0 for unlimited)
+    pub fn max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Skip paths matching a glob pattern (`--exclude`); may be repeated
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.excludes.push(pattern.into());
+        self
+    }
+
+    /// Decide per scanned entry whether to sync it, for selection logic glob
+    /// patterns can't express
+    ///
+    /// Called with the path relative to the source root, for entries the
+    /// exclude patterns keep. Excluding a directory skips everything below it.
+    pub fn filter<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Path, &EntryMetadata) -> FilterDecision + Send + std::marker::Sync + 'static,
+    {
+        self.filter = Some(Arc::new(callback));
+        self
+    }
+
+    /// Call `callback` after each file is handled
+    ///
+    /// It runs on the transfer tasks, so it should return quickly.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Progress) + Send + std::marker::Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Call `callback` with the metrics of the run when it finishes
+    ///
+    /// Runs are also recorded into [`crate::metrics::global`], whose
+    /// `render_prometheus()` suits a `/metrics` endpoint of the host app.
+    pub fn on_metrics<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&MetricsSnapshot) + Send + std::marker::Sync + 'static,
+    {
+        self.on_metrics = Some(Arc::new(callback));
+        self
+    }
+
+    /// Stop the sync when `token` is cancelled
+    ///
+    /// Transfers in flight are dropped along with their temp files and the
+    /// run returns `Ok` with `SyncStats::cancelled` set.
+    pub fn cancel(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Run the sync to completion
+    pub async fn run(self) -> Result<SyncStats> {
+        self.execute(None).await
+    }
+
+    /// Run the sync on a background task, reporting its progress as a stream
+    ///
+    /// The stream ends with [`SyncEvent::Done`]. Per-file events are sent for
+    /// directory syncs; a single-file sync only reports `Done`. Dropping the
+    /// stream doesn't stop the sync. Must be called within a Tokio runtime.
+    pub fn run_with_events(self) -> impl Stream<Item = SyncEvent> + Unpin {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let result = self.execute(Some(tx.clone())).await;
+            let _ = tx.send(SyncEvent::Done(result));
+        });
+        futures::stream::poll_fn(move |cx| rx.poll_recv(cx))
+    }
+
+    async fn execute(self, events: Option<EventSender>) -> Result<SyncStats> {
+        let source = SyncPath::parse(
+            self.source
+                .as_deref()
+                .ok_or_else(|| SyncError::Config("source path is required".into()))?,
+        );
+        let dest = SyncPath::parse(
+            self.dest
+                .as_deref()
+                .ok_or_else(|| SyncError::Config("destination path is required".into()))?,
+        );
+
+        let mut filter_engine = FilterEngine::new();
+        for pattern in &self.excludes {
+            filter_engine
+                .add_exclude(pattern)
+                .map_err(|e| SyncError::Config(format!("Invalid exclude pattern: {}", e)))?;
+        }
+
+        let checksum_type = if self.verify {
+            ChecksumType::Fast
+        } else {
+            ChecksumType::None
+        };
+        let transport = TransportRouter::new(
+            &source,
+            &dest,
+            checksum_type,
+            false,
+            self.parallel,
+            RetryConfig::default(),
+            &SshSettings::default(),
+        )
+        .await?
+        .with_scan_options(ScanOptions::default());
+
+        let mut engine = SyncEngine::new(
+            transport,
+            self.dry_run,
+            false, // diff_mode
+            self.delete,
+            self.delete_threshold,
+            false, // trash
+            self.force_delete,
+            true, // quiet
+            self.parallel,
+            self.max_errors,
+            None, // min_size
+            None, // max_size
+            filter_engine,
+            None,              // bwlimit
+            false,             // resume
+            100,               // checkpoint_files
+            100 * 1024 * 1024, // checkpoint_bytes
+            false,             // json
+            checksum_type,
+            false, // verify_on_write
+            0,     // verify_retries
+            None,  // verify_sample
+            SymlinkMode::Preserve,
+            false, // preserve_xattrs
+            false, // preserve_hardlinks
+            false, // preserve_acls
+            false, // preserve_flags
+            false, // per_file_progress
+            false, // ignore_times
+            false, // size_only
+            self.checksum,
+            false, // update_only
+            false, // ignore_existing
+            false, // use_cache
+            false, // clear_cache
+            false, // checksum_db
+            false, // clear_checksum_db
+            false, // prune_checksum_db
+            dest.is_remote(),
+            false, // perf
+        );
+        if let Some(callback) = self.filter {
+            engine = engine.with_filter(callback);
+        }
+        if let Some(callback) = self.on_progress {
+            engine = engine.with_progress(callback);
+        }
+        if let Some(events) = events {
+            engine = engine.with_events(events);
+        }
+        if let Some(callback) = self.on_metrics {
+            engine = engine.with_metrics(callback);
+        }
+        if let Some(token) = self.cancel {
+            engine = engine.with_cancel(token);
+        }
+
+        if source.is_local() && source.path().is_file() {
+            engine.sync_single_file(source.path(), dest.path()).await
+        } else {
+            engine
+                .sync(source.path(), &source.effective_destination(&dest))
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_builder_syncs_with_progress() {
+        let src = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        std::fs::write(src.path().join("a.txt"), "alpha").unwrap();
+        std::fs::create_dir(src.path().join("sub")).unwrap();
+        std::fs::write(src.path().join("sub/b.txt"), "beta").unwrap();
+        std::fs::write(src.path().join("skip.log"), "noise").unwrap();
+        std::fs::write(dest.path().join("stale.txt"), "old").unwrap();
+
+        let calls = Arc::new(AtomicU64::new(0));
+        let seen = Arc::clone(&calls);
+        let stats = Sync::builder()
+            .source(format!("{}/", src.path().display()))
+            .dest(dest.path().display().to_string())
+            .delete(true)
+            .force_delete(true)
+            .exclude("*.log")
+            .on_progress(move |p| {
+                assert!(p.files_done <= p.files_total);
+                seen.fetch_add(1, Ordering::Relaxed);
+            })
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dest.path().join("sub/b.txt")).unwrap(),
+            "beta"
+        );
+        assert!(dest.path().join("a.txt").exists());
+        assert!(!dest.path().join("skip.log").exists());
+        assert!(!dest.path().join("stale.txt").exists());
+        assert_eq!(stats.files_deleted, 1);
+        assert!(calls.load(Ordering::Relaxed) >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_builder_filter_callback() {
+        let src = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        std::fs::write(src.path().join("small.txt"), "tiny").unwrap();
+        std::fs::write(src.path().join("large.txt"), "x".repeat(1000)).unwrap();
+        std::fs::create_dir(src.path().join("private")).unwrap();
+        std::fs::write(src.path().join("private/tiny.txt"), "hidden").unwrap();
+
+        // Only files over 100 bytes, and nothing under private/
+        let stats = Sync::builder()
+            .source(format!("{}/", src.path().display()))
+            .dest(dest.path().display().to_string())
+            .filter(|path, meta| {
+                if path.starts_with("private") || (!meta.is_dir && meta.size < 100) {
+                    FilterDecision::Exclude
+                } else {
+                    FilterDecision::Include
+                }
+            })
+            .run()
+            .await
+            .unwrap();
+
+        assert!(dest.path().join("large.txt").exists());
+        assert!(!dest.path().join("small.txt").exists());
+        assert!(!dest.path().join("private").exists());
+        assert_eq!(stats.files_created, 1);
+    }
+
+    #[tokio::test]
+    async fn test_builder_cancel() {
+        let src = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        for i in 0..20 {
+            std::fs::write(src.path().join(format!("{}.txt", i)), "data").unwrap();
+        }
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let stats = Sync::builder()
+            .source(format!("{}/", src.path().display()))
+            .dest(dest.path().display().to_string())
+            .cancel(token)
+            .run()
+            .await
+            .unwrap();
+
+        assert!(stats.cancelled);
+        assert!(stats.files_created < 20);
+    }
+
+    #[tokio::test]
+    async fn test_builder_on_metrics() {
+        let src = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        std::fs::write(src.path().join("a.txt"), "alpha").unwrap();
+        std::fs::write(src.path().join("b.txt"), "beta").unwrap();
+
+        let runs = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&runs);
+        Sync::builder()
+            .source(format!("{}/", src.path().display()))
+            .dest(dest.path().display().to_string())
+            .on_metrics(move |m| seen.lock().unwrap().push(m.clone()))
+            .run()
+            .await
+            .unwrap();
+
+        let runs = runs.lock().unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].syncs_ok, 1);
+        assert_eq!(runs[0].files_created, 2);
+        assert_eq!(runs[0].bytes_received, 9);
+        assert_eq!(runs[0].bytes_sent, 0);
+        assert!(runs[0].total_time >= runs[0].transfer_time);
+        assert!(crate::metrics::global().snapshot().syncs_ok >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_with_events() {
+        use futures::StreamExt;
+
+        let src = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        std::fs::write(src.path().join("a.txt"), "alpha").unwrap();
+        std::fs::write(src.path().join("b.txt"), "beta").unwrap();
+        std::fs::write(dest.path().join("stale.txt"), "old").unwrap();
+
+        let events: Vec<SyncEvent> = Sync::builder()
+            .source(format!("{}/", src.path().display()))
+            .dest(dest.path().display().to_string())
+            .delete(true)
+            .force_delete(true)
+            .run_with_events()
+            .collect()
+            .await;
+
+        assert!(matches!(
+            events.first(),
+            Some(SyncEvent::Scanned { files_total: 3, .. })
+        ));
+        let started = events
+            .iter()
+            .filter(|e| matches!(e, SyncEvent::FileStarted { .. }))
+            .count();
+        let done = events
+            .iter()
+            .filter(|e| matches!(e, SyncEvent::FileDone(_)))
+            .count();
+        assert_eq!((started, done), (2, 2));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, SyncEvent::Deleted { path } if path.ends_with("stale.txt"))));
+        match events.last() {
+            Some(SyncEvent::Done(Ok(stats))) => assert_eq!(stats.files_created, 2),
+            other => panic!("expected Done, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_with_events_reports_failure() {
+        use futures::StreamExt;
+
+        let events: Vec<SyncEvent> = Sync::builder().run_with_events().collect().await;
+        assert!(matches!(
+            events.as_slice(),
+            [SyncEvent::Done(Err(SyncError::Config(_)))]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_builder_requires_paths() {
+        let err = Sync::builder().dest("/tmp").run().await.unwrap_err();
+        assert!(matches!(err, SyncError::Config(_)));
+    }
+}
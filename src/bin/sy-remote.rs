@@ -73,6 +73,14 @@ enum Commands {
         #[arg(long)]
         mtime: Option<u64>,
     },
+    /// Set an extended attribute, reading its value from stdin
+    SetXattr {
+        /// File to set the attribute on
+        path: PathBuf,
+        /// Attribute name as read on the source
+        #[arg(long)]
+        name: String,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -317,6 +325,18 @@ fn main() -> anyhow::Result<()> {
                 data_regions.len()
             );
         }
+        Commands::SetXattr { path, name } => {
+            let mut value = Vec::new();
+            std::io::stdin().read_to_end(&mut value)?;
+
+            #[cfg(unix)]
+            sy::fs_util::set_xattr(&path, &name, &value)?;
+            #[cfg(not(unix))]
+            {
+                let _ = (path, name, value);
+                anyhow::bail!("extended attributes are not supported on this platform");
+            }
+        }
     }
 
     Ok(())
@@ -262,6 +262,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         }
     }
 
@@ -281,6 +281,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         }
     }
 
@@ -1,5 +1,6 @@
 use crate::path::SyncPath;
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::{Component, Path, PathBuf};
 
 // Import integrity types for verification modes
 use crate::integrity::ChecksumType;
@@ -7,12 +8,48 @@ use crate::integrity::ChecksumType;
 // Import compression types for detection modes
 use crate::compress::CompressionDetection;
 
+use crate::config::{DenySettings, HostKeyPolicy, SshSettings};
+use crate::streaming::{
+    Backup, DeleteTiming, FsyncPolicy, IdMap, IoOptions, OwnerMap, ReceivePolicy, TransferLog,
+};
+use crate::sync::namecrypt::{NameCipher, NameCrypt};
 use crate::sync::scanner::ScanOptions;
+use anyhow::Context;
 
 fn parse_sync_path(s: &str) -> Result<SyncPath, String> {
     Ok(SyncPath::parse(s))
 }
 
+fn parse_id_map(s: &str) -> Result<IdMap, String> {
+    s.parse()
+}
+
+/// Parse a percentage like "10" or "2.5%" (must be in (0, 100])
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let num = s.trim().trim_end_matches('%').trim();
+    let pct: f64 = num
+        .parse()
+        .map_err(|e| format!("Invalid percentage '{}': {}", s, e))?;
+    if !(pct > 0.0 && pct <= 100.0) {
+        return Err(format!("Percentage must be between 0 and 100, got {}", s));
+    }
+    Ok(pct)
+}
+
+/// Parse a delta block size: a size from 512 bytes to 128KB
+fn parse_block_size(s: &str) -> Result<u32, String> {
+    use crate::delta::{MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+
+    let size = parse_size(s)?;
+    if !(MIN_BLOCK_SIZE as u64..=MAX_BLOCK_SIZE as u64).contains(&size) {
+        return Err(format!(
+            "Block size must be between 512B and 128KB, got {}",
+            s
+        ));
+    }
+    Ok(size as u32)
+}
+
 pub fn parse_size(s: &str) -> Result<u64, String> {
     let s = s.trim().to_uppercase();
 
@@ -85,6 +122,158 @@ pub enum SymlinkMode {
     Skip,
 }
 
+/// When local copies share blocks with their source (`--reflink`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReflinkMode {
+    /// Clone on Btrfs/XFS/APFS when both sides share a filesystem, else copy (default)
+    #[default]
+    Auto,
+
+    /// Clone every file, failing where the filesystem can't
+    Always,
+
+    /// Always copy the bytes
+    Never,
+}
+
+/// Which file times a sync preserves (`--times`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimesMode {
+    /// Modification times only (default)
+    #[default]
+    Mtime,
+
+    /// Access and (where the filesystem keeps one) creation times too
+    All,
+}
+
+/// Standalone commands that don't perform a sync
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Create or verify a directory manifest
+    #[command(subcommand)]
+    Manifest(ManifestCommand),
+
+    /// Re-read every file in DIR and check it against the checksum database
+    /// to detect bitrot (content changed while size and mtime did not)
+    Scrub {
+        /// Directory to scrub (database lives in DIR/.sy-checksums)
+        dir: PathBuf,
+
+        /// Limit read rate (e.g., "50MB") to run as a background job
+        #[arg(long, value_parser = parse_size)]
+        bwlimit: Option<u64>,
+    },
+
+    /// Browse a remote tree read-only through FUSE until interrupted
+    /// (requires the 'mount' feature)
+    Mount {
+        /// Tree to show (user@host:/path)
+        #[arg(value_parser = parse_sync_path)]
+        remote: SyncPath,
+
+        /// Empty directory to mount it on
+        mountpoint: PathBuf,
+    },
+
+    /// Show past runs: when each started, how it ended and what it moved
+    History {
+        /// Print the runs as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Show only the most recent N runs
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+
+    /// Record a snapshot of SOURCE in a deduplicating store
+    Push {
+        /// Directory to snapshot
+        source: PathBuf,
+
+        /// Store file contents as content-addressed chunks plus a tree
+        /// manifest per snapshot in DIR (created if missing)
+        #[arg(long, value_name = "DIR")]
+        dedup_store: PathBuf,
+    },
+
+    /// Write a snapshot from a --dedup-store back out as a normal tree
+    Restore {
+        /// Store to read from
+        store: PathBuf,
+
+        /// Directory to restore into
+        target: PathBuf,
+
+        /// Snapshot to restore (default: the latest)
+        #[arg(long)]
+        snapshot: Option<String>,
+    },
+
+    /// Time synthetic transfers to a host over the real protocol and
+    /// suggest tuning (writes to REMOTE/.sy-bench)
+    Bench {
+        /// Where to transfer to (user@host:, user@host:/path or a local path)
+        #[arg(value_parser = parse_sync_path)]
+        remote: SyncPath,
+
+        /// Size of the large-file workload
+        #[arg(long, value_parser = parse_size, default_value = "256MB")]
+        size: u64,
+
+        /// Number of files in the small-files workload
+        #[arg(long, default_value_t = 2000)]
+        files: usize,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum ManifestCommand {
+    /// Print a JSON manifest (path, size, mtime, BLAKE3) of every file in DIR
+    Create {
+        /// Directory to snapshot
+        dir: PathBuf,
+
+        /// Write the manifest to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Check DIR against a manifest (exit 1 on differences)
+    Verify {
+        /// Directory to check
+        dir: PathBuf,
+
+        /// Manifest file produced by `sy manifest create`
+        manifest: PathBuf,
+
+        /// Public key; requires a valid signature before checking the tree
+        #[arg(long)]
+        key: Option<PathBuf>,
+
+        /// Detached signature file (default: <MANIFEST>.sig)
+        #[arg(long, requires = "key")]
+        signature: Option<PathBuf>,
+    },
+
+    /// Sign a manifest with an ed25519 secret key (writes <MANIFEST>.sig)
+    Sign {
+        /// Manifest file to sign
+        manifest: PathBuf,
+
+        /// Secret key created by `sy manifest keygen`
+        #[arg(long)]
+        key: PathBuf,
+    },
+
+    /// Generate an ed25519 signing key (secret at PATH, public at PATH.pub)
+    Keygen {
+        /// Where to write the secret key
+        path: PathBuf,
+    },
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "sy")]
 #[command(about = "Modern file synchronization tool", long_about = None)]
@@ -132,8 +321,43 @@ pub enum SymlinkMode {
     sy /source user@host:/dest --resume-only    # Only resume, don't start new transfers
     sy /source user@host:/dest --clear-resume-state  # Clear all resume state
 
+    # Snapshot a tree and validate a copy later
+    sy manifest create /backup > backup.json
+    sy manifest verify /offsite/backup backup.json
+
+    # Tamper-evident manifests
+    sy manifest keygen ~/.sy/manifest.key
+    sy manifest sign backup.json --key ~/.sy/manifest.key
+    sy manifest verify /offsite/backup backup.json --key ~/.sy/manifest.key.pub
+
+    # Detect bitrot (low-priority background scrub)
+    sy scrub /backup --bwlimit 20MB
+
+    # Run an existing rsync command line with sy
+    sy rsync -avz --delete --exclude '*.tmp' /src/ user@host:/dest/
+
+    # Browse a remote backup without pulling it
+    sy mount user@host:/backup /mnt/backup
+
+    # When did the nightly backup last succeed?
+    sy history -n 20
+
+    # Deduplicated snapshots, and getting one back
+    sy push /data --dedup-store /backup/store
+    sy restore /backup/store /restore/data
+
+    # Is it the network or sy?
+    sy bench user@host:
+
+    # Serve the [daemon.modules] in the config file over TCP
+    sy --daemon --listen 0.0.0.0:8730
+
 For more information: https://github.com/nijaru/sy")]
 pub struct Cli {
+    /// Standalone command (omit for a regular sync)
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Source path (local: /path or remote: user@host:/path)
     /// Optional when using --profile
     #[arg(value_parser = parse_sync_path)]
@@ -148,15 +372,48 @@ pub struct Cli {
     #[arg(short = 'n', long)]
     pub dry_run: bool,
 
+    /// Only compare source and destination (by size and mtime, no file
+    /// contents are read) and print how much would transfer and how long
+    /// it would take
+    #[arg(long, conflicts_with_all = ["watch", "bidirectional", "verify_only", "tui"])]
+    pub estimate: bool,
+
+    /// Bandwidth the --estimate time assumes (e.g., "100MB"; default:
+    /// --bwlimit, or 1 Gbit/s)
+    #[arg(long, value_parser = parse_size, requires = "estimate")]
+    pub estimate_rate: Option<u64>,
+
     /// Show detailed changes in dry-run mode (file sizes, byte changes)
     /// Requires --dry-run to be effective
     #[arg(long)]
     pub diff: bool,
 
     /// Delete files in destination not present in source
-    #[arg(short, long)]
+    #[arg(short, long, default_value_ifs = [
+        ("delete_before", "true", Some("true")),
+        ("delete_during", "true", Some("true")),
+        ("delete_after", "true", Some("true")),
+    ])]
     pub delete: bool,
 
+    /// Delete before sending any file, freeing space first (implies --delete)
+    #[arg(long, conflicts_with_all = ["delete_during", "delete_after"])]
+    pub delete_before: bool,
+
+    /// Delete each directory's extraneous entries as the sync reaches the
+    /// directory (implies --delete)
+    #[arg(long, conflicts_with = "delete_after")]
+    pub delete_during: bool,
+
+    /// Delete once every file is sent, the default (implies --delete)
+    #[arg(long)]
+    pub delete_after: bool,
+
+    /// Abort before deleting anything if more than N entries would be
+    /// deleted, e.g. because the source is empty or not mounted
+    #[arg(long, value_name = "N", conflicts_with = "bidirectional")]
+    pub max_delete_count: Option<u64>,
+
     /// Maximum percentage of files that can be deleted (0-100, default: 50)
     /// Prevents accidental mass deletion
     #[arg(long, default_value = "50")]
@@ -182,6 +439,16 @@ pub struct Cli {
     #[arg(long)]
     pub perf: bool,
 
+    /// Serve Prometheus metrics at http://ADDR/metrics while sy runs
+    /// (e.g. 127.0.0.1:9184; most useful with --watch)
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Export tracing spans to an OpenTelemetry collector over OTLP/HTTP
+    /// (e.g. http://localhost:4318; requires a build with the otel feature)
+    #[arg(long, value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+
     /// Show progress bar for each large file (>= 1MB) being transferred
     /// Automatically hidden when output is piped or with --quiet
     #[arg(long)]
@@ -232,7 +499,8 @@ pub struct Cli {
     #[arg(long)]
     pub ignore_template: Vec<String>,
 
-    /// Bandwidth limit in bytes per second (e.g., "1MB", "500KB")
+    /// Bandwidth limit in bytes per second (e.g., "500K", "10M", "1G");
+    /// over SSH it caps pushes and pulls alike
     #[arg(long, value_parser = parse_size)]
     pub bwlimit: Option<u64>,
 
@@ -240,7 +508,8 @@ pub struct Cli {
     #[arg(long, overrides_with = "no_resume")]
     resume: bool,
 
-    /// Disable resume support
+    /// Disable resume support; interrupted server-mode transfers then
+    /// discard their temp files instead of continuing them next run
     #[arg(long, overrides_with = "resume")]
     pub no_resume: bool,
 
@@ -269,7 +538,9 @@ pub struct Cli {
     pub clean_state: bool,
 
     /// Use directory cache for faster re-syncs (default: false)
-    /// The cache stores directory mtimes to skip unchanged directories
+    /// The cache stores directory mtimes and listings, so files in unchanged
+    /// directories aren't statted (an in-place edit isn't noticed until its
+    /// directory changes)
     #[arg(long, default_value = "false", action = clap::ArgAction::Set)]
     pub use_cache: bool,
 
@@ -277,6 +548,12 @@ pub struct Cli {
     #[arg(long)]
     pub clear_cache: bool,
 
+    /// Keep a manifest of the destination between pushes (server mode):
+    /// unchanged files aren't re-checksummed and only changed entries are
+    /// sent in the Initial Exchange
+    #[arg(long)]
+    pub dest_manifest: bool,
+
     /// Use checksum database for faster --checksum re-syncs (default: false)
     /// The database stores checksums to avoid recomputation for unchanged files
     #[arg(long, default_value = "false", action = clap::ArgAction::Set)]
@@ -297,6 +574,29 @@ pub struct Cli {
     #[arg(long)]
     pub verify: bool,
 
+    /// Re-send a file whole-file up to N times when --verify detects a
+    /// checksum mismatch, before counting it as a verification failure
+    #[arg(long, default_value = "0", value_name = "N")]
+    pub verify_retries: u32,
+
+    /// Verify only a random N% of transferred files (implies --verify)
+    ///
+    /// Cuts the extra read I/O of full verification on huge syncs while
+    /// still giving statistical confidence. The subset is chosen by hashing
+    /// each path with a per-run seed, shown in the summary.
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent)]
+    pub verify_sample: Option<f64>,
+
+    /// Seed for --verify-sample (reuse a previous run's seed to re-check the same files)
+    #[arg(long, value_name = "SEED", requires = "verify_sample")]
+    pub verify_seed: Option<u64>,
+
+    /// Check each file end to end in server mode: the sending side hashes
+    /// it (xxHash3-128) and the receiving side compares what it wrote
+    /// before putting it in place, discarding it on a mismatch
+    #[arg(long)]
+    pub checksum_verify: bool,
+
     /// Enable compression for network transfers (auto-detects based on file type)
     #[arg(short = 'z', long)]
     pub compress: bool,
@@ -309,10 +609,155 @@ pub struct Cli {
     #[arg(long, value_enum, default_value = "auto")]
     pub compression_detection: CompressionDetection,
 
+    /// zstd level for --compress in server-mode transfers (1-19, default 3);
+    /// higher trades CPU for bandwidth
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(i32).range(1..=19))]
+    pub compress_level: Option<i32>,
+
+    /// Use io_uring for file reads and writes in server-mode transfers
+    /// (Linux; requires a build with the io-uring feature, otherwise falls back
+    /// to standard file I/O)
+    #[arg(long)]
+    pub io_uring: bool,
+
+    /// Drop transferred files from the page cache after reading/writing them
+    /// in server-mode transfers, so large copies don't evict other data
+    #[arg(long)]
+    pub drop_cache: bool,
+
+    /// Bypass the page cache with O_DIRECT in server-mode transfers (Linux;
+    /// falls back to buffered I/O where the filesystem doesn't support it)
+    #[arg(long)]
+    pub direct_io: bool,
+
+    /// When to fsync written files in server-mode transfers
+    /// - never: Leave writeback to the OS (fastest, least durable)
+    /// - per-file: fsync each file before renaming it into place (default)
+    /// - batch: Sync the filesystem periodically and before reporting success
+    #[arg(long, value_enum, default_value = "per-file")]
+    pub fsync: FsyncPolicy,
+
+    /// Memory cap for destination block checksums held during server-mode
+    /// transfers (e.g., "256MB"); beyond it they're spilled to a temporary
+    /// store on disk [default: 512MB]
+    #[arg(long, value_parser = parse_size)]
+    pub index_memory: Option<u64>,
+
+    /// Delta block size for server-mode transfers (e.g., "8KB"; 512B-128KB);
+    /// by default each file's blocks are about the square root of its size
+    #[arg(long, value_name = "SIZE", value_parser = parse_block_size)]
+    pub block_size: Option<u32>,
+
+    /// Hash files at least this large through a memory map in server-mode
+    /// transfers (e.g., "16MB"; 0 never maps) [default: 64MB]. A file
+    /// truncated while it's mapped ends the transfer with SIGBUS
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub mmap_threshold: Option<u64>,
+
+    /// Reserve disk space for each file before writing it in server-mode
+    /// transfers, reducing fragmentation and failing early when the
+    /// destination is full (sparse files keep their holes)
+    #[arg(long)]
+    pub preallocate: bool,
+
+    /// Write files that don't exist on the destination directly at their
+    /// final path in server-mode transfers, skipping the temp file and rename
+    /// (faster initial seeds; an interrupted transfer can leave partial files)
+    #[arg(long)]
+    pub no_atomic: bool,
+
+    /// Apply a server-mode transfer all or nothing: files are staged in a
+    /// hidden directory and only moved into place, along with deletes and
+    /// other changes, once everything arrived without errors
+    #[arg(long, conflicts_with = "no_atomic")]
+    pub atomic: bool,
+
+    /// When a sync is interrupted, keep the part of each file received so
+    /// far under its real name instead of discarding it; the next run only
+    /// sends the rest as a delta
+    #[arg(long)]
+    pub partial: bool,
+
+    /// Keep the old version of each file a server-mode transfer overwrites
+    /// or deletes: renamed with --suffix beside it, or moved under
+    /// --backup-dir
+    #[arg(short = 'b', long)]
+    pub backup: bool,
+
+    /// Move old versions to this directory, relative to the destination and
+    /// keeping their paths, instead of beside their file (implies --backup)
+    #[arg(long, value_name = "DIR")]
+    pub backup_dir: Option<String>,
+
+    /// Append SUFFIX to the names of old versions (default "~", or nothing
+    /// with --backup-dir)
+    #[arg(long, value_name = "SUFFIX")]
+    pub suffix: Option<String>,
+
+    /// Also append the time the sync started (.YYYYMMDD-HHMMSS), so each run
+    /// keeps its own old versions (implies --backup)
+    #[arg(long)]
+    pub backup_timestamp: bool,
+
+    /// Give up on a file whose read makes no progress for this many seconds
+    /// (e.g. a hung NFS source), reporting it as failed and going on with
+    /// the rest (server-mode full-file transfers)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub stall_timeout: Option<u64>,
+
+    /// Send up to this many files at once in server-mode transfers, their
+    /// DATA interleaved on the one connection (default 1); helps most on
+    /// high-latency links with many files
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..=64))]
+    pub transfers: Option<u64>,
+
+    /// Confine the remote `sy --server` (Linux) to the sync root with
+    /// Landlock and to the system calls it needs with seccomp
+    #[arg(long)]
+    pub sandbox: bool,
+
+    /// What to do with SSH host keys that aren't in known_hosts or don't
+    /// match it (strict, accept-new, insecure; overrides `[ssh]` in the
+    /// config file and StrictHostKeyChecking in ~/.ssh/config)
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub host_key_policy: Option<HostKeyPolicy>,
+
+    /// Start the remote `sy --server` through an elevation command, so an
+    /// unprivileged SSH user can sync root-owned directories. Defaults to
+    /// `sudo -n` (never prompts); pass another as `--remote-sudo="doas -n"`.
+    /// Overrides `remote_sudo` under `[ssh]` in the config file
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "sudo -n"
+    )]
+    pub remote_sudo: Option<String>,
+
+    /// Refuse to receive files at paths matching pattern (can be repeated;
+    /// pulls, added to `[deny] paths` in the config file)
+    /// Examples: ".ssh/authorized_keys", "*.so"
+    #[arg(long = "deny", value_name = "PATTERN")]
+    pub deny_paths: Vec<String>,
+
+    /// Refuse to receive setuid/setgid files (pulls)
+    #[arg(long)]
+    pub deny_setuid: bool,
+
+    /// Refuse to receive device nodes, FIFOs and sockets (pulls)
+    #[arg(long)]
+    pub deny_devices: bool,
+
     /// Symlink handling mode (preserve, follow, skip)
     #[arg(long, value_enum, default_value = "preserve")]
     pub links: SymlinkMode,
 
+    /// Clone files instead of copying them in local syncs: `auto` where the
+    /// filesystem supports it, `always`, or `never`
+    #[arg(long, value_enum, value_name = "WHEN", default_value = "auto")]
+    pub reflink: ReflinkMode,
+
     /// Follow symlinks and copy targets (shortcut for --links follow)
     #[arg(short = 'L', long)]
     pub copy_links: bool,
@@ -330,7 +775,7 @@ pub struct Cli {
     pub preserve_acls: bool,
 
     /// Preserve BSD file flags (macOS only: hidden, immutable, nodump, etc.; no-op on other platforms)
-    #[arg(short = 'F', long)]
+    #[arg(short = 'F', long, visible_alias = "fileflags")]
     pub preserve_flags: bool,
 
     /// Preserve permissions
@@ -341,14 +786,28 @@ pub struct Cli {
     #[arg(short = 't', long)]
     pub preserve_times: bool,
 
-    /// Preserve group (requires appropriate permissions)
-    #[arg(short = 'g', long)]
+    /// Which times to preserve: `mtime`, or `all` for the access and (where
+    /// supported) creation times too, set once each file is in place
+    #[arg(long, value_enum, value_name = "WHICH", default_value = "mtime")]
+    pub times: TimesMode,
+
+    /// Preserve group (applied when the destination runs as root)
+    #[arg(short = 'g', long, visible_alias = "group")]
     pub preserve_group: bool,
 
-    /// Preserve owner (requires root)
-    #[arg(short = 'o', long)]
+    /// Preserve owner (applied when the destination runs as root)
+    #[arg(short = 'o', long, visible_alias = "owner")]
     pub preserve_owner: bool,
 
+    /// Map owner uids with -o: comma-separated FROM:TO pairs, `*` for any
+    /// uid (e.g. `1000:1001,*:65534`)
+    #[arg(long, value_parser = parse_id_map, value_name = "MAP")]
+    pub usermap: Option<IdMap>,
+
+    /// Map group gids with -g, like --usermap
+    #[arg(long, value_parser = parse_id_map, value_name = "MAP")]
+    pub groupmap: Option<IdMap>,
+
     /// Preserve device files and special files (requires root)
     #[arg(short = 'D', long)]
     pub preserve_devices: bool,
@@ -396,6 +855,23 @@ pub struct Cli {
     #[arg(long)]
     pub ignore_existing: bool,
 
+    /// Destination is FAT/exFAT: 2-second mtime window (size-only when times
+    /// are off by a time zone shift), no permissions, symlinks, xattrs, ACLs
+    /// or file flags, and names with characters FAT forbids rewritten to `_`
+    #[arg(long)]
+    pub fat: bool,
+
+    /// Encrypt file and directory names on the destination with the key in
+    /// FILE (deterministic AES-SIV, so re-syncs still match by size + mtime);
+    /// FILE.salt is created on first use and is needed to restore
+    #[arg(long, value_name = "FILE", conflicts_with = "decrypt_names")]
+    pub encrypt_names: Option<PathBuf>,
+
+    /// Decrypt names written by --encrypt-names with the key in FILE and its
+    /// FILE.salt (restore)
+    #[arg(long, value_name = "FILE")]
+    pub decrypt_names: Option<PathBuf>,
+
     /// Verify-only mode: audit file integrity without modifying anything
     /// Compares source and destination checksums and reports mismatches
     /// Returns exit code 0 if all match, 1 if mismatches found, 2 on error
@@ -406,10 +882,25 @@ pub struct Cli {
     #[arg(long)]
     pub json: bool,
 
+    /// Append a JSON line per file sent or received to this log: path,
+    /// bytes, delta ratio, duration and result (server-mode syncs)
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Rotate --log-file once it reaches this size, keeping the last few
+    /// as FILE.1, FILE.2, ... (e.g., "10MB")
+    #[arg(long, value_parser = parse_size, default_value = "10MB", requires = "log_file")]
+    pub log_file_size: u64,
+
     /// Watch mode - continuously monitor source for changes
     #[arg(short = 'w', long)]
     pub watch: bool,
 
+    /// Live dashboard: files in flight, throughput, queue, errors and ETA
+    /// (requires the 'tui' feature)
+    #[arg(long, conflicts_with_all = ["json", "quiet"])]
+    pub tui: bool,
+
     /// Disable hook execution (skip pre-sync and post-sync hooks)
     #[arg(long)]
     pub no_hooks: bool,
@@ -465,10 +956,32 @@ pub struct Cli {
     #[arg(long, default_value = "1")]
     pub retry_delay: u64,
 
+    /// Send a file again, up to this many times, when it fails with a
+    /// transient error (an I/O error, or the connection dropping), waiting
+    /// --retry-delay and then twice as long each time (server-mode syncs;
+    /// default 0)
+    #[arg(long, value_name = "N", default_value = "0")]
+    pub retries: u32,
+
+    /// Abort a server-mode sync at the first file that fails to transfer,
+    /// instead of reporting it and going on with the rest
+    #[arg(long)]
+    pub stop_on_error: bool,
+
     /// Internal: Run in server mode (used over SSH)
     #[arg(long, hide = true)]
     pub server: bool,
 
+    /// Serve the `[daemon.modules]` from the config file to sy clients over
+    /// TCP until interrupted (no authentication: trusted networks only)
+    #[arg(long, conflicts_with = "server")]
+    pub daemon: bool,
+
+    /// Address for --daemon to listen on (default: `[daemon] listen`, else
+    /// 127.0.0.1:873)
+    #[arg(long, value_name = "ADDR", requires = "daemon")]
+    pub listen: Option<String>,
+
     // === rsync compatibility flags (hidden, no-op) ===
     /// Recursive (no-op: sy is always recursive, for rsync compatibility)
     #[arg(short = 'r', hide = true)]
@@ -556,8 +1069,42 @@ impl Cli {
             }
         }
 
+        // Backups stay inside the destination, and never replace the file
+        if let Some(dir) = &self.backup_dir {
+            let components = || Path::new(dir).components();
+            let inside = components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                && components().any(|c| matches!(c, Component::Normal(_)));
+            if !inside {
+                anyhow::bail!(
+                    "--backup-dir must be a path inside the destination (got: {})",
+                    dir
+                );
+            }
+        } else if self.suffix.as_deref() == Some("") && !self.backup_timestamp {
+            anyhow::bail!("--suffix can't be empty without --backup-dir or --backup-timestamp");
+        }
+
+        // Names are mapped by the sync engine; the SSH server protocol and
+        // bidirectional sync have their own paths
+        if self.encrypt_names.is_some() || self.decrypt_names.is_some() {
+            if self.bidirectional {
+                anyhow::bail!(
+                    "--encrypt-names/--decrypt-names cannot be used with --bidirectional"
+                );
+            }
+            let over_ssh = [&self.source, &self.destination]
+                .into_iter()
+                .any(|p| p.as_ref().is_some_and(|p| p.is_remote()));
+            if over_ssh {
+                anyhow::bail!(
+                    "--encrypt-names/--decrypt-names are not supported over SSH yet (local, S3 and GCS only)"
+                );
+            }
+        }
+
         // --list-profiles and --show-profile don't need source/destination
-        if self.list_profiles || self.show_profile.is_some() || self.server {
+        if self.list_profiles || self.show_profile.is_some() || self.server || self.daemon {
             return Ok(());
         }
 
@@ -580,9 +1127,122 @@ impl Cli {
         Ok(())
     }
 
+    /// File I/O options for the streaming Sender and Receiver
+    pub fn io_options(&self) -> IoOptions {
+        IoOptions {
+            io_uring: self.io_uring,
+            drop_cache: self.drop_cache,
+            direct_io: self.direct_io,
+            fsync: self.fsync,
+            index_memory: self.index_memory,
+            block_size: self.block_size,
+            mmap_threshold: self.mmap_threshold,
+            preallocate: self.preallocate,
+            no_atomic: self.no_atomic,
+            partial: self.partial,
+            stall_timeout: self.stall_timeout.map(std::time::Duration::from_secs),
+            retries: self.retries,
+            retry_delay: std::time::Duration::from_secs(self.retry_delay),
+            stop_on_error: self.stop_on_error,
+            compress_level: self.compress_level,
+            bwlimit: self.bwlimit,
+            transfers: self.transfers.map(|n| n as usize),
+            links: self.symlink_mode(),
+            delete_timing: self.delete_timing(),
+            max_delete: self.max_delete_count,
+            file_flags: self.preserve_flags && !self.fat,
+            perms: self.should_preserve_permissions() && !self.fat,
+            resume: self.resume() && !self.atomic,
+            owner: self.should_preserve_owner() && !self.fat,
+            group: self.should_preserve_group() && !self.fat,
+            xattrs: self.preserve_xattrs && !self.fat,
+            acls: self.preserve_acls && !self.fat,
+            hardlinks: self.preserve_hardlinks && !self.fat,
+            all_times: self.times == TimesMode::All,
+            strong_hash: crate::delta::StrongHash::default(),
+            checksum_verify: self.checksum_verify,
+            dry_run: self.dry_run,
+            atomic: self.atomic,
+            fat: self.fat,
+            sandbox: self.sandbox,
+        }
+    }
+
+    /// When --delete sends its deletes
+    pub fn delete_timing(&self) -> DeleteTiming {
+        if self.delete_before {
+            DeleteTiming::Before
+        } else if self.delete_during {
+            DeleteTiming::During
+        } else {
+            DeleteTiming::After
+        }
+    }
+
+    /// Where --backup moves the old versions of files, if anywhere
+    pub fn backup(&self) -> Option<Backup> {
+        (self.backup || self.backup_dir.is_some() || self.backup_timestamp).then(|| {
+            Backup::new(
+                self.backup_dir.clone(),
+                self.suffix.clone(),
+                self.backup_timestamp,
+            )
+        })
+    }
+
+    /// The --log-file transfer log, opened for appending
+    pub fn transfer_log(&self) -> anyhow::Result<Option<TransferLog>> {
+        self.log_file
+            .as_deref()
+            .map(|path| TransferLog::open(path, self.log_file_size))
+            .transpose()
+    }
+
+    /// How --usermap/--groupmap rewrite owners
+    pub fn owner_map(&self) -> OwnerMap {
+        OwnerMap {
+            users: self.usermap.clone().unwrap_or_default(),
+            groups: self.groupmap.clone().unwrap_or_default(),
+        }
+    }
+
+    /// What this machine refuses to receive: `[deny]` from the config file
+    /// plus the --deny* flags, which can only add to it
+    pub fn receive_policy(&self, deny: &DenySettings) -> anyhow::Result<ReceivePolicy> {
+        let patterns: Vec<String> = deny.paths.iter().chain(&self.deny_paths).cloned().collect();
+        ReceivePolicy::new(
+            deny.setuid || self.deny_setuid,
+            deny.devices || self.deny_devices,
+            &patterns,
+        )
+    }
+
+    /// Host key settings: `[ssh]` from the config file with
+    /// --host-key-policy on top
+    pub fn ssh_settings(&self, ssh: &SshSettings) -> SshSettings {
+        SshSettings {
+            host_key_policy: self.host_key_policy.or(ssh.host_key_policy),
+            remote_sudo: self.remote_sudo.clone().or(ssh.remote_sudo.clone()),
+            ..ssh.clone()
+        }
+    }
+
+    /// Filename encryption from --encrypt-names / --decrypt-names
+    pub fn name_crypt(&self) -> anyhow::Result<Option<NameCrypt>> {
+        let load = |path: &PathBuf, create_salt: bool| {
+            NameCipher::from_key_file(path, create_salt)
+                .with_context(|| format!("Failed to read name key {}", path.display()))
+        };
+        Ok(match (&self.encrypt_names, &self.decrypt_names) {
+            (Some(path), _) => Some(NameCrypt::Encrypt(load(path, true)?)),
+            (None, Some(path)) => Some(NameCrypt::Decrypt(load(path, false)?)),
+            (None, None) => None,
+        })
+    }
+
     /// Get the verification mode based on --verify flag
     pub fn verification_mode(&self) -> VerificationMode {
-        if self.verify {
+        if self.verify || self.verify_sample.is_some() {
             VerificationMode::Verify
         } else {
             VerificationMode::None
@@ -593,6 +1253,9 @@ impl Cli {
     pub fn symlink_mode(&self) -> SymlinkMode {
         if self.copy_links {
             SymlinkMode::Follow
+        } else if self.fat {
+            // FAT can't store symlinks at all
+            SymlinkMode::Skip
         } else {
             self.links
         }
@@ -664,13 +1327,11 @@ impl Cli {
     }
 
     /// Check if group should be preserved (archive mode or explicit flag)
-    #[allow(dead_code)] // Public API for group preservation (planned feature)
     pub fn should_preserve_group(&self) -> bool {
         self.archive || self.preserve_group
     }
 
     /// Check if owner should be preserved (archive mode or explicit flag)
-    #[allow(dead_code)] // Public API for owner preservation (planned feature)
     pub fn should_preserve_owner(&self) -> bool {
         self.archive || self.preserve_owner
     }
@@ -719,6 +1380,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -750,8 +1415,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -763,6 +1432,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -777,15 +1448,57 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert!(cli.validate().is_ok());
     }
@@ -807,6 +1520,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -838,8 +1555,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -851,6 +1572,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -865,15 +1588,57 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         let result = cli.validate();
         assert!(result.is_err());
@@ -901,6 +1666,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -930,8 +1699,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -943,6 +1716,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -957,6 +1732,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -964,10 +1740,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         // Single file sync is now supported
         assert!(cli.validate().is_ok());
@@ -994,6 +1811,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -1023,8 +1844,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1036,6 +1861,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1050,6 +1877,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1057,10 +1885,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert!(cli.validate().is_ok());
     }
@@ -1082,6 +1951,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: true,
             perf: false,
@@ -1111,8 +1984,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1124,6 +2001,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1138,6 +2017,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1145,10 +2025,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert_eq!(cli.log_level(), tracing::Level::ERROR);
     }
@@ -1170,6 +2091,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -1199,8 +2124,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1212,6 +2141,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1226,6 +2157,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1233,10 +2165,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert_eq!(cli.log_level(), tracing::Level::INFO);
     }
@@ -1258,6 +2231,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 1,
             quiet: false,
             perf: false,
@@ -1287,8 +2264,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1300,6 +2281,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1314,6 +2297,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1321,10 +2305,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert_eq!(cli.log_level(), tracing::Level::DEBUG);
     }
@@ -1346,6 +2371,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 2,
             quiet: false,
             perf: false,
@@ -1375,8 +2404,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1388,6 +2421,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1402,6 +2437,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1409,10 +2445,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert_eq!(cli.log_level(), tracing::Level::TRACE);
     }
@@ -1453,6 +2530,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -1482,8 +2563,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1495,6 +2580,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1509,6 +2596,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1516,10 +2604,51 @@ mod tests {
             max_size: Some(500 * 1024),  // 500KB (smaller than min)
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
 
         let result = cli.validate();
@@ -1544,6 +2673,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -1573,8 +2706,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1586,6 +2723,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1600,6 +2739,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1607,10 +2747,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert_eq!(cli.verification_mode(), VerificationMode::None);
     }
@@ -1632,6 +2813,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -1661,8 +2846,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1674,6 +2863,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1688,6 +2879,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1695,10 +2887,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         // verify flag should override mode to Verify
         assert_eq!(cli.verification_mode(), VerificationMode::Verify);
@@ -1733,6 +2966,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -1762,8 +2999,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1775,6 +3016,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1789,6 +3032,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1796,10 +3040,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert_eq!(cli.symlink_mode(), SymlinkMode::Preserve);
     }
@@ -1821,6 +3106,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -1850,8 +3139,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1863,6 +3156,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1877,6 +3172,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1884,10 +3180,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert_eq!(cli.symlink_mode(), SymlinkMode::Follow);
     }
@@ -1909,6 +3246,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -1938,8 +3279,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -1951,6 +3296,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -1965,6 +3312,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -1972,10 +3320,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
         assert_eq!(cli.symlink_mode(), SymlinkMode::Skip);
     }
@@ -1997,6 +3386,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -2026,8 +3419,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: true, // Archive mode enabled
             gitignore: false,
@@ -2039,6 +3436,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -2053,6 +3452,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -2060,10 +3460,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
 
         // Archive mode should enable all these flags
@@ -2092,6 +3533,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -2121,8 +3566,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: true, // Only permissions enabled
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -2134,6 +3583,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -2148,6 +3599,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -2155,10 +3607,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
 
         // Only permissions should be enabled
@@ -2186,6 +3679,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -2215,8 +3712,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: true, // Explicit flag also enabled
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: true, // Archive mode also enabled
             gitignore: false,
@@ -2228,6 +3729,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -2242,6 +3745,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -2249,10 +3753,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
 
         // All should be enabled (archive mode OR individual flags)
@@ -2281,6 +3826,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -2310,8 +3859,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -2323,6 +3876,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -2337,6 +3892,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -2344,10 +3900,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
 
         let result = cli.validate();
@@ -2376,6 +3973,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -2405,8 +4006,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -2418,6 +4023,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -2432,6 +4039,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -2439,10 +4047,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
 
         // Should be valid - only one comparison flag
@@ -2468,6 +4117,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -2497,8 +4150,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -2510,6 +4167,8 @@ mod tests {
             checksum: true, // Only this flag enabled
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -2524,6 +4183,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -2531,10 +4191,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         };
 
         // Should be valid - only one comparison flag
@@ -2586,6 +4287,291 @@ mod tests {
         assert!(!options.include_git_dir);
     }
 
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("10").unwrap(), 10.0);
+        assert_eq!(parse_percent("2.5%").unwrap(), 2.5);
+        assert_eq!(parse_percent("100%").unwrap(), 100.0);
+        assert!(parse_percent("0").is_err());
+        assert!(parse_percent("150").is_err());
+        assert!(parse_percent("abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_block_size() {
+        assert_eq!(parse_block_size("8KB").unwrap(), 8192);
+        assert_eq!(parse_block_size("512").unwrap(), 512);
+        assert_eq!(parse_block_size("128K").unwrap(), 128 * 1024);
+        assert!(parse_block_size("256").is_err());
+        assert!(parse_block_size("1MB").is_err());
+    }
+
+    #[test]
+    fn test_verify_sample_implies_verify() {
+        let mut cli = create_test_cli();
+        assert_eq!(cli.verification_mode(), VerificationMode::None);
+        cli.verify_sample = Some(5.0);
+        assert_eq!(cli.verification_mode(), VerificationMode::Verify);
+    }
+
+    #[test]
+    fn test_subcommand_parsing() {
+        let cli = Cli::try_parse_from(["sy", "manifest", "create", "/data"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Manifest(ManifestCommand::Create {
+                dir: PathBuf::from("/data"),
+                output: None,
+            }))
+        );
+        assert!(cli.source.is_none());
+
+        let cli = Cli::try_parse_from(["sy", "manifest", "verify", "/copy", "m.json"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Manifest(ManifestCommand::Verify {
+                dir: PathBuf::from("/copy"),
+                manifest: PathBuf::from("m.json"),
+                key: None,
+                signature: None,
+            }))
+        );
+
+        let cli = Cli::try_parse_from(["sy", "manifest", "sign", "m.json", "--key", "k"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Manifest(ManifestCommand::Sign {
+                manifest: PathBuf::from("m.json"),
+                key: PathBuf::from("k"),
+            }))
+        );
+
+        // --signature only makes sense with --key
+        assert!(Cli::try_parse_from([
+            "sy",
+            "manifest",
+            "verify",
+            "/copy",
+            "m.json",
+            "--signature",
+            "s"
+        ])
+        .is_err());
+
+        let cli = Cli::try_parse_from(["sy", "scrub", "/data", "--bwlimit", "10MB"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Scrub {
+                dir: PathBuf::from("/data"),
+                bwlimit: Some(10 * 1024 * 1024),
+            })
+        );
+
+        let cli = Cli::try_parse_from(["sy", "mount", "backup:/srv/data", "/mnt/data"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Mount {
+                remote: SyncPath::parse("backup:/srv/data"),
+                mountpoint: PathBuf::from("/mnt/data"),
+            })
+        );
+
+        let cli = Cli::try_parse_from(["sy", "history", "--json", "-n", "5"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::History {
+                json: true,
+                limit: Some(5),
+            })
+        );
+
+        let cli = Cli::try_parse_from(["sy", "push", "/data", "--dedup-store", "/store"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Push {
+                source: PathBuf::from("/data"),
+                dedup_store: PathBuf::from("/store"),
+            })
+        );
+
+        let cli = Cli::try_parse_from([
+            "sy",
+            "restore",
+            "/store",
+            "/restore",
+            "--snapshot",
+            "20260101T000000Z",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Restore {
+                store: PathBuf::from("/store"),
+                target: PathBuf::from("/restore"),
+                snapshot: Some("20260101T000000Z".to_string()),
+            })
+        );
+
+        let cli = Cli::try_parse_from(["sy", "bench", "backup:", "--size", "1GB"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Bench {
+                remote: SyncPath::parse("backup:"),
+                size: 1024 * 1024 * 1024,
+                files: 2000,
+            })
+        );
+
+        // Regular sync arguments still parse as positionals
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.source.is_some());
+    }
+
+    #[test]
+    fn test_io_options() {
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst"]).unwrap();
+        assert_eq!(cli.io_options().fsync, FsyncPolicy::PerFile);
+        assert!(cli.io_options().server_args().is_empty());
+
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "--drop-cache", "--fsync", "batch"])
+            .unwrap();
+        let io = cli.io_options();
+        assert!(io.drop_cache);
+        assert!(!io.direct_io);
+        assert_eq!(io.fsync, FsyncPolicy::Batch);
+
+        assert!(Cli::try_parse_from(["sy", "/src", "/dst", "--fsync", "sometimes"]).is_err());
+
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "--index-memory", "64MB"]).unwrap();
+        assert_eq!(cli.io_options().index_memory(), 64 * 1024 * 1024);
+
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "--links", "skip"]).unwrap();
+        assert_eq!(cli.io_options().links, SymlinkMode::Skip);
+        assert_eq!(cli.io_options().server_args(), ["--links", "skip"]);
+
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "--fileflags"]).unwrap();
+        assert!(cli.io_options().file_flags);
+
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "--fat", "--fileflags"]).unwrap();
+        let io = cli.io_options();
+        assert!(io.fat);
+        assert!(!io.file_flags);
+        assert_eq!(io.links, SymlinkMode::Skip);
+        assert_eq!(io.server_args(), ["--links", "skip", "--fat"]);
+
+        // Negotiated in the HELLO, never on the server's command line
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "--times=all"]).unwrap();
+        assert!(cli.io_options().all_times);
+        assert!(cli.io_options().server_args().is_empty());
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "-t"]).unwrap();
+        assert!(!cli.io_options().all_times);
+        assert!(Cli::try_parse_from(["sy", "/src", "/dst", "--times", "ctime"]).is_err());
+    }
+
+    #[test]
+    fn test_delete_timing_flags() {
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "--delete"]).unwrap();
+        assert_eq!(cli.delete_timing(), DeleteTiming::After);
+
+        // Each timing implies --delete
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst", "--delete-during"]).unwrap();
+        assert!(cli.delete);
+        assert_eq!(cli.delete_timing(), DeleteTiming::During);
+        let cli = Cli::try_parse_from([
+            "sy",
+            "/src",
+            "/dst",
+            "--delete-before",
+            "--max-delete-count",
+            "10",
+        ])
+        .unwrap();
+        assert!(cli.delete);
+        assert_eq!(
+            cli.io_options().server_args(),
+            ["--delete-before", "--max-delete-count", "10"]
+        );
+
+        assert!(
+            Cli::try_parse_from(["sy", "/src", "/dst", "--delete-before", "--delete-after"])
+                .is_err()
+        );
+        assert!(Cli::try_parse_from([
+            "sy",
+            "/src",
+            "/dst",
+            "--bidirectional",
+            "--max-delete-count",
+            "1"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_remote_sudo_flag() {
+        let config = SshSettings {
+            remote_sudo: Some("doas -n".to_string()),
+            ..Default::default()
+        };
+
+        let cli = Cli::try_parse_from(["sy", "/src", "host:/dst"]).unwrap();
+        assert_eq!(
+            cli.ssh_settings(&config).remote_sudo.as_deref(),
+            Some("doas -n")
+        );
+
+        // Without a value the flag doesn't swallow the next argument
+        let cli = Cli::try_parse_from(["sy", "--remote-sudo", "/src", "host:/dst"]).unwrap();
+        assert_eq!(cli.remote_sudo.as_deref(), Some("sudo -n"));
+        assert!(cli.source.is_some() && cli.destination.is_some());
+        assert_eq!(
+            cli.ssh_settings(&config).remote_sudo.as_deref(),
+            Some("sudo -n")
+        );
+
+        let cli =
+            Cli::try_parse_from(["sy", "--remote-sudo=sudo -n -u backup", "/src", "host:/dst"])
+                .unwrap();
+        assert_eq!(cli.remote_sudo.as_deref(), Some("sudo -n -u backup"));
+    }
+
+    #[test]
+    fn test_name_crypt_flags() {
+        assert!(Cli::try_parse_from([
+            "sy",
+            "/src",
+            "/dst",
+            "--encrypt-names",
+            "key",
+            "--decrypt-names",
+            "key"
+        ])
+        .is_err());
+
+        let cli =
+            Cli::try_parse_from(["sy", "/src", "host:/dst", "--encrypt-names", "key"]).unwrap();
+        assert!(cli.validate().is_err());
+
+        let cli = Cli::try_parse_from(["sy", "/src", "/dst"]).unwrap();
+        assert!(cli.name_crypt().unwrap().is_none());
+        let cli =
+            Cli::try_parse_from(["sy", "/src", "/dst", "--decrypt-names", "/nonexistent"]).unwrap();
+        assert!(cli.name_crypt().is_err());
+    }
+
+    #[test]
+    fn test_daemon_flags() {
+        // No source or destination needed
+        let cli = Cli::try_parse_from(["sy", "--daemon", "--listen", "127.0.0.1:8730"]).unwrap();
+        assert!(cli.daemon);
+        assert_eq!(cli.listen.as_deref(), Some("127.0.0.1:8730"));
+        assert!(cli.validate().is_ok());
+
+        assert!(Cli::try_parse_from(["sy", "--listen", "127.0.0.1:8730"]).is_err());
+        assert!(Cli::try_parse_from(["sy", "--daemon", "--server"]).is_err());
+    }
+
     // Helper to create a minimal test CLI
     fn create_test_cli() -> Cli {
         Cli {
@@ -2603,6 +4589,10 @@ mod tests {
             delete_threshold: 50,
             trash: false,
             force_delete: false,
+            delete_before: false,
+            delete_during: false,
+            delete_after: false,
+            max_delete_count: None,
             verbose: 0,
             quiet: false,
             perf: false,
@@ -2632,8 +4622,12 @@ mod tests {
             preserve_flags: false,
             preserve_permissions: false,
             preserve_times: false,
+            times: TimesMode::Mtime,
+            reflink: ReflinkMode::Auto,
             preserve_group: false,
             preserve_owner: false,
+            usermap: None,
+            groupmap: None,
             preserve_devices: false,
             archive: false,
             gitignore: false,
@@ -2645,6 +4639,8 @@ mod tests {
             checksum: false,
             verify_only: false,
             json: false,
+            log_file: None,
+            log_file_size: 10 * 1024 * 1024,
             stream: false,
             watch: false,
             no_hooks: false,
@@ -2659,6 +4655,7 @@ mod tests {
             force_resync: false,
             use_cache: false,
             clear_cache: false,
+            dest_manifest: false,
             checksum_db: false,
             clear_checksum_db: false,
             prune_checksum_db: false,
@@ -2666,10 +4663,51 @@ mod tests {
             max_size: None,
             retry: 3,
             retry_delay: 1,
+            retries: 0,
+            stop_on_error: false,
             resume_only: false,
             clear_resume_state: false,
             recursive: false,
             server: false,
+            daemon: false,
+            listen: None,
+            tui: false,
+            estimate: false,
+            estimate_rate: None,
+            host_key_policy: None,
+            remote_sudo: None,
+            deny_devices: false,
+            deny_setuid: false,
+            deny_paths: Vec::new(),
+            sandbox: false,
+            encrypt_names: None,
+            decrypt_names: None,
+            fat: false,
+            otlp_endpoint: None,
+            metrics_addr: None,
+            no_atomic: false,
+            atomic: false,
+            backup: false,
+            backup_dir: None,
+            suffix: None,
+            backup_timestamp: false,
+            partial: false,
+            stall_timeout: None,
+            transfers: None,
+            compress_level: None,
+            preallocate: false,
+            index_memory: None,
+            block_size: None,
+            mmap_threshold: None,
+            fsync: FsyncPolicy::PerFile,
+            drop_cache: false,
+            direct_io: false,
+            io_uring: false,
+            verify_sample: None,
+            verify_seed: None,
+            checksum_verify: false,
+            verify_retries: 0,
+            command: None,
         }
     }
 }
@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 #[derive(Debug, Default, Deserialize)]
@@ -10,6 +10,182 @@ pub struct Config {
     pub defaults: Defaults,
     #[serde(default)]
     pub profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    pub server: ServerSettings,
+    #[serde(default)]
+    pub daemon: DaemonSettings,
+    #[serde(default)]
+    pub audit: AuditSettings,
+    #[serde(default)]
+    pub deny: DenySettings,
+    #[serde(default)]
+    pub ssh: SshSettings,
+    /// Default notifications; a profile's own `notify` replaces them
+    pub notify: Option<NotifySettings>,
+}
+
+/// `[server]`: applies when this machine is the remote end (`sy --server`)
+#[derive(Debug, Default, Deserialize)]
+pub struct ServerSettings {
+    /// Directories clients may sync to or from; unset allows any
+    /// (overridden by `SY_SERVER_ROOTS`)
+    pub allowed_roots: Option<Vec<PathBuf>>,
+    /// Always run sessions under `--sandbox`, whatever the client asks for
+    #[serde(default)]
+    pub sandbox: bool,
+    /// Paths pulls never serve, in `.syignore` syntax; a `.syignore` in the
+    /// served root adds to these
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Log of every file sessions here receive or send, rotated like
+    /// `--log-file`; unset keeps none
+    pub transfer_log: Option<PathBuf>,
+}
+
+/// `[daemon]`: what `sy --daemon` serves over TCP; `[server]` exclusions,
+/// `[deny]` and `[audit]` apply to its sessions too
+#[derive(Debug, Default, Deserialize)]
+pub struct DaemonSettings {
+    /// Address to listen on (`--listen` overrides; default 127.0.0.1:873)
+    pub listen: Option<String>,
+    /// Addresses or networks (`10.0.0.0/8`) allowed to connect; empty allows
+    /// any host that can reach `listen`
+    #[serde(default)]
+    pub hosts_allow: Vec<String>,
+    /// Sessions served at once; more connections are turned away (default
+    /// 32)
+    pub max_connections: Option<usize>,
+    /// Module name -> directory, or `{ path = "...", read_only = false }`;
+    /// clients name a module, optionally with a path below it, and nothing
+    /// else is reachable
+    #[serde(default)]
+    pub modules: BTreeMap<String, DaemonModule>,
+}
+
+/// One `[daemon.modules]` entry
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "ModuleEntry")]
+pub struct DaemonModule {
+    pub path: PathBuf,
+    /// Refuse pushes (the default); pulls and browsing still work
+    pub read_only: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ModuleEntry {
+    Path(PathBuf),
+    Table {
+        path: PathBuf,
+        #[serde(default = "read_only_default")]
+        read_only: bool,
+    },
+}
+
+fn read_only_default() -> bool {
+    true
+}
+
+impl From<ModuleEntry> for DaemonModule {
+    fn from(entry: ModuleEntry) -> Self {
+        match entry {
+            ModuleEntry::Path(path) => Self {
+                path,
+                read_only: true,
+            },
+            ModuleEntry::Table { path, read_only } => Self { path, read_only },
+        }
+    }
+}
+
+/// `[audit]`: record destructive changes made to destinations on this
+/// machine, by pulls here and by pushes to `sy --server` here
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditSettings {
+    /// Append-only log of deletes, overwrites and permission changes;
+    /// unset disables auditing
+    pub log: Option<PathBuf>,
+}
+
+/// `[deny]`: what this machine refuses to receive as a destination, by
+/// pulls here and by pushes to `sy --server` here
+#[derive(Debug, Default, Deserialize)]
+pub struct DenySettings {
+    /// Refuse setuid/setgid files
+    #[serde(default)]
+    pub setuid: bool,
+    /// Refuse device nodes, FIFOs and sockets
+    #[serde(default)]
+    pub devices: bool,
+    /// Globs for paths to refuse, e.g. `.ssh/authorized_keys`
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+/// `[ssh]`: host key checking for the built-in SSH client, and how the
+/// remote `sy --server` is started
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SshSettings {
+    /// Overrides `StrictHostKeyChecking` from ~/.ssh/config
+    pub host_key_policy: Option<HostKeyPolicy>,
+    /// known_hosts file to check and add to (default ~/.ssh/known_hosts)
+    pub known_hosts: Option<PathBuf>,
+    /// Pinned fingerprints per host, as `ssh-keygen -l` prints them
+    /// (`SHA256:...`); a pinned host must present one of its keys, whatever
+    /// known_hosts says
+    #[serde(default)]
+    pub fingerprints: HashMap<String, Vec<String>>,
+    /// Elevation command the remote `sy --server` runs under, e.g.
+    /// `sudo -n` (`--remote-sudo`)
+    pub remote_sudo: Option<String>,
+}
+
+/// What to do with a host key that isn't known to be right
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyPolicy {
+    /// Only connect to hosts already in known_hosts
+    Strict,
+    /// Add unknown hosts to known_hosts; refuse changed keys
+    #[default]
+    AcceptNew,
+    /// Connect whatever the key (warns about unknown and changed keys)
+    Insecure,
+}
+
+/// `[notify]` or `[profiles.<name>.notify]`: who to tell when a sync
+/// finishes or fails
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NotifySettings {
+    /// Which outcomes to report
+    #[serde(default)]
+    pub on: NotifyOn,
+    /// URL to POST a JSON summary to
+    pub webhook: Option<String>,
+    /// Addresses to email the summary to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub email: Vec<String>,
+    /// Sender of the email (default `sy@localhost`)
+    pub from: Option<String>,
+    /// Send email through this server (`smtp://` or `smtps://` URL)
+    /// instead of the local sendmail
+    pub smtp: Option<String>,
+    /// SMTP login; the password is read from `SY_SMTP_PASSWORD`
+    pub smtp_user: Option<String>,
+    /// Show a desktop notification
+    #[serde(default)]
+    pub desktop: bool,
+}
+
+/// Which outcomes of a sync get a notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotifyOn {
+    /// Every sync, whether it worked or not
+    #[default]
+    Always,
+    /// Only syncs that failed or had files fail
+    Failure,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -34,6 +210,7 @@ pub struct Profile {
     pub dry_run: Option<bool>,
     pub quiet: Option<bool>,
     pub verbose: Option<u8>,
+    pub notify: Option<NotifySettings>,
 }
 
 impl Config {
@@ -60,11 +237,43 @@ impl Config {
         Ok(config_dir.join("sy").join("config.toml"))
     }
 
+    /// The `[audit]` log, opened for appending, if one is configured
+    pub fn audit_log(&self) -> Result<Option<crate::streaming::AuditLog>> {
+        self.audit
+            .log
+            .as_deref()
+            .map(crate::streaming::AuditLog::open)
+            .transpose()
+    }
+
+    /// The `[server]` transfer log, opened for appending, if one is
+    /// configured
+    pub fn transfer_log(&self) -> Result<Option<crate::streaming::TransferLog>> {
+        self.server
+            .transfer_log
+            .as_deref()
+            .map(|path| {
+                crate::streaming::TransferLog::open(
+                    path,
+                    crate::streaming::transfer_log::DEFAULT_LOG_SIZE,
+                )
+            })
+            .transpose()
+    }
+
     /// Get a profile by name
     pub fn get_profile(&self, name: &str) -> Option<&Profile> {
         self.profiles.get(name)
     }
 
+    /// Notifications for a run of `profile` (or a run without one)
+    pub fn notify_settings(&self, profile: Option<&str>) -> Option<&NotifySettings> {
+        profile
+            .and_then(|name| self.get_profile(name))
+            .and_then(|p| p.notify.as_ref())
+            .or(self.notify.as_ref())
+    }
+
     /// List all available profile names
     pub fn list_profiles(&self) -> Vec<&String> {
         let mut names: Vec<&String> = self.profiles.keys().collect();
@@ -125,6 +334,142 @@ resume = true
         assert_eq!(profile.resume, Some(true));
     }
 
+    #[test]
+    fn test_parse_audit_settings() {
+        let config: Config = toml::from_str("[audit]\nlog = \"/var/log/sy-audit.log\"\n").unwrap();
+        assert_eq!(
+            config.audit.log,
+            Some(PathBuf::from("/var/log/sy-audit.log"))
+        );
+        assert!(Config::default().audit.log.is_none());
+    }
+
+    #[test]
+    fn test_parse_server_settings() {
+        let toml = r#"
+[server]
+sandbox = true
+exclude = ["*.key", "cache/"]
+        "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.server.sandbox);
+        assert_eq!(config.server.exclude, vec!["*.key", "cache/"]);
+        assert!(Config::default().server.exclude.is_empty());
+    }
+
+    #[test]
+    fn test_parse_daemon_settings() {
+        let toml = r#"
+[daemon]
+listen = "127.0.0.1:8730"
+hosts_allow = ["10.0.0.0/8", "192.168.1.5"]
+max_connections = 4
+
+[daemon.modules]
+backup = { path = "/srv/backup", read_only = false }
+media = "~/media"
+        "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.daemon.listen.as_deref(), Some("127.0.0.1:8730"));
+        assert_eq!(config.daemon.hosts_allow, ["10.0.0.0/8", "192.168.1.5"]);
+        assert_eq!(config.daemon.max_connections, Some(4));
+        assert_eq!(
+            config.daemon.modules.keys().collect::<Vec<_>>(),
+            ["backup", "media"]
+        );
+        assert_eq!(
+            config.daemon.modules["backup"],
+            DaemonModule {
+                path: PathBuf::from("/srv/backup"),
+                read_only: false,
+            }
+        );
+        assert!(config.daemon.modules["media"].read_only);
+        assert!(Config::default().daemon.modules.is_empty());
+        assert!(Config::default().daemon.hosts_allow.is_empty());
+    }
+
+    #[test]
+    fn test_parse_deny_settings() {
+        let toml = r#"
+[deny]
+setuid = true
+paths = [".ssh/authorized_keys", "*.so"]
+        "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        assert!(config.deny.setuid);
+        assert!(!config.deny.devices);
+        assert_eq!(config.deny.paths, vec![".ssh/authorized_keys", "*.so"]);
+    }
+
+    #[test]
+    fn test_parse_ssh_settings() {
+        let toml = r#"
+[ssh]
+host_key_policy = "accept-new"
+known_hosts = "/etc/sy/known_hosts"
+remote_sudo = "doas -n"
+
+[ssh.fingerprints]
+"backup.example.com" = ["SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU"]
+        "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.ssh.host_key_policy, Some(HostKeyPolicy::AcceptNew));
+        assert_eq!(
+            config.ssh.known_hosts,
+            Some(PathBuf::from("/etc/sy/known_hosts"))
+        );
+        assert_eq!(config.ssh.fingerprints["backup.example.com"].len(), 1);
+        assert_eq!(config.ssh.remote_sudo.as_deref(), Some("doas -n"));
+        assert!(toml::from_str::<Config>("[ssh]\nhost_key_policy = \"ask\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_notify_settings() {
+        let toml = r#"
+[notify]
+desktop = true
+
+[profiles.nightly]
+source = "/data"
+
+[profiles.nightly.notify]
+on = "failure"
+webhook = "https://hooks.example.com/sy"
+email = ["ops@example.com"]
+smtp = "smtps://smtp.example.com"
+
+[profiles.adhoc]
+source = "/tmp"
+        "#;
+
+        let config: Config = toml::from_str(toml).unwrap();
+        let nightly = config.notify_settings(Some("nightly")).unwrap();
+        assert_eq!(nightly.on, NotifyOn::Failure);
+        assert_eq!(
+            nightly.webhook.as_deref(),
+            Some("https://hooks.example.com/sy")
+        );
+        assert_eq!(nightly.email, ["ops@example.com"]);
+        assert!(!nightly.desktop);
+
+        // Runs without a notify section of their own get the default
+        for profile in [Some("adhoc"), None] {
+            let settings = config.notify_settings(profile).unwrap();
+            assert_eq!(settings.on, NotifyOn::Always);
+            assert!(settings.desktop);
+        }
+        assert!(config
+            .show_profile("nightly")
+            .unwrap()
+            .contains("on = \"failure\""));
+        assert!(toml::from_str::<Config>("[notify]\non = \"never\"\n").is_err());
+    }
+
     #[test]
     fn test_list_profiles() {
         let toml = r#"
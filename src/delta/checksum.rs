@@ -16,8 +16,31 @@ pub struct BlockChecksum {
     pub size: usize,
     /// Weak rolling checksum (Adler-32)
     pub weak: u32,
-    /// Strong checksum (xxHash3)
-    pub strong: u64,
+    /// Strong checksum (see `StrongHash`; 64-bit ones zero-extended)
+    pub strong: u128,
+}
+
+/// Strong hash that confirms a weak checksum match
+///
+/// Streaming peers agree on one in the HELLO (HelloFlags::STRONG_128) and
+/// fall back to 64 bits with older ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StrongHash {
+    /// xxHash3-64
+    Xxh3_64,
+    /// xxHash3-128: over millions of blocks, 64 bits leave a real chance
+    /// of confirming the wrong one
+    #[default]
+    Xxh3_128,
+}
+
+impl StrongHash {
+    pub fn hash(self, data: &[u8]) -> u128 {
+        match self {
+            Self::Xxh3_64 => xxhash_rust::xxh3::xxh3_64(data) as u128,
+            Self::Xxh3_128 => xxhash_rust::xxh3::xxh3_128(data),
+        }
+    }
 }
 
 /// Compute checksums for all blocks in a file
@@ -29,6 +52,15 @@ pub struct BlockChecksum {
 /// Each thread processes blocks independently with its own file handle.
 #[allow(dead_code)] // Reserved for future remote sync implementation
 pub fn compute_checksums(path: &Path, block_size: usize) -> io::Result<Vec<BlockChecksum>> {
+    compute_checksums_with(path, block_size, StrongHash::Xxh3_64)
+}
+
+/// Compute checksums for all blocks in a file, confirming with `strong`
+pub fn compute_checksums_with(
+    path: &Path,
+    block_size: usize,
+    strong: StrongHash,
+) -> io::Result<Vec<BlockChecksum>> {
     // Get file size to determine number of blocks
     let metadata = std::fs::metadata(path)?;
     let file_size = metadata.len();
@@ -61,17 +93,12 @@ pub fn compute_checksums(path: &Path, block_size: usize) -> io::Result<Vec<Block
             // Compute weak checksum (Adler-32)
             let weak = Adler32::hash(block);
 
-            // Compute strong checksum (xxHash3)
-            let mut hasher = xxhash_rust::xxh3::Xxh3::new();
-            hasher.update(block);
-            let strong = hasher.digest();
-
             Ok(BlockChecksum {
                 index,
                 offset,
                 size: bytes_read,
                 weak,
-                strong,
+                strong: strong.hash(block),
             })
         })
         .collect();
@@ -79,6 +106,56 @@ pub fn compute_checksums(path: &Path, block_size: usize) -> io::Result<Vec<Block
     checksums
 }
 
+/// `compute_checksums_with`, hashing blocks straight out of a memory map
+///
+/// Saves each block its own file handle, seek and read. Files that can't be
+/// mapped are read as usual.
+pub fn compute_checksums_mapped(
+    path: &Path,
+    block_size: usize,
+    strong: StrongHash,
+) -> io::Result<Vec<BlockChecksum>> {
+    let file = File::open(path)?;
+    let Some(map) = map_file(&file, file.metadata()?.len()) else {
+        return compute_checksums_with(path, block_size, strong);
+    };
+
+    Ok(map
+        .par_chunks(block_size)
+        .enumerate()
+        .map(|(index, block)| BlockChecksum {
+            index: index as u64,
+            offset: (index * block_size) as u64,
+            size: block.len(),
+            weak: Adler32::hash(block),
+            strong: strong.hash(block),
+        })
+        .collect())
+}
+
+/// Map the first `len` bytes of `file` read-only, or None where it can't
+/// be (empty files, some network and special filesystems)
+///
+/// Truncating a file while it's mapped raises SIGBUS on the pages that went
+/// away, which is why only files past `--mmap-threshold` are mapped.
+pub(crate) fn map_file(file: &File, len: u64) -> Option<memmap2::Mmap> {
+    if len == 0 {
+        return None;
+    }
+    // SAFETY: the map is read-only and dropped before the file is replaced
+    match unsafe { memmap2::MmapOptions::new().len(len as usize).map(file) } {
+        Ok(map) => {
+            #[cfg(unix)]
+            let _ = map.advise(memmap2::Advice::Sequential);
+            Some(map)
+        }
+        Err(e) => {
+            tracing::debug!("Can't map file, reading it instead: {}", e);
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -144,4 +221,25 @@ mod tests {
         assert_eq!(checksums_small.len(), 10); // 100 / 10 = 10 blocks
         assert_eq!(checksums_large.len(), 2); // 100 / 50 = 2 blocks
     }
+
+    #[test]
+    fn test_mapped_checksums_match_reads() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        temp_file.write_all(&data).unwrap();
+        temp_file.flush().unwrap();
+
+        for strong in [StrongHash::Xxh3_64, StrongHash::Xxh3_128] {
+            let read = compute_checksums_with(temp_file.path(), 4096, strong).unwrap();
+            let mapped = compute_checksums_mapped(temp_file.path(), 4096, strong).unwrap();
+            assert_eq!(mapped, read);
+        }
+
+        let empty = NamedTempFile::new().unwrap();
+        assert!(
+            compute_checksums_mapped(empty.path(), 4096, StrongHash::default())
+                .unwrap()
+                .is_empty()
+        );
+    }
 }
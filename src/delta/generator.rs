@@ -1,4 +1,4 @@
-use super::{Adler32, BlockChecksum};
+use super::{Adler32, BlockChecksum, StrongHash};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
@@ -69,7 +69,118 @@ pub fn generate_delta_streaming(
     dest_checksums: &[BlockChecksum],
     block_size: usize,
 ) -> io::Result<Delta> {
-    const CHUNK_SIZE: usize = 256 * 1024; // 256KB chunks
+    generate_delta_streaming_with(source_path, dest_checksums, block_size, StrongHash::Xxh3_64)
+}
+
+/// `generate_delta_streaming` against checksums confirmed with `strong`
+pub fn generate_delta_streaming_with(
+    source_path: &Path,
+    dest_checksums: &[BlockChecksum],
+    block_size: usize,
+    strong_hash: StrongHash,
+) -> io::Result<Delta> {
+    let source_file = File::open(source_path)?;
+    let source_size = source_file.metadata()?.len();
+    delta_from(
+        ReadSource::new(source_file, block_size),
+        source_size,
+        dest_checksums,
+        block_size,
+        strong_hash,
+    )
+}
+
+/// `generate_delta_streaming_with`, scanning the source through a memory map
+///
+/// The rolling hash walks the mapped pages directly instead of copying each
+/// chunk into a window. Files that can't be mapped are read as usual.
+pub fn generate_delta_mapped(
+    source_path: &Path,
+    dest_checksums: &[BlockChecksum],
+    block_size: usize,
+    strong_hash: StrongHash,
+) -> io::Result<Delta> {
+    let source_file = File::open(source_path)?;
+    let source_size = source_file.metadata()?.len();
+    match super::checksum::map_file(&source_file, source_size) {
+        Some(map) => delta_from(
+            &map[..],
+            source_size,
+            dest_checksums,
+            block_size,
+            strong_hash,
+        ),
+        None => delta_from(
+            ReadSource::new(source_file, block_size),
+            source_size,
+            dest_checksums,
+            block_size,
+            strong_hash,
+        ),
+    }
+}
+
+/// Where the delta loop reads the source from
+trait Source {
+    /// Bytes read and not yet dropped
+    fn window(&self) -> &[u8];
+
+    /// Drop the first `consumed` bytes and read more; false once there's
+    /// nothing left to read
+    fn refill(&mut self, consumed: usize) -> io::Result<bool>;
+}
+
+/// A source read 256KB at a time
+struct ReadSource<R> {
+    reader: R,
+    window: Vec<u8>,
+    chunk: Vec<u8>,
+}
+
+impl<R: Read> ReadSource<R> {
+    const CHUNK_SIZE: usize = 256 * 1024;
+
+    fn new(reader: R, block_size: usize) -> Self {
+        Self {
+            reader,
+            // Large enough for rolling hash + read ahead
+            window: Vec::with_capacity(block_size + Self::CHUNK_SIZE),
+            chunk: vec![0u8; Self::CHUNK_SIZE],
+        }
+    }
+}
+
+impl<R: Read> Source for ReadSource<R> {
+    fn window(&self) -> &[u8] {
+        &self.window
+    }
+
+    fn refill(&mut self, consumed: usize) -> io::Result<bool> {
+        self.window.drain(0..consumed);
+        let n = self.reader.read(&mut self.chunk)?;
+        self.window.extend_from_slice(&self.chunk[..n]);
+        Ok(n > 0)
+    }
+}
+
+/// A source already in memory (mapped)
+impl Source for &[u8] {
+    fn window(&self) -> &[u8] {
+        self
+    }
+
+    fn refill(&mut self, _consumed: usize) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+fn delta_from(
+    mut source: impl Source,
+    source_size: u64,
+    dest_checksums: &[BlockChecksum],
+    block_size: usize,
+    strong_hash: StrongHash,
+) -> io::Result<Delta> {
     const MAX_LITERAL_SIZE: usize = 1024 * 1024; // Flush literals at 1MB to bound memory
 
     // Build hash map for O(1) lookup
@@ -81,9 +192,6 @@ pub fn generate_delta_streaming(
             .push(checksum);
     }
 
-    let mut source_file = File::open(source_path)?;
-    let source_size = source_file.metadata()?.len();
-
     if source_size == 0 {
         return Ok(Delta {
             ops: vec![],
@@ -95,26 +203,20 @@ pub fn generate_delta_streaming(
     let mut ops = Vec::new();
     let mut literal_buffer = Vec::new();
 
-    // Sliding window buffer: large enough for rolling hash + read ahead
-    let mut window = Vec::with_capacity(block_size + CHUNK_SIZE);
-    let mut chunk_buf = vec![0u8; CHUNK_SIZE];
-
     // Read initial chunk
-    let mut bytes_read = source_file.read(&mut chunk_buf)?;
-    if bytes_read > 0 {
-        window.extend_from_slice(&chunk_buf[..bytes_read]);
-    }
+    let mut more = source.refill(0)?;
 
     // Initialize rolling hash
     let mut rolling = Adler32::new(block_size);
-    if window.len() >= block_size {
-        rolling.update_block(&window[0..block_size]);
+    if source.window().len() >= block_size {
+        rolling.update_block(&source.window()[0..block_size]);
     }
 
     let mut window_pos = 0; // Position within window
     let mut _file_pos = 0u64; // Absolute position in file (for debugging)
 
-    while window_pos < window.len() {
+    while window_pos < source.window().len() {
+        let window = source.window();
         let remaining = window.len() - window_pos;
         let mut found_match = false;
 
@@ -126,9 +228,7 @@ pub fn generate_delta_streaming(
                 let block = &window[window_pos..window_pos + block_size];
 
                 // Verify with strong hash
-                let mut hasher = xxhash_rust::xxh3::Xxh3::new();
-                hasher.update(block);
-                let strong = hasher.digest();
+                let strong = strong_hash.hash(block);
 
                 for checksum in candidates {
                     if checksum.strong == strong {
@@ -160,9 +260,7 @@ pub fn generate_delta_streaming(
             let weak = Adler32::hash(partial);
 
             if let Some(candidates) = checksum_map.get(&weak) {
-                let mut hasher = xxhash_rust::xxh3::Xxh3::new();
-                hasher.update(partial);
-                let strong = hasher.digest();
+                let strong = strong_hash.hash(partial);
 
                 for checksum in candidates {
                     if checksum.size == partial.len() && checksum.strong == strong {
@@ -203,20 +301,15 @@ pub fn generate_delta_streaming(
         }
 
         // Refill window when needed
-        if window_pos >= block_size && bytes_read > 0 && window.len() - window_pos < block_size {
-            // Shift window: remove processed bytes
-            window.drain(0..window_pos);
+        if window_pos >= block_size && more && window.len() - window_pos < block_size {
+            // Shift window (removing processed bytes) and read more data
+            more = source.refill(window_pos)?;
             window_pos = 0;
 
-            // Read more data
-            bytes_read = source_file.read(&mut chunk_buf)?;
-            if bytes_read > 0 {
-                window.extend_from_slice(&chunk_buf[..bytes_read]);
-
-                // Re-initialize rolling hash if we have enough data
-                if window.len() >= block_size {
-                    rolling.update_block(&window[0..block_size]);
-                }
+            // Re-initialize rolling hash if we have enough data
+            let window = source.window();
+            if more && window.len() >= block_size {
+                rolling.update_block(&window[0..block_size]);
             }
         }
     }
@@ -295,10 +388,8 @@ pub fn generate_delta(
             if let Some(candidates) = checksum_map.get(&weak) {
                 let block = &source_data[pos..pos + block_size];
 
-                // Verify with strong hash (xxHash3)
-                let mut hasher = xxhash_rust::xxh3::Xxh3::new();
-                hasher.update(block);
-                let strong = hasher.digest();
+                // Verify with strong hash
+                let strong = StrongHash::Xxh3_64.hash(block);
 
                 // Find exact match
                 for checksum in candidates {
@@ -333,9 +424,7 @@ pub fn generate_delta(
             let weak = Adler32::hash(partial_block);
 
             if let Some(candidates) = checksum_map.get(&weak) {
-                let mut hasher = xxhash_rust::xxh3::Xxh3::new();
-                hasher.update(partial_block);
-                let strong = hasher.digest();
+                let strong = StrongHash::Xxh3_64.hash(partial_block);
 
                 for checksum in candidates {
                     if checksum.size == partial_block.len() && checksum.strong == strong {
@@ -387,7 +476,7 @@ pub fn generate_delta(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::delta::compute_checksums;
+    use crate::delta::{compute_checksums, compute_checksums_with};
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -608,4 +697,30 @@ mod tests {
         assert_eq!(delta.ops.len(), 0);
         assert_eq!(delta.source_size, 0);
     }
+
+    #[test]
+    fn test_mapped_delta_matches_streaming() {
+        let mut source = NamedTempFile::new().unwrap();
+        let mut dest = NamedTempFile::new().unwrap();
+
+        // Over two read chunks, with an edit in the middle and a short tail
+        let old: Vec<u8> = (0..600_000u32).map(|i| (i * 31 % 253) as u8).collect();
+        let mut new = old.clone();
+        new[300_000..300_100].fill(0xFF);
+        new.extend_from_slice(b"tail");
+        source.write_all(&new).unwrap();
+        dest.write_all(&old).unwrap();
+        source.flush().unwrap();
+        dest.flush().unwrap();
+
+        let strong = StrongHash::default();
+        let checksums = compute_checksums_with(dest.path(), 2048, strong).unwrap();
+        let streamed =
+            generate_delta_streaming_with(source.path(), &checksums, 2048, strong).unwrap();
+        let mapped = generate_delta_mapped(source.path(), &checksums, 2048, strong).unwrap();
+
+        assert_eq!(mapped.ops, streamed.ops);
+        assert_eq!(mapped.source_size, new.len() as u64);
+        assert!(mapped.compression_ratio() < 0.01);
+    }
 }
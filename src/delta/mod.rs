@@ -8,18 +8,29 @@ pub mod rolling;
 #[allow(unused_imports)]
 pub use applier::apply_delta;
 #[allow(unused_imports)]
-pub use checksum::{compute_checksums, BlockChecksum};
+pub use checksum::{
+    compute_checksums, compute_checksums_mapped, compute_checksums_with, BlockChecksum, StrongHash,
+};
 #[allow(unused_imports)]
-pub use generator::{generate_delta, generate_delta_streaming, Delta, DeltaOp};
+pub use generator::{
+    generate_delta, generate_delta_mapped, generate_delta_streaming, generate_delta_streaming_with,
+    Delta, DeltaOp,
+};
 #[allow(unused_imports)]
 pub use ratio::{estimate_change_ratio, ChangeRatioResult};
 pub use rolling::Adler32;
 
+/// Smallest block size `calculate_block_size` picks (and `--block-size` takes)
+pub const MIN_BLOCK_SIZE: usize = 512;
+
+/// Largest block size `calculate_block_size` picks (and `--block-size` takes)
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
 /// Default block size calculation: sqrt(filesize)
 /// Capped between 512 bytes and 128KB
 pub fn calculate_block_size(file_size: u64) -> usize {
     let size = (file_size as f64).sqrt() as usize;
-    size.clamp(512, 128 * 1024)
+    size.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
 }
 
 #[cfg(test)]
@@ -25,12 +25,82 @@ pub struct Adler32 {
 
 const MOD_ADLER: u32 = 65521; // Largest prime < 2^16
 
+/// Bytes folded per vector step. Lane-wise u32 arithmetic over 16 lanes is
+/// the shape LLVM auto-vectorizes (SSE2/AVX2/NEON) without unsafe intrinsics.
+const LANES: usize = 16;
+
+/// Vector steps per reduction chunk. Per-lane `q` accumulators grow as
+/// 255 * n^2 / 2, which stays below u32::MAX for n = 4096.
+const STEPS_PER_CHUNK: usize = 4096;
+
+/// Fold `data` into the running (a, b) sums.
+///
+/// The byte-serial form (`a += x; b += a`) is a dependency chain the CPU
+/// can't parallelize. Instead, bytes are treated as rows of 16 lanes:
+/// `p[i]` sums lane i, and `q[i]` sums lane i weighted by how many rows
+/// follow it. For L bytes in N rows this recovers the serial result as:
+///
+/// ```text
+/// a' = a + sum(p)
+/// b' = b + L*a + 16 * sum(q + p) - sum(i * p[i])
+/// ```
+#[inline]
+fn fold(mut a: u32, mut b: u32, data: &[u8]) -> (u32, u32) {
+    let mut chunks = data.chunks_exact(LANES * STEPS_PER_CHUNK);
+    for chunk in &mut chunks {
+        (a, b) = fold_rows(a, b, chunk);
+    }
+
+    let rest = chunks.remainder();
+    let rows = rest.len() / LANES * LANES;
+    (a, b) = fold_rows(a, b, &rest[..rows]);
+
+    // Fewer than 16 trailing bytes: serial update, a and b are < MOD_ADLER
+    for &byte in &rest[rows..] {
+        a += byte as u32;
+        b += a;
+    }
+    (a % MOD_ADLER, b % MOD_ADLER)
+}
+
+/// Fold whole 16-byte rows (at most `STEPS_PER_CHUNK` of them).
+#[inline]
+fn fold_rows(a: u32, b: u32, rows: &[u8]) -> (u32, u32) {
+    let mut p = [0u32; LANES];
+    let mut q = [0u32; LANES];
+
+    for row in rows.chunks_exact(LANES) {
+        let row: &[u8; LANES] = row.try_into().expect("chunks_exact yields LANES bytes");
+        for ((p, q), &byte) in p.iter_mut().zip(q.iter_mut()).zip(row) {
+            *q += *p;
+            *p += byte as u32;
+        }
+    }
+
+    let mut sum_p = 0u64;
+    let mut sum_q = 0u64;
+    let mut sum_ip = 0u64;
+    for i in 0..LANES {
+        sum_p += p[i] as u64;
+        sum_q += q[i] as u64;
+        sum_ip += i as u64 * p[i] as u64;
+    }
+
+    let len = rows.len() as u64;
+    let a64 = a as u64 + sum_p;
+    let b64 = b as u64 + len * a as u64 + LANES as u64 * (sum_q + sum_p) - sum_ip;
+    (
+        (a64 % MOD_ADLER as u64) as u32,
+        (b64 % MOD_ADLER as u64) as u32,
+    )
+}
+
 impl Adler32 {
     /// Create a new Adler-32 hasher
     pub fn new(block_size: usize) -> Self {
         // Precompute lookup table for (block_size * i) % MOD_ADLER
         let mut n_mod_table = [0u32; 256];
-        let n = block_size as u32;
+        let n = (block_size % MOD_ADLER as usize) as u32;
         for (i, entry) in n_mod_table.iter_mut().enumerate() {
             *entry = (n * (i as u32)) % MOD_ADLER;
         }
@@ -45,96 +115,49 @@ impl Adler32 {
 
     /// Hash a block of data (non-rolling)
     pub fn hash(data: &[u8]) -> u32 {
-        let mut a: u32 = 1;
-        let mut b: u32 = 0;
-
-        // Process in chunks to defer modulo
-        // N=5552: largest n such that 255 * n * (n+1) / 2 + (n+1) * (MOD_ADLER-1) <= 2^32 - 1
-        // Safe value is 3800 to be conservative with B accumulation starting from non-zero
-        const CHUNK_SIZE: usize = 3800;
-
-        for chunk in data.chunks(CHUNK_SIZE) {
-            for &byte in chunk {
-                a += byte as u32;
-                b += a;
-            }
-            a %= MOD_ADLER;
-            b %= MOD_ADLER;
-        }
-
+        let (a, b) = fold(1, 0, data);
         (b << 16) | a
     }
 
     /// Initialize with a full block
     pub fn update_block(&mut self, block: &[u8]) {
-        self.a = 1;
-        self.b = 0;
-
-        const CHUNK_SIZE: usize = 3800;
-
-        for chunk in block.chunks(CHUNK_SIZE) {
-            for &byte in chunk {
-                self.a += byte as u32;
-                self.b += self.a;
-            }
-            self.a %= MOD_ADLER;
-            self.b %= MOD_ADLER;
-        }
+        let (a, b) = fold(1, 0, block);
+        self.a = a;
+        self.b = b;
     }
 
     /// Roll the hash: remove old byte, add new byte
     /// This is the key operation for rsync algorithm
     ///
-    /// Optimized implementation using lookup table and conditional subtraction
-    /// to avoid expensive division/modulo operations.
+    /// Uses a lookup table and bounded conditional subtraction to avoid
+    /// division/modulo on the per-byte path.
     #[inline]
     pub fn roll(&mut self, old_byte: u8, new_byte: u8) {
         let old = old_byte as u32;
         let new = new_byte as u32;
 
-        // Update A: remove old byte, add new byte
-        // Formula: A = (A - old + new) % M
-        // Logic:
-        // 1. A + new can overflow 65521 slightly (max 65520 + 255 = 65775)
-        // 2. Subtract old.
-        // 3. If negative (wrapped), add MOD_ADLER.
-
-        let mut a = self.a + new;
-        if old > a {
-            // Handle wrap-around conceptually (a - old < 0)
-            a += MOD_ADLER;
+        // Update A: A = (A - old + new) % M
+        // Adding M up front keeps the intermediate non-negative; the result
+        // is < 2M + 255, so at most two conditional subtractions normalize it.
+        let mut a = self.a + MOD_ADLER + new - old;
+        if a >= MOD_ADLER {
+            a -= MOD_ADLER;
         }
-        a -= old;
         if a >= MOD_ADLER {
             a -= MOD_ADLER;
         }
         self.a = a;
 
-        // Update B: remove contribution of old byte across all positions
-        // Formula: B = (B - n*old + A - 1) % M
-        //
-        // 1. B + A - 1. Max ~131000.
-        // 2. Subtract (n*old)%M from lookup table.
-        // 3. Normalize.
-
-        let n_old_mod = self.n_mod_table[old_byte as usize];
-
-        let mut b = self.b + self.a;
-        // b + a can be up to ~131040.
-        // We want to subtract (n_old_mod + 1).
-        // n_old_mod is < 65521. 1 is 1.
-        // So we subtract up to 65522.
-
-        let sub = n_old_mod + 1;
-        if sub > b {
-            b += MOD_ADLER * 2; // Add enough multiples to ensure positivity
+        // Update B: B = (B - n*old + A - 1) % M
+        // (n*old) % M comes from the lookup table, so the subtrahend is at
+        // most M. B + A + M - sub lies in [0, 3M): two branch-free-friendly
+        // conditional subtractions replace the old normalization loop.
+        let sub = self.n_mod_table[old_byte as usize] + 1;
+        let mut b = self.b + a + MOD_ADLER - sub;
+        if b >= MOD_ADLER {
+            b -= MOD_ADLER;
         }
-        b -= sub;
-
-        // Now b is positive. Normalize.
-        // Since we added at most 2*M, and original sum was ~2*M, result is ~4*M max.
-        // Fast modulo by subtraction loop (at most 3-4 iterations)
-        while b >= MOD_ADLER {
+        if b >= MOD_ADLER {
             b -= MOD_ADLER;
         }
         self.b = b;
@@ -325,4 +348,42 @@ mod tests {
             );
         }
     }
+
+    /// Byte-at-a-time Adler-32, the reference [`Adler32::hash`] is checked
+    /// against
+    fn hash_scalar(data: &[u8]) -> u32 {
+        let mut a: u32 = 1;
+        let mut b: u32 = 0;
+        for &byte in data {
+            a = (a + byte as u32) % MOD_ADLER;
+            b = (b + a) % MOD_ADLER;
+        }
+        (b << 16) | a
+    }
+
+    #[test]
+    fn test_adler32_fast_matches_scalar() {
+        // Lengths straddle the 16-byte step and zlib's 5552-byte chunks
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i * 31 + 7) as u8).collect();
+        for len in [
+            0, 1, 15, 16, 17, 255, 5535, 5536, 5537, 5552, 5553, 11_104, 20_000,
+        ] {
+            assert_eq!(
+                Adler32::hash(&data[..len]),
+                hash_scalar(&data[..len]),
+                "Mismatch at length {}",
+                len
+            );
+        }
+
+        // All-0xFF maximizes the per-lane accumulators
+        let ones = vec![0xFFu8; 300_000];
+        assert_eq!(Adler32::hash(&ones), hash_scalar(&ones));
+    }
+
+    #[test]
+    fn test_adler32_matches_reference_value() {
+        // Standard Adler-32 test vector
+        assert_eq!(Adler32::hash(b"Wikipedia"), 0x11E6_0398);
+    }
 }
@@ -1,4 +1,5 @@
 use crate::resource::format_bytes;
+pub use crate::streaming::protocol::ErrorCode;
 use std::path::PathBuf;
 use thiserror::Error;
 
@@ -42,6 +43,35 @@ pub enum SyncError {
         hint: String,
     },
 
+    #[error("Server root not allowed: {path}\nThe remote sy only serves directories listed in SY_SERVER_ROOTS or [server] allowed_roots.")]
+    RootNotAllowed { path: PathBuf },
+
+    #[error("Not in a module: {path}\nThe sy daemon only serves the [daemon.modules] in its config file, as module or module/path.")]
+    NotAModule { path: PathBuf },
+
+    #[error("Module {module} is read-only\nThe sy daemon only accepts pushes to modules with read_only = false under [daemon.modules].")]
+    ReadOnlyModule { module: String },
+
+    #[error("Host not allowed: {host}\nThe sy daemon only accepts connections from its [daemon] hosts_allow.")]
+    HostNotAllowed { host: std::net::IpAddr },
+
+    #[error("Too many connections: the sy daemon serves {max} at once\nTry again later, or raise max_connections under [daemon].")]
+    TooManyConnections { max: usize },
+
+    #[error("Host key verification failed for {host}: it presented {fingerprint}, which doesn't match {expected}\nIf the key really changed, update that entry; otherwise someone may be intercepting the connection.")]
+    HostKeyMismatch {
+        host: String,
+        fingerprint: String,
+        expected: String,
+    },
+
+    #[error("Unknown host key for {host} ({fingerprint})\nWith --host-key-policy strict, hosts must already be in {known_hosts}. Add the key (ssh-keyscan, or connect once with accept-new) or pin its fingerprint under [ssh] fingerprints.")]
+    HostKeyUnknown {
+        host: String,
+        fingerprint: String,
+        known_hosts: PathBuf,
+    },
+
     #[error("Invalid path: {path}\nPaths must be valid UTF-8 and not contain invalid characters.")]
     InvalidPath { path: PathBuf },
 
@@ -86,6 +116,12 @@ pub enum SyncError {
         lock_file: String,
     },
 
+    #[error("Invalid manifest: {path}\nReason: {reason}\nRegenerate it with: sy manifest create <dir> > manifest.json")]
+    InvalidManifest { path: PathBuf, reason: String },
+
+    #[error("Manifest signature check failed: {path}\nReason: {reason}\nThe manifest or the tree it describes may have been tampered with.")]
+    ManifestSignature { path: PathBuf, reason: String },
+
     #[error("Database error: {0}\nCheck that the destination directory is writable.")]
     Database(String),
 
@@ -96,6 +132,32 @@ pub enum SyncError {
         expected_checksum: String,
         actual_checksum: String,
     },
+
+    #[error("No space left on the destination: {context}\nFree up space or reduce the amount of data to sync.")]
+    DiskFull { context: String },
+
+    #[error("Transfer digest mismatch (local {local:032x}, remote {remote:032x}): sender and receiver disagree on the set of changes")]
+    DigestMismatch { local: u128, remote: u128 },
+
+    #[error("Protocol error: {0}\nMake sure the same version of sy is installed on both ends.")]
+    Protocol(String),
+
+    #[error("Remote error{}: {message}", if path.is_empty() { String::new() } else { format!(" on {}", path) })]
+    Remote {
+        /// `None` if the peer sent a code this version doesn't know
+        code: Option<ErrorCode>,
+        path: String,
+        message: String,
+    },
+
+    #[error("Sync cancelled")]
+    Cancelled,
+
+    #[error("Refusing to delete {count} entries, more than --max-delete-count {max}\nCheck that the source isn't empty or unmounted, or raise --max-delete-count.")]
+    TooManyDeletes { count: u64, max: u64 },
+
+    #[error(transparent)]
+    Other(anyhow::Error),
 }
 
 impl From<bincode::Error> for SyncError {
@@ -110,7 +172,99 @@ impl From<fjall::Error> for SyncError {
     }
 }
 
+/// Recover a typed error from the streaming pipeline's `anyhow` chains
+///
+/// A `SyncError` anywhere in the chain wins; an `io::Error` is classified by
+/// kind and keeps the context chain as its message.
+impl From<anyhow::Error> for SyncError {
+    fn from(err: anyhow::Error) -> Self {
+        for (depth, cause) in err.chain().enumerate() {
+            if cause.is::<crate::streaming::channel::Cancelled>() {
+                return SyncError::Cancelled;
+            }
+            if let Some(typed) = cause.downcast_ref::<SyncError>() {
+                match typed {
+                    SyncError::Protocol(message) if depth > 0 => {
+                        return SyncError::Protocol(format!("{}: {}", err, message))
+                    }
+                    SyncError::Remote {
+                        code,
+                        path,
+                        message,
+                    } => {
+                        return SyncError::Remote {
+                            code: *code,
+                            path: path.clone(),
+                            message: message.clone(),
+                        }
+                    }
+                    // Taken out of the chain as is below
+                    _ => break,
+                }
+            }
+            if let Some(io_err) = cause.downcast_ref::<std::io::Error>() {
+                let context = format!("{:#}", err);
+                return match io_err.kind() {
+                    std::io::ErrorKind::StorageFull => SyncError::DiskFull { context },
+                    std::io::ErrorKind::PermissionDenied => SyncError::PermissionDenied {
+                        path: PathBuf::from(context),
+                    },
+                    kind => SyncError::Io(std::io::Error::new(kind, context)),
+                };
+            }
+        }
+        match err.downcast::<SyncError>() {
+            Ok(typed) => typed,
+            Err(err) => SyncError::Other(err),
+        }
+    }
+}
+
 impl SyncError {
+    /// Error from a peer's ERROR or FATAL frame
+    pub fn remote(code: u16, path: impl Into<String>, message: impl Into<String>) -> Self {
+        SyncError::Remote {
+            code: ErrorCode::from_u16(code),
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// The protocol error code this error is reported with over the wire
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            SyncError::SourceNotFound { .. } | SyncError::DestinationNotFound { .. } => {
+                ErrorCode::NotFound
+            }
+            SyncError::PermissionDenied { .. }
+            | SyncError::RootNotAllowed { .. }
+            | SyncError::NotAModule { .. }
+            | SyncError::ReadOnlyModule { .. }
+            | SyncError::HostNotAllowed { .. }
+            | SyncError::HostKeyMismatch { .. }
+            | SyncError::HostKeyUnknown { .. } => ErrorCode::PermissionDenied,
+            SyncError::InsufficientDiskSpace { .. } | SyncError::DiskFull { .. } => {
+                ErrorCode::DiskFull
+            }
+            SyncError::BlockCorruption { .. } | SyncError::DigestMismatch { .. } => {
+                ErrorCode::ChecksumMismatch
+            }
+            SyncError::Protocol(_) => ErrorCode::Protocol,
+            SyncError::Cancelled => ErrorCode::Cancelled,
+            SyncError::Remote { code, .. } => code.unwrap_or(ErrorCode::IoError),
+            SyncError::Io(source)
+            | SyncError::ReadDirError { source, .. }
+            | SyncError::CopyError { source, .. }
+            | SyncError::DeltaSyncError { source, .. } => match source.kind() {
+                std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+                std::io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+                std::io::ErrorKind::StorageFull => ErrorCode::DiskFull,
+                _ => ErrorCode::IoError,
+            },
+            _ => ErrorCode::IoError,
+        }
+    }
+
     /// Check if this error is retryable (network issues that might succeed on retry)
     pub fn is_retryable(&self) -> bool {
         matches!(
@@ -324,6 +478,57 @@ mod tests {
         assert!(!sync_err.is_retryable());
     }
 
+    #[test]
+    fn test_from_anyhow_classifies_io_errors() {
+        use anyhow::Context;
+
+        let full: anyhow::Result<()> = Err(std::io::Error::from(ErrorKind::StorageFull))
+            .context("Failed to write /dest/big.iso");
+        let err = SyncError::from(full.unwrap_err());
+        assert!(matches!(&err, SyncError::DiskFull { context } if context.contains("big.iso")));
+        assert_eq!(err.error_code(), ErrorCode::DiskFull);
+
+        let denied: anyhow::Result<()> =
+            Err(std::io::Error::from(ErrorKind::PermissionDenied)).context("Failed to open a");
+        let err = SyncError::from(denied.unwrap_err());
+        assert!(matches!(err, SyncError::PermissionDenied { .. }));
+        assert_eq!(err.error_code(), ErrorCode::PermissionDenied);
+
+        let missing = anyhow::Error::from(std::io::Error::from(ErrorKind::NotFound));
+        assert_eq!(SyncError::from(missing).error_code(), ErrorCode::NotFound);
+    }
+
+    #[test]
+    fn test_from_anyhow_keeps_typed_errors() {
+        use anyhow::Context;
+
+        let protocol: anyhow::Result<()> =
+            Err(SyncError::Protocol("Data payload too short".into())).context("Reading frame");
+        let err = SyncError::from(protocol.unwrap_err());
+        assert!(
+            matches!(&err, SyncError::Protocol(m) if m == "Reading frame: Data payload too short")
+        );
+        assert_eq!(err.error_code(), ErrorCode::Protocol);
+
+        let cancelled = anyhow::Error::from(crate::streaming::channel::Cancelled);
+        assert!(matches!(SyncError::from(cancelled), SyncError::Cancelled));
+
+        let opaque = SyncError::from(anyhow::anyhow!("something else"));
+        assert!(matches!(opaque, SyncError::Other(_)));
+        assert_eq!(opaque.error_code(), ErrorCode::IoError);
+    }
+
+    #[test]
+    fn test_remote_error_code() {
+        let err = SyncError::remote(ErrorCode::DiskFull as u16, "a.bin", "No space left");
+        assert_eq!(err.error_code(), ErrorCode::DiskFull);
+        assert!(err.to_string().contains("a.bin"));
+
+        let unknown = SyncError::remote(999, "", "new failure");
+        assert!(matches!(unknown, SyncError::Remote { code: None, .. }));
+        assert_eq!(unknown.error_code(), ErrorCode::IoError);
+    }
+
     #[test]
     fn test_from_ssh_io_error_context_preserved() {
         let io_err = std::io::Error::new(ErrorKind::ConnectionReset, "reset");
@@ -19,8 +19,7 @@ pub struct FilterRule {
     pub action: FilterAction,
     /// Compiled glob pattern
     pub pattern: glob::Pattern,
-    /// Original pattern string (for debugging)
-    #[allow(dead_code)] // Used for debugging and error messages
+    /// Original pattern string (for debugging, and sending rules to a peer)
     pub pattern_str: String,
     /// Whether pattern contains '/' (affects matching behavior)
     pub has_slash: bool,
@@ -137,6 +136,42 @@ impl FilterRule {
     }
 }
 
+/// What a filter callback decides for one entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)] // Library API (sy::Sync::builder().filter)
+pub enum FilterDecision {
+    /// Keep the entry (the size limits still apply)
+    Include,
+    /// Skip the entry; for a directory, everything below it too
+    Exclude,
+}
+
+/// What a filter callback knows about an entry
+#[derive(Debug, Clone)]
+#[allow(dead_code)] // Library API (sy::Sync::builder().filter)
+pub struct EntryMetadata {
+    pub size: u64,
+    pub modified: std::time::SystemTime,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+}
+
+impl From<&crate::sync::scanner::FileEntry> for EntryMetadata {
+    fn from(entry: &crate::sync::scanner::FileEntry) -> Self {
+        Self {
+            size: entry.size,
+            modified: entry.modified,
+            is_dir: entry.is_dir,
+            is_symlink: entry.is_symlink,
+        }
+    }
+}
+
+/// Selection logic supplied by library callers, consulted for every scanned
+/// entry the glob rules keep, with its path relative to the source root
+pub type FilterCallback =
+    std::sync::Arc<dyn Fn(&Path, &EntryMetadata) -> FilterDecision + Send + Sync>;
+
 /// Filter engine that processes include/exclude rules
 #[derive(Debug, Clone)]
 pub struct FilterEngine {
@@ -291,6 +326,31 @@ impl FilterEngine {
         !self.should_include(path, is_dir)
     }
 
+    /// Whether `path` or any directory above it is excluded, for callers
+    /// that don't walk the tree top-down and prune excluded directories
+    pub fn excludes_tree(&self, path: &Path, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        path.ancestors()
+            .take_while(|p| !p.as_os_str().is_empty())
+            .enumerate()
+            .any(|(i, p)| self.should_exclude(p, is_dir || i > 0))
+    }
+
+    /// The rules in `add_rule` syntax ("+ pattern" / "- pattern"), in
+    /// order, for rebuilding the same engine elsewhere (the server applies
+    /// a client's rules this way)
+    pub fn rules(&self) -> Vec<String> {
+        self.rules
+            .iter()
+            .map(|rule| match rule.action {
+                FilterAction::Include => format!("+ {}", rule.pattern_str),
+                FilterAction::Exclude => format!("- {}", rule.pattern_str),
+            })
+            .collect()
+    }
+
     /// Get number of rules
     #[allow(dead_code)] // Public API for filter introspection
     pub fn rule_count(&self) -> usize {
@@ -298,7 +358,6 @@ impl FilterEngine {
     }
 
     /// Check if filter has any rules
-    #[allow(dead_code)] // Public API for filter introspection
     pub fn is_empty(&self) -> bool {
         self.rules.is_empty()
     }
@@ -539,4 +598,40 @@ mod tests {
         assert!(filter2.should_include(Path::new("build/output.txt"), false)); // basename is "output.txt", not "build"
         assert!(filter2.should_include(Path::new("building"), false)); // basename is "building", not "build"
     }
+
+    #[test]
+    fn test_excludes_tree() {
+        let mut filter = FilterEngine::new();
+        filter.add_rule("secrets").unwrap();
+
+        assert!(filter.excludes_tree(Path::new("secrets"), true));
+        assert!(filter.excludes_tree(Path::new("secrets/deep/key.pem"), false));
+        assert!(filter.excludes_tree(Path::new("app/secrets/key.pem"), false));
+        assert!(!filter.excludes_tree(Path::new("app/config.toml"), false));
+        assert!(!FilterEngine::new().excludes_tree(Path::new("secrets"), true));
+    }
+
+    #[test]
+    fn test_rules_rebuild_the_same_engine() {
+        let mut filter = FilterEngine::new();
+        filter.add_rule("+ keep.log").unwrap();
+        filter.add_exclude("*.log").unwrap();
+        filter.add_include("src/").unwrap();
+
+        let rules = filter.rules();
+        assert_eq!(rules, vec!["+ keep.log", "- *.log", "+ src/"]);
+
+        let mut rebuilt = FilterEngine::new();
+        for rule in &rules {
+            rebuilt.add_rule(rule).unwrap();
+        }
+        for (path, is_dir) in [("keep.log", false), ("app.log", false), ("src", true)] {
+            assert_eq!(
+                rebuilt.should_include(Path::new(path), is_dir),
+                filter.should_include(Path::new(path), is_dir),
+                "{}",
+                path
+            );
+        }
+    }
 }
@@ -2,7 +2,9 @@
 ///
 /// This module provides platform-specific filesystem detection to enable intelligent
 /// strategy selection in delta sync operations.
-use std::path::Path;
+use crate::cli::SymlinkMode;
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
 
 /// Check if a filesystem supports copy-on-write (COW) reflinks
 ///
@@ -221,12 +223,884 @@ pub fn has_hard_links(_path: &Path) -> bool {
     false
 }
 
+/// Permission bits (including setuid, setgid and sticky) from `meta`
+#[cfg(unix)]
+pub fn permission_bits(meta: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+/// The Unix mode closest to `meta`'s read-only attribute
+#[cfg(not(unix))]
+pub fn permission_bits(meta: &std::fs::Metadata) -> u32 {
+    if meta.is_dir() {
+        0o755
+    } else if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// A macOS file's resource fork, as exposed through xattrs
+pub const RESOURCE_FORK_XATTR: &str = "com.apple.ResourceFork";
+
+/// Finder flags, type/creator codes and icon position, as an xattr
+pub const FINDER_INFO_XATTR: &str = "com.apple.FinderInfo";
+
+/// Linux file capabilities (what lets an unprivileged `ping` open raw sockets)
+pub const CAPABILITY_XATTR: &str = "security.capability";
+
+/// CAP_SETFCAP, needed to write `security.capability`
+const CAP_SETFCAP: u32 = 31;
+
+/// FinderInfo is a fixed-size record; macOS rejects any other length
+const FINDER_INFO_LEN: usize = 32;
+
+/// Largest xattr value Linux stores (XATTR_SIZE_MAX)
+const LINUX_XATTR_SIZE_MAX: usize = 64 * 1024;
+
+/// Namespaces Linux accepts xattr names in
+const LINUX_XATTR_NAMESPACES: &[&str] = &["user.", "trusted.", "security.", "system."];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum XattrPlatform {
+    Linux,
+    MacOs,
+    Other,
+}
+
+const XATTR_PLATFORM: XattrPlatform = if cfg!(target_os = "linux") {
+    XattrPlatform::Linux
+} else if cfg!(target_os = "macos") {
+    XattrPlatform::MacOs
+} else {
+    XattrPlatform::Other
+};
+
+/// Name to store a source xattr under on this platform, or why it can't be
+///
+/// macOS names have no namespace (`com.apple.ResourceFork`), while Linux only
+/// takes `user.`, `trusted.`, `security.` and `system.` names, so those are
+/// kept as `user.<name>` on Linux and get their original name back on a Mac.
+/// FinderInfo must be exactly 32 bytes, and Linux refuses values over 64 KiB,
+/// which a resource fork easily exceeds; macOS stores resource forks of any
+/// size.
+pub fn local_xattr_name<'a>(name: &'a str, value: &[u8]) -> Result<Cow<'a, str>, String> {
+    xattr_name_on(XATTR_PLATFORM, name, value)
+}
+
+fn xattr_name_on<'a>(
+    platform: XattrPlatform,
+    name: &'a str,
+    value: &[u8],
+) -> Result<Cow<'a, str>, String> {
+    let bare = name.strip_prefix("user.").unwrap_or(name);
+    if bare == FINDER_INFO_XATTR && value.len() != FINDER_INFO_LEN {
+        return Err(format!(
+            "FinderInfo must be {} bytes, got {}",
+            FINDER_INFO_LEN,
+            value.len()
+        ));
+    }
+
+    match platform {
+        _ if name == CAPABILITY_XATTR && platform != XattrPlatform::Linux => {
+            Err("file capabilities only exist on Linux".to_string())
+        }
+        XattrPlatform::Linux => {
+            if value.len() > LINUX_XATTR_SIZE_MAX {
+                let what = if bare == RESOURCE_FORK_XATTR {
+                    "resource fork"
+                } else {
+                    "value"
+                };
+                return Err(format!(
+                    "{} of {} bytes is over Linux's {} byte xattr limit",
+                    what,
+                    value.len(),
+                    LINUX_XATTR_SIZE_MAX
+                ));
+            }
+            if LINUX_XATTR_NAMESPACES.iter().any(|ns| name.starts_with(ns)) {
+                Ok(Cow::Borrowed(name))
+            } else {
+                Ok(Cow::Owned(format!("user.{}", name)))
+            }
+        }
+        XattrPlatform::MacOs if bare.starts_with("com.apple.") => Ok(Cow::Borrowed(bare)),
+        _ => Ok(Cow::Borrowed(name)),
+    }
+}
+
+/// Set an xattr read from a source file, mapped with [`local_xattr_name`]
+///
+/// File capabilities need CAP_SETFCAP; without it they're skipped, with a
+/// single warning per process rather than one per file. Rewriting a file or
+/// changing its owner drops them, so xattrs go on after data and ownership.
+#[cfg(unix)]
+pub fn set_xattr(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+    let name = local_xattr_name(name, value)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    if name == CAPABILITY_XATTR && !has_cap_setfcap() {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| {
+            tracing::warn!(
+                "Not restoring file capabilities (security.capability, e.g. on {}): \
+                 that needs CAP_SETFCAP, so run as root to keep binaries like ping working",
+                path.display()
+            );
+        });
+        tracing::debug!("Skipping security.capability on {}", path.display());
+        return Ok(());
+    }
+    xattr::set(path, name.as_ref(), value)
+}
+
+/// Set the access ACL of `path` from its text form, one entry per line
+///
+/// A filesystem without ACL support (mounted without `acl`, FAT, tmpfs on
+/// older kernels) gets the file's mode bits only, with a single warning per
+/// process rather than one per file.
+#[cfg(all(unix, feature = "acl"))]
+pub fn set_acl(path: &Path, text: &str) -> std::io::Result<()> {
+    use std::str::FromStr;
+
+    let entries = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            exacl::AclEntry::from_str(line).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid ACL entry '{}': {}", line, e),
+                )
+            })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    match exacl::setfacl(&[path], &entries, None) {
+        // Linux has one errno for both, macOS two
+        Err(e) if [Some(libc::EOPNOTSUPP), Some(libc::ENOTSUP)].contains(&e.raw_os_error()) => {
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            WARNED.call_once(|| {
+                tracing::warn!(
+                    "Not restoring ACLs (e.g. on {}): the filesystem doesn't support them",
+                    path.display()
+                );
+            });
+            tracing::debug!("Skipping ACL on {}", path.display());
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(not(all(unix, feature = "acl")))]
+pub fn set_acl(_path: &Path, _text: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Whether this process may set file capabilities (CAP_SETFCAP is effective)
+#[cfg(unix)]
+pub fn has_cap_setfcap() -> bool {
+    static HAS_CAP: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *HAS_CAP.get_or_init(|| {
+        std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| effective_caps(&status))
+            .is_some_and(|caps| caps & (1 << CAP_SETFCAP) != 0)
+    })
+}
+
+/// The CapEff mask from /proc/<pid>/status
+#[cfg(unix)]
+fn effective_caps(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+}
+
+/// Owner uid and group gid from `meta`; `None` where there are none
+#[cfg(unix)]
+pub fn ownership(meta: &std::fs::Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.uid(), meta.gid()))
+}
+
+#[cfg(not(unix))]
+pub fn ownership(_meta: &std::fs::Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+/// Change the owner and/or group of `path` without following a symlink
+///
+/// `None` leaves that id alone. Through fchownat(2) rather than lchown,
+/// which not every architecture has as a system call.
+#[cfg(unix)]
+pub fn set_ownership(path: &Path, uid: Option<u32>, gid: Option<u32>) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    // -1 leaves an id unchanged
+    let uid = uid.map_or(libc::uid_t::MAX, |uid| uid as libc::uid_t);
+    let gid = gid.map_or(libc::gid_t::MAX, |gid| gid as libc::gid_t);
+    // SAFETY: NUL-terminated path
+    if unsafe {
+        libc::fchownat(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            uid,
+            gid,
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_ownership(_path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Whether this process can give files away to other users
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    // SAFETY: geteuid can't fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    false
+}
+
+/// Set BSD file flags (chflags(2): `hidden`, `nodump`, `uchg`, ...)
+///
+/// Replaces all flags; 0 clears them. macOS only, a no-op elsewhere.
+#[cfg(target_os = "macos")]
+pub fn set_bsd_flags(path: &Path, flags: u32) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: NUL-terminated path
+    if unsafe { libc::chflags(c_path.as_ptr(), flags as _) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// BSD file flags are only restored on macOS
+#[cfg(not(target_os = "macos"))]
+pub fn set_bsd_flags(_path: &Path, _flags: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// FILE_ATTRIBUTE bits carried between Windows hosts: READONLY, HIDDEN,
+/// SYSTEM and ARCHIVE
+///
+/// Other bits (compressed, encrypted, sparse, reparse point, ...) describe how
+/// the file is stored rather than what applications expect, so they're left to
+/// the destination filesystem.
+#[cfg_attr(not(windows), allow(dead_code))] // Only read on Windows
+pub const WINDOWS_ATTRIBUTE_MASK: u32 = 0x1 | 0x2 | 0x4 | 0x20;
+
+/// Replace the [`WINDOWS_ATTRIBUTE_MASK`] bits of `path` with those in `attrs`
+///
+/// Callers apply this last: once READONLY is on, the file can't be written
+/// (its times still can, through [`set_mtime`]).
+#[cfg(windows)]
+pub fn set_windows_attributes(path: &Path, attrs: u32) -> std::io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{SetFileAttributesW, FILE_ATTRIBUTE_NORMAL};
+
+    let current = std::fs::symlink_metadata(path)?.file_attributes();
+    let mut wanted = (current & !WINDOWS_ATTRIBUTE_MASK) | (attrs & WINDOWS_ATTRIBUTE_MASK);
+    if wanted == 0 {
+        // Only valid on its own, and what "no attributes" has to be spelled as
+        wanted = FILE_ATTRIBUTE_NORMAL;
+    }
+    if wanted == current {
+        return Ok(());
+    }
+
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    if unsafe { SetFileAttributesW(wide_path.as_ptr(), wanted) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Windows attributes only exist on Windows
+#[cfg(not(windows))]
+pub fn set_windows_attributes(_path: &Path, _attrs: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Set the modification time of `path`, following symlinks
+#[cfg(not(windows))]
+pub fn set_mtime(path: &Path, mtime: filetime::FileTime) -> std::io::Result<()> {
+    filetime::set_file_mtime(path, mtime)
+}
+
+/// Set the modification time of `path` with SetFileTime
+#[cfg(windows)]
+pub fn set_mtime(path: &Path, mtime: filetime::FileTime) -> std::io::Result<()> {
+    set_file_times(path, None, None, Some(mtime))
+}
+
+/// Set the access time of `path` and, if given, its creation time,
+/// following symlinks
+///
+/// Linux has no way to set a creation time, so there only the access time
+/// changes.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn set_times(
+    path: &Path,
+    atime: filetime::FileTime,
+    _btime: Option<filetime::FileTime>,
+) -> std::io::Result<()> {
+    filetime::set_file_atime(path, atime)
+}
+
+/// Set the access time of `path` and, if given, its creation time (with
+/// setattrlist(2)), following symlinks
+#[cfg(target_os = "macos")]
+pub fn set_times(
+    path: &Path,
+    atime: filetime::FileTime,
+    btime: Option<filetime::FileTime>,
+) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    filetime::set_file_atime(path, atime)?;
+    let Some(btime) = btime else {
+        return Ok(());
+    };
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut attrs = libc::attrlist {
+        bitmapcount: libc::ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: libc::ATTR_CMN_CRTIME,
+        volattr: 0,
+        dirattr: 0,
+        fileattr: 0,
+        forkattr: 0,
+    };
+    let mut crtime = libc::timespec {
+        tv_sec: btime.unix_seconds() as libc::time_t,
+        tv_nsec: btime.nanoseconds() as libc::c_long,
+    };
+    // SAFETY: NUL-terminated path; the buffer is the one timespec asked for
+    let rc = unsafe {
+        libc::setattrlist(
+            c_path.as_ptr(),
+            (&mut attrs as *mut libc::attrlist).cast(),
+            (&mut crtime as *mut libc::timespec).cast(),
+            std::mem::size_of::<libc::timespec>(),
+            0,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set the access time of `path` and, if given, its creation time with
+/// SetFileTime
+#[cfg(windows)]
+pub fn set_times(
+    path: &Path,
+    atime: filetime::FileTime,
+    btime: Option<filetime::FileTime>,
+) -> std::io::Result<()> {
+    set_file_times(path, btime, Some(atime), None)
+}
+
+/// SetFileTime on `path`; `None` leaves that time as it is
+///
+/// The handle only asks for FILE_WRITE_ATTRIBUTES, which Windows grants on
+/// READONLY files too (opening them for writing, as `filetime` does, is
+/// refused), and BACKUP_SEMANTICS lets it open directories.
+#[cfg(windows)]
+fn set_file_times(
+    path: &Path,
+    created: Option<filetime::FileTime>,
+    accessed: Option<filetime::FileTime>,
+    written: Option<filetime::FileTime>,
+) -> std::io::Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::FILETIME;
+    use windows_sys::Win32::Storage::FileSystem::{
+        SetFileTime, FILE_FLAG_BACKUP_SEMANTICS, FILE_WRITE_ATTRIBUTES,
+    };
+
+    /// Seconds from 1601-01-01, where FILETIME counts from, to 1970-01-01
+    const FILETIME_UNIX_EPOCH: i64 = 11_644_473_600;
+
+    // 100ns intervals; FILETIME can't go before 1601
+    let to_filetime = |time: filetime::FileTime| {
+        let intervals = (time
+            .unix_seconds()
+            .saturating_add(FILETIME_UNIX_EPOCH)
+            .max(0) as u64)
+            .saturating_mul(10_000_000)
+            .saturating_add(u64::from(time.nanoseconds() / 100));
+        FILETIME {
+            dwLowDateTime: intervals as u32,
+            dwHighDateTime: (intervals >> 32) as u32,
+        }
+    };
+    let (created, accessed, written) = (
+        created.map(to_filetime),
+        accessed.map(to_filetime),
+        written.map(to_filetime),
+    );
+    let ptr = |time: &Option<FILETIME>| time.as_ref().map_or(std::ptr::null(), |t| t as *const _);
+
+    let file = std::fs::OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)?;
+    // SAFETY: the handle is open for the duration of the call, and the
+    // FILETIMEs outlive it
+    let ok = unsafe {
+        SetFileTime(
+            file.as_raw_handle() as _,
+            ptr(&created),
+            ptr(&accessed),
+            ptr(&written),
+        )
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Device names Windows reserves in every directory, whatever the extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Why Windows would store `path` as something other than the file it
+/// names, if it would
+///
+/// A `:` makes the rest of a name an alternate data stream of the file
+/// before it, a reserved device name (`NUL`, `com1.txt`, ...) opens the
+/// device, and a trailing dot or space is dropped, so `a.` overwrites `a`.
+/// [`fat_safe_path`] renames all but the device names.
+pub fn windows_name_problem(path: &Path) -> Option<&'static str> {
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        let name = name.to_string_lossy();
+        if name.contains(':') {
+            return Some("Names with ':' (alternate data streams) not allowed on Windows");
+        }
+        let stem = name.split('.').next().unwrap_or("").trim_end_matches(' ');
+        if WINDOWS_RESERVED_NAMES
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        {
+            return Some("Reserved device names not allowed on Windows");
+        }
+        if name.ends_with(['.', ' ']) {
+            return Some("Names ending in a dot or space not allowed on Windows");
+        }
+    }
+    None
+}
+
+/// FAT stores modification times with 2-second resolution (`--fat`)
+pub const FAT_MODIFY_WINDOW: u64 = 2;
+
+/// Characters FAT and exFAT refuse in file names, besides control characters
+const FAT_FORBIDDEN_CHARS: &[char] = &['"', '*', ':', '<', '>', '?', '\\', '|'];
+
+/// Largest time zone offset (UTC+14) a FAT timestamp can be shifted by
+const FAT_MAX_ZONE_OFFSET: u64 = 14 * 3600;
+
+/// Rewrite `path` so every component is a valid FAT file name (`--fat`)
+///
+/// Forbidden characters become `_`, as does a trailing dot or space (Windows
+/// strips those, so `a.` and `a` would collide). Already valid names, and
+/// names this has been applied to, come back unchanged.
+pub fn fat_safe_path(path: &Path) -> Cow<'_, Path> {
+    let fixed = |c: Component<'_>| match c {
+        Component::Normal(name) => fat_safe_name(&name.to_string_lossy()),
+        _ => None,
+    };
+    if !path.components().any(|c| fixed(c).is_some()) {
+        return Cow::Borrowed(path);
+    }
+    let mut safe = PathBuf::new();
+    for component in path.components() {
+        match fixed(component) {
+            Some(name) => safe.push(name),
+            None => safe.push(component.as_os_str()),
+        }
+    }
+    Cow::Owned(safe)
+}
+
+/// The FAT-safe spelling of `name`, or None if it's already valid
+fn fat_safe_name(name: &str) -> Option<String> {
+    let forbidden = |c: char| c.is_control() || FAT_FORBIDDEN_CHARS.contains(&c);
+    let trailing = name.ends_with(['.', ' ']);
+    if !trailing && !name.contains(forbidden) {
+        return None;
+    }
+    let mut fixed: String = name
+        .chars()
+        .map(|c| if forbidden(c) { '_' } else { c })
+        .collect();
+    if trailing {
+        fixed.pop();
+        fixed.push('_');
+    }
+    Some(fixed)
+}
+
+/// Whether two mtimes (Unix seconds and nanoseconds) are the same
+///
+/// Nanoseconds only count when both sides have them: a peer too old to
+/// send them, or a filesystem that keeps whole seconds, reads as 0.
+pub fn mtime_matches(a: i64, a_nsec: u32, b: i64, b_nsec: u32) -> bool {
+    a == b && (a_nsec == b_nsec || a_nsec == 0 || b_nsec == 0)
+}
+
+/// Whether two Unix mtimes (seconds) count as equal on a FAT destination
+///
+/// Beyond the 2-second window, FAT keeps local time, so a time zone or DST
+/// change shows up as a whole offset of 15-minute steps on every file. Such
+/// mtimes are unreliable and the comparison falls back to size alone (the
+/// caller has already checked sizes).
+pub fn fat_mtime_matches(a: i64, b: i64) -> bool {
+    let diff = a.abs_diff(b);
+    if diff <= FAT_MODIFY_WINDOW {
+        return true;
+    }
+    let off_step = diff % 900;
+    diff <= FAT_MAX_ZONE_OFFSET
+        && (off_step <= FAT_MODIFY_WINDOW || 900 - off_step <= FAT_MODIFY_WINDOW)
+}
+
+/// What [`create_symlink`] ended up putting at the link path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkOutcome {
+    /// A real symlink
+    Linked,
+    /// A copy of the target, because the link couldn't be created
+    Copied,
+    /// Nothing; the link was dropped with a warning
+    Skipped,
+}
+
+/// Create `link` pointing at `target`, falling back per `links` where the
+/// platform won't allow it
+///
+/// Windows needs `symlink_dir` for directory targets and `symlink_file`
+/// otherwise, so the target is resolved relative to the link's parent to
+/// pick one. Creating symlinks there also needs SeCreateSymbolicLinkPrivilege
+/// (or Developer Mode); without it the link is replaced by a copy of its
+/// target, or skipped with a warning under `--links skip` or when the target
+/// can't be copied.
+pub fn create_symlink(
+    target: &Path,
+    link: &Path,
+    links: SymlinkMode,
+) -> std::io::Result<SymlinkOutcome> {
+    match platform_symlink(target, link) {
+        Ok(()) => Ok(SymlinkOutcome::Linked),
+        Err(e) if is_privilege_error(&e) => {
+            tracing::debug!("Cannot create symlink {}: {}", link.display(), e);
+            Ok(symlink_fallback(target, link, links))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn platform_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn platform_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    if resolve_link_target(target, link).is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn platform_symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+/// ERROR_PRIVILEGE_NOT_HELD, what Windows returns without the symlink privilege
+const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+fn is_privilege_error(e: &std::io::Error) -> bool {
+    cfg!(windows)
+        && (e.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD)
+            || e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+/// Where `target` points, as seen from `link` (relative targets are relative
+/// to the link's directory, not the working directory)
+fn resolve_link_target(target: &Path, link: &Path) -> PathBuf {
+    match link.parent() {
+        Some(parent) if target.is_relative() => parent.join(target),
+        _ => target.to_path_buf(),
+    }
+}
+
+fn symlink_fallback(target: &Path, link: &Path, links: SymlinkMode) -> SymlinkOutcome {
+    if links == SymlinkMode::Skip {
+        tracing::warn!(
+            "Skipping symlink {} -> {}: symlinks can't be created here",
+            link.display(),
+            target.display()
+        );
+        return SymlinkOutcome::Skipped;
+    }
+
+    let resolved = resolve_link_target(target, link);
+    let copied = if resolved.is_dir() {
+        copy_dir_all(&resolved, link)
+    } else {
+        std::fs::copy(&resolved, link).map(|_| ())
+    };
+    match copied {
+        Ok(()) => {
+            tracing::warn!(
+                "Copied {} in place of symlink {}: symlinks can't be created here",
+                resolved.display(),
+                link.display()
+            );
+            SymlinkOutcome::Copied
+        }
+        Err(e) => {
+            tracing::warn!(
+                "Skipping symlink {} -> {}: symlinks can't be created here and the target can't be copied: {}",
+                link.display(),
+                target.display(),
+                e
+            );
+            SymlinkOutcome::Skipped
+        }
+    }
+}
+
+fn copy_dir_all(src: &Path, dest: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dest)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let to = dest.join(entry.file_name());
+        // Only follows the top-level link; nested links would risk cycles
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir_all(&entry.path(), &to)?;
+        } else if file_type.is_file() {
+            std::fs::copy(entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_windows_name_problem() {
+        assert_eq!(windows_name_problem(Path::new("docs/a b/c.txt")), None);
+        assert_eq!(windows_name_problem(Path::new("console.log")), None);
+        assert_eq!(windows_name_problem(Path::new("dir/.hidden")), None);
+
+        for bad in [
+            "a.txt:evil",
+            "dir/file::$DATA",
+            "nul",
+            "x/COM1.txt",
+            "aux .c",
+            "a.",
+            "b ",
+        ] {
+            assert!(windows_name_problem(Path::new(bad)).is_some(), "{}", bad);
+        }
+        // --fat renames everything but device names
+        assert_eq!(
+            windows_name_problem(&fat_safe_path(Path::new("a:b/c."))),
+            None
+        );
+        assert!(windows_name_problem(&fat_safe_path(Path::new("lpt3"))).is_some());
+    }
+
+    #[test]
+    fn test_fat_safe_path() {
+        let clean = Path::new("photos/2024/img.jpg");
+        assert!(matches!(fat_safe_path(clean), Cow::Borrowed(_)));
+
+        assert_eq!(
+            fat_safe_path(Path::new("notes/a:b?.txt")),
+            Path::new("notes/a_b_.txt")
+        );
+        assert_eq!(
+            fat_safe_path(Path::new("dir. /what*\"\u{1}")),
+            Path::new("dir._/what___")
+        );
+        // Idempotent, so dest names and sanitized source names agree
+        let once = fat_safe_path(Path::new("x|y./z ")).into_owned();
+        assert_eq!(once, Path::new("x_y_/z_"));
+        assert_eq!(fat_safe_path(&once), once.as_path());
+    }
+
+    #[test]
+    fn test_mtime_matches() {
+        let t = 1_700_000_000;
+        assert!(mtime_matches(t, 500, t, 500));
+        assert!(!mtime_matches(t, 500, t, 501));
+        assert!(!mtime_matches(t, 0, t + 1, 0));
+        // Whole seconds on one side
+        assert!(mtime_matches(t, 0, t, 999_999_999));
+        assert!(mtime_matches(t, 123, t, 0));
+    }
+
+    #[test]
+    fn test_fat_mtime_matches() {
+        let t = 1_700_000_000;
+        assert!(fat_mtime_matches(t, t));
+        assert!(fat_mtime_matches(t, t + 2));
+        assert!(!fat_mtime_matches(t, t + 3));
+        assert!(!fat_mtime_matches(t, t - 600));
+
+        // Time zone and DST shifts fall back to comparing sizes
+        assert!(fat_mtime_matches(t, t + 3600));
+        assert!(fat_mtime_matches(t, t - 5 * 3600 - 1800 + 1));
+        assert!(!fat_mtime_matches(t, t + 15 * 3600));
+    }
+
+    #[test]
+    fn test_xattr_name_mapping() {
+        let finder_info = [0u8; FINDER_INFO_LEN];
+        let fork = vec![1u8; 1024 * 1024];
+
+        // macOS attributes get a namespace on Linux...
+        assert_eq!(
+            xattr_name_on(XattrPlatform::Linux, FINDER_INFO_XATTR, &finder_info).unwrap(),
+            "user.com.apple.FinderInfo"
+        );
+        assert_eq!(
+            xattr_name_on(XattrPlatform::Linux, "user.comment", b"x").unwrap(),
+            "user.comment"
+        );
+        // ...and lose it again on the way back
+        assert_eq!(
+            xattr_name_on(XattrPlatform::MacOs, "user.com.apple.ResourceFork", &fork).unwrap(),
+            RESOURCE_FORK_XATTR
+        );
+        assert_eq!(
+            xattr_name_on(XattrPlatform::MacOs, "user.comment", b"x").unwrap(),
+            "user.comment"
+        );
+
+        // Size limits
+        assert!(xattr_name_on(XattrPlatform::Linux, RESOURCE_FORK_XATTR, &fork).is_err());
+        assert!(xattr_name_on(XattrPlatform::MacOs, FINDER_INFO_XATTR, &[0u8; 16]).is_err());
+        assert!(xattr_name_on(XattrPlatform::Other, FINDER_INFO_XATTR, &finder_info).is_ok());
+
+        // Capabilities are Linux-only
+        assert!(xattr_name_on(XattrPlatform::Linux, CAPABILITY_XATTR, &[0u8; 20]).is_ok());
+        assert!(xattr_name_on(XattrPlatform::MacOs, CAPABILITY_XATTR, &[0u8; 20]).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_effective_caps() {
+        let root = "Name:\tping\nCapInh:\t0000000000000000\nCapEff:\t000001ffffffffff\n";
+        let caps = effective_caps(root).unwrap();
+        assert_ne!(caps & (1 << CAP_SETFCAP), 0);
+
+        let user = "CapPrm:\t0000000000000000\nCapEff:\t0000000000000000\n";
+        assert_eq!(effective_caps(user), Some(0));
+        assert_eq!(effective_caps("Name:\tsy\n"), None);
+    }
+
+    #[test]
+    fn test_resolve_link_target() {
+        assert_eq!(
+            resolve_link_target(Path::new("../a/b"), Path::new("/x/y/link")),
+            PathBuf::from("/x/y/../a/b")
+        );
+        assert_eq!(
+            resolve_link_target(Path::new("/abs"), Path::new("/x/link")),
+            PathBuf::from("/abs")
+        );
+    }
+
+    #[test]
+    fn test_symlink_fallback() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("file.txt"), b"data").unwrap();
+        fs::create_dir_all(temp.path().join("dir/sub")).unwrap();
+        fs::write(temp.path().join("dir/sub/inner.txt"), b"inner").unwrap();
+
+        // Relative targets resolve against the link's directory
+        let link = temp.path().join("file_link");
+        let outcome = symlink_fallback(Path::new("file.txt"), &link, SymlinkMode::Preserve);
+        assert_eq!(outcome, SymlinkOutcome::Copied);
+        assert_eq!(fs::read(&link).unwrap(), b"data");
+
+        let link = temp.path().join("dir_link");
+        let outcome = symlink_fallback(Path::new("dir"), &link, SymlinkMode::Follow);
+        assert_eq!(outcome, SymlinkOutcome::Copied);
+        assert_eq!(fs::read(link.join("sub/inner.txt")).unwrap(), b"inner");
+
+        let link = temp.path().join("skipped");
+        let outcome = symlink_fallback(Path::new("file.txt"), &link, SymlinkMode::Skip);
+        assert_eq!(outcome, SymlinkOutcome::Skipped);
+        assert!(!link.exists());
+
+        let link = temp.path().join("dangling");
+        let outcome = symlink_fallback(Path::new("missing"), &link, SymlinkMode::Preserve);
+        assert_eq!(outcome, SymlinkOutcome::Skipped);
+        assert!(!link.exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_create_symlink() {
+        let temp = TempDir::new().unwrap();
+        let link = temp.path().join("link");
+        let outcome = create_symlink(Path::new("target"), &link, SymlinkMode::Preserve).unwrap();
+        assert_eq!(outcome, SymlinkOutcome::Linked);
+        assert_eq!(fs::read_link(&link).unwrap(), PathBuf::from("target"));
+    }
+
     #[test]
     fn test_cow_detection() {
         let temp = TempDir::new().unwrap();
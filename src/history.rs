@@ -0,0 +1,261 @@
+//! Record of past runs, for `sy history`
+//!
+//! Every sync that gets as far as starting is appended as one JSON line to
+//! `history.jsonl` under the user's data directory (`~/.local/share/sy/` on
+//! Linux), or to the file `SY_HISTORY` names; an empty `SY_HISTORY` turns
+//! recording off. Only the last [`MAX_RUNS`] runs are kept. Recording is best
+//! effort: a history that can't be written only logs a warning.
+
+use crate::notifier::Summary;
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Runs kept in the history
+pub const MAX_RUNS: usize = 1000;
+
+/// Failed paths kept per run
+const MAX_FAILED: usize = 20;
+
+/// Size past which the next record trims the file back to [`MAX_RUNS`] runs
+const TRIM_BYTES: u64 = 4 * 1024 * 1024;
+
+/// One recorded run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    /// RFC 3339, UTC
+    pub started_at: String,
+    pub profile: Option<String>,
+    pub source: String,
+    pub destination: String,
+    /// `ok`, `partial` (some files failed or vanished), `interrupted` or
+    /// `failed`
+    pub status: String,
+    /// Why the run failed outright
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub files_scanned: u64,
+    pub files_created: u64,
+    pub files_updated: u64,
+    pub files_deleted: usize,
+    /// Source files deleted while the sync ran
+    #[serde(default)]
+    pub files_vanished: usize,
+    pub bytes_transferred: u64,
+    pub duration_secs: f64,
+    /// The first paths that failed to sync
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<String>,
+    /// How many paths failed, including those not listed in `failed`
+    #[serde(default)]
+    pub failed_count: usize,
+    pub dry_run: bool,
+}
+
+impl Run {
+    /// A run that started at `started` and ended as `summary` says, taking
+    /// `duration_secs` in all
+    pub fn new(started: SystemTime, summary: &Summary, duration_secs: f64) -> Self {
+        Self {
+            started_at: chrono::DateTime::<chrono::Utc>::from(started)
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            profile: summary.profile.clone(),
+            source: summary.source.clone(),
+            destination: summary.destination.clone(),
+            status: summary.status.to_string(),
+            error: summary.error.clone(),
+            files_scanned: summary.files_scanned,
+            files_created: summary.files_created,
+            files_updated: summary.files_updated,
+            files_deleted: summary.files_deleted,
+            files_vanished: summary.files_vanished,
+            bytes_transferred: summary.bytes_transferred,
+            duration_secs,
+            failed: summary.failed.iter().take(MAX_FAILED).cloned().collect(),
+            failed_count: summary.failed.len(),
+            dry_run: summary.dry_run,
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// The run history file
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    /// Default location: `$SY_HISTORY`, else `<data dir>/sy/history.jsonl`;
+    /// None when `SY_HISTORY` is set but empty
+    pub fn default_path() -> Result<Option<PathBuf>> {
+        if let Some(path) = std::env::var_os("SY_HISTORY") {
+            return Ok((!path.is_empty()).then(|| PathBuf::from(path)));
+        }
+        let data_dir = dirs::data_local_dir().context("Cannot determine data directory")?;
+        Ok(Some(data_dir.join("sy").join("history.jsonl")))
+    }
+
+    /// The history at `path`, which needn't exist yet
+    pub fn open(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Append `run`, trimming the oldest runs once the file has grown large
+    pub fn record(&self, run: &Run) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let mut line = serde_json::to_vec(run)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open history {}", self.path.display()))?;
+        file.write_all(&line)?;
+        if file.metadata()?.len() > TRIM_BYTES {
+            self.trim()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the file with only the last [`MAX_RUNS`] runs
+    fn trim(&self) -> Result<()> {
+        let content = std::fs::read_to_string(&self.path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        let kept = &lines[lines.len().saturating_sub(MAX_RUNS)..];
+        let temp = self.path.with_extension("jsonl.tmp");
+        std::fs::write(&temp, kept.join("\n") + "\n")?;
+        std::fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+
+    /// The most recent `limit` runs (all of them if `None`), oldest first
+    pub fn runs(&self, limit: Option<usize>) -> Result<Vec<Run>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()))
+            }
+        };
+        let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+        let skip = limit.map_or(0, |limit| lines.len().saturating_sub(limit));
+        lines[skip..]
+            .iter()
+            .map(|line| serde_json::from_str(line).context("Corrupt history record"))
+            .collect()
+    }
+}
+
+/// Record a run in the default history, logging rather than failing
+pub fn record(started: SystemTime, summary: &Summary) {
+    let duration_secs = started.elapsed().map_or(0.0, |d| d.as_secs_f64());
+    let run = Run::new(started, summary, duration_secs);
+    let result = History::default_path().and_then(|path| match path {
+        Some(path) => History::open(&path).record(&run),
+        None => Ok(()),
+    });
+    if let Err(e) = result {
+        tracing::warn!("Failed to record run in history: {:#}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    fn summary(source: &str, status: &'static str) -> Summary {
+        Summary {
+            profile: Some("nightly".to_string()),
+            source: source.to_string(),
+            destination: "backup:/srv".to_string(),
+            status,
+            error: None,
+            files_scanned: 10,
+            files_created: 2,
+            files_updated: 1,
+            files_deleted: 0,
+            files_vanished: 0,
+            bytes_transferred: 4096,
+            duration_secs: 0.0,
+            failed: Vec::new(),
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn test_history_returns_latest_runs_oldest_first() {
+        let dir = TempDir::new().unwrap();
+        let history = History::open(&dir.path().join("sy/history.jsonl"));
+        let t0 = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert!(history.runs(None).unwrap().is_empty());
+
+        for (secs, source) in [(0, "a"), (10, "b"), (20, "c")] {
+            let started = t0 + Duration::from_secs(secs);
+            let run = Run::new(started, &summary(source, "ok"), 1.5);
+            history.record(&run).unwrap();
+        }
+
+        let sources = |runs: Vec<Run>| runs.into_iter().map(|r| r.source).collect::<Vec<_>>();
+        assert_eq!(sources(history.runs(None).unwrap()), ["a", "b", "c"]);
+        assert_eq!(sources(history.runs(Some(2)).unwrap()), ["b", "c"]);
+
+        let run = &history.runs(Some(1)).unwrap()[0];
+        assert_eq!(run.started_at, "2023-11-14T22:13:40Z");
+        assert_eq!(run.profile.as_deref(), Some("nightly"));
+        assert_eq!(run.bytes_transferred, 4096);
+        assert!(run.succeeded());
+    }
+
+    #[test]
+    fn test_history_persists_across_opens() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let started = SystemTime::now();
+        {
+            let history = History::open(&path);
+            let run = Run::new(started, &summary("src", "failed"), 0.2);
+            history.record(&run).unwrap();
+        }
+
+        let runs = History::open(&path).runs(None).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert!(!runs[0].succeeded());
+    }
+
+    #[test]
+    fn test_history_caps_failed_paths_and_runs() {
+        let dir = TempDir::new().unwrap();
+        let history = History::open(&dir.path().join("history.jsonl"));
+        let mut failed = summary("src", "partial");
+        failed.failed = (0..100).map(|i| format!("file_{}", i)).collect();
+        let run = Run::new(SystemTime::now(), &failed, 0.1);
+        assert_eq!(run.failed.len(), MAX_FAILED);
+        assert_eq!(run.failed_count, 100);
+
+        history.record(&run).unwrap();
+        history.trim().unwrap();
+        assert_eq!(history.runs(None).unwrap(), [run]);
+
+        let ok = Run::new(SystemTime::now(), &summary("src", "ok"), 0.1);
+        for _ in 0..MAX_RUNS {
+            history.record(&ok).unwrap();
+        }
+        history.trim().unwrap();
+        let runs = history.runs(None).unwrap();
+        assert_eq!(runs.len(), MAX_RUNS);
+        assert!(runs.iter().all(Run::succeeded));
+    }
+}
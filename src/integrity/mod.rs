@@ -150,6 +150,47 @@ impl IntegrityVerifier {
     }
 }
 
+/// Selects a deterministic random subset of files for post-transfer verification
+///
+/// Selection hashes each relative path with the run's seed, so the same seed
+/// picks the same files (reproducible) while different runs cover different
+/// subsets over time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerifySample {
+    percent: f64,
+    seed: u64,
+}
+
+impl VerifySample {
+    /// Sample `percent` (0-100] of files using `seed`
+    pub fn new(percent: f64, seed: u64) -> Self {
+        Self {
+            percent: percent.clamp(0.0, 100.0),
+            seed,
+        }
+    }
+
+    pub fn percent(&self) -> f64 {
+        self.percent
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Whether the file at `relative_path` should be verified
+    pub fn selects(&self, relative_path: &Path) -> bool {
+        if self.percent >= 100.0 {
+            return true;
+        }
+        let hash = xxhash_rust::xxh3::xxh3_64_with_seed(
+            relative_path.to_string_lossy().as_bytes(),
+            self.seed,
+        );
+        (hash as f64 / u64::MAX as f64) * 100.0 < self.percent
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -336,4 +377,32 @@ mod tests {
         let corrupted = b"Corrupted checksum data";
         assert!(!verifier.verify_block(data, corrupted).unwrap());
     }
+
+    #[test]
+    fn test_verify_sample_deterministic() {
+        let sample = VerifySample::new(25.0, 42);
+        let paths: Vec<_> = (0..2000)
+            .map(|i| std::path::PathBuf::from(format!("dir/file{}.txt", i)))
+            .collect();
+
+        let picked: Vec<bool> = paths.iter().map(|p| sample.selects(p)).collect();
+        let again: Vec<bool> = paths.iter().map(|p| sample.selects(p)).collect();
+        assert_eq!(picked, again);
+
+        // Roughly 25% selected
+        let count = picked.iter().filter(|&&b| b).count();
+        assert!((400..600).contains(&count), "selected {}", count);
+
+        // A different seed picks a different subset
+        let other = VerifySample::new(25.0, 43);
+        let other_picked: Vec<bool> = paths.iter().map(|p| other.selects(p)).collect();
+        assert_ne!(picked, other_picked);
+    }
+
+    #[test]
+    fn test_verify_sample_bounds() {
+        let path = Path::new("a.txt");
+        assert!(VerifySample::new(100.0, 1).selects(path));
+        assert!(!VerifySample::new(0.0, 1).selects(path));
+    }
 }
@@ -0,0 +1,53 @@
+//! Stopping cleanly on SIGINT, SIGTERM and SIGHUP
+//!
+//! [`install`] hands out a token the first signal cancels. A sync holding it
+//! stops between files: unfinished files are removed (or kept with
+//! `--partial`), the resume state is saved, the peer gets a FATAL, and the
+//! stats so far are reported. A second signal exits at once.
+
+use tokio_util::sync::CancellationToken;
+
+/// Exit status of an interrupted sync (128 + SIGINT, as shells report it)
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Cancel the returned token on the first SIGINT, SIGTERM or SIGHUP (the
+/// terminal, or the SSH session of a server, went away)
+///
+/// Must be called inside a Tokio runtime. Signals no longer kill the
+/// process once this runs, so install it only around work that watches the
+/// token.
+pub fn install() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        if let Err(e) = next_signal().await {
+            tracing::warn!("Failed to listen for signals: {}", e);
+            return;
+        }
+        tracing::warn!("Interrupted, cleaning up (interrupt again to quit immediately)");
+        cancel.cancel();
+
+        if next_signal().await.is_ok() {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    });
+    token
+}
+
+#[cfg(unix)]
+async fn next_signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+        _ = hangup.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn next_signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
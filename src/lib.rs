@@ -1,3 +1,4 @@
+pub mod api;
 pub mod binary;
 pub mod bisync;
 pub mod cli;
@@ -9,11 +10,17 @@ pub mod filter;
 pub mod fs_util;
 pub mod hooks;
 pub mod integrity;
+pub mod interrupt;
+pub mod manifest;
+pub mod metrics;
+#[cfg(all(unix, feature = "mount"))]
+pub mod mount;
 pub mod path;
 pub mod perf;
 pub mod resource;
 pub mod resume;
 pub mod retry;
+pub mod rsync_compat;
 pub mod server;
 pub mod sparse;
 #[cfg(feature = "ssh")]
@@ -22,3 +29,12 @@ pub mod streaming;
 pub mod sync;
 pub mod temp_file;
 pub mod transport;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+pub use api::{Sync, SyncBuilder};
+pub use filter::{EntryMetadata, FilterDecision};
+pub use metrics::MetricsSnapshot;
+pub use server::{serve, ServerConfig};
+pub use sync::progress::{Progress, SyncEvent};
+pub use sync::SyncStats;
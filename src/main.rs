@@ -7,20 +7,33 @@ mod delta;
 mod error;
 mod filter;
 mod fs_util;
+mod history;
 mod hooks;
 mod integrity;
+mod interrupt;
+mod manifest;
+mod metrics;
+#[cfg(all(unix, feature = "mount"))]
+mod mount;
+mod notifier;
 mod path;
 mod perf;
 mod resource;
 mod resume;
 mod retry;
+mod rsync_compat;
 mod server;
 mod sparse;
 mod ssh;
 mod streaming;
 mod sync;
+mod telemetry;
 mod temp_file;
 mod transport;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "watch")]
+mod watch;
 
 use anyhow::{Context as _, Result};
 use clap::Parser;
@@ -32,45 +45,48 @@ use hooks::{HookContext, HookExecutor, HookType};
 use path::SyncPath;
 use resource::format_bytes;
 use std::path::PathBuf;
+use sync::estimate::{Estimate, DEFAULT_ESTIMATE_RATE};
 #[cfg(feature = "watch")]
 use sync::watch::WatchMode;
 use sync::SyncEngine;
-use tracing_subscriber::{fmt, EnvFilter};
+use tracing_subscriber::EnvFilter;
 use transport::router::TransportRouter;
 
-/// Compute effective destination path based on rsync trailing slash semantics
-///
-/// Trailing slash behavior (applies to directories):
-/// - Source without trailing slash (`/a/dir`): Copy directory itself → `dest/dir/`
-/// - Source with trailing slash (`/a/dir/`): Copy contents only → `dest/`
-///
-/// For files, trailing slash semantics don't apply - the sync engine handles them
-/// by using the destination path directly or appending the filename as needed.
-///
-/// Note: This function works with path strings and doesn't check the filesystem,
-/// so it works correctly for local, remote (SSH), and S3 sources.
-fn compute_destination_path(source: &SyncPath, destination: &SyncPath) -> PathBuf {
-    let source_path = source.path();
-
-    // For sources with trailing slash, use destination as-is (copy contents)
-    if source.has_trailing_slash() {
-        return destination.path().to_path_buf();
-    }
-
-    // For sources without trailing slash, append source name to destination
-    // (copies the directory/file itself)
-    if let Some(name) = source_path.file_name() {
-        destination.path().join(name)
-    } else {
-        // Fallback: use destination as-is (e.g., root paths)
-        destination.path().to_path_buf()
-    }
+fn main() -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let result = runtime.block_on(run());
+    // A read given up on by --stall-timeout can still hold a blocking
+    // thread; exit without waiting for it
+    runtime.shutdown_background();
+    result
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Parse CLI arguments
-    let mut cli = Cli::parse();
+async fn run() -> Result<()> {
+    // Parse CLI arguments; `sy rsync ...` takes an rsync command line
+    let args: Vec<String> = std::env::args().collect();
+    let (mut cli, ignored_rsync_options) = if args.get(1).is_some_and(|arg| arg == "rsync") {
+        let translation = rsync_compat::translate(&args[2..])?;
+        (Cli::parse_from(translation.args), translation.ignored)
+    } else {
+        (Cli::parse(), Vec::new())
+    };
+
+    // Standalone commands (no sync)
+    if let Some(command) = cli.command.take() {
+        return match command {
+            cli::Command::Mount { remote, mountpoint } => {
+                run_mount(&cli, &remote, &mountpoint).await
+            }
+            cli::Command::Bench {
+                remote,
+                size,
+                files,
+            } => run_bench(&cli, &remote, sync::bench::BenchOptions { size, files }).await,
+            command => run_command(command),
+        };
+    }
 
     // Load config file
     let config = Config::load()?;
@@ -104,7 +120,24 @@ async fn main() -> Result<()> {
 
     // Server mode (internal use)
     if cli.server {
-        return sy::server::run_server().await;
+        let allowed_roots = server::allowed_roots(config.server.allowed_roots.as_deref());
+        let mut io = cli.io_options();
+        io.sandbox |= config.server.sandbox;
+        let policy = cli.receive_policy(&config.deny)?;
+        let mut exclude = FilterEngine::new();
+        for rule in &config.server.exclude {
+            exclude.add_rule(rule)?;
+        }
+        return server::run_server(
+            io,
+            allowed_roots,
+            config.audit_log()?,
+            config.transfer_log()?,
+            policy,
+            exclude,
+            cli.backup(),
+        )
+        .await;
     }
 
     // Merge profile with CLI args if --profile is set
@@ -166,22 +199,27 @@ async fn main() -> Result<()> {
         }
     }
 
-    // Setup logging
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new(cli.log_level().as_str()));
+    // Setup logging; the dashboard owns the terminal and shows errors itself
+    let filter = if cli.tui {
+        EnvFilter::new("off")
+    } else {
+        EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new(cli.log_level().as_str()))
+    };
 
-    fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .with_thread_ids(false)
-        .with_file(false)
-        .with_line_number(false)
-        .compact()
-        .init();
+    // Flushes exported spans when main returns
+    let telemetry_guard = telemetry::init(filter, cli.otlp_endpoint.as_deref())?;
+    for option in &ignored_rsync_options {
+        tracing::debug!("Ignoring rsync option {}", option);
+    }
 
     // Validate arguments
     cli.validate()?;
 
+    if cli.daemon {
+        return run_daemon(&cli, &config).await;
+    }
+
     // After validation, source and destination must be present
     let source = cli
         .source
@@ -235,11 +273,19 @@ async fn main() -> Result<()> {
     let verification_mode = cli.verification_mode();
     let checksum_type = verification_mode.checksum_type();
     let verify_on_write = verification_mode.verify_blocks();
+    let verify_sample = cli.verify_sample.map(|percent| {
+        let seed = cli
+            .verify_seed
+            .unwrap_or_else(|| rand_core::RngCore::next_u64(&mut rand_core::OsRng));
+        integrity::VerifySample::new(percent, seed)
+    });
 
     // Create retry config from CLI args for network interruption recovery
     let retry_config =
         retry::RetryConfig::new(cli.retry, std::time::Duration::from_secs(cli.retry_delay));
 
+    let ssh_settings = cli.ssh_settings(&config.ssh);
+
     // Create transport router based on source and destination
     // Use worker count for SSH connection pool size to enable true parallel transfers
     let transport = TransportRouter::new(
@@ -249,9 +295,11 @@ async fn main() -> Result<()> {
         verify_on_write,
         cli.parallel, // SSH connection pool size = number of workers
         retry_config,
+        &ssh_settings,
     )
     .await?
-    .with_scan_options(cli.scan_options());
+    .with_scan_options(cli.scan_options())
+    .with_reflink(cli.reflink);
 
     // Get symlink mode
     let symlink_mode = cli.symlink_mode();
@@ -392,15 +440,26 @@ Or install from local source with: cargo install --path . --features acl"#
         );
     }
 
+    if let Some(addr) = cli.metrics_addr {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen for metrics on {}", addr))?;
+        tracing::info!("Serving metrics at http://{}/metrics", addr);
+        tokio::spawn(metrics::serve(listener));
+    }
+
+    // Server-mode syncs hand the same rules to both ends
+    let stream_filter = filter_engine.clone();
     let engine = SyncEngine::new(
         transport,
-        cli.dry_run,
+        // An estimate is a dry run that only reports totals
+        cli.dry_run || cli.estimate,
         cli.diff,
         cli.delete,
         cli.delete_threshold,
         cli.trash,
         cli.force_delete,
-        cli.quiet || cli.json, // JSON mode implies quiet
+        cli.quiet || cli.json || cli.tui || cli.estimate, // JSON mode implies quiet
         cli.parallel,
         cli.max_errors,
         cli.min_size,
@@ -413,11 +472,14 @@ Or install from local source with: cargo install --path . --features acl"#
         cli.json,
         checksum_type,
         verify_on_write,
+        cli.verify_retries,
+        verify_sample,
         symlink_mode,
-        cli.preserve_xattrs,
+        // FAT has nowhere to keep xattrs, ACLs or flags
+        cli.preserve_xattrs && !cli.fat,
         cli.preserve_hardlinks,
-        cli.preserve_acls,
-        cli.preserve_flags,
+        cli.preserve_acls && !cli.fat,
+        cli.preserve_flags && !cli.fat,
         cli.per_file_progress,
         cli.ignore_times,
         cli.size_only,
@@ -430,8 +492,46 @@ Or install from local source with: cargo install --path . --features acl"#
         cli.clear_checksum_db,
         cli.prune_checksum_db,
         destination.is_remote(),
-        cli.perf,
-    );
+        // Phase timing feeds the metrics too
+        cli.perf || cli.metrics_addr.is_some(),
+    )
+    .with_fat(cli.fat)
+    .with_name_crypt(cli.name_crypt()?);
+
+    // Estimate-only mode: compare, report, change nothing
+    if cli.estimate {
+        let estimate = if source.is_local() && destination.is_remote() {
+            sync::server_mode::estimate_push(
+                source.path(),
+                destination,
+                cli.delete,
+                cli.io_options(),
+                stream_filter.clone(),
+                &ssh_settings,
+            )
+            .await?
+        } else if cli.is_single_file() {
+            Estimate::from_dry_run(
+                &engine
+                    .sync_single_file(source.path(), destination.path())
+                    .await?,
+            )
+        } else {
+            let effective_dest = source.effective_destination(destination);
+            Estimate::from_dry_run(&engine.sync(source.path(), &effective_dest).await?)
+        };
+
+        let rate = cli
+            .estimate_rate
+            .or(cli.bwlimit)
+            .unwrap_or(DEFAULT_ESTIMATE_RATE);
+        if cli.json {
+            estimate.to_event(rate).emit();
+        } else if !cli.quiet {
+            print_estimate(&estimate, rate, cli.delete);
+        }
+        return Ok(());
+    }
 
     // Execute pre-sync hook
     if let Some(ref executor) = hook_executor {
@@ -548,6 +648,30 @@ Or install from local source with: cargo install --path . --features acl"#
         std::process::exit(exit_code);
     }
 
+    // The dashboard follows the engine's events, so only syncs the engine
+    // runs itself get one
+    #[cfg(feature = "tui")]
+    let (engine, dashboard) = if cli.tui {
+        if (!cli.watch || destination.is_remote())
+            && (cli.bidirectional
+                || !source.is_local()
+                || !destination.is_local()
+                || cli.is_single_file())
+        {
+            anyhow::bail!(
+                "--tui is only available for local directory syncs and watch mode to local destinations"
+            );
+        }
+        let (events, dashboard) = tui::Dashboard::start(cli.parallel)?;
+        (engine.with_events(events), Some(dashboard))
+    } else {
+        (engine, None)
+    };
+    #[cfg(not(feature = "tui"))]
+    if cli.tui {
+        anyhow::bail!("--tui requires the 'tui' feature. Enable it with --features tui");
+    }
+
     // Watch mode or regular sync
     if cli.watch {
         #[cfg(feature = "watch")]
@@ -555,6 +679,16 @@ Or install from local source with: cargo install --path . --features acl"#
             if !source.is_local() {
                 anyhow::bail!("Watch mode currently only supports local sources.");
             }
+            if destination.is_remote() {
+                return watch_push(
+                    &cli,
+                    source.path(),
+                    destination,
+                    &stream_filter,
+                    &ssh_settings,
+                )
+                .await;
+            }
 
             // Watch mode - continuous sync on file changes
             let watch_mode = WatchMode::new(
@@ -562,7 +696,8 @@ Or install from local source with: cargo install --path . --features acl"#
                 source.path().to_path_buf(),
                 destination.path().to_path_buf(),
                 std::time::Duration::from_millis(500), // 500ms debounce
-            );
+            )
+            .with_quiet(cli.tui);
 
             watch_mode.watch().await?;
             return Ok(()); // Watch mode handles its own output
@@ -576,215 +711,319 @@ Or install from local source with: cargo install --path . --features acl"#
     }
 
     // Run sync (single file, directory, or bidirectional)
-    let stats = if cli.bidirectional {
-        // ... existing bisync logic ...
-        // Bidirectional sync mode
-        if !cli.quiet && !cli.json {
-            println!("sy v{}", env!("CARGO_PKG_VERSION"));
-            println!("Mode: Bidirectional sync");
-            println!("Strategy: {}", cli.conflict_resolve);
-            println!("{} ↔ {}\n", source, destination);
-        }
-
-        // Create transports for source and destination
-        let (source_transport, dest_transport): (
-            std::sync::Arc<dyn transport::Transport>,
-            std::sync::Arc<dyn transport::Transport>,
-        ) = match (&source, &destination) {
-            (crate::path::SyncPath::Local { .. }, crate::path::SyncPath::Local { .. }) => {
-                // Both local
-                let verifier = integrity::IntegrityVerifier::new(checksum_type, verify_on_write);
-                let local_source = std::sync::Arc::new(
-                    transport::local::LocalTransport::with_verifier(verifier.clone()),
-                );
-                let local_dest =
-                    std::sync::Arc::new(transport::local::LocalTransport::with_verifier(verifier));
-                (local_source, local_dest)
-            }
-            (
-                crate::path::SyncPath::Local { .. },
-                crate::path::SyncPath::Remote { host, user, .. },
-            ) => {
-                // Local → Remote
-                let config = if let Some(user) = user {
-                    ssh::config::SshConfig {
-                        hostname: host.clone(),
-                        user: user.clone(),
-                        ..Default::default()
+    let notify = config.notify_settings(cli.profile.as_deref());
+    let cancel = interrupt::install();
+    let engine = engine.with_cancel(cancel.clone());
+    let started = std::time::SystemTime::now();
+    let run = async {
+        Ok::<_, anyhow::Error>(if cli.bidirectional {
+            // ... existing bisync logic ...
+            // Bidirectional sync mode
+            if !cli.quiet && !cli.json {
+                println!("sy v{}", env!("CARGO_PKG_VERSION"));
+                println!("Mode: Bidirectional sync");
+                println!("Strategy: {}", cli.conflict_resolve);
+                println!("{} ↔ {}\n", source, destination);
+            }
+
+            // Create transports for source and destination
+            let (source_transport, dest_transport): (
+                std::sync::Arc<dyn transport::Transport>,
+                std::sync::Arc<dyn transport::Transport>,
+            ) = match (&source, &destination) {
+                (crate::path::SyncPath::Local { .. }, crate::path::SyncPath::Local { .. }) => {
+                    // Both local
+                    let verifier =
+                        integrity::IntegrityVerifier::new(checksum_type, verify_on_write);
+                    let local_source = std::sync::Arc::new(
+                        transport::local::LocalTransport::with_verifier(verifier.clone())
+                            .with_reflink(cli.reflink),
+                    );
+                    let local_dest = std::sync::Arc::new(
+                        transport::local::LocalTransport::with_verifier(verifier)
+                            .with_reflink(cli.reflink),
+                    );
+                    (local_source, local_dest)
+                }
+                (
+                    crate::path::SyncPath::Local { .. },
+                    crate::path::SyncPath::Remote { host, user, .. },
+                ) => {
+                    // Local → Remote
+                    let config = if let Some(user) = user {
+                        ssh::config::SshConfig {
+                            hostname: host.clone(),
+                            user: user.clone(),
+                            ..Default::default()
+                        }
+                    } else {
+                        ssh::config::parse_ssh_config(host)?
                     }
-                } else {
-                    ssh::config::parse_ssh_config(host)?
-                };
-                let verifier = integrity::IntegrityVerifier::new(checksum_type, verify_on_write);
-                let local =
-                    std::sync::Arc::new(transport::local::LocalTransport::with_verifier(verifier));
-                let remote = std::sync::Arc::new(
-                    transport::ssh::SshTransport::with_pool_size(&config, cli.parallel).await?,
-                );
-                (local, remote)
-            }
-            (
-                crate::path::SyncPath::Remote { host, user, .. },
-                crate::path::SyncPath::Local { .. },
-            ) => {
-                // Remote → Local
-                let config = if let Some(user) = user {
-                    ssh::config::SshConfig {
-                        hostname: host.clone(),
-                        user: user.clone(),
-                        ..Default::default()
+                    .with_host_keys(&ssh_settings, host);
+                    let verifier =
+                        integrity::IntegrityVerifier::new(checksum_type, verify_on_write);
+                    let local = std::sync::Arc::new(
+                        transport::local::LocalTransport::with_verifier(verifier),
+                    );
+                    let remote = std::sync::Arc::new(
+                        transport::ssh::SshTransport::with_pool_size(&config, cli.parallel).await?,
+                    );
+                    (local, remote)
+                }
+                (
+                    crate::path::SyncPath::Remote { host, user, .. },
+                    crate::path::SyncPath::Local { .. },
+                ) => {
+                    // Remote → Local
+                    let config = if let Some(user) = user {
+                        ssh::config::SshConfig {
+                            hostname: host.clone(),
+                            user: user.clone(),
+                            ..Default::default()
+                        }
+                    } else {
+                        ssh::config::parse_ssh_config(host)?
                     }
-                } else {
-                    ssh::config::parse_ssh_config(host)?
-                };
-                let verifier = integrity::IntegrityVerifier::new(checksum_type, verify_on_write);
-                let remote = std::sync::Arc::new(
-                    transport::ssh::SshTransport::with_pool_size(&config, cli.parallel).await?,
-                );
-                let local =
-                    std::sync::Arc::new(transport::local::LocalTransport::with_verifier(verifier));
-                (remote, local)
-            }
-            (
-                crate::path::SyncPath::Remote {
-                    host: host1,
-                    user: user1,
-                    ..
-                },
-                crate::path::SyncPath::Remote {
-                    host: host2,
-                    user: user2,
-                    ..
-                },
-            ) => {
-                // Remote → Remote
-                let config1 = if let Some(user) = user1 {
-                    ssh::config::SshConfig {
-                        hostname: host1.clone(),
-                        user: user.clone(),
-                        ..Default::default()
+                    .with_host_keys(&ssh_settings, host);
+                    let verifier =
+                        integrity::IntegrityVerifier::new(checksum_type, verify_on_write);
+                    let remote = std::sync::Arc::new(
+                        transport::ssh::SshTransport::with_pool_size(&config, cli.parallel).await?,
+                    );
+                    let local = std::sync::Arc::new(
+                        transport::local::LocalTransport::with_verifier(verifier),
+                    );
+                    (remote, local)
+                }
+                (
+                    crate::path::SyncPath::Remote {
+                        host: host1,
+                        user: user1,
+                        ..
+                    },
+                    crate::path::SyncPath::Remote {
+                        host: host2,
+                        user: user2,
+                        ..
+                    },
+                ) => {
+                    // Remote → Remote
+                    let config1 = if let Some(user) = user1 {
+                        ssh::config::SshConfig {
+                            hostname: host1.clone(),
+                            user: user.clone(),
+                            ..Default::default()
+                        }
+                    } else {
+                        ssh::config::parse_ssh_config(host1)?
                     }
-                } else {
-                    ssh::config::parse_ssh_config(host1)?
-                };
-                let config2 = if let Some(user) = user2 {
-                    ssh::config::SshConfig {
-                        hostname: host2.clone(),
-                        user: user.clone(),
-                        ..Default::default()
+                    .with_host_keys(&ssh_settings, host1);
+                    let config2 = if let Some(user) = user2 {
+                        ssh::config::SshConfig {
+                            hostname: host2.clone(),
+                            user: user.clone(),
+                            ..Default::default()
+                        }
+                    } else {
+                        ssh::config::parse_ssh_config(host2)?
                     }
-                } else {
-                    ssh::config::parse_ssh_config(host2)?
-                };
-                let remote1 = std::sync::Arc::new(
-                    transport::ssh::SshTransport::with_pool_size(&config1, cli.parallel).await?,
-                );
-                let remote2 = std::sync::Arc::new(
-                    transport::ssh::SshTransport::with_pool_size(&config2, cli.parallel).await?,
-                );
-                (remote1, remote2)
-            }
-            _ => {
-                anyhow::bail!("Bidirectional sync does not support S3 paths");
-            }
-        };
+                    .with_host_keys(&ssh_settings, host2);
+                    let remote1 = std::sync::Arc::new(
+                        transport::ssh::SshTransport::with_pool_size(&config1, cli.parallel)
+                            .await?,
+                    );
+                    let remote2 = std::sync::Arc::new(
+                        transport::ssh::SshTransport::with_pool_size(&config2, cli.parallel)
+                            .await?,
+                    );
+                    (remote1, remote2)
+                }
+                _ => {
+                    anyhow::bail!("Bidirectional sync does not support S3 paths");
+                }
+            };
 
-        let bisync_engine = bisync::BisyncEngine::new(source_transport, dest_transport);
-        let bisync_opts = bisync::BisyncOptions {
-            conflict_resolution: bisync::ConflictResolution::from_str(&cli.conflict_resolve)
-                .ok_or_else(|| anyhow::anyhow!("Invalid conflict resolution strategy"))?,
-            max_delete_percent: cli.max_delete,
-            dry_run: cli.dry_run,
-            clear_state: cli.clear_bisync_state,
-            force_resync: cli.force_resync,
-        };
+            let bisync_engine = bisync::BisyncEngine::new(source_transport, dest_transport);
+            let bisync_opts = bisync::BisyncOptions {
+                conflict_resolution: bisync::ConflictResolution::from_str(&cli.conflict_resolve)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid conflict resolution strategy"))?,
+                max_delete_percent: cli.max_delete,
+                dry_run: cli.dry_run,
+                clear_state: cli.clear_bisync_state,
+                force_resync: cli.force_resync,
+            };
 
-        // Compute effective destination path based on trailing slash semantics
-        let effective_dest = compute_destination_path(source, destination);
+            // Compute effective destination path based on trailing slash semantics
+            let effective_dest = source.effective_destination(destination);
 
-        let bisync_result = bisync_engine
-            .sync(source.path(), &effective_dest, bisync_opts)
-            .await?;
+            let bisync_result = bisync_engine
+                .sync(source.path(), &effective_dest, bisync_opts)
+                .await?;
 
-        // Print conflicts if any
-        if !bisync_result.conflicts.is_empty() && !cli.quiet && !cli.json {
-            println!("\n{} conflicts detected:", bisync_result.conflicts.len());
-            for conflict in &bisync_result.conflicts {
-                println!("  {} - {}", conflict.path.display(), conflict.action);
+            // Print conflicts if any
+            if !bisync_result.conflicts.is_empty() && !cli.quiet && !cli.json {
+                println!("\n{} conflicts detected:", bisync_result.conflicts.len());
+                for conflict in &bisync_result.conflicts {
+                    println!("  {} - {}", conflict.path.display(), conflict.action);
+                }
+                println!();
             }
-            println!();
-        }
-
-        // Convert BisyncStats to SyncStats for compatibility
-        sync::SyncStats {
-            files_scanned: (bisync_result.stats.files_synced_to_source
-                + bisync_result.stats.files_synced_to_dest) as u64,
-            files_created: bisync_result.stats.files_synced_to_dest as u64,
-            files_updated: bisync_result.stats.files_synced_to_source as u64,
-            files_deleted: bisync_result.stats.files_deleted_from_source
-                + bisync_result.stats.files_deleted_from_dest,
-            files_skipped: 0,
-            bytes_transferred: bisync_result.stats.bytes_transferred,
-            files_delta_synced: 0,
-            delta_bytes_saved: 0,
-            files_compressed: 0,
-            compression_bytes_saved: 0,
-            files_verified: 0,
-            verification_failures: 0,
-            duration: std::time::Duration::from_millis(bisync_result.stats.duration_ms as u64),
-            bytes_would_add: 0,
-            bytes_would_change: 0,
-            bytes_would_delete: 0,
-            dirs_created: 0,
-            symlinks_created: 0,
-            errors: bisync_result
-                .errors
-                .into_iter()
-                .map(|e| sync::SyncError {
-                    path: PathBuf::new(),
-                    error: e,
-                    action: "bidirectional sync".to_string(),
-                })
-                .collect(),
-        }
-    } else if source.is_local() && destination.is_remote() {
-        // Use server mode for local → remote SSH (faster than SFTP)
-        if !cli.quiet && !cli.json {
-            println!("Mode: Server protocol (push)\n");
-        }
-        sync::server_mode::sync_push(source.path(), destination, cli.delete, cli.compress).await?
-    } else if source.is_remote() && destination.is_local() {
-        // Use server mode for remote → local SSH (faster than SFTP)
-        if !cli.quiet && !cli.json {
-            println!("Mode: Server protocol (pull)\n");
-        }
-        sync::server_mode::sync_pull(source, destination.path(), cli.delete, cli.compress).await?
-    } else if cli.is_single_file() {
-        if !cli.quiet && !cli.json {
-            println!("Mode: Single file sync\n");
-        }
-        // For single files, trailing slash doesn't apply - use destination as-is
-        engine
-            .sync_single_file(source.path(), destination.path())
-            .await?
-    } else {
-        // Compute effective destination path based on trailing slash semantics
-        let effective_dest = compute_destination_path(source, destination);
 
-        if cli.stream {
+            // Convert BisyncStats to SyncStats for compatibility
+            sync::SyncStats {
+                files_scanned: (bisync_result.stats.files_synced_to_source
+                    + bisync_result.stats.files_synced_to_dest)
+                    as u64,
+                files_created: bisync_result.stats.files_synced_to_dest as u64,
+                files_updated: bisync_result.stats.files_synced_to_source as u64,
+                files_touched: 0,
+                files_backed_up: 0,
+                files_vanished: 0,
+                files_deleted: bisync_result.stats.files_deleted_from_source
+                    + bisync_result.stats.files_deleted_from_dest,
+                files_skipped: 0,
+                bytes_transferred: bisync_result.stats.bytes_transferred,
+                files_delta_synced: 0,
+                delta_bytes_saved: 0,
+                files_compressed: 0,
+                compression_bytes_saved: 0,
+                files_sparse: 0,
+                sparse_bytes_skipped: 0,
+                files_verified: 0,
+                verification_failures: 0,
+                files_verify_skipped: 0,
+                duration: std::time::Duration::from_millis(bisync_result.stats.duration_ms as u64),
+                bytes_would_add: 0,
+                bytes_would_change: 0,
+                bytes_would_delete: 0,
+                dirs_created: 0,
+                symlinks_created: 0,
+                cancelled: false,
+                phases: None,
+                errors: bisync_result
+                    .errors
+                    .into_iter()
+                    .map(|e| sync::SyncError {
+                        path: PathBuf::new(),
+                        error: e,
+                        action: "bidirectional sync".to_string(),
+                    })
+                    .collect(),
+            }
+        } else if source.is_local()
+            && (destination.is_remote() || destination.is_s3() && cli.encrypt_names.is_none())
+        {
+            // Use server mode for local → remote SSH (faster than SFTP); S3
+            // runs the same pipeline in-process, unless names are encrypted
+            if !cli.quiet && !cli.json {
+                if destination.is_s3() {
+                    println!("Mode: Streaming (push to S3)\n");
+                } else {
+                    println!("Mode: Server protocol (push)\n");
+                }
+            }
+            let (pb, progress) = remote_progress(&cli);
+            let stats = sync::server_mode::sync_push(
+                source.path(),
+                destination,
+                cli.delete,
+                cli.compress,
+                cli.io_options(),
+                stream_filter.clone(),
+                cli.owner_map(),
+                cli.backup(),
+                cli.transfer_log()?,
+                &ssh_settings,
+                cli.dest_manifest,
+                None,
+                progress,
+                cancel.clone(),
+            )
+            .await;
+            pb.finish_and_clear();
+            let stats = stats?;
+            if !stats.errors.is_empty() && !cli.quiet && !cli.json {
+                sync::print_error_report(&stats.errors);
+            }
+            stats
+        } else if source.is_remote() && destination.is_local() {
+            // Use server mode for remote → local SSH (faster than SFTP)
             if !cli.quiet && !cli.json {
-                println!("Mode: Streaming sync (experimental)\n");
+                println!("Mode: Server protocol (pull)\n");
             }
+            let (pb, progress) = remote_progress(&cli);
+            let stats = sync::server_mode::sync_pull(
+                source,
+                destination.path(),
+                cli.delete,
+                cli.compress,
+                cli.io_options(),
+                stream_filter.clone(),
+                cli.owner_map(),
+                cli.backup(),
+                cli.transfer_log()?,
+                &ssh_settings,
+                config.audit_log()?,
+                cli.receive_policy(&config.deny)?,
+                progress,
+                cancel.clone(),
+            )
+            .await;
+            pb.finish_and_clear();
+            let stats = stats?;
+            if !stats.errors.is_empty() && !cli.quiet && !cli.json {
+                sync::print_error_report(&stats.errors);
+            }
+            stats
+        } else if cli.is_single_file() {
+            if !cli.quiet && !cli.json {
+                println!("Mode: Single file sync\n");
+            }
+            // For single files, trailing slash doesn't apply - use destination as-is
             engine
-                .sync_streaming(source.path(), &effective_dest)
+                .sync_single_file(source.path(), destination.path())
                 .await?
         } else {
-            engine.sync(source.path(), &effective_dest).await?
+            // Compute effective destination path based on trailing slash semantics
+            let effective_dest = source.effective_destination(destination);
+
+            if cli.stream {
+                if !cli.quiet && !cli.json && !cli.tui {
+                    println!("Mode: Streaming sync (experimental)\n");
+                }
+                engine
+                    .sync_streaming(source.path(), &effective_dest)
+                    .await?
+            } else {
+                engine.sync(source.path(), &effective_dest).await?
+            }
+        })
+    }
+    .await;
+    let stats = match run {
+        Ok(stats) => stats,
+        Err(e) => {
+            let summary = notifier::Summary::from_error(
+                cli.profile.as_deref(),
+                source.to_string(),
+                destination.to_string(),
+                &e,
+                cli.dry_run,
+            );
+            history::record(started, &summary);
+            if let Some(settings) = notify {
+                notifier::send(settings, &summary);
+            }
+            return Err(e);
         }
     };
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        dashboard.finish()?;
+    }
 
-    // Execute post-sync hook
-    if let Some(ref executor) = hook_executor {
+    // Execute post-sync hook; an interrupted sync didn't get that far
+    if let Some(executor) = hook_executor.as_ref().filter(|_| !stats.cancelled) {
         let post_context = HookContext {
             source: source.to_string(),
             destination: destination.to_string(),
@@ -804,13 +1043,44 @@ Or install from local source with: cargo install --path . --features acl"#
         }
     }
 
+    let summary = notifier::Summary::from_stats(
+        cli.profile.as_deref(),
+        source.to_string(),
+        destination.to_string(),
+        &stats,
+        cli.dry_run,
+    );
+    history::record(started, &summary);
+    if let Some(settings) = notify {
+        notifier::send(settings, &summary);
+    }
+
     // Print summary (skip if JSON mode - already emitted JSON summary)
     if !cli.quiet && !cli.json {
-        if cli.dry_run {
+        if stats.cancelled {
+            println!("\n{}\n", "⚠ Sync interrupted".yellow().bold());
+        } else if cli.dry_run {
             println!(
                 "\n{}\n",
                 "✓ Dry-run complete (no changes made)".green().bold()
             );
+        } else if !stats.errors.is_empty() {
+            println!(
+                "\n{}\n",
+                format!("⚠ Sync finished with {} errors", stats.errors.len())
+                    .yellow()
+                    .bold()
+            );
+        } else if stats.files_vanished > 0 {
+            println!(
+                "\n{}\n",
+                format!(
+                    "⚠ Sync partial: {} source files vanished",
+                    stats.files_vanished
+                )
+                .yellow()
+                .bold()
+            );
         } else {
             println!("\n{}\n", "✓ Sync complete".green().bold());
         }
@@ -888,10 +1158,22 @@ Or install from local source with: cargo install --path . --features acl"#
                     stats.files_updated.to_string().bright_black()
                 );
             }
+            if stats.files_touched > 0 {
+                println!(
+                    "  Metadata only:     {}",
+                    stats.files_touched.to_string().bright_black()
+                );
+            }
             println!(
                 "  Files skipped:     {}",
                 stats.files_skipped.to_string().bright_black()
             );
+            if stats.files_vanished > 0 {
+                println!(
+                    "  Files vanished:    {}",
+                    stats.files_vanished.to_string().yellow()
+                );
+            }
             if cli.delete && stats.files_deleted > 0 {
                 println!(
                     "  Files deleted:     {}",
@@ -903,6 +1185,12 @@ Or install from local source with: cargo install --path . --features acl"#
                     stats.files_deleted.to_string().bright_black()
                 );
             }
+            if stats.files_backed_up > 0 {
+                println!(
+                    "  Backed up:         {}",
+                    stats.files_backed_up.to_string().bright_black()
+                );
+            }
         }
 
         // Transfer stats
@@ -911,11 +1199,17 @@ Or install from local source with: cargo install --path . --features acl"#
             "  Bytes transferred: {}",
             format_bytes(stats.bytes_transferred).cyan()
         );
+        if stats.files_sparse > 0 {
+            println!(
+                "  Data copied:       {}",
+                format_bytes(stats.data_bytes()).cyan()
+            );
+        }
 
-        // Calculate and display transfer rate
+        // Rate over the data actually copied: holes in sparse files cost nothing
         let duration_secs = stats.duration.as_secs_f64();
-        if duration_secs > 0.0 && stats.bytes_transferred > 0 {
-            let bytes_per_sec = stats.bytes_transferred as f64 / duration_secs;
+        if duration_secs > 0.0 && stats.data_bytes() > 0 {
+            let bytes_per_sec = stats.data_bytes() as f64 / duration_secs;
             println!(
                 "  Transfer rate:     {}",
                 format!("{}/s", format_bytes(bytes_per_sec as u64)).cyan()
@@ -926,6 +1220,15 @@ Or install from local source with: cargo install --path . --features acl"#
             "  Duration:          {}",
             format_duration(stats.duration).cyan()
         );
+        if let Some(phases) = stats.phases {
+            println!(
+                "  Phases:            scan {}, exchange {}, transfer {}, delete {}",
+                format_duration(phases.scan).bright_black(),
+                format_duration(phases.exchange).bright_black(),
+                format_duration(phases.transfer).bright_black(),
+                format_duration(phases.delete).bright_black()
+            );
+        }
 
         // Delta sync stats (if used)
         if stats.files_delta_synced > 0 {
@@ -938,6 +1241,17 @@ Or install from local source with: cargo install --path . --features acl"#
             );
         }
 
+        // Sparse files: logical size vs data actually copied
+        if stats.files_sparse > 0 {
+            println!();
+            println!(
+                "  {}      {} files, {} of holes not copied",
+                "Sparse files:".bright_blue(),
+                stats.files_sparse.to_string().bright_blue(),
+                format_bytes(stats.sparse_bytes_skipped).bright_blue()
+            );
+        }
+
         // Compression stats (if used)
         if stats.files_compressed > 0 {
             println!();
@@ -966,6 +1280,14 @@ Or install from local source with: cargo install --path . --features acl"#
                     stats.files_verified.to_string().green(),
                 );
             }
+            if let Some(sample) = verify_sample {
+                println!(
+                    "                   {}% sample: {} skipped (--verify-seed {})",
+                    sample.percent(),
+                    stats.files_verify_skipped,
+                    sample.seed()
+                );
+            }
         }
 
         // Print performance summary if --perf is enabled
@@ -976,9 +1298,530 @@ Or install from local source with: cargo install --path . --features acl"#
         }
     }
 
+    if stats.cancelled {
+        // Flushes pending spans, which exit() would skip; a no-op without otel
+        #[allow(clippy::drop_non_drop)]
+        drop(telemetry_guard);
+        std::process::exit(interrupt::EXIT_INTERRUPTED);
+    }
+
+    // Whatever could be synced was; the failures still fail the run
+    if !stats.errors.is_empty() {
+        anyhow::bail!("{} entries failed to sync", stats.errors.len());
+    }
+
+    // Partial, but only because the source changed under the sync
+    if stats.files_vanished > 0 {
+        #[allow(clippy::drop_non_drop)]
+        drop(telemetry_guard);
+        std::process::exit(sync::EXIT_VANISHED);
+    }
+
     Ok(())
 }
 
+/// The live bar of a server-mode push or pull, hidden when the output isn't
+/// for a person watching it
+fn remote_progress(cli: &Cli) -> (indicatif::ProgressBar, Option<streaming::ProgressReport>) {
+    if cli.quiet || cli.json || cli.tui {
+        return (indicatif::ProgressBar::hidden(), None);
+    }
+    let (pb, report) = sync::progress::create_remote_progress_bar();
+    (pb, Some(report))
+}
+
+/// Print what an `--estimate` run found
+fn print_estimate(estimate: &Estimate, rate: u64, delete: bool) {
+    println!("\n{}\n", "Estimate (nothing transferred)".bold());
+    println!(
+        "  New files:         {} ({})",
+        estimate.files_new.to_string().green(),
+        format_bytes(estimate.bytes_new)
+    );
+    println!(
+        "  Changed files:     {} ({})",
+        estimate.files_changed.to_string().yellow(),
+        format_bytes(estimate.bytes_changed)
+    );
+    println!(
+        "  Unchanged files:   {}",
+        estimate.files_unchanged.to_string().bright_black()
+    );
+    if delete {
+        println!(
+            "  Files to delete:   {}",
+            estimate.files_deleted.to_string().red()
+        );
+    }
+    println!();
+    println!(
+        "  To transfer:       up to {}",
+        format_bytes(estimate.bytes()).cyan()
+    );
+    println!(
+        "  Transfer time:     ~{} at {}/s",
+        format_duration(estimate.transfer_time(rate)).cyan(),
+        format_bytes(rate)
+    );
+    println!(
+        "  Compared in:       {}",
+        format_duration(estimate.duration).bright_black()
+    );
+}
+
+/// `sy mount`: show `remote` read-only at `mountpoint` until interrupted
+async fn run_mount(cli: &Cli, remote: &SyncPath, mountpoint: &std::path::Path) -> Result<()> {
+    #[cfg(all(unix, feature = "mount"))]
+    {
+        let config = Config::load()?;
+        let tree = sync::server_mode::browse(remote, &cli.ssh_settings(&config.ssh)).await?;
+        println!(
+            "{} Mounted {} on {} (read-only); Ctrl-C to unmount",
+            "✓".green(),
+            remote,
+            mountpoint.display()
+        );
+        mount::mount(tree, mountpoint, &remote.to_string()).await
+    }
+    #[cfg(not(all(unix, feature = "mount")))]
+    {
+        let _ = (cli, remote, mountpoint);
+        anyhow::bail!("sy mount requires the 'mount' feature. Enable it with --features mount");
+    }
+}
+
+/// `sy --daemon`: serve the configured modules over TCP until interrupted
+async fn run_daemon(cli: &Cli, config: &Config) -> Result<()> {
+    if config.daemon.modules.is_empty() {
+        anyhow::bail!(
+            "--daemon needs [daemon.modules] in {}",
+            Config::config_path()?.display()
+        );
+    }
+    let listen = cli
+        .listen
+        .clone()
+        .or_else(|| config.daemon.listen.clone())
+        .unwrap_or_else(|| server::daemon::DEFAULT_LISTEN.to_string());
+    let hosts_allow = server::daemon::parse_hosts(&config.daemon.hosts_allow)?;
+
+    let mut exclude = FilterEngine::new();
+    for rule in &config.server.exclude {
+        exclude.add_rule(rule)?;
+    }
+    let mut session = server::ServerConfig::new(".")
+        .with_io(cli.io_options())
+        .with_policy(cli.receive_policy(&config.deny)?)
+        .with_exclude(exclude)
+        .with_cancel(interrupt::install());
+    if let Some(audit) = config.audit_log()? {
+        session = session.with_audit(audit);
+    }
+    if let Some(log) = cli.transfer_log()?.or(config.transfer_log()?) {
+        session = session.with_transfer_log(log);
+    }
+
+    let listener = tokio::net::TcpListener::bind(&listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", listen))?;
+    tracing::info!(
+        "Serving {} module(s) on {}",
+        config.daemon.modules.len(),
+        listen
+    );
+    let max_connections = config
+        .daemon
+        .max_connections
+        .unwrap_or(server::daemon::DEFAULT_MAX_CONNECTIONS);
+    server::daemon::Daemon::new(config.daemon.modules.clone(), session)
+        .with_hosts_allow(hosts_allow)
+        .with_max_connections(max_connections)
+        .run(listener)
+        .await
+}
+
+/// `sy --watch` to a server-mode destination: push everything, then push
+/// what changed as it changes
+#[cfg(feature = "watch")]
+async fn watch_push(
+    cli: &Cli,
+    source: &std::path::Path,
+    destination: &SyncPath,
+    filter: &FilterEngine,
+    ssh: &config::SshSettings,
+) -> Result<()> {
+    let cancel = interrupt::install();
+    let show = !cli.quiet && !cli.json;
+    let transfer_log = cli.transfer_log()?;
+    if show {
+        println!(
+            "Watching {} for changes, pushing to {} (Ctrl+C to stop)...\n",
+            source.display(),
+            destination
+        );
+    }
+    watch::Watch::new(source)
+        .with_cancel(cancel.clone())
+        .run(|batch| {
+            let cancel = cancel.clone();
+            let transfer_log = transfer_log.clone();
+            async move {
+                let what = match &batch {
+                    watch::Batch::All => "everything".to_string(),
+                    watch::Batch::Paths(paths) => format!("{} changed path(s)", paths.len()),
+                };
+                let stats = sync::server_mode::sync_push(
+                    source,
+                    destination,
+                    cli.delete,
+                    cli.compress,
+                    cli.io_options(),
+                    filter.clone(),
+                    cli.owner_map(),
+                    cli.backup(),
+                    transfer_log,
+                    ssh,
+                    cli.dest_manifest,
+                    batch.into_scope(),
+                    None,
+                    cancel,
+                )
+                .await?;
+                if show && !stats.cancelled {
+                    println!(
+                        "✓ Synced {}: {} created, {} updated, {} deleted",
+                        what, stats.files_created, stats.files_updated, stats.files_deleted
+                    );
+                    if !stats.errors.is_empty() {
+                        sync::print_error_report(&stats.errors);
+                    }
+                }
+                Ok(())
+            }
+        })
+        .await
+}
+
+/// `sy bench`: time synthetic transfers to `remote` and print the report
+async fn run_bench(cli: &Cli, remote: &SyncPath, options: sync::bench::BenchOptions) -> Result<()> {
+    let config = Config::load()?;
+    println!(
+        "Benchmarking {} ({} file, {} small files)...",
+        remote,
+        format_bytes(options.size),
+        options.files
+    );
+    let report = sync::bench::run(
+        remote,
+        options,
+        cli.io_options(),
+        &cli.ssh_settings(&config.ssh),
+        interrupt::install(),
+    )
+    .await?;
+
+    println!("\n{}\n", "Benchmark".bold());
+    println!(
+        "  {:<14} {}",
+        "connect",
+        format_duration(report.connect).cyan()
+    );
+    for workload in &report.workloads {
+        let rate = if workload.files > 1 {
+            format!("{:.0} files/s", workload.file_rate())
+        } else {
+            format!("{}/s", format_bytes(workload.throughput() as u64))
+        };
+        println!(
+            "  {:<14} {}  {} sent in {}",
+            workload.name,
+            format!("{:>12}", rate).cyan(),
+            format_bytes(workload.sent),
+            format_duration(workload.duration)
+        );
+        if let Some(phases) = workload.phases {
+            println!(
+                "  {:<14} scan {}, exchange {}, transfer {}, delete {}",
+                "",
+                format_duration(phases.scan).bright_black(),
+                format_duration(phases.exchange).bright_black(),
+                format_duration(phases.transfer).bright_black(),
+                format_duration(phases.delete).bright_black()
+            );
+        }
+    }
+
+    if let Some((chunk, window)) = report.tuning() {
+        println!(
+            "\n  Suggested tuning: {} chunks, {} in flight",
+            format_bytes(chunk as u64),
+            window
+        );
+    }
+    for suggestion in report.suggestions() {
+        println!("  {} {}", "→".yellow(), suggestion);
+    }
+    Ok(())
+}
+
+/// Run a standalone subcommand
+fn run_command(command: cli::Command) -> Result<()> {
+    use cli::{Command, ManifestCommand};
+    use manifest::Manifest;
+
+    match command {
+        Command::Manifest(ManifestCommand::Create { dir, output }) => {
+            let manifest = Manifest::create(&dir)
+                .with_context(|| format!("Failed to create manifest for {}", dir.display()))?;
+            let json = manifest.to_json();
+            match output {
+                Some(path) => std::fs::write(&path, json + "\n")
+                    .with_context(|| format!("Failed to write {}", path.display()))?,
+                None => println!("{}", json),
+            }
+            Ok(())
+        }
+        Command::Manifest(ManifestCommand::Keygen { path }) => {
+            manifest::generate_key(&path)
+                .with_context(|| format!("Failed to write key {}", path.display()))?;
+            println!("Secret key: {}", path.display());
+            println!("Public key: {}", manifest::public_key_path(&path).display());
+            Ok(())
+        }
+        Command::Manifest(ManifestCommand::Sign { manifest, key }) => {
+            let key = manifest::load_signing_key(&key)?;
+            let sig_path = manifest::sign_file(&manifest, &key)?;
+            println!(
+                "{} Signed {} → {}",
+                "✓".green(),
+                manifest.display(),
+                sig_path.display()
+            );
+            Ok(())
+        }
+        Command::Manifest(ManifestCommand::Verify {
+            dir,
+            manifest,
+            key,
+            signature,
+        }) => {
+            if let Some(key) = key {
+                let key = manifest::load_verifying_key(&key)?;
+                let sig_path = signature.unwrap_or_else(|| manifest::signature_path(&manifest));
+                manifest::verify_file_signature(&manifest, &sig_path, &key)?;
+                println!("{} Manifest signature valid", "✓".green());
+            }
+
+            let expected = Manifest::load(&manifest)?;
+            let report = expected.verify(&dir)?;
+
+            for m in &report.mismatched {
+                println!("  {} {} ({})", "✗".red(), m.path, m.reason);
+            }
+            for path in &report.missing {
+                println!("  {} {} (missing)", "-".red(), path);
+            }
+            for path in &report.extra {
+                println!("  {} {} (not in manifest)", "+".yellow(), path);
+            }
+
+            println!(
+                "\n  Files matched:        {}/{}",
+                report.files_matched,
+                expected.files.len()
+            );
+            if !report.mtime_changed.is_empty() {
+                println!(
+                    "  Mtime changed:        {} (contents match)",
+                    report.mtime_changed.len()
+                );
+            }
+
+            if report.is_clean() {
+                println!("\n{} {} matches manifest", "✓".green(), dir.display());
+                Ok(())
+            } else {
+                println!("\n{} {} differs from manifest", "✗".red(), dir.display());
+                std::process::exit(1);
+            }
+        }
+        Command::Scrub { dir, bwlimit } => {
+            let options = sync::scrub::ScrubOptions { bwlimit };
+            let report = sync::scrub::scrub(&dir, &options)
+                .with_context(|| format!("Failed to scrub {}", dir.display()))?;
+
+            for path in &report.corrupted {
+                println!(
+                    "  {} {} (content changed, metadata unchanged)",
+                    "✗".red(),
+                    path.display()
+                );
+            }
+            for (path, error) in &report.errors {
+                println!("  {} {}: {}", "!".yellow(), path.display(), error);
+            }
+
+            println!("\n  Files scanned:        {}", report.files_scanned);
+            println!("  Files verified:       {}", report.files_verified);
+            println!("  Checksums recorded:   {}", report.files_recorded);
+            println!(
+                "  Bytes read:           {}",
+                format_bytes(report.bytes_read)
+            );
+            println!(
+                "  Duration:             {}",
+                format_duration(report.duration)
+            );
+
+            if !report.corrupted.is_empty() {
+                println!(
+                    "\n{} {} corrupted file(s) detected",
+                    "✗".red(),
+                    report.corrupted.len()
+                );
+                std::process::exit(1);
+            } else if !report.errors.is_empty() {
+                std::process::exit(2);
+            }
+            println!("\n{} No bitrot detected", "✓".green());
+            Ok(())
+        }
+        Command::History { json, limit } => {
+            let runs = match history::History::default_path()? {
+                Some(path) => history::History::open(&path).runs(limit)?,
+                None => Vec::new(),
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&runs)?);
+                return Ok(());
+            }
+            if runs.is_empty() {
+                println!("No runs recorded");
+                return Ok(());
+            }
+
+            for run in &runs {
+                let status = match run.status.as_str() {
+                    "ok" => "ok".green(),
+                    "partial" => "partial".yellow(),
+                    "interrupted" => "interrupted".yellow(),
+                    status => status.red(),
+                };
+                let what = match &run.profile {
+                    Some(profile) => format!("[{}] ", profile),
+                    None => String::new(),
+                };
+                println!(
+                    "{}  {:<11}  {}{} → {}{}",
+                    run.started_at,
+                    status,
+                    what,
+                    run.source,
+                    run.destination,
+                    if run.dry_run { " (dry run)" } else { "" }
+                );
+                match &run.error {
+                    Some(error) => println!("    {}", error.red()),
+                    None => println!(
+                        "    {} created, {} updated, {} deleted, {} in {}{}",
+                        run.files_created,
+                        run.files_updated,
+                        run.files_deleted,
+                        format_bytes(run.bytes_transferred),
+                        format_duration(
+                            std::time::Duration::try_from_secs_f64(run.duration_secs)
+                                .unwrap_or_default()
+                        ),
+                        match run.failed_count.max(run.failed.len()) {
+                            0 => String::new(),
+                            failed => format!(", {} failed", failed),
+                        }
+                    ),
+                }
+            }
+
+            if let Some(last) = runs
+                .iter()
+                .rev()
+                .find(|run| run.succeeded() && !run.dry_run)
+            {
+                println!("\nLast successful run: {}", last.started_at);
+            } else {
+                println!("\n{}", "No successful run in this history".yellow());
+            }
+            Ok(())
+        }
+        // Needs the runtime: handled in main
+        Command::Push {
+            source,
+            dedup_store,
+        } => {
+            let store = sync::dedup::Store::open(&dedup_store)?;
+            let report = store
+                .push(&source)
+                .with_context(|| format!("Failed to snapshot {}", source.display()))?;
+
+            println!(
+                "{} Snapshot {} of {}",
+                "✓".green(),
+                report.snapshot.bold(),
+                source.display()
+            );
+            println!(
+                "\n  Files:                {} ({} unchanged)",
+                report.files, report.files_unchanged
+            );
+            println!(
+                "  Bytes read:           {}",
+                format_bytes(report.bytes_read)
+            );
+            println!(
+                "  Chunks:               {} new, {} already stored",
+                report.chunks_new, report.chunks_reused
+            );
+            println!(
+                "  Added to store:       {}",
+                format_bytes(report.bytes_stored)
+            );
+            println!(
+                "  Duration:             {}",
+                format_duration(report.duration)
+            );
+            Ok(())
+        }
+        Command::Restore {
+            store,
+            target,
+            snapshot,
+        } => {
+            let report = sync::dedup::Store::open(&store)?
+                .restore(snapshot.as_deref(), &target)
+                .with_context(|| format!("Failed to restore from {}", store.display()))?;
+
+            println!(
+                "{} Restored snapshot {} to {}",
+                "✓".green(),
+                report.snapshot.bold(),
+                target.display()
+            );
+            println!(
+                "\n  Files:                {} ({})",
+                report.files,
+                format_bytes(report.bytes_written)
+            );
+            println!("  Directories:          {}", report.dirs);
+            println!("  Symlinks:             {}", report.symlinks);
+            println!(
+                "  Duration:             {}",
+                format_duration(report.duration)
+            );
+            Ok(())
+        }
+        Command::Mount { .. } => unreachable!("sy mount runs from main"),
+        Command::Bench { .. } => unreachable!("sy bench runs from main"),
+    }
+}
+
 fn format_duration(duration: std::time::Duration) -> String {
     let secs = duration.as_secs();
     let millis = duration.subsec_millis();
@@ -0,0 +1,448 @@
+//! Directory manifests (`sy manifest create` / `sy manifest verify`)
+//!
+//! A manifest is a JSON snapshot of a tree's expected state: one entry per
+//! regular file with its relative path, size, mtime and BLAKE3 hash. It can be
+//! written next to a backup and later used to validate an offsite copy without
+//! access to the original source.
+//!
+//! Manifests can be signed with an ed25519 key (detached `<manifest>.sig`),
+//! so verification also detects deliberate tampering: an attacker who rewrites
+//! both the tree and the manifest can't produce a valid signature.
+
+use crate::error::{Result, SyncError};
+use crate::integrity::Blake3Hasher;
+use crate::sync::scanner::Scanner;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current manifest format version
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Snapshot of a directory tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// Creation time (RFC 3339)
+    pub created: String,
+    /// Hash algorithm used for `ManifestEntry::hash`
+    pub algorithm: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// A single regular file in a manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the manifest root, always `/`-separated
+    pub path: String,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: i64,
+    /// Hex-encoded BLAKE3 hash of the file contents
+    pub hash: String,
+}
+
+/// A file whose contents differ from its manifest entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestMismatch {
+    pub path: String,
+    pub reason: String,
+}
+
+/// Result of checking a directory against a manifest
+#[derive(Debug, Default)]
+pub struct ManifestReport {
+    pub files_matched: usize,
+    pub mismatched: Vec<ManifestMismatch>,
+    /// Files listed in the manifest but absent from the directory
+    pub missing: Vec<String>,
+    /// Files present in the directory but not listed in the manifest
+    pub extra: Vec<String>,
+    /// Files whose contents match but whose mtime differs
+    pub mtime_changed: Vec<String>,
+}
+
+impl ManifestReport {
+    /// True when every manifest entry was found with matching contents
+    /// and the directory contains no unlisted files
+    pub fn is_clean(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// Metadata gathered for a file during a manifest scan
+struct ScannedFile {
+    path: String,
+    full_path: std::path::PathBuf,
+    size: u64,
+    mtime: i64,
+}
+
+pub(crate) fn unix_mtime(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+pub(crate) fn manifest_path(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Scan regular files under `root`, sorted by manifest path
+fn scan_files(root: &Path) -> Result<Vec<ScannedFile>> {
+    if !root.is_dir() {
+        return Err(SyncError::SourceNotFound {
+            path: root.to_path_buf(),
+        });
+    }
+
+    let mut files: Vec<ScannedFile> = Scanner::new(root)
+        .scan()?
+        .into_iter()
+        .filter(|e| !e.is_dir && !e.is_symlink)
+        .map(|e| ScannedFile {
+            path: manifest_path(&e.relative_path),
+            full_path: (*e.path).clone(),
+            size: e.size,
+            mtime: unix_mtime(e.modified),
+        })
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    Ok(Blake3Hasher::hash_file(path)?.to_hex().to_string())
+}
+
+impl Manifest {
+    /// Build a manifest for every regular file under `root`
+    ///
+    /// Files are hashed in parallel. Symlinks and directories are not recorded.
+    pub fn create(root: &Path) -> Result<Self> {
+        let files = scan_files(root)?;
+
+        let entries = files
+            .into_par_iter()
+            .map(|f| {
+                Ok(ManifestEntry {
+                    hash: hash_file(&f.full_path)?,
+                    path: f.path,
+                    size: f.size,
+                    mtime: f.mtime,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            created: chrono::Utc::now().to_rfc3339(),
+            algorithm: "blake3".to_string(),
+            files: entries,
+        })
+    }
+
+    /// Serialize as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("manifest is always serializable")
+    }
+
+    /// Load a manifest from a JSON file
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let manifest: Self =
+            serde_json::from_str(&data).map_err(|e| SyncError::InvalidManifest {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
+            })?;
+
+        if manifest.version != MANIFEST_VERSION {
+            return Err(SyncError::InvalidManifest {
+                path: path.to_path_buf(),
+                reason: format!("unsupported manifest version {}", manifest.version),
+            });
+        }
+        if manifest.algorithm != "blake3" {
+            return Err(SyncError::InvalidManifest {
+                path: path.to_path_buf(),
+                reason: format!("unsupported hash algorithm '{}'", manifest.algorithm),
+            });
+        }
+        Ok(manifest)
+    }
+
+    /// Check the files under `root` against this manifest
+    ///
+    /// Size and content hash decide whether a file matches. Files whose size
+    /// differs are reported without being hashed.
+    pub fn verify(&self, root: &Path) -> Result<ManifestReport> {
+        let mut on_disk: HashMap<String, ScannedFile> = scan_files(root)?
+            .into_iter()
+            .map(|f| (f.path.clone(), f))
+            .collect();
+
+        let mut report = ManifestReport::default();
+        let mut to_hash = Vec::new();
+
+        for entry in &self.files {
+            match on_disk.remove(&entry.path) {
+                None => report.missing.push(entry.path.clone()),
+                Some(file) if file.size != entry.size => {
+                    report.mismatched.push(ManifestMismatch {
+                        path: entry.path.clone(),
+                        reason: format!("size {} (expected {})", file.size, entry.size),
+                    });
+                }
+                Some(file) => to_hash.push((entry, file)),
+            }
+        }
+
+        let hashed: Vec<_> = to_hash
+            .into_par_iter()
+            .map(|(entry, file)| (entry, file.mtime, hash_file(&file.full_path)))
+            .collect();
+
+        for (entry, mtime, hash) in hashed {
+            match hash {
+                Ok(hash) if hash == entry.hash => {
+                    report.files_matched += 1;
+                    if mtime != entry.mtime {
+                        report.mtime_changed.push(entry.path.clone());
+                    }
+                }
+                Ok(_) => report.mismatched.push(ManifestMismatch {
+                    path: entry.path.clone(),
+                    reason: "content hash differs".to_string(),
+                }),
+                Err(e) => report.mismatched.push(ManifestMismatch {
+                    path: entry.path.clone(),
+                    reason: format!("read failed: {}", e),
+                }),
+            }
+        }
+
+        report.extra = on_disk.into_keys().collect();
+        report.extra.sort();
+
+        Ok(report)
+    }
+}
+
+// =============================================================================
+// Signing
+// =============================================================================
+
+/// Path of the detached signature for a manifest file
+pub fn signature_path(manifest: &Path) -> PathBuf {
+    let mut name = manifest.as_os_str().to_owned();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+/// Path of the public key written alongside a secret key
+pub fn public_key_path(secret: &Path) -> PathBuf {
+    let mut name = secret.as_os_str().to_owned();
+    name.push(".pub");
+    PathBuf::from(name)
+}
+
+fn read_hex_key<const N: usize>(path: &Path, what: &str) -> Result<[u8; N]> {
+    let text = std::fs::read_to_string(path)?;
+    let bytes = hex::decode(text.trim()).map_err(|e| SyncError::ManifestSignature {
+        path: path.to_path_buf(),
+        reason: format!("{} is not valid hex: {}", what, e),
+    })?;
+    bytes.try_into().map_err(|_| SyncError::ManifestSignature {
+        path: path.to_path_buf(),
+        reason: format!("{} must be {} bytes", what, N),
+    })
+}
+
+/// Generate a new signing key, writing the secret key to `path` (mode 0600)
+/// and the public key to `path.pub`
+pub fn generate_key(path: &Path) -> Result<VerifyingKey> {
+    let key = SigningKey::generate(&mut rand_core::OsRng);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    std::io::Write::write_all(
+        &mut file,
+        format!("{}\n", hex::encode(key.to_bytes())).as_bytes(),
+    )?;
+
+    let public = key.verifying_key();
+    std::fs::write(
+        public_key_path(path),
+        format!("{}\n", hex::encode(public.to_bytes())),
+    )?;
+    Ok(public)
+}
+
+/// Load a secret key written by `generate_key`
+pub fn load_signing_key(path: &Path) -> Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&read_hex_key(path, "secret key")?))
+}
+
+/// Load a public key written by `generate_key`
+pub fn load_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    let bytes = read_hex_key(path, "public key")?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| SyncError::ManifestSignature {
+        path: path.to_path_buf(),
+        reason: format!("invalid public key: {}", e),
+    })
+}
+
+/// Sign the exact bytes of a manifest file, writing `<manifest>.sig`
+pub fn sign_file(manifest: &Path, key: &SigningKey) -> Result<PathBuf> {
+    let data = std::fs::read(manifest)?;
+    let signature = key.sign(&data);
+    let sig_path = signature_path(manifest);
+    std::fs::write(
+        &sig_path,
+        format!("{}\n", hex::encode(signature.to_bytes())),
+    )?;
+    Ok(sig_path)
+}
+
+/// Check a manifest file against its detached signature
+pub fn verify_file_signature(manifest: &Path, signature: &Path, key: &VerifyingKey) -> Result<()> {
+    let data = std::fs::read(manifest)?;
+    let sig_bytes = read_hex_key::<64>(signature, "signature")?;
+    key.verify(&data, &Signature::from_bytes(&sig_bytes))
+        .map_err(|_| SyncError::ManifestSignature {
+            path: manifest.to_path_buf(),
+            reason: "signature does not match (manifest modified or wrong key)".to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn sample_tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), b"alpha").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/b.bin"), vec![7u8; 10_000]).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_create_lists_files_sorted() {
+        let dir = sample_tree();
+        let manifest = Manifest::create(dir.path()).unwrap();
+
+        let paths: Vec<_> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.txt", "sub/b.bin"]);
+        assert_eq!(manifest.files[0].size, 5);
+        assert_eq!(
+            manifest.files[0].hash,
+            blake3::hash(b"alpha").to_hex().to_string()
+        );
+    }
+
+    #[test]
+    fn test_roundtrip_and_verify_clean() {
+        let dir = sample_tree();
+        let manifest = Manifest::create(dir.path()).unwrap();
+
+        let out = TempDir::new().unwrap();
+        let manifest_file = out.path().join("m.json");
+        fs::write(&manifest_file, manifest.to_json()).unwrap();
+
+        let loaded = Manifest::load(&manifest_file).unwrap();
+        assert_eq!(loaded, manifest);
+
+        let report = loaded.verify(dir.path()).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.files_matched, 2);
+    }
+
+    #[test]
+    fn test_verify_detects_changes() {
+        let dir = sample_tree();
+        let manifest = Manifest::create(dir.path()).unwrap();
+
+        // Same size, different content
+        fs::write(dir.path().join("a.txt"), b"ALPHA").unwrap();
+        fs::remove_file(dir.path().join("sub/b.bin")).unwrap();
+        fs::write(dir.path().join("new.txt"), b"new").unwrap();
+
+        let report = manifest.verify(dir.path()).unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.files_matched, 0);
+        assert_eq!(report.mismatched.len(), 1);
+        assert_eq!(report.mismatched[0].path, "a.txt");
+        assert_eq!(report.missing, vec!["sub/b.bin"]);
+        assert_eq!(report.extra, vec!["new.txt"]);
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_version() {
+        let out = TempDir::new().unwrap();
+        let path = out.path().join("m.json");
+        fs::write(
+            &path,
+            r#"{"version":99,"created":"","algorithm":"blake3","files":[]}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Manifest::load(&path),
+            Err(SyncError::InvalidManifest { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sign_and_verify_manifest() {
+        let dir = sample_tree();
+        let out = TempDir::new().unwrap();
+        let key_path = out.path().join("sy.key");
+        let manifest_file = out.path().join("m.json");
+        fs::write(
+            &manifest_file,
+            Manifest::create(dir.path()).unwrap().to_json(),
+        )
+        .unwrap();
+
+        let public = generate_key(&key_path).unwrap();
+        assert_eq!(
+            load_verifying_key(&public_key_path(&key_path)).unwrap(),
+            public
+        );
+
+        let secret = load_signing_key(&key_path).unwrap();
+        let sig = sign_file(&manifest_file, &secret).unwrap();
+        assert_eq!(sig, out.path().join("m.json.sig"));
+        verify_file_signature(&manifest_file, &sig, &public).unwrap();
+
+        // Any edit to the manifest invalidates the signature
+        let tampered = fs::read_to_string(&manifest_file)
+            .unwrap()
+            .replace("a.txt", "b.txt");
+        fs::write(&manifest_file, tampered).unwrap();
+        assert!(matches!(
+            verify_file_signature(&manifest_file, &sig, &public),
+            Err(SyncError::ManifestSignature { .. })
+        ));
+
+        // Refuses to overwrite an existing key
+        assert!(generate_key(&key_path).is_err());
+    }
+}
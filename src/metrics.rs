@@ -0,0 +1,369 @@
+//! Sync metrics for monitoring
+//!
+//! Every finished sync is recorded into a process-wide [`Metrics`] registry
+//! ([`global`]): bytes sent and received, files per state, time per phase,
+//! and retries. Long-running processes (`--watch`) can expose it to
+//! Prometheus with `--metrics-addr`; library users get a
+//! [`MetricsSnapshot`] of each run through `SyncBuilder::on_metrics`.
+
+use crate::perf::PerformanceMetrics;
+use crate::sync::SyncStats;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Callback receiving the metrics of each finished sync
+pub type MetricsCallback = Arc<dyn Fn(&MetricsSnapshot) + Send + Sync>;
+
+/// Counters of one sync, or totals over many
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    pub syncs_ok: u64,
+    pub syncs_failed: u64,
+    /// Bytes written to a remote destination
+    pub bytes_sent: u64,
+    /// Bytes written to a local destination (pulls and local copies)
+    pub bytes_received: u64,
+    pub files_created: u64,
+    pub files_updated: u64,
+    pub files_skipped: u64,
+    pub files_deleted: u64,
+    pub files_failed: u64,
+    pub retries: u64,
+    pub scan_time: Duration,
+    pub plan_time: Duration,
+    pub transfer_time: Duration,
+    pub total_time: Duration,
+}
+
+impl MetricsSnapshot {
+    /// Metrics of a finished sync; phase times need the performance monitor
+    pub fn from_sync(
+        stats: &SyncStats,
+        phases: Option<&PerformanceMetrics>,
+        dest_is_remote: bool,
+    ) -> Self {
+        let (bytes_sent, bytes_received) = if dest_is_remote {
+            (stats.bytes_transferred, 0)
+        } else {
+            (0, stats.bytes_transferred)
+        };
+        Self {
+            syncs_ok: 1,
+            bytes_sent,
+            bytes_received,
+            files_created: stats.files_created,
+            files_updated: stats.files_updated,
+            files_skipped: stats.files_skipped as u64,
+            files_deleted: stats.files_deleted as u64,
+            files_failed: stats.errors.len() as u64,
+            scan_time: phases.map_or(Duration::ZERO, |p| p.scan_duration),
+            plan_time: phases.map_or(Duration::ZERO, |p| p.plan_duration),
+            transfer_time: phases.map_or(Duration::ZERO, |p| p.transfer_duration),
+            total_time: stats.duration,
+            ..Default::default()
+        }
+    }
+
+    /// Metrics of a sync that returned an error
+    pub fn failed(total_time: Duration) -> Self {
+        Self {
+            syncs_failed: 1,
+            total_time,
+            ..Default::default()
+        }
+    }
+}
+
+/// Cumulative metrics of all syncs in this process
+#[derive(Debug, Default)]
+pub struct Metrics {
+    syncs_ok: AtomicU64,
+    syncs_failed: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    files_created: AtomicU64,
+    files_updated: AtomicU64,
+    files_skipped: AtomicU64,
+    files_deleted: AtomicU64,
+    files_failed: AtomicU64,
+    retries: AtomicU64,
+    scan_nanos: AtomicU64,
+    plan_nanos: AtomicU64,
+    transfer_nanos: AtomicU64,
+    total_nanos: AtomicU64,
+    /// Unix time of the last finished sync, 0 before the first
+    last_sync_secs: AtomicU64,
+}
+
+/// The process-wide registry syncs are recorded into
+pub fn global() -> &'static Metrics {
+    static GLOBAL: Metrics = Metrics::new();
+    &GLOBAL
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            syncs_ok: AtomicU64::new(0),
+            syncs_failed: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            files_created: AtomicU64::new(0),
+            files_updated: AtomicU64::new(0),
+            files_skipped: AtomicU64::new(0),
+            files_deleted: AtomicU64::new(0),
+            files_failed: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            scan_nanos: AtomicU64::new(0),
+            plan_nanos: AtomicU64::new(0),
+            transfer_nanos: AtomicU64::new(0),
+            total_nanos: AtomicU64::new(0),
+            last_sync_secs: AtomicU64::new(0),
+        }
+    }
+
+    /// Add a finished sync to the totals
+    pub fn record(&self, run: &MetricsSnapshot) {
+        let add = |counter: &AtomicU64, value: u64| {
+            counter.fetch_add(value, Ordering::Relaxed);
+        };
+        let nanos = |d: Duration| d.as_nanos().min(u64::MAX as u128) as u64;
+        add(&self.syncs_ok, run.syncs_ok);
+        add(&self.syncs_failed, run.syncs_failed);
+        add(&self.bytes_sent, run.bytes_sent);
+        add(&self.bytes_received, run.bytes_received);
+        add(&self.files_created, run.files_created);
+        add(&self.files_updated, run.files_updated);
+        add(&self.files_skipped, run.files_skipped);
+        add(&self.files_deleted, run.files_deleted);
+        add(&self.files_failed, run.files_failed);
+        add(&self.scan_nanos, nanos(run.scan_time));
+        add(&self.plan_nanos, nanos(run.plan_time));
+        add(&self.transfer_nanos, nanos(run.transfer_time));
+        add(&self.total_nanos, nanos(run.total_time));
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.last_sync_secs.store(now, Ordering::Relaxed);
+    }
+
+    /// Count one retried operation (SSH reconnects, ...)
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            syncs_ok: load(&self.syncs_ok),
+            syncs_failed: load(&self.syncs_failed),
+            bytes_sent: load(&self.bytes_sent),
+            bytes_received: load(&self.bytes_received),
+            files_created: load(&self.files_created),
+            files_updated: load(&self.files_updated),
+            files_skipped: load(&self.files_skipped),
+            files_deleted: load(&self.files_deleted),
+            files_failed: load(&self.files_failed),
+            retries: load(&self.retries),
+            scan_time: Duration::from_nanos(load(&self.scan_nanos)),
+            plan_time: Duration::from_nanos(load(&self.plan_nanos)),
+            transfer_time: Duration::from_nanos(load(&self.transfer_nanos)),
+            total_time: Duration::from_nanos(load(&self.total_nanos)),
+        }
+    }
+
+    /// The totals in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let s = self.snapshot();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+
+        metric(
+            "sy_syncs_total",
+            "counter",
+            "Finished syncs by result",
+            &[
+                (r#"{result="ok"}"#, s.syncs_ok.to_string()),
+                (r#"{result="failed"}"#, s.syncs_failed.to_string()),
+            ],
+        );
+        metric(
+            "sy_bytes_sent_total",
+            "counter",
+            "Bytes written to remote destinations",
+            &[("", s.bytes_sent.to_string())],
+        );
+        metric(
+            "sy_bytes_received_total",
+            "counter",
+            "Bytes written to local destinations",
+            &[("", s.bytes_received.to_string())],
+        );
+        metric(
+            "sy_files_total",
+            "counter",
+            "Files by what the sync did with them",
+            &[
+                (r#"{state="created"}"#, s.files_created.to_string()),
+                (r#"{state="updated"}"#, s.files_updated.to_string()),
+                (r#"{state="skipped"}"#, s.files_skipped.to_string()),
+                (r#"{state="deleted"}"#, s.files_deleted.to_string()),
+                (r#"{state="failed"}"#, s.files_failed.to_string()),
+            ],
+        );
+        metric(
+            "sy_retries_total",
+            "counter",
+            "Operations retried after a transient error",
+            &[("", s.retries.to_string())],
+        );
+        metric(
+            "sy_phase_seconds_total",
+            "counter",
+            "Time spent per sync phase",
+            &[
+                (r#"{phase="scan"}"#, s.scan_time.as_secs_f64().to_string()),
+                (r#"{phase="plan"}"#, s.plan_time.as_secs_f64().to_string()),
+                (
+                    r#"{phase="transfer"}"#,
+                    s.transfer_time.as_secs_f64().to_string(),
+                ),
+            ],
+        );
+        metric(
+            "sy_sync_seconds_total",
+            "counter",
+            "Wall time of finished syncs",
+            &[("", s.total_time.as_secs_f64().to_string())],
+        );
+        metric(
+            "sy_last_sync_timestamp_seconds",
+            "gauge",
+            "Unix time the last sync finished",
+            &[("", self.last_sync_secs.load(Ordering::Relaxed).to_string())],
+        );
+        out
+    }
+}
+
+/// Answer `GET /metrics` on `listener` with the [`global`] registry until
+/// the task is dropped
+pub async fn serve(listener: TcpListener) -> std::io::Result<()> {
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        tokio::spawn(async move {
+            // Only the request line matters; 4KB covers any sane scraper
+            let mut buf = [0u8; 4096];
+            let n = match stream.read(&mut buf).await {
+                Ok(n) => n,
+                Err(e) => {
+                    tracing::debug!("Metrics request from {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let mut words = request.split_whitespace();
+            let response = match (words.next(), words.next()) {
+                (Some("GET"), Some("/metrics")) => {
+                    let body = global().render_prometheus();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_accumulates_runs() {
+        let metrics = Metrics::new();
+        let stats = SyncStats {
+            files_created: 2,
+            files_updated: 1,
+            files_skipped: 5,
+            bytes_transferred: 300,
+            ..Default::default()
+        };
+        metrics.record(&MetricsSnapshot::from_sync(&stats, None, true));
+        metrics.record(&MetricsSnapshot::from_sync(&stats, None, false));
+        metrics.record(&MetricsSnapshot::failed(Duration::from_secs(1)));
+        metrics.record_retry();
+
+        let s = metrics.snapshot();
+        assert_eq!(s.syncs_ok, 2);
+        assert_eq!(s.syncs_failed, 1);
+        assert_eq!(s.bytes_sent, 300);
+        assert_eq!(s.bytes_received, 300);
+        assert_eq!(s.files_created, 4);
+        assert_eq!(s.files_skipped, 10);
+        assert_eq!(s.retries, 1);
+        assert_eq!(s.total_time, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = Metrics::new();
+        let stats = SyncStats {
+            files_deleted: 3,
+            bytes_transferred: 42,
+            ..Default::default()
+        };
+        metrics.record(&MetricsSnapshot::from_sync(&stats, None, false));
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("# TYPE sy_files_total counter\n"));
+        assert!(text.contains("sy_files_total{state=\"deleted\"} 3\n"));
+        assert!(text.contains("sy_bytes_received_total 42\n"));
+        assert!(text.contains("sy_syncs_total{result=\"ok\"} 1\n"));
+        assert!(!text.contains("sy_last_sync_timestamp_seconds 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener));
+
+        let fetch = |path: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let ok = fetch("/metrics").await;
+        assert!(ok.starts_with("HTTP/1.1 200 OK"));
+        assert!(ok.contains("sy_retries_total"));
+        assert!(fetch("/").await.starts_with("HTTP/1.1 404"));
+        server.abort();
+    }
+}
@@ -0,0 +1,292 @@
+//! `sy mount`: a remote tree as a read-only FUSE filesystem
+//!
+//! Directories are listed from the server the first time they're looked
+//! at and kept for the life of the mount; file contents are read on demand
+//! over the same browse session, so only what's opened crosses the network.
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyOpen, Request,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::runtime::Handle;
+
+use crate::error::{ErrorCode, SyncError};
+use crate::streaming::protocol::Read;
+use crate::streaming::{FileEntry, FileFlags, RemoteTree};
+
+/// How long the kernel may cache attributes and lookups
+const TTL: Duration = Duration::from_secs(60);
+
+const ROOT_INO: u64 = 1;
+
+/// Mount `tree` read-only at `mountpoint` until SIGINT or SIGTERM
+///
+/// Must be called inside a multi-threaded Tokio runtime: FUSE requests are
+/// answered on a thread of their own, which blocks on the runtime.
+pub async fn mount<R, W>(tree: RemoteTree<R, W>, mountpoint: &Path, name: &str) -> Result<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let fs = RemoteFs::new(tree, Handle::current());
+    let options = [
+        MountOption::RO,
+        MountOption::FSName(name.to_string()),
+        MountOption::Subtype("sy".to_string()),
+        MountOption::DefaultPermissions,
+    ];
+    let session = fuser::spawn_mount2(fs, mountpoint, &options)?;
+
+    crate::interrupt::install().cancelled().await;
+    // Unmounts
+    drop(session);
+    Ok(())
+}
+
+struct Node {
+    /// Relative to the root of the tree; empty for the root
+    path: String,
+    parent: u64,
+    attr: FileAttr,
+    symlink_target: Option<String>,
+    /// Inodes of the directory's entries, once listed
+    children: Option<Vec<u64>>,
+}
+
+/// The filesystem: inode N is `nodes[N - 1]`, handed out as entries are
+/// first listed
+struct RemoteFs<R, W> {
+    tree: RemoteTree<R, W>,
+    runtime: Handle,
+    nodes: Vec<Node>,
+    uid: u32,
+    gid: u32,
+}
+
+impl<R, W> RemoteFs<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    fn new(tree: RemoteTree<R, W>, runtime: Handle) -> Self {
+        // SAFETY: getuid/getgid can't fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut fs = Self {
+            tree,
+            runtime,
+            nodes: Vec::new(),
+            uid,
+            gid,
+        };
+        let root = FileEntry {
+            path: "".into(),
+            size: 0,
+            mtime: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64),
+            mtime_nsec: 0,
+            mode: 0o755,
+            inode: 0,
+            flags: FileFlags::DIR,
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        fs.add_node(root, ROOT_INO);
+        fs
+    }
+
+    fn add_node(&mut self, entry: FileEntry, parent: u64) -> u64 {
+        let ino = self.nodes.len() as u64 + 1;
+        let attr = self.attr(ino, &entry);
+        self.nodes.push(Node {
+            path: entry.path.to_string(),
+            parent,
+            attr,
+            symlink_target: entry.symlink_target,
+            children: None,
+        });
+        ino
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        ino.checked_sub(1).and_then(|i| self.nodes.get(i as usize))
+    }
+
+    fn attr(&self, ino: u64, entry: &FileEntry) -> FileAttr {
+        let (kind, nlink) = if entry.is_dir() {
+            (FileType::Directory, 2)
+        } else if entry.is_symlink() {
+            (FileType::Symlink, 1)
+        } else {
+            (FileType::RegularFile, 1)
+        };
+        let mtime = UNIX_EPOCH + Duration::from_secs(entry.mtime.max(0) as u64);
+        FileAttr {
+            ino,
+            size: entry.size,
+            blocks: entry.size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: mtime,
+            kind,
+            // Read-only whatever the server's modes say
+            perm: (entry.mode & 0o7555) as u16,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    /// Inodes of directory `ino`'s entries, listing it on first use
+    fn children(&mut self, ino: u64) -> Result<Vec<u64>, i32> {
+        let node = self.node(ino).ok_or(libc::ENOENT)?;
+        if node.attr.kind != FileType::Directory {
+            return Err(libc::ENOTDIR);
+        }
+        if let Some(children) = &node.children {
+            return Ok(children.clone());
+        }
+
+        let path = node.path.clone();
+        let entries = self
+            .runtime
+            .block_on(self.tree.list(&path))
+            .map_err(errno)?;
+        let children: Vec<u64> = entries
+            .into_iter()
+            .map(|entry| self.add_node(entry, ino))
+            .collect();
+        self.nodes[ino as usize - 1].children = Some(children.clone());
+        Ok(children)
+    }
+
+    fn name(&self, ino: u64) -> &str {
+        let path = &self.nodes[ino as usize - 1].path;
+        path.rsplit('/').next().unwrap_or(path)
+    }
+}
+
+impl<R, W> Filesystem for RemoteFs<R, W>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let children = match self.children(parent) {
+            Ok(children) => children,
+            Err(e) => return reply.error(e),
+        };
+        match children
+            .into_iter()
+            .find(|&ino| OsStr::new(self.name(ino)) == name)
+        {
+            Some(ino) => reply.entry(&TTL, &self.nodes[ino as usize - 1].attr, 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.node(ino) {
+            Some(node) => reply.attr(&TTL, &node.attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        match self.node(ino).and_then(|node| node.symlink_target.as_ref()) {
+            Some(target) => reply.data(target.as_bytes()),
+            None => reply.error(libc::EINVAL),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        if self.node(ino).is_none() {
+            return reply.error(libc::ENOENT);
+        }
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return reply.error(libc::EROFS);
+        }
+        reply.opened(0, 0);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(node) = self.node(ino) else {
+            return reply.error(libc::ENOENT);
+        };
+        let path = node.path.clone();
+        let read = self
+            .tree
+            .read(&path, offset.max(0) as u64, size.min(Read::MAX_LEN));
+        match self.runtime.block_on(read) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(errno(e)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let children = match self.children(ino) {
+            Ok(children) => children,
+            Err(e) => return reply.error(e),
+        };
+        let parent = self.nodes[ino as usize - 1].parent;
+        let entries = [
+            (ino, FileType::Directory, "."),
+            (parent, FileType::Directory, ".."),
+        ]
+        .into_iter()
+        .chain(children.iter().map(|&child| {
+            (
+                child,
+                self.nodes[child as usize - 1].attr.kind,
+                self.name(child),
+            )
+        }));
+        for (i, (ino, kind, name)) in entries.enumerate().skip(offset as usize) {
+            // Full: the kernel asks again from the next offset
+            if reply.add(ino, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// The errno to answer a request with when the server couldn't
+fn errno(err: anyhow::Error) -> i32 {
+    match SyncError::from(err).error_code() {
+        ErrorCode::NotFound => libc::ENOENT,
+        ErrorCode::PermissionDenied => libc::EACCES,
+        _ => libc::EIO,
+    }
+}
@@ -0,0 +1,365 @@
+//! Completion and failure notifications for unattended syncs
+//!
+//! Configured with `[notify]` (or per profile) in the config file. Each
+//! channel goes through a standard tool rather than a client library: the
+//! webhook and SMTP delivery use `curl`, local mail uses `sendmail -t`, and
+//! desktop notifications use `notify-send` (Linux) or `osascript` (macOS).
+//! A channel that fails only logs a warning; it never changes the outcome
+//! of the sync it reports on.
+
+use crate::config::{NotifyOn, NotifySettings};
+use crate::sync::SyncStats;
+use anyhow::{bail, Context as _, Result};
+use serde::Serialize;
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+
+/// What a notification says about a finished (or failed) run
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub profile: Option<String>,
+    pub source: String,
+    pub destination: String,
+    /// `ok`, `partial` (some files failed or vanished), `interrupted` or
+    /// `failed`
+    pub status: &'static str,
+    /// Why the run failed outright
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub files_scanned: u64,
+    pub files_created: u64,
+    pub files_updated: u64,
+    pub files_deleted: usize,
+    /// Source files deleted while the sync ran
+    pub files_vanished: usize,
+    pub bytes_transferred: u64,
+    pub duration_secs: f64,
+    /// Paths that failed to sync
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<String>,
+    pub dry_run: bool,
+}
+
+impl Summary {
+    /// Summary of a run that got as far as producing stats
+    pub fn from_stats(
+        profile: Option<&str>,
+        source: String,
+        destination: String,
+        stats: &SyncStats,
+        dry_run: bool,
+    ) -> Self {
+        Self {
+            profile: profile.map(str::to_string),
+            source,
+            destination,
+            status: if stats.cancelled {
+                "interrupted"
+            } else if stats.errors.is_empty() && stats.files_vanished == 0 {
+                "ok"
+            } else {
+                "partial"
+            },
+            error: None,
+            files_scanned: stats.files_scanned,
+            files_created: stats.files_created,
+            files_updated: stats.files_updated,
+            files_deleted: stats.files_deleted,
+            files_vanished: stats.files_vanished,
+            bytes_transferred: stats.bytes_transferred,
+            duration_secs: stats.duration.as_secs_f64(),
+            failed: stats
+                .errors
+                .iter()
+                .map(|e| e.path.display().to_string())
+                .collect(),
+            dry_run,
+        }
+    }
+
+    /// Summary of a run that stopped with `error`
+    pub fn from_error(
+        profile: Option<&str>,
+        source: String,
+        destination: String,
+        error: &anyhow::Error,
+        dry_run: bool,
+    ) -> Self {
+        Self {
+            profile: profile.map(str::to_string),
+            source,
+            destination,
+            status: "failed",
+            error: Some(format!("{:#}", error)),
+            files_scanned: 0,
+            files_created: 0,
+            files_updated: 0,
+            files_deleted: 0,
+            files_vanished: 0,
+            bytes_transferred: 0,
+            duration_secs: 0.0,
+            failed: Vec::new(),
+            dry_run,
+        }
+    }
+
+    fn is_failure(&self) -> bool {
+        self.status != "ok"
+    }
+
+    /// One-line headline, used as email subject and notification title
+    fn title(&self) -> String {
+        let what = match &self.profile {
+            Some(name) => format!("sy {}", name),
+            None => format!("sy {} → {}", self.source, self.destination),
+        };
+        match self.status {
+            "ok" => format!("{}: finished", what),
+            "partial" if self.failed.is_empty() => {
+                format!("{}: {} files vanished", what, self.files_vanished)
+            }
+            "partial" => format!("{}: {} files failed", what, self.failed.len()),
+            "interrupted" => format!("{}: interrupted", what),
+            _ => format!("{}: failed", what),
+        }
+    }
+
+    /// Plain-text body for email and desktop notifications
+    fn body(&self) -> String {
+        let mut body = format!("{} → {}\n", self.source, self.destination);
+        if let Some(ref error) = self.error {
+            body.push_str(&format!("Error: {}\n", error));
+        } else {
+            body.push_str(&format!(
+                "{} scanned, {} created, {} updated, {} deleted, {} bytes in {:.1}s\n",
+                self.files_scanned,
+                self.files_created,
+                self.files_updated,
+                self.files_deleted,
+                self.bytes_transferred,
+                self.duration_secs
+            ));
+        }
+        if self.files_vanished > 0 {
+            body.push_str(&format!(
+                "{} source files vanished during the sync\n",
+                self.files_vanished
+            ));
+        }
+        for path in &self.failed {
+            body.push_str(&format!("Failed: {}\n", path));
+        }
+        body
+    }
+}
+
+/// Send `summary` on every channel in `settings` it qualifies for
+pub fn send(settings: &NotifySettings, summary: &Summary) {
+    if settings.on == NotifyOn::Failure && !summary.is_failure() {
+        return;
+    }
+
+    if let Some(ref url) = settings.webhook {
+        if let Err(e) = post_webhook(url, summary) {
+            tracing::warn!("Webhook notification to {} failed: {:#}", url, e);
+        }
+    }
+    if !settings.email.is_empty() {
+        if let Err(e) = send_email(settings, summary) {
+            tracing::warn!("Email notification failed: {:#}", e);
+        }
+    }
+    if settings.desktop {
+        if let Err(e) = show_desktop(summary) {
+            tracing::warn!("Desktop notification failed: {:#}", e);
+        }
+    }
+}
+
+fn post_webhook(url: &str, summary: &Summary) -> Result<()> {
+    let json = serde_json::to_vec(summary)?;
+    run_with_stdin(
+        Command::new("curl").args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            "30",
+            "--header",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+            url,
+        ]),
+        &json,
+    )
+}
+
+fn send_email(settings: &NotifySettings, summary: &Summary) -> Result<()> {
+    let from = settings.from.as_deref().unwrap_or("sy@localhost");
+    let message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}",
+        from,
+        settings.email.join(", "),
+        summary.title(),
+        summary.body().replace('\n', "\r\n")
+    );
+
+    let Some(ref server) = settings.smtp else {
+        // The To: header carries the recipients
+        return run_with_stdin(
+            Command::new("sendmail").args(["-t", "-f", from]),
+            message.as_bytes(),
+        );
+    };
+
+    if !server.starts_with("smtp://") && !server.starts_with("smtps://") {
+        bail!("smtp must be an smtp:// or smtps:// URL, got {}", server);
+    }
+    let mut curl = Command::new("curl");
+    curl.args([
+        "--silent",
+        "--show-error",
+        "--max-time",
+        "60",
+        "--url",
+        server,
+    ])
+    .args(["--mail-from", from]);
+    for rcpt in &settings.email {
+        curl.args(["--mail-rcpt", rcpt]);
+    }
+    if let Some(ref user) = settings.smtp_user {
+        let password = std::env::var("SY_SMTP_PASSWORD")
+            .context("smtp_user is set but SY_SMTP_PASSWORD is not")?;
+        // STARTTLS on plain smtp://, so credentials never go out in clear
+        curl.args(["--ssl-reqd", "--user", &format!("{}:{}", user, password)]);
+    }
+    curl.args(["--upload-file", "-"]);
+    run_with_stdin(&mut curl, message.as_bytes())
+}
+
+fn show_desktop(summary: &Summary) -> Result<()> {
+    let title = summary.title();
+    let body = summary.body();
+
+    if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {} with title {}",
+            applescript_string(&body),
+            applescript_string(&title)
+        );
+        run(Command::new("osascript").args(["-e", &script]))
+    } else {
+        let urgency = if summary.is_failure() {
+            "critical"
+        } else {
+            "normal"
+        };
+        run(Command::new("notify-send").args([
+            "--app-name",
+            "sy",
+            "--urgency",
+            urgency,
+            &title,
+            &body,
+        ]))
+    }
+}
+
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    run_with_stdin(command, &[])
+}
+
+fn run_with_stdin(command: &mut Command, input: &[u8]) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(failed: Vec<String>) -> Summary {
+        Summary {
+            profile: Some("nightly".to_string()),
+            source: "/data".to_string(),
+            destination: "backup:/data".to_string(),
+            status: if failed.is_empty() { "ok" } else { "partial" },
+            error: None,
+            files_scanned: 10,
+            files_created: 2,
+            files_updated: 1,
+            files_deleted: 0,
+            files_vanished: 0,
+            bytes_transferred: 4096,
+            duration_secs: 1.5,
+            failed,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let json = serde_json::to_value(summary(vec!["a.txt".to_string()])).unwrap();
+        assert_eq!(json["status"], "partial");
+        assert_eq!(json["profile"], "nightly");
+        assert_eq!(json["failed"][0], "a.txt");
+        assert!(json.get("error").is_none());
+
+        let json = serde_json::to_value(summary(Vec::new())).unwrap();
+        assert!(json.get("failed").is_none());
+    }
+
+    #[test]
+    fn test_summary_title() {
+        assert_eq!(summary(Vec::new()).title(), "sy nightly: finished");
+        assert_eq!(
+            summary(vec!["a".to_string(), "b".to_string()]).title(),
+            "sy nightly: 2 files failed"
+        );
+        let error = anyhow::anyhow!("connection refused");
+        let failed = Summary::from_error(None, "/a".into(), "/b".into(), &error, false);
+        assert_eq!(failed.title(), "sy /a → /b: failed");
+        assert!(failed.body().contains("connection refused"));
+    }
+
+    #[test]
+    fn test_failure_only_skips_successful_runs() {
+        // No channels would fire anyway; this must not spawn anything
+        let settings = NotifySettings {
+            on: NotifyOn::Failure,
+            desktop: true,
+            ..Default::default()
+        };
+        send(&settings, &summary(Vec::new()));
+    }
+
+    #[test]
+    fn test_applescript_string_escapes() {
+        assert_eq!(applescript_string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+    }
+}
@@ -1,7 +1,7 @@
 use std::path::{Path, PathBuf};
 
 /// Represents a sync path that can be either local, remote (SSH), or S3
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SyncPath {
     Local {
         path: PathBuf,
@@ -208,6 +208,31 @@ impl SyncPath {
         }
     }
 
+    /// Where this source lands under `destination`, by rsync trailing slash
+    /// semantics (applies to directories):
+    /// - Without a trailing slash (`/a/dir`): the directory itself → `dest/dir/`
+    /// - With a trailing slash (`/a/dir/`): its contents only → `dest/`
+    ///
+    /// For files, trailing slash semantics don't apply - the sync engine handles them
+    /// by using the destination path directly or appending the filename as needed.
+    ///
+    /// Works on the path strings without touching the filesystem, so it's
+    /// correct for local, remote (SSH), and S3 sources alike.
+    pub fn effective_destination(&self, destination: &SyncPath) -> PathBuf {
+        // With a trailing slash, use destination as-is (copy contents)
+        if self.has_trailing_slash() {
+            return destination.path().to_path_buf();
+        }
+
+        // Without one, append the source name (copies the directory/file itself)
+        if let Some(name) = self.path().file_name() {
+            destination.path().join(name)
+        } else {
+            // Fallback: use destination as-is (e.g., root paths)
+            destination.path().to_path_buf()
+        }
+    }
+
     /// Check if this is a remote SSH path
     #[allow(dead_code)] // Used in tests
     pub fn is_remote(&self) -> bool {
@@ -220,7 +245,6 @@ impl SyncPath {
     }
 
     /// Check if this is an S3 path
-    #[allow(dead_code)] // Public API for S3 path detection
     pub fn is_s3(&self) -> bool {
         matches!(self, SyncPath::S3 { .. })
     }
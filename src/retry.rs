@@ -51,7 +51,7 @@ impl RetryConfig {
     }
 
     /// Calculate delay for a given attempt number (0-indexed)
-    fn calculate_delay(&self, attempt: u32) -> Duration {
+    pub fn calculate_delay(&self, attempt: u32) -> Duration {
         let delay_secs =
             self.initial_delay.as_secs_f64() * self.backoff_multiplier.powi(attempt as i32);
         let delay = Duration::from_secs_f64(delay_secs);
@@ -101,6 +101,7 @@ where
                     delay
                 );
 
+                crate::metrics::global().record_retry();
                 tokio::time::sleep(delay).await;
                 attempt += 1;
             }
@@ -0,0 +1,276 @@
+//! `sy rsync ...`: accept an rsync command line
+//!
+//! Scripts and cron jobs that call rsync can switch to sy by putting
+//! `sy rsync` in place of `rsync`. The common rsync options are rewritten
+//! into their sy equivalents before the usual argument parsing; options that
+//! only change rsync's output (`--progress`, `--stats`, `-h`) are dropped, and
+//! anything sy can't honour is refused rather than silently ignored.
+
+use anyhow::{bail, Result};
+
+/// An rsync command line rewritten for sy
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Translation {
+    /// Arguments for sy, starting with the program name
+    pub args: Vec<String>,
+    /// rsync options that were accepted but have no effect
+    pub ignored: Vec<String>,
+}
+
+/// Short options that are the same in rsync and sy
+const SAME_SHORT: &str = "avqnzcuHAXptgoDLb";
+
+/// Long options that are the same in rsync and sy, with whether they take a
+/// value
+const SAME_LONG: &[(&str, bool)] = &[
+    ("archive", false),
+    ("verbose", false),
+    ("quiet", false),
+    ("dry-run", false),
+    ("compress", false),
+    ("checksum", false),
+    ("update", false),
+    ("copy-links", false),
+    ("delete", false),
+    ("delete-before", false),
+    ("delete-during", false),
+    ("delete-after", false),
+    ("partial", false),
+    ("size-only", false),
+    ("ignore-times", false),
+    ("ignore-existing", false),
+    ("backup", false),
+    ("backup-dir", true),
+    ("suffix", true),
+    ("exclude", true),
+    ("include", true),
+    ("filter", true),
+    ("exclude-from", true),
+    ("include-from", true),
+    ("min-size", true),
+    ("max-size", true),
+    ("usermap", true),
+    ("groupmap", true),
+];
+
+/// Long options with a different name in sy
+const RENAMED_LONG: &[(&str, &str)] = &[
+    ("hard-links", "--preserve-hardlinks"),
+    ("acls", "--preserve-acls"),
+    ("xattrs", "--preserve-xattrs"),
+    ("perms", "--preserve-permissions"),
+    ("times", "--preserve-times"),
+    ("atimes", "--times=all"),
+    ("crtimes", "--times=all"),
+    ("group", "--preserve-group"),
+    ("owner", "--preserve-owner"),
+    ("devices", "--preserve-devices"),
+    ("specials", "--preserve-devices"),
+    ("del", "--delete-during"),
+    ("delete-delay", "--delete-after"),
+    ("inplace", "--no-atomic"),
+    ("cvs-exclude", "--exclude-vcs"),
+];
+
+/// Long options sy already does by default, or that only change rsync's
+/// output
+const NO_OP_LONG: &[&str] = &[
+    "recursive",
+    "links",
+    "progress",
+    "stats",
+    "human-readable",
+    "whole-file",
+    "itemize-changes",
+];
+
+/// Rewrite the rsync arguments `args` (everything after `sy rsync`) as sy
+/// arguments
+pub fn translate(args: &[String]) -> Result<Translation> {
+    let mut out = Translation {
+        args: vec!["sy".to_string()],
+        ignored: Vec::new(),
+    };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            out.args
+                .extend(std::iter::once(arg).chain(args.by_ref()).cloned());
+            break;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            translate_long(name, value, &mut args, &mut out)?;
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            translate_short(&arg[1..], &mut args, &mut out)?;
+        } else {
+            out.args.push(arg.clone());
+        }
+    }
+
+    Ok(out)
+}
+
+fn translate_long<'a>(
+    name: &str,
+    inline: Option<String>,
+    rest: &mut impl Iterator<Item = &'a String>,
+    out: &mut Translation,
+) -> Result<()> {
+    // `--name=value` or `--name value`
+    let mut value = || match inline.clone().or_else(|| rest.next().cloned()) {
+        Some(value) => Ok(value),
+        None => bail!("rsync option --{} needs a value", name),
+    };
+
+    if let Some(&(_, takes_value)) = SAME_LONG.iter().find(|(n, _)| *n == name) {
+        out.args.push(format!("--{}", name));
+        if takes_value {
+            let value = value()?;
+            out.args.push(value);
+        }
+    } else if let Some((_, sy)) = RENAMED_LONG.iter().find(|(n, _)| *n == name) {
+        out.args.push(sy.to_string());
+    } else if NO_OP_LONG.contains(&name) || name.starts_with("info") {
+        out.ignored.push(format!("--{}", name));
+    } else {
+        match name {
+            "bwlimit" => {
+                let limit = value()?;
+                out.args.push("--bwlimit".to_string());
+                out.args.push(bwlimit(&limit));
+            }
+            // A count in rsync; sy's --max-delete is bisync's percentage
+            "max-delete" => {
+                let max = value()?;
+                out.args.push("--max-delete-count".to_string());
+                out.args.push(max);
+            }
+            "rsh" => rsh(&value()?, out)?,
+            _ => bail!(
+                "rsync option --{} is not supported by sy; run `sy --help` for sy's options",
+                name
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// A cluster of short options like `avz` or `avhe ssh`
+fn translate_short<'a>(
+    cluster: &str,
+    rest: &mut impl Iterator<Item = &'a String>,
+    out: &mut Translation,
+) -> Result<()> {
+    for (i, flag) in cluster.char_indices() {
+        // Options that take a value use the rest of the cluster, or the
+        // next argument
+        let attached = &cluster[i + flag.len_utf8()..];
+        let mut value = || match (attached, rest.next()) {
+            ("", Some(value)) => Ok(value.clone()),
+            ("", None) => bail!("rsync option -{} needs a value", flag),
+            (attached, _) => Ok(attached.to_string()),
+        };
+
+        match flag {
+            c if SAME_SHORT.contains(c) => out.args.push(format!("-{}", c)),
+            'P' => {
+                out.args.push("--partial".to_string());
+                out.ignored.push("--progress".to_string());
+            }
+            'r' | 'l' | 'h' | 'W' | 'i' => out.ignored.push(format!("-{}", flag)),
+            'C' => out.args.push("--exclude-vcs".to_string()),
+            'I' => out.args.push("--ignore-times".to_string()),
+            'U' | 'N' => out.args.push("--times=all".to_string()),
+            'e' => return rsh(&value()?, out),
+            'f' => {
+                let rule = value()?;
+                out.args.push("--filter".to_string());
+                out.args.push(rule);
+                return Ok(());
+            }
+            _ => bail!(
+                "rsync option -{} is not supported by sy; run `sy --help` for sy's options",
+                flag
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// sy makes its own SSH connections, reading ~/.ssh/config, so a plain
+/// `-e ssh` changes nothing but options for the ssh command can't be kept
+fn rsh(command: &str, out: &mut Translation) -> Result<()> {
+    if command.trim() != "ssh" {
+        bail!(
+            "rsync option -e '{}' is not supported by sy; set SSH options for the host in ~/.ssh/config",
+            command
+        );
+    }
+    out.ignored.push(format!("--rsh={}", command));
+    Ok(())
+}
+
+/// rsync reads a bare --bwlimit number as KiB per second; sy reads bytes
+fn bwlimit(limit: &str) -> String {
+    if limit.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        format!("{}K", limit)
+    } else {
+        limit.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_translate_common_command_line() {
+        let t = translate(&args(
+            "-avzPb --delete-after --backup-dir=old --exclude=*.tmp --exclude .cache -e ssh /src/ host:/dst",
+        ))
+        .unwrap();
+        assert_eq!(
+            t.args,
+            args("sy -a -v -z --partial -b --delete-after --backup-dir old --exclude *.tmp --exclude .cache /src/ host:/dst")
+        );
+        assert_eq!(t.ignored, ["--progress", "--rsh=ssh"]);
+    }
+
+    #[test]
+    fn test_translate_renamed_and_valued_options() {
+        let mut rsync = args(
+            "-rlptHU --hard-links --crtimes --bwlimit=500 --bwlimit 2M --inplace --max-delete=10 -f",
+        );
+        rsync.extend(["- *.o".to_string(), "a".to_string(), "b".to_string()]);
+        let t = translate(&rsync).unwrap();
+        let mut expected = args(
+            "sy -p -t -H --times=all --preserve-hardlinks --times=all --bwlimit 500K --bwlimit 2M \
+             --no-atomic --max-delete-count 10 --filter",
+        );
+        expected.extend(["- *.o".to_string(), "a".to_string(), "b".to_string()]);
+        assert_eq!(t.args, expected);
+        assert_eq!(t.ignored, ["-r", "-l"]);
+    }
+
+    #[test]
+    fn test_translate_refuses_unsupported_options() {
+        assert!(translate(&args("-av --one-file-system a b")).is_err());
+        assert!(translate(&args("-avR a b")).is_err());
+        assert!(translate(&args("a b --exclude")).is_err());
+        assert!(translate(&["-e".to_string(), "ssh -p 2222".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_translate_passes_paths_after_double_dash() {
+        let t = translate(&args("-a -- -weird-name dst")).unwrap();
+        assert_eq!(t.args, args("sy -a -- -weird-name dst"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Browse sessions: the client (`sy mount`) lists directories and reads byte
+//! ranges of files under the root, one request at a time, and nothing is
+//! written. The server's exclusions hide paths here as they do from pulls.
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::error::SyncError;
+use crate::filter::FilterEngine;
+use crate::streaming::protocol::{self as v2, DataFlags, FileFlags, MessageType};
+
+/// Answer LIST and READ requests until the client hangs up
+pub(super) async fn run_server_browse(
+    root: PathBuf,
+    exclude: FilterEngine,
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let root = Arc::new(root);
+    let exclude = Arc::new(exclude);
+    loop {
+        let (msg_type, payload) = match v2::read_frame(&mut reader).await {
+            Ok(frame) => frame,
+            Err(e) if is_hangup(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let reply = match msg_type {
+            MessageType::List => {
+                let req = v2::List::decode(payload)?;
+                let (root, exclude) = (Arc::clone(&root), Arc::clone(&exclude));
+                let path = req.path.clone();
+                let listing =
+                    tokio::task::spawn_blocking(move || list(&root, &exclude, &path)).await?;
+                match listing {
+                    Ok(frames) => frames,
+                    Err(e) => error_frame(&req.path, e),
+                }
+            }
+            MessageType::Read => {
+                let req = v2::Read::decode(payload)?;
+                let (root, exclude) = (Arc::clone(&root), Arc::clone(&exclude));
+                let (path, offset) = (req.path.clone(), req.offset);
+                let len = req.len.min(v2::Read::MAX_LEN);
+                let data =
+                    tokio::task::spawn_blocking(move || read(&root, &exclude, &path, offset, len))
+                        .await?;
+                match data {
+                    Ok(data) => v2::Data {
+                        path: req.path.into(),
+                        offset: req.offset,
+                        flags: DataFlags::FINAL,
+                        data,
+                    }
+                    .encode(),
+                    Err(e) => error_frame(&req.path, e),
+                }
+            }
+            MessageType::Done => return Ok(()),
+            _ => {
+                return Err(SyncError::Protocol(format!(
+                    "Unexpected message in browse session: {:?}",
+                    msg_type
+                ))
+                .into())
+            }
+        };
+
+        v2::write_frame(&mut writer, &reply).await?;
+        writer.flush().await?;
+    }
+}
+
+/// The client closed the connection between requests
+fn is_hangup(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof)
+}
+
+fn error_frame(path: &str, err: std::io::Error) -> Bytes {
+    let err = SyncError::Io(err);
+    v2::Error {
+        path: path.to_string(),
+        code: err.error_code() as u16,
+        message: err.to_string(),
+    }
+    .encode()
+}
+
+/// `rel` under `root`, refusing anything that would leave it: `..`,
+/// absolute paths, symlinks on the way, and paths the server excludes
+fn resolve(
+    root: &Path,
+    exclude: &FilterEngine,
+    rel: &str,
+    is_dir: bool,
+) -> std::io::Result<PathBuf> {
+    let rel = Path::new(rel);
+    let not_found =
+        || std::io::Error::new(std::io::ErrorKind::NotFound, "No such file or directory");
+    if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(not_found());
+    }
+    if exclude.excludes_tree(rel, is_dir) {
+        return Err(not_found());
+    }
+
+    let mut path = root.to_path_buf();
+    for component in rel.components() {
+        path.push(component);
+        if std::fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Symlinks are not followed",
+            ));
+        }
+    }
+    Ok(path)
+}
+
+/// FILE_ENTRY frames for the entries of directory `rel`, sorted by name,
+/// followed by FILE_END
+fn list(root: &Path, exclude: &FilterEngine, rel: &str) -> std::io::Result<Bytes> {
+    let dir = resolve(root, exclude, rel, true)?;
+    let mut names: Vec<String> = std::fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+
+    let mut frames = BytesMut::new();
+    let (mut files, mut bytes) = (0u64, 0u64);
+    for name in names {
+        let child = if rel.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", rel, name)
+        };
+        let path = dir.join(&name);
+        // Gone since read_dir: leave it out
+        let Ok(meta) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if exclude.should_exclude(Path::new(&child), meta.is_dir()) {
+            continue;
+        }
+
+        let mut flags = FileFlags::empty();
+        let symlink_target = if meta.file_type().is_symlink() {
+            flags |= FileFlags::SYMLINK;
+            std::fs::read_link(&path)
+                .ok()
+                .map(|t| t.to_string_lossy().into_owned())
+        } else {
+            None
+        };
+        if meta.is_dir() {
+            flags |= FileFlags::DIR;
+        } else {
+            files += 1;
+            bytes += meta.len();
+        }
+
+        let since_epoch = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        v2::FileEntry {
+            path: child.into(),
+            size: meta.len(),
+            mtime: since_epoch.as_secs() as i64,
+            mtime_nsec: since_epoch.subsec_nanos(),
+            mode: crate::fs_util::permission_bits(&meta),
+            inode: 0,
+            flags,
+            symlink_target,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        }
+        .encode_into(&mut frames);
+    }
+
+    v2::FileEnd {
+        total_files: files,
+        total_bytes: bytes,
+        skipped: 0,
+    }
+    .encode_into(&mut frames);
+    Ok(frames.freeze())
+}
+
+/// Up to `len` bytes of file `rel` from `offset`; fewer at the end of the file
+fn read(
+    root: &Path,
+    exclude: &FilterEngine,
+    rel: &str,
+    offset: u64,
+    len: u32,
+) -> std::io::Result<Bytes> {
+    let path = resolve(root, exclude, rel, false)?;
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(len as usize);
+    file.take(len as u64).read_to_end(&mut data)?;
+    Ok(data.into())
+}
@@ -0,0 +1,422 @@
+//! Daemon mode - runs when invoked as `sy --daemon`
+//!
+//! Listens on TCP instead of being started over SSH, and serves each
+//! connection as one protocol v2 session. Clients can't name arbitrary
+//! paths: the root in their HELLO is `module[/path]`, where the module is
+//! one of the `[daemon.modules]` in the config file and the path has to
+//! stay below it. Everything else about a session (I/O options, receive
+//! policy, exclusions, audit log) comes from the daemon's own settings.
+//!
+//! Modules are read-only unless configured otherwise, the daemon listens on
+//! loopback by default, and `hosts_allow` limits who may connect. Before
+//! its HELLO a connection gets little: a few seconds to send it, a small
+//! frame to send it in, and only `max_connections` are served at once.
+//! There's no authentication or encryption beyond that: expose it to
+//! trusted networks only, or put it behind a tunnel.
+
+use super::{expand_tilde, serve, ServerConfig};
+use crate::config::DaemonModule;
+use crate::error::SyncError;
+use crate::streaming::protocol::{self as v2, HelloFlags, MessageType};
+use anyhow::{Context as _, Result};
+use bytes::{BufMut, BytesMut};
+use ipnet::IpNet;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tracing::Instrument;
+
+/// Where `--daemon` listens without `--listen` or `[daemon] listen`
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:873";
+
+/// Sessions served at once without `[daemon] max_connections`
+pub const DEFAULT_MAX_CONNECTIONS: usize = 32;
+
+/// How long a new connection has to send its HELLO
+const HELLO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest HELLO accepted: a module path and a few fixed fields
+const MAX_HELLO_SIZE: u32 = 64 * 1024;
+
+/// Parse `[daemon] hosts_allow`: addresses (`192.168.1.5`) or networks
+/// (`10.0.0.0/8`)
+pub fn parse_hosts(entries: &[String]) -> Result<Vec<IpNet>> {
+    entries
+        .iter()
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .with_context(|| format!("Invalid hosts_allow entry: {}", entry))
+        })
+        .collect()
+}
+
+/// A TCP server for the modules it was given
+pub struct Daemon {
+    /// Module name -> settings, with `~` in the directory expanded
+    modules: BTreeMap<String, DaemonModule>,
+    /// Peers allowed to connect; empty allows any
+    hosts_allow: Vec<IpNet>,
+    /// One permit per session in flight
+    connections: Arc<Semaphore>,
+    max_connections: usize,
+    /// What every session starts from; its root is replaced by the
+    /// client's module, and cancelling it shuts the daemon down
+    session: ServerConfig,
+}
+
+impl Daemon {
+    pub fn new(modules: BTreeMap<String, DaemonModule>, session: ServerConfig) -> Self {
+        let modules = modules
+            .into_iter()
+            .map(|(name, module)| {
+                let path = expand_tilde(&module.path);
+                (name, DaemonModule { path, ..module })
+            })
+            .collect();
+        Self {
+            modules,
+            hosts_allow: Vec::new(),
+            connections: Arc::new(Semaphore::new(DEFAULT_MAX_CONNECTIONS)),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            session,
+        }
+    }
+
+    /// Only accept connections from `hosts` (all if empty)
+    pub fn with_hosts_allow(mut self, hosts: Vec<IpNet>) -> Self {
+        self.hosts_allow = hosts;
+        self
+    }
+
+    /// Serve at most `max` sessions at once, turning away the rest
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.connections = Arc::new(Semaphore::new(max));
+        self.max_connections = max;
+        self
+    }
+
+    fn host_allowed(&self, host: IpAddr) -> bool {
+        // A dual-stack listener sees IPv4 peers as ::ffff:a.b.c.d
+        let host = host.to_canonical();
+        self.hosts_allow.is_empty() || self.hosts_allow.iter().any(|net| net.contains(&host))
+    }
+
+    /// Session settings for the root a client asked for, refusing anything
+    /// outside the modules and pushes to read-only ones
+    fn resolve(&self, requested: &str, push: bool) -> Result<ServerConfig, SyncError> {
+        let refused = || SyncError::NotAModule {
+            path: PathBuf::from(requested),
+        };
+        let requested_path = requested.trim_start_matches('/');
+        let (name, rest) = requested_path
+            .split_once('/')
+            .unwrap_or((requested_path, ""));
+        let module = self.modules.get(name).ok_or_else(refused)?;
+        let rest = Path::new(rest);
+        if !rest.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(refused());
+        }
+        if push && module.read_only {
+            return Err(SyncError::ReadOnlyModule {
+                module: name.to_string(),
+            });
+        }
+
+        let mut config = self.session.clone();
+        config.root = module.path.join(rest);
+        // Also catches symlinks inside the module that lead out of it
+        config.allowed_roots = Some(vec![module.path.clone()]);
+        config.cancel = self.session.cancel.child_token();
+        Ok(config)
+    }
+
+    /// Serve connections from `listener` until the session template's
+    /// cancel token fires, then wait for sessions in flight to stop
+    pub async fn run(self, listener: TcpListener) -> Result<()> {
+        let daemon = Arc::new(self);
+        let cancel = daemon.session.cancel.clone();
+        let mut sessions = JoinSet::new();
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Out of descriptors and the like: the listener survives
+                        tracing::warn!("Failed to accept connection: {}", e);
+                        continue;
+                    }
+                },
+                _ = cancel.cancelled() => break,
+            };
+            let span = tracing::info_span!("session", %peer);
+            let permit = Arc::clone(&daemon.connections).try_acquire_owned().ok();
+            sessions.spawn(
+                Arc::clone(&daemon)
+                    .handle(stream, peer, permit)
+                    .instrument(span),
+            );
+            // Reap finished sessions as we go
+            while sessions.try_join_next().is_some() {}
+        }
+        while sessions.join_next().await.is_some() {}
+        Ok(())
+    }
+
+    /// Serve one connection: read the HELLO to find the module, then hand
+    /// the connection (HELLO included) to [`serve`]
+    ///
+    /// `permit` is the connection's place among `max_connections`; without
+    /// one it's turned away.
+    async fn handle(
+        self: Arc<Self>,
+        stream: TcpStream,
+        peer: SocketAddr,
+        permit: Option<OwnedSemaphorePermit>,
+    ) {
+        let (mut reader, mut writer) = stream.into_split();
+        if !self.host_allowed(peer.ip()) {
+            let err = SyncError::HostNotAllowed { host: peer.ip() };
+            tracing::warn!("Refusing connection: {}", err);
+            refuse(&mut writer, err).await;
+            return;
+        }
+        let Some(_permit) = permit else {
+            let err = SyncError::TooManyConnections {
+                max: self.max_connections,
+            };
+            tracing::warn!("Refusing connection: {}", err);
+            refuse(&mut writer, err).await;
+            return;
+        };
+        let read = v2::read_frame_within(&mut reader, MAX_HELLO_SIZE);
+        let hello = match tokio::time::timeout(HELLO_TIMEOUT, read).await {
+            Ok(Ok((MessageType::Hello, payload))) => payload,
+            Ok(Ok((msg_type, _))) => {
+                tracing::warn!("Expected HELLO, got {:?}", msg_type);
+                return;
+            }
+            Ok(Err(e)) => {
+                tracing::debug!("Connection closed before HELLO: {:#}", e);
+                return;
+            }
+            Err(_) => {
+                tracing::debug!("No HELLO within {:?}", HELLO_TIMEOUT);
+                return;
+            }
+        };
+        let (requested, push) = match v2::Hello::decode(hello.clone()) {
+            Ok(decoded) => (
+                decoded.root_path,
+                !decoded
+                    .flags
+                    .intersects(HelloFlags::PULL | HelloFlags::BROWSE),
+            ),
+            Err(e) => {
+                tracing::warn!("Malformed HELLO: {:#}", e);
+                return;
+            }
+        };
+
+        let mut config = match self.resolve(&requested, push) {
+            Ok(config) => config,
+            Err(err) => {
+                tracing::warn!("Refusing {}: {}", requested, err);
+                refuse(&mut writer, err).await;
+                return;
+            }
+        };
+        if let Some(audit) = &config.audit {
+            config.audit = Some(audit.with_peer(peer.to_string()));
+        }
+        tracing::info!("Serving {}", config.root.display());
+
+        // serve() starts from the HELLO, so put it back in front
+        let mut frame = BytesMut::with_capacity(5 + hello.len());
+        frame.put_u32(hello.len() as u32);
+        frame.put_u8(MessageType::Hello as u8);
+        frame.put_slice(&hello);
+        let reader = std::io::Cursor::new(frame.freeze()).chain(reader);
+        if let Err(e) = serve(reader, writer, config).await {
+            tracing::warn!("Session failed: {}", e);
+        }
+    }
+}
+
+/// Tell the client why it's being turned away
+async fn refuse(writer: &mut (impl AsyncWriteExt + Unpin), err: SyncError) {
+    let fatal = v2::Fatal {
+        code: err.error_code() as u16,
+        message: err.to_string(),
+    };
+    // Best effort: the client may already be gone, or not reading
+    let _ = tokio::time::timeout(HELLO_TIMEOUT, async {
+        if v2::write_frame(writer, &fatal.encode()).await.is_ok() {
+            let _ = writer.flush().await;
+        }
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::StreamingSync;
+    use tempfile::TempDir;
+
+    fn daemon(module: &Path) -> Daemon {
+        let modules = BTreeMap::from([
+            (
+                "backup".to_string(),
+                DaemonModule {
+                    path: module.to_path_buf(),
+                    read_only: false,
+                },
+            ),
+            (
+                "archive".to_string(),
+                DaemonModule {
+                    path: module.to_path_buf(),
+                    read_only: true,
+                },
+            ),
+        ]);
+        Daemon::new(modules, ServerConfig::new("."))
+    }
+
+    #[test]
+    fn test_resolve_stays_inside_modules() {
+        let module = TempDir::new().unwrap();
+        let daemon = daemon(module.path());
+
+        assert_eq!(daemon.resolve("backup", true).unwrap().root, module.path());
+        assert_eq!(
+            daemon.resolve("/backup/hosts/web1", true).unwrap().root,
+            module.path().join("hosts/web1")
+        );
+        for refused in ["other", "", "backup/../etc", "backup//abs/./x"] {
+            assert!(daemon.resolve(refused, false).is_err(), "{}", refused);
+        }
+
+        // Read-only modules can be pulled from but not pushed to
+        assert!(daemon.resolve("archive/web1", false).is_ok());
+        assert!(matches!(
+            daemon.resolve("archive/web1", true),
+            Err(SyncError::ReadOnlyModule { .. })
+        ));
+    }
+
+    #[test]
+    fn test_hosts_allow() {
+        let module = TempDir::new().unwrap();
+        let anyone = daemon(module.path());
+        assert!(anyone.host_allowed("203.0.113.9".parse().unwrap()));
+
+        let hosts = parse_hosts(&["10.0.0.0/8".to_string(), "::1".to_string()]).unwrap();
+        let daemon = daemon(module.path()).with_hosts_allow(hosts);
+        assert!(daemon.host_allowed("10.1.2.3".parse().unwrap()));
+        assert!(daemon.host_allowed("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(daemon.host_allowed("::1".parse().unwrap()));
+        assert!(!daemon.host_allowed("127.0.0.1".parse().unwrap()));
+        assert!(!daemon.host_allowed("192.168.0.1".parse().unwrap()));
+
+        assert!(parse_hosts(&["10.0.0.0/33".to_string()]).is_err());
+        assert!(parse_hosts(&["web1".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_daemon_serves_modules_over_tcp() {
+        let local = TempDir::new().unwrap();
+        let module = TempDir::new().unwrap();
+        std::fs::write(local.path().join("a.txt"), "pushed").unwrap();
+
+        let daemon = daemon(module.path());
+        let cancel = daemon.session.cancel.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let daemon_task = tokio::spawn(daemon.run(listener));
+
+        let push = |remote: &str| {
+            let sync = StreamingSync::new(
+                local.path().to_path_buf(),
+                PathBuf::from(remote),
+                false,
+                false,
+            );
+            async move {
+                let stream = TcpStream::connect(addr).await.unwrap();
+                let (mut reader, mut writer) = stream.into_split();
+                sync.push(&mut reader, &mut writer).await
+            }
+        };
+
+        let stats = push("backup/web1").await.unwrap();
+        assert_eq!(stats.files_ok, 1);
+        assert_eq!(
+            std::fs::read_to_string(module.path().join("web1/a.txt")).unwrap(),
+            "pushed"
+        );
+
+        // Nothing outside the modules is reachable
+        let err = push("backup/../escape").await.unwrap_err();
+        assert!(err.to_string().contains("Not in a module"), "{}", err);
+        assert!(!module.path().parent().unwrap().join("escape").exists());
+        assert!(push("/tmp").await.is_err());
+        let err = push("archive/web1").await.unwrap_err();
+        assert!(err.to_string().contains("read-only"), "{}", err);
+
+        cancel.cancel();
+        daemon_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_daemon_limits_connections_and_hello_size() {
+        let local = TempDir::new().unwrap();
+        let module = TempDir::new().unwrap();
+        std::fs::write(local.path().join("a.txt"), "pushed").unwrap();
+
+        let daemon = daemon(module.path()).with_max_connections(1);
+        let cancel = daemon.session.cancel.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let daemon_task = tokio::spawn(daemon.run(listener));
+
+        // An idle connection takes the only place...
+        let mut idle = TcpStream::connect(addr).await.unwrap();
+        let mut turned_away = TcpStream::connect(addr).await.unwrap();
+        let (msg_type, payload) = v2::read_frame(&mut turned_away).await.unwrap();
+        assert_eq!(msg_type, MessageType::Fatal);
+        let fatal = v2::Fatal::decode(payload).unwrap();
+        assert!(
+            fatal.message.contains("Too many connections"),
+            "{}",
+            fatal.message
+        );
+
+        // ...until it announces a HELLO larger than any real one
+        idle.write_u32(MAX_HELLO_SIZE + 1).await.unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(idle.read(&mut buf).await.unwrap(), 0);
+
+        let sync = StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("backup"),
+            false,
+            false,
+        );
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut reader, mut writer) = stream.into_split();
+        let stats = sync.push(&mut reader, &mut writer).await.unwrap();
+        assert_eq!(stats.files_ok, 1);
+
+        cancel.cancel();
+        daemon_task.await.unwrap().unwrap();
+    }
+}
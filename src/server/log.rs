@@ -0,0 +1,210 @@
+//! Forwarding server-side diagnostics to the client
+//!
+//! Under `--server`, stdout carries the protocol and anything written to
+//! stderr comes out wherever ssh puts it, interleaved with the client's own
+//! output. [`LogQueue`] collects the session's warnings and errors instead;
+//! the session sends them as LOG frames at points where the client is
+//! reading, and the client logs them alongside its own. Clients that don't
+//! set `HelloFlags::LOG` get them on stderr.
+
+use crate::streaming::protocol::Log;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// Messages kept while nothing drains them; later ones are only counted
+const MAX_QUEUED: usize = 1000;
+
+/// Where a session's diagnostics go
+#[derive(Debug, Clone, Default)]
+pub struct LogQueue {
+    inner: Arc<Mutex<Queue>>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    mode: Mode,
+    logs: VecDeque<Log>,
+    dropped: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Until the client's HELLO says whether it shows LOG frames
+    #[default]
+    Pending,
+    Forward,
+    Stderr,
+}
+
+impl LogQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The layer feeding this queue from `tracing`
+    pub fn layer(&self) -> ForwardLayer {
+        ForwardLayer {
+            queue: self.clone(),
+        }
+    }
+
+    /// The client shows LOG frames: keep messages for [`Self::drain`]
+    pub fn forward(&self) {
+        self.lock().mode = Mode::Forward;
+    }
+
+    /// The client predates LOG frames: write what's queued, and everything
+    /// after it, to stderr
+    pub fn to_stderr(&self) {
+        let mut queue = self.lock();
+        queue.mode = Mode::Stderr;
+        for log in queue.logs.drain(..) {
+            print_log(&log);
+        }
+    }
+
+    /// Take what's queued for the client, as LOG frames to send
+    ///
+    /// Empty unless the client asked for them; messages queued before its
+    /// HELLO go to stderr if the session ends before one arrives.
+    pub fn drain(&self) -> Vec<Log> {
+        let mut queue = self.lock();
+        match queue.mode {
+            Mode::Forward => {}
+            Mode::Pending => {
+                drop(queue);
+                self.to_stderr();
+                return Vec::new();
+            }
+            Mode::Stderr => return Vec::new(),
+        }
+        let mut logs: Vec<_> = queue.logs.drain(..).collect();
+        if queue.dropped > 0 {
+            logs.push(Log {
+                level: Log::WARN,
+                message: format!("{} more server messages dropped", queue.dropped),
+            });
+            queue.dropped = 0;
+        }
+        logs
+    }
+
+    fn push(&self, log: Log) {
+        let mut queue = self.lock();
+        if queue.mode == Mode::Stderr {
+            print_log(&log);
+        } else if queue.logs.len() < MAX_QUEUED {
+            queue.logs.push_back(log);
+        } else {
+            queue.dropped += 1;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        // A panic while holding the lock leaves nothing half-updated
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn print_log(log: &Log) {
+    let level = match log.level {
+        Log::ERROR => "ERROR",
+        Log::WARN => "WARN",
+        _ => "INFO",
+    };
+    eprintln!("sy server: {}: {}", level, log.message);
+}
+
+/// Queues WARN and ERROR events for the client
+pub struct ForwardLayer {
+    queue: LogQueue,
+}
+
+impl<S: Subscriber> Layer<S> for ForwardLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let level = match *event.metadata().level() {
+            Level::ERROR => Log::ERROR,
+            Level::WARN => Log::WARN,
+            _ => return,
+        };
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+        self.queue.push(Log {
+            level,
+            message: message.0,
+        });
+    }
+}
+
+/// Renders an event as its message followed by its other fields
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        use std::fmt::Write;
+        let sep = if self.0.is_empty() { "" } else { " " };
+        if field.name() == "message" {
+            let _ = write!(self.0, "{}{:?}", sep, value);
+        } else {
+            let _ = write!(self.0, "{}{}={:?}", sep, field.name(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_queue_forwards_warnings_once_the_client_asks() {
+        let queue = LogQueue::new();
+        let subscriber = tracing_subscriber::registry().with(queue.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(path = "a.txt", "Failed to set permissions");
+            tracing::info!("Not forwarded");
+            queue.forward();
+            tracing::error!("Disk full");
+        });
+
+        let logs = queue.drain();
+        let logs: Vec<_> = logs.iter().map(|l| (l.level, l.message.as_str())).collect();
+        assert_eq!(
+            logs,
+            [
+                (Log::WARN, "Failed to set permissions path=\"a.txt\""),
+                (Log::ERROR, "Disk full"),
+            ]
+        );
+        assert!(queue.drain().is_empty());
+    }
+
+    #[test]
+    fn test_queue_counts_what_it_drops() {
+        let queue = LogQueue::new();
+        queue.forward();
+        for i in 0..MAX_QUEUED + 5 {
+            queue.push(Log {
+                level: Log::WARN,
+                message: i.to_string(),
+            });
+        }
+        let logs = queue.drain();
+        assert_eq!(logs.len(), MAX_QUEUED + 1);
+        assert_eq!(logs[MAX_QUEUED].message, "5 more server messages dropped");
+
+        // Without a client that shows them, nothing is held for it
+        let queue = LogQueue::new();
+        queue.push(Log {
+            level: Log::WARN,
+            message: "early".to_string(),
+        });
+        assert!(queue.drain().is_empty());
+        assert!(queue.lock().logs.is_empty());
+    }
+}
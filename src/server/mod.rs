@@ -1,20 +1,49 @@
 //! Server mode - runs when invoked as `sy --server <path>`
 //!
-//! Uses streaming protocol (v2) for all operations.
+//! Uses streaming protocol (v2) for all operations. [`serve`] speaks it over
+//! any reader/writer pair, so applications can host the protocol inside
+//! their own listeners (custom RPC, websockets, test harnesses);
+//! [`run_server`] is the `--server` entry point on stdin/stdout, and
+//! [`daemon`] serves sessions over TCP (`--daemon`).
 //!
 //! Code appears "dead" to the compiler since it's only used at runtime.
 #![allow(dead_code)]
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bytes::Bytes;
 use std::path::{Path, PathBuf};
-use tokio::fs;
-use tokio::io::{self, AsyncWriteExt};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
+mod browse;
+pub mod daemon;
+pub mod log;
+mod sandbox;
+
+pub use log::LogQueue;
+
+use crate::delta::StrongHash;
+use crate::error::SyncError;
+use crate::filter::FilterEngine;
 use crate::streaming::{
-    channel::file_job_channel,
+    audit::{self, AuditLog},
+    backup::Backup,
+    channel::{
+        file_job_channel, FileCounts, PhaseClock, PhaseTimes, ProgressCounter, WouldBytes,
+        PROGRESS_INTERVAL,
+    },
+    io::IoOptions,
+    owner::OwnerMap,
+    policy::ReceivePolicy,
     protocol::{self as v2, HelloFlags, MessageType},
+    transfer_log::TransferLog,
+    tuning::AutoTune,
     Generator, GeneratorConfig, Receiver, ReceiverConfig, Sender, SenderConfig,
 };
 
@@ -35,120 +64,607 @@ fn expand_tilde(path: &Path) -> PathBuf {
     }
 }
 
+/// Roots a server may use, separated like PATH; overrides the config file
+pub const ALLOWED_ROOTS_ENV: &str = "SY_SERVER_ROOTS";
+
+/// Settings for one server session
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Directory the client syncs to or from; created if missing
+    pub root: PathBuf,
+    /// How files are read and written
+    pub io: IoOptions,
+    /// Directories `root` has to be inside; None allows any
+    pub allowed_roots: Option<Vec<PathBuf>>,
+    /// Records what pushes delete, overwrite or chmod
+    pub audit: Option<AuditLog>,
+    /// Records each file pushes send here and pulls send from here
+    pub transfer_log: Option<TransferLog>,
+    /// What pushes may not write, whatever the client sends
+    pub policy: ReceivePolicy,
+    /// Where pushes move the old versions of files (`--backup`)
+    pub backup: Option<Backup>,
+    /// What pulls and browse sessions never serve, whatever the client asks
+    /// for; the root's `.syignore` is added when the session starts
+    pub exclude: FilterEngine,
+    /// Diagnostics to forward to clients that show LOG frames
+    pub logs: Option<LogQueue>,
+    /// Cancelling stops the session and tells the client with a FATAL
+    pub cancel: CancellationToken,
+    /// Confine the session's thread once the root exists (`run_server` only:
+    /// it gives the session a thread of its own)
+    sandboxed: bool,
+}
+
+impl ServerConfig {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            io: IoOptions::default(),
+            allowed_roots: None,
+            audit: None,
+            transfer_log: None,
+            policy: ReceivePolicy::default(),
+            backup: None,
+            exclude: FilterEngine::new(),
+            logs: None,
+            cancel: CancellationToken::new(),
+            sandboxed: false,
+        }
+    }
+
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    /// Refuse sessions whose root isn't inside one of `roots`
+    pub fn with_allowed_roots(mut self, roots: Vec<PathBuf>) -> Self {
+        self.allowed_roots = Some(roots);
+        self
+    }
+
+    /// Refuse entries `policy` denies when receiving pushes
+    pub fn with_policy(mut self, policy: ReceivePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Move the old versions of files pushes replace or delete as `backup`
+    /// says
+    pub fn with_backup(mut self, backup: Option<Backup>) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    /// Leave paths `exclude` matches (and everything below them) out of
+    /// pulls; the client neither receives nor deletes them
+    pub fn with_exclude(mut self, exclude: FilterEngine) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    /// Record destructive changes made by pushes in `audit`
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Record each file received by pushes or sent by pulls in `log`
+    pub fn with_transfer_log(mut self, log: TransferLog) -> Self {
+        self.transfer_log = Some(log);
+        self
+    }
+
+    /// Send what `logs` collects (see [`LogQueue::layer`]) to the client
+    pub fn with_logs(mut self, logs: LogQueue) -> Self {
+        self.logs = Some(logs);
+        self
+    }
+
+    /// Stop the session when `cancel` is cancelled: files in flight are
+    /// given up on as in an interrupted client, and the client gets a FATAL
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+}
+
+/// The allowlist for this server: [`ALLOWED_ROOTS_ENV`] if set (an empty
+/// value allows nothing), else `configured` from `[server] allowed_roots`
+pub fn allowed_roots(configured: Option<&[PathBuf]>) -> Option<Vec<PathBuf>> {
+    let roots = match std::env::var_os(ALLOWED_ROOTS_ENV) {
+        Some(list) => std::env::split_paths(&list)
+            .filter(|p| !p.as_os_str().is_empty())
+            .collect(),
+        None => configured?.to_vec(),
+    };
+    Some(roots.iter().map(|p| expand_tilde(p)).collect())
+}
+
+/// Whether `root` lies inside one of `allowed`
+///
+/// Both sides are resolved first (symlinks and `..` included), so a client
+/// can't climb out of an allowed directory through either.
+fn root_allowed(root: &Path, allowed: &[PathBuf]) -> bool {
+    let Some(root) = resolve_path(root) else {
+        return false;
+    };
+    allowed
+        .iter()
+        .filter_map(|dir| resolve_path(dir))
+        .any(|dir| root.starts_with(dir))
+}
+
+/// `path` made absolute with symlinks resolved as far as it exists; None if
+/// the part that doesn't exist yet contains `..`
+fn resolve_path(path: &Path) -> Option<PathBuf> {
+    let path = std::path::absolute(path).ok()?;
+    let mut existing = path.as_path();
+    let mut missing = Vec::new();
+    let mut resolved = loop {
+        match std::fs::canonicalize(existing) {
+            Ok(resolved) => break resolved,
+            Err(_) => {
+                missing.push(existing.file_name()?);
+                existing = existing.parent()?;
+            }
+        }
+    };
+    for name in missing.into_iter().rev() {
+        resolved.push(name);
+    }
+    Some(resolved)
+}
+
 /// Main server entry point
-pub async fn run_server() -> Result<()> {
+///
+/// `io` comes from flags on the server command line (`--io-uring`,
+/// `--drop-cache`, ...), which the client passes through from its own.
+/// `allowed_roots` is the server-side allowlist (see [`allowed_roots`]),
+/// `audit` the server's audit log, recording the SSH peer, `transfer_log`
+/// its log of files sent and received, `policy` what pushes may not write
+/// and `exclude` what pulls never serve; the client can't change any of
+/// them. `backup` comes from the command line, like `io`.
+pub async fn run_server(
+    io: IoOptions,
+    allowed_roots: Option<Vec<PathBuf>>,
+    audit: Option<AuditLog>,
+    transfer_log: Option<TransferLog>,
+    policy: ReceivePolicy,
+    exclude: FilterEngine,
+    backup: Option<Backup>,
+) -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
     let raw_path = args
         .last()
         .map(PathBuf::from)
         .unwrap_or_else(|| PathBuf::from("."));
 
-    let root_path = expand_tilde(&raw_path);
+    // Nothing else sets up logging in server mode
+    let logs = LogQueue::new();
+    let _ = tracing_subscriber::registry().with(logs.layer()).try_init();
+
+    let mut config = ServerConfig::new(expand_tilde(&raw_path))
+        .with_io(io)
+        .with_policy(policy)
+        .with_backup(backup)
+        .with_exclude(exclude)
+        .with_logs(logs)
+        .with_cancel(crate::interrupt::install());
+    if let Some(roots) = allowed_roots {
+        config = config.with_allowed_roots(roots);
+    }
+    if let Some(audit) = audit {
+        config = config.with_audit(audit.with_peer(audit::server_peer()));
+    }
+    if let Some(log) = transfer_log {
+        config = config.with_transfer_log(log);
+    }
+    if !io.sandbox {
+        return Ok(serve(io::stdin(), io::stdout(), config).await?);
+    }
+
+    // Landlock confines the thread that enters it and the threads that
+    // thread starts, so the session gets a thread and runtime of its own
+    config.sandboxed = true;
+    let session = std::thread::spawn(move || -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        Ok(runtime.block_on(serve(io::stdin(), io::stdout(), config))?)
+    });
+    tokio::task::spawn_blocking(move || session.join())
+        .await?
+        .map_err(|_| anyhow::anyhow!("Sandboxed server session panicked"))?
+}
+
+/// Serve one client session over `reader`/`writer`
+///
+/// The client decides the direction in its HELLO; `config.root` is the
+/// destination of a push and the source of a pull or browse session,
+/// whatever path the client names. A session that fails is reported to the client as a
+/// FATAL frame carrying the error's `ErrorCode`.
+pub async fn serve<R, W>(
+    mut reader: R,
+    mut writer: W,
+    config: ServerConfig,
+) -> crate::error::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let logs = config.logs.clone();
+    let err = match run_session(&mut reader, &mut writer, config).await {
+        Ok(()) => return Ok(()),
+        Err(e) => SyncError::from(e),
+    };
+
+    // Best effort: the client may already be gone
+    let _ = send_logs(logs.as_ref(), &mut writer).await;
+    let fatal = v2::Fatal {
+        code: err.error_code() as u16,
+        message: err.to_string(),
+    };
+    if v2::write_frame(&mut writer, &fatal.encode()).await.is_ok() {
+        let _ = writer.flush().await;
+    }
+    Err(err)
+}
+
+#[tracing::instrument(name = "serve", skip_all, fields(root = %config.root.display()))]
+async fn run_session<R, W>(mut reader: R, mut writer: W, config: ServerConfig) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let ServerConfig {
+        root: root_path,
+        io,
+        allowed_roots,
+        audit,
+        transfer_log,
+        policy,
+        backup,
+        mut exclude,
+        logs,
+        cancel,
+        sandboxed,
+    } = config;
 
+    if let Some(allowed) = &allowed_roots {
+        if !root_allowed(&root_path, allowed) {
+            tracing::warn!(
+                "Refusing root outside the allowlist: {}",
+                root_path.display()
+            );
+            return Err(SyncError::RootNotAllowed { path: root_path }.into());
+        }
+    }
+
+    // Not through the blocking pool: a thread started before the sandbox is
+    // entered would stay outside it
     if !root_path.exists() {
         std::fs::create_dir_all(&root_path)?;
     }
-
-    let mut stdin = io::stdin();
-    let mut stdout = io::stdout();
+    if sandboxed {
+        sandbox::enter(&root_path)?;
+    }
 
     // Read Hello frame
-    let (msg_type, payload) = v2::read_frame(&mut stdin).await?;
+    let (msg_type, payload) = v2::read_frame(&mut reader).await?;
 
     if msg_type != MessageType::Hello {
-        let fatal = v2::Fatal {
-            code: 1,
-            message: format!("Expected HELLO, got {:?}", msg_type),
-        };
-        v2::write_frame(&mut stdout, &fatal.encode()).await?;
-        stdout.flush().await?;
-        return Ok(());
+        return Err(SyncError::Protocol(format!("Expected HELLO, got {:?}", msg_type)).into());
     }
 
     let hello = v2::Hello::decode(payload)?;
-
-    // Ensure root exists
-    if !root_path.exists() {
-        fs::create_dir_all(&root_path).await?;
+    if let Some(logs) = &logs {
+        if hello.flags.contains(HelloFlags::LOG) {
+            logs.forward();
+        } else {
+            logs.to_stderr();
+        }
     }
 
-    // Send Hello response
-    let resp = v2::Hello::new(HelloFlags::empty(), "");
-    v2::write_frame(&mut stdout, &resp.encode()).await?;
-    stdout.flush().await?;
+    // Send Hello response, confirming what we support of what was asked
+    let mut supported = HelloFlags::COMPRESSION
+        | HelloFlags::RESUME
+        | HelloFlags::FILTER
+        | HelloFlags::OWNER
+        | HelloFlags::GROUP
+        | HelloFlags::XATTRS
+        | HelloFlags::HARDLINKS
+        | HelloFlags::TIMES
+        | HelloFlags::VERIFY
+        | HelloFlags::STRONG_128
+        | HelloFlags::DRY_RUN
+        | HelloFlags::PROGRESS
+        | HelloFlags::ATOMIC
+        | HelloFlags::CHANNELS
+        | HelloFlags::KEEPALIVE;
+    // Without exacl we can neither read nor apply ACLs
+    supported.set(HelloFlags::ACLS, cfg!(all(unix, feature = "acl")));
+    let resp = v2::Hello::new(hello.flags & supported, "");
+    v2::write_frame(&mut writer, &resp.encode()).await?;
+    writer.flush().await?;
+    let filter = if hello.flags.contains(HelloFlags::FILTER) {
+        read_filter(&mut reader).await?
+    } else {
+        FilterEngine::new()
+    };
 
-    if hello.flags.contains(HelloFlags::PULL) {
-        run_server_pull(hello, root_path, stdin, stdout).await
+    let io = IoOptions {
+        perms: hello.flags.contains(HelloFlags::PERMS),
+        // A staged transfer is written from scratch
+        resume: hello.flags.contains(HelloFlags::RESUME)
+            && !hello.flags.contains(HelloFlags::ATOMIC),
+        owner: hello.flags.contains(HelloFlags::OWNER),
+        group: hello.flags.contains(HelloFlags::GROUP),
+        xattrs: hello.flags.contains(HelloFlags::XATTRS),
+        acls: (hello.flags & supported).contains(HelloFlags::ACLS),
+        hardlinks: hello.flags.contains(HelloFlags::HARDLINKS),
+        all_times: hello.flags.contains(HelloFlags::TIMES),
+        strong_hash: if hello.flags.contains(HelloFlags::STRONG_128) {
+            StrongHash::Xxh3_128
+        } else {
+            StrongHash::Xxh3_64
+        },
+        checksum_verify: hello.flags.contains(HelloFlags::VERIFY),
+        dry_run: hello.flags.contains(HelloFlags::DRY_RUN),
+        atomic: hello.flags.contains(HelloFlags::ATOMIC),
+        ..io
+    };
+    if hello
+        .flags
+        .intersects(HelloFlags::PULL | HelloFlags::BROWSE)
+    {
+        exclude.add_syignore_if_exists(&root_path)?;
+    }
+    if hello.flags.contains(HelloFlags::BROWSE) {
+        browse::run_server_browse(root_path, exclude, reader, writer).await
+    } else if hello.flags.contains(HelloFlags::PULL) {
+        run_server_pull(
+            hello,
+            root_path,
+            io,
+            exclude,
+            filter,
+            transfer_log,
+            logs,
+            cancel,
+            reader,
+            writer,
+        )
+        .await
     } else {
-        run_server_push(hello, root_path, stdin, stdout).await
+        run_server_push(
+            hello,
+            root_path,
+            io,
+            audit,
+            transfer_log,
+            policy,
+            backup,
+            filter,
+            logs,
+            cancel,
+            reader,
+            writer,
+        )
+        .await
+    }
+}
+
+/// The client's include/exclude rules, sent right after our HELLO
+async fn read_filter(reader: &mut (impl AsyncRead + Unpin)) -> Result<FilterEngine> {
+    let (msg_type, payload) = v2::read_frame(reader).await?;
+    if msg_type != MessageType::Filter {
+        return Err(SyncError::Protocol(format!("Expected FILTER, got {:?}", msg_type)).into());
+    }
+    let mut filter = FilterEngine::new();
+    for rule in v2::Filter::decode(payload)?.rules {
+        filter
+            .add_rule(&rule)
+            .with_context(|| format!("Invalid filter rule from client: {}", rule))?;
+    }
+    Ok(filter)
+}
+
+/// Send the diagnostics queued in `logs` as LOG frames
+async fn send_logs(logs: Option<&LogQueue>, out: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    for log in logs.map(LogQueue::drain).unwrap_or_default() {
+        v2::write_frame(out, &log.encode()).await?;
+    }
+    Ok(())
+}
+
+/// When the first PROGRESS goes out, if the client asked for them
+fn first_progress(hello: &v2::Hello) -> Option<Instant> {
+    hello
+        .flags
+        .contains(HelloFlags::PROGRESS)
+        .then(|| Instant::now() + PROGRESS_INTERVAL)
+}
+
+/// How long the client may stay silent, if it sends KEEPALIVEs
+fn idle_timeout(hello: &v2::Hello) -> Option<std::time::Duration> {
+    hello
+        .flags
+        .contains(HelloFlags::KEEPALIVE)
+        .then_some(v2::IDLE_TIMEOUT)
+}
+
+/// Whether a PROGRESS is due, moving `next` on if so
+fn progress_due(next: &mut Option<Instant>) -> bool {
+    match next {
+        Some(at) if Instant::now() >= *at => {
+            *at = Instant::now() + PROGRESS_INTERVAL;
+            true
+        }
+        _ => false,
     }
 }
 
 /// Handle PULL mode: client pulls files from server (we are source)
+#[allow(clippy::too_many_arguments)]
 async fn run_server_pull(
     hello: v2::Hello,
     root_path: PathBuf,
-    mut stdin: impl io::AsyncRead + Unpin,
+    io: IoOptions,
+    exclude: FilterEngine,
+    filter: FilterEngine,
+    transfer_log: Option<TransferLog>,
+    logs: Option<LogQueue>,
+    cancel: CancellationToken,
+    stdin: impl io::AsyncRead + Unpin,
     mut stdout: impl io::AsyncWrite + Unpin,
 ) -> Result<()> {
+    let mut clock = PhaseClock::start();
+    let mut phases = PhaseTimes::default();
+    let mut stdin = v2::IdleReader::new(stdin, idle_timeout(&hello));
+    let mut heartbeat = v2::Heartbeat::new(hello.flags.contains(HelloFlags::KEEPALIVE));
+
     // 1. Receive DEST_FILE_ENTRY messages from client (Initial Exchange)
+    let progress = Arc::new(ProgressCounter::default());
     let mut generator = Generator::new(GeneratorConfig {
         root: root_path.clone(),
         include_hidden: true,
         follow_symlinks: false,
         delete_enabled: hello.flags.contains(HelloFlags::DELETE),
-    });
+        delete_timing: io.delete_timing,
+        max_delete: io.max_delete,
+        index_memory: io.index_memory(),
+        file_flags: hello.flags.contains(HelloFlags::FILE_FLAGS),
+        perms: io.perms,
+        fat: io.fat,
+        // The client maps what we send
+        owners: (io.owner || io.group).then(OwnerMap::default),
+        xattrs: io.xattrs,
+        acls: io.acls,
+        hardlinks: io.hardlinks,
+        times: io.all_times,
+        exclude,
+        filter,
+        scope: None,
+        cancel: cancel.clone(),
+    })
+    .with_progress(Arc::clone(&progress));
 
     loop {
         let (msg_type, payload) = v2::read_frame(&mut stdin).await?;
         match msg_type {
             MessageType::DestFileEntry => {
                 let entry = v2::DestFileEntry::decode(payload)?;
-                generator.add_dest_entry(entry);
+                generator.add_dest_entry(entry)?;
             }
             MessageType::DestFileEnd => break,
-            _ => anyhow::bail!("Unexpected message during Initial Exchange: {:?}", msg_type),
+            MessageType::Fatal => {
+                // The client was interrupted while scanning
+                let fatal = v2::Fatal::decode(payload)?;
+                tracing::warn!("Client stopped the sync: {}", fatal.message);
+                return Err(SyncError::Cancelled.into());
+            }
+            _ => {
+                return Err(SyncError::Protocol(format!(
+                    "Unexpected message during Initial Exchange: {:?}",
+                    msg_type
+                ))
+                .into())
+            }
         }
     }
 
+    phases.exchange = clock.lap();
+
     // 2. Run Generator and Sender pipeline
     let (tx, rx) = file_job_channel();
-    let gen_handle = tokio::spawn(async move { generator.run(tx).await });
+    let gen_handle = tokio::spawn(async move { generator.run(tx).await }.in_current_span());
 
+    let tune = Arc::new(AutoTune::new());
     let sender = Sender::new(SenderConfig {
         root: root_path,
         compress: hello.flags.contains(HelloFlags::COMPRESSION),
-    });
+        tune: Some(Arc::clone(&tune)),
+        cancel: cancel.clone(),
+        io,
+    })
+    .with_progress(Arc::clone(&progress))
+    .with_transfer_log(transfer_log);
 
     // Use unbounded channel to avoid blocking_send (panics in tokio context)
     let (data_tx, mut data_rx) = mpsc::unbounded_channel::<Bytes>();
 
     // Spawn sender - uses unbounded_send which never blocks
-    let sender_handle = tokio::spawn(async move {
-        sender
-            .run(rx, |bytes| {
-                data_tx
-                    .send(bytes)
-                    .map_err(|_| anyhow::anyhow!("Data channel closed"))
-            })
-            .await
-    });
+    let sender_handle = tokio::spawn(
+        async move {
+            sender
+                .run(rx, |bytes| {
+                    data_tx
+                        .send(bytes)
+                        .map_err(|_| anyhow::anyhow!("Data channel closed"))
+                })
+                .await
+        }
+        .in_current_span(),
+    );
 
     // Stream data to client (concurrent with sender)
-    while let Some(bytes) = data_rx.recv().await {
-        v2::write_frame(&mut stdout, &bytes).await?;
+    let mut file_end_sent = false;
+    let mut next_progress = first_progress(&hello);
+    let channels = hello.flags.contains(HelloFlags::CHANNELS);
+    // The client waits while we walk the source and hash what changed
+    while let Some(bytes) = heartbeat.wait(&mut stdout, data_rx.recv()).await? {
+        if cancel.is_cancelled() {
+            break;
+        }
+        // Split, a large DATA leaves room for LOGs and PROGRESS in between
+        let parts = if channels {
+            v2::split_frames(&bytes, v2::CHANNEL_DATA)
+        } else {
+            vec![bytes.clone()]
+        };
+        for part in &parts {
+            v2::write_frame(&mut stdout, part).await?;
+            send_logs(logs.as_ref(), &mut stdout).await?;
+            if progress_due(&mut next_progress) {
+                v2::write_frame(&mut stdout, &progress.snapshot().encode()).await?;
+                stdout.flush().await?;
+            }
+        }
+        tune.written(&bytes);
+        if bytes.get(4) == Some(&(MessageType::FileEnd as u8)) {
+            phases.transfer = clock.lap();
+            file_end_sent = true;
+        }
+    }
+    if cancel.is_cancelled() {
+        gen_handle.abort();
+        sender_handle.abort();
+        return Err(SyncError::Cancelled.into());
     }
     stdout.flush().await?;
+    if file_end_sent {
+        phases.delete = clock.lap();
+    }
 
-    let (total_files, total_bytes) = gen_handle.await??;
-    sender_handle.await??;
+    let (total_files, total_bytes, scan_time) = gen_handle.await??;
+    let digest = sender_handle.await??;
+    phases.scan = scan_time;
+    phases.transfer = phases.transfer.saturating_sub(scan_time);
 
     // Send DONE
+    send_logs(logs.as_ref(), &mut stdout).await?;
     let done = v2::Done {
         files_ok: total_files,
         files_err: 0,
         bytes: total_bytes,
-        duration_ms: 0,
+        duration_ms: clock.elapsed().as_millis() as u64,
+        digest: Some(digest.value()),
+        phases,
+        counts: FileCounts::default(),
+        would: WouldBytes::default(),
+        backed_up: 0,
     };
     v2::write_frame(&mut stdout, &done.encode()).await?;
     stdout.flush().await?;
@@ -157,66 +673,898 @@ async fn run_server_pull(
 }
 
 /// Handle PUSH mode: client pushes files to server (we are destination)
+#[allow(clippy::too_many_arguments)]
 async fn run_server_push(
-    _hello: v2::Hello,
+    hello: v2::Hello,
     root_path: PathBuf,
-    mut stdin: impl io::AsyncRead + Unpin,
+    io: IoOptions,
+    audit: Option<AuditLog>,
+    transfer_log: Option<TransferLog>,
+    policy: ReceivePolicy,
+    backup: Option<Backup>,
+    filter: FilterEngine,
+    logs: Option<LogQueue>,
+    cancel: CancellationToken,
+    stdin: impl io::AsyncRead + Unpin,
     mut stdout: impl io::AsyncWrite + Unpin,
 ) -> Result<()> {
     let mut receiver = Receiver::new(ReceiverConfig {
         root: root_path.clone(),
-        block_size: 4096,
-    });
+        compress_checksums: false,
+        cancel: cancel.clone(),
+        io,
+    })
+    .with_audit(audit)
+    .with_transfer_log(transfer_log)
+    .with_policy(policy)
+    .with_backup(backup.clone())
+    .with_compression(hello.flags.contains(HelloFlags::COMPRESSION));
+    let mut clock = PhaseClock::start();
+    let mut phases = PhaseTimes::default();
+    let mut stdin = v2::IdleReader::new(stdin, idle_timeout(&hello));
+    let mut heartbeat = v2::Heartbeat::new(hello.flags.contains(HelloFlags::KEEPALIVE));
 
     // 1. Send Initial Exchange (our files metadata)
     // Use unbounded channel to avoid blocking_send (panics in tokio context)
     let (data_tx, mut data_rx) = mpsc::unbounded_channel::<Bytes>();
     let receiver_root = root_path.clone();
+    let scan_cancel = cancel.clone();
+    let compress_checksums = hello.flags.contains(HelloFlags::COMPRESSION);
+    let (perms, resume, block_size) = (io.perms, io.resume, io.block_size);
+    let estimate = hello.flags.contains(HelloFlags::ESTIMATE);
+    // Neither compares content: a dry run reports every differing file
+    let checksums = !estimate && !io.dry_run;
+    let manifest = hello
+        .flags
+        .contains(HelloFlags::DEST_MANIFEST)
+        .then_some(hello.manifest);
 
     // Spawn scanner - uses unbounded_send which never blocks
-    let scan_handle = tokio::spawn(async move {
-        let receiver = Receiver::new(ReceiverConfig {
-            root: receiver_root,
-            block_size: 4096,
-        });
-        receiver
-            .scan_dest(|bytes| {
-                data_tx
-                    .send(bytes)
-                    .map_err(|_| anyhow::anyhow!("Data channel closed"))
+    let scan_handle = tokio::spawn(
+        async move {
+            let receiver = Receiver::new(ReceiverConfig {
+                root: receiver_root,
+                compress_checksums,
+                cancel: scan_cancel,
+                io: IoOptions {
+                    perms,
+                    resume,
+                    block_size,
+                    ..Default::default()
+                },
             })
-            .await
-    });
+            .with_checksums(checksums)
+            .with_dest_manifest(manifest)
+            .with_filter(filter)
+            .with_backup(backup);
+            receiver
+                .scan_dest(|bytes| {
+                    data_tx
+                        .send(bytes)
+                        .map_err(|_| anyhow::anyhow!("Data channel closed"))
+                })
+                .await
+        }
+        .in_current_span(),
+    );
 
-    // Write data as it arrives (concurrent with scan)
-    while let Some(bytes) = data_rx.recv().await {
+    // Write data as it arrives (concurrent with scan); the client waits
+    // while we hash files with no checksums to send yet
+    while let Some(bytes) = heartbeat.wait(&mut stdout, data_rx.recv()).await? {
         v2::write_frame(&mut stdout, &bytes).await?;
     }
     stdout.flush().await?;
 
     // Wait for scanner to complete
     scan_handle.await??;
+    phases.exchange = clock.lap();
 
-    // 2. Receive streaming messages
+    // An estimate only needed our side of the comparison
+    if estimate {
+        return Ok(());
+    }
+
+    // 2. Receive streaming messages; until the first one arrives the client
+    //    is walking its source
+    let mut first = true;
+    let mut next_progress = first_progress(&hello);
+    let mut demux = v2::Demux::new();
     loop {
-        let (msg_type, payload) = v2::read_frame(&mut stdin).await?;
+        let (msg_type, payload) = tokio::select! {
+            // The beat carries on from read to read, so the client, reading
+            // our replies all along, hears from us while its data flows too
+            frame = heartbeat.wait(&mut stdout, demux.read_frame(&mut stdin)) => match frame.and_then(|frame| frame) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    // The connection dropped: with resume, keep what arrived
+                    receiver.abort().await;
+                    return Err(e);
+                }
+            },
+            _ = cancel.cancelled() => {
+                receiver.abort().await;
+                return Err(SyncError::Cancelled.into());
+            }
+        };
 
+        if std::mem::take(&mut first) {
+            phases.scan = clock.lap();
+        }
+        if msg_type == MessageType::FileEnd {
+            phases.transfer = clock.lap();
+        }
         if msg_type == MessageType::Done {
+            // The client reports its digest; it also verifies ours, so just
+            // log here, unless a mismatch would still go into place
+            let client_done = v2::Done::decode(payload)?;
+            let checked = client_done
+                .digest
+                .map_or(Ok(()), |value| receiver.digest().verify(value));
+            if let Err(e) = checked {
+                tracing::error!("{}", e);
+                if io.atomic {
+                    receiver.abort().await;
+                    return Err(e);
+                }
+            }
             break;
         }
+        if msg_type == MessageType::Fatal {
+            // The client was interrupted
+            let fatal = v2::Fatal::decode(payload)?;
+            tracing::warn!("Client stopped the sync: {}", fatal.message);
+            receiver.abort().await;
+            return Err(SyncError::Cancelled.into());
+        }
 
-        receiver.handle_message(msg_type, payload).await?;
+        if let Err(e) = receiver.handle_message(msg_type, payload).await {
+            receiver.abort().await;
+            return Err(e);
+        }
+        if progress_due(&mut next_progress) {
+            v2::write_frame(&mut stdout, &receiver.progress().encode()).await?;
+            stdout.flush().await?;
+        }
     }
+    // The client waits for our DONE while we commit
+    heartbeat.wait(&mut stdout, receiver.finish()).await??;
+    phases.delete = clock.lap();
 
-    // 3. Send DONE
+    // 3. Report what failed, then send DONE; the client only reads again
+    //    once it has sent everything, so diagnostics wait until now
+    send_logs(logs.as_ref(), &mut stdout).await?;
+    for error in &receiver.stats().errors {
+        v2::write_frame(&mut stdout, &error.encode()).await?;
+    }
     let done = v2::Done {
         files_ok: receiver.stats().files_ok,
         files_err: receiver.stats().files_err,
         bytes: receiver.stats().bytes_transferred,
-        duration_ms: 0,
+        duration_ms: clock.elapsed().as_millis() as u64,
+        digest: Some(receiver.digest().value()),
+        phases,
+        counts: receiver.stats().counts,
+        would: receiver.stats().would,
+        backed_up: receiver.stats().backed_up,
     };
     v2::write_frame(&mut stdout, &done.encode()).await?;
     stdout.flush().await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::StreamingSync;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_serve_push_and_pull_over_duplex() {
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        std::fs::create_dir(local.path().join("sub")).unwrap();
+        std::fs::write(local.path().join("sub/a.txt"), "pushed").unwrap();
+
+        // Push: the server root is the destination
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let stats = StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+        assert_eq!(stats.files_ok, 1);
+        assert_eq!(
+            std::fs::read_to_string(remote.path().join("sub/a.txt")).unwrap(),
+            "pushed"
+        );
+
+        // Pull: the server root is the source
+        std::fs::write(remote.path().join("b.txt"), "pulled").unwrap();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .pull(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(local.path().join("b.txt")).unwrap(),
+            "pulled"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transfer_logs_on_both_ends() {
+        use crate::streaming::transfer_log::DEFAULT_LOG_SIZE;
+
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        let logs = TempDir::new().unwrap();
+        std::fs::write(local.path().join("a.txt"), "pushed").unwrap();
+        let log = |name: &str| {
+            let path = logs.path().join(name);
+            (
+                path.clone(),
+                TransferLog::open(&path, DEFAULT_LOG_SIZE).unwrap(),
+            )
+        };
+        let read = |path: &Path| -> Vec<serde_json::Value> {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect()
+        };
+        let (client_path, client_log) = log("client.log");
+        let (server_path, server_log) = log("server.log");
+
+        // The client logs what it sends, the server what it receives
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()).with_transfer_log(server_log),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .with_transfer_log(Some(client_log))
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+
+        for path in [&client_path, &server_path] {
+            let lines = read(path);
+            assert_eq!(lines.len(), 1, "{}", path.display());
+            assert_eq!(lines[0]["path"], "a.txt");
+            assert_eq!(lines[0]["size"], 6);
+            assert_eq!(lines[0]["bytes"], 6);
+            assert_eq!(lines[0]["result"], "ok");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deny_policy_applies_on_the_receiving_side() {
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        let deny = || ReceivePolicy::new(true, true, &[".ssh/authorized_keys".to_string()]);
+        std::fs::create_dir_all(local.path().join("home/.ssh")).unwrap();
+        std::fs::write(local.path().join("home/.ssh/authorized_keys"), "evil").unwrap();
+        std::fs::write(local.path().join("home/notes.txt"), "fine").unwrap();
+
+        // Push: the server's policy holds whatever the client sends
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()).with_policy(deny().unwrap()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let stats = StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+        assert_eq!(stats.files_ok, 1);
+        assert_eq!(stats.files_err, 1);
+        // The server reports what it refused, so the client can list it
+        assert_eq!(stats.errors.len(), 1);
+        assert_eq!(stats.errors[0].path, "home/.ssh/authorized_keys");
+        assert!(remote.path().join("home/notes.txt").exists());
+        assert!(!remote.path().join("home/.ssh/authorized_keys").exists());
+
+        // Pull: the client's policy holds whatever the server sends
+        std::fs::create_dir_all(remote.path().join("home/.ssh")).unwrap();
+        std::fs::write(remote.path().join("home/.ssh/authorized_keys"), "evil").unwrap();
+        let pulled = TempDir::new().unwrap();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let stats = StreamingSync::new(
+            pulled.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .with_policy(deny().unwrap())
+        .pull(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+        assert_eq!((stats.files_ok, stats.files_err), (1, 1));
+        assert_eq!(stats.errors[0].path, "home/.ssh/authorized_keys");
+        assert_eq!(
+            std::fs::read_to_string(pulled.path().join("home/notes.txt")).unwrap(),
+            "fine"
+        );
+        assert!(!pulled.path().join("home/.ssh/authorized_keys").exists());
+    }
+
+    #[tokio::test]
+    async fn test_pull_leaves_out_server_exclusions() {
+        let remote = TempDir::new().unwrap();
+        std::fs::write(remote.path().join(".syignore"), "cache/\n").unwrap();
+        std::fs::write(remote.path().join("notes.txt"), "served").unwrap();
+        std::fs::write(remote.path().join("server.key"), "secret").unwrap();
+        std::fs::create_dir_all(remote.path().join("cache/deep")).unwrap();
+        std::fs::write(remote.path().join("cache/deep/blob"), "cached").unwrap();
+
+        // The client's own copy of an excluded path survives --delete
+        let pulled = TempDir::new().unwrap();
+        std::fs::write(pulled.path().join("server.key"), "mine").unwrap();
+
+        let mut exclude = FilterEngine::new();
+        exclude.add_rule("*.key").unwrap();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()).with_exclude(exclude),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        StreamingSync::new(
+            pulled.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            true,
+            false,
+        )
+        .pull(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+
+        assert!(pulled.path().join("notes.txt").exists());
+        assert!(!pulled.path().join("cache").exists());
+        assert_eq!(
+            std::fs::read_to_string(pulled.path().join("server.key")).unwrap(),
+            "mine"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_filter_applies_on_both_ends() {
+        let mut filter = FilterEngine::new();
+        for rule in ["+ *.key", "+ important.log", "- *.log"] {
+            filter.add_rule(rule).unwrap();
+        }
+
+        // Pull: the server leaves filtered files out, but the client's
+        // rules can't bring back what the server excludes
+        let remote = TempDir::new().unwrap();
+        for name in ["a.txt", "debug.log", "important.log", "server.key"] {
+            std::fs::write(remote.path().join(name), name).unwrap();
+        }
+        let pulled = TempDir::new().unwrap();
+        std::fs::write(pulled.path().join("old.log"), "mine").unwrap();
+        let mut exclude = FilterEngine::new();
+        exclude.add_rule("*.key").unwrap();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()).with_exclude(exclude),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        StreamingSync::new(
+            pulled.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            true,
+            false,
+        )
+        .with_filter(filter.clone())
+        .pull(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+
+        let mut names: Vec<_> = std::fs::read_dir(pulled.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.txt", "important.log", "old.log"]);
+
+        // Push: filtered files aren't sent, and the server neither lists
+        // nor deletes its own
+        let local = TempDir::new().unwrap();
+        std::fs::write(local.path().join("b.txt"), "pushed").unwrap();
+        std::fs::write(local.path().join("trace.log"), "local only").unwrap();
+        let pushed = TempDir::new().unwrap();
+        std::fs::write(pushed.path().join("stale.log"), "kept").unwrap();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(pushed.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let stats = StreamingSync::new(
+            local.path().to_path_buf(),
+            pushed.path().to_path_buf(),
+            true,
+            false,
+        )
+        .with_filter(filter)
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+
+        assert_eq!(stats.files_ok, 1);
+        assert!(pushed.path().join("b.txt").exists());
+        assert!(!pushed.path().join("trace.log").exists());
+        assert!(pushed.path().join("stale.log").exists());
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_xattrs_cross_the_wire() {
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        std::fs::create_dir(local.path().join("dir")).unwrap();
+        std::fs::write(local.path().join("dir/a.txt"), "tagged").unwrap();
+        if xattr::set(local.path().join("dir/a.txt"), "user.tag", b"file").is_err() {
+            eprintln!("Skipping test: no user xattrs on this filesystem");
+            return;
+        }
+        xattr::set(local.path().join("dir"), "user.tag", b"dir").unwrap();
+        let io = IoOptions {
+            xattrs: true,
+            ..Default::default()
+        };
+
+        // Push: the server applies them after each file lands
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .with_io(io)
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+        let tag = |path: &Path| xattr::get(path, "user.tag").unwrap();
+        assert_eq!(tag(&remote.path().join("dir/a.txt")).unwrap(), b"file");
+        assert_eq!(tag(&remote.path().join("dir")).unwrap(), b"dir");
+
+        // Pull: only sent when asked for
+        let pulled = TempDir::new().unwrap();
+        for (io, expected) in [(IoOptions::default(), None), (io, Some(b"file".to_vec()))] {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let (server_read, server_write) = tokio::io::split(server);
+            let server_task = tokio::spawn(serve(
+                server_read,
+                server_write,
+                ServerConfig::new(remote.path()),
+            ));
+            let (mut client_read, mut client_write) = tokio::io::split(client);
+            let _ = std::fs::remove_file(pulled.path().join("dir/a.txt"));
+            StreamingSync::new(
+                pulled.path().to_path_buf(),
+                PathBuf::from("ignored"),
+                false,
+                false,
+            )
+            .with_io(io)
+            .pull(&mut client_read, &mut client_write)
+            .await
+            .unwrap();
+            server_task.await.unwrap().unwrap();
+            assert_eq!(tag(&pulled.path().join("dir/a.txt")), expected);
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_hard_links_cross_the_wire() {
+        use std::os::unix::fs::MetadataExt;
+
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        std::fs::create_dir(local.path().join("dir")).unwrap();
+        std::fs::write(local.path().join("a.txt"), "shared").unwrap();
+        std::fs::hard_link(local.path().join("a.txt"), local.path().join("dir/b.txt")).unwrap();
+        // Already on the destination as a file of its own
+        std::fs::create_dir(remote.path().join("dir")).unwrap();
+        std::fs::write(remote.path().join("dir/b.txt"), "stale").unwrap();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .with_io(IoOptions {
+            hardlinks: true,
+            ..Default::default()
+        })
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+
+        let meta = |path: &str| std::fs::metadata(remote.path().join(path)).unwrap();
+        assert_eq!(meta("a.txt").ino(), meta("dir/b.txt").ino());
+        assert_eq!(meta("a.txt").nlink(), 2);
+        assert_eq!(
+            std::fs::read_to_string(remote.path().join("dir/b.txt")).unwrap(),
+            "shared"
+        );
+        assert!(!remote.path().join("dir/b.sy.tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn test_push_over_max_delete_stops_server() {
+        // Pushing an empty source over a full destination
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        for name in ["a.txt", "b.txt"] {
+            std::fs::write(remote.path().join(name), "keep").unwrap();
+        }
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let err = StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            true,
+            false,
+        )
+        .with_io(IoOptions {
+            max_delete: Some(1),
+            ..Default::default()
+        })
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            SyncError::TooManyDeletes { count: 2, max: 1 }
+        ));
+
+        // The server hears why and stops without touching anything
+        let _ = server_task.await.unwrap();
+        assert!(remote.path().join("a.txt").exists());
+        assert!(remote.path().join("b.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_push_reports_progress() {
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        for i in 0..4 {
+            std::fs::write(local.path().join(format!("{}.bin", i)), vec![i; 128 * 1024]).unwrap();
+        }
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&reports);
+        // Held to about a second, so a few reports go out
+        StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .with_io(IoOptions {
+            bwlimit: Some(256 * 1024),
+            ..Default::default()
+        })
+        .with_progress(move |progress| seen.lock().unwrap().push(progress))
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+
+        let reports = reports.lock().unwrap();
+        assert!(!reports.is_empty());
+        assert!(reports.windows(2).all(|w| w[0].files <= w[1].files));
+        let last = reports.last().unwrap();
+        assert_eq!(last.files_total, 4);
+        assert_eq!(last.bytes_total, 4 * 128 * 1024);
+        assert!(last.files <= last.files_total);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_push_reports_without_writing() {
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        std::fs::write(local.path().join("new.txt"), "fresh").unwrap();
+        std::fs::write(local.path().join("changed.txt"), "new content").unwrap();
+        std::fs::write(remote.path().join("changed.txt"), "old").unwrap();
+        std::fs::write(remote.path().join("gone.txt"), "remove me").unwrap();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let stats = StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            true,
+            false,
+        )
+        .with_io(IoOptions {
+            dry_run: true,
+            ..Default::default()
+        })
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        server_task.await.unwrap().unwrap();
+
+        assert_eq!(stats.counts.created, 1);
+        assert_eq!(stats.counts.updated, 1);
+        assert_eq!(
+            stats.would,
+            WouldBytes {
+                add: 5,
+                change: 11,
+                delete: 9,
+            }
+        );
+        assert!(!remote.path().join("new.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(remote.path().join("changed.txt")).unwrap(),
+            "old"
+        );
+        assert!(remote.path().join("gone.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_push_stops_the_server() {
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        std::fs::write(local.path().join("a.txt"), "never sent").unwrap();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()),
+        ));
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let stats = StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .with_cancel(cancel)
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap();
+        assert!(stats.cancelled);
+
+        // The client's FATAL ends the session instead of a broken pipe
+        let err = server_task.await.unwrap().unwrap_err();
+        assert!(matches!(err, SyncError::Cancelled));
+        assert!(!remote.path().join("a.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_serve_rejects_missing_hello() {
+        let remote = TempDir::new().unwrap();
+        let (mut client, server) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path().join("new")),
+        ));
+
+        let done = v2::Done {
+            files_ok: 0,
+            files_err: 0,
+            bytes: 0,
+            duration_ms: 0,
+            digest: None,
+            phases: PhaseTimes::default(),
+            counts: FileCounts::default(),
+            would: WouldBytes::default(),
+            backed_up: 0,
+        };
+        v2::write_frame(&mut client, &done.encode()).await.unwrap();
+        let err = server_task.await.unwrap().unwrap_err();
+        assert!(matches!(err, SyncError::Protocol(_)));
+
+        let (msg_type, payload) = v2::read_frame(&mut client).await.unwrap();
+        assert_eq!(msg_type, MessageType::Fatal);
+        let fatal = v2::Fatal::decode(payload).unwrap();
+        assert_eq!(fatal.code, v2::ErrorCode::Protocol as u16);
+        assert!(remote.path().join("new").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_client_gets_typed_remote_fatal() {
+        let local = TempDir::new().unwrap();
+        let (client, mut server) = tokio::io::duplex(1 << 16);
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+
+        // A server that accepts the session, then runs out of space
+        let server_task = tokio::spawn(async move {
+            v2::read_frame(&mut server).await.unwrap();
+            let hello = v2::Hello::new(HelloFlags::empty(), "");
+            v2::write_frame(&mut server, &hello.encode()).await.unwrap();
+            let fatal = v2::Fatal {
+                code: v2::ErrorCode::DiskFull as u16,
+                message: "No space left on device".to_string(),
+            };
+            v2::write_frame(&mut server, &fatal.encode()).await.unwrap();
+            server
+        });
+
+        let err = StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap_err();
+        let _server = server_task.await.unwrap();
+        assert!(matches!(err, SyncError::Remote { .. }));
+        assert_eq!(err.error_code(), v2::ErrorCode::DiskFull);
+    }
+
+    #[test]
+    fn test_root_allowed() {
+        let temp = TempDir::new().unwrap();
+        let allowed = vec![temp.path().join("backups")];
+        std::fs::create_dir(&allowed[0]).unwrap();
+
+        assert!(root_allowed(&temp.path().join("backups"), &allowed));
+        assert!(root_allowed(
+            &temp.path().join("backups/host/new"),
+            &allowed
+        ));
+        assert!(!root_allowed(temp.path(), &allowed));
+        assert!(!root_allowed(&temp.path().join("backups-old"), &allowed));
+        assert!(!root_allowed(&temp.path().join("backups/../etc"), &allowed));
+        assert!(!root_allowed(
+            &temp.path().join("backups/new/../../etc"),
+            &allowed
+        ));
+        assert!(!root_allowed(Path::new("/"), &[]));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(temp.path(), allowed[0].join("escape")).unwrap();
+            assert!(!root_allowed(&allowed[0].join("escape/etc"), &allowed));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_rejects_root_outside_allowlist() {
+        let local = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path().join("elsewhere"))
+                .with_allowed_roots(vec![remote.path().join("allowed")]),
+        ));
+
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let err = StreamingSync::new(
+            local.path().to_path_buf(),
+            PathBuf::from("ignored"),
+            false,
+            false,
+        )
+        .push(&mut client_read, &mut client_write)
+        .await
+        .unwrap_err();
+        let server_err = server_task.await.unwrap().unwrap_err();
+        assert!(matches!(server_err, SyncError::RootNotAllowed { .. }));
+        assert_eq!(err.error_code(), v2::ErrorCode::PermissionDenied);
+        assert!(!remote.path().join("elsewhere").exists());
+    }
+}
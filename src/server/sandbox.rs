@@ -0,0 +1,292 @@
+//! Confinement for `sy --server --sandbox` (Linux)
+//!
+//! Two layers, both irreversible for the process:
+//!
+//! - Landlock limits filesystem access to the sync root (and forbids TCP
+//!   entirely; the server only talks over stdin/stdout). It applies to the
+//!   calling thread and every thread it creates afterwards, which is why the
+//!   sandboxed session runs on its own thread and runtime.
+//! - A seccomp filter, synced to every thread, allows only the system calls a
+//!   server session needs; anything else fails with EPERM.
+//!
+//! Both are best effort: a kernel without Landlock (or seccomp) gets a
+//! warning rather than a refused session.
+
+use anyhow::Result;
+use std::path::Path;
+
+/// Confine this thread (and threads it starts) to `root`, and the whole
+/// process to the server's system calls
+#[cfg(target_os = "linux")]
+pub fn enter(root: &Path) -> Result<()> {
+    // Reads /proc, which Landlock is about to hide
+    crate::fs_util::has_cap_setfcap();
+
+    restrict_paths(root)?;
+    restrict_syscalls()?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enter(_root: &Path) -> Result<()> {
+    tracing::warn!("--sandbox is only supported on Linux; the server is not confined");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn restrict_paths(root: &Path) -> Result<()> {
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, AccessNet, Ruleset, RulesetAttr, RulesetCreatedAttr,
+        RulesetStatus, ABI,
+    };
+
+    let abi = ABI::V5;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .handle_access(AccessNet::from_all(abi))?
+        .create()?
+        .add_rules(path_beneath_rules([root], AccessFs::from_all(abi)))?
+        .restrict_self()?;
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => {
+            tracing::debug!("Landlock: confined to {}", root.display())
+        }
+        RulesetStatus::PartiallyEnforced => tracing::warn!(
+            "Landlock is only partly supported by this kernel; some access outside {} is still possible",
+            root.display()
+        ),
+        RulesetStatus::NotEnforced => tracing::warn!(
+            "Landlock is not supported by this kernel; filesystem access is not restricted"
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn restrict_syscalls() -> Result<()> {
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+
+    let Ok(arch) = TargetArch::try_from(std::env::consts::ARCH) else {
+        tracing::warn!(
+            "seccomp filtering is not available on {}; system calls are not restricted",
+            std::env::consts::ARCH
+        );
+        return Ok(());
+    };
+    let filter = SeccompFilter::new(
+        allowed_syscalls().map(|nr| (nr, Vec::new())).collect(),
+        SeccompAction::Errno(libc::EPERM as u32),
+        SeccompAction::Allow,
+        arch,
+    )?;
+    let program: BpfProgram = filter.try_into()?;
+    if let Err(e) = seccompiler::apply_filter_all_threads(&program) {
+        tracing::warn!(
+            "Could not install seccomp filter ({}); system calls are not restricted",
+            e
+        );
+    }
+    Ok(())
+}
+
+/// What a server session uses: file I/O under the root, memory, threads and
+/// the async runtime's polling, clocks and signals
+#[cfg(target_os = "linux")]
+fn allowed_syscalls() -> impl Iterator<Item = i64> {
+    const COMMON: &[libc::c_long] = &[
+        // Files
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_readv,
+        libc::SYS_writev,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
+        libc::SYS_preadv,
+        libc::SYS_pwritev,
+        libc::SYS_openat,
+        libc::SYS_close,
+        libc::SYS_lseek,
+        libc::SYS_fstat,
+        libc::SYS_newfstatat,
+        libc::SYS_statx,
+        libc::SYS_statfs,
+        libc::SYS_fstatfs,
+        libc::SYS_getdents64,
+        libc::SYS_mkdirat,
+        libc::SYS_unlinkat,
+        libc::SYS_renameat,
+        libc::SYS_renameat2,
+        libc::SYS_symlinkat,
+        libc::SYS_readlinkat,
+        libc::SYS_linkat,
+        libc::SYS_faccessat,
+        libc::SYS_faccessat2,
+        libc::SYS_fchmod,
+        libc::SYS_fchmodat,
+        libc::SYS_fchown,
+        libc::SYS_fchownat,
+        libc::SYS_utimensat,
+        libc::SYS_fsync,
+        libc::SYS_fdatasync,
+        libc::SYS_sync,
+        libc::SYS_syncfs,
+        libc::SYS_ftruncate,
+        libc::SYS_fallocate,
+        libc::SYS_fadvise64,
+        libc::SYS_copy_file_range,
+        libc::SYS_sendfile,
+        libc::SYS_splice,
+        libc::SYS_ioctl,
+        libc::SYS_fcntl,
+        libc::SYS_dup,
+        libc::SYS_dup3,
+        libc::SYS_getcwd,
+        libc::SYS_getxattr,
+        libc::SYS_lgetxattr,
+        libc::SYS_fgetxattr,
+        libc::SYS_listxattr,
+        libc::SYS_llistxattr,
+        libc::SYS_flistxattr,
+        libc::SYS_setxattr,
+        libc::SYS_lsetxattr,
+        libc::SYS_fsetxattr,
+        libc::SYS_removexattr,
+        libc::SYS_lremovexattr,
+        libc::SYS_fremovexattr,
+        libc::SYS_io_uring_setup,
+        libc::SYS_io_uring_enter,
+        libc::SYS_io_uring_register,
+        // Memory
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mprotect,
+        libc::SYS_mremap,
+        libc::SYS_madvise,
+        libc::SYS_brk,
+        // Threads
+        libc::SYS_clone,
+        libc::SYS_clone3,
+        libc::SYS_futex,
+        libc::SYS_set_robust_list,
+        libc::SYS_rseq,
+        libc::SYS_set_tid_address,
+        libc::SYS_sched_yield,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_gettid,
+        libc::SYS_getpid,
+        libc::SYS_tgkill,
+        libc::SYS_prctl,
+        libc::SYS_prlimit64,
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+        libc::SYS_restart_syscall,
+        // Polling
+        libc::SYS_epoll_create1,
+        libc::SYS_epoll_ctl,
+        libc::SYS_epoll_pwait,
+        libc::SYS_epoll_pwait2,
+        libc::SYS_eventfd2,
+        libc::SYS_pipe2,
+        libc::SYS_ppoll,
+        // Clocks, signals, misc
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_getres,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_nanosleep,
+        libc::SYS_gettimeofday,
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_sigaltstack,
+        libc::SYS_getrandom,
+        libc::SYS_uname,
+        libc::SYS_getuid,
+        libc::SYS_geteuid,
+        libc::SYS_getgid,
+        libc::SYS_getegid,
+    ];
+    // Older calls that aarch64 and riscv64 only have in their *at form
+    #[cfg(target_arch = "x86_64")]
+    const LEGACY: &[libc::c_long] = &[
+        libc::SYS_open,
+        libc::SYS_stat,
+        libc::SYS_lstat,
+        libc::SYS_access,
+        libc::SYS_rename,
+        libc::SYS_mkdir,
+        libc::SYS_rmdir,
+        libc::SYS_unlink,
+        libc::SYS_readlink,
+        libc::SYS_symlink,
+        libc::SYS_link,
+        libc::SYS_chmod,
+        libc::SYS_getdents,
+        libc::SYS_dup2,
+        libc::SYS_pipe,
+        libc::SYS_poll,
+        libc::SYS_epoll_wait,
+        libc::SYS_epoll_create,
+        libc::SYS_eventfd,
+        libc::SYS_arch_prctl,
+    ];
+    #[cfg(not(target_arch = "x86_64"))]
+    const LEGACY: &[libc::c_long] = &[];
+
+    // c_long is only i64 on 64-bit targets
+    #[allow(clippy::unnecessary_cast)]
+    COMMON.iter().chain(LEGACY).map(|&nr| nr as i64)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_syscall_filter_compiles() {
+        use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+
+        let Ok(arch) = TargetArch::try_from(std::env::consts::ARCH) else {
+            return;
+        };
+        let filter = SeccompFilter::new(
+            allowed_syscalls().map(|nr| (nr, Vec::new())).collect(),
+            SeccompAction::Errno(libc::EPERM as u32),
+            SeccompAction::Allow,
+            arch,
+        )
+        .unwrap();
+        let program: BpfProgram = filter.try_into().unwrap();
+        assert!(!program.is_empty());
+    }
+
+    #[test]
+    fn test_landlock_confines_thread_to_root() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(temp.path().join("outside.txt"), "secret").unwrap();
+
+        // Landlock is per thread, so the test thread stays unconfined
+        let outside = temp.path().join("outside.txt");
+        let confined_root = root.clone();
+        let (inside_ok, outside_result) = std::thread::spawn(move || {
+            restrict_paths(&confined_root).unwrap();
+            let inside_ok = std::fs::write(confined_root.join("a.txt"), "ok").is_ok();
+            (inside_ok, std::fs::read_to_string(&outside))
+        })
+        .join()
+        .unwrap();
+
+        assert!(inside_ok);
+        if landlock_supported() {
+            assert!(outside_result.is_err());
+        }
+        assert_eq!(std::fs::read_to_string(root.join("a.txt")).unwrap(), "ok");
+    }
+
+    fn landlock_supported() -> bool {
+        // landlock_create_ruleset(NULL, 0, LANDLOCK_CREATE_RULESET_VERSION)
+        unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, 0usize, 0usize, 1u32) > 0 }
+    }
+}
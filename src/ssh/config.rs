@@ -1,3 +1,4 @@
+use crate::config::{HostKeyPolicy, SshSettings};
 use crate::error::{Result, SyncError};
 use std::fs;
 use std::path::PathBuf;
@@ -16,6 +17,14 @@ pub struct SshConfig {
     pub control_path: Option<PathBuf>,
     pub control_persist: Option<Duration>,
     pub compression: bool,
+    /// What to do with unknown or changed host keys
+    pub host_key_policy: HostKeyPolicy,
+    /// known_hosts file; None is ~/.ssh/known_hosts
+    pub known_hosts: Option<PathBuf>,
+    /// Pinned `SHA256:` fingerprints; when set, known_hosts isn't consulted
+    pub host_key_fingerprints: Vec<String>,
+    /// Elevation command (e.g. `sudo -n`) the remote `sy --server` runs under
+    pub remote_sudo: Option<String>,
 }
 
 impl Default for SshConfig {
@@ -30,6 +39,10 @@ impl Default for SshConfig {
             control_path: None,
             control_persist: None,
             compression: false,
+            host_key_policy: HostKeyPolicy::default(),
+            known_hosts: None,
+            host_key_fingerprints: Vec::new(),
+            remote_sudo: None,
         }
     }
 }
@@ -47,9 +60,36 @@ impl SshConfig {
             control_path: None,
             control_persist: None,
             compression: false,
+            host_key_policy: HostKeyPolicy::default(),
+            known_hosts: None,
+            host_key_fingerprints: Vec::new(),
+            remote_sudo: None,
         }
     }
 
+    /// Apply sy's `[ssh]` settings (and `--host-key-policy`, `--remote-sudo`), which take
+    /// precedence over ~/.ssh/config; `alias` is the host as the user wrote
+    /// it, looked up in `fingerprints` before the resolved hostname
+    pub fn with_host_keys(mut self, settings: &SshSettings, alias: &str) -> Self {
+        if let Some(policy) = settings.host_key_policy {
+            self.host_key_policy = policy;
+        }
+        if let Some(path) = &settings.known_hosts {
+            self.known_hosts = Some(path.clone());
+        }
+        if let Some(pins) = settings
+            .fingerprints
+            .get(alias)
+            .or_else(|| settings.fingerprints.get(&self.hostname))
+        {
+            self.host_key_fingerprints = pins.clone();
+        }
+        if let Some(command) = &settings.remote_sudo {
+            self.remote_sudo = Some(command.clone());
+        }
+        self
+    }
+
     /// Expand ~ and environment variables in paths
     fn expand_path(path: &str) -> PathBuf {
         if let Some(home) = dirs::home_dir() {
@@ -163,6 +203,21 @@ pub fn parse_ssh_config_from_str(host: &str, content: &str) -> Result<SshConfig>
                     config.compression = value.to_lowercase() == "yes";
                 }
             }
+            "stricthostkeychecking" => {
+                if let Some(value) = parts.get(1) {
+                    // sy never prompts, so "ask" is as strict as "yes"
+                    config.host_key_policy = match value.to_lowercase().as_str() {
+                        "accept-new" => HostKeyPolicy::AcceptNew,
+                        "no" | "off" => HostKeyPolicy::Insecure,
+                        _ => HostKeyPolicy::Strict,
+                    };
+                }
+            }
+            "userknownhostsfile" => {
+                if let Some(value) = parts.get(1) {
+                    config.known_hosts = Some(SshConfig::expand_path(value));
+                }
+            }
             _ => {
                 // Ignore unknown directives
             }
@@ -259,6 +314,65 @@ Host example
         assert_eq!(config.user, "admin");
     }
 
+    #[test]
+    fn test_parse_host_key_checking() {
+        let content = r#"
+Host lab-*
+    StrictHostKeyChecking no
+    UserKnownHostsFile /dev/null
+
+Host backup
+    StrictHostKeyChecking ask
+"#;
+
+        let lab = parse_ssh_config_from_str("lab-1", content).unwrap();
+        assert_eq!(lab.host_key_policy, HostKeyPolicy::Insecure);
+        assert_eq!(lab.known_hosts, Some(PathBuf::from("/dev/null")));
+
+        let backup = parse_ssh_config_from_str("backup", content).unwrap();
+        assert_eq!(backup.host_key_policy, HostKeyPolicy::Strict);
+        assert_eq!(backup.known_hosts, None);
+
+        let other = parse_ssh_config_from_str("other", content).unwrap();
+        assert_eq!(other.host_key_policy, HostKeyPolicy::AcceptNew);
+    }
+
+    #[test]
+    fn test_with_host_keys_overrides_ssh_config() {
+        let content = r#"
+Host backup
+    HostName backup.example.com
+    StrictHostKeyChecking no
+"#;
+        let mut settings = SshSettings {
+            host_key_policy: Some(HostKeyPolicy::Strict),
+            known_hosts: Some(PathBuf::from("/etc/sy/known_hosts")),
+            ..Default::default()
+        };
+        settings.fingerprints.insert(
+            "backup.example.com".to_string(),
+            vec!["SHA256:abc".to_string()],
+        );
+
+        let config = parse_ssh_config_from_str("backup", content)
+            .unwrap()
+            .with_host_keys(&settings, "backup");
+        assert_eq!(config.host_key_policy, HostKeyPolicy::Strict);
+        assert_eq!(
+            config.known_hosts,
+            Some(PathBuf::from("/etc/sy/known_hosts"))
+        );
+        // Found under the resolved hostname
+        assert_eq!(config.host_key_fingerprints, vec!["SHA256:abc"]);
+
+        // Nothing set in [ssh] leaves ~/.ssh/config alone
+        let config = parse_ssh_config_from_str("backup", content)
+            .unwrap()
+            .with_host_keys(&SshSettings::default(), "backup");
+        assert_eq!(config.host_key_policy, HostKeyPolicy::Insecure);
+        assert!(config.host_key_fingerprints.is_empty());
+    }
+
     #[test]
     fn test_parse_wildcard_host() {
         let content = r#"
@@ -14,7 +14,8 @@ const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
 /// 1. Establishes a TCP connection to the SSH server
 /// 2. Creates an SSH session
 /// 3. Performs SSH handshake
-/// 4. Authenticates using available methods (keys, agent, password)
+/// 4. Verifies the host key (see [`super::hostkey`])
+/// 5. Authenticates using available methods (keys, agent, password)
 pub async fn connect(config: &SshConfig) -> Result<Session> {
     // Establish TCP connection
     let tcp = connect_tcp(&config.hostname, config.port).await?;
@@ -22,6 +23,7 @@ pub async fn connect(config: &SshConfig) -> Result<Session> {
     // Clone config data needed for authentication
     let username = config.user.clone();
     let identity_files = config.identity_file.clone();
+    let config = config.clone();
 
     // Wrap all sync operations (session creation, handshake, auth) in spawn_blocking
     let session = tokio::task::spawn_blocking(move || {
@@ -48,6 +50,9 @@ pub async fn connect(config: &SshConfig) -> Result<Session> {
             )))
         })?;
 
+        // Before any credentials are offered
+        super::hostkey::verify(&session, &config)?;
+
         // Configure keepalive to prevent connection drops during long transfers
         // Send keepalive every 60 seconds, disconnect after 3 missed responses
         session.set_keepalive(true, 60);
@@ -153,6 +158,7 @@ mod tests {
             control_path: None,
             control_persist: None,
             compression: false,
+            ..Default::default()
         };
 
         assert_eq!(config.hostname, "localhost");
@@ -0,0 +1,281 @@
+//! Host key verification for the built-in SSH client
+//!
+//! Runs right after the handshake, before any credentials are offered.
+//! Pinned fingerprints (`[ssh] fingerprints`) win outright; otherwise the
+//! key is looked up in known_hosts and `HostKeyPolicy` decides what an
+//! unknown or changed key means.
+
+use super::config::SshConfig;
+use crate::config::HostKeyPolicy;
+use crate::error::{Result, SyncError};
+use data_encoding::{BASE64, BASE64_NOPAD};
+use ssh2::{CheckResult, HashType, HostKeyType, KnownHostFileKind, Session};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Check the key `session` was offered against `config`
+pub fn verify(session: &Session, config: &SshConfig) -> Result<()> {
+    let (key, key_type) = session
+        .host_key()
+        .ok_or_else(|| SyncError::Io(std::io::Error::other("SSH server sent no host key")))?;
+    let fingerprint = session
+        .host_key_hash(HashType::Sha256)
+        .map(|hash| format!("SHA256:{}", BASE64_NOPAD.encode(hash)))
+        .ok_or_else(|| SyncError::Io(std::io::Error::other("Failed to hash SSH host key")))?;
+
+    if !config.host_key_fingerprints.is_empty() {
+        return check_pins(
+            &config.hostname,
+            &fingerprint,
+            &config.host_key_fingerprints,
+        );
+    }
+
+    let known_hosts = match &config.known_hosts {
+        Some(path) => path.clone(),
+        None => default_known_hosts()?,
+    };
+    check_known_hosts(
+        session,
+        &known_hosts,
+        &config.hostname,
+        config.port,
+        (key, key_type),
+        &fingerprint,
+        config.host_key_policy,
+    )
+}
+
+fn default_known_hosts() -> Result<PathBuf> {
+    dirs::home_dir()
+        .map(|home| home.join(".ssh/known_hosts"))
+        .ok_or_else(|| SyncError::Io(std::io::Error::other("Cannot find home directory")))
+}
+
+fn check_pins(host: &str, fingerprint: &str, pins: &[String]) -> Result<()> {
+    if pins.iter().any(|pin| pin.trim() == fingerprint) {
+        tracing::debug!("Host key for {} matches pinned {}", host, fingerprint);
+        return Ok(());
+    }
+    Err(SyncError::HostKeyMismatch {
+        host: host.to_string(),
+        fingerprint: fingerprint.to_string(),
+        expected: "the fingerprints pinned under [ssh] fingerprints".to_string(),
+    })
+}
+
+fn check_known_hosts(
+    session: &Session,
+    path: &Path,
+    host: &str,
+    port: u16,
+    (key, key_type): (&[u8], HostKeyType),
+    fingerprint: &str,
+    policy: HostKeyPolicy,
+) -> Result<()> {
+    let ssh_err = |e: ssh2::Error| SyncError::Io(std::io::Error::other(e.to_string()));
+
+    let mut known = session.known_hosts().map_err(ssh_err)?;
+    if path.exists() {
+        known
+            .read_file(path, KnownHostFileKind::OpenSSH)
+            .map_err(|e| {
+                SyncError::Io(std::io::Error::other(format!(
+                    "Failed to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            })?;
+    }
+
+    match (known.check_port(host, port, key), policy) {
+        (CheckResult::Match, _) => Ok(()),
+        (CheckResult::Mismatch, HostKeyPolicy::Insecure) => {
+            tracing::warn!(
+                "Host key for {} ({}) doesn't match {}; connecting anyway (--host-key-policy insecure)",
+                host,
+                fingerprint,
+                path.display()
+            );
+            Ok(())
+        }
+        (CheckResult::Mismatch, _) => Err(SyncError::HostKeyMismatch {
+            host: host.to_string(),
+            fingerprint: fingerprint.to_string(),
+            expected: format!("the key recorded in {}", path.display()),
+        }),
+        (CheckResult::NotFound, HostKeyPolicy::Strict) => Err(SyncError::HostKeyUnknown {
+            host: host.to_string(),
+            fingerprint: fingerprint.to_string(),
+            known_hosts: path.to_path_buf(),
+        }),
+        (CheckResult::NotFound, HostKeyPolicy::AcceptNew) => {
+            add_known_host(path, host, port, key, key_type)?;
+            tracing::warn!(
+                "Permanently added {} ({}) to {}",
+                host,
+                fingerprint,
+                path.display()
+            );
+            Ok(())
+        }
+        (CheckResult::NotFound, HostKeyPolicy::Insecure) => {
+            tracing::warn!(
+                "Unknown host key for {} ({}); connecting anyway (--host-key-policy insecure)",
+                host,
+                fingerprint
+            );
+            Ok(())
+        }
+        (CheckResult::Failure, _) => Err(SyncError::Io(std::io::Error::other(format!(
+            "Failed to check host key for {} against {}",
+            host,
+            path.display()
+        )))),
+    }
+}
+
+/// Append one line for `host` to known_hosts
+///
+/// Appended by hand rather than through libssh2's writer, which rewrites
+/// the whole file and drops the lines it can't parse.
+fn add_known_host(
+    path: &Path,
+    host: &str,
+    port: u16,
+    key: &[u8],
+    key_type: HostKeyType,
+) -> Result<()> {
+    let key_name = match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed25519 => "ssh-ed25519",
+        HostKeyType::Unknown => {
+            return Err(SyncError::Io(std::io::Error::other(format!(
+                "Can't record host key of unknown type for {}",
+                host
+            ))))
+        }
+    };
+    let line = format!(
+        "{} {} {}\n",
+        known_hosts_name(host, port),
+        key_name,
+        BASE64.encode(key)
+    );
+
+    let write = || -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)?.write_all(line.as_bytes())
+    };
+    write().map_err(|e| {
+        SyncError::Io(std::io::Error::new(
+            e.kind(),
+            format!("Failed to add host key to {}: {}", path.display(), e),
+        ))
+    })
+}
+
+/// How known_hosts names a host: bare on port 22, `[host]:port` otherwise
+fn known_hosts_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// An ed25519 public key blob (the key itself doesn't have to be valid)
+    fn ed25519_key(seed: u8) -> Vec<u8> {
+        let mut blob = Vec::new();
+        blob.extend_from_slice(&11u32.to_be_bytes());
+        blob.extend_from_slice(b"ssh-ed25519");
+        blob.extend_from_slice(&32u32.to_be_bytes());
+        blob.extend_from_slice(&[seed; 32]);
+        blob
+    }
+
+    fn check(path: &Path, port: u16, seed: u8, policy: HostKeyPolicy) -> Result<()> {
+        let session = Session::new().unwrap();
+        check_known_hosts(
+            &session,
+            path,
+            "backup.example.com",
+            port,
+            (&ed25519_key(seed), HostKeyType::Ed25519),
+            "SHA256:test",
+            policy,
+        )
+    }
+
+    #[test]
+    fn test_known_hosts_policies() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("ssh/known_hosts");
+
+        // Strict refuses what isn't there, and doesn't add it
+        let err = check(&path, 22, 1, HostKeyPolicy::Strict).unwrap_err();
+        assert!(matches!(err, SyncError::HostKeyUnknown { .. }));
+        assert!(!path.exists());
+
+        // Insecure connects without recording anything
+        check(&path, 22, 1, HostKeyPolicy::Insecure).unwrap();
+        assert!(!path.exists());
+
+        // Accept-new records the key; after that even strict accepts it
+        check(&path, 22, 1, HostKeyPolicy::AcceptNew).unwrap();
+        check(&path, 22, 1, HostKeyPolicy::Strict).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("backup.example.com ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"));
+
+        // A changed key is refused by everything but insecure
+        for policy in [HostKeyPolicy::Strict, HostKeyPolicy::AcceptNew] {
+            let err = check(&path, 22, 2, policy).unwrap_err();
+            assert!(matches!(err, SyncError::HostKeyMismatch { .. }));
+        }
+        check(&path, 22, 2, HostKeyPolicy::Insecure).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+
+        // Other ports are recorded as [host]:port
+        let path = tmp.path().join("known_hosts_2222");
+        check(&path, 2222, 2, HostKeyPolicy::AcceptNew).unwrap();
+        check(&path, 2222, 2, HostKeyPolicy::Strict).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("[backup.example.com]:2222 ssh-ed25519 "));
+    }
+
+    #[test]
+    fn test_check_pins() {
+        let pins = vec![
+            "SHA256:old".to_string(),
+            " SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU ".to_string(),
+        ];
+        check_pins(
+            "h",
+            "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU",
+            &pins,
+        )
+        .unwrap();
+        let err = check_pins("h", "SHA256:other", &pins).unwrap_err();
+        assert!(matches!(err, SyncError::HostKeyMismatch { .. }));
+        assert!(err.to_string().contains("SHA256:other"));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod config;
 pub mod connect;
+pub mod hostkey;
 
 // Re-export for convenience when SSH transport is implemented
 #[allow(unused_imports)]
@@ -0,0 +1,183 @@
+//! Audit log of destructive operations
+//!
+//! With `[audit] log = "..."` in the config file, every delete, overwrite
+//! and permission change the Receiver makes is appended to that file, one
+//! JSON object per line:
+//!
+//! ```text
+//! {"time":"2026-03-01T12:00:00Z","peer":"backup@10.0.0.5","op":"overwrite","path":"a.txt"}
+//! {"time":"2026-03-01T12:00:00Z","peer":"backup@10.0.0.5","op":"chmod","path":"a.txt","from":"644","to":"600"}
+//! ```
+//!
+//! The file is only ever opened for appending, and each record goes out in
+//! a single write, so concurrent sessions don't interleave lines. A record
+//! that can't be written fails the operation's message rather than letting
+//! changes go unrecorded.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// What the Receiver did to an existing entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOp {
+    /// Removed a file, directory or symlink
+    Delete,
+    /// Replaced an existing entry's contents
+    Overwrite,
+    /// Changed permission bits from one mode to another
+    Chmod { from: u32, to: u32 },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    peer: &'a str,
+    op: &'static str,
+    path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+}
+
+/// Append-only log shared by everything that writes on behalf of a peer
+///
+/// Clones share the file; [`AuditLog::with_peer`] gives a clone that
+/// records a different peer.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    file: Arc<Mutex<File>>,
+    peer: Arc<str>,
+}
+
+impl AuditLog {
+    /// Open (or create) the log at `path` for appending
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            peer: "local".into(),
+        })
+    }
+
+    /// The same log, recording `peer` as the other side
+    pub fn with_peer(&self, peer: impl Into<Arc<str>>) -> Self {
+        Self {
+            file: Arc::clone(&self.file),
+            peer: peer.into(),
+        }
+    }
+
+    /// Append one record for `path` (relative to the sync root)
+    pub fn record(&self, op: AuditOp, path: &str) -> Result<()> {
+        let (name, from, to) = match op {
+            AuditOp::Delete => ("delete", None, None),
+            AuditOp::Overwrite => ("overwrite", None, None),
+            AuditOp::Chmod { from, to } => (
+                "chmod",
+                Some(format!("{:o}", from)),
+                Some(format!("{:o}", to)),
+            ),
+        };
+        let record = Record {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            peer: &self.peer,
+            op: name,
+            path,
+            from,
+            to,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line)
+            .context("Failed to write to audit log")?;
+        Ok(())
+    }
+}
+
+/// Who is on the other end of a `sy --server` session
+///
+/// `user@address` from the SSH connection, or just the user when the server
+/// wasn't started by sshd.
+pub fn server_peer() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let address = std::env::var("SSH_CONNECTION")
+        .or_else(|_| std::env::var("SSH_CLIENT"))
+        .ok()
+        .and_then(|conn| conn.split_whitespace().next().map(str::to_string));
+    match address {
+        Some(address) => format!("{}@{}", user, address),
+        None => user,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_audit_log_appends_json_lines() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.log");
+
+        let log = AuditLog::open(&path).unwrap().with_peer("alice@10.0.0.5");
+        log.record(AuditOp::Delete, "old.txt").unwrap();
+        log.record(
+            AuditOp::Chmod {
+                from: 0o644,
+                to: 0o600,
+            },
+            "a.txt",
+        )
+        .unwrap();
+        drop(log);
+
+        // Reopening appends rather than truncating
+        AuditLog::open(&path)
+            .unwrap()
+            .record(AuditOp::Overwrite, "a.txt")
+            .unwrap();
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["op"], "delete");
+        assert_eq!(lines[0]["peer"], "alice@10.0.0.5");
+        assert_eq!(lines[0]["path"], "old.txt");
+        assert!(lines[0].get("from").is_none());
+        assert_eq!(lines[1]["op"], "chmod");
+        assert_eq!(lines[1]["from"], "644");
+        assert_eq!(lines[1]["to"], "600");
+        assert_eq!(lines[2]["op"], "overwrite");
+        assert_eq!(lines[2]["peer"], "local");
+        assert!(chrono::DateTime::parse_from_rfc3339(lines[2]["time"].as_str().unwrap()).is_ok());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
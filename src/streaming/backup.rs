@@ -0,0 +1,144 @@
+//! Keeping the old version of what a sync replaces (`--backup`)
+//!
+//! Like rsync's `--backup`: before the Receiver overwrites or deletes a
+//! regular file, it renames it out of the way. Without `--backup-dir` the
+//! old version stays beside the file, with `--suffix` (`~` by default)
+//! appended to its name; with it, the old version moves to the same relative
+//! path under that directory, which lives inside the destination root.
+//! `--backup-timestamp` adds the time the sync started to every name, so
+//! each run keeps its own copies instead of replacing the last run's.
+//!
+//! A push's server learns all of it from its command line, as with
+//! `--bwlimit`. Scans of the destination leave backups out, so a later
+//! `--delete` doesn't remove them.
+
+use std::path::Path;
+
+/// Suffix of backups kept beside their file, unless `--suffix` says otherwise
+pub const DEFAULT_SUFFIX: &str = "~";
+
+/// strftime format of the `--backup-timestamp` stamp
+const STAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Length of a stamp: `.` then `YYYYMMDD-HHMMSS`
+const STAMP_LEN: usize = 16;
+
+/// Where the Receiver moves the old versions of files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// Directory under the destination root that old versions go to,
+    /// keeping their relative paths; None keeps them beside their file
+    pub dir: Option<String>,
+    /// Appended to the name of each backup
+    pub suffix: String,
+    /// Stamp appended after `suffix` (`--backup-timestamp`)
+    pub stamp: Option<String>,
+}
+
+impl Backup {
+    /// Back up beside each file, or under `dir`, with `suffix` (default
+    /// `~` beside the file, nothing under a directory) and, with
+    /// `timestamp`, the current time
+    pub fn new(dir: Option<String>, suffix: Option<String>, timestamp: bool) -> Self {
+        let suffix = suffix.unwrap_or_else(|| match dir {
+            Some(_) => String::new(),
+            None => DEFAULT_SUFFIX.to_string(),
+        });
+        let stamp = timestamp.then(|| format!(".{}", chrono::Local::now().format(STAMP_FORMAT)));
+        Self { dir, suffix, stamp }
+    }
+
+    /// Where the old version of `path` goes, relative to the root
+    pub fn path_of(&self, path: &str) -> String {
+        let stamp = self.stamp.as_deref().unwrap_or("");
+        match &self.dir {
+            Some(dir) => format!(
+                "{}/{}{}{}",
+                dir.trim_end_matches('/'),
+                path,
+                self.suffix,
+                stamp
+            ),
+            None => format!("{}{}{}", path, self.suffix, stamp),
+        }
+    }
+
+    /// Whether `path` (relative to the root) is a backup, or the backup
+    /// directory, rather than part of the synced tree
+    pub fn is_backup(&self, path: &Path) -> bool {
+        if let Some(dir) = &self.dir {
+            return path.starts_with(dir);
+        }
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        let name = match &self.stamp {
+            Some(_) => match name.len().checked_sub(STAMP_LEN) {
+                Some(end) if name.is_char_boundary(end) && is_stamp(&name[end..]) => &name[..end],
+                _ => return false,
+            },
+            None => name,
+        };
+        name.len() > self.suffix.len() && name.ends_with(&self.suffix)
+    }
+
+    /// Flags that give a remote `sy --server` the same backups
+    pub fn server_args(&self) -> Vec<String> {
+        let mut args = vec!["--backup".to_string()];
+        if let Some(dir) = &self.dir {
+            args.extend(["--backup-dir".into(), dir.clone()]);
+        }
+        let default = if self.dir.is_some() {
+            ""
+        } else {
+            DEFAULT_SUFFIX
+        };
+        if self.suffix != default {
+            args.extend(["--suffix".into(), self.suffix.clone()]);
+        }
+        if self.stamp.is_some() {
+            args.push("--backup-timestamp".into());
+        }
+        args
+    }
+}
+
+/// Whether `s` looks like a stamp from [`Backup::new`]
+fn is_stamp(s: &str) -> bool {
+    s.strip_prefix('.')
+        .is_some_and(|stamp| chrono::NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_paths() {
+        let beside = Backup::new(None, None, false);
+        assert_eq!(beside.path_of("a/b.txt"), "a/b.txt~");
+        assert!(beside.is_backup(Path::new("a/b.txt~")));
+        assert!(!beside.is_backup(Path::new("a/b.txt")));
+        assert!(!beside.is_backup(Path::new("~")));
+        assert_eq!(beside.server_args(), ["--backup"]);
+
+        let under = Backup::new(Some("old/".into()), None, false);
+        assert_eq!(under.path_of("a/b.txt"), "old/a/b.txt");
+        assert!(under.is_backup(Path::new("old/a/b.txt")));
+        assert!(!under.is_backup(Path::new("older/a")));
+        assert_eq!(under.server_args(), ["--backup", "--backup-dir", "old/"]);
+
+        let stamped = Backup::new(None, Some(".bak".into()), true);
+        let path = stamped.path_of("b.txt");
+        assert!(path.starts_with("b.txt.bak."));
+        assert_eq!(path.len(), "b.txt.bak".len() + STAMP_LEN);
+        assert!(stamped.is_backup(Path::new(&path)));
+        assert!(stamped.is_backup(Path::new("b.txt.bak.20240101-093000")));
+        assert!(!stamped.is_backup(Path::new("b.txt.bak")));
+        assert!(!stamped.is_backup(Path::new("b.txt.bak.2024010-0930000")));
+        assert_eq!(
+            stamped.server_args(),
+            ["--backup", "--suffix", ".bak", "--backup-timestamp"]
+        );
+    }
+}
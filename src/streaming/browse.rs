@@ -0,0 +1,159 @@
+//! Client side of a browse session (`sy mount`)
+//!
+//! [`RemoteTree`] opens a session with `HelloFlags::BROWSE` and turns LIST
+//! and READ requests into directory listings and byte ranges. Requests go
+//! one at a time, each answered before the next is sent.
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::error::SyncError;
+use crate::streaming::protocol::{self as v2, FileEntry, Hello, HelloFlags, MessageType};
+
+/// A tree served by `sy --server`, read over one browse session
+pub struct RemoteTree<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R, W> RemoteTree<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    /// Start a browse session with the server at the other end of
+    /// `reader`/`writer`
+    pub async fn open(mut reader: R, mut writer: W) -> Result<Self> {
+        let hello = Hello::new(HelloFlags::BROWSE, "");
+        v2::write_frame(&mut writer, &hello.encode()).await?;
+        writer.flush().await?;
+
+        let (msg_type, payload) = v2::read_frame(&mut reader).await?;
+        if msg_type != MessageType::Hello {
+            return Err(unexpected(msg_type, payload));
+        }
+
+        Ok(Self { reader, writer })
+    }
+
+    /// Entries of directory `path` (relative to the root, "" for the root
+    /// itself), sorted by name; their paths are relative to the root too
+    pub async fn list(&mut self, path: &str) -> Result<Vec<FileEntry>> {
+        let list = v2::List {
+            path: path.to_string(),
+        };
+        self.send(&list.encode()).await?;
+
+        let mut entries = Vec::new();
+        loop {
+            let (msg_type, payload) = v2::read_frame(&mut self.reader).await?;
+            match msg_type {
+                MessageType::FileEntry => entries.push(FileEntry::decode(payload)?),
+                MessageType::FileEnd => return Ok(entries),
+                _ => return Err(unexpected(msg_type, payload)),
+            }
+        }
+    }
+
+    /// Up to `len` bytes of file `path` from `offset`; fewer at the end of
+    /// the file, and at most `Read::MAX_LEN`
+    pub async fn read(&mut self, path: &str, offset: u64, len: u32) -> Result<Bytes> {
+        let read = v2::Read {
+            path: path.to_string(),
+            offset,
+            len,
+        };
+        self.send(&read.encode()).await?;
+
+        let (msg_type, payload) = v2::read_frame(&mut self.reader).await?;
+        match msg_type {
+            MessageType::Data => Ok(v2::Data::decode(payload)?.data),
+            _ => Err(unexpected(msg_type, payload)),
+        }
+    }
+
+    /// End the session
+    pub async fn close(mut self) -> Result<()> {
+        self.writer.shutdown().await?;
+        Ok(())
+    }
+
+    async fn send(&mut self, frame: &Bytes) -> Result<()> {
+        v2::write_frame(&mut self.writer, frame).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// The error a reply other than the one expected stands for
+fn unexpected(msg_type: MessageType, payload: Bytes) -> anyhow::Error {
+    match msg_type {
+        MessageType::Error => match v2::Error::decode(payload) {
+            Ok(err) => SyncError::remote(err.code, err.path, err.message).into(),
+            Err(e) => e,
+        },
+        MessageType::Fatal => match v2::Fatal::decode(payload) {
+            Ok(fatal) => SyncError::remote(fatal.code, "", fatal.message).into(),
+            Err(e) => e,
+        },
+        _ => SyncError::Protocol(format!("Unexpected {:?} in browse session", msg_type)).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::filter::FilterEngine;
+    use crate::server::{serve, ServerConfig};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_browse_lists_and_reads() {
+        let remote = TempDir::new().unwrap();
+        fs::create_dir_all(remote.path().join("photos/2024")).unwrap();
+        fs::write(remote.path().join("photos/2024/a.jpg"), "0123456789").unwrap();
+        fs::write(remote.path().join("notes.txt"), "hello").unwrap();
+        fs::write(remote.path().join("server.key"), "secret").unwrap();
+
+        let mut exclude = FilterEngine::new();
+        exclude.add_rule("*.key").unwrap();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (server_read, server_write) = tokio::io::split(server);
+        let server_task = tokio::spawn(serve(
+            server_read,
+            server_write,
+            ServerConfig::new(remote.path()).with_exclude(exclude),
+        ));
+        let (client_read, client_write) = tokio::io::split(client);
+        let mut tree = RemoteTree::open(client_read, client_write).await.unwrap();
+
+        let root = tree.list("").await.unwrap();
+        let names: Vec<&str> = root.iter().map(|e| &*e.path).collect();
+        assert_eq!(names, ["notes.txt", "photos"]);
+        assert!(root[1].is_dir());
+
+        let photos = tree.list("photos/2024").await.unwrap();
+        assert_eq!(&*photos[0].path, "photos/2024/a.jpg");
+        assert_eq!(photos[0].size, 10);
+
+        let data = tree.read("photos/2024/a.jpg", 4, 100).await.unwrap();
+        assert_eq!(&data[..], b"456789");
+        assert!(tree
+            .read("photos/2024/a.jpg", 20, 4)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Excluded, missing and escaping paths all look absent
+        for path in ["server.key", "missing.txt", "../etc/passwd"] {
+            let err = SyncError::from(tree.read(path, 0, 4).await.unwrap_err());
+            assert_eq!(err.error_code(), ErrorCode::NotFound, "{}", path);
+        }
+
+        tree.close().await.unwrap();
+        server_task.await.unwrap().unwrap();
+    }
+}
@@ -3,10 +3,15 @@
 //! Three-task pipeline: Generator -> Sender -> Receiver
 //! Using bounded channels for backpressure.
 
-use crate::streaming::protocol::BlockChecksum;
+use crate::streaming::protocol::{
+    BlockChecksum, ErrorCode, Ownership, Progress, SetAttrFlags, Times, XattrEntry,
+};
 use bytes::Bytes;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
 /// Channel size for Generator -> Sender (file entries)
@@ -24,6 +29,9 @@ pub const DELTA_CHUNK_SIZE: usize = 16 * 1024 * 1024;
 /// Minimum file size for delta sync
 pub const DELTA_MIN_SIZE: u64 = 64 * 1024; // 64KB
 
+/// How often a server reports PROGRESS while files are in flight
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
 // =============================================================================
 // FileJob: Generator -> Sender
 // =============================================================================
@@ -41,18 +49,48 @@ pub struct FileJob {
     /// Modification time (Unix timestamp)
     pub mtime: i64,
 
+    /// Nanoseconds past `mtime`
+    pub mtime_nsec: u32,
+
     /// File mode/permissions
     pub mode: u32,
 
     /// Inode number (for hard link detection)
     pub inode: u64,
 
+    /// Whether the source file has holes
+    pub sparse: bool,
+
+    /// FILE_ATTRIBUTE bits (Windows sources only)
+    pub win_attrs: Option<u32>,
+
+    /// BSD file flags (macOS sources, under `--preserve-flags`)
+    pub bsd_flags: Option<u32>,
+
+    /// Owner and group, already mapped (under `-o`/`-g`)
+    pub owner: Option<Ownership>,
+
+    /// Access and creation times (under `--times=all`)
+    pub times: Option<Times>,
+
+    /// Whether the destination already has a file at this path
+    pub exists: bool,
+
     /// Whether this file needs delta transfer
     pub need_delta: bool,
 
     /// Block checksums from destination (for delta computation)
     /// Only present if need_delta is true and file exists on dest
     pub checksums: Option<DeltaInfo>,
+
+    /// Block checksums of the destination's partial temp file, left by an
+    /// interrupted transfer; the Sender continues after the prefix that
+    /// still matches
+    pub resume: Option<DeltaInfo>,
+
+    /// An earlier path of this run with the same inode (`-H`): the
+    /// destination links to it instead of receiving the content again
+    pub link_target: Option<Arc<PathBuf>>,
 }
 
 /// Delta information from destination file
@@ -105,16 +143,47 @@ pub enum GeneratorMessage {
     File(FileJob),
 
     /// A directory that needs to be created
-    Mkdir { path: Arc<PathBuf>, mode: u32 },
+    Mkdir {
+        path: Arc<PathBuf>,
+        mode: u32,
+        owner: Option<Ownership>,
+    },
 
     /// A symlink that needs to be created
-    Symlink { path: Arc<PathBuf>, target: String },
+    Symlink {
+        path: Arc<PathBuf>,
+        target: String,
+        owner: Option<Ownership>,
+    },
+
+    /// Extended attributes of the file or directory just sent
+    Xattr {
+        path: Arc<PathBuf>,
+        entries: Vec<XattrEntry>,
+    },
+
+    /// POSIX ACL of the file or directory just sent, in text form
+    Acl { path: Arc<PathBuf>, text: String },
+
+    /// A file whose content is up to date but whose `flags` metadata isn't
+    SetAttr {
+        path: Arc<PathBuf>,
+        mode: u32,
+        mtime: i64,
+        mtime_nsec: u32,
+        flags: SetAttrFlags,
+    },
 
     /// A file or directory that needs to be deleted
     Delete { path: Arc<PathBuf>, is_dir: bool },
 
-    /// End of file list - no more files coming
-    FileEnd { total_files: u64, total_bytes: u64 },
+    /// End of file list - no more files coming; `skipped` files were
+    /// already up to date
+    FileEnd {
+        total_files: u64,
+        total_bytes: u64,
+        skipped: u64,
+    },
 
     /// End of deletes
     DeleteEnd { count: u64 },
@@ -157,6 +226,9 @@ pub struct DestFileState {
     /// Modification time
     pub mtime: i64,
 
+    /// Nanoseconds past `mtime`
+    pub mtime_nsec: u32,
+
     /// File mode
     pub mode: u32,
 
@@ -167,47 +239,7 @@ pub struct DestFileState {
     pub delta_info: Option<DeltaInfo>,
 }
 
-/// Destination file index, built during Initial Exchange
-#[derive(Debug, Default)]
-pub struct DestIndex {
-    /// Map of path -> dest state
-    files: std::collections::HashMap<String, DestFileState>,
-}
-
-impl DestIndex {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn insert(&mut self, path: String, state: DestFileState) {
-        self.files.insert(path, state);
-    }
-
-    pub fn get(&self, path: &str) -> Option<&DestFileState> {
-        self.files.get(path)
-    }
-
-    pub fn remove(&mut self, path: &str) -> Option<DestFileState> {
-        self.files.remove(path)
-    }
-
-    pub fn contains(&self, path: &str) -> bool {
-        self.files.contains_key(path)
-    }
-
-    /// Get all remaining paths (for delete detection)
-    pub fn remaining_paths(&self) -> impl Iterator<Item = (&String, &DestFileState)> {
-        self.files.iter()
-    }
-
-    pub fn len(&self) -> usize {
-        self.files.len()
-    }
-
-    pub fn is_empty(&self) -> bool {
-        self.files.is_empty()
-    }
-}
+pub use crate::streaming::dest_index::DestIndex;
 
 // =============================================================================
 // Sync statistics
@@ -242,12 +274,219 @@ pub struct SyncStats {
 
     /// Hard links created
     pub hardlinks_created: u64,
+
+    /// Old versions moved aside before being overwritten or deleted
+    /// (`--backup`)
+    pub backed_up: u64,
+
+    /// Files discarded because what was written didn't hash to the
+    /// source's hash (`--checksum-verify`)
+    pub checksum_mismatches: u64,
+
+    /// The sync was cancelled; these stats cover what finished before that
+    pub cancelled: bool,
+
+    /// Every entry that failed, on either side, in the order reported
+    pub errors: Vec<crate::streaming::protocol::Error>,
+
+    /// Wall-clock time of the whole sync
+    pub duration: Duration,
+
+    /// Where that time went
+    pub phases: PhaseTimes,
+
+    /// What the files that made it were, and how many needed nothing
+    pub counts: FileCounts,
+
+    /// What a dry run would have written and removed (`--dry-run`)
+    pub would: WouldBytes,
+
+    /// Files sent again after a transient error (`--retries`), with how
+    /// many times each was
+    pub retries: BTreeMap<String, u32>,
+}
+
+/// How the files of a sync break down
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileCounts {
+    /// Files that didn't exist on the destination
+    pub created: u64,
+    /// Existing files whose content changed
+    pub updated: u64,
+    /// Existing files whose content was already right: only their
+    /// metadata (mtime, mode) was applied
+    pub touched: u64,
+    /// Files the Generator left alone because size and mtime matched
+    pub skipped: u64,
+}
+
+/// Bytes a dry run found it would write or remove
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBytes {
+    /// Size of the files that would be created
+    pub add: u64,
+    /// Size of the existing files that would be rewritten
+    pub change: u64,
+    /// Size of the files that would be deleted
+    pub delete: u64,
 }
 
 impl SyncStats {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// The files that failed with a transient error, each once
+    pub fn transient_failures(&self) -> Vec<String> {
+        let mut paths: Vec<String> = Vec::new();
+        for error in &self.errors {
+            let transient = ErrorCode::from_u16(error.code).is_some_and(ErrorCode::is_transient);
+            if transient && !paths.contains(&error.path) {
+                paths.push(error.path.clone());
+            }
+        }
+        paths
+    }
+
+    /// Fold in `again`, a run that sent `retried` (our transient failures)
+    /// once more: their errors give way to whatever it reported
+    pub fn absorb_retry(&mut self, retried: &[String], again: SyncStats) {
+        self.errors.retain(|error| !retried.contains(&error.path));
+        self.errors.extend(again.errors);
+        self.files_err = self.files_err.saturating_sub(retried.len() as u64) + again.files_err;
+        self.files_ok += again.files_ok;
+        self.bytes_transferred += again.bytes_transferred;
+        self.delta_files += again.delta_files;
+        self.delta_bytes_saved += again.delta_bytes_saved;
+        self.dirs_created += again.dirs_created;
+        self.symlinks_created += again.symlinks_created;
+        self.deleted += again.deleted;
+        self.hardlinks_created += again.hardlinks_created;
+        self.backed_up += again.backed_up;
+        self.checksum_mismatches += again.checksum_mismatches;
+        self.cancelled = again.cancelled;
+        self.duration += again.duration;
+        // What the retry found up to date was counted the first time
+        self.counts.created += again.counts.created;
+        self.counts.updated += again.counts.updated;
+        self.counts.touched += again.counts.touched;
+        for path in retried {
+            *self.retries.entry(path.clone()).or_default() += 1;
+        }
+    }
+}
+
+/// Wall-clock time spent in each phase of a sync
+///
+/// The phases follow each other: the Initial Exchange, then the Generator
+/// walks the source, then files are sent until FILE_END, then deletes.
+/// Each end times what it sees; `merge` combines the two views.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTimes {
+    /// Walking the source tree
+    pub scan: Duration,
+    /// Scanning and checksumming the destination and sending it over
+    pub exchange: Duration,
+    /// Sending files, up to FILE_END
+    pub transfer: Duration,
+    /// From FILE_END until the last delete is applied
+    pub delete: Duration,
+}
+
+impl PhaseTimes {
+    /// Take the other end's view into account: a phase took as long as
+    /// the side that waited longest for it saw
+    pub fn merge(&mut self, other: &PhaseTimes) {
+        self.scan = self.scan.max(other.scan);
+        self.exchange = self.exchange.max(other.exchange);
+        self.transfer = self.transfer.max(other.transfer);
+        self.delete = self.delete.max(other.delete);
+    }
+}
+
+/// Stopwatch for the boundaries between phases
+pub struct PhaseClock {
+    start: Instant,
+    last: Instant,
+}
+
+impl PhaseClock {
+    pub fn start() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+        }
+    }
+
+    /// Time since the previous boundary (or the start), which becomes the
+    /// new boundary
+    pub fn lap(&mut self) -> Duration {
+        let now = Instant::now();
+        let lap = now - self.last;
+        self.last = now;
+        lap
+    }
+
+    /// Time since the start
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Files and bytes of a transfer so far, counted by the tasks that see
+/// them: the Generator adds the files it finds, the Sender what it sent
+#[derive(Debug, Default)]
+pub struct ProgressCounter {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    files_total: AtomicU64,
+    bytes_total: AtomicU64,
+}
+
+impl ProgressCounter {
+    /// One more file to send, of `size` bytes (0 for a hard link)
+    pub fn found(&self, size: u64) {
+        self.files_total.fetch_add(1, Ordering::Relaxed);
+        self.bytes_total.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// One more file sent in full, as a delta or as an error
+    pub fn file_done(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `len` more bytes of DATA on the wire
+    pub fn sent(&self, len: u64) {
+        self.bytes.fetch_add(len, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Progress {
+        Progress {
+            files: self.files.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            files_total: self.files_total.load(Ordering::Relaxed),
+            bytes_total: self.bytes_total.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Error a pipeline task returns when it stops because its
+/// `CancellationToken` was cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("sync cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Whether `err` is (or wraps) a `Cancelled`
+pub fn is_cancelled(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<Cancelled>())
 }
 
 // =============================================================================
@@ -258,31 +497,6 @@ impl SyncStats {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_dest_index() {
-        let mut index = DestIndex::new();
-
-        index.insert(
-            "file.txt".to_string(),
-            DestFileState {
-                size: 1024,
-                mtime: 1234567890,
-                mode: 0o644,
-                is_dir: false,
-                delta_info: None,
-            },
-        );
-
-        assert!(index.contains("file.txt"));
-        assert!(!index.contains("other.txt"));
-
-        let state = index.get("file.txt").unwrap();
-        assert_eq!(state.size, 1024);
-
-        index.remove("file.txt");
-        assert!(!index.contains("file.txt"));
-    }
-
     #[tokio::test]
     async fn test_file_job_channel() {
         let (tx, mut rx) = file_job_channel();
@@ -291,10 +505,19 @@ mod tests {
             path: Arc::new(PathBuf::from("test.txt")),
             size: 100,
             mtime: 0,
+            mtime_nsec: 0,
             mode: 0o644,
             inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: false,
             need_delta: false,
             checksums: None,
+            resume: None,
+            link_target: None,
         });
 
         tx.send(job).await.unwrap();
@@ -321,4 +544,41 @@ mod tests {
         assert_eq!(stats.files_ok, 100);
         assert_eq!(stats.delta_files, 10);
     }
+
+    #[test]
+    fn test_absorb_retry() {
+        let error = |path: &str, code: ErrorCode| crate::streaming::protocol::Error {
+            path: path.to_string(),
+            code: code as u16,
+            message: "failed".to_string(),
+        };
+        let mut stats = SyncStats {
+            files_ok: 5,
+            files_err: 3,
+            errors: vec![
+                error("flaky.bin", ErrorCode::IoError),
+                error("flaky.bin", ErrorCode::IoError),
+                error("denied.txt", ErrorCode::PermissionDenied),
+                error("lost.bin", ErrorCode::IoError),
+            ],
+            ..Default::default()
+        };
+        let failed = stats.transient_failures();
+        assert_eq!(failed, vec!["flaky.bin", "lost.bin"]);
+
+        let again = SyncStats {
+            files_ok: 1,
+            files_err: 1,
+            bytes_transferred: 10,
+            errors: vec![error("lost.bin", ErrorCode::IoError)],
+            ..Default::default()
+        };
+        stats.absorb_retry(&failed, again);
+        assert_eq!((stats.files_ok, stats.files_err), (6, 2));
+        assert_eq!(stats.bytes_transferred, 10);
+        let paths: Vec<_> = stats.errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["denied.txt", "lost.bin"]);
+        assert_eq!(stats.retries.get("flaky.bin"), Some(&1));
+        assert_eq!(stats.transient_failures(), vec!["lost.bin"]);
+    }
 }
@@ -0,0 +1,428 @@
+//! Compact destination index for the Generator.
+//!
+//! The Initial Exchange can describe tens of millions of destination files,
+//! so the index avoids a heap allocation per entry: paths are interned into
+//! one arena string and looked up by their xxh3 hash, and per-file metadata is
+//! a small fixed-size record.
+//!
+//! Block checksums dominate memory for large files. They're kept in RAM up to
+//! a configurable cap; past that, further checksum arrays are spilled to a
+//! temporary fjall store and loaded back when the Generator reaches the file.
+
+use crate::streaming::channel::{DeltaInfo, DestFileState};
+use crate::streaming::protocol::BlockChecksum;
+use anyhow::{Context, Result};
+use bytes::{Buf, BufMut};
+use fjall::{Config, Keyspace, PartitionHandle};
+use std::collections::hash_map::{self, HashMap};
+use std::mem;
+use tempfile::TempDir;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Default cap on checksum memory held by a `DestIndex` (512MB)
+pub const DEFAULT_INDEX_MEMORY: u64 = 512 * 1024 * 1024;
+
+/// Partition name for spilled checksum arrays
+const SPILL_PARTITION: &str = "checksums";
+
+/// Where an entry's block checksums live
+#[derive(Debug)]
+enum Checksums {
+    None,
+    Resident(Box<[BlockChecksum]>),
+    Spilled,
+}
+
+/// Fixed-size record for one destination entry
+#[derive(Debug)]
+struct Entry {
+    /// Byte range of the path in the arena
+    path_start: usize,
+    path_len: u32,
+    mode: u32,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: u32,
+    block_size: u32,
+    is_dir: bool,
+    removed: bool,
+    checksums: Checksums,
+}
+
+/// Temporary on-disk store for checksum arrays over the memory cap
+struct SpillStore {
+    // Fields drop in order: partition, keyspace, then the directory itself
+    partition: PartitionHandle,
+    _keyspace: Keyspace,
+    _dir: TempDir,
+}
+
+impl SpillStore {
+    fn open() -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("sy-dest-index")
+            .tempdir()
+            .context("Failed to create checksum spill directory")?;
+        let keyspace = Config::new(dir.path()).temporary(true).open()?;
+        let partition = keyspace.open_partition(SPILL_PARTITION, Default::default())?;
+        Ok(Self {
+            partition,
+            _keyspace: keyspace,
+            _dir: dir,
+        })
+    }
+
+    fn put(&self, id: u32, checksums: &[BlockChecksum]) -> Result<()> {
+        let mut buf = Vec::with_capacity(checksums.len() * BlockChecksum::WIDE_SIZE);
+        for cs in checksums {
+            buf.put_u64(cs.offset);
+            buf.put_u32(cs.weak);
+            buf.put_u128(cs.strong);
+        }
+        self.partition.insert(id.to_be_bytes(), buf)?;
+        Ok(())
+    }
+
+    fn take(&self, id: u32) -> Result<Vec<BlockChecksum>> {
+        let checksums = self.get(id)?;
+        self.partition.remove(id.to_be_bytes())?;
+        Ok(checksums)
+    }
+
+    fn get(&self, id: u32) -> Result<Vec<BlockChecksum>> {
+        let value = self
+            .partition
+            .get(id.to_be_bytes())?
+            .context("Spilled checksums missing from dest index")?;
+        let mut buf = &value[..];
+        let mut checksums = Vec::with_capacity(buf.len() / BlockChecksum::WIDE_SIZE);
+        while buf.remaining() >= BlockChecksum::WIDE_SIZE {
+            checksums.push(BlockChecksum {
+                offset: buf.get_u64(),
+                weak: buf.get_u32(),
+                strong: buf.get_u128(),
+            });
+        }
+        Ok(checksums)
+    }
+}
+
+/// Destination file index, built during Initial Exchange
+pub struct DestIndex {
+    /// All paths, concatenated
+    arena: String,
+    entries: Vec<Entry>,
+    /// xxh3 of path -> entry id
+    by_hash: HashMap<u64, u32>,
+    /// Entries whose path hash collided with a different path (rare)
+    collisions: HashMap<String, u32>,
+    /// Entries not yet removed
+    live: usize,
+    /// Bytes of checksums held in RAM
+    resident_bytes: u64,
+    memory_limit: u64,
+    spill: Option<SpillStore>,
+    spilled: usize,
+}
+
+impl Default for DestIndex {
+    fn default() -> Self {
+        Self::with_memory_limit(DEFAULT_INDEX_MEMORY)
+    }
+}
+
+impl std::fmt::Debug for DestIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DestIndex")
+            .field("len", &self.live)
+            .field("resident_bytes", &self.resident_bytes)
+            .field("spilled", &self.spilled)
+            .finish()
+    }
+}
+
+impl DestIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index that spills checksums to disk once `bytes` of them are in RAM
+    pub fn with_memory_limit(bytes: u64) -> Self {
+        Self {
+            arena: String::new(),
+            entries: Vec::new(),
+            by_hash: HashMap::new(),
+            collisions: HashMap::new(),
+            live: 0,
+            resident_bytes: 0,
+            memory_limit: bytes,
+            spill: None,
+            spilled: 0,
+        }
+    }
+
+    pub fn insert(&mut self, path: String, state: DestFileState) -> Result<()> {
+        // Replacing an entry: drop the old one first so its checksums are released
+        if self.contains(&path) {
+            self.remove(&path)?;
+        }
+
+        let id = u32::try_from(self.entries.len()).context("Too many destination entries")?;
+        let (block_size, checksums) = match state.delta_info {
+            Some(info) => (info.block_size, self.store_checksums(id, info.checksums)?),
+            None => (0, Checksums::None),
+        };
+
+        let path_start = self.arena.len();
+        self.arena.push_str(&path);
+        self.entries.push(Entry {
+            path_start,
+            path_len: path.len() as u32,
+            mode: state.mode,
+            size: state.size,
+            mtime: state.mtime,
+            mtime_nsec: state.mtime_nsec,
+            block_size,
+            is_dir: state.is_dir,
+            removed: false,
+            checksums,
+        });
+
+        match self.by_hash.entry(xxh3_64(path.as_bytes())) {
+            hash_map::Entry::Occupied(_) => {
+                self.collisions.insert(path, id);
+            }
+            hash_map::Entry::Vacant(slot) => {
+                slot.insert(id);
+            }
+        }
+        self.live += 1;
+        Ok(())
+    }
+
+    /// Look up an entry, loading spilled checksums from disk
+    pub fn get(&self, path: &str) -> Result<Option<DestFileState>> {
+        let Some(id) = self.find(path) else {
+            return Ok(None);
+        };
+        let entry = &self.entries[id as usize];
+        let checksums = match &entry.checksums {
+            Checksums::None => None,
+            Checksums::Resident(cs) => Some(cs.to_vec()),
+            Checksums::Spilled => Some(self.spill_store()?.get(id)?),
+        };
+        Ok(Some(Self::state(entry, checksums)))
+    }
+
+    /// Remove an entry, handing back its state (with checksums)
+    pub fn remove(&mut self, path: &str) -> Result<Option<DestFileState>> {
+        let Some(id) = self.find(path) else {
+            return Ok(None);
+        };
+
+        let hash = xxh3_64(path.as_bytes());
+        if self.by_hash.get(&hash) == Some(&id) {
+            self.by_hash.remove(&hash);
+        } else {
+            self.collisions.remove(path);
+        }
+        self.live -= 1;
+
+        let entry = &mut self.entries[id as usize];
+        entry.removed = true;
+        let checksums = match mem::replace(&mut entry.checksums, Checksums::None) {
+            Checksums::None => None,
+            Checksums::Resident(cs) => {
+                self.resident_bytes -= checksum_bytes(cs.len());
+                Some(cs.into_vec())
+            }
+            Checksums::Spilled => {
+                self.spilled -= 1;
+                Some(self.spill_store()?.take(id)?)
+            }
+        };
+        Ok(Some(Self::state(&self.entries[id as usize], checksums)))
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.find(path).is_some()
+    }
+
+    /// Remaining paths and whether each is a directory (for delete detection)
+    pub fn remaining_paths(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.entries
+            .iter()
+            .filter(|e| !e.removed)
+            .map(|e| (self.path(e), e.is_dir))
+    }
+
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    /// Number of entries whose checksums are currently on disk
+    pub fn spilled(&self) -> usize {
+        self.spilled
+    }
+
+    /// Bytes of checksums currently held in RAM
+    pub fn resident_bytes(&self) -> u64 {
+        self.resident_bytes
+    }
+
+    fn find(&self, path: &str) -> Option<u32> {
+        let hash = xxh3_64(path.as_bytes());
+        if let Some(&id) = self.by_hash.get(&hash) {
+            if self.path(&self.entries[id as usize]) == path {
+                return Some(id);
+            }
+        }
+        self.collisions.get(path).copied()
+    }
+
+    fn path(&self, entry: &Entry) -> &str {
+        &self.arena[entry.path_start..entry.path_start + entry.path_len as usize]
+    }
+
+    fn store_checksums(&mut self, id: u32, checksums: Vec<BlockChecksum>) -> Result<Checksums> {
+        let bytes = checksum_bytes(checksums.len());
+        if self.resident_bytes + bytes <= self.memory_limit {
+            self.resident_bytes += bytes;
+            return Ok(Checksums::Resident(checksums.into_boxed_slice()));
+        }
+
+        if self.spill.is_none() {
+            tracing::debug!(
+                "Dest index checksums exceed {} bytes, spilling to disk",
+                self.memory_limit
+            );
+            self.spill = Some(SpillStore::open()?);
+        }
+        self.spill_store()?.put(id, &checksums)?;
+        self.spilled += 1;
+        Ok(Checksums::Spilled)
+    }
+
+    fn spill_store(&self) -> Result<&SpillStore> {
+        self.spill
+            .as_ref()
+            .context("Dest index has no checksum spill store")
+    }
+
+    fn state(entry: &Entry, checksums: Option<Vec<BlockChecksum>>) -> DestFileState {
+        DestFileState {
+            size: entry.size,
+            mtime: entry.mtime,
+            mtime_nsec: entry.mtime_nsec,
+            mode: entry.mode,
+            is_dir: entry.is_dir,
+            delta_info: checksums.map(|checksums| DeltaInfo {
+                block_size: entry.block_size,
+                file_size: entry.size,
+                checksums,
+            }),
+        }
+    }
+}
+
+/// In-memory size of `count` block checksums
+fn checksum_bytes(count: usize) -> u64 {
+    (count * mem::size_of::<BlockChecksum>()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(size: u64, blocks: u64) -> DestFileState {
+        DestFileState {
+            size,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            is_dir: false,
+            delta_info: (blocks > 0).then(|| DeltaInfo {
+                block_size: 4096,
+                file_size: size,
+                checksums: (0..blocks)
+                    .map(|i| BlockChecksum {
+                        offset: i * 4096,
+                        weak: i as u32,
+                        strong: i as u128 * 31,
+                    })
+                    .collect(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_dest_index() {
+        let mut index = DestIndex::new();
+        index
+            .insert("file.txt".to_string(), state(1024, 0))
+            .unwrap();
+
+        assert!(index.contains("file.txt"));
+        assert!(!index.contains("other.txt"));
+
+        let state = index.get("file.txt").unwrap().unwrap();
+        assert_eq!(state.size, 1024);
+
+        index.remove("file.txt").unwrap();
+        assert!(!index.contains("file.txt"));
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn test_dest_index_spills_over_memory_limit() {
+        // Room for one 100-block array in RAM; the rest go to disk
+        let mut index = DestIndex::with_memory_limit(checksum_bytes(150));
+        for i in 0..4 {
+            index
+                .insert(format!("dir/file{i}.bin"), state(409600, 100))
+                .unwrap();
+        }
+        index.insert("small.txt".to_string(), state(10, 0)).unwrap();
+
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.spilled(), 3);
+        assert!(index.resident_bytes() <= checksum_bytes(150));
+
+        // Spilled checksums come back intact, and stay on disk for `get`
+        let got = index.get("dir/file3.bin").unwrap().unwrap();
+        assert_eq!(got.delta_info.unwrap().checksums[7].strong, 7 * 31);
+        assert_eq!(index.spilled(), 3);
+
+        for i in 0..4 {
+            let removed = index.remove(&format!("dir/file{i}.bin")).unwrap().unwrap();
+            let info = removed.delta_info.unwrap();
+            assert_eq!(info.checksums.len(), 100);
+            assert_eq!(info.checksums[99].offset, 99 * 4096);
+            assert_eq!(info.file_size, 409600);
+        }
+        assert_eq!(index.spilled(), 0);
+        assert_eq!(index.resident_bytes(), 0);
+
+        let remaining: Vec<_> = index.remaining_paths().collect();
+        assert_eq!(remaining, vec![("small.txt", false)]);
+        assert!(index.remove("dir/file0.bin").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_dest_index_replace_entry() {
+        let mut index = DestIndex::new();
+        index.insert("a".to_string(), state(100, 10)).unwrap();
+        index.insert("a".to_string(), state(200, 0)).unwrap();
+
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.resident_bytes(), 0);
+        let got = index.get("a").unwrap().unwrap();
+        assert_eq!(got.size, 200);
+        assert!(got.delta_info.is_none());
+        assert_eq!(index.remaining_paths().count(), 1);
+    }
+}
@@ -0,0 +1,284 @@
+//! Destination manifest kept between pushes (`--dest-manifest`)
+//!
+//! The receiving end saves the DEST_FILE_ENTRYs of its last Initial Exchange
+//! in `<root>/.sy-manifest`. On the next scan, files whose size and mtime
+//! haven't changed reuse their block checksums instead of being read again.
+//! The client caches the manifest too, and while its copy has the id of the
+//! one the server saved, the server only sends the entries that changed
+//! since (plus REMOVED markers for paths that are gone).
+//!
+//! A file rewritten within the same second at the same size keeps its old
+//! checksums; that is the same trade-off size + mtime comparison makes.
+
+use crate::streaming::protocol::{next_frame, DestFileEntry, DestFileFlags, MessageType};
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Manifest file in the destination root; never synced or deleted
+pub const MANIFEST_FILE: &str = ".sy-manifest";
+
+const MAGIC: &[u8; 8] = b"SYMANIF1";
+
+/// DEST_FILE_ENTRYs of a tree, under an id that changes with the tree
+#[derive(Debug, Clone, Default)]
+pub struct DestManifest {
+    id: u128,
+    entries: HashMap<String, DestFileEntry>,
+}
+
+impl DestManifest {
+    /// Manifest of `entries`, saved by the server as `id`
+    pub fn from_entries(entries: impl IntoIterator<Item = DestFileEntry>, id: u128) -> Self {
+        Self {
+            id,
+            entries: entries
+                .into_iter()
+                .map(|entry| (entry.path.clone(), entry))
+                .collect(),
+        }
+    }
+
+    /// Read a saved manifest; a missing or unreadable one is None
+    pub fn load(path: &Path) -> Option<Self> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                tracing::warn!("Ignoring manifest {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        match Self::decode(Bytes::from(data)) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                tracing::warn!("Ignoring manifest {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn decode(mut data: Bytes) -> Result<Self> {
+        if data.len() < MAGIC.len() + 16 || &data[..MAGIC.len()] != MAGIC {
+            bail!("not a sy manifest");
+        }
+        data.advance(MAGIC.len());
+        let id = data.get_u128();
+        let mut entries = HashMap::new();
+        while let Some((msg_type, payload)) = next_frame(&mut data)? {
+            if msg_type != MessageType::DestFileEntry {
+                bail!("unexpected {:?} in manifest", msg_type);
+            }
+            let entry = DestFileEntry::decode(payload)?;
+            entries.insert(entry.path.clone(), entry);
+        }
+        Ok(Self { id, entries })
+    }
+
+    /// Write the manifest to `path`, replacing any previous one atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut buf = BytesMut::new();
+        buf.put_slice(MAGIC);
+        buf.put_u128(self.id);
+        for entry in self.entries.values() {
+            entry.encode_into(&mut buf);
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        std::fs::write(&tmp, &buf)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn id(&self) -> u128 {
+        self.id
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The saved entry for `path` if the file still has the same size,
+    /// mtime (seconds and nanoseconds) and type
+    pub fn unchanged(
+        &self,
+        path: &str,
+        size: u64,
+        mtime: (i64, u32),
+        is_dir: bool,
+    ) -> Option<&DestFileEntry> {
+        self.entries.get(path).filter(|entry| {
+            entry.size == size
+                && (entry.mtime, entry.mtime_nsec) == mtime
+                && entry.flags.contains(DestFileFlags::DIR) == is_dir
+        })
+    }
+
+    /// Whether `entry` is what the manifest already says about its path
+    pub fn contains(&self, entry: &DestFileEntry) -> bool {
+        self.entries.get(&entry.path).is_some_and(|saved| {
+            saved.size == entry.size
+                && saved.mtime == entry.mtime
+                && saved.mtime_nsec == entry.mtime_nsec
+                && saved.mode == entry.mode
+                && saved.flags.difference(DestFileFlags::CHECKSUMS_ZSTD)
+                    == entry.flags.difference(DestFileFlags::CHECKSUMS_ZSTD)
+        })
+    }
+
+    pub fn insert(&mut self, entry: DestFileEntry) {
+        self.entries.insert(entry.path.clone(), entry);
+    }
+
+    /// REMOVED markers for the paths of this manifest missing from `next`
+    pub fn removed_in(&self, next: &DestManifest) -> Vec<DestFileEntry> {
+        self.entries
+            .keys()
+            .filter(|path| !next.entries.contains_key(*path))
+            .map(|path| DestFileEntry {
+                path: path.clone(),
+                size: 0,
+                mtime: 0,
+                mtime_nsec: 0,
+                mode: 0,
+                flags: DestFileFlags::REMOVED,
+                block_size: 0,
+                checksums: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Apply the changes a server sent against this manifest, which then
+    /// becomes its manifest `id`
+    pub fn apply(&mut self, changes: Vec<DestFileEntry>, id: u128) {
+        for entry in changes {
+            if entry.flags.contains(DestFileFlags::REMOVED) {
+                self.entries.remove(&entry.path);
+            } else {
+                self.insert(entry);
+            }
+        }
+        self.id = id;
+    }
+
+    /// Give the manifest an id derived from its contents (never 0)
+    pub fn seal(&mut self) {
+        let mut paths: Vec<&String> = self.entries.keys().collect();
+        paths.sort_unstable();
+
+        let mut hasher = blake3::Hasher::new();
+        for path in paths {
+            let entry = &self.entries[path];
+            hasher.update(path.as_bytes());
+            hasher.update(&[0]);
+            hasher.update(&entry.size.to_be_bytes());
+            hasher.update(&entry.mtime.to_be_bytes());
+            hasher.update(&entry.mtime_nsec.to_be_bytes());
+            hasher.update(&entry.mode.to_be_bytes());
+            hasher.update(&[entry.flags.difference(DestFileFlags::CHECKSUMS_ZSTD).bits()]);
+        }
+        let hash = hasher.finalize();
+        let id = u128::from_be_bytes(hash.as_bytes()[..16].try_into().expect("16-byte slice"));
+        self.id = id.max(1);
+    }
+
+    pub fn into_entries(self) -> impl Iterator<Item = DestFileEntry> {
+        self.entries.into_values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::protocol::BlockChecksum;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64, mtime: i64) -> DestFileEntry {
+        DestFileEntry {
+            path: path.to_string(),
+            size,
+            mtime,
+            mtime_nsec: 0,
+            mode: 0o644,
+            flags: DestFileFlags::HAS_CHECKSUMS,
+            block_size: 4096,
+            checksums: vec![BlockChecksum {
+                offset: 0,
+                weak: 1,
+                strong: 2,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join(MANIFEST_FILE);
+        assert!(DestManifest::load(&path).is_none());
+
+        let mut manifest = DestManifest::from_entries([entry("a", 10, 1), entry("b", 20, 2)], 0);
+        manifest.seal();
+        manifest.save(&path).unwrap();
+
+        let loaded = DestManifest::load(&path).unwrap();
+        assert_eq!(loaded.id(), manifest.id());
+        assert_eq!(loaded.len(), 2);
+        let a = loaded.unchanged("a", 10, (1, 0), false).unwrap();
+        assert_eq!(a.checksums[0].strong, 2);
+        assert!(loaded.unchanged("a", 10, (5, 0), false).is_none());
+        assert!(loaded.unchanged("b", 21, (2, 0), false).is_none());
+
+        std::fs::write(&path, b"garbage").unwrap();
+        assert!(DestManifest::load(&path).is_none());
+    }
+
+    #[test]
+    fn test_id_follows_contents() {
+        let mut a = DestManifest::from_entries([entry("a", 10, 1), entry("b", 20, 2)], 0);
+        let mut b = DestManifest::from_entries([entry("b", 20, 2), entry("a", 10, 1)], 0);
+        let mut c = DestManifest::from_entries([entry("a", 10, 1), entry("b", 20, 3)], 0);
+        a.seal();
+        b.seal();
+        c.seal();
+        assert_ne!(a.id(), 0);
+        assert_eq!(a.id(), b.id());
+        assert_ne!(a.id(), c.id());
+    }
+
+    #[test]
+    fn test_changes_roundtrip() {
+        let mut server_prev =
+            DestManifest::from_entries([entry("keep", 1, 1), entry("gone", 2, 2)], 0);
+        server_prev.seal();
+        let mut client = server_prev.clone();
+
+        let mut server_next =
+            DestManifest::from_entries([entry("keep", 1, 1), entry("new", 3, 3)], 0);
+        server_next.seal();
+
+        // What the server sends: entries the old manifest doesn't have, then removals
+        let mut changes: Vec<_> = server_next
+            .clone()
+            .into_entries()
+            .filter(|e| !server_prev.contains(e))
+            .collect();
+        assert_eq!(changes.len(), 1);
+        changes.extend(server_prev.removed_in(&server_next));
+
+        client.apply(changes, server_next.id());
+        assert_eq!(client.id(), server_next.id());
+        assert_eq!(client.len(), 2);
+        assert!(client.unchanged("new", 3, (3, 0), false).is_some());
+        assert!(client.unchanged("gone", 2, (2, 0), false).is_none());
+    }
+}
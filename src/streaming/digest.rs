@@ -0,0 +1,186 @@
+//! Aggregate transfer digest.
+//!
+//! Both ends fold every change they send or apply into a `TransferDigest`
+//! and exchange the result in DONE. Per-file digests cover the FILE_ENTRY,
+//! DATA and DATA_END frames of one file; MKDIR, SYMLINK, DELETE, SET_ATTR,
+//! XATTR and ACL each count as a change on their own, as does the FILE_ENTRY
+//! of a hard link.
+//!
+//! Changes are combined with wrapping addition, so the aggregate doesn't
+//! depend on the order files complete in, while a lost or duplicated change
+//! still alters it (unlike XOR, where duplicates cancel out).
+//!
+//! `content_hash` is another matter: the hash of a file's content that
+//! `--checksum-verify` compares between the source and what was written.
+
+use crate::streaming::protocol::MessageType;
+use std::io::Read;
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Digest over the frames of a single file transfer
+pub struct FileDigest(Xxh3);
+
+impl FileDigest {
+    pub fn new() -> Self {
+        Self(Xxh3::new())
+    }
+
+    /// Add a frame (message type + payload, without the length prefix)
+    pub fn update(&mut self, msg_type: MessageType, payload: &[u8]) {
+        self.0.update(&[msg_type as u8]);
+        self.0.update(&(payload.len() as u32).to_be_bytes());
+        self.0.update(payload);
+    }
+
+    pub fn finish(&self) -> u128 {
+        self.0.digest128()
+    }
+}
+
+impl Default for FileDigest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// xxh3-128 of the first `len` bytes of the file at `path` (all of it for
+/// `u64::MAX`), for `--checksum-verify`
+pub fn content_hash(path: &Path, len: u64) -> std::io::Result<u128> {
+    let mut reader = std::fs::File::open(path)?.take(len);
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; 256 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher.digest128());
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+/// Order-independent digest over all changes in a transfer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferDigest(u128);
+
+impl TransferDigest {
+    pub fn new() -> Self {
+        Self(0)
+    }
+
+    /// Fold in a completed file
+    pub fn add_file(&mut self, file: &FileDigest) {
+        self.0 = self.0.wrapping_add(file.finish());
+    }
+
+    /// Fold in a single-frame change (MKDIR, SYMLINK, DELETE, SET_ATTR)
+    pub fn add_frame(&mut self, msg_type: MessageType, payload: &[u8]) {
+        let mut d = FileDigest::new();
+        d.update(msg_type, payload);
+        self.add_file(&d);
+    }
+
+    /// Fold in an encoded frame (`len:u32 | type:u8 | payload`) as produced by `encode()`
+    ///
+    /// Frames that don't represent a change (e.g. FILE_END) are ignored.
+    pub fn add_encoded(&mut self, frame: &[u8]) {
+        if let Some((msg_type, payload)) = split_frame(frame) {
+            if is_standalone_change(msg_type) {
+                self.add_frame(msg_type, payload);
+            }
+        }
+    }
+
+    pub fn value(&self) -> u128 {
+        self.0
+    }
+
+    pub fn from_value(value: u128) -> Self {
+        Self(value)
+    }
+
+    /// Check that the peer's digest (from its DONE) matches ours
+    pub fn verify(&self, peer: u128) -> anyhow::Result<()> {
+        if self.0 != peer {
+            return Err(crate::error::SyncError::DigestMismatch {
+                local: self.0,
+                remote: peer,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Whether a message type is a change that isn't part of a file transfer
+pub fn is_standalone_change(msg_type: MessageType) -> bool {
+    matches!(
+        msg_type,
+        MessageType::Mkdir
+            | MessageType::Symlink
+            | MessageType::Delete
+            | MessageType::SetAttr
+            | MessageType::Xattr
+            | MessageType::Acl
+    )
+}
+
+/// Split an encoded frame into its message type and payload
+pub fn split_frame(frame: &[u8]) -> Option<(MessageType, &[u8])> {
+    if frame.len() < 5 {
+        return None;
+    }
+    let msg_type = MessageType::from_u8(frame[4])?;
+    Some((msg_type, &frame[5..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::protocol::{Delete, Mkdir};
+
+    #[test]
+    fn test_digest_is_order_independent() {
+        let a = Mkdir {
+            path: "a".into(),
+            mode: 0o755,
+            owner: None,
+        }
+        .encode();
+        let b = Delete {
+            path: "b".into(),
+            is_dir: false,
+        }
+        .encode();
+
+        let mut d1 = TransferDigest::new();
+        d1.add_encoded(&a);
+        d1.add_encoded(&b);
+
+        let mut d2 = TransferDigest::new();
+        d2.add_frame(MessageType::Delete, &b[5..]);
+        d2.add_frame(MessageType::Mkdir, &a[5..]);
+
+        assert_eq!(d1, d2);
+        assert_ne!(d1, TransferDigest::new());
+    }
+
+    #[test]
+    fn test_digest_detects_duplicate() {
+        let a = Mkdir {
+            path: "a".into(),
+            mode: 0o755,
+            owner: None,
+        }
+        .encode();
+
+        let mut once = TransferDigest::new();
+        once.add_encoded(&a);
+        let mut twice = once;
+        twice.add_encoded(&a);
+
+        assert_ne!(once, twice);
+        assert!(twice.verify(once.value()).is_err());
+        assert!(once.verify(once.value()).is_ok());
+    }
+}
@@ -3,15 +3,37 @@
 //! Scans source directory and streams file metadata to Sender.
 //! Receives destination state during Initial Exchange.
 
+use crate::error::SyncError;
+use crate::filter::FilterEngine;
 use crate::streaming::channel::{
-    DeltaInfo, DestFileState, DestIndex, FileJob, FileJobSender, GeneratorMessage, DELTA_MIN_SIZE,
+    Cancelled, DeltaInfo, DestFileState, DestIndex, FileJob, FileJobSender, GeneratorMessage,
+    ProgressCounter, DELTA_MIN_SIZE,
 };
-use crate::streaming::protocol::{DestFileEntry, DestFileFlags};
-use crate::sync::scanner::Scanner;
+use crate::streaming::owner::OwnerMap;
+use crate::streaming::protocol::{
+    DestFileEntry, DestFileFlags, Ownership, SetAttrFlags, Times, XattrEntry,
+};
+use crate::sync::scanner::{scan_entry, FileEntry, Scanner};
 use anyhow::Result;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// When `--delete` removes what the source no longer has
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeleteTiming {
+    /// Before any file is sent, freeing space first (`--delete-before`)
+    Before,
+    /// A directory's extraneous entries when the scan reaches the
+    /// directory (`--delete-during`)
+    During,
+    /// Once every file is sent (`--delete-after`, the default)
+    #[default]
+    After,
+}
 
 /// Generator configuration
 pub struct GeneratorConfig {
@@ -23,6 +45,46 @@ pub struct GeneratorConfig {
     pub follow_symlinks: bool,
     /// Whether --delete is enabled
     pub delete_enabled: bool,
+    /// When deletes are sent
+    pub delete_timing: DeleteTiming,
+    /// Fail before sending anything when more entries than this would be
+    /// deleted (`--max-delete-count`)
+    pub max_delete: Option<u64>,
+    /// Cap on destination checksums held in RAM before spilling to disk
+    pub index_memory: u64,
+    /// Include BSD file flags in file jobs (`--preserve-flags`)
+    pub file_flags: bool,
+    /// Send real permission bits, and fix files whose only difference is
+    /// their mode with SET_ATTR (`-p`)
+    pub perms: bool,
+    /// Match against a FAT destination's names and mtimes (`--fat`)
+    pub fat: bool,
+    /// Send each entry's owner and group, mapped through these (`-o`/`-g`
+    /// to a destination that applies them)
+    pub owners: Option<OwnerMap>,
+    /// Follow each file and directory that has extended attributes with an
+    /// XATTR (`-X` to a destination that applies them)
+    pub xattrs: bool,
+    /// Follow each file and directory that has an extended ACL with an ACL
+    /// (`-A` to a destination that applies them)
+    pub acls: bool,
+    /// Send later paths to an already-sent inode as hard links to the
+    /// first (`-H` to a destination that creates them)
+    pub hardlinks: bool,
+    /// Send each file's access and creation times (`--times=all` to a
+    /// destination that applies them)
+    pub times: bool,
+    /// Paths never sent nor deleted, with everything below them (the
+    /// server's own exclusions for pulls)
+    pub exclude: FilterEngine,
+    /// The sync's own include/exclude rules, kept apart from `exclude` so
+    /// neither side's rules can re-include what the other excludes
+    pub filter: FilterEngine,
+    /// Scan only these paths (relative to `root`, with everything below
+    /// them) and delete only below them; `None` scans the whole tree
+    pub scope: Option<Vec<PathBuf>>,
+    /// Stops the scan when cancelled
+    pub cancel: CancellationToken,
 }
 
 /// Generator state
@@ -30,20 +92,55 @@ pub struct Generator {
     config: GeneratorConfig,
     dest_index: DestIndex,
     seen_inodes: HashMap<u64, Arc<PathBuf>>, // For hard link detection
+    /// Checksums of the destination's partial temp files, by temp path
+    partials: HashMap<String, DeltaInfo>,
+    /// Where the files found are counted as they're queued
+    progress: Option<Arc<ProgressCounter>>,
 }
 
 impl Generator {
     pub fn new(config: GeneratorConfig) -> Self {
         Self {
+            dest_index: DestIndex::with_memory_limit(config.index_memory),
             config,
-            dest_index: DestIndex::new(),
             seen_inodes: HashMap::new(),
+            partials: HashMap::new(),
+            progress: None,
         }
     }
 
+    /// Count every file queued into `progress`, for PROGRESS reports
+    pub fn with_progress(mut self, progress: Arc<ProgressCounter>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
     /// Process a DEST_FILE_ENTRY received during Initial Exchange.
     /// Call this for each entry before starting the scan.
-    pub fn add_dest_entry(&mut self, entry: DestFileEntry) {
+    pub fn add_dest_entry(&mut self, entry: DestFileEntry) -> Result<()> {
+        if entry.flags.contains(DestFileFlags::PARTIAL) {
+            // Still indexed, so --delete removes it if nothing resumes it
+            self.partials.insert(
+                entry.path.clone(),
+                DeltaInfo {
+                    block_size: entry.block_size,
+                    file_size: entry.size,
+                    checksums: entry.checksums,
+                },
+            );
+            return self.dest_index.insert(
+                entry.path,
+                DestFileState {
+                    size: entry.size,
+                    mtime: entry.mtime,
+                    mtime_nsec: entry.mtime_nsec,
+                    mode: entry.mode,
+                    is_dir: false,
+                    delta_info: None,
+                },
+            );
+        }
+
         let delta_info = if entry.flags.contains(DestFileFlags::HAS_CHECKSUMS) {
             Some(DeltaInfo {
                 block_size: entry.block_size,
@@ -59,11 +156,12 @@ impl Generator {
             DestFileState {
                 size: entry.size,
                 mtime: entry.mtime,
+                mtime_nsec: entry.mtime_nsec,
                 mode: entry.mode,
                 is_dir: entry.flags.contains(DestFileFlags::DIR),
                 delta_info,
             },
-        );
+        )
     }
 
     /// Called after all DEST_FILE_ENTRY received (after DEST_FILE_END).
@@ -72,8 +170,9 @@ impl Generator {
     }
 
     /// Run the generator, scanning source and sending to channel.
-    /// Returns (total_files, total_bytes).
-    pub async fn run(mut self, tx: FileJobSender) -> Result<(u64, u64)> {
+    /// Returns (total_files, total_bytes, time spent walking the source).
+    #[tracing::instrument(name = "generator", skip_all, fields(root = %self.config.root.display()))]
+    pub async fn run(mut self, tx: FileJobSender) -> Result<(u64, u64, Duration)> {
         let mut scanner = Scanner::new(&self.config.root);
         scanner = scanner.follow_links(self.config.follow_symlinks);
 
@@ -82,45 +181,169 @@ impl Generator {
 
         let mut total_files = 0u64;
         let mut total_bytes = 0u64;
+        let mut skipped = 0u64;
 
         // Scanner::scan() is blocking, so we run it in spawn_blocking
-        let entries = tokio::task::spawn_blocking(move || scanner.scan()).await??;
+        let scan_start = Instant::now();
+        let entries = match self.config.scope.clone() {
+            None => tokio::task::spawn_blocking(move || scanner.scan()).await??,
+            Some(scope) => {
+                let (root, follow) = (self.config.root.clone(), self.config.follow_symlinks);
+                tokio::task::spawn_blocking(move || scan_scope(&root, &scope, follow)).await??
+            }
+        };
+        let scan_time = scan_start.elapsed();
+
+        // Known in full before anything is sent, so --max-delete-count can stop
+        // a sync from an empty or unmounted source before it does harm
+        let (mut doomed, held): (Vec<_>, Vec<_>) = if self.config.delete_enabled {
+            let (doomed, held) = self.doomed(&entries);
+            (doomed.into_iter().map(Some).collect(), held)
+        } else {
+            Default::default()
+        };
+        if let Some(max) = self.config.max_delete {
+            let count = doomed.len() as u64;
+            if count > max {
+                return Err(SyncError::TooManyDeletes { count, max }.into());
+            }
+        }
+        // Indexes into `doomed` by parent directory, for --delete-during
+        let mut doomed_in: HashMap<String, Vec<usize>> = HashMap::new();
+        match self.config.delete_timing {
+            DeleteTiming::Before => {
+                for doomed in &mut doomed {
+                    send_delete(&tx, doomed.take()).await?;
+                }
+            }
+            DeleteTiming::During => {
+                for (i, doomed) in doomed.iter().enumerate() {
+                    if let Some((path, _)) = doomed {
+                        let parent = Path::new(path).parent().unwrap_or(Path::new(""));
+                        doomed_in
+                            .entry(parent.to_string_lossy().into_owned())
+                            .or_default()
+                            .push(i);
+                    }
+                }
+                // The root comes first
+                for i in doomed_in.remove("").unwrap_or_default() {
+                    send_delete(&tx, doomed[i].take()).await?;
+                }
+            }
+            DeleteTiming::After => {}
+        }
 
         for entry in entries {
+            if self.config.cancel.is_cancelled() {
+                // Before any deletes (short of --delete-before): a partial
+                // scan must not remove anything
+                return Err(Cancelled.into());
+            }
             let rel_path = entry.relative_path.as_ref().to_path_buf();
-            let rel_path_str = rel_path.to_string_lossy().to_string();
+            let dest_path = self.dest_path(&rel_path);
+
+            if entry.is_dir {
+                for i in doomed_in.remove(&dest_path).unwrap_or_default() {
+                    send_delete(&tx, doomed[i].take()).await?;
+                }
+            }
 
             // Skip root directory (empty relative path)
-            if rel_path_str.is_empty() {
+            if dest_path.is_empty() {
                 continue;
             }
 
             // Get destination state before removing from index
-            let dest_state = self.dest_index.remove(&rel_path_str);
+            let dest_state = self.dest_index.remove(&dest_path)?;
 
-            let mtime = entry
+            // Out of the index too, so the destination's copy isn't deleted
+            if self.excluded(&rel_path, entry.is_dir) {
+                continue;
+            }
+
+            let since_epoch = entry
                 .modified
                 .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs() as i64;
+                .unwrap_or_default();
+            let (mtime, mtime_nsec) = (since_epoch.as_secs() as i64, since_epoch.subsec_nanos());
 
-            // TODO: Scanner should provide mode. For now use default.
-            let mode = if entry.is_dir { 0o755 } else { 0o644 };
+            // Without -p every file gets the same default mode
+            let default_mode = if entry.is_dir { 0o755 } else { 0o644 };
+            // The scan keeps neither the permission bits, the owner nor the
+            // access and creation times
+            let meta = if self.config.perms || self.config.owners.is_some() || self.config.times {
+                std::fs::symlink_metadata(entry.path.as_ref()).ok()
+            } else {
+                None
+            };
+            let mode = match &meta {
+                Some(meta) if self.config.perms => crate::fs_util::permission_bits(meta),
+                _ => default_mode,
+            };
+            let owner = self
+                .config
+                .owners
+                .as_ref()
+                .zip(meta.as_ref().and_then(crate::fs_util::ownership))
+                .map(|(owners, (uid, gid))| owners.apply(Ownership { uid, gid }));
+            let times = meta
+                .as_ref()
+                .filter(|_| self.config.times && !entry.is_dir && !entry.is_symlink)
+                .map(file_times);
 
             // Skip unchanged files (matching size and mtime)
             if !entry.is_dir && !entry.is_symlink {
                 if let Some(ref dest) = dest_state {
-                    if dest.size == entry.size && dest.mtime == mtime {
-                        // File unchanged, skip it
+                    let same_mtime = if self.config.fat {
+                        crate::fs_util::fat_mtime_matches(dest.mtime, mtime)
+                    } else {
+                        crate::fs_util::mtime_matches(
+                            dest.mtime,
+                            dest.mtime_nsec,
+                            mtime,
+                            mtime_nsec,
+                        )
+                    };
+                    if dest.size == entry.size && same_mtime {
+                        if self.config.perms && dest.mode != mode {
+                            // Same content, new permissions
+                            tx.send(GeneratorMessage::SetAttr {
+                                path: Arc::new(rel_path),
+                                mode,
+                                mtime,
+                                mtime_nsec,
+                                flags: SetAttrFlags::MODE,
+                            })
+                            .await?;
+                        } else {
+                            // File unchanged, skip it
+                            skipped += 1;
+                        }
                         continue;
                     }
                 }
             }
 
+            // A later path to an inode already sent only links to it, and
+            // shares its xattrs and ACL
+            let link_target = if entry.is_dir || entry.is_symlink {
+                None
+            } else {
+                self.link_target(&entry, &rel_path)
+            };
+            let (xattr, acl) = match link_target {
+                Some(_) => (None, None),
+                None => (
+                    self.xattr_message(&entry, &rel_path),
+                    self.acl_message(&entry, &rel_path),
+                ),
+            };
             let msg = if entry.is_dir {
                 GeneratorMessage::Mkdir {
                     path: Arc::new(rel_path),
                     mode,
+                    owner,
                 }
             } else if entry.is_symlink {
                 GeneratorMessage::Symlink {
@@ -130,97 +353,332 @@ impl Generator {
                         .as_ref()
                         .map(|t| t.to_string_lossy().to_string())
                         .unwrap_or_default(),
+                    owner,
                 }
+            } else if link_target.is_some() {
+                total_files += 1;
+                self.found(0);
+                GeneratorMessage::File(FileJob {
+                    path: Arc::new(rel_path),
+                    size: entry.size,
+                    mtime,
+                    mtime_nsec,
+                    mode,
+                    inode: entry.inode.unwrap_or(0),
+                    sparse: false,
+                    win_attrs: None,
+                    bsd_flags: None,
+                    owner: None,
+                    times: None,
+                    exists: dest_state.is_some(),
+                    need_delta: false,
+                    checksums: None,
+                    resume: None,
+                    link_target,
+                })
             } else {
-                // Check for hard link
-                let inode = entry.inode.unwrap_or(0);
-                let _link_target = if entry.nlink > 1 {
-                    if let Some(existing) = self.seen_inodes.get(&inode) {
-                        Some(existing.clone())
-                    } else {
-                        self.seen_inodes.insert(inode, Arc::new(rel_path.clone()));
-                        None
-                    }
-                } else {
-                    None
-                };
-
                 // Determine if delta is needed
-                let (need_delta, checksums) =
-                    self.check_delta_for_state(dest_state.as_ref(), entry.size);
+                let exists = dest_state.is_some();
+                let (need_delta, checksums) = Self::check_delta_for_state(dest_state, entry.size);
+                let resume = self.take_partial(&rel_path)?;
 
                 total_files += 1;
                 total_bytes += entry.size;
+                self.found(entry.size);
 
                 GeneratorMessage::File(FileJob {
                     path: Arc::new(rel_path),
                     size: entry.size,
                     mtime,
+                    mtime_nsec,
                     mode,
-                    inode,
+                    inode: entry.inode.unwrap_or(0),
+                    sparse: entry.is_sparse,
+                    win_attrs: entry.win_attrs,
+                    bsd_flags: entry.bsd_flags.filter(|_| self.config.file_flags),
+                    owner,
+                    times,
+                    exists,
                     need_delta,
                     checksums,
+                    resume,
+                    link_target: None,
                 })
             };
 
             tx.send(msg).await?;
+            if let Some(xattr) = xattr {
+                tx.send(xattr).await?;
+            }
+            if let Some(acl) = acl {
+                tx.send(acl).await?;
+            }
         }
 
         // Send FILE_END
         tx.send(GeneratorMessage::FileEnd {
             total_files,
             total_bytes,
+            skipped,
         })
         .await?;
 
-        // Send deletes if enabled
+        // Send the deletes not sent yet: all of them by default, and with
+        // --delete-during those below directories that were deleted whole.
+        // Partials held back go too, unless a transfer resumed them
         if self.config.delete_enabled {
-            let mut delete_count = 0u64;
-            let remaining: Vec<_> = self
-                .dest_index
-                .remaining_paths()
-                .map(|(path, state)| (path.clone(), state.is_dir))
-                .collect();
-
-            for (path, is_dir) in remaining {
-                tx.send(GeneratorMessage::Delete {
-                    path: Arc::new(PathBuf::from(path)),
-                    is_dir,
-                })
-                .await?;
-                delete_count += 1;
+            let unresumed = held
+                .into_iter()
+                .filter(|path| self.partials.contains_key(path))
+                .map(|path| Some((path, false)));
+            let doomed: Vec<_> = doomed.into_iter().chain(unresumed).collect();
+            let count = doomed.len() as u64;
+            for doomed in doomed {
+                send_delete(&tx, doomed).await?;
+            }
+            tx.send(GeneratorMessage::DeleteEnd { count }).await?;
+        }
+
+        Ok((total_files, total_bytes, scan_time))
+    }
+
+    /// The path sent earlier with `entry`'s inode, under `-H`; the first
+    /// path of each multiply-linked inode is remembered for the later ones
+    fn link_target(&mut self, entry: &FileEntry, rel_path: &Path) -> Option<Arc<PathBuf>> {
+        let inode = entry
+            .inode
+            .filter(|_| self.config.hardlinks && entry.nlink > 1)?;
+        match self.seen_inodes.get(&inode) {
+            Some(first) => Some(Arc::clone(first)),
+            None => {
+                self.seen_inodes
+                    .insert(inode, Arc::new(rel_path.to_path_buf()));
+                None
             }
-            tx.send(GeneratorMessage::DeleteEnd {
-                count: delete_count,
+        }
+    }
+
+    /// The XATTR to send after `entry`, if it has extended attributes
+    ///
+    /// Symlinks keep theirs: Linux only allows `user.` xattrs on regular
+    /// files and directories.
+    fn xattr_message(&self, entry: &FileEntry, rel_path: &Path) -> Option<GeneratorMessage> {
+        if !self.config.xattrs || entry.is_symlink {
+            return None;
+        }
+        let mut entries: Vec<XattrEntry> = entry
+            .xattrs
+            .as_ref()?
+            .iter()
+            .map(|(name, value)| XattrEntry {
+                name: name.clone(),
+                value: Bytes::copy_from_slice(value),
             })
-            .await?;
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Some(GeneratorMessage::Xattr {
+            path: Arc::new(rel_path.to_path_buf()),
+            entries,
+        })
+    }
+
+    /// The ACL to send after `entry`, if it has one beyond its mode bits
+    ///
+    /// On Linux every file reports its owner, group and other entries; an
+    /// ACL of just those says nothing chmod doesn't, so it isn't sent.
+    fn acl_message(&self, entry: &FileEntry, rel_path: &Path) -> Option<GeneratorMessage> {
+        if !self.config.acls || entry.is_symlink {
+            return None;
+        }
+        let text = String::from_utf8_lossy(entry.acls.as_ref()?);
+        let entries = text.lines().filter(|line| !line.trim().is_empty()).count();
+        let base = if cfg!(target_os = "linux") { 3 } else { 0 };
+        if entries <= base {
+            return None;
         }
+        Some(GeneratorMessage::Acl {
+            path: Arc::new(rel_path.to_path_buf()),
+            text: text.into_owned(),
+        })
+    }
 
-        Ok((total_files, total_bytes))
+    /// What `--delete` removes: the destination's entries the source
+    /// doesn't have, leaving out excluded paths and anything outside the
+    /// scope. The partial temp files of source files come back separately,
+    /// since the transfer may still resume them
+    fn doomed(&self, entries: &[FileEntry]) -> (Vec<(String, bool)>, Vec<String>) {
+        let mut source = HashSet::new();
+        let mut resumable = HashSet::new();
+        for entry in entries {
+            let dest_path = self.dest_path(entry.relative_path.as_ref());
+            if !self.partials.is_empty() && !entry.is_dir && !entry.is_symlink {
+                let temp = crate::streaming::storage::temp_path(Path::new(&dest_path));
+                resumable.insert(temp.to_string_lossy().into_owned());
+            }
+            source.insert(dest_path);
+        }
+        let scope = self.dest_scope();
+        let (held, doomed): (Vec<_>, Vec<_>) = self
+            .dest_index
+            .remaining_paths()
+            .filter(|(path, _)| !source.contains(*path))
+            .filter(|(path, is_dir)| !self.excluded(Path::new(path), *is_dir))
+            .filter(|(path, _)| {
+                scope
+                    .as_ref()
+                    .is_none_or(|scope| scope.iter().any(|dir| Path::new(path).starts_with(dir)))
+            })
+            .map(|(path, is_dir)| (path.to_string(), is_dir))
+            .partition(|(path, _)| resumable.contains(path));
+        (doomed, held.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// The name the destination has for `rel_path`: on FAT the receiver
+    /// stores it under its sanitized name
+    fn dest_path(&self, rel_path: &Path) -> String {
+        if self.config.fat {
+            crate::fs_util::fat_safe_path(rel_path)
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            rel_path.to_string_lossy().into_owned()
+        }
+    }
+
+    /// Whether `path` is left alone: neither sent nor deleted
+    fn excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.config.exclude.excludes_tree(path, is_dir)
+            || self.config.filter.excludes_tree(path, is_dir)
+    }
+
+    /// The scope as the destination names it, for matching its entries
+    fn dest_scope(&self) -> Option<Vec<PathBuf>> {
+        let scope = self.config.scope.as_ref()?;
+        Some(
+            scope
+                .iter()
+                .map(|path| {
+                    if self.config.fat {
+                        crate::fs_util::fat_safe_path(path).into_owned()
+                    } else {
+                        path.clone()
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    fn found(&self, size: u64) {
+        if let Some(progress) = &self.progress {
+            progress.found(size);
+        }
+    }
+
+    /// The partial temp file the destination kept for `rel_path`, taken out
+    /// of the index so it isn't deleted while it's being resumed
+    fn take_partial(&mut self, rel_path: &Path) -> Result<Option<DeltaInfo>> {
+        if self.partials.is_empty() {
+            return Ok(None);
+        }
+        let dest_path = if self.config.fat {
+            crate::fs_util::fat_safe_path(rel_path).into_owned()
+        } else {
+            rel_path.to_path_buf()
+        };
+        let temp = crate::streaming::storage::temp_path(&dest_path);
+        let temp = temp.to_string_lossy();
+        let Some(partial) = self.partials.remove(&*temp) else {
+            return Ok(None);
+        };
+        self.dest_index.remove(&temp)?;
+        Ok(Some(partial))
     }
 
     fn check_delta_for_state(
-        &self,
-        dest_state: Option<&DestFileState>,
+        dest_state: Option<DestFileState>,
         size: u64,
     ) -> (bool, Option<DeltaInfo>) {
         if size < DELTA_MIN_SIZE {
             return (false, None);
         }
 
-        if let Some(state) = dest_state {
-            if let Some(ref delta_info) = state.delta_info {
-                return (true, Some(delta_info.clone()));
-            }
+        if let Some(delta_info) = dest_state.and_then(|state| state.delta_info) {
+            return (true, Some(delta_info));
         }
 
         (false, None)
     }
 }
 
+/// Access and creation times from `meta`
+fn file_times(meta: &std::fs::Metadata) -> Times {
+    let atime = filetime::FileTime::from_last_access_time(meta);
+    let btime = filetime::FileTime::from_creation_time(meta);
+    Times {
+        atime: atime.unix_seconds(),
+        atime_nsec: atime.nanoseconds(),
+        btime: btime.map_or(0, |t| t.unix_seconds()),
+        btime_nsec: btime.map_or(0, |t| t.nanoseconds()),
+    }
+}
+
+/// Send the delete for `doomed`, unless it was already sent
+async fn send_delete(tx: &FileJobSender, doomed: Option<(String, bool)>) -> Result<()> {
+    if let Some((path, is_dir)) = doomed {
+        tx.send(GeneratorMessage::Delete {
+            path: Arc::new(PathBuf::from(path)),
+            is_dir,
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// Entries for just the `scope` paths below `root`: those that still exist,
+/// everything below the directories among them, and the directories leading
+/// to them, so a file in a new directory has somewhere to go. Paths that
+/// are gone produce nothing; their deletes come from the destination index
+fn scan_scope(root: &Path, scope: &[PathBuf], follow_symlinks: bool) -> Result<Vec<FileEntry>> {
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for rel in scope {
+        let path = root.join(rel);
+        let Ok(meta) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+
+        let parents: Vec<_> = rel
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect();
+        for dir in parents.into_iter().rev() {
+            if seen.insert(dir.to_path_buf()) {
+                entries.push(scan_entry(root, &root.join(dir))?);
+            }
+        }
+
+        if seen.insert(rel.clone()) {
+            entries.push(scan_entry(root, &path)?);
+        }
+        if meta.is_dir() {
+            let below = Scanner::new(&path).follow_links(follow_symlinks).scan()?;
+            for mut entry in below {
+                let entry_rel = rel.join(entry.relative_path.as_ref());
+                if seen.insert(entry_rel.clone()) {
+                    entry.relative_path = Arc::new(entry_rel);
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::streaming::dest_index::DEFAULT_INDEX_MEMORY;
     use std::fs;
     use tempfile::TempDir;
 
@@ -232,6 +690,21 @@ mod tests {
             include_hidden: false,
             follow_symlinks: false,
             delete_enabled: false,
+            delete_timing: DeleteTiming::default(),
+            max_delete: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            file_flags: false,
+            perms: false,
+            fat: false,
+            owners: None,
+            xattrs: false,
+            acls: false,
+            hardlinks: false,
+            times: false,
+            exclude: FilterEngine::new(),
+            filter: FilterEngine::new(),
+            scope: None,
+            cancel: CancellationToken::new(),
         };
 
         let (tx, mut rx) = crate::streaming::channel::file_job_channel();
@@ -262,6 +735,21 @@ mod tests {
             include_hidden: false,
             follow_symlinks: false,
             delete_enabled: false,
+            delete_timing: DeleteTiming::default(),
+            max_delete: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            file_flags: false,
+            perms: false,
+            fat: false,
+            owners: None,
+            xattrs: false,
+            acls: false,
+            hardlinks: false,
+            times: false,
+            exclude: FilterEngine::new(),
+            filter: FilterEngine::new(),
+            scope: None,
+            cancel: CancellationToken::new(),
         };
 
         let (tx, mut rx) = crate::streaming::channel::file_job_channel();
@@ -300,6 +788,21 @@ mod tests {
             include_hidden: false,
             follow_symlinks: false,
             delete_enabled: true,
+            delete_timing: DeleteTiming::default(),
+            max_delete: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            file_flags: false,
+            perms: false,
+            fat: false,
+            owners: None,
+            xattrs: false,
+            acls: false,
+            hardlinks: false,
+            times: false,
+            exclude: FilterEngine::new(),
+            filter: FilterEngine::new(),
+            scope: None,
+            cancel: CancellationToken::new(),
         };
 
         let (tx, mut rx) = crate::streaming::channel::file_job_channel();
@@ -310,11 +813,13 @@ mod tests {
             path: "delete_me.txt".to_string(),
             size: 100,
             mtime: 0,
+            mtime_nsec: 0,
             mode: 0o644,
             flags: DestFileFlags::empty(),
             block_size: 0,
             checksums: vec![],
-        });
+        })
+        .unwrap();
 
         tokio::spawn(async move {
             gen.run(tx).await.unwrap();
@@ -323,10 +828,10 @@ mod tests {
         let mut got_delete = false;
         while let Some(msg) = rx.recv().await {
             match msg {
-                GeneratorMessage::Delete { path, .. } => {
-                    if path.to_string_lossy() == "delete_me.txt" {
-                        got_delete = true;
-                    }
+                GeneratorMessage::Delete { path, .. }
+                    if path.to_string_lossy() == "delete_me.txt" =>
+                {
+                    got_delete = true;
                 }
                 GeneratorMessage::DeleteEnd { .. } => break,
                 _ => {}
@@ -335,4 +840,452 @@ mod tests {
 
         assert!(got_delete, "Should have received delete for delete_me.txt");
     }
+
+    #[tokio::test]
+    async fn test_generator_resumes_partials_and_deletes_stale_ones() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("big.bin"), "source").unwrap();
+
+        let config = GeneratorConfig {
+            root: tmp.path().to_path_buf(),
+            include_hidden: false,
+            follow_symlinks: false,
+            delete_enabled: true,
+            delete_timing: DeleteTiming::default(),
+            max_delete: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            file_flags: false,
+            perms: false,
+            fat: false,
+            owners: None,
+            xattrs: false,
+            acls: false,
+            hardlinks: false,
+            times: false,
+            exclude: FilterEngine::new(),
+            filter: FilterEngine::new(),
+            scope: None,
+            cancel: CancellationToken::new(),
+        };
+        let mut gen = Generator::new(config);
+        for path in ["big.sy.tmp", "gone.sy.tmp"] {
+            gen.add_dest_entry(DestFileEntry {
+                path: path.to_string(),
+                size: 4096,
+                mtime: 0,
+                mtime_nsec: 0,
+                mode: 0o644,
+                flags: DestFileFlags::PARTIAL | DestFileFlags::HAS_CHECKSUMS,
+                block_size: 4096,
+                checksums: vec![],
+            })
+            .unwrap();
+        }
+
+        let (tx, mut rx) = crate::streaming::channel::file_job_channel();
+        tokio::spawn(async move {
+            gen.run(tx).await.unwrap();
+        });
+
+        let mut resumed = Vec::new();
+        let mut deleted = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                GeneratorMessage::File(job) if job.resume.is_some() => {
+                    resumed.push(job.path.to_string_lossy().into_owned())
+                }
+                GeneratorMessage::Delete { path, .. } => {
+                    deleted.push(path.to_string_lossy().into_owned())
+                }
+                GeneratorMessage::DeleteEnd { .. } => break,
+                _ => {}
+            }
+        }
+
+        // The one being resumed stays; the other has nothing to resume it
+        assert_eq!(resumed, ["big.bin"]);
+        assert_eq!(deleted, ["gone.sy.tmp"]);
+    }
+
+    #[tokio::test]
+    async fn test_generator_skips_excluded_paths() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("keep.txt"), "keep").unwrap();
+        fs::create_dir_all(tmp.path().join("secrets/nested")).unwrap();
+        fs::write(tmp.path().join("secrets/nested/token"), "hidden").unwrap();
+
+        let mut exclude = FilterEngine::new();
+        exclude.add_rule("secrets").unwrap();
+        let config = GeneratorConfig {
+            root: tmp.path().to_path_buf(),
+            include_hidden: false,
+            follow_symlinks: false,
+            delete_enabled: true,
+            delete_timing: DeleteTiming::default(),
+            max_delete: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            file_flags: false,
+            perms: false,
+            fat: false,
+            owners: None,
+            xattrs: false,
+            acls: false,
+            hardlinks: false,
+            times: false,
+            exclude,
+            filter: FilterEngine::new(),
+            scope: None,
+            cancel: CancellationToken::new(),
+        };
+
+        let (tx, mut rx) = crate::streaming::channel::file_job_channel();
+        let mut gen = Generator::new(config);
+
+        // Only on the destination, but under an excluded directory
+        gen.add_dest_entry(DestFileEntry {
+            path: "secrets/old".to_string(),
+            size: 1,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            flags: DestFileFlags::empty(),
+            block_size: 0,
+            checksums: vec![],
+        })
+        .unwrap();
+
+        tokio::spawn(async move {
+            gen.run(tx).await.unwrap();
+        });
+
+        let mut sent = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                GeneratorMessage::File(job) => sent.push(job.path.to_path_buf()),
+                GeneratorMessage::Mkdir { path, .. } => sent.push(path.to_path_buf()),
+                GeneratorMessage::Delete { path, .. } => panic!("deleted {}", path.display()),
+                GeneratorMessage::DeleteEnd { count } => {
+                    assert_eq!(count, 0);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(sent, vec![PathBuf::from("keep.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_generator_sends_mode_only_changes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        for name in ["same.sh", "chmod.sh"] {
+            let path = tmp.path().join(name);
+            fs::write(&path, "#!/bin/sh").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let mtime = fs::metadata(tmp.path().join("same.sh"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let config = GeneratorConfig {
+            root: tmp.path().to_path_buf(),
+            include_hidden: false,
+            follow_symlinks: false,
+            delete_enabled: false,
+            delete_timing: DeleteTiming::default(),
+            max_delete: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            file_flags: false,
+            perms: true,
+            fat: false,
+            owners: None,
+            xattrs: false,
+            acls: false,
+            hardlinks: false,
+            times: false,
+            exclude: FilterEngine::new(),
+            filter: FilterEngine::new(),
+            scope: None,
+            cancel: CancellationToken::new(),
+        };
+        let (tx, mut rx) = crate::streaming::channel::file_job_channel();
+        let mut gen = Generator::new(config);
+        for (name, mode) in [("same.sh", 0o755), ("chmod.sh", 0o644)] {
+            gen.add_dest_entry(DestFileEntry {
+                path: name.to_string(),
+                size: 9,
+                mtime,
+                mtime_nsec: 0,
+                mode,
+                flags: DestFileFlags::empty(),
+                block_size: 0,
+                checksums: vec![],
+            })
+            .unwrap();
+        }
+
+        tokio::spawn(async move {
+            gen.run(tx).await.unwrap();
+        });
+
+        let mut attrs = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                GeneratorMessage::SetAttr {
+                    path, mode, flags, ..
+                } => attrs.push((path.to_path_buf(), mode, flags)),
+                GeneratorMessage::File(job) => panic!("resent {}", job.path.display()),
+                GeneratorMessage::FileEnd { skipped, .. } => {
+                    assert_eq!(skipped, 1);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(
+            attrs,
+            vec![(PathBuf::from("chmod.sh"), 0o755, SetAttrFlags::MODE)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generator_compares_mtime_nanoseconds() {
+        let tmp = TempDir::new().unwrap();
+        let mtime = filetime::FileTime::from_unix_time(1_700_000_000, 500);
+        for name in ["same", "newer", "whole"] {
+            let path = tmp.path().join(name);
+            fs::write(&path, "content").unwrap();
+            filetime::set_file_mtime(&path, mtime).unwrap();
+        }
+
+        let config = GeneratorConfig {
+            root: tmp.path().to_path_buf(),
+            include_hidden: false,
+            follow_symlinks: false,
+            delete_enabled: false,
+            delete_timing: DeleteTiming::default(),
+            max_delete: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            file_flags: false,
+            perms: false,
+            fat: false,
+            owners: None,
+            xattrs: false,
+            acls: false,
+            hardlinks: false,
+            times: false,
+            exclude: FilterEngine::new(),
+            filter: FilterEngine::new(),
+            scope: None,
+            cancel: CancellationToken::new(),
+        };
+        let (tx, mut rx) = crate::streaming::channel::file_job_channel();
+        let mut gen = Generator::new(config);
+        // A whole-second destination (or an older peer) only compares seconds
+        for (name, mtime_nsec) in [("same", 500), ("newer", 499), ("whole", 0)] {
+            gen.add_dest_entry(DestFileEntry {
+                path: name.to_string(),
+                size: 7,
+                mtime: 1_700_000_000,
+                mtime_nsec,
+                mode: 0o644,
+                flags: DestFileFlags::empty(),
+                block_size: 0,
+                checksums: vec![],
+            })
+            .unwrap();
+        }
+
+        tokio::spawn(async move {
+            gen.run(tx).await.unwrap();
+        });
+
+        let mut sent = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                GeneratorMessage::File(job) => {
+                    sent.push((job.path.to_path_buf(), job.mtime, job.mtime_nsec))
+                }
+                GeneratorMessage::FileEnd { skipped, .. } => {
+                    assert_eq!(skipped, 2);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(sent, vec![(PathBuf::from("newer"), 1_700_000_000, 500)]);
+    }
+
+    #[tokio::test]
+    async fn test_generator_scope_rescans_only_dirty_paths() {
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir_all(tmp.path().join("a/new")).unwrap();
+        fs::write(tmp.path().join("a/new/file.txt"), "new").unwrap();
+        fs::create_dir(tmp.path().join("b")).unwrap();
+        fs::write(tmp.path().join("b/stale.txt"), "changed").unwrap();
+
+        let config = GeneratorConfig {
+            root: tmp.path().to_path_buf(),
+            include_hidden: false,
+            follow_symlinks: false,
+            delete_enabled: true,
+            delete_timing: DeleteTiming::default(),
+            max_delete: None,
+            index_memory: DEFAULT_INDEX_MEMORY,
+            file_flags: false,
+            perms: false,
+            fat: false,
+            owners: None,
+            xattrs: false,
+            acls: false,
+            hardlinks: false,
+            times: false,
+            exclude: FilterEngine::new(),
+            filter: FilterEngine::new(),
+            scope: Some(vec![PathBuf::from("a/new"), PathBuf::from("a/gone.txt")]),
+            cancel: CancellationToken::new(),
+        };
+        let (tx, mut rx) = crate::streaming::channel::file_job_channel();
+        let mut gen = Generator::new(config);
+        // Out of date, and only on the destination, but outside the scope
+        for path in ["a/gone.txt", "b/stale.txt", "c/other.txt"] {
+            gen.add_dest_entry(DestFileEntry {
+                path: path.to_string(),
+                size: 1,
+                mtime: 0,
+                mtime_nsec: 0,
+                mode: 0o644,
+                flags: DestFileFlags::empty(),
+                block_size: 0,
+                checksums: vec![],
+            })
+            .unwrap();
+        }
+
+        tokio::spawn(async move {
+            gen.run(tx).await.unwrap();
+        });
+
+        let mut sent = Vec::new();
+        let mut deleted = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                GeneratorMessage::File(job) => sent.push(job.path.to_path_buf()),
+                GeneratorMessage::Mkdir { path, .. } => sent.push(path.to_path_buf()),
+                GeneratorMessage::Delete { path, .. } => deleted.push(path.to_path_buf()),
+                GeneratorMessage::DeleteEnd { .. } => break,
+                _ => {}
+            }
+        }
+
+        assert_eq!(
+            sent,
+            vec![
+                PathBuf::from("a"),
+                PathBuf::from("a/new"),
+                PathBuf::from("a/new/file.txt")
+            ]
+        );
+        assert_eq!(deleted, vec![PathBuf::from("a/gone.txt")]);
+    }
+
+    #[tokio::test]
+    async fn test_generator_delete_timing_and_max_delete() {
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir(tmp.path().join("dir")).unwrap();
+        fs::write(tmp.path().join("dir/new.txt"), "new").unwrap();
+
+        let run = |delete_timing, max_delete| {
+            let config = GeneratorConfig {
+                root: tmp.path().to_path_buf(),
+                include_hidden: false,
+                follow_symlinks: false,
+                delete_enabled: true,
+                delete_timing,
+                max_delete,
+                index_memory: DEFAULT_INDEX_MEMORY,
+                file_flags: false,
+                perms: false,
+                fat: false,
+                owners: None,
+                xattrs: false,
+                acls: false,
+                hardlinks: false,
+                times: false,
+                exclude: FilterEngine::new(),
+                filter: FilterEngine::new(),
+                scope: None,
+                cancel: CancellationToken::new(),
+            };
+            let mut gen = Generator::new(config);
+            for path in ["gone.txt", "dir/gone.txt"] {
+                gen.add_dest_entry(DestFileEntry {
+                    path: path.to_string(),
+                    size: 1,
+                    mtime: 0,
+                    mtime_nsec: 0,
+                    mode: 0o644,
+                    flags: DestFileFlags::empty(),
+                    block_size: 0,
+                    checksums: vec![],
+                })
+                .unwrap();
+            }
+            async move {
+                let (tx, mut rx) = crate::streaming::channel::file_job_channel();
+                let result = tokio::spawn(gen.run(tx));
+                let mut order = Vec::new();
+                while let Some(msg) = rx.recv().await {
+                    match msg {
+                        GeneratorMessage::File(job) => order.push(job.path.to_path_buf()),
+                        GeneratorMessage::Delete { path, .. } => {
+                            order.push(PathBuf::from("-").join(path.as_ref()))
+                        }
+                        GeneratorMessage::FileEnd { .. } => order.push(PathBuf::from("end")),
+                        _ => {}
+                    }
+                }
+                (result.await.unwrap(), order)
+            }
+        };
+
+        let (_, order) = run(DeleteTiming::Before, None).await;
+        assert_eq!(
+            order[2..],
+            [PathBuf::from("dir/new.txt"), PathBuf::from("end")]
+        );
+        let (_, order) = run(DeleteTiming::During, None).await;
+        assert_eq!(
+            order,
+            [
+                PathBuf::from("-/gone.txt"),
+                PathBuf::from("-/dir/gone.txt"),
+                PathBuf::from("dir/new.txt"),
+                PathBuf::from("end")
+            ]
+        );
+        let (_, order) = run(DeleteTiming::After, Some(2)).await;
+        assert_eq!(
+            order[..2],
+            [PathBuf::from("dir/new.txt"), PathBuf::from("end")]
+        );
+        assert_eq!(order.len(), 4);
+
+        // Over the limit nothing is sent at all
+        let (result, order) = run(DeleteTiming::Before, Some(1)).await;
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<SyncError>(),
+            Some(SyncError::TooManyDeletes { count: 2, max: 1 })
+        ));
+        assert!(order.is_empty());
+    }
 }
@@ -0,0 +1,73 @@
+//! Per-session path interning.
+//!
+//! Every FILE_ENTRY, DATA and DATA_END frame carries the file's relative path,
+//! and a large file sends one DATA frame per chunk. Decoding each of those
+//! into a fresh String means a multi-million-file sync spends its time
+//! allocating and copying the same paths over and over.
+//!
+//! The Sender builds one `Arc<str>` per file that every message for it
+//! shares. The Receiver resolves incoming paths through a `PathTable`, so
+//! frames for a file that's in flight reuse the allocation made for its
+//! FILE_ENTRY, and the table drops the path again at DATA_END.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Paths of the files currently in flight
+#[derive(Debug, Default)]
+pub struct PathTable {
+    paths: HashSet<Arc<str>>,
+}
+
+impl PathTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shared copy of `path`, allocating only if it isn't in the table yet
+    pub fn intern(&mut self, path: &str) -> Arc<str> {
+        if let Some(existing) = self.paths.get(path) {
+            return Arc::clone(existing);
+        }
+        let path: Arc<str> = Arc::from(path);
+        self.paths.insert(Arc::clone(&path));
+        path
+    }
+
+    /// Forget a path once no more frames will refer to it
+    pub fn release(&mut self, path: &str) {
+        self.paths.remove(path);
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_shares_allocation() {
+        let mut table = PathTable::new();
+        let a = table.intern("dir/file.bin");
+        let b = table.intern("dir/file.bin");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(table.len(), 1);
+
+        let c = table.intern("other.bin");
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert_eq!(table.len(), 2);
+
+        // Outstanding handles stay valid after release
+        table.release("dir/file.bin");
+        assert_eq!(table.len(), 1);
+        assert_eq!(&*a, "dir/file.bin");
+        assert!(!Arc::ptr_eq(&a, &table.intern("dir/file.bin")));
+    }
+}
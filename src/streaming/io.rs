@@ -0,0 +1,954 @@
+//! File I/O options for the streaming Sender and Receiver.
+//!
+//! Large sequential transfers otherwise fill the page cache with data that's
+//! read or written exactly once, evicting the working set of anything else on
+//! the machine. `--drop-cache` hands pages back with POSIX_FADV_DONTNEED as
+//! they're done with; `--direct-io` bypasses the cache with O_DIRECT.
+//!
+//! `--index-memory` caps the destination checksums the Generator holds in RAM
+//! during a transfer; beyond it they're spilled to a temporary store on disk.
+//!
+//! Files of at least `--mmap-threshold` bytes are hashed through a memory map
+//! rather than read, both for the destination's block checksums and for the
+//! Sender's delta scan; `0` turns that off.
+//!
+//! `--fsync` trades durability for throughput: the default fsyncs each file
+//! before it's renamed into place, `batch` syncs the whole filesystem
+//! periodically and once more before the transfer reports success, and
+//! `never` leaves writeback to the OS.
+//!
+//! `--preallocate` reserves each incoming file's full size before the first
+//! write, so the filesystem can lay it out contiguously and a full disk fails
+//! the file up front instead of partway through. Sparse sources only get their
+//! length set, leaving holes unallocated.
+//!
+//! `--no-atomic` writes files that don't exist yet straight to their final
+//! path, skipping the temp file and rename. Seeding an empty destination
+//! then costs about half the metadata operations, but an interrupted transfer
+//! can leave a partial file behind under its real name until the next run.
+//!
+//! Temp files are normally renamed into place. When the temp file ends up on
+//! another filesystem than its target, the rename fails with EXDEV and
+//! `move_across_filesystems` copies it over instead.
+//!
+//! In-process local transfers skip the wire format for whole files and copy
+//! between descriptors with `copy_range` (copy_file_range on Linux, which can
+//! reflink or copy server-side on filesystems that support it). On macOS they
+//! are cloned with `clone_file` instead, which is instant on APFS.
+//!
+//! A delta's copies of unchanged blocks go through `copy_file_range_at` on
+//! Linux: aligned runs are reflinked with FICLONERANGE on Btrfs and XFS, the
+//! rest is copied in the kernel, and either way the data never passes
+//! through a userspace buffer.
+//!
+//! O_DIRECT needs block-aligned buffers, offsets and lengths. Full-file chunks
+//! are aligned except for the tail of a file, which goes through the regular
+//! buffered path. Filesystems that reject O_DIRECT (and non-Linux platforms)
+//! fall back to buffered I/O.
+
+use crate::cli::SymlinkMode;
+use crate::delta::StrongHash;
+use crate::streaming::dest_index::DEFAULT_INDEX_MEMORY;
+use crate::streaming::generator::DeleteTiming;
+use std::alloc::{self, Layout};
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::sync::Once;
+use std::time::Duration;
+
+/// Files at least this large are hashed through a memory map by default
+pub const DEFAULT_MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Alignment for O_DIRECT buffers, offsets and lengths
+pub const DIRECT_ALIGN: usize = 4096;
+
+/// When the Receiver forces written data to stable storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FsyncPolicy {
+    /// Never fsync; the OS writes data back in its own time
+    Never,
+
+    /// fsync every file before renaming it into place (default)
+    #[default]
+    PerFile,
+
+    /// Sync the destination filesystem periodically and before reporting success
+    Batch,
+}
+
+/// How the streaming pipeline touches files on disk and bounds its memory
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IoOptions {
+    /// Batch reads and writes through io_uring (`--io-uring`)
+    pub io_uring: bool,
+    /// Drop file pages from the page cache once read or written (`--drop-cache`)
+    pub drop_cache: bool,
+    /// Bypass the page cache with O_DIRECT (`--direct-io`)
+    pub direct_io: bool,
+    /// When written files are synced (`--fsync`)
+    pub fsync: FsyncPolicy,
+    /// Cap on destination checksums held in RAM (`--index-memory`)
+    pub index_memory: Option<u64>,
+    /// Delta block size for every file (`--block-size`) instead of one
+    /// that grows with the file
+    pub block_size: Option<u32>,
+    /// Smallest file hashed through a memory map (`--mmap-threshold`; 0
+    /// never maps)
+    pub mmap_threshold: Option<u64>,
+    /// Reserve disk space for incoming files before writing (`--preallocate`)
+    pub preallocate: bool,
+    /// Write new files in place instead of via temp file + rename (`--no-atomic`)
+    pub no_atomic: bool,
+    /// Keep what an interrupted transfer received of a file under its real
+    /// name, so the next run can delta from it (`--partial`)
+    pub partial: bool,
+    /// Fail a file whose read makes no progress for this long
+    /// (`--stall-timeout`)
+    pub stall_timeout: Option<Duration>,
+    /// Send again the files that failed with a transient error, up to this
+    /// many times (`--retries`); the client runs the retries, so the server
+    /// never gets it
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after (`--retry-delay`)
+    pub retry_delay: Duration,
+    /// Fail the whole sync at the first file that can't be written or sent
+    /// (`--stop-on-error`); otherwise each failure is reported and the rest
+    /// go on
+    pub stop_on_error: bool,
+    /// zstd level for compressed DATA (`--compress-level`)
+    pub compress_level: Option<i32>,
+    /// Cap on DATA sent per second, in bytes (`--bwlimit`); a pulling
+    /// server gets it on its command line
+    pub bwlimit: Option<u64>,
+    /// Files the Sender sends at once (`--transfers`, default 1); like
+    /// `bwlimit`, a pulling server gets it on its command line
+    pub transfers: Option<usize>,
+    /// What to do with symlinks the destination can't create (`--links`)
+    pub links: SymlinkMode,
+    /// When `--delete` sends its deletes (`--delete-before/-during/-after`);
+    /// a pulling server gets it on its command line
+    pub delete_timing: DeleteTiming,
+    /// Abort before deleting more entries than this (`--max-delete-count`); a
+    /// pulling server gets it on its command line too
+    pub max_delete: Option<u64>,
+    /// Carry BSD file flags (`--preserve-flags`); pulls ask the server for
+    /// them in the HELLO rather than on its command line
+    pub file_flags: bool,
+    /// Carry permission bits (`-p`); without it files go out as 0644 and
+    /// directories as 0755. Like `file_flags`, the server learns it from
+    /// the HELLO
+    pub perms: bool,
+    /// Keep the temp files of interrupted transfers and continue them next
+    /// time (on unless `--no-resume`); negotiated in the HELLO too
+    pub resume: bool,
+    /// Carry the owner's uid (`-o`) and the group's gid (`-g`), applied when
+    /// the destination runs as root; negotiated in the HELLO
+    pub owner: bool,
+    pub group: bool,
+    /// Carry extended attributes (`-X`); negotiated in the HELLO as well
+    pub xattrs: bool,
+    /// Carry POSIX ACLs (`-A`); negotiated in the HELLO as well
+    pub acls: bool,
+    /// Recreate hard links (`-H`); negotiated in the HELLO as well
+    pub hardlinks: bool,
+    /// Carry access and creation times (`--times=all`); negotiated in the
+    /// HELLO as well
+    pub all_times: bool,
+    /// Strong hash confirming delta blocks: xxh3-128 unless the peer only
+    /// knows xxh3-64; negotiated in the HELLO as well
+    pub strong_hash: StrongHash,
+    /// Hash each file on the sending end and check what was written
+    /// against it before committing (`--checksum-verify`); negotiated in
+    /// the HELLO as well
+    pub checksum_verify: bool,
+    /// Only report what would change (`--dry-run`): no file is read for
+    /// sending and nothing is written; negotiated in the HELLO, and a
+    /// server that doesn't echo it is never synced with for real
+    pub dry_run: bool,
+    /// Stage the whole transfer and move it into place only once all of it
+    /// arrived without errors (`--atomic`); a push negotiates it in the
+    /// HELLO
+    pub atomic: bool,
+    /// The destination is FAT/exFAT (`--fat`)
+    pub fat: bool,
+    /// Confine a Linux `--server` to its root with Landlock and seccomp
+    /// (`--sandbox`)
+    pub sandbox: bool,
+}
+
+impl IoOptions {
+    /// Flags forwarded to a remote `sy --server`
+    pub fn server_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        if self.io_uring {
+            args.push("--io-uring".into());
+        }
+        if self.drop_cache {
+            args.push("--drop-cache".into());
+        }
+        if self.direct_io {
+            args.push("--direct-io".into());
+        }
+        match self.fsync {
+            FsyncPolicy::PerFile => {}
+            FsyncPolicy::Never => args.extend(["--fsync".into(), "never".into()]),
+            FsyncPolicy::Batch => args.extend(["--fsync".into(), "batch".into()]),
+        }
+        if let Some(bytes) = self.index_memory {
+            args.extend(["--index-memory".into(), bytes.to_string()]);
+        }
+        if let Some(bytes) = self.block_size {
+            args.extend(["--block-size".into(), bytes.to_string()]);
+        }
+        if let Some(bytes) = self.mmap_threshold {
+            args.extend(["--mmap-threshold".into(), bytes.to_string()]);
+        }
+        if self.preallocate {
+            args.push("--preallocate".into());
+        }
+        if self.no_atomic {
+            args.push("--no-atomic".into());
+        }
+        if self.partial {
+            args.push("--partial".into());
+        }
+        if let Some(limit) = self.stall_timeout {
+            args.extend(["--stall-timeout".into(), limit.as_secs().to_string()]);
+        }
+        if self.stop_on_error {
+            args.push("--stop-on-error".into());
+        }
+        if let Some(level) = self.compress_level {
+            args.extend(["--compress-level".into(), level.to_string()]);
+        }
+        if let Some(limit) = self.bwlimit {
+            args.extend(["--bwlimit".into(), limit.to_string()]);
+        }
+        if let Some(n) = self.transfers {
+            args.extend(["--transfers".into(), n.to_string()]);
+        }
+        match self.links {
+            SymlinkMode::Preserve => {}
+            SymlinkMode::Follow => args.extend(["--links".into(), "follow".into()]),
+            SymlinkMode::Skip => args.extend(["--links".into(), "skip".into()]),
+        }
+        match self.delete_timing {
+            DeleteTiming::After => {}
+            DeleteTiming::Before => args.push("--delete-before".into()),
+            DeleteTiming::During => args.push("--delete-during".into()),
+        }
+        if let Some(max) = self.max_delete {
+            args.extend(["--max-delete-count".into(), max.to_string()]);
+        }
+        if self.fat {
+            args.push("--fat".into());
+        }
+        if self.sandbox {
+            args.push("--sandbox".into());
+        }
+        args
+    }
+
+    /// Checksum memory cap for the Generator's destination index
+    pub fn index_memory(&self) -> u64 {
+        self.index_memory.unwrap_or(DEFAULT_INDEX_MEMORY)
+    }
+
+    /// Delta block size for a destination file of `size` bytes: about the
+    /// square root of the size (so checksum lists stay short for large
+    /// files), unless `--block-size` fixes it
+    pub fn block_size(&self, size: u64) -> u32 {
+        self.block_size
+            .unwrap_or_else(|| crate::delta::calculate_block_size(size) as u32)
+    }
+
+    /// Whether a file of `size` bytes is hashed through a memory map
+    pub fn mmap(&self, size: u64) -> bool {
+        match self.mmap_threshold.unwrap_or(DEFAULT_MMAP_THRESHOLD) {
+            0 => false,
+            threshold => size >= threshold,
+        }
+    }
+
+    /// How many files the Sender has in flight at once
+    pub fn transfers(&self) -> usize {
+        self.transfers.unwrap_or(1).max(1)
+    }
+
+    /// Effective fsync policy (batching needs filesystem sync, which is Unix-only)
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        match self.fsync {
+            FsyncPolicy::Batch if !cfg!(unix) => FsyncPolicy::PerFile,
+            policy => policy,
+        }
+    }
+}
+
+/// Extend `file` to `len` bytes, reserving the blocks unless `sparse`
+///
+/// Uses fallocate on Linux; elsewhere, or where the filesystem doesn't
+/// support it, only the length is set. Running out of space is an error.
+pub fn preallocate(file: &File, len: u64, sparse: bool) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    if !sparse && len > 0 {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: plain syscall on a valid descriptor
+        let ret = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off_t) };
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if !matches!(
+            err.raw_os_error(),
+            Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS)
+        ) {
+            return Err(err);
+        }
+    }
+    let _ = sparse;
+    file.set_len(len)
+}
+
+/// Tell the kernel a range of `file` won't be needed again (`len` 0 = to EOF)
+///
+/// Only clean pages are dropped, so call this after data has been synced.
+/// Advisory: errors are ignored, and it's a no-op off Linux.
+#[cfg(unix)]
+pub fn drop_cache(file: &impl std::os::unix::io::AsFd, offset: u64, len: u64) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: plain syscall on a valid descriptor
+        unsafe {
+            libc::posix_fadvise(
+                file.as_fd().as_raw_fd(),
+                offset as libc::off_t,
+                len as libc::off_t,
+                libc::POSIX_FADV_DONTNEED,
+            );
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (file, offset, len);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn drop_cache<F>(_file: &F, _offset: u64, _len: u64) {}
+
+/// Flush all dirty data on the filesystem containing `path`
+///
+/// Linux uses syncfs(2); other Unixes fall back to a global sync(2).
+#[cfg(unix)]
+pub fn sync_filesystem(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        let dir = File::open(path)?;
+        // SAFETY: plain syscall on a valid descriptor
+        if unsafe { libc::syncfs(dir.as_raw_fd()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        // SAFETY: sync(2) takes no arguments and can't fail
+        unsafe { libc::sync() };
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn sync_filesystem(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "filesystem sync not supported",
+    ))
+}
+
+/// Open `path` with O_DIRECT for reading, or for writing to an existing file
+pub fn open_direct(path: &Path, write: bool) -> io::Result<File> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        std::fs::OpenOptions::new()
+            .read(!write)
+            .write(write)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (path, write);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "O_DIRECT is only supported on Linux",
+        ))
+    }
+}
+
+/// Copy `len` bytes from the start of `src` to the start of `dst`, returning bytes copied
+///
+/// Uses copy_file_range(2) on Linux, falling back to `std::io::copy` (itself
+/// sendfile/splice where available) when the kernel or filesystem refuses.
+/// Stops short only if `src` is shorter than `len`.
+pub fn copy_range(src: &File, dst: &File, len: u64) -> io::Result<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        let mut copied = 0u64;
+        while copied < len {
+            let chunk = (len - copied).min(1 << 30) as usize;
+            // SAFETY: plain syscall on valid descriptors, using their file offsets
+            let n = unsafe {
+                libc::copy_file_range(
+                    src.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    dst.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    chunk,
+                    0,
+                )
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                return match err.raw_os_error() {
+                    Some(libc::EXDEV | libc::ENOSYS | libc::EINVAL | libc::EOPNOTSUPP)
+                        if copied == 0 =>
+                    {
+                        copy_fallback(src, dst, len)
+                    }
+                    Some(libc::EINTR) => continue,
+                    _ => Err(err),
+                };
+            }
+            if n == 0 {
+                break;
+            }
+            copied += n as u64;
+        }
+        Ok(copied)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        copy_fallback(src, dst, len)
+    }
+}
+
+/// Copy `len` bytes of `src` from `src_offset` to `dst_offset` of `dst`
+/// without passing them through userspace
+///
+/// Block-aligned ranges are first cloned with FICLONERANGE, which shares the
+/// blocks on Btrfs and XFS; the rest goes through copy_file_range(2). Neither
+/// moves the files' offsets. Fails with `Unsupported`, having copied
+/// nothing, where the filesystem (or, off Linux, the platform) can't do
+/// either, so callers can copy through a buffer instead.
+pub fn copy_file_range_at(
+    src: &File,
+    src_offset: u64,
+    dst: &File,
+    dst_offset: u64,
+    len: u64,
+) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        if is_aligned(src_offset, len as usize) && dst_offset.is_multiple_of(DIRECT_ALIGN as u64) {
+            let range = libc::file_clone_range {
+                src_fd: src.as_raw_fd() as i64,
+                src_offset,
+                src_length: len,
+                dest_offset: dst_offset,
+            };
+            // SAFETY: valid descriptors and a live argument struct
+            if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONERANGE, &range) } == 0 {
+                return Ok(());
+            }
+        }
+
+        let mut off_in = src_offset as libc::loff_t;
+        let mut off_out = dst_offset as libc::loff_t;
+        let mut copied = 0u64;
+        while copied < len {
+            let chunk = (len - copied).min(1 << 30) as usize;
+            // SAFETY: plain syscall on valid descriptors and live offsets
+            let n = unsafe {
+                libc::copy_file_range(
+                    src.as_raw_fd(),
+                    &mut off_in,
+                    dst.as_raw_fd(),
+                    &mut off_out,
+                    chunk,
+                    0,
+                )
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                return match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    Some(libc::EXDEV | libc::ENOSYS | libc::EINVAL | libc::EOPNOTSUPP)
+                        if copied == 0 =>
+                    {
+                        Err(io::Error::new(io::ErrorKind::Unsupported, err))
+                    }
+                    _ => Err(err),
+                };
+            }
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Copy source ends before the range",
+                ));
+            }
+            copied += n as u64;
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (src, src_offset, dst, dst_offset, len);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "copy_file_range is only available on Linux",
+        ))
+    }
+}
+
+/// Replace `dst` with a copy-on-write clone of `src` (clonefile(2) on macOS,
+/// FICLONE on Linux)
+///
+/// The clone shares the source's blocks, so it's instant whatever the size.
+/// Fails on filesystems other than APFS, Btrfs and XFS, across volumes, and
+/// with `Unsupported` on other platforms, leaving `dst` untouched so callers
+/// can fall back to `copy_range`.
+pub fn clone_file(src: &File, dst: &Path, fsync: bool) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::AsRawFd;
+
+        // fclonefileat only creates new files, so clone next to `dst` and
+        // rename over it
+        let mut staged = dst.as_os_str().to_owned();
+        staged.push(".clone");
+        let staged = std::path::PathBuf::from(staged);
+        let c_path = CString::new(staged.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: valid descriptor and NUL-terminated path
+        if unsafe { libc::fclonefileat(src.as_raw_fd(), libc::AT_FDCWD, c_path.as_ptr(), 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let finish = || -> io::Result<()> {
+            if fsync {
+                File::open(&staged)?.sync_all()?;
+            }
+            std::fs::rename(&staged, dst)
+        };
+        finish().inspect_err(|_| {
+            let _ = std::fs::remove_file(&staged);
+        })
+    }
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let mut staged = dst.as_os_str().to_owned();
+        staged.push(".clone");
+        let staged = std::path::PathBuf::from(staged);
+        let clone = File::create(&staged)?;
+        let finish = || -> io::Result<()> {
+            // SAFETY: both descriptors are open for the duration of the call
+            if unsafe { libc::ioctl(clone.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if fsync {
+                clone.sync_all()?;
+            }
+            std::fs::rename(&staged, dst)
+        };
+        finish().inspect_err(|_| {
+            let _ = std::fs::remove_file(&staged);
+        })
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        let _ = (src, dst, fsync);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file cloning is only available on macOS and Linux",
+        ))
+    }
+}
+
+fn copy_fallback(src: &File, mut dst: &File, len: u64) -> io::Result<u64> {
+    use std::io::Read;
+    io::copy(&mut src.take(len), &mut dst)
+}
+
+/// Whether a rename failed because source and target are on different
+/// filesystems (EXDEV)
+pub fn is_cross_device(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        err.raw_os_error() == Some(libc::EXDEV)
+    }
+    #[cfg(windows)]
+    {
+        // ERROR_NOT_SAME_DEVICE
+        err.raw_os_error() == Some(17)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+/// Replace `dst` with `src` where a rename can't, across filesystems
+///
+/// `src` is copied to a staging file next to `dst`, fsynced, and renamed
+/// over `dst`, so `dst` still switches from the old contents to the new in
+/// one step; `src` is removed last. A crash in between leaves both copies,
+/// never a truncated `dst`.
+pub fn move_across_filesystems(src: &Path, dst: &Path) -> io::Result<()> {
+    let mut staged = dst.as_os_str().to_owned();
+    staged.push(".xdev");
+    let staged = PathBuf::from(staged);
+
+    let stage = || -> io::Result<()> {
+        std::fs::copy(src, &staged)?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&staged)?
+            .sync_all()?;
+        std::fs::rename(&staged, dst)
+    };
+    stage().inspect_err(|_| {
+        let _ = std::fs::remove_file(&staged);
+    })?;
+    std::fs::remove_file(src)
+}
+
+/// Whether a transfer at `offset` of `len` bytes can go through O_DIRECT
+pub fn is_aligned(offset: u64, len: usize) -> bool {
+    offset.is_multiple_of(DIRECT_ALIGN as u64) && len.is_multiple_of(DIRECT_ALIGN) && len > 0
+}
+
+/// Fill `buf` from `offset` of an O_DIRECT file, returning bytes read
+///
+/// Stops short only at end of file.
+#[cfg(unix)]
+pub fn read_direct(file: &File, buf: &mut AlignedBuf, offset: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = file.read_at(&mut buf[filled..], offset + filled as u64)?;
+        filled += n;
+        // A read that isn't a whole number of blocks means EOF, and the next
+        // offset wouldn't be aligned anyway
+        if n == 0 || !n.is_multiple_of(DIRECT_ALIGN) {
+            break;
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(not(unix))]
+pub fn read_direct(_file: &File, _buf: &mut AlignedBuf, _offset: u64) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "O_DIRECT not supported",
+    ))
+}
+
+/// Write `data` at `offset` of an O_DIRECT file (both must be aligned)
+#[cfg(unix)]
+pub fn write_direct(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    debug_assert!(is_aligned(offset, data.len()));
+    let buf = AlignedBuf::copy_from(data);
+    file.write_all_at(&buf[..data.len()], offset)
+}
+
+#[cfg(not(unix))]
+pub fn write_direct(_file: &File, _data: &[u8], _offset: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "O_DIRECT not supported",
+    ))
+}
+
+/// Warn (once per process) that `--direct-io` fell back to buffered I/O
+pub fn warn_direct_unsupported(path: &Path, err: &io::Error) {
+    static WARN: Once = Once::new();
+    WARN.call_once(|| {
+        tracing::warn!(
+            "O_DIRECT not supported for {} ({}), using buffered I/O",
+            path.display(),
+            err
+        );
+    });
+}
+
+/// Heap buffer aligned to `DIRECT_ALIGN`, as O_DIRECT requires
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// SAFETY: AlignedBuf uniquely owns its allocation, like Vec<u8>
+unsafe impl Send for AlignedBuf {}
+
+impl AlignedBuf {
+    /// Zeroed buffer of `len` bytes, rounded up to a multiple of `DIRECT_ALIGN`
+    pub fn new(len: usize) -> Self {
+        let len = len.max(1).next_multiple_of(DIRECT_ALIGN);
+        let layout = Self::layout(len);
+        // SAFETY: layout has non-zero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        Self { ptr, len }
+    }
+
+    /// Aligned copy of `data` (padded with zeros to the alignment)
+    pub fn copy_from(data: &[u8]) -> Self {
+        let mut buf = Self::new(data.len());
+        buf[..data.len()].copy_from_slice(data);
+        buf
+    }
+
+    fn layout(len: usize) -> Layout {
+        Layout::from_size_align(len, DIRECT_ALIGN).expect("valid aligned layout")
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: ptr is valid for len initialized bytes
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: ptr is valid for len initialized bytes and uniquely owned
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with the same layout
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.len)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_across_filesystems() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let src = tmp.path().join("f.sy.tmp");
+        let dst = tmp.path().join("f");
+        std::fs::write(&src, b"new").unwrap();
+        std::fs::write(&dst, b"old contents").unwrap();
+
+        move_across_filesystems(&src, &dst).unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), b"new");
+        assert!(!src.exists());
+        assert!(!tmp.path().join("f.xdev").exists());
+
+        // A failed copy leaves the target alone
+        assert!(move_across_filesystems(&src, &dst).is_err());
+        assert_eq!(std::fs::read(&dst).unwrap(), b"new");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_cross_device() {
+        assert!(is_cross_device(&io::Error::from_raw_os_error(libc::EXDEV)));
+        assert!(!is_cross_device(&io::Error::from_raw_os_error(
+            libc::ENOENT
+        )));
+    }
+
+    #[test]
+    fn test_aligned_buf() {
+        let buf = AlignedBuf::copy_from(b"hello");
+        assert_eq!(buf.len(), DIRECT_ALIGN);
+        assert_eq!(buf.as_ptr() as usize % DIRECT_ALIGN, 0);
+        assert_eq!(&buf[..5], b"hello");
+        assert!(buf[5..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_copy_range() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let content: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(tmp.path().join("src"), &content).unwrap();
+
+        let src = File::open(tmp.path().join("src")).unwrap();
+        let dst = File::create(tmp.path().join("dst")).unwrap();
+        assert_eq!(
+            copy_range(&src, &dst, content.len() as u64).unwrap(),
+            300_000
+        );
+        assert_eq!(std::fs::read(tmp.path().join("dst")).unwrap(), content);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_copy_file_range_at() {
+        use std::os::unix::fs::FileExt;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let content: Vec<u8> = (0..64 * 1024u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(tmp.path().join("src"), &content).unwrap();
+        let src = File::open(tmp.path().join("src")).unwrap();
+        let dst = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(tmp.path().join("dst"))
+            .unwrap();
+        dst.set_len(3 * DIRECT_ALIGN as u64).unwrap();
+
+        // Aligned (a clone where the filesystem can) and not
+        for (from, to, len) in [(8192, 0, 8192), (1000, 9000, 3000)] {
+            match copy_file_range_at(&src, from, &dst, to, len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => return,
+                Err(e) => panic!("{}", e),
+            }
+            let mut got = vec![0u8; len as usize];
+            dst.read_exact_at(&mut got, to).unwrap();
+            assert_eq!(got, content[from as usize..(from + len) as usize]);
+        }
+        assert!(copy_file_range_at(&src, 60 * 1024, &dst, 0, 8192).is_err());
+    }
+
+    #[test]
+    fn test_preallocate() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let file = File::create(tmp.path().join("full")).unwrap();
+        preallocate(&file, 1 << 20, false).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 1 << 20);
+
+        let file = File::create(tmp.path().join("sparse")).unwrap();
+        preallocate(&file, 1 << 20, true).unwrap();
+        let meta = file.metadata().unwrap();
+        assert_eq!(meta.len(), 1 << 20);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert!(meta.blocks() * 512 < 1 << 20);
+        }
+    }
+
+    #[test]
+    fn test_is_aligned() {
+        assert!(is_aligned(0, 256 * 1024));
+        assert!(is_aligned(8192, 4096));
+        assert!(!is_aligned(100, 4096));
+        assert!(!is_aligned(4096, 100));
+        assert!(!is_aligned(0, 0));
+    }
+
+    #[test]
+    fn test_server_args() {
+        assert!(IoOptions::default().server_args().is_empty());
+        let io = IoOptions {
+            io_uring: false,
+            drop_cache: true,
+            direct_io: true,
+            fsync: FsyncPolicy::Batch,
+            index_memory: Some(1048576),
+            block_size: Some(8192),
+            mmap_threshold: Some(0),
+            preallocate: true,
+            no_atomic: true,
+            partial: true,
+            stall_timeout: Some(Duration::from_secs(30)),
+            compress_level: Some(9),
+            bwlimit: Some(1048576),
+            transfers: Some(4),
+            links: SymlinkMode::Skip,
+            delete_timing: DeleteTiming::During,
+            max_delete: Some(100),
+            file_flags: true,
+            perms: true,
+            resume: true,
+            owner: true,
+            group: true,
+            xattrs: true,
+            acls: true,
+            hardlinks: true,
+            all_times: true,
+            strong_hash: StrongHash::Xxh3_64,
+            checksum_verify: true,
+            dry_run: true,
+            atomic: true,
+            fat: true,
+            sandbox: true,
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+            stop_on_error: true,
+        };
+        assert_eq!(
+            io.server_args(),
+            vec![
+                "--drop-cache",
+                "--direct-io",
+                "--fsync",
+                "batch",
+                "--index-memory",
+                "1048576",
+                "--block-size",
+                "8192",
+                "--mmap-threshold",
+                "0",
+                "--preallocate",
+                "--no-atomic",
+                "--partial",
+                "--stall-timeout",
+                "30",
+                "--stop-on-error",
+                "--compress-level",
+                "9",
+                "--bwlimit",
+                "1048576",
+                "--transfers",
+                "4",
+                "--links",
+                "skip",
+                "--delete-during",
+                "--max-delete-count",
+                "100",
+                "--fat",
+                "--sandbox"
+            ]
+        );
+        assert_eq!(io.index_memory(), 1048576);
+        assert_eq!(IoOptions::default().index_memory(), DEFAULT_INDEX_MEMORY);
+        assert_eq!(io.block_size(1 << 40), 8192);
+        assert_eq!(IoOptions::default().block_size(1 << 40), 128 * 1024);
+        assert_eq!(IoOptions::default().block_size(100_000_000), 10_000);
+        assert_eq!(IoOptions::default().block_size(64 * 1024), 512);
+        assert!(!io.mmap(1 << 40));
+        assert!(IoOptions::default().mmap(DEFAULT_MMAP_THRESHOLD));
+        assert!(!IoOptions::default().mmap(DEFAULT_MMAP_THRESHOLD - 1));
+        assert_eq!(io.transfers(), 4);
+        assert_eq!(IoOptions::default().transfers(), 1);
+    }
+}
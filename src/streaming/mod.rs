@@ -23,29 +23,54 @@
 
 #![allow(unused_imports, dead_code)]
 
+pub mod audit;
+pub mod backup;
+pub mod browse;
 pub mod channel;
+pub mod dest_index;
+pub mod dest_manifest;
+pub mod digest;
 pub mod generator;
+pub mod intern;
+pub mod io;
+pub mod owner;
 pub mod pipeline;
+pub mod policy;
 pub mod protocol;
 pub mod receiver;
 pub mod sender;
+pub mod storage;
+pub mod transfer_log;
+pub mod tuning;
+pub mod uring;
 
+pub use audit::{AuditLog, AuditOp};
+pub use backup::Backup;
+pub use browse::RemoteTree;
 pub use channel::{
-    DataChunk, DeltaInfo, DestFileState, DestIndex, FileJob, FileJobReceiver, FileJobSender,
-    GeneratorMessage, SyncDirection, SyncStats, DATA_CHUNK_SIZE, DELTA_MIN_SIZE,
-    GENERATOR_CHANNEL_SIZE, SENDER_CHANNEL_SIZE,
+    DataChunk, DeltaInfo, DestFileState, DestIndex, FileCounts, FileJob, FileJobReceiver,
+    FileJobSender, GeneratorMessage, PhaseClock, PhaseTimes, ProgressCounter, SyncDirection,
+    SyncStats, WouldBytes, DATA_CHUNK_SIZE, DELTA_MIN_SIZE, GENERATOR_CHANNEL_SIZE,
+    SENDER_CHANNEL_SIZE,
 };
 
-pub use generator::{Generator, GeneratorConfig};
-pub use pipeline::StreamingSync;
+pub use dest_manifest::DestManifest;
+pub use digest::{FileDigest, TransferDigest};
+pub use generator::{DeleteTiming, Generator, GeneratorConfig};
+pub use io::{FsyncPolicy, IoOptions};
+pub use owner::{IdMap, OwnerMap};
+pub use pipeline::{ProgressReport, StreamingSync};
+pub use policy::ReceivePolicy;
 pub use receiver::{Receiver, ReceiverConfig};
 pub use sender::{Sender, SenderConfig};
+pub use storage::{LocalStorage, Storage, StorageFile};
+pub use transfer_log::{Transfer, TransferLog};
 
 pub use protocol::{
     BlockChecksum, Data, DataEnd, DataFlags, Delete, DeleteEnd, DestFileEnd, DestFileEntry,
     DestFileFlags, Done, Error, ErrorCode, Fatal, FileEnd, FileEntry, FileFlags, Hello, HelloFlags,
-    MessageType, Mkdir, Progress, Symlink, Xattr, XattrEntry, PROTOCOL_VERSION,
-    PROTOCOL_VERSION_MAX, PROTOCOL_VERSION_MIN, PROTOCOL_VERSION_V1,
+    List, MessageType, Mkdir, Ownership, Progress, Read, SetAttr, SetAttrFlags, Symlink, Xattr,
+    XattrEntry, PROTOCOL_VERSION, PROTOCOL_VERSION_MAX, PROTOCOL_VERSION_MIN, PROTOCOL_VERSION_V1,
 };
 
 pub use protocol::{
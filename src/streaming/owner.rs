@@ -0,0 +1,82 @@
+//! Ownership mapping for `-o`/`-g` (`--usermap`, `--groupmap`)
+//!
+//! Owners travel as numeric ids. Only the client maps them: its Generator
+//! before sending (push, local) or its Receiver before applying (pull), so
+//! a server never learns about the maps.
+
+use crate::streaming::protocol::Ownership;
+use std::str::FromStr;
+
+/// Rewrites numeric ids: `FROM:TO` pairs where the first matching `FROM`
+/// wins and `*` matches any id; unmatched ids are kept
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMap(Vec<(Option<u32>, u32)>);
+
+impl IdMap {
+    pub fn map(&self, id: u32) -> u32 {
+        self.0
+            .iter()
+            .find(|(from, _)| from.is_none_or(|from| from == id))
+            .map_or(id, |&(_, to)| to)
+    }
+}
+
+impl FromStr for IdMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|pair| {
+                let (from, to) = pair
+                    .split_once(':')
+                    .ok_or_else(|| format!("Expected FROM:TO, got '{}'", pair))?;
+                let from = match from.trim() {
+                    "*" => None,
+                    from => Some(parse_id(from)?),
+                };
+                Ok((from, parse_id(to)?))
+            })
+            .collect::<Result<_, String>>()
+            .map(IdMap)
+    }
+}
+
+fn parse_id(s: &str) -> Result<u32, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("Invalid id '{}': expected a number", s.trim()))
+}
+
+/// The uid and gid maps of one sync
+#[derive(Debug, Clone, Default)]
+pub struct OwnerMap {
+    pub users: IdMap,
+    pub groups: IdMap,
+}
+
+impl OwnerMap {
+    pub fn apply(&self, owner: Ownership) -> Ownership {
+        Ownership {
+            uid: self.users.map(owner.uid),
+            gid: self.groups.map(owner.gid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_map_first_match_wins() {
+        let map: IdMap = "1000:2000, 0:65534,*:99".parse().unwrap();
+        assert_eq!(map.map(1000), 2000);
+        assert_eq!(map.map(0), 65534);
+        assert_eq!(map.map(42), 99);
+        assert_eq!(IdMap::default().map(42), 42);
+
+        assert!("1000".parse::<IdMap>().is_err());
+        assert!("alice:1000".parse::<IdMap>().is_err());
+        assert!("1000:".parse::<IdMap>().is_err());
+    }
+}
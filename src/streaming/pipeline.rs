@@ -1,24 +1,79 @@
 //! Streaming sync pipeline.
 //!
 //! Orchestrates Generator, Sender, and Receiver tasks.
+//!
+//! `push`/`pull` talk to a remote `sy --server` over a byte stream. `local`
+//! runs all three tasks in-process: whole files are copied straight between
+//! descriptors and only delta transfers, directories, symlinks and deletes go
+//! through frames.
 
+use crate::delta::StrongHash;
+use crate::error::SyncError;
+use crate::filter::FilterEngine;
 use crate::streaming::{
-    channel::{file_job_channel, SyncStats},
-    protocol::{read_frame, write_frame, Done, Hello, HelloFlags, MessageType},
+    audit::AuditLog,
+    backup::Backup,
+    channel::{
+        file_job_channel, is_cancelled, FileCounts, GeneratorMessage, PhaseClock, PhaseTimes,
+        ProgressCounter, SyncStats, WouldBytes,
+    },
+    dest_manifest::DestManifest,
+    io::IoOptions,
+    owner::OwnerMap,
+    policy::ReceivePolicy,
+    protocol::{
+        next_frame, read_frame, split_frames, write_frame, Demux, DestFileEnd, DestFileEntry, Done,
+        Error, ErrorCode, Fatal, FileEntry, FileFlags, Filter, Heartbeat, Hello, HelloFlags,
+        IdleReader, Log, MessageType, Progress, CHANNEL_DATA, IDLE_TIMEOUT,
+    },
+    storage::{LocalStorage, Storage},
+    transfer_log::TransferLog,
+    tuning::AutoTune,
     Generator, GeneratorConfig, Receiver, ReceiverConfig, Sender, SenderConfig,
 };
+use crate::sync::estimate::Estimate;
 use anyhow::Result;
 use bytes::Bytes;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+/// Called with each PROGRESS report of a push or pull
+pub type ProgressReport = Arc<dyn Fn(Progress) + Send + Sync>;
 
 /// Orchestrator for streaming sync
+#[derive(Clone)]
 pub struct StreamingSync {
     pub local_root: PathBuf,
     pub remote_root: PathBuf,
     pub delete_enabled: bool,
     pub compress: bool,
+    /// How local files are read and written
+    pub io: IoOptions,
+    /// Cancelling stops the transfer and returns the stats so far
+    pub cancel: CancellationToken,
+    /// Records destructive changes to the local destination (pull, local)
+    pub audit: Option<AuditLog>,
+    /// What the local destination refuses to receive (pull, local)
+    pub policy: ReceivePolicy,
+    /// Where the destination moves the old versions of files (`--backup`;
+    /// a push's server gets it on its command line)
+    pub backup: Option<Backup>,
+    /// Where the client caches the server's destination manifest (push)
+    pub dest_manifest: Option<PathBuf>,
+    /// Only these source paths are rescanned and synced (watch mode)
+    pub scope: Option<Vec<PathBuf>>,
+    /// Include/exclude rules, applied on both sides
+    pub filter: FilterEngine,
+    /// How uids and gids are mapped under `-o`/`-g`
+    pub owners: OwnerMap,
+    /// Where the server's PROGRESS reports go (push, pull)
+    pub progress: Option<ProgressReport>,
+    /// Where each file we send (push) or receive (pull, local) is recorded
+    pub transfer_log: Option<TransferLog>,
 }
 
 impl StreamingSync {
@@ -33,141 +88,629 @@ impl StreamingSync {
             remote_root,
             delete_enabled,
             compress,
+            io: IoOptions::default(),
+            cancel: CancellationToken::new(),
+            audit: None,
+            policy: ReceivePolicy::default(),
+            backup: None,
+            dest_manifest: None,
+            scope: None,
+            filter: FilterEngine::new(),
+            owners: OwnerMap::default(),
+            progress: None,
+            transfer_log: None,
+        }
+    }
+
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    /// Record deletes, overwrites and permission changes made to the local
+    /// destination (a push's destination is audited by the server)
+    pub fn with_audit(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Refuse entries `policy` denies when writing to the local destination
+    /// (a push's destination applies the server's own policy)
+    pub fn with_policy(mut self, policy: ReceivePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Move the old versions of files the local destination overwrites or
+    /// deletes as `backup` says (pull, local)
+    pub fn with_backup(mut self, backup: Option<Backup>) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    /// Have the server keep a manifest of the destination, cached by us at
+    /// `cache`, so later pushes skip unchanged files in the Initial Exchange
+    pub fn with_dest_manifest(mut self, cache: Option<PathBuf>) -> Self {
+        self.dest_manifest = cache;
+        self
+    }
+
+    /// Leave what `filter` excludes alone: it isn't sent, listed by the
+    /// destination nor deleted there. A server gets the rules in a FILTER
+    /// message; pulls from a server too old to apply them fail
+    pub fn with_filter(mut self, filter: FilterEngine) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Sync only `scope`, paths relative to the source with everything below
+    /// them, leaving the rest of the destination alone (pushes and local
+    /// syncs; `sy --watch` passes what changed since its last run)
+    pub fn with_scope(mut self, scope: Option<Vec<PathBuf>>) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Map owners and groups through `owners` (`--usermap`/`--groupmap`);
+    /// only used when `io` carries ownership
+    pub fn with_owner_map(mut self, owners: OwnerMap) -> Self {
+        self.owners = owners;
+        self
+    }
+
+    /// Ask the server for PROGRESS reports and pass them to `report`, a few
+    /// times a second while files are in flight
+    ///
+    /// A push fills in the totals from our own scan. Servers too old to
+    /// report progress just don't call it.
+    pub fn with_progress(mut self, report: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(report));
+        self
+    }
+
+    /// Record each file in `log` (`--log-file`): those we send in a push,
+    /// those we receive in a pull or local sync; a push's server keeps its
+    /// own
+    pub fn with_transfer_log(mut self, log: Option<TransferLog>) -> Self {
+        self.transfer_log = log;
+        self
+    }
+
+    /// Stop the sync when `cancel` is cancelled
+    ///
+    /// Tasks stop between messages, temp files of unfinished files are
+    /// removed (kept under their real name with `--partial`), no deletes are
+    /// sent after a partial scan, and the sync returns `Ok` with
+    /// `SyncStats::cancelled` set. Over the wire the peer gets a FATAL
+    /// instead of DONE, so it gives up on its unfinished files too.
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Generator over the local source, sending the metadata `io` carries
+    fn generator(&self, io: &IoOptions) -> Generator {
+        Generator::new(GeneratorConfig {
+            root: self.local_root.clone(),
+            include_hidden: true,
+            follow_symlinks: false,
+            delete_enabled: self.delete_enabled,
+            delete_timing: io.delete_timing,
+            max_delete: io.max_delete,
+            index_memory: io.index_memory(),
+            file_flags: io.file_flags,
+            perms: io.perms,
+            fat: io.fat,
+            owners: (io.owner || io.group).then(|| self.owners.clone()),
+            xattrs: io.xattrs,
+            acls: io.acls,
+            hardlinks: io.hardlinks,
+            times: io.all_times,
+            exclude: FilterEngine::new(),
+            filter: self.filter.clone(),
+            scope: self.scope.clone(),
+            cancel: self.cancel.clone(),
+        })
+    }
+
+    fn report(&self, progress: Progress) {
+        if let Some(report) = &self.progress {
+            report(progress);
         }
     }
 
+    /// HELLO flags asking for metadata the server has to agree to carry
+    fn metadata_flags(&self) -> HelloFlags {
+        let mut flags = HelloFlags::empty();
+        flags.set(HelloFlags::OWNER, self.io.owner);
+        flags.set(HelloFlags::GROUP, self.io.group);
+        flags.set(HelloFlags::XATTRS, self.io.xattrs);
+        flags.set(HelloFlags::ACLS, self.io.acls);
+        flags.set(HelloFlags::HARDLINKS, self.io.hardlinks);
+        flags.set(HelloFlags::TIMES, self.io.all_times);
+        flags.set(HelloFlags::VERIFY, self.io.checksum_verify);
+        flags
+    }
+
+    /// HELLO flags for the block checksum suite; a server that doesn't know
+    /// them syncs with xxh3-64, which needs no warning
+    fn checksum_flags(&self) -> HelloFlags {
+        match self.io.strong_hash {
+            StrongHash::Xxh3_64 => HelloFlags::empty(),
+            StrongHash::Xxh3_128 => HelloFlags::STRONG_128,
+        }
+    }
+
+    /// `io` less what the server didn't echo of the metadata and resume we
+    /// asked for; older servers sync without them
+    ///
+    /// A dry run is the exception: a server that can't do one would write
+    /// for real, so we stop instead.
+    fn negotiated_io(&self, server_hello: &Hello) -> Result<IoOptions> {
+        let echoed = |flag| server_hello.flags.contains(flag);
+        if self.io.dry_run && !echoed(HelloFlags::DRY_RUN) {
+            return Err(SyncError::Protocol(
+                "The server can't do a dry run (--dry-run); upgrade sy there".to_string(),
+            )
+            .into());
+        }
+        if !echoed(self.metadata_flags() - HelloFlags::ACLS) {
+            tracing::warn!(
+                "The server can't preserve ownership, xattrs, hard links or access and \
+                 creation times, or verify checksums \
+                 (-o/-g/-X/-H/--times=all/--checksum-verify); upgrade sy there"
+            );
+        }
+        if self.io.acls && !echoed(HelloFlags::ACLS) {
+            tracing::warn!(
+                "The server can't preserve ACLs (-A); it needs a newer sy built with ACL support"
+            );
+        }
+        Ok(IoOptions {
+            owner: self.io.owner && echoed(HelloFlags::OWNER),
+            group: self.io.group && echoed(HelloFlags::GROUP),
+            xattrs: self.io.xattrs && echoed(HelloFlags::XATTRS),
+            acls: self.io.acls && echoed(HelloFlags::ACLS),
+            hardlinks: self.io.hardlinks && echoed(HelloFlags::HARDLINKS),
+            all_times: self.io.all_times && echoed(HelloFlags::TIMES),
+            strong_hash: if echoed(self.checksum_flags()) {
+                self.io.strong_hash
+            } else {
+                StrongHash::Xxh3_64
+            },
+            checksum_verify: self.io.checksum_verify && echoed(HelloFlags::VERIFY),
+            resume: self.io.resume && echoed(HelloFlags::RESUME),
+            ..self.io
+        })
+    }
+
+    /// Send our rules to a server that echoed FILTER in its HELLO
+    async fn send_filter<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        server_hello: &Hello,
+    ) -> Result<()> {
+        if self.filter.is_empty() || !server_hello.flags.contains(HelloFlags::FILTER) {
+            return Ok(());
+        }
+        let filter = Filter {
+            rules: self.filter.rules(),
+        };
+        write_frame(writer, &filter.encode()).await?;
+        // The server reads it before its Initial Exchange
+        writer.flush().await?;
+        Ok(())
+    }
+
     /// Run a push sync (local -> remote).
-    pub async fn push<R, W>(&self, reader: &mut R, writer: &mut W) -> Result<SyncStats>
+    pub async fn push<R, W>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> crate::error::Result<SyncStats>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        Ok(self.run_push(reader, writer).await?)
+    }
+
+    #[tracing::instrument(name = "push", skip_all, fields(local = %self.local_root.display(), remote = %self.remote_root.display()))]
+    async fn run_push<R, W>(&self, reader: &mut R, writer: &mut W) -> Result<SyncStats>
     where
         R: AsyncRead + Unpin,
         W: AsyncWrite + Unpin,
     {
+        let mut clock = PhaseClock::start();
+        let mut phases = PhaseTimes::default();
+
         // 1. Send HELLO
-        let hello = Hello::new(
-            HelloFlags::empty(),
-            self.remote_root.to_string_lossy().into_owned(),
-        );
+        let mut flags = HelloFlags::LOG | HelloFlags::CHANNELS | HelloFlags::KEEPALIVE;
+        if self.compress {
+            flags |= HelloFlags::COMPRESSION;
+        }
+        if self.io.perms {
+            flags |= HelloFlags::PERMS;
+        }
+        if self.io.resume {
+            flags |= HelloFlags::RESUME;
+        }
+        if !self.filter.is_empty() {
+            flags |= HelloFlags::FILTER;
+        }
+        if self.io.dry_run {
+            flags |= HelloFlags::DRY_RUN;
+        }
+        // The server stages a push (a pull is staged on our side)
+        if self.io.atomic {
+            flags |= HelloFlags::ATOMIC;
+        }
+        if self.progress.is_some() {
+            flags |= HelloFlags::PROGRESS;
+        }
+        flags |= self.metadata_flags();
+        flags |= self.checksum_flags();
+        // A dry run leaves the manifest as it is, like everything else
+        let dest_manifest = self.dest_manifest.as_deref().filter(|_| !self.io.dry_run);
+        let cached = dest_manifest.map(|path| {
+            flags |= HelloFlags::DEST_MANIFEST;
+            DestManifest::load(path).unwrap_or_default()
+        });
+        let hello = Hello::new(flags, self.remote_root.to_string_lossy().into_owned())
+            .with_manifest(cached.as_ref().map_or(0, DestManifest::id));
         write_frame(writer, &hello.encode()).await?;
         writer.flush().await?;
 
         // 2. Receive HELLO response
         let (msg_type, payload) = read_frame(reader).await?;
-        if msg_type != MessageType::Hello {
-            anyhow::bail!("Expected Hello response, got {:?}", msg_type);
+        let server_hello = expect_hello(msg_type, payload)?;
+        // Older servers don't echo COMPRESSION and can't decompress DATA
+        let compress = self.compress && server_hello.flags.contains(HelloFlags::COMPRESSION);
+        // Older servers read every frame whole
+        let channels = server_hello.flags.contains(HelloFlags::CHANNELS);
+        let (mut idle, mut heartbeat) = keepalive(reader, &server_hello);
+        let reader = &mut idle;
+        // Older servers list everything; our Generator still leaves the
+        // filtered paths alone
+        self.send_filter(writer, &server_hello).await?;
+        let io = self.negotiated_io(&server_hello)?;
+        if io.atomic && !server_hello.flags.contains(HelloFlags::ATOMIC) {
+            return Err(SyncError::Protocol(
+                "The server can't apply a push atomically (--atomic); upgrade sy there".to_string(),
+            )
+            .into());
         }
-        let _server_hello = Hello::decode(payload)?;
 
         // 3. Receive DEST_FILE_ENTRY messages (Initial Exchange)
-        let mut generator = Generator::new(GeneratorConfig {
-            root: self.local_root.clone(),
-            include_hidden: true,
-            follow_symlinks: false,
-            delete_enabled: self.delete_enabled,
-        });
-
-        loop {
-            let (msg_type, payload) = read_frame(reader).await?;
-            match msg_type {
-                MessageType::DestFileEntry => {
-                    let entry = crate::streaming::protocol::DestFileEntry::decode(payload)?;
-                    generator.add_dest_entry(entry);
-                }
-                MessageType::DestFileEnd => {
-                    break;
-                }
-                MessageType::Fatal => {
-                    let fatal = crate::streaming::protocol::Fatal::decode(payload)?;
-                    anyhow::bail!("Remote fatal error: {}", fatal.message);
-                }
-                _ => {
-                    anyhow::bail!("Unexpected message during Initial Exchange: {:?}", msg_type);
+        let mut generator = self.generator(&io);
+        let exchange = async {
+            match (cached, dest_manifest) {
+                (Some(cached), Some(path)) => {
+                    receive_with_manifest(reader, &mut generator, cached, path).await
                 }
+                _ => receive_dest_entries(reader, |entry| generator.add_dest_entry(entry))
+                    .await
+                    .map(drop),
+            }
+        };
+        tokio::select! {
+            exchange = exchange => exchange?,
+            // The server may be hashing its tree for a while yet
+            _ = self.cancel.cancelled() => {
+                send_cancelled(writer).await;
+                return Ok(SyncStats {
+                    cancelled: true,
+                    duration: clock.elapsed(),
+                    ..Default::default()
+                });
             }
         }
+        phases.exchange = clock.lap();
 
         // 4. Run Generator and Sender
         let (tx, rx) = file_job_channel();
 
-        let gen_handle = tokio::spawn(async move { generator.run(tx).await });
+        // Our Generator knows the totals the server's PROGRESS leaves out
+        let found = Arc::new(ProgressCounter::default());
+        let generator = generator.with_progress(Arc::clone(&found));
+        let gen_handle = tokio::spawn(async move { generator.run(tx).await }.in_current_span());
 
+        let tune = Arc::new(AutoTune::new());
         let sender = Sender::new(SenderConfig {
             root: self.local_root.clone(),
-            compress: self.compress,
-        });
+            compress,
+            tune: Some(Arc::clone(&tune)),
+            cancel: self.cancel.clone(),
+            io,
+        })
+        .with_transfer_log(self.transfer_log.clone());
 
         // Use unbounded channel to avoid blocking_send (panics in tokio context)
         let (data_tx, mut data_rx) = mpsc::unbounded_channel::<Bytes>();
 
         // Spawn sender - uses unbounded_send which never blocks
-        let sender_handle = tokio::spawn(async move {
-            sender
-                .run(rx, |bytes| {
-                    data_tx
-                        .send(bytes)
-                        .map_err(|_| anyhow::anyhow!("Data channel closed"))
-                })
-                .await
-        });
+        let sender_handle = tokio::spawn(
+            async move {
+                sender
+                    .run(rx, |bytes| {
+                        data_tx
+                            .send(bytes)
+                            .map_err(|_| anyhow::anyhow!("Data channel closed"))
+                    })
+                    .await
+            }
+            .in_current_span(),
+        );
+
+        // The server's replies, read while we send so its PROGRESS never
+        // backs up: an ERROR for every entry that failed on either side,
+        // then DONE
+        let replies = async {
+            let mut errors = Vec::new();
+            loop {
+                let (msg_type, payload) = read_frame(reader).await?;
+                match msg_type {
+                    MessageType::Error => errors.push(Error::decode(payload)?),
+                    MessageType::Log => remote_log(payload)?,
+                    MessageType::Progress => {
+                        let progress = Progress::decode(payload)?;
+                        let totals = found.snapshot();
+                        self.report(Progress {
+                            files_total: totals.files_total,
+                            bytes_total: totals.bytes_total,
+                            ..progress
+                        });
+                    }
+                    _ => return Ok::<_, anyhow::Error>((errors, msg_type, payload)),
+                }
+            }
+        };
+        tokio::pin!(replies);
 
         // Pipe data to writer concurrently with sender
-        while let Some(bytes) = data_rx.recv().await {
-            writer.write_all(&bytes).await?;
-        }
+        let mut sent = SyncStats::new();
+        let transfer = async {
+            let mut file_end_sent = false;
+            // The server waits while we walk the source and hash what changed
+            while let Some(bytes) = heartbeat.wait(writer, data_rx.recv()).await? {
+                if self.cancel.is_cancelled() {
+                    break;
+                }
+                if channels {
+                    // Interrupted, the FATAL goes out between the pieces
+                    for part in split_frames(&bytes, CHANNEL_DATA) {
+                        if self.cancel.is_cancelled() {
+                            break;
+                        }
+                        writer.write_all(&part).await?;
+                    }
+                } else {
+                    writer.write_all(&bytes).await?;
+                }
+                tune.written(&bytes);
+                match bytes.get(4).copied() {
+                    Some(t) if t == MessageType::Data as u8 => {
+                        sent.bytes_transferred += bytes.len() as u64
+                    }
+                    Some(t) if t == MessageType::DataEnd as u8 => sent.files_ok += 1,
+                    Some(t) if t == MessageType::FileEnd as u8 => {
+                        phases.transfer = clock.lap();
+                        file_end_sent = true;
+                    }
+                    _ => {}
+                }
+            }
+            if self.cancel.is_cancelled() {
+                send_cancelled(writer).await;
+                gen_handle.abort();
+                sender_handle.abort();
+                return Ok(None);
+            }
+            if file_end_sent {
+                phases.delete = clock.lap();
+            }
 
-        // Send DONE (Wait, DONE is sent by Receiver)
-        // Wait, the Sender should send a final message to signal completion
-        // Protocol v2 uses DONE (0x10) from Receiver to Client.
-        // Client doesn't send DONE. It just finishes sending messages.
-        // But we should signal the server that we are done.
-        // Let's use DONE with 0 values or just close the stream?
-        // Actually, the protocol says DONE is from R->client.
-        // Maybe we need a message from client to server to say "I'm finished sending".
-        // Let's use Done message but client side.
-        let client_done = Done {
-            files_ok: 0,
-            files_err: 0,
-            bytes: 0,
-            duration_ms: 0,
-        };
-        write_frame(writer, &client_done.encode()).await?;
-        writer.flush().await?;
+            let (total_files, total_bytes, scan_time) = match gen_handle.await? {
+                Ok(totals) => totals,
+                Err(e) => {
+                    // Say why, e.g. --max-delete-count, so the server gives up
+                    // instead of waiting for the rest of the stream
+                    let err = SyncError::from(e);
+                    send_fatal(writer, err.error_code(), err.to_string()).await;
+                    sender_handle.abort();
+                    return Err(err.into());
+                }
+            };
+            let digest = sender_handle.await??;
+            phases.scan = scan_time;
+            phases.transfer = phases.transfer.saturating_sub(scan_time);
 
-        let (total_files, total_bytes) = gen_handle.await??;
-        sender_handle.await??;
+            // Signal end of stream; our digest lets the server check what it applied
+            let client_done = Done {
+                files_ok: 0,
+                files_err: 0,
+                bytes: 0,
+                duration_ms: clock.elapsed().as_millis() as u64,
+                digest: Some(digest.value()),
+                phases,
+                counts: FileCounts::default(),
+                would: WouldBytes::default(),
+                backed_up: 0,
+            };
+            write_frame(writer, &client_done.encode()).await?;
+            writer.flush().await?;
+            Ok::<_, anyhow::Error>(Some((total_files, total_bytes, digest)))
+        };
+        let sent_all = tokio::select! {
+            sent_all = transfer => sent_all?,
+            // Before our DONE, the server only answers when it gives up
+            reply = &mut replies => {
+                let (_, msg_type, payload) = reply?;
+                if msg_type == MessageType::Fatal {
+                    return Err(remote_fatal(payload));
+                }
+                return Err(SyncError::Protocol(format!(
+                    "Unexpected {:?} from the server during the transfer",
+                    msg_type
+                ))
+                .into());
+            }
+        };
+        let Some((total_files, total_bytes, digest)) = sent_all else {
+            sent.cancelled = true;
+            sent.duration = clock.elapsed();
+            return Ok(sent);
+        };
 
         // Finally receive DONE from server
-        let (msg_type, payload) = read_frame(reader).await?;
+        let (errors, msg_type, payload) = replies.await?;
+        if msg_type == MessageType::Fatal {
+            return Err(remote_fatal(payload));
+        }
         if msg_type == MessageType::Done {
             let done = Done::decode(payload)?;
+            // Older servers don't send one
+            if let Some(value) = done.digest {
+                digest.verify(value)?;
+            }
+            phases.merge(&done.phases);
+            let checksum_mismatches = errors
+                .iter()
+                .filter(|e| e.code == ErrorCode::ChecksumMismatch as u16)
+                .count() as u64;
             Ok(SyncStats {
                 files_ok: done.files_ok,
                 files_err: done.files_err,
                 bytes_transferred: done.bytes,
+                checksum_mismatches,
+                errors,
+                duration: clock.elapsed(),
+                phases,
+                counts: done.counts,
+                would: done.would,
+                backed_up: done.backed_up,
                 ..Default::default()
             })
         } else {
             Ok(SyncStats {
                 files_ok: total_files,
                 bytes_transferred: total_bytes,
+                duration: clock.elapsed(),
+                phases,
                 ..Default::default()
             })
         }
     }
 
+    /// Compare the local source against the remote destination without
+    /// sending anything (`--estimate`)
+    ///
+    /// The server skips block checksums and ends the session after its
+    /// Initial Exchange, so neither side reads file contents.
+    pub async fn estimate_push<R, W>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> crate::error::Result<Estimate>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        Ok(self.run_estimate_push(reader, writer).await?)
+    }
+
+    #[tracing::instrument(name = "estimate", skip_all, fields(local = %self.local_root.display(), remote = %self.remote_root.display()))]
+    async fn run_estimate_push<R, W>(&self, reader: &mut R, writer: &mut W) -> Result<Estimate>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let clock = PhaseClock::start();
+
+        let hello = Hello::new(
+            HelloFlags::LOG | HelloFlags::ESTIMATE,
+            self.remote_root.to_string_lossy().into_owned(),
+        );
+        write_frame(writer, &hello.encode()).await?;
+        writer.flush().await?;
+
+        let (msg_type, payload) = read_frame(reader).await?;
+        let _server_hello = expect_hello(msg_type, payload)?;
+
+        // Only comparing: no metadata goes anywhere and nothing is deleted
+        let mut generator = self.generator(&IoOptions {
+            owner: false,
+            group: false,
+            xattrs: false,
+            acls: false,
+            max_delete: None,
+            ..self.io
+        });
+        receive_dest_entries(reader, |entry| generator.add_dest_entry(entry)).await?;
+
+        let (tx, mut rx) = file_job_channel();
+        let gen_handle = tokio::spawn(async move { generator.run(tx).await }.in_current_span());
+        let mut estimate = Estimate::default();
+        while let Some(msg) = rx.recv().await {
+            estimate.record(&msg);
+        }
+        gen_handle.await??;
+
+        estimate.duration = clock.elapsed();
+        Ok(estimate)
+    }
+
     /// Run a pull sync (remote -> local).
-    pub async fn pull<R, W>(&self, reader: &mut R, writer: &mut W) -> Result<SyncStats>
+    pub async fn pull<R, W>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> crate::error::Result<SyncStats>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        Ok(self.run_pull(reader, writer).await?)
+    }
+
+    #[tracing::instrument(name = "pull", skip_all, fields(local = %self.local_root.display(), remote = %self.remote_root.display()))]
+    async fn run_pull<R, W>(&self, reader: &mut R, writer: &mut W) -> Result<SyncStats>
     where
         R: AsyncRead + Unpin,
         W: AsyncWrite + Unpin,
     {
+        let mut clock = PhaseClock::start();
+        let mut phases = PhaseTimes::default();
+
         // 1. Send HELLO with PULL flag
-        let mut flags = HelloFlags::PULL;
+        let mut flags =
+            HelloFlags::PULL | HelloFlags::LOG | HelloFlags::CHANNELS | HelloFlags::KEEPALIVE;
         if self.delete_enabled {
             flags |= HelloFlags::DELETE;
         }
+        if self.io.file_flags {
+            flags |= HelloFlags::FILE_FLAGS;
+        }
+        if self.io.perms {
+            flags |= HelloFlags::PERMS;
+        }
         if self.compress {
             flags |= HelloFlags::COMPRESSION;
         }
+        if self.io.resume {
+            flags |= HelloFlags::RESUME;
+        }
+        if !self.filter.is_empty() {
+            flags |= HelloFlags::FILTER;
+        }
+        if self.io.dry_run {
+            flags |= HelloFlags::DRY_RUN;
+        }
+        if self.progress.is_some() {
+            flags |= HelloFlags::PROGRESS;
+        }
+        flags |= self.metadata_flags();
+        flags |= self.checksum_flags();
 
         let hello = Hello::new(flags, self.remote_root.to_string_lossy().into_owned());
         write_frame(writer, &hello.encode()).await?;
@@ -175,10 +718,21 @@ impl StreamingSync {
 
         // 2. Receive HELLO response
         let (msg_type, payload) = read_frame(reader).await?;
-        if msg_type != MessageType::Hello {
-            anyhow::bail!("Expected Hello response, got {:?}", msg_type);
+        let server_hello = expect_hello(msg_type, payload)?;
+        // Older servers don't echo COMPRESSION and read checksums raw
+        let compress = self.compress && server_hello.flags.contains(HelloFlags::COMPRESSION);
+        let (mut idle, mut heartbeat) = keepalive(reader, &server_hello);
+        let reader = &mut idle;
+        // Older servers can't continue our temp files, so don't keep them
+        let io = self.negotiated_io(&server_hello)?;
+        // The server picks what to send, so it has to apply the rules
+        if !self.filter.is_empty() && !server_hello.flags.contains(HelloFlags::FILTER) {
+            return Err(SyncError::Protocol(
+                "The server can't apply include/exclude rules; upgrade sy there".to_string(),
+            )
+            .into());
         }
-        let _server_hello = Hello::decode(payload)?;
+        self.send_filter(writer, &server_hello).await?;
 
         // Ensure local root exists
         if !self.local_root.exists() {
@@ -189,50 +743,451 @@ impl StreamingSync {
         // Use unbounded channel to avoid blocking_send (panics in tokio context)
         let (data_tx, mut data_rx) = mpsc::unbounded_channel::<Bytes>();
         let receiver_root = self.local_root.clone();
+        let compress_checksums = compress;
+        let cancel = self.cancel.clone();
+        let (perms, resume, block_size) = (io.perms, io.resume, io.block_size);
+        // A dry run reports every differing file without comparing content
+        let checksums = !io.dry_run;
+        let filter = self.filter.clone();
+        let backup = self.backup.clone();
 
         // Spawn scanner - uses unbounded_send which never blocks
-        let scan_handle = tokio::spawn(async move {
-            let receiver = Receiver::new(ReceiverConfig {
-                root: receiver_root,
-                block_size: 4096,
-            });
-            receiver
-                .scan_dest(|bytes| {
-                    data_tx
-                        .send(bytes)
-                        .map_err(|_| anyhow::anyhow!("Data channel closed"))
+        let scan_handle = tokio::spawn(
+            async move {
+                let receiver = Receiver::new(ReceiverConfig {
+                    root: receiver_root,
+                    compress_checksums,
+                    cancel,
+                    io: IoOptions {
+                        perms,
+                        resume,
+                        block_size,
+                        ..Default::default()
+                    },
                 })
-                .await
-        });
+                .with_checksums(checksums)
+                .with_filter(filter)
+                .with_backup(backup);
+                receiver
+                    .scan_dest(|bytes| {
+                        data_tx
+                            .send(bytes)
+                            .map_err(|_| anyhow::anyhow!("Data channel closed"))
+                    })
+                    .await
+            }
+            .in_current_span(),
+        );
 
-        // Write data as it arrives (concurrent with scan)
-        while let Some(bytes) = data_rx.recv().await {
+        // Write data as it arrives (concurrent with scan); the server waits
+        // while we hash files with no checksums to send yet
+        while let Some(bytes) = heartbeat.wait(writer, data_rx.recv()).await? {
             writer.write_all(&bytes).await?;
         }
         writer.flush().await?;
+        if self.cancel.is_cancelled() {
+            // Stopped mid-scan; the server is waiting for the rest of it
+            scan_handle.abort();
+            send_cancelled(writer).await;
+            return Ok(SyncStats {
+                cancelled: true,
+                duration: clock.elapsed(),
+                ..Default::default()
+            });
+        }
 
         // Wait for scanner to complete
         scan_handle.await??;
+        phases.exchange = clock.lap();
 
         // 4. Receive and process streaming messages
         let mut receiver = Receiver::new(ReceiverConfig {
             root: self.local_root.clone(),
-            block_size: 4096,
-        });
+            compress_checksums: false,
+            cancel: self.cancel.clone(),
+            io,
+        })
+        .with_audit(self.audit.clone())
+        .with_policy(self.policy.clone())
+        .with_owner_map(self.owners.clone())
+        .with_backup(self.backup.clone())
+        .with_transfer_log(self.transfer_log.clone())
+        .with_compression(compress);
 
+        // Until the first message arrives the server is walking its source
+        let mut first = true;
+        let mut demux = Demux::new();
         loop {
-            let (msg_type, payload) = read_frame(reader).await?;
+            let (msg_type, payload) = tokio::select! {
+                frame = demux.read_frame(reader) => match frame {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        // The connection dropped: with resume, keep what arrived
+                        receiver.abort().await;
+                        return Err(e);
+                    }
+                },
+                _ = self.cancel.cancelled() => {
+                    send_cancelled(writer).await;
+                    return Ok(cancelled(&mut receiver, &clock).await);
+                }
+            };
 
+            if msg_type == MessageType::Log {
+                remote_log(payload)?;
+                continue;
+            }
+            if msg_type == MessageType::Progress {
+                self.report(Progress::decode(payload)?);
+                continue;
+            }
+            if std::mem::take(&mut first) {
+                phases.scan = clock.lap();
+            }
+            if msg_type == MessageType::FileEnd {
+                phases.transfer = clock.lap();
+            }
+            if msg_type == MessageType::Fatal {
+                receiver.abort().await;
+                return Err(remote_fatal(payload));
+            }
             if msg_type == MessageType::Done {
                 let done = Done::decode(payload)?;
+                if let Some(value) = done.digest {
+                    receiver.digest().verify(value)?;
+                }
+                receiver.finish().await?;
+                phases.delete = clock.lap();
+                phases.merge(&done.phases);
+                // File counts are our own: we saw every file land or fail
                 let mut stats = receiver.stats().clone();
-                stats.files_ok = done.files_ok;
-                stats.files_err = done.files_err;
                 stats.bytes_transferred = done.bytes;
+                stats.duration = clock.elapsed();
+                stats.phases = phases;
                 return Ok(stats);
             }
 
-            receiver.handle_message(msg_type, payload).await?;
+            match receiver.handle_message(msg_type, payload).await {
+                Err(e) if is_cancelled(&e) => {
+                    send_cancelled(writer).await;
+                    return Ok(cancelled(&mut receiver, &clock).await);
+                }
+                result => result?,
+            }
         }
     }
+
+    /// Run a sync between two local directories in-process
+    /// (`local_root` -> `remote_root`).
+    ///
+    /// The Initial Exchange, delta transfers, directories, symlinks and
+    /// deletes take the same path as over the wire. Files sent in full skip
+    /// DATA framing and are copied with copy_file_range instead, unless
+    /// `--direct-io` asks for O_DIRECT.
+    pub async fn local(&self) -> crate::error::Result<SyncStats> {
+        Ok(self.run_local(None::<LocalStorage>).await?)
+    }
+
+    /// Like `local`, but into `storage` instead of `remote_root`: a backend
+    /// that lists itself for the Initial Exchange (see `Storage::list`),
+    /// such as an object store. Every file goes through it whole
+    pub async fn local_to(
+        &self,
+        storage: impl Storage + 'static,
+    ) -> crate::error::Result<SyncStats> {
+        Ok(self.run_local(Some(storage)).await?)
+    }
+
+    #[tracing::instrument(name = "local", skip_all, fields(source = %self.local_root.display(), dest = %self.remote_root.display()))]
+    async fn run_local(&self, storage: Option<impl Storage + 'static>) -> Result<SyncStats> {
+        let mut clock = PhaseClock::start();
+        let mut phases = PhaseTimes::default();
+        // A storage backend's receiver only checks paths against its root,
+        // which stays empty
+        let empty_root = match storage {
+            Some(_) => Some(tempfile::tempdir()?),
+            None => None,
+        };
+        let root = match &empty_root {
+            Some(dir) => dir.path().to_path_buf(),
+            None => self.remote_root.clone(),
+        };
+        if !root.exists() {
+            tokio::fs::create_dir_all(&root).await?;
+        }
+
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root,
+            compress_checksums: false,
+            cancel: self.cancel.clone(),
+            io: self.io,
+        })
+        .with_audit(self.audit.clone())
+        .with_policy(self.policy.clone())
+        .with_filter(self.filter.clone())
+        .with_checksums(!self.io.dry_run)
+        .with_backup(self.backup.clone())
+        .with_transfer_log(self.transfer_log.clone());
+        if let Some(storage) = storage {
+            receiver = receiver.with_storage(storage);
+        }
+
+        // 1. Initial Exchange, decoded in place
+        let mut generator = self.generator(&self.io);
+        let scanned = receiver
+            .scan_dest(|mut batch| {
+                while let Some((msg_type, payload)) = next_frame(&mut batch)? {
+                    if msg_type == MessageType::DestFileEntry {
+                        generator.add_dest_entry(DestFileEntry::decode(payload)?)?;
+                    }
+                }
+                Ok(())
+            })
+            .await;
+        if self.cancel.is_cancelled() {
+            return Ok(cancelled(&mut receiver, &clock).await);
+        }
+        scanned?;
+        phases.exchange = clock.lap();
+
+        // 2. Generator feeds us; anything that isn't a whole-file copy goes
+        //    through the Sender and comes back as frames
+        let (tx, mut rx) = file_job_channel();
+        let gen_handle = tokio::spawn(async move { generator.run(tx).await }.in_current_span());
+
+        let (sender_tx, sender_rx) = file_job_channel();
+        let sender = Sender::new(SenderConfig {
+            root: self.local_root.clone(),
+            compress: false,
+            tune: None,
+            cancel: self.cancel.clone(),
+            io: self.io,
+        });
+        let (data_tx, mut data_rx) = mpsc::unbounded_channel::<Bytes>();
+        let sender_handle = tokio::spawn(
+            async move {
+                sender
+                    .run(sender_rx, |bytes| {
+                        data_tx
+                            .send(bytes)
+                            .map_err(|_| anyhow::anyhow!("Data channel closed"))
+                    })
+                    .await
+            }
+            .in_current_span(),
+        );
+
+        // --bwlimit only holds back, --checksum-verify only checks and a dry
+        // run only tallies what goes through the Sender
+        let fast_copy = !self.io.direct_io
+            && self.io.bwlimit.is_none()
+            && !self.io.checksum_verify
+            && !self.io.dry_run;
+        let mut sender_tx = Some(sender_tx);
+        let transfer = async {
+            loop {
+                tokio::select! {
+                    // Apply frames first so directories exist before their files
+                    biased;
+                    Some(mut frame) = data_rx.recv() => {
+                        while let Some((msg_type, payload)) = next_frame(&mut frame)? {
+                            if msg_type == MessageType::FileEnd {
+                                phases.transfer = clock.lap();
+                            }
+                            receiver.handle_message(msg_type, payload).await?;
+                        }
+                    }
+                    msg = rx.recv(), if sender_tx.is_some() => match msg {
+                        Some(GeneratorMessage::File(job)) if fast_copy && !job.need_delta && job.resume.is_none() && job.link_target.is_none() => {
+                            let entry = FileEntry {
+                                path: Arc::from(job.path.to_string_lossy()),
+                                size: job.size,
+                                mtime: job.mtime,
+                                mtime_nsec: job.mtime_nsec,
+                                mode: job.mode,
+                                inode: job.inode,
+                                flags: FileFlags::empty(),
+                                symlink_target: None,
+                                link_target: None,
+                                win_attrs: job.win_attrs,
+                                bsd_flags: job.bsd_flags,
+                                owner: job.owner,
+                                times: job.times,
+                            };
+                            receiver
+                                .copy_local(&self.local_root.join(job.path.as_ref()), entry)
+                                .await?;
+                        }
+                        Some(msg) => {
+                            if let Some(tx) = &sender_tx {
+                                tx.send(msg).await?;
+                            }
+                        }
+                        // Generator done: closing the Sender's input ends its frames
+                        None => sender_tx = None,
+                    },
+                    else => break,
+                }
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        let result = tokio::select! {
+            biased;
+            _ = self.cancel.cancelled() => Ok(()),
+            result = transfer => result,
+        };
+        match result {
+            Err(e) if !is_cancelled(&e) => return Err(e),
+            _ if self.cancel.is_cancelled() => {
+                gen_handle.abort();
+                sender_handle.abort();
+                return Ok(cancelled(&mut receiver, &clock).await);
+            }
+            _ => {}
+        }
+
+        let (_, _, scan_time) = gen_handle.await??;
+        let digest = sender_handle.await??;
+        receiver.digest().verify(digest.value())?;
+        receiver.finish().await?;
+        phases.delete = clock.lap();
+        phases.scan = scan_time;
+        phases.transfer = phases.transfer.saturating_sub(scan_time);
+
+        let mut stats = receiver.stats().clone();
+        stats.duration = clock.elapsed();
+        stats.phases = phases;
+        Ok(stats)
+    }
+}
+
+/// Stats for a sync stopped by its cancellation token, after giving up on
+/// the files it was in the middle of
+async fn cancelled(receiver: &mut Receiver, clock: &PhaseClock) -> SyncStats {
+    receiver.abort().await;
+    let mut stats = receiver.stats().clone();
+    stats.cancelled = true;
+    stats.duration = clock.elapsed();
+    stats
+}
+
+/// Tell the peer we stopped, so it gives up on its unfinished files rather
+/// than waiting for more; best effort, since it may be gone already
+async fn send_cancelled<W: AsyncWrite + Unpin>(writer: &mut W) {
+    send_fatal(writer, ErrorCode::Cancelled, "Sync interrupted".to_string()).await;
+}
+
+/// Tell the peer the sync failed and why; best effort, like `send_cancelled`
+async fn send_fatal<W: AsyncWrite + Unpin>(writer: &mut W, code: ErrorCode, message: String) {
+    let fatal = Fatal {
+        code: code as u16,
+        message,
+    };
+    if write_frame(writer, &fatal.encode()).await.is_ok() {
+        let _ = writer.flush().await;
+    }
+}
+
+/// Pass the peer's DEST_FILE_ENTRY messages to `on_entry`, up to DEST_FILE_END
+/// The server's end of the connection with an idle timeout, and our
+/// heartbeat, if it sends and reads KEEPALIVEs
+fn keepalive<'r, R: AsyncRead + Unpin>(
+    reader: &'r mut R,
+    server_hello: &Hello,
+) -> (IdleReader<&'r mut R>, Heartbeat) {
+    let enabled = server_hello.flags.contains(HelloFlags::KEEPALIVE);
+    (
+        IdleReader::new(reader, enabled.then_some(IDLE_TIMEOUT)),
+        Heartbeat::new(enabled),
+    )
+}
+
+async fn receive_dest_entries<R, F>(reader: &mut R, mut on_entry: F) -> Result<DestFileEnd>
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(DestFileEntry) -> Result<()>,
+{
+    loop {
+        let (msg_type, payload) = read_frame(reader).await?;
+        match msg_type {
+            MessageType::DestFileEntry => on_entry(DestFileEntry::decode(payload)?)?,
+            MessageType::DestFileEnd => return DestFileEnd::decode(payload),
+            MessageType::Fatal => return Err(remote_fatal(payload)),
+            MessageType::Log => remote_log(payload)?,
+            _ => {
+                return Err(SyncError::Protocol(format!(
+                    "Unexpected message during Initial Exchange: {:?}",
+                    msg_type
+                ))
+                .into());
+            }
+        }
+    }
+}
+
+/// Receive an Initial Exchange that may be changes against the manifest we
+/// cached at `path`, give `generator` the whole tree and cache the server's
+/// new manifest
+async fn receive_with_manifest<R>(
+    reader: &mut R,
+    generator: &mut Generator,
+    mut cached: DestManifest,
+    path: &Path,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut received = Vec::new();
+    let end = receive_dest_entries(reader, |entry| {
+        received.push(entry);
+        Ok(())
+    })
+    .await?;
+
+    let manifest = if end.base != 0 && end.base == cached.id() {
+        cached.apply(received, end.manifest);
+        cached
+    } else {
+        DestManifest::from_entries(received, end.manifest)
+    };
+    // A server that keeps no manifest (or couldn't save it) sends id 0
+    if end.manifest != 0 {
+        if let Err(e) = manifest.save(path) {
+            tracing::warn!("Failed to cache destination manifest: {}", e);
+        }
+    }
+
+    for entry in manifest.into_entries() {
+        generator.add_dest_entry(entry)?;
+    }
+    Ok(())
+}
+
+/// The server's HELLO response; a FATAL instead means it refused the session
+fn expect_hello(msg_type: MessageType, payload: Bytes) -> Result<Hello> {
+    match msg_type {
+        MessageType::Hello => Hello::decode(payload),
+        MessageType::Fatal => Err(remote_fatal(payload)),
+        _ => {
+            Err(SyncError::Protocol(format!("Expected Hello response, got {:?}", msg_type)).into())
+        }
+    }
+}
+
+fn remote_fatal(payload: Bytes) -> anyhow::Error {
+    match Fatal::decode(payload) {
+        Ok(fatal) => SyncError::remote(fatal.code, "", fatal.message).into(),
+        Err(e) => e,
+    }
+}
+
+/// Log a diagnostic the server forwarded as if it were our own
+fn remote_log(payload: Bytes) -> Result<()> {
+    let log = Log::decode(payload)?;
+    match log.level {
+        Log::ERROR => tracing::error!("remote: {}", log.message),
+        Log::WARN => tracing::warn!("remote: {}", log.message),
+        _ => tracing::info!("remote: {}", log.message),
+    }
+    Ok(())
 }
@@ -0,0 +1,136 @@
+//! What a destination refuses to receive, whatever the sending side asks
+//!
+//! Set by the destination itself: `[deny]` in the config file (and the
+//! `--deny*` flags for pulls). The Receiver checks every FILE_ENTRY and
+//! SYMLINK against it; a refused entry is skipped with a warning and counted
+//! as failed, and the rest of the transfer goes on.
+
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use std::path::Path;
+
+/// File type bits of a Unix mode
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Set-user-ID and set-group-ID bits
+const SETID_BITS: u32 = 0o6000;
+
+/// Deny rules for incoming entries
+#[derive(Debug, Clone, Default)]
+pub struct ReceivePolicy {
+    /// Refuse files with the setuid or setgid bit
+    pub deny_setuid: bool,
+    /// Refuse entries whose mode says device node, FIFO or socket
+    pub deny_devices: bool,
+    deny_paths: Vec<Pattern>,
+}
+
+impl ReceivePolicy {
+    /// Build a policy; `patterns` are globs for paths to refuse
+    ///
+    /// A pattern matches the whole relative path or any trailing part of it
+    /// that starts at a directory boundary, so `.ssh/authorized_keys` also
+    /// catches `home/alice/.ssh/authorized_keys` and `*.so` any `.so` file.
+    /// `*` doesn't cross `/`; `**` does.
+    pub fn new(deny_setuid: bool, deny_devices: bool, patterns: &[String]) -> Result<Self> {
+        let deny_paths = patterns
+            .iter()
+            .map(|p| Pattern::new(p).with_context(|| format!("Invalid deny pattern: {}", p)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            deny_setuid,
+            deny_devices,
+            deny_paths,
+        })
+    }
+
+    /// Whether nothing is denied
+    pub fn is_empty(&self) -> bool {
+        !self.deny_setuid && !self.deny_devices && self.deny_paths.is_empty()
+    }
+
+    /// Why a file with `mode` may not be written at `path`, if it may not
+    pub fn refuse_file(&self, path: &str, mode: u32) -> Option<String> {
+        let kind = mode & S_IFMT;
+        if self.deny_devices && kind != 0 && kind != S_IFREG && kind != S_IFLNK {
+            return Some(format!("special file (mode {:o})", mode));
+        }
+        if self.deny_setuid && mode & SETID_BITS != 0 {
+            return Some(format!("setuid/setgid file (mode {:o})", mode));
+        }
+        self.refuse_path(path)
+    }
+
+    /// Why nothing may be created at `path`, if it may not
+    pub fn refuse_path(&self, path: &str) -> Option<String> {
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        // The whole path, then each trailing part of it
+        let mut components = Path::new(path).components();
+        loop {
+            let suffix = components.as_path();
+            if suffix.as_os_str().is_empty() {
+                return None;
+            }
+            if let Some(pattern) = self
+                .deny_paths
+                .iter()
+                .find(|p| p.matches_path_with(suffix, options))
+            {
+                return Some(format!("matches deny pattern {}", pattern.as_str()));
+            }
+            components.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refuse_path_patterns() {
+        let policy = ReceivePolicy::new(
+            false,
+            false,
+            &[".ssh/authorized_keys".to_string(), "*.so".to_string()],
+        )
+        .unwrap();
+
+        assert!(policy.refuse_path(".ssh/authorized_keys").is_some());
+        assert!(policy
+            .refuse_path("home/alice/.ssh/authorized_keys")
+            .is_some());
+        assert!(policy.refuse_path("lib/libevil.so").is_some());
+        assert!(policy.refuse_path("libevil.so").is_some());
+
+        assert!(policy.refuse_path(".ssh/known_hosts").is_none());
+        assert!(policy.refuse_path("x.ssh/authorized_keys").is_none());
+        assert!(policy.refuse_path("lib/libevil.so.1").is_none());
+        assert!(ReceivePolicy::new(false, false, &["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_refuse_file_modes() {
+        let policy = ReceivePolicy::new(true, true, &[]).unwrap();
+
+        assert!(policy.refuse_file("a", 0o100644).is_none());
+        // Modes without type bits are plain files
+        assert!(policy.refuse_file("a", 0o755).is_none());
+        assert!(policy.refuse_file("a", 0o104755).is_some());
+        assert!(policy.refuse_file("a", 0o2755).is_some());
+        assert!(policy.refuse_file("a", 0o020644).is_some()); // character device
+        assert!(policy.refuse_file("a", 0o060644).is_some()); // block device
+        assert!(policy.refuse_file("a", 0o010644).is_some()); // FIFO
+
+        let open = ReceivePolicy::default();
+        assert!(open.is_empty());
+        assert!(open.refuse_file("a", 0o104755).is_none());
+        assert!(open.refuse_file("a", 0o020644).is_none());
+    }
+}
@@ -3,9 +3,24 @@
 //! Clean break from v1 - no backward compatibility.
 //! Unidirectional streaming with no ACKs in critical path.
 
+use crate::streaming::channel::{FileCounts, PhaseTimes, WouldBytes};
+use crate::streaming::intern::PathTable;
 use anyhow::{Context, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// Bail out with a `SyncError::Protocol`: the peer sent something we can't decode
+macro_rules! malformed {
+    ($($arg:tt)*) => {
+        return Err(crate::error::SyncError::Protocol(format!($($arg)*)).into())
+    };
+}
 
 /// Protocol version 2 (streaming)
 pub const PROTOCOL_VERSION: u16 = 2;
@@ -22,6 +37,10 @@ pub const PROTOCOL_VERSION_MAX: u16 = 2;
 /// Wire format: all multi-byte integers are big-endian
 /// Strings are length-prefixed (u16 len + UTF-8)
 /// Frame format: len:u32 | type:u8 | payload
+///
+/// Every message has `encode()`, returning a standalone frame, and
+/// `encode_into()`, appending the frame to a caller-owned buffer so batching
+/// paths can reuse one allocation across many small messages.
 
 // =============================================================================
 // Message Types
@@ -46,6 +65,14 @@ pub enum MessageType {
     Fatal = 0x0E,
     Xattr = 0x0F,
     Done = 0x10,
+    Log = 0x11,
+    List = 0x12,
+    Read = 0x13,
+    SetAttr = 0x14,
+    Filter = 0x15,
+    Acl = 0x16,
+    Part = 0x17,
+    Keepalive = 0x18,
 }
 
 impl MessageType {
@@ -67,6 +94,14 @@ impl MessageType {
             0x0E => Some(Self::Fatal),
             0x0F => Some(Self::Xattr),
             0x10 => Some(Self::Done),
+            0x11 => Some(Self::Log),
+            0x12 => Some(Self::List),
+            0x13 => Some(Self::Read),
+            0x14 => Some(Self::SetAttr),
+            0x15 => Some(Self::Filter),
+            0x16 => Some(Self::Acl),
+            0x17 => Some(Self::Part),
+            0x18 => Some(Self::Keepalive),
             _ => None,
         }
     }
@@ -82,9 +117,93 @@ bitflags::bitflags! {
         const PULL = 1 << 0;
         const DELETE = 1 << 1;
         const CHECKSUM = 1 << 2;
+        /// zstd-compress DATA payloads (`--compress`). The client asks; a
+        /// server that can decompress them echoes it in its HELLO, and a
+        /// push only compresses when it does. A pulling server compresses
+        /// whenever asked, so compressed pulls need a client that knows it
         const COMPRESSION = 1 << 3;
+        /// Extended attributes are preserved (`-X`): a server that applies
+        /// or sends them echoes the flag, and only then does each file or
+        /// directory with xattrs get an XATTR after its DATA_END or MKDIR
         const XATTRS = 1 << 4;
+        /// POSIX ACLs are preserved (`-A`): like XATTRS, but the server only
+        /// echoes it when built with ACL support, and the frames are ACLs
         const ACLS = 1 << 5;
+        /// Pull: the server includes BSD file flags in its file entries
+        const FILE_FLAGS = 1 << 6;
+        /// The client shows LOG frames; without it the server keeps its
+        /// diagnostics on stderr
+        const LOG = 1 << 7;
+        /// Push: the client only compares (`--estimate`), so the server
+        /// sends its Initial Exchange without block checksums and then
+        /// ends the session
+        const ESTIMATE = 1 << 8;
+        /// Push: the server keeps a manifest of its tree between sessions
+        /// (`--dest-manifest`), reusing the checksums of unchanged files
+        const DEST_MANIFEST = 1 << 9;
+        /// The client browses the tree read-only (`sy mount`): it sends
+        /// LIST and READ requests, one at a time, until it hangs up
+        const BROWSE = 1 << 10;
+        /// Permissions are preserved (`-p`): the source sends real modes,
+        /// the destination reports its own in the Initial Exchange, and a
+        /// mode-only difference is fixed with SET_ATTR
+        const PERMS = 1 << 11;
+        /// Interrupted transfers leave their temp files behind, which the
+        /// destination reports in the Initial Exchange (DestFileFlags::PARTIAL)
+        /// so the source can continue them (FileFlags::RESUME)
+        const RESUME = 1 << 12;
+        /// The client has include/exclude rules: a server that applies
+        /// them echoes the flag and reads a FILTER right after its HELLO,
+        /// then leaves what they exclude out of its scans and deletes
+        const FILTER = 1 << 13;
+        /// Ownership is preserved (`-o` for the uid, `-g` for the gid): a
+        /// server that can apply or report it echoes the flags, and only
+        /// then do file entries, MKDIRs and SYMLINKs carry an Ownership
+        const OWNER = 1 << 14;
+        const GROUP = 1 << 15;
+        /// Hard links are preserved (`-H`): a server that creates them
+        /// echoes the flag, and only then does a later path to an inode
+        /// already sent come as a FileFlags::HARDLINK entry naming the
+        /// first, with no DATA or DATA_END
+        const HARDLINKS = 1 << 16;
+        /// Files are checked end to end (`--checksum-verify`): a server
+        /// that verifies or hashes them echoes the flag, and only then do
+        /// DATA_ENDs carry a hash of the source file for the receiving end
+        /// to compare with what it wrote before committing
+        const VERIFY = 1 << 17;
+        /// Nothing is written (`--dry-run`): the sending end sends a bare
+        /// FILE_ENTRY per file, with no DATA or DATA_END, and the receiving
+        /// end only tallies what each message would change. A server that
+        /// can do that echoes the flag; the client stops if it doesn't
+        const DRY_RUN = 1 << 18;
+        /// The client shows progress: a server that reports it echoes the
+        /// flag and sends PROGRESS frames every PROGRESS_INTERVAL or so
+        /// while files are in flight, before its ERRORs and DONE
+        const PROGRESS = 1 << 19;
+        /// A push is applied all or nothing (`--atomic`): the server stages
+        /// it and only moves it into place after a DONE with no errors. A
+        /// server that can echoes the flag; the client stops if it doesn't
+        const ATOMIC = 1 << 20;
+        /// Frames longer than PART_SIZE may be split into PARTs, so LOG,
+        /// PROGRESS and FATAL frames can go out between the pieces of a
+        /// multi-megabyte DATA. Pull: the client asks and the server splits
+        /// what it sends. Push: the server echoes it and the client splits
+        const CHANNELS = 1 << 21;
+        /// Each end sends a KEEPALIVE every KEEPALIVE_INTERVAL while it's
+        /// busy (scanning, hashing, committing) or waiting, and gives up
+        /// once the other has been silent for IDLE_TIMEOUT. The client
+        /// asks; a server that sends them echoes it
+        const KEEPALIVE = 1 << 22;
+        /// Access and creation times are preserved (`--times=all`): a
+        /// server that echoes the flag reads or applies them, and only then
+        /// do file entries carry Times
+        const TIMES = 1 << 23;
+        /// Delta blocks are confirmed with xxh3-128 instead of xxh3-64: a
+        /// server that hashes with it echoes the flag, and only then do
+        /// DEST_FILE_ENTRYs carry DestFileFlags::STRONG_128 checksums and
+        /// delta DATA_ENDs a hash of the whole source file, which the
+        /// receiving end checks the rebuilt file against before committing
+        const STRONG_128 = 1 << 24;
     }
 }
 
@@ -100,6 +219,13 @@ bitflags::bitflags! {
         const HARDLINK = 1 << 2;
         const HAS_XATTRS = 1 << 3;
         const SPARSE = 1 << 4;
+        /// Windows FILE_ATTRIBUTE bits follow the link targets (u32)
+        const WIN_ATTRS = 1 << 5;
+        /// BSD file flags follow the Windows attributes (u32)
+        const BSD_FLAGS = 1 << 6;
+        /// Continue the destination's partial temp file: it already holds
+        /// the file up to the first DATA offset
+        const RESUME = 1 << 7;
     }
 }
 
@@ -112,6 +238,32 @@ bitflags::bitflags! {
     pub struct DestFileFlags: u8 {
         const DIR = 1 << 0;
         const HAS_CHECKSUMS = 1 << 1;
+        /// Checksum array is zstd-compressed (columnar layout, see
+        /// `pack_checksums`). Only meaningful with HAS_CHECKSUMS.
+        const CHECKSUMS_ZSTD = 1 << 2;
+        /// Manifest delta: the path is gone since the manifest the client
+        /// already has (no other fields are meaningful)
+        const REMOVED = 1 << 3;
+        /// A temp file left by an interrupted transfer; its checksums
+        /// cover what was written so far
+        const PARTIAL = 1 << 4;
+        /// Strong checksums are xxh3-128, 16 bytes each instead of 8
+        /// (HelloFlags::STRONG_128)
+        const STRONG_128 = 1 << 5;
+    }
+}
+
+// =============================================================================
+// Set Attr Flags
+// =============================================================================
+
+bitflags::bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct SetAttrFlags: u8 {
+        /// Apply `mode`
+        const MODE = 1 << 0;
+        /// Apply `mtime`
+        const MTIME = 1 << 1;
     }
 }
 
@@ -122,6 +274,7 @@ bitflags::bitflags! {
 bitflags::bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
     pub struct DataFlags: u8 {
+        /// The payload is zstd-compressed
         const COMPRESSED = 1 << 0;
         const DELTA = 1 << 1;
         const FINAL = 1 << 2;
@@ -140,6 +293,11 @@ pub enum ErrorCode {
     NotFound = 3,
     ChecksumMismatch = 4,
     DiskFull = 5,
+    /// Malformed or unexpected frame, or a version mismatch
+    Protocol = 6,
+    Cancelled = 7,
+    /// A source file was deleted between the scan and the read
+    Vanished = 8,
 }
 
 impl ErrorCode {
@@ -150,9 +308,19 @@ impl ErrorCode {
             3 => Some(Self::NotFound),
             4 => Some(Self::ChecksumMismatch),
             5 => Some(Self::DiskFull),
+            6 => Some(Self::Protocol),
+            7 => Some(Self::Cancelled),
+            8 => Some(Self::Vanished),
             _ => None,
         }
     }
+
+    /// Whether the same file may well go through if sent again
+    /// (`--retries`): a read or write that failed, e.g. on a flaky network
+    /// filesystem, as opposed to a refusal or a full disk
+    pub fn is_transient(self) -> bool {
+        self == Self::IoError
+    }
 }
 
 // =============================================================================
@@ -164,6 +332,9 @@ pub struct Hello {
     pub version: u16,
     pub flags: HelloFlags,
     pub root_path: String,
+    /// Push with DEST_MANIFEST: id of the server's manifest the client has
+    /// cached (0 for none); older peers don't send it
+    pub manifest: u128,
 }
 
 impl Hello {
@@ -172,17 +343,29 @@ impl Hello {
             version: PROTOCOL_VERSION,
             flags,
             root_path: root_path.into(),
+            manifest: 0,
         }
     }
 
+    pub fn with_manifest(mut self, manifest: u128) -> Self {
+        self.manifest = manifest;
+        self
+    }
+
     pub fn is_pull(&self) -> bool {
         self.flags.contains(HelloFlags::PULL)
     }
 
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.root_path.as_bytes();
-        let payload_len = 2 + 4 + 2 + path_bytes.len();
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        let payload_len = 2 + 4 + 2 + path_bytes.len() + 16;
+        buf.reserve(5 + payload_len);
 
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::Hello as u8);
@@ -190,45 +373,187 @@ impl Hello {
         buf.put_u32(self.flags.bits());
         buf.put_u16(path_bytes.len() as u16);
         buf.put_slice(path_bytes);
-
-        buf.freeze()
+        buf.put_u128(self.manifest);
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 8 {
-            anyhow::bail!("Hello payload too short");
+            malformed!("Hello payload too short");
         }
         let version = payload.get_u16();
         let flags = HelloFlags::from_bits_truncate(payload.get_u32());
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len {
-            anyhow::bail!("Hello path truncated");
+            malformed!("Hello path truncated");
         }
         let root_path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
             .context("Invalid UTF-8 in Hello path")?;
+        let manifest = if payload.remaining() >= 16 {
+            payload.get_u128()
+        } else {
+            0
+        };
 
         Ok(Self {
             version,
             flags,
             root_path,
+            manifest,
         })
     }
 }
 
+/// Take a `len`-byte path off the front of `payload`, interning it if a
+/// table is given
+fn get_path(
+    payload: &mut Bytes,
+    len: usize,
+    paths: Option<&mut PathTable>,
+) -> std::result::Result<Arc<str>, std::str::Utf8Error> {
+    let bytes = payload.split_to(len);
+    let path = std::str::from_utf8(&bytes)?;
+    Ok(match paths {
+        Some(paths) => paths.intern(path),
+        None => Arc::from(path),
+    })
+}
+
 // =============================================================================
 // FILE_ENTRY (0x02)
 // =============================================================================
 
+/// Numeric owner of an entry (`-o`/`-g`)
+///
+/// Trails the entries that carry it, after every other field but the mtime's
+/// nanoseconds, so it needs no flag bit: it's there when 8 more bytes are
+/// (once Times, if any, are read). Only sent to a peer that echoed
+/// HelloFlags::OWNER or GROUP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ownership {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Ownership {
+    pub const SIZE: usize = 8;
+
+    fn encoded_len(owner: Option<Ownership>) -> usize {
+        owner.map_or(0, |_| Self::SIZE)
+    }
+
+    fn put(owner: Option<Ownership>, buf: &mut BytesMut) {
+        if let Some(owner) = owner {
+            buf.put_u32(owner.uid);
+            buf.put_u32(owner.gid);
+        }
+    }
+
+    fn get(payload: &mut Bytes) -> Option<Ownership> {
+        (payload.remaining() >= Self::SIZE).then(|| Ownership {
+            uid: payload.get_u32(),
+            gid: payload.get_u32(),
+        })
+    }
+}
+
+/// Access and creation times of a file, under `--times=all`
+///
+/// Follow a FILE_ENTRY's BSD flags, ahead of the Ownership: at 24 bytes they
+/// outnumber everything after them, so they're there when that many more
+/// bytes are. Only sent to a peer that echoed HelloFlags::TIMES.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Times {
+    pub atime: i64,
+    pub atime_nsec: u32,
+    /// Creation time; 0 (and 0 nanoseconds) where the source has none
+    pub btime: i64,
+    pub btime_nsec: u32,
+}
+
+impl Times {
+    pub const SIZE: usize = 24;
+
+    fn encoded_len(times: Option<Times>) -> usize {
+        times.map_or(0, |_| Self::SIZE)
+    }
+
+    fn put(times: Option<Times>, buf: &mut BytesMut) {
+        if let Some(times) = times {
+            buf.put_i64(times.atime);
+            buf.put_u32(times.atime_nsec);
+            buf.put_i64(times.btime);
+            buf.put_u32(times.btime_nsec);
+        }
+    }
+
+    fn get(payload: &mut Bytes) -> Option<Times> {
+        (payload.remaining() >= Self::SIZE).then(|| Times {
+            atime: payload.get_i64(),
+            atime_nsec: payload.get_u32(),
+            btime: payload.get_i64(),
+            btime_nsec: payload.get_u32(),
+        })
+    }
+
+    /// The creation time, if the source had one
+    pub fn birth(&self) -> Option<(i64, u32)> {
+        (self.btime != 0 || self.btime_nsec != 0).then_some((self.btime, self.btime_nsec))
+    }
+}
+
+/// Sub-second part of an mtime, in nanoseconds
+///
+/// Ends FILE_ENTRY (after the Ownership), DEST_FILE_ENTRY and SET_ATTR, and
+/// only when nonzero: an older peer stops reading before it and sees whole
+/// seconds, and one that doesn't send it reads as 0.
+struct Nsec;
+
+impl Nsec {
+    const SIZE: usize = 4;
+
+    fn encoded_len(nsec: u32) -> usize {
+        if nsec == 0 {
+            0
+        } else {
+            Self::SIZE
+        }
+    }
+
+    fn put(nsec: u32, buf: &mut BytesMut) {
+        if nsec != 0 {
+            buf.put_u32(nsec);
+        }
+    }
+
+    fn get(payload: &mut Bytes) -> u32 {
+        if payload.remaining() >= Self::SIZE {
+            payload.get_u32()
+        } else {
+            0
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FileEntry {
-    pub path: String,
+    pub path: Arc<str>,
     pub size: u64,
     pub mtime: i64,
+    /// Nanoseconds past `mtime`
+    pub mtime_nsec: u32,
     pub mode: u32,
     pub inode: u64,
     pub flags: FileFlags,
     pub symlink_target: Option<String>,
     pub link_target: Option<String>,
+    /// FILE_ATTRIBUTE bits from a Windows source (see `WINDOWS_ATTRIBUTE_MASK`)
+    pub win_attrs: Option<u32>,
+    /// BSD file flags (chflags) from a macOS source, under `--preserve-flags`
+    pub bsd_flags: Option<u32>,
+    /// Owner and group, under `-o`/`-g`
+    pub owner: Option<Ownership>,
+    /// Access and creation times, under `--times=all`
+    pub times: Option<Times>,
 }
 
 impl FileEntry {
@@ -245,6 +570,12 @@ impl FileEntry {
     }
 
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
         let symlink_bytes = self.symlink_target.as_ref().map(|s| s.as_bytes());
         let link_bytes = self.link_target.as_ref().map(|s| s.as_bytes());
@@ -256,8 +587,20 @@ impl FileEntry {
         if let Some(b) = link_bytes {
             payload_len += 2 + b.len();
         }
-
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        if self.win_attrs.is_some() {
+            payload_len += 4;
+        }
+        if self.bsd_flags.is_some() {
+            payload_len += 4;
+        }
+        payload_len += Times::encoded_len(self.times);
+        payload_len += Ownership::encoded_len(self.owner);
+        payload_len += Nsec::encoded_len(self.mtime_nsec);
+        let mut flags = self.flags;
+        flags.set(FileFlags::WIN_ATTRS, self.win_attrs.is_some());
+        flags.set(FileFlags::BSD_FLAGS, self.bsd_flags.is_some());
+
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::FileEntry as u8);
         buf.put_u16(path_bytes.len() as u16);
@@ -266,7 +609,7 @@ impl FileEntry {
         buf.put_i64(self.mtime);
         buf.put_u32(self.mode);
         buf.put_u64(self.inode);
-        buf.put_u8(self.flags.bits());
+        buf.put_u8(flags.bits());
 
         if let Some(b) = symlink_bytes {
             buf.put_u16(b.len() as u16);
@@ -276,20 +619,36 @@ impl FileEntry {
             buf.put_u16(b.len() as u16);
             buf.put_slice(b);
         }
+        if let Some(attrs) = self.win_attrs {
+            buf.put_u32(attrs);
+        }
+        if let Some(bsd_flags) = self.bsd_flags {
+            buf.put_u32(bsd_flags);
+        }
+        Times::put(self.times, buf);
+        Ownership::put(self.owner, buf);
+        Nsec::put(self.mtime_nsec, buf);
+    }
 
-        buf.freeze()
+    pub fn decode(payload: Bytes) -> Result<Self> {
+        Self::decode_with(payload, None)
     }
 
-    pub fn decode(mut payload: Bytes) -> Result<Self> {
+    /// Decode, taking the path from (and adding it to) a session's table
+    pub fn decode_interned(payload: Bytes, paths: &mut PathTable) -> Result<Self> {
+        Self::decode_with(payload, Some(paths))
+    }
+
+    fn decode_with(mut payload: Bytes, paths: Option<&mut PathTable>) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("FileEntry payload too short");
+            malformed!("FileEntry payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 29 {
-            anyhow::bail!("FileEntry payload truncated");
+            malformed!("FileEntry payload truncated");
         }
-        let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
-            .context("Invalid UTF-8 in FileEntry path")?;
+        let path =
+            get_path(&mut payload, path_len, paths).context("Invalid UTF-8 in FileEntry path")?;
         let size = payload.get_u64();
         let mtime = payload.get_i64();
         let mode = payload.get_u32();
@@ -298,11 +657,11 @@ impl FileEntry {
 
         let symlink_target = if flags.contains(FileFlags::SYMLINK) {
             if payload.remaining() < 2 {
-                anyhow::bail!("FileEntry symlink target length truncated");
+                malformed!("FileEntry symlink target length truncated");
             }
             let len = payload.get_u16() as usize;
             if payload.remaining() < len {
-                anyhow::bail!(
+                malformed!(
                     "FileEntry symlink target truncated: expected {} bytes, got {}",
                     len,
                     payload.remaining()
@@ -318,11 +677,11 @@ impl FileEntry {
 
         let link_target = if flags.contains(FileFlags::HARDLINK) {
             if payload.remaining() < 2 {
-                anyhow::bail!("FileEntry hardlink target length truncated");
+                malformed!("FileEntry hardlink target length truncated");
             }
             let len = payload.get_u16() as usize;
             if payload.remaining() < len {
-                anyhow::bail!(
+                malformed!(
                     "FileEntry hardlink target truncated: expected {} bytes, got {}",
                     len,
                     payload.remaining()
@@ -336,15 +695,41 @@ impl FileEntry {
             None
         };
 
+        let win_attrs = if flags.contains(FileFlags::WIN_ATTRS) {
+            if payload.remaining() < 4 {
+                malformed!("FileEntry Windows attributes truncated");
+            }
+            Some(payload.get_u32())
+        } else {
+            None
+        };
+
+        let bsd_flags = if flags.contains(FileFlags::BSD_FLAGS) {
+            if payload.remaining() < 4 {
+                malformed!("FileEntry BSD flags truncated");
+            }
+            Some(payload.get_u32())
+        } else {
+            None
+        };
+        let times = Times::get(&mut payload);
+        let owner = Ownership::get(&mut payload);
+        let mtime_nsec = Nsec::get(&mut payload);
+
         Ok(Self {
             path,
             size,
             mtime,
+            mtime_nsec,
             mode,
             inode,
             flags,
             symlink_target,
             link_target,
+            win_attrs,
+            bsd_flags,
+            owner,
+            times,
         })
     }
 }
@@ -357,25 +742,39 @@ impl FileEntry {
 pub struct FileEnd {
     pub total_files: u64,
     pub total_bytes: u64,
+    /// Source files left alone because the destination already had them;
+    /// older peers leave it out, read as 0
+    pub skipped: u64,
 }
 
 impl FileEnd {
     pub fn encode(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(5 + 16);
-        buf.put_u32(16);
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(5 + 24);
+        buf.put_u32(24);
         buf.put_u8(MessageType::FileEnd as u8);
         buf.put_u64(self.total_files);
         buf.put_u64(self.total_bytes);
-        buf.freeze()
+        buf.put_u64(self.skipped);
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 16 {
-            anyhow::bail!("FileEnd payload too short");
+            malformed!("FileEnd payload too short");
         }
         Ok(Self {
             total_files: payload.get_u64(),
             total_bytes: payload.get_u64(),
+            skipped: if payload.remaining() >= 8 {
+                payload.get_u64()
+            } else {
+                0
+            },
         })
     }
 }
@@ -388,11 +787,38 @@ impl FileEnd {
 pub struct BlockChecksum {
     pub offset: u64,
     pub weak: u32,
-    pub strong: u64,
+    /// 64-bit strong checksums are zero-extended
+    pub strong: u128,
 }
 
 impl BlockChecksum {
     pub const SIZE: usize = 20;
+    /// With DestFileFlags::STRONG_128
+    pub const WIDE_SIZE: usize = 28;
+
+    pub fn size(wide: bool) -> usize {
+        if wide {
+            Self::WIDE_SIZE
+        } else {
+            Self::SIZE
+        }
+    }
+
+    fn put_strong(&self, wide: bool, buf: &mut impl BufMut) {
+        if wide {
+            buf.put_u128(self.strong);
+        } else {
+            buf.put_u64(self.strong as u64);
+        }
+    }
+}
+
+fn get_strong(wide: bool, buf: &mut impl Buf) -> u128 {
+    if wide {
+        buf.get_u128()
+    } else {
+        buf.get_u64() as u128
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -400,23 +826,105 @@ pub struct DestFileEntry {
     pub path: String,
     pub size: u64,
     pub mtime: i64,
+    /// Nanoseconds past `mtime`
+    pub mtime_nsec: u32,
     pub mode: u32,
     pub flags: DestFileFlags,
     pub block_size: u32,
     pub checksums: Vec<BlockChecksum>,
 }
 
+/// Minimum checksum count before compressing a DestFileEntry's checksum array.
+/// Below this the zstd frame header overhead outweighs the savings.
+pub const CHECKSUM_COMPRESS_MIN: usize = 64;
+
+/// zstd level for checksum arrays. Low level: most of the win comes from the
+/// predictable offset column, which even level 1 collapses.
+const CHECKSUM_ZSTD_LEVEL: i32 = 3;
+
+/// Upper bound on a decompressed checksum array (guards against zstd bombs).
+const MAX_CHECKSUM_BYTES: usize = 256 * 1024 * 1024;
+
+/// Serialize checksums column by column: offset deltas, then weak, then strong.
+///
+/// Weak and strong hashes are effectively random, but offsets advance by the
+/// block size, so storing them as deltas turns 8 of every 20 bytes into a
+/// repeating pattern zstd compresses to almost nothing.
+fn pack_checksums(checksums: &[BlockChecksum], wide: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(checksums.len() * BlockChecksum::size(wide));
+    let mut prev = 0u64;
+    for cs in checksums {
+        buf.put_u64(cs.offset.wrapping_sub(prev));
+        prev = cs.offset;
+    }
+    for cs in checksums {
+        buf.put_u32(cs.weak);
+    }
+    for cs in checksums {
+        cs.put_strong(wide, &mut buf);
+    }
+    buf
+}
+
+fn unpack_checksums(packed: &[u8], count: usize, wide: bool) -> Result<Vec<BlockChecksum>> {
+    if packed.len() != count * BlockChecksum::size(wide) {
+        malformed!(
+            "Compressed checksums size mismatch: expected {} bytes, got {}",
+            count * BlockChecksum::size(wide),
+            packed.len()
+        );
+    }
+    let (offsets, rest) = packed.split_at(count * 8);
+    let (weaks, mut strongs) = rest.split_at(count * 4);
+
+    let mut checksums = Vec::with_capacity(count);
+    let mut offset = 0u64;
+    for i in 0..count {
+        offset = offset.wrapping_add(u64::from_be_bytes(
+            offsets[i * 8..i * 8 + 8].try_into().expect("8-byte slice"),
+        ));
+        checksums.push(BlockChecksum {
+            offset,
+            weak: u32::from_be_bytes(weaks[i * 4..i * 4 + 4].try_into().expect("4-byte slice")),
+            strong: get_strong(wide, &mut strongs),
+        });
+    }
+    Ok(checksums)
+}
+
 impl DestFileEntry {
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
         let has_checksums = self.flags.contains(DestFileFlags::HAS_CHECKSUMS);
+        let wide = self.flags.contains(DestFileFlags::STRONG_128);
+        let mut flags = self.flags;
+
+        // Compress up front so the frame length is known; fall back to the
+        // raw layout if zstd fails for any reason.
+        let compressed = if has_checksums && flags.contains(DestFileFlags::CHECKSUMS_ZSTD) {
+            zstd::bulk::compress(&pack_checksums(&self.checksums, wide), CHECKSUM_ZSTD_LEVEL).ok()
+        } else {
+            None
+        };
+        if compressed.is_none() {
+            flags.remove(DestFileFlags::CHECKSUMS_ZSTD);
+        }
 
         let mut payload_len = 2 + path_bytes.len() + 8 + 8 + 4 + 1;
-        if has_checksums {
-            payload_len += 4 + 4 + self.checksums.len() * BlockChecksum::SIZE;
+        if let Some(ref packed) = compressed {
+            payload_len += 4 + 4 + 4 + packed.len();
+        } else if has_checksums {
+            payload_len += 4 + 4 + self.checksums.len() * BlockChecksum::size(wide);
         }
+        payload_len += Nsec::encoded_len(self.mtime_nsec);
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::DestFileEntry as u8);
         buf.put_u16(path_bytes.len() as u16);
@@ -424,28 +932,32 @@ impl DestFileEntry {
         buf.put_u64(self.size);
         buf.put_i64(self.mtime);
         buf.put_u32(self.mode);
-        buf.put_u8(self.flags.bits());
+        buf.put_u8(flags.bits());
 
-        if has_checksums {
+        if let Some(packed) = compressed {
+            buf.put_u32(self.block_size);
+            buf.put_u32(self.checksums.len() as u32);
+            buf.put_u32(packed.len() as u32);
+            buf.put_slice(&packed);
+        } else if has_checksums {
             buf.put_u32(self.block_size);
             buf.put_u32(self.checksums.len() as u32);
             for cs in &self.checksums {
                 buf.put_u64(cs.offset);
                 buf.put_u32(cs.weak);
-                buf.put_u64(cs.strong);
+                cs.put_strong(wide, buf);
             }
         }
-
-        buf.freeze()
+        Nsec::put(self.mtime_nsec, buf);
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("DestFileEntry payload too short");
+            malformed!("DestFileEntry payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 21 {
-            anyhow::bail!("DestFileEntry payload truncated");
+            malformed!("DestFileEntry payload truncated");
         }
         let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
             .context("Invalid UTF-8 in DestFileEntry path")?;
@@ -453,18 +965,43 @@ impl DestFileEntry {
         let mtime = payload.get_i64();
         let mode = payload.get_u32();
         let flags = DestFileFlags::from_bits_truncate(payload.get_u8());
+        let wide = flags.contains(DestFileFlags::STRONG_128);
+
+        let (block_size, checksums) = if flags.contains(DestFileFlags::HAS_CHECKSUMS)
+            && flags.contains(DestFileFlags::CHECKSUMS_ZSTD)
+        {
+            if payload.remaining() < 12 {
+                malformed!("DestFileEntry compressed checksum header truncated");
+            }
+            let bs = payload.get_u32();
+            let count = payload.get_u32() as usize;
+            let compressed_len = payload.get_u32() as usize;
+            if payload.remaining() < compressed_len {
+                malformed!(
+                    "DestFileEntry compressed checksums truncated: expected {} bytes, got {}",
+                    compressed_len,
+                    payload.remaining()
+                );
+            }
 
-        let (block_size, checksums) = if flags.contains(DestFileFlags::HAS_CHECKSUMS) {
+            let raw_len = count
+                .checked_mul(BlockChecksum::size(wide))
+                .filter(|&n| n <= MAX_CHECKSUM_BYTES)
+                .with_context(|| format!("DestFileEntry checksum count {} too large", count))?;
+            let packed = zstd::bulk::decompress(&payload.copy_to_bytes(compressed_len), raw_len)
+                .context("Failed to decompress DestFileEntry checksums")?;
+            (bs, unpack_checksums(&packed, count, wide)?)
+        } else if flags.contains(DestFileFlags::HAS_CHECKSUMS) {
             if payload.remaining() < 8 {
-                anyhow::bail!("DestFileEntry checksum header truncated");
+                malformed!("DestFileEntry checksum header truncated");
             }
             let bs = payload.get_u32();
             let count = payload.get_u32() as usize;
 
             // Validate we have enough data for all checksums
-            let required = count * BlockChecksum::SIZE;
+            let required = count * BlockChecksum::size(wide);
             if payload.remaining() < required {
-                anyhow::bail!(
+                malformed!(
                         "DestFileEntry checksums truncated: expected {} checksums ({} bytes), got {} bytes",
                         count,
                         required,
@@ -477,18 +1014,20 @@ impl DestFileEntry {
                 checksums.push(BlockChecksum {
                     offset: payload.get_u64(),
                     weak: payload.get_u32(),
-                    strong: payload.get_u64(),
+                    strong: get_strong(wide, &mut payload),
                 });
             }
             (bs, checksums)
         } else {
             (0, Vec::new())
         };
+        let mtime_nsec = Nsec::get(&mut payload);
 
         Ok(Self {
             path,
             size,
             mtime,
+            mtime_nsec,
             mode,
             flags,
             block_size,
@@ -505,26 +1044,45 @@ impl DestFileEntry {
 pub struct DestFileEnd {
     pub total_files: u64,
     pub total_bytes: u64,
+    /// Id of the manifest the server saved for this tree (0 for none)
+    pub manifest: u128,
+    /// Id of the manifest the entries are changes against; 0 means they
+    /// are the whole tree
+    pub base: u128,
 }
 
 impl DestFileEnd {
     pub fn encode(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(5 + 16);
-        buf.put_u32(16);
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(5 + 48);
+        buf.put_u32(48);
         buf.put_u8(MessageType::DestFileEnd as u8);
         buf.put_u64(self.total_files);
         buf.put_u64(self.total_bytes);
-        buf.freeze()
+        buf.put_u128(self.manifest);
+        buf.put_u128(self.base);
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 16 {
-            anyhow::bail!("DestFileEnd payload too short");
+            malformed!("DestFileEnd payload too short");
         }
-        Ok(Self {
+        let mut end = Self {
             total_files: payload.get_u64(),
             total_bytes: payload.get_u64(),
-        })
+            manifest: 0,
+            base: 0,
+        };
+        if payload.remaining() >= 32 {
+            end.manifest = payload.get_u128();
+            end.base = payload.get_u128();
+        }
+        Ok(end)
     }
 }
 
@@ -534,7 +1092,7 @@ impl DestFileEnd {
 
 #[derive(Debug, Clone)]
 pub struct Data {
-    pub path: String,
+    pub path: Arc<str>,
     pub offset: u64,
     pub flags: DataFlags,
     pub data: Bytes,
@@ -542,10 +1100,16 @@ pub struct Data {
 
 impl Data {
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
         let payload_len = 2 + path_bytes.len() + 8 + 1 + 4 + self.data.len();
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::Data as u8);
         buf.put_u16(path_bytes.len() as u16);
@@ -554,25 +1118,31 @@ impl Data {
         buf.put_u8(self.flags.bits());
         buf.put_u32(self.data.len() as u32);
         buf.put_slice(&self.data);
+    }
 
-        buf.freeze()
+    pub fn decode(payload: Bytes) -> Result<Self> {
+        Self::decode_with(payload, None)
     }
 
-    pub fn decode(mut payload: Bytes) -> Result<Self> {
+    /// Decode, sharing the path with the file's entry in a session's table
+    pub fn decode_interned(payload: Bytes, paths: &mut PathTable) -> Result<Self> {
+        Self::decode_with(payload, Some(paths))
+    }
+
+    fn decode_with(mut payload: Bytes, paths: Option<&mut PathTable>) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("Data payload too short");
+            malformed!("Data payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 13 {
-            anyhow::bail!("Data payload truncated");
+            malformed!("Data payload truncated");
         }
-        let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
-            .context("Invalid UTF-8 in Data path")?;
+        let path = get_path(&mut payload, path_len, paths).context("Invalid UTF-8 in Data path")?;
         let offset = payload.get_u64();
         let flags = DataFlags::from_bits_truncate(payload.get_u8());
         let data_len = payload.get_u32() as usize;
         if payload.remaining() < data_len {
-            anyhow::bail!("Data content truncated");
+            malformed!("Data content truncated");
         }
         let data = payload.copy_to_bytes(data_len);
 
@@ -591,8 +1161,11 @@ impl Data {
 
 #[derive(Debug, Clone)]
 pub struct DataEnd {
-    pub path: String,
+    pub path: Arc<str>,
     pub status: u8,
+    /// xxh3-128 of the whole source file (`--checksum-verify`), trailing
+    /// the status; older peers neither send nor read it
+    pub hash: Option<u128>,
 }
 
 impl DataEnd {
@@ -600,32 +1173,49 @@ impl DataEnd {
     pub const STATUS_ERROR: u8 = 1;
 
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
-        let payload_len = 2 + path_bytes.len() + 1;
+        let payload_len = 2 + path_bytes.len() + 1 + if self.hash.is_some() { 16 } else { 0 };
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::DataEnd as u8);
         buf.put_u16(path_bytes.len() as u16);
         buf.put_slice(path_bytes);
         buf.put_u8(self.status);
+        if let Some(hash) = self.hash {
+            buf.put_u128(hash);
+        }
+    }
 
-        buf.freeze()
+    pub fn decode(payload: Bytes) -> Result<Self> {
+        Self::decode_with(payload, None)
     }
 
-    pub fn decode(mut payload: Bytes) -> Result<Self> {
+    /// Decode, sharing the path with the file's entry in a session's table
+    pub fn decode_interned(payload: Bytes, paths: &mut PathTable) -> Result<Self> {
+        Self::decode_with(payload, Some(paths))
+    }
+
+    fn decode_with(mut payload: Bytes, paths: Option<&mut PathTable>) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("DataEnd payload too short");
+            malformed!("DataEnd payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 1 {
-            anyhow::bail!("DataEnd payload truncated");
+            malformed!("DataEnd payload truncated");
         }
-        let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
-            .context("Invalid UTF-8 in DataEnd path")?;
+        let path =
+            get_path(&mut payload, path_len, paths).context("Invalid UTF-8 in DataEnd path")?;
         let status = payload.get_u8();
+        let hash = (payload.remaining() >= 16).then(|| payload.get_u128());
 
-        Ok(Self { path, status })
+        Ok(Self { path, status, hash })
     }
 }
 
@@ -641,26 +1231,30 @@ pub struct Delete {
 
 impl Delete {
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
         let payload_len = 2 + path_bytes.len() + 1;
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::Delete as u8);
         buf.put_u16(path_bytes.len() as u16);
         buf.put_slice(path_bytes);
         buf.put_u8(self.is_dir as u8);
-
-        buf.freeze()
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("Delete payload too short");
+            malformed!("Delete payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 1 {
-            anyhow::bail!("Delete payload truncated");
+            malformed!("Delete payload truncated");
         }
         let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
             .context("Invalid UTF-8 in Delete path")?;
@@ -681,16 +1275,21 @@ pub struct DeleteEnd {
 
 impl DeleteEnd {
     pub fn encode(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(5 + 8);
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(5 + 8);
         buf.put_u32(8);
         buf.put_u8(MessageType::DeleteEnd as u8);
         buf.put_u64(self.count);
-        buf.freeze()
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 8 {
-            anyhow::bail!("DeleteEnd payload too short");
+            malformed!("DeleteEnd payload too short");
         }
         Ok(Self {
             count: payload.get_u64(),
@@ -706,36 +1305,43 @@ impl DeleteEnd {
 pub struct Mkdir {
     pub path: String,
     pub mode: u32,
+    pub owner: Option<Ownership>,
 }
 
 impl Mkdir {
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
-        let payload_len = 2 + path_bytes.len() + 4;
+        let payload_len = 2 + path_bytes.len() + 4 + Ownership::encoded_len(self.owner);
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::Mkdir as u8);
         buf.put_u16(path_bytes.len() as u16);
         buf.put_slice(path_bytes);
         buf.put_u32(self.mode);
-
-        buf.freeze()
+        Ownership::put(self.owner, buf);
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("Mkdir payload too short");
+            malformed!("Mkdir payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 4 {
-            anyhow::bail!("Mkdir payload truncated");
+            malformed!("Mkdir payload truncated");
         }
         let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
             .context("Invalid UTF-8 in Mkdir path")?;
         let mode = payload.get_u32();
+        let owner = Ownership::get(&mut payload);
 
-        Ok(Self { path, mode })
+        Ok(Self { path, mode, owner })
     }
 }
 
@@ -747,43 +1353,55 @@ impl Mkdir {
 pub struct Symlink {
     pub path: String,
     pub target: String,
+    pub owner: Option<Ownership>,
 }
 
 impl Symlink {
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
         let target_bytes = self.target.as_bytes();
-        let payload_len = 2 + path_bytes.len() + 2 + target_bytes.len();
+        let payload_len =
+            2 + path_bytes.len() + 2 + target_bytes.len() + Ownership::encoded_len(self.owner);
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::Symlink as u8);
         buf.put_u16(path_bytes.len() as u16);
         buf.put_slice(path_bytes);
         buf.put_u16(target_bytes.len() as u16);
         buf.put_slice(target_bytes);
-
-        buf.freeze()
+        Ownership::put(self.owner, buf);
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("Symlink payload too short");
+            malformed!("Symlink payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 2 {
-            anyhow::bail!("Symlink payload truncated");
+            malformed!("Symlink payload truncated");
         }
         let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
             .context("Invalid UTF-8 in Symlink path")?;
         let target_len = payload.get_u16() as usize;
         if payload.remaining() < target_len {
-            anyhow::bail!("Symlink target truncated");
+            malformed!("Symlink target truncated");
         }
         let target = String::from_utf8(payload.copy_to_bytes(target_len).to_vec())
             .context("Invalid UTF-8 in Symlink target")?;
+        let owner = Ownership::get(&mut payload);
 
-        Ok(Self { path, target })
+        Ok(Self {
+            path,
+            target,
+            owner,
+        })
     }
 }
 
@@ -791,7 +1409,13 @@ impl Symlink {
 // PROGRESS (0x0C)
 // =============================================================================
 
-#[derive(Debug, Clone, Copy)]
+/// How far a transfer has got: files done and bytes of DATA moved, against
+/// the files the sending end's Generator has found so far and their size
+///
+/// The totals grow while the source is still being walked. A server
+/// receiving a push doesn't know them and sends zero; the client fills in
+/// its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Progress {
     pub files: u64,
     pub bytes: u64,
@@ -801,19 +1425,24 @@ pub struct Progress {
 
 impl Progress {
     pub fn encode(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(5 + 32);
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(5 + 32);
         buf.put_u32(32);
         buf.put_u8(MessageType::Progress as u8);
         buf.put_u64(self.files);
         buf.put_u64(self.bytes);
         buf.put_u64(self.files_total);
         buf.put_u64(self.bytes_total);
-        buf.freeze()
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 32 {
-            anyhow::bail!("Progress payload too short");
+            malformed!("Progress payload too short");
         }
         Ok(Self {
             files: payload.get_u64(),
@@ -837,11 +1466,17 @@ pub struct Error {
 
 impl Error {
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
         let msg_bytes = self.message.as_bytes();
         let payload_len = 2 + path_bytes.len() + 2 + 2 + msg_bytes.len();
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::Error as u8);
         buf.put_u16(path_bytes.len() as u16);
@@ -849,24 +1484,22 @@ impl Error {
         buf.put_u16(self.code);
         buf.put_u16(msg_bytes.len() as u16);
         buf.put_slice(msg_bytes);
-
-        buf.freeze()
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("Error payload too short");
+            malformed!("Error payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 4 {
-            anyhow::bail!("Error payload truncated");
+            malformed!("Error payload truncated");
         }
         let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
             .context("Invalid UTF-8 in Error path")?;
         let code = payload.get_u16();
         let msg_len = payload.get_u16() as usize;
         if payload.remaining() < msg_len {
-            anyhow::bail!("Error message truncated");
+            malformed!("Error message truncated");
         }
         let message = String::from_utf8(payload.copy_to_bytes(msg_len).to_vec())
             .context("Invalid UTF-8 in Error message")?;
@@ -891,27 +1524,31 @@ pub struct Fatal {
 
 impl Fatal {
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let msg_bytes = self.message.as_bytes();
         let payload_len = 2 + 2 + msg_bytes.len();
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::Fatal as u8);
         buf.put_u16(self.code);
         buf.put_u16(msg_bytes.len() as u16);
         buf.put_slice(msg_bytes);
-
-        buf.freeze()
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 4 {
-            anyhow::bail!("Fatal payload too short");
+            malformed!("Fatal payload too short");
         }
         let code = payload.get_u16();
         let msg_len = payload.get_u16() as usize;
         if payload.remaining() < msg_len {
-            anyhow::bail!("Fatal message truncated");
+            malformed!("Fatal message truncated");
         }
         let message = String::from_utf8(payload.copy_to_bytes(msg_len).to_vec())
             .context("Invalid UTF-8 in Fatal message")?;
@@ -938,13 +1575,19 @@ pub struct Xattr {
 
 impl Xattr {
     pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
         let path_bytes = self.path.as_bytes();
         let mut payload_len = 2 + path_bytes.len() + 2;
         for entry in &self.entries {
             payload_len += 2 + entry.name.len() + 4 + entry.value.len();
         }
 
-        let mut buf = BytesMut::with_capacity(5 + payload_len);
+        buf.reserve(5 + payload_len);
         buf.put_u32(payload_len as u32);
         buf.put_u8(MessageType::Xattr as u8);
         buf.put_u16(path_bytes.len() as u16);
@@ -958,17 +1601,15 @@ impl Xattr {
             buf.put_u32(entry.value.len() as u32);
             buf.put_slice(&entry.value);
         }
-
-        buf.freeze()
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 2 {
-            anyhow::bail!("Xattr payload too short");
+            malformed!("Xattr payload too short");
         }
         let path_len = payload.get_u16() as usize;
         if payload.remaining() < path_len + 2 {
-            anyhow::bail!("Xattr payload truncated");
+            malformed!("Xattr payload truncated");
         }
         let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
             .context("Invalid UTF-8 in Xattr path")?;
@@ -977,7 +1618,7 @@ impl Xattr {
         let mut entries = Vec::with_capacity(count);
         for i in 0..count {
             if payload.remaining() < 2 {
-                anyhow::bail!(
+                malformed!(
                     "Xattr entry {} name length truncated: expected 2 bytes, got {}",
                     i,
                     payload.remaining()
@@ -985,7 +1626,7 @@ impl Xattr {
             }
             let name_len = payload.get_u16() as usize;
             if payload.remaining() < name_len + 4 {
-                anyhow::bail!(
+                malformed!(
                     "Xattr entry {} truncated: expected {} bytes for name + value length, got {}",
                     i,
                     name_len + 4,
@@ -996,7 +1637,7 @@ impl Xattr {
                 .context("Invalid UTF-8 in Xattr name")?;
             let value_len = payload.get_u32() as usize;
             if payload.remaining() < value_len {
-                anyhow::bail!(
+                malformed!(
                     "Xattr entry {} value truncated: expected {} bytes, got {}",
                     i,
                     value_len,
@@ -1021,64 +1662,754 @@ pub struct Done {
     pub files_err: u64,
     pub bytes: u64,
     pub duration_ms: u64,
+    /// Aggregate digest over all changes (see `streaming::digest`); None
+    /// from peers that predate it, which send a 32-byte DONE
+    pub digest: Option<u128>,
+    /// How long each phase took as the sending end saw it; peers that
+    /// predate it send a shorter DONE, read as all zero
+    pub phases: PhaseTimes,
+    /// How the receiving end's files_ok split up, plus the files skipped
+    /// as up to date (zero from a sending end or an older peer)
+    pub counts: FileCounts,
+    /// What a dry run found it would write and remove (zero outside
+    /// `--dry-run` or from an older peer)
+    pub would: WouldBytes,
+    /// Files the receiving end moved aside before replacing or deleting
+    /// them (`--backup`; zero from a sending end or an older peer)
+    pub backed_up: u64,
 }
 
 impl Done {
     pub fn encode(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(5 + 32);
-        buf.put_u32(32);
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(5 + 144);
+        buf.put_u32(144);
         buf.put_u8(MessageType::Done as u8);
         buf.put_u64(self.files_ok);
         buf.put_u64(self.files_err);
         buf.put_u64(self.bytes);
         buf.put_u64(self.duration_ms);
-        buf.freeze()
+        // Everything after the first 32 bytes is read by position, so a
+        // DONE always carries the digest from here on
+        buf.put_u128(self.digest.unwrap_or_default());
+        for phase in [
+            self.phases.scan,
+            self.phases.exchange,
+            self.phases.transfer,
+            self.phases.delete,
+        ] {
+            buf.put_u64(phase.as_millis() as u64);
+        }
+        buf.put_u64(self.counts.created);
+        buf.put_u64(self.counts.updated);
+        buf.put_u64(self.counts.touched);
+        buf.put_u64(self.counts.skipped);
+        buf.put_u64(self.would.add);
+        buf.put_u64(self.would.change);
+        buf.put_u64(self.would.delete);
+        buf.put_u64(self.backed_up);
     }
 
     pub fn decode(mut payload: Bytes) -> Result<Self> {
         if payload.remaining() < 32 {
-            anyhow::bail!("Done payload too short");
+            malformed!("Done payload too short");
         }
-        Ok(Self {
+        let mut done = Self {
             files_ok: payload.get_u64(),
             files_err: payload.get_u64(),
             bytes: payload.get_u64(),
             duration_ms: payload.get_u64(),
-        })
+            digest: None,
+            phases: PhaseTimes::default(),
+            counts: FileCounts::default(),
+            would: WouldBytes::default(),
+            backed_up: 0,
+        };
+        if payload.remaining() >= 16 {
+            done.digest = Some(payload.get_u128());
+        }
+        if payload.remaining() >= 32 {
+            let mut ms = || Duration::from_millis(payload.get_u64());
+            done.phases = PhaseTimes {
+                scan: ms(),
+                exchange: ms(),
+                transfer: ms(),
+                delete: ms(),
+            };
+        }
+        if payload.remaining() >= 32 {
+            done.counts = FileCounts {
+                created: payload.get_u64(),
+                updated: payload.get_u64(),
+                touched: payload.get_u64(),
+                skipped: payload.get_u64(),
+            };
+        }
+        if payload.remaining() >= 24 {
+            done.would = WouldBytes {
+                add: payload.get_u64(),
+                change: payload.get_u64(),
+                delete: payload.get_u64(),
+            };
+        }
+        if payload.remaining() >= 8 {
+            done.backed_up = payload.get_u64();
+        }
+        Ok(done)
     }
 }
 
 // =============================================================================
-// Frame reading/writing
+// LOG (0x11)
 // =============================================================================
 
-/// Maximum frame size (64MB) - prevents OOM from malicious/corrupted frames
-pub const MAX_FRAME_SIZE: u32 = 64 * 1024 * 1024;
+/// A diagnostic from the server, for the client to show with its own
+#[derive(Debug, Clone)]
+pub struct Log {
+    pub level: u8,
+    pub message: String,
+}
 
-/// Read a single frame from the stream.
-/// Returns (message_type, payload).
-pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> Result<(MessageType, Bytes)> {
-    let len = r.read_u32().await.context("Failed to read frame length")?;
+impl Log {
+    pub const ERROR: u8 = 1;
+    pub const WARN: u8 = 2;
+    pub const INFO: u8 = 3;
 
-    // Validate frame size before allocation
-    if len > MAX_FRAME_SIZE {
-        anyhow::bail!(
-            "Frame size {} exceeds maximum allowed size {}",
-            len,
-            MAX_FRAME_SIZE
-        );
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
     }
 
-    let msg_type = r.read_u8().await.context("Failed to read message type")?;
-    let msg_type = MessageType::from_u8(msg_type).context("Unknown message type")?;
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        // Cut long messages to what the length prefix can carry
+        let mut end = self.message.len().min(u16::MAX as usize);
+        while !self.message.is_char_boundary(end) {
+            end -= 1;
+        }
+        let msg_bytes = &self.message.as_bytes()[..end];
+        let payload_len = 1 + 2 + msg_bytes.len();
 
-    let payload_len = len as usize;
-    let mut payload = vec![0u8; payload_len];
-    r.read_exact(&mut payload)
-        .await
-        .context("Failed to read frame payload")?;
+        buf.reserve(5 + payload_len);
+        buf.put_u32(payload_len as u32);
+        buf.put_u8(MessageType::Log as u8);
+        buf.put_u8(self.level);
+        buf.put_u16(msg_bytes.len() as u16);
+        buf.put_slice(msg_bytes);
+    }
 
-    Ok((msg_type, Bytes::from(payload)))
+    pub fn decode(mut payload: Bytes) -> Result<Self> {
+        if payload.remaining() < 3 {
+            malformed!("Log payload too short");
+        }
+        let level = payload.get_u8();
+        let msg_len = payload.get_u16() as usize;
+        if payload.remaining() < msg_len {
+            malformed!("Log message truncated");
+        }
+        let message = String::from_utf8(payload.copy_to_bytes(msg_len).to_vec())
+            .context("Invalid UTF-8 in Log message")?;
+
+        Ok(Self { level, message })
+    }
+}
+
+// =============================================================================
+// LIST (0x12)
+// =============================================================================
+
+/// Browse request for one directory's entries, answered with a FILE_ENTRY
+/// per entry (paths relative to the root) and FILE_END, or an ERROR
+#[derive(Debug, Clone)]
+pub struct List {
+    /// Directory relative to the root; empty for the root itself
+    pub path: String,
+}
+
+impl List {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        let path_bytes = self.path.as_bytes();
+        let payload_len = 2 + path_bytes.len();
+
+        buf.reserve(5 + payload_len);
+        buf.put_u32(payload_len as u32);
+        buf.put_u8(MessageType::List as u8);
+        buf.put_u16(path_bytes.len() as u16);
+        buf.put_slice(path_bytes);
+    }
+
+    pub fn decode(mut payload: Bytes) -> Result<Self> {
+        if payload.remaining() < 2 {
+            malformed!("List payload too short");
+        }
+        let path_len = payload.get_u16() as usize;
+        if payload.remaining() < path_len {
+            malformed!("List payload truncated");
+        }
+        let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
+            .context("Invalid UTF-8 in List path")?;
+
+        Ok(Self { path })
+    }
+}
+
+// =============================================================================
+// READ (0x13)
+// =============================================================================
+
+/// Browse request for a byte range of one file, answered with a single DATA
+/// frame (short or empty past the end of the file) or an ERROR
+#[derive(Debug, Clone)]
+pub struct Read {
+    pub path: String,
+    pub offset: u64,
+    /// Bytes wanted; servers cap it at [`Read::MAX_LEN`]
+    pub len: u32,
+}
+
+impl Read {
+    pub const MAX_LEN: u32 = 1024 * 1024;
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        let path_bytes = self.path.as_bytes();
+        let payload_len = 2 + path_bytes.len() + 8 + 4;
+
+        buf.reserve(5 + payload_len);
+        buf.put_u32(payload_len as u32);
+        buf.put_u8(MessageType::Read as u8);
+        buf.put_u16(path_bytes.len() as u16);
+        buf.put_slice(path_bytes);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.len);
+    }
+
+    pub fn decode(mut payload: Bytes) -> Result<Self> {
+        if payload.remaining() < 2 {
+            malformed!("Read payload too short");
+        }
+        let path_len = payload.get_u16() as usize;
+        if payload.remaining() < path_len + 12 {
+            malformed!("Read payload truncated");
+        }
+        let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
+            .context("Invalid UTF-8 in Read path")?;
+        let offset = payload.get_u64();
+        let len = payload.get_u32();
+
+        Ok(Self { path, offset, len })
+    }
+}
+
+// =============================================================================
+// SET_ATTR (0x14)
+// =============================================================================
+
+/// Metadata-only update of a file whose content the destination already
+/// has: the fields in `flags` are applied in place, with no data sent
+#[derive(Debug, Clone)]
+pub struct SetAttr {
+    pub path: String,
+    pub mode: u32,
+    pub mtime: i64,
+    /// Nanoseconds past `mtime`
+    pub mtime_nsec: u32,
+    pub flags: SetAttrFlags,
+}
+
+impl SetAttr {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        let path_bytes = self.path.as_bytes();
+        let payload_len = 2 + path_bytes.len() + 4 + 8 + 1 + Nsec::encoded_len(self.mtime_nsec);
+
+        buf.reserve(5 + payload_len);
+        buf.put_u32(payload_len as u32);
+        buf.put_u8(MessageType::SetAttr as u8);
+        buf.put_u16(path_bytes.len() as u16);
+        buf.put_slice(path_bytes);
+        buf.put_u32(self.mode);
+        buf.put_i64(self.mtime);
+        buf.put_u8(self.flags.bits());
+        Nsec::put(self.mtime_nsec, buf);
+    }
+
+    pub fn decode(mut payload: Bytes) -> Result<Self> {
+        if payload.remaining() < 2 {
+            malformed!("SetAttr payload too short");
+        }
+        let path_len = payload.get_u16() as usize;
+        if payload.remaining() < path_len + 13 {
+            malformed!("SetAttr payload truncated");
+        }
+        let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
+            .context("Invalid UTF-8 in SetAttr path")?;
+        let mode = payload.get_u32();
+        let mtime = payload.get_i64();
+        let flags = SetAttrFlags::from_bits_truncate(payload.get_u8());
+        let mtime_nsec = Nsec::get(&mut payload);
+
+        Ok(Self {
+            path,
+            mode,
+            mtime,
+            mtime_nsec,
+            flags,
+        })
+    }
+}
+
+// =============================================================================
+// FILTER (0x15)
+// =============================================================================
+
+/// The client's include/exclude rules, in `FilterEngine::add_rule` syntax
+/// and in order (first match wins). Rules are length-prefixed with a u32,
+/// since a pattern has no length limit of its own
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub rules: Vec<String>,
+}
+
+impl Filter {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        let payload_len = 4 + self.rules.iter().map(|r| 4 + r.len()).sum::<usize>();
+
+        buf.reserve(5 + payload_len);
+        buf.put_u32(payload_len as u32);
+        buf.put_u8(MessageType::Filter as u8);
+        buf.put_u32(self.rules.len() as u32);
+        for rule in &self.rules {
+            buf.put_u32(rule.len() as u32);
+            buf.put_slice(rule.as_bytes());
+        }
+    }
+
+    pub fn decode(mut payload: Bytes) -> Result<Self> {
+        if payload.remaining() < 4 {
+            malformed!("Filter payload too short");
+        }
+        let count = payload.get_u32() as usize;
+        // Each rule takes at least its length prefix
+        let mut rules = Vec::with_capacity(count.min(payload.remaining() / 4));
+        for _ in 0..count {
+            if payload.remaining() < 4 {
+                malformed!("Filter rule truncated");
+            }
+            let len = payload.get_u32() as usize;
+            if payload.remaining() < len {
+                malformed!("Filter rule truncated");
+            }
+            let rule = String::from_utf8(payload.copy_to_bytes(len).to_vec())
+                .context("Invalid UTF-8 in Filter rule")?;
+            rules.push(rule);
+        }
+        Ok(Self { rules })
+    }
+}
+
+// =============================================================================
+// ACL (0x16)
+// =============================================================================
+
+/// A file's or directory's access ACL in POSIX text form, one entry per
+/// line. The text is u32-prefixed: a long ACL outgrows a u16
+#[derive(Debug, Clone)]
+pub struct Acl {
+    pub path: String,
+    pub text: String,
+}
+
+impl Acl {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        let path_bytes = self.path.as_bytes();
+        let payload_len = 2 + path_bytes.len() + 4 + self.text.len();
+
+        buf.reserve(5 + payload_len);
+        buf.put_u32(payload_len as u32);
+        buf.put_u8(MessageType::Acl as u8);
+        buf.put_u16(path_bytes.len() as u16);
+        buf.put_slice(path_bytes);
+        buf.put_u32(self.text.len() as u32);
+        buf.put_slice(self.text.as_bytes());
+    }
+
+    pub fn decode(mut payload: Bytes) -> Result<Self> {
+        if payload.remaining() < 2 {
+            malformed!("Acl payload too short");
+        }
+        let path_len = payload.get_u16() as usize;
+        if payload.remaining() < path_len + 4 {
+            malformed!("Acl payload truncated");
+        }
+        let path = String::from_utf8(payload.copy_to_bytes(path_len).to_vec())
+            .context("Invalid UTF-8 in Acl path")?;
+        let text_len = payload.get_u32() as usize;
+        if payload.remaining() < text_len {
+            malformed!("Acl text truncated");
+        }
+        let text = String::from_utf8(payload.copy_to_bytes(text_len).to_vec())
+            .context("Invalid UTF-8 in Acl text")?;
+        Ok(Self { path, text })
+    }
+}
+
+// =============================================================================
+// PART (0x17)
+// =============================================================================
+
+/// Longest PART payload; shorter frames go out whole
+pub const PART_SIZE: usize = 64 * 1024;
+
+/// The channel the Sender's frames are split on. Frames sent whole don't
+/// belong to any, so they never wait behind a channel's pieces
+pub const CHANNEL_DATA: u8 = 1;
+
+/// A piece of a frame split on a channel (HelloFlags::CHANNELS). The
+/// receiving end joins a channel's pieces up to the `last` one and reads
+/// the frames they make up; whole frames can arrive in between
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub channel: u8,
+    pub last: bool,
+    pub data: Bytes,
+}
+
+impl Part {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        let payload_len = 2 + self.data.len();
+
+        buf.reserve(5 + payload_len);
+        buf.put_u32(payload_len as u32);
+        buf.put_u8(MessageType::Part as u8);
+        buf.put_u8(self.channel);
+        buf.put_u8(self.last as u8);
+        buf.put_slice(&self.data);
+    }
+
+    pub fn decode(mut payload: Bytes) -> Result<Self> {
+        if payload.remaining() < 2 {
+            malformed!("Part payload too short");
+        }
+        let channel = payload.get_u8();
+        let last = payload.get_u8() != 0;
+        Ok(Self {
+            channel,
+            last,
+            data: payload,
+        })
+    }
+}
+
+/// Split encoded frames into PARTs on `channel`, or leave them whole if
+/// they fit in one
+pub fn split_frames(frames: &Bytes, channel: u8) -> Vec<Bytes> {
+    if frames.len() <= PART_SIZE {
+        return vec![frames.clone()];
+    }
+    let count = frames.len().div_ceil(PART_SIZE);
+    (0..count)
+        .map(|i| {
+            let start = i * PART_SIZE;
+            let end = (start + PART_SIZE).min(frames.len());
+            Part {
+                channel,
+                last: i + 1 == count,
+                data: frames.slice(start..end),
+            }
+            .encode()
+        })
+        .collect()
+}
+
+/// Reads frames from a stream that may carry PARTs, joining each
+/// channel's pieces back into the frames they were split from
+#[derive(Debug, Default)]
+pub struct Demux {
+    partial: HashMap<u8, BytesMut>,
+    ready: VecDeque<(MessageType, Bytes)>,
+}
+
+impl Demux {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next whole frame: like [`read_frame`], but never a PART
+    pub async fn read_frame<R: AsyncRead + Unpin>(
+        &mut self,
+        r: &mut R,
+    ) -> Result<(MessageType, Bytes)> {
+        loop {
+            if let Some(frame) = self.ready.pop_front() {
+                return Ok(frame);
+            }
+            let (msg_type, payload) = read_frame(r).await?;
+            if msg_type != MessageType::Part {
+                return Ok((msg_type, payload));
+            }
+            self.add(Part::decode(payload)?)?;
+        }
+    }
+
+    fn add(&mut self, part: Part) -> Result<()> {
+        let buf = self.partial.entry(part.channel).or_default();
+        if buf.len() + part.data.len() > 5 + MAX_FRAME_SIZE as usize {
+            malformed!(
+                "Parts on channel {} exceed maximum frame size {}",
+                part.channel,
+                MAX_FRAME_SIZE
+            );
+        }
+        buf.extend_from_slice(&part.data);
+        if part.last {
+            let mut frames = self
+                .partial
+                .remove(&part.channel)
+                .unwrap_or_default()
+                .freeze();
+            while let Some(frame) = next_frame(&mut frames)? {
+                if frame.0 == MessageType::Part {
+                    malformed!("Part nested in a Part");
+                }
+                self.ready.push_back(frame);
+            }
+        }
+        Ok(())
+    }
+}
+
+// =============================================================================
+// KEEPALIVE (0x18)
+// =============================================================================
+
+/// How often a KEEPALIVE goes out (HelloFlags::KEEPALIVE)
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long a peer that sends KEEPALIVEs may go without sending anything
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Says the sender is still there; it has no payload, and [`read_frame`]
+/// skips it
+#[derive(Debug, Clone, Copy)]
+pub struct Keepalive;
+
+impl Keepalive {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(5);
+        buf.put_u32(0);
+        buf.put_u8(MessageType::Keepalive as u8);
+    }
+}
+
+/// Sends a KEEPALIVE every KEEPALIVE_INTERVAL, as long as its end is
+/// waiting on something through [`Heartbeat::wait`]
+#[derive(Debug)]
+pub struct Heartbeat {
+    ticker: Option<tokio::time::Interval>,
+}
+
+impl Heartbeat {
+    /// A heartbeat that only beats if `enabled` (the peer reads KEEPALIVEs)
+    pub fn new(enabled: bool) -> Self {
+        let ticker = enabled.then(|| {
+            let start = tokio::time::Instant::now() + KEEPALIVE_INTERVAL;
+            let mut ticker = tokio::time::interval_at(start, KEEPALIVE_INTERVAL);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker
+        });
+        Self { ticker }
+    }
+
+    /// Wait for `fut`, writing KEEPALIVEs to `w` whenever one is due
+    pub async fn wait<F, W>(&mut self, w: &mut W, fut: F) -> Result<F::Output>
+    where
+        F: Future,
+        W: AsyncWrite + Unpin,
+    {
+        let Some(ticker) = &mut self.ticker else {
+            return Ok(fut.await);
+        };
+        tokio::pin!(fut);
+        loop {
+            tokio::select! {
+                biased;
+                _ = ticker.tick() => {
+                    write_frame(w, &Keepalive.encode()).await?;
+                    w.flush().await.context("Failed to write frame")?;
+                }
+                output = &mut fut => return Ok(output),
+            }
+        }
+    }
+}
+
+/// A reader that fails once it has waited `IDLE_TIMEOUT` for the peer
+/// without getting a byte. Only the time spent waiting counts, not the
+/// time its owner takes between reads
+pub struct IdleReader<R> {
+    inner: R,
+    idle: Option<Duration>,
+    deadline: Pin<Box<tokio::time::Sleep>>,
+    waiting: bool,
+}
+
+impl<R> IdleReader<R> {
+    /// Wrap `inner`, timing it out after `idle` (never if `None`)
+    pub fn new(inner: R, idle: Option<Duration>) -> Self {
+        Self {
+            inner,
+            idle,
+            deadline: Box::pin(tokio::time::sleep(Duration::ZERO)),
+            waiting: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for IdleReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if let Poll::Ready(read) = Pin::new(&mut this.inner).poll_read(cx, buf) {
+            this.waiting = false;
+            return Poll::Ready(read);
+        }
+        let Some(idle) = this.idle else {
+            return Poll::Pending;
+        };
+        if !std::mem::replace(&mut this.waiting, true) {
+            this.deadline
+                .as_mut()
+                .reset(tokio::time::Instant::now() + idle);
+        }
+        match this.deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!(
+                    "the remote went silent for {}s; it may have hung, or the connection dropped",
+                    idle.as_secs()
+                ),
+            ))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// =============================================================================
+// Frame reading/writing
+// =============================================================================
+
+/// Maximum frame size (64MB) - prevents OOM from malicious/corrupted frames
+pub const MAX_FRAME_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Read a single frame from the stream, skipping KEEPALIVEs.
+/// Returns (message_type, payload).
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> Result<(MessageType, Bytes)> {
+    read_frame_within(r, MAX_FRAME_SIZE).await
+}
+
+/// [`read_frame`], refusing frames over `max` bytes before allocating
+/// them: for a peer nothing is known about yet (the daemon's HELLO)
+pub async fn read_frame_within<R: AsyncRead + Unpin>(
+    r: &mut R,
+    max: u32,
+) -> Result<(MessageType, Bytes)> {
+    loop {
+        let (msg_type, payload) = read_any_frame(r, max).await?;
+        if msg_type != MessageType::Keepalive {
+            return Ok((msg_type, payload));
+        }
+    }
+}
+
+async fn read_any_frame<R: AsyncRead + Unpin>(r: &mut R, max: u32) -> Result<(MessageType, Bytes)> {
+    let len = r.read_u32().await.context("Failed to read frame length")?;
+
+    // Validate frame size before allocation
+    if len > max {
+        malformed!("Frame size {} exceeds maximum allowed size {}", len, max);
+    }
+
+    let msg_type = r.read_u8().await.context("Failed to read message type")?;
+    let msg_type = MessageType::from_u8(msg_type).ok_or_else(|| unknown_type(msg_type))?;
+
+    let payload_len = len as usize;
+    let mut payload = vec![0u8; payload_len];
+    r.read_exact(&mut payload)
+        .await
+        .context("Failed to read frame payload")?;
+
+    Ok((msg_type, Bytes::from(payload)))
+}
+
+/// Split the next frame off a buffer of whole encoded frames (e.g. a batch
+/// of DEST_FILE_ENTRY), or `None` once the buffer is empty.
+pub fn next_frame(buf: &mut Bytes) -> Result<Option<(MessageType, Bytes)>> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    if buf.len() < 5 {
+        malformed!("Truncated frame header");
+    }
+    let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    let msg_type = MessageType::from_u8(buf[4]).ok_or_else(|| unknown_type(buf[4]))?;
+    if buf.len() < 5 + len {
+        malformed!("Truncated frame payload");
+    }
+    let mut frame = buf.split_to(5 + len);
+    Ok(Some((msg_type, frame.split_off(5))))
+}
+
+fn unknown_type(msg_type: u8) -> crate::error::SyncError {
+    crate::error::SyncError::Protocol(format!("Unknown message type 0x{:02x}", msg_type))
 }
 
 /// Write a pre-encoded frame to the stream.
@@ -1150,25 +2481,103 @@ mod tests {
         assert!(decoded.is_pull());
         assert!(decoded.flags.contains(HelloFlags::DELETE));
         assert_eq!(decoded.root_path, "/tmp/dest");
+        assert_eq!(decoded.manifest, 0);
+
+        let hello = Hello::new(HelloFlags::DEST_MANIFEST, "/srv").with_manifest(42);
+        let decoded = Hello::decode(hello.encode().slice(5..)).unwrap();
+        assert_eq!(decoded.manifest, 42);
+
+        // Peers that predate the manifest id send none
+        let mut old = BytesMut::new();
+        old.put_u16(PROTOCOL_VERSION);
+        old.put_u32(HelloFlags::PULL.bits());
+        old.put_u16(4);
+        old.put_slice(b"/srv");
+        assert_eq!(Hello::decode(old.freeze()).unwrap().manifest, 0);
+    }
+
+    #[tokio::test]
+    async fn test_encode_into_appends_frames() {
+        let mkdir = Mkdir {
+            path: "dir".to_string(),
+            mode: 0o755,
+            owner: None,
+        };
+        let delete = Delete {
+            path: "old.txt".to_string(),
+            is_dir: false,
+        };
+        let end = DestFileEnd {
+            total_files: 3,
+            total_bytes: 42,
+            manifest: 7,
+            base: 0,
+        };
+
+        let mut buf = BytesMut::new();
+        mkdir.encode_into(&mut buf);
+        delete.encode_into(&mut buf);
+        end.encode_into(&mut buf);
+
+        let mut expected = BytesMut::new();
+        expected.extend_from_slice(&mkdir.encode());
+        expected.extend_from_slice(&delete.encode());
+        expected.extend_from_slice(&end.encode());
+        assert_eq!(buf, expected);
+
+        // Frames read back individually
+        let mut reader = &buf[..];
+        let (msg_type, payload) = read_frame(&mut reader).await.unwrap();
+        assert_eq!(msg_type, MessageType::Mkdir);
+        assert_eq!(Mkdir::decode(payload).unwrap().path, "dir");
+        let (msg_type, _) = read_frame(&mut reader).await.unwrap();
+        assert_eq!(msg_type, MessageType::Delete);
+        let (msg_type, payload) = read_frame(&mut reader).await.unwrap();
+        assert_eq!(msg_type, MessageType::DestFileEnd);
+        assert_eq!(DestFileEnd::decode(payload).unwrap().total_bytes, 42);
+        assert!(reader.is_empty());
+
+        // Same frames split synchronously from the batch
+        let mut batch = buf.clone().freeze();
+        let (msg_type, payload) = next_frame(&mut batch).unwrap().unwrap();
+        assert_eq!(msg_type, MessageType::Mkdir);
+        assert_eq!(Mkdir::decode(payload).unwrap().path, "dir");
+        assert_eq!(
+            next_frame(&mut batch).unwrap().unwrap().0,
+            MessageType::Delete
+        );
+        assert_eq!(
+            next_frame(&mut batch).unwrap().unwrap().0,
+            MessageType::DestFileEnd
+        );
+        assert!(next_frame(&mut batch).unwrap().is_none());
+
+        let mut truncated = buf.freeze().slice(..7);
+        assert!(next_frame(&mut truncated).is_err());
     }
 
     #[test]
     fn test_file_entry_roundtrip() {
         let entry = FileEntry {
-            path: "test/file.txt".to_string(),
+            path: "test/file.txt".into(),
             size: 1024,
             mtime: 1234567890,
+            mtime_nsec: 0,
             mode: 0o644,
             inode: 12345,
             flags: FileFlags::empty(),
             symlink_target: None,
             link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
         };
         let encoded = entry.encode();
         let payload = Bytes::copy_from_slice(&encoded[5..]);
         let decoded = FileEntry::decode(payload).unwrap();
 
-        assert_eq!(decoded.path, "test/file.txt");
+        assert_eq!(&*decoded.path, "test/file.txt");
         assert_eq!(decoded.size, 1024);
         assert_eq!(decoded.mtime, 1234567890);
         assert_eq!(decoded.mode, 0o644);
@@ -1178,14 +2587,19 @@ mod tests {
     #[test]
     fn test_file_entry_symlink() {
         let entry = FileEntry {
-            path: "link".to_string(),
+            path: "link".into(),
             size: 0,
             mtime: 1234567890,
+            mtime_nsec: 0,
             mode: 0o777,
             inode: 0,
             flags: FileFlags::SYMLINK,
             symlink_target: Some("target.txt".to_string()),
             link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
         };
         let encoded = entry.encode();
         let payload = Bytes::copy_from_slice(&encoded[5..]);
@@ -1198,14 +2612,19 @@ mod tests {
     #[test]
     fn test_file_entry_hardlink() {
         let entry = FileEntry {
-            path: "hardlink".to_string(),
+            path: "hardlink".into(),
             size: 1024,
             mtime: 1234567890,
+            mtime_nsec: 0,
             mode: 0o644,
             inode: 12345,
             flags: FileFlags::HARDLINK,
             symlink_target: None,
             link_target: Some("original.txt".to_string()),
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
         };
         let encoded = entry.encode();
         let payload = Bytes::copy_from_slice(&encoded[5..]);
@@ -1215,12 +2634,108 @@ mod tests {
         assert_eq!(decoded.link_target, Some("original.txt".to_string()));
     }
 
+    #[test]
+    fn test_file_entry_attributes() {
+        let entry = FileEntry {
+            path: "desktop.ini".into(),
+            size: 64,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: FileFlags::HARDLINK,
+            symlink_target: None,
+            link_target: Some("original.ini".to_string()),
+            win_attrs: Some(0x2 | 0x4),
+            bsd_flags: Some(0x8000),
+            owner: None,
+            times: None,
+        };
+        let encoded = entry.encode();
+        let payload = Bytes::copy_from_slice(&encoded[5..]);
+        let decoded = FileEntry::decode(payload).unwrap();
+
+        assert!(decoded.flags.contains(FileFlags::WIN_ATTRS));
+        assert_eq!(decoded.link_target, Some("original.ini".to_string()));
+        assert_eq!(decoded.win_attrs, Some(0x2 | 0x4));
+        assert!(decoded.flags.contains(FileFlags::BSD_FLAGS));
+        assert_eq!(decoded.bsd_flags, Some(0x8000));
+
+        // Cut the flags off
+        let truncated = Bytes::copy_from_slice(&encoded[5..encoded.len() - 2]);
+        assert!(FileEntry::decode(truncated).is_err());
+    }
+
+    #[test]
+    fn test_ownership_trails_entries() {
+        let owner = Some(Ownership {
+            uid: 1000,
+            gid: 100,
+        });
+        let entry = FileEntry {
+            path: "link".into(),
+            size: 0,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o777,
+            inode: 0,
+            flags: FileFlags::SYMLINK,
+            symlink_target: Some("target".to_string()),
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: Some(0x8000),
+            owner,
+            times: None,
+        };
+        let encoded = entry.encode();
+        let decoded = FileEntry::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.symlink_target.as_deref(), Some("target"));
+        assert_eq!(decoded.bsd_flags, Some(0x8000));
+        assert_eq!(decoded.owner, owner);
+
+        // What an older peer sends, or we send without -o/-g
+        let plain = FileEntry {
+            owner: None,
+            ..entry
+        }
+        .encode();
+        assert_eq!(plain.len() + Ownership::SIZE, encoded.len());
+        let decoded = FileEntry::decode(Bytes::copy_from_slice(&plain[5..])).unwrap();
+        assert_eq!(decoded.owner, None);
+
+        let mkdir = Mkdir {
+            path: "dir".to_string(),
+            mode: 0o2775,
+            owner,
+        }
+        .encode();
+        let decoded = Mkdir::decode(Bytes::copy_from_slice(&mkdir[5..])).unwrap();
+        assert_eq!((decoded.mode, decoded.owner), (0o2775, owner));
+
+        let symlink = Symlink {
+            path: "link".to_string(),
+            target: "target".to_string(),
+            owner,
+        }
+        .encode();
+        let decoded = Symlink::decode(Bytes::copy_from_slice(&symlink[5..])).unwrap();
+        assert_eq!(decoded.owner, owner);
+        let symlink = Symlink {
+            owner: None,
+            ..decoded
+        }
+        .encode();
+        let decoded = Symlink::decode(Bytes::copy_from_slice(&symlink[5..])).unwrap();
+        assert_eq!(decoded.owner, None);
+    }
+
     #[test]
     fn test_dest_file_entry_with_checksums() {
         let entry = DestFileEntry {
             path: "large.bin".to_string(),
             size: 1024 * 1024,
             mtime: 1234567890,
+            mtime_nsec: 0,
             mode: 0o644,
             flags: DestFileFlags::HAS_CHECKSUMS,
             block_size: 4096,
@@ -1249,10 +2764,215 @@ mod tests {
         assert_eq!(decoded.checksums[1].strong, 0x0FEDCBA987654321);
     }
 
+    #[test]
+    fn test_mtime_nsec_trails_entries() {
+        let entry = FileEntry {
+            path: "build/out.o".into(),
+            size: 10,
+            mtime: 1234567890,
+            mtime_nsec: 123_456_789,
+            mode: 0o644,
+            inode: 0,
+            flags: FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        let encoded = entry.encode();
+        let decoded = FileEntry::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!((decoded.mtime_nsec, decoded.owner), (123_456_789, None));
+
+        // After the Ownership, when there is one
+        let owner = Some(Ownership { uid: 1, gid: 2 });
+        let encoded = FileEntry {
+            owner,
+            ..entry.clone()
+        }
+        .encode();
+        let decoded = FileEntry::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!((decoded.mtime_nsec, decoded.owner), (123_456_789, owner));
+
+        // Whole seconds add nothing to the frame
+        let whole = FileEntry {
+            mtime_nsec: 0,
+            ..entry.clone()
+        }
+        .encode();
+        assert_eq!(whole.len() + 4, entry.encode().len());
+        let decoded = FileEntry::decode(Bytes::copy_from_slice(&whole[5..])).unwrap();
+        assert_eq!(decoded.mtime_nsec, 0);
+
+        let dest = DestFileEntry {
+            path: "build/out.o".to_string(),
+            size: 10,
+            mtime: 1234567890,
+            mtime_nsec: 999_999_999,
+            mode: 0o644,
+            flags: DestFileFlags::HAS_CHECKSUMS,
+            block_size: 4096,
+            checksums: vec![BlockChecksum {
+                offset: 0,
+                weak: 1,
+                strong: 2,
+            }],
+        }
+        .encode();
+        let decoded = DestFileEntry::decode(Bytes::copy_from_slice(&dest[5..])).unwrap();
+        assert_eq!(
+            (decoded.mtime_nsec, decoded.checksums.len()),
+            (999_999_999, 1)
+        );
+
+        let attr = SetAttr {
+            path: "build/out.o".to_string(),
+            mode: 0o644,
+            mtime: 1234567890,
+            mtime_nsec: 1,
+            flags: SetAttrFlags::MTIME,
+        }
+        .encode();
+        let decoded = SetAttr::decode(Bytes::copy_from_slice(&attr[5..])).unwrap();
+        assert_eq!((decoded.mtime, decoded.mtime_nsec), (1234567890, 1));
+    }
+
+    #[test]
+    fn test_times_lead_the_trailers() {
+        let times = Times {
+            atime: 1_700_000_000,
+            atime_nsec: 7,
+            btime: -1,
+            btime_nsec: 999_999_999,
+        };
+        let owner = Some(Ownership { uid: 1, gid: 2 });
+        let entry = FileEntry {
+            path: "f".into(),
+            size: 1,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: Some(times),
+        };
+        // Every mix of the optional trailers reads back as it was written
+        for (owner, mtime_nsec) in [(None, 0), (owner, 0), (None, 5), (owner, 5)] {
+            let encoded = FileEntry {
+                owner,
+                mtime_nsec,
+                ..entry.clone()
+            }
+            .encode();
+            let decoded = FileEntry::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+            assert_eq!(
+                (decoded.times, decoded.owner, decoded.mtime_nsec),
+                (Some(times), owner, mtime_nsec)
+            );
+        }
+        assert_eq!(times.birth(), Some((-1, 999_999_999)));
+
+        let encoded = FileEntry {
+            times: None,
+            ..entry
+        }
+        .encode();
+        let decoded = FileEntry::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.times, None);
+    }
+
+    #[test]
+    fn test_dest_file_entry_compressed_checksums() {
+        let checksums: Vec<BlockChecksum> = (0..1000u64)
+            .map(|i| BlockChecksum {
+                offset: i * 4096,
+                weak: (i as u32).wrapping_mul(0x9E37_79B9),
+                strong: i.wrapping_mul(0x9E37_79B9_7F4A_7C15) as u128,
+            })
+            .collect();
+        let mut entry = DestFileEntry {
+            path: "big.bin".to_string(),
+            size: 1000 * 4096,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            flags: DestFileFlags::HAS_CHECKSUMS,
+            block_size: 4096,
+            checksums,
+        };
+        let raw_len = entry.encode().len();
+
+        entry.flags |= DestFileFlags::CHECKSUMS_ZSTD;
+        let encoded = entry.encode();
+        assert!(
+            encoded.len() < raw_len,
+            "compressed {} should be smaller than raw {}",
+            encoded.len(),
+            raw_len
+        );
+
+        let decoded = DestFileEntry::decode(encoded.slice(5..)).unwrap();
+        assert!(decoded.flags.contains(DestFileFlags::CHECKSUMS_ZSTD));
+        assert_eq!(decoded.block_size, 4096);
+        assert_eq!(decoded.checksums.len(), 1000);
+        for (a, b) in decoded.checksums.iter().zip(&entry.checksums) {
+            assert_eq!(a.offset, b.offset);
+            assert_eq!(a.weak, b.weak);
+            assert_eq!(a.strong, b.strong);
+        }
+    }
+
+    #[test]
+    fn test_dest_file_entry_wide_checksums() {
+        let checksums: Vec<BlockChecksum> = (0..100u64)
+            .map(|i| BlockChecksum {
+                offset: i * 4096,
+                weak: i as u32,
+                strong: u128::MAX - i as u128,
+            })
+            .collect();
+        let entry = DestFileEntry {
+            path: "big.bin".to_string(),
+            size: 100 * 4096,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            flags: DestFileFlags::HAS_CHECKSUMS | DestFileFlags::STRONG_128,
+            block_size: 4096,
+            checksums,
+        };
+        let narrow = DestFileEntry {
+            flags: DestFileFlags::HAS_CHECKSUMS,
+            ..entry.clone()
+        };
+        assert_eq!(
+            entry.encode().len() - narrow.encode().len(),
+            100 * (BlockChecksum::WIDE_SIZE - BlockChecksum::SIZE)
+        );
+
+        for flags in [DestFileFlags::empty(), DestFileFlags::CHECKSUMS_ZSTD] {
+            let entry = DestFileEntry {
+                flags: entry.flags | flags,
+                ..entry.clone()
+            };
+            let decoded = DestFileEntry::decode(entry.encode().slice(5..)).unwrap();
+            assert_eq!(decoded.flags, entry.flags);
+            assert_eq!(decoded.checksums.len(), 100);
+            assert_eq!(decoded.checksums[7].strong, u128::MAX - 7);
+            assert_eq!(decoded.checksums[99].offset, 99 * 4096);
+        }
+    }
+
     #[test]
     fn test_data_roundtrip() {
         let data = Data {
-            path: "file.txt".to_string(),
+            path: "file.txt".into(),
             offset: 1024,
             flags: DataFlags::COMPRESSED,
             data: Bytes::from(vec![1, 2, 3, 4, 5]),
@@ -1261,7 +2981,7 @@ mod tests {
         let payload = Bytes::copy_from_slice(&encoded[5..]);
         let decoded = Data::decode(payload).unwrap();
 
-        assert_eq!(decoded.path, "file.txt");
+        assert_eq!(&*decoded.path, "file.txt");
         assert_eq!(decoded.offset, 1024);
         assert!(decoded.flags.contains(DataFlags::COMPRESSED));
         assert_eq!(decoded.data.as_ref(), &[1, 2, 3, 4, 5]);
@@ -1308,6 +3028,93 @@ mod tests {
         assert_eq!(decoded.entries[0].name, "user.comment");
     }
 
+    #[test]
+    fn test_data_end_hash_trails_status() {
+        let end = DataEnd {
+            path: "file.bin".into(),
+            status: DataEnd::STATUS_OK,
+            hash: Some(0x0123_4567_89ab_cdef_fedc_ba98_7654_3210),
+        };
+        let encoded = end.encode();
+        let decoded = DataEnd::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.hash, end.hash);
+
+        // Without one, the frame is what older peers send
+        let plain = DataEnd { hash: None, ..end }.encode();
+        assert_eq!(plain.len(), encoded.len() - 16);
+        let decoded = DataEnd::decode(Bytes::copy_from_slice(&plain[5..])).unwrap();
+        assert_eq!((decoded.status, decoded.hash), (DataEnd::STATUS_OK, None));
+    }
+
+    #[test]
+    fn test_acl_roundtrip() {
+        let acl = Acl {
+            path: "dir/file.txt".to_string(),
+            text: "user::rw-\nuser:1000:r--\ngroup::r--\nmask::r--\nother::---".to_string(),
+        };
+        let encoded = acl.encode();
+        let decoded = Acl::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.path, acl.path);
+        assert_eq!(decoded.text, acl.text);
+
+        assert!(Acl::decode(Bytes::copy_from_slice(&encoded[5..encoded.len() - 1])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keepalives_are_skipped_and_silence_times_out() {
+        let mut wire = BytesMut::new();
+        Keepalive.encode_into(&mut wire);
+        Keepalive.encode_into(&mut wire);
+        wire.extend_from_slice(&DeleteEnd { count: 3 }.encode());
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(&wire).await.unwrap();
+
+        let mut reader = IdleReader::new(server, Some(Duration::from_millis(50)));
+        let (msg_type, _) = read_frame(&mut reader).await.unwrap();
+        assert_eq!(msg_type, MessageType::DeleteEnd);
+
+        // The peer is still connected, but says nothing
+        let err = read_frame(&mut reader).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("the remote went silent"));
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn test_demux_joins_parts_around_control_frames() {
+        let data = Data {
+            path: Arc::from("big.bin"),
+            offset: 0,
+            flags: DataFlags::empty(),
+            data: Bytes::from(vec![7u8; 3 * PART_SIZE]),
+        }
+        .encode();
+        let parts = split_frames(&data, CHANNEL_DATA);
+        assert_eq!(parts.len(), 4);
+        let log = Log {
+            level: 1,
+            message: "between".to_string(),
+        }
+        .encode();
+        assert_eq!(split_frames(&log, CHANNEL_DATA), vec![log.clone()]);
+
+        // A LOG written between the pieces arrives before the DATA
+        let mut wire = BytesMut::new();
+        wire.extend_from_slice(&parts[0]);
+        wire.extend_from_slice(&log);
+        for part in &parts[1..] {
+            wire.extend_from_slice(part);
+        }
+        let mut reader = &wire[..];
+        let mut demux = Demux::new();
+        let (msg_type, payload) = demux.read_frame(&mut reader).await.unwrap();
+        assert_eq!(msg_type, MessageType::Log);
+        assert_eq!(Log::decode(payload).unwrap().message, "between");
+        let (msg_type, payload) = demux.read_frame(&mut reader).await.unwrap();
+        assert_eq!(msg_type, MessageType::Data);
+        assert_eq!(payload, data.slice(5..));
+        assert!(demux.read_frame(&mut reader).await.is_err());
+    }
+
     #[test]
     fn test_done_roundtrip() {
         let done = Done {
@@ -1315,15 +3122,162 @@ mod tests {
             files_err: 2,
             bytes: 1024 * 1024 * 50,
             duration_ms: 5000,
+            digest: Some(0x0123_4567_89ab_cdef_fedc_ba98_7654_3210),
+            phases: PhaseTimes {
+                scan: Duration::from_millis(1200),
+                exchange: Duration::from_millis(300),
+                transfer: Duration::from_millis(3400),
+                delete: Duration::from_millis(100),
+            },
+            counts: FileCounts {
+                created: 60,
+                updated: 30,
+                touched: 10,
+                skipped: 400,
+            },
+            would: WouldBytes {
+                add: 4096,
+                change: 512,
+                delete: 64,
+            },
+            backed_up: 7,
         };
         let encoded = done.encode();
         let payload = Bytes::copy_from_slice(&encoded[5..]);
-        let decoded = Done::decode(payload).unwrap();
+        let decoded = Done::decode(payload.clone()).unwrap();
 
         assert_eq!(decoded.files_ok, 100);
         assert_eq!(decoded.files_err, 2);
         assert_eq!(decoded.bytes, 1024 * 1024 * 50);
         assert_eq!(decoded.duration_ms, 5000);
+        assert_eq!(
+            decoded.digest,
+            Some(0x0123_4567_89ab_cdef_fedc_ba98_7654_3210)
+        );
+        assert_eq!(decoded.phases, done.phases);
+        assert_eq!(decoded.counts, done.counts);
+        assert_eq!(decoded.would, done.would);
+        assert_eq!(decoded.backed_up, 7);
+
+        // A DONE without backups, dry-run bytes, file counts or phase
+        // times, from an older peer
+        let decoded = Done::decode(payload.slice(..136)).unwrap();
+        assert_eq!(decoded.would, done.would);
+        assert_eq!(decoded.backed_up, 0);
+        let decoded = Done::decode(payload.slice(..112)).unwrap();
+        assert_eq!(decoded.counts, done.counts);
+        assert_eq!(decoded.would, WouldBytes::default());
+        let decoded = Done::decode(payload.slice(..80)).unwrap();
+        assert_eq!(decoded.phases, done.phases);
+        assert_eq!(decoded.counts, FileCounts::default());
+        let decoded = Done::decode(payload.slice(..48)).unwrap();
+        assert_eq!(decoded.duration_ms, 5000);
+        assert_eq!(decoded.phases, PhaseTimes::default());
+        // ...or without the digest either
+        let decoded = Done::decode(payload.slice(..32)).unwrap();
+        assert_eq!(decoded.files_ok, 100);
+        assert_eq!(decoded.digest, None);
+        assert!(Done::decode(payload.slice(..31)).is_err());
+    }
+
+    #[test]
+    fn test_log_roundtrip() {
+        let log = Log {
+            level: Log::WARN,
+            message: "Failed to set permissions on a.txt".to_string(),
+        };
+        let encoded = log.encode();
+        assert_eq!(encoded[4], MessageType::Log as u8);
+        let decoded = Log::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.level, Log::WARN);
+        assert_eq!(decoded.message, log.message);
+
+        // Too long for the length prefix: cut at a character boundary
+        let log = Log {
+            level: Log::ERROR,
+            message: "é".repeat(40_000),
+        };
+        let encoded = log.encode();
+        let decoded = Log::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.message.len(), 65_534);
+        assert!(log.message.starts_with(&decoded.message));
+    }
+
+    #[test]
+    fn test_list_and_read_roundtrip() {
+        let list = List {
+            path: "photos/2024".to_string(),
+        };
+        let encoded = list.encode();
+        assert_eq!(encoded[4], MessageType::List as u8);
+        let decoded = List::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.path, "photos/2024");
+
+        let read = Read {
+            path: "photos/2024/a.jpg".to_string(),
+            offset: 1 << 33,
+            len: 128 * 1024,
+        };
+        let encoded = read.encode();
+        assert_eq!(encoded[4], MessageType::Read as u8);
+        let decoded = Read::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.path, read.path);
+        assert_eq!(decoded.offset, 1 << 33);
+        assert_eq!(decoded.len, 128 * 1024);
+        assert!(Read::decode(Bytes::copy_from_slice(&encoded[5..encoded.len() - 1])).is_err());
+    }
+
+    #[test]
+    fn test_set_attr_roundtrip() {
+        let attr = SetAttr {
+            path: "bin/run.sh".to_string(),
+            mode: 0o755,
+            mtime: -86_400,
+            mtime_nsec: 0,
+            flags: SetAttrFlags::MODE | SetAttrFlags::MTIME,
+        };
+        let encoded = attr.encode();
+        assert_eq!(encoded[4], MessageType::SetAttr as u8);
+        let decoded = SetAttr::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.path, "bin/run.sh");
+        assert_eq!(decoded.mode, 0o755);
+        assert_eq!(decoded.mtime, -86_400);
+        assert_eq!(decoded.flags, SetAttrFlags::MODE | SetAttrFlags::MTIME);
+        assert!(SetAttr::decode(Bytes::copy_from_slice(&encoded[5..encoded.len() - 1])).is_err());
+    }
+
+    #[test]
+    fn test_filter_roundtrip() {
+        let filter = Filter {
+            rules: vec!["+ *.rs".to_string(), "- target/".to_string()],
+        };
+        let encoded = filter.encode();
+        assert_eq!(encoded[4], MessageType::Filter as u8);
+        let decoded = Filter::decode(Bytes::copy_from_slice(&encoded[5..])).unwrap();
+        assert_eq!(decoded.rules, filter.rules);
+        assert!(Filter::decode(Bytes::copy_from_slice(&encoded[5..encoded.len() - 1])).is_err());
+        assert!(Filter::decode(Filter::default().encode().slice(5..))
+            .unwrap()
+            .rules
+            .is_empty());
+    }
+
+    #[test]
+    fn test_file_end_roundtrip() {
+        let end = FileEnd {
+            total_files: 12,
+            total_bytes: 4096,
+            skipped: 7,
+        };
+        let encoded = end.encode();
+        let payload = Bytes::copy_from_slice(&encoded[5..]);
+        assert_eq!(FileEnd::decode(payload.clone()).unwrap().skipped, 7);
+
+        // Older peers don't send the skipped count
+        let decoded = FileEnd::decode(payload.slice(..16)).unwrap();
+        assert_eq!(decoded.total_files, 12);
+        assert_eq!(decoded.total_bytes, 4096);
+        assert_eq!(decoded.skipped, 0);
     }
 
     #[test]
@@ -1331,6 +3285,12 @@ mod tests {
         assert_eq!(MessageType::from_u8(0x01), Some(MessageType::Hello));
         assert_eq!(MessageType::from_u8(0x06), Some(MessageType::Data));
         assert_eq!(MessageType::from_u8(0x10), Some(MessageType::Done));
+        assert_eq!(MessageType::from_u8(0x11), Some(MessageType::Log));
+        assert_eq!(MessageType::from_u8(0x13), Some(MessageType::Read));
+        assert_eq!(MessageType::from_u8(0x14), Some(MessageType::SetAttr));
+        assert_eq!(MessageType::from_u8(0x15), Some(MessageType::Filter));
+        assert_eq!(MessageType::from_u8(0x17), Some(MessageType::Part));
+        assert_eq!(MessageType::from_u8(0x18), Some(MessageType::Keepalive));
         assert_eq!(MessageType::from_u8(0xFF), None);
     }
 
@@ -1,25 +1,62 @@
 //! Receiver task for streaming sync.
 //!
-//! Receives Data messages and writes files to disk.
+//! Receives Data messages and writes files through a storage backend
+//! (the local filesystem unless `with_storage` says otherwise).
 //! Handles Initial Exchange by sending DEST_FILE_ENTRY.
 
-use crate::streaming::channel::SyncStats;
+use crate::delta::StrongHash;
+use crate::error::{ErrorCode, SyncError};
+use crate::filter::FilterEngine;
+use crate::streaming::audit::{AuditLog, AuditOp};
+use crate::streaming::backup::Backup;
 use crate::streaming::channel::DELTA_MIN_SIZE;
+use crate::streaming::channel::{Cancelled, SyncStats};
+use crate::streaming::dest_manifest::{DestManifest, MANIFEST_FILE};
+use crate::streaming::digest::{FileDigest, TransferDigest};
+use crate::streaming::intern::PathTable;
+use crate::streaming::io::{FsyncPolicy, IoOptions};
+use crate::streaming::owner::OwnerMap;
+use crate::streaming::policy::ReceivePolicy;
 use crate::streaming::protocol::{
-    Data, DataEnd, DataFlags, Delete, DeleteEnd, DestFileEnd, DestFileEntry, DestFileFlags,
-    FileEnd, FileEntry, MessageType, Mkdir, Symlink,
+    Acl, Data, DataEnd, DataFlags, Delete, DeleteEnd, DestFileEnd, DestFileEntry, DestFileFlags,
+    Error, FileEnd, FileEntry, FileFlags, MessageType, Mkdir, Ownership, Progress, SetAttr,
+    SetAttrFlags, Symlink, Xattr, CHECKSUM_COMPRESS_MIN, MAX_FRAME_SIZE,
 };
-use crate::temp_file::TempFileGuard;
+use crate::streaming::storage::{is_temp_path, Listed, LocalStorage, Storage, StorageFile};
+use crate::streaming::transfer_log::{Transfer, TransferLog};
 use anyhow::{Context, Result};
 use bytes::{Buf, Bytes, BytesMut};
-use std::collections::HashMap;
+use filetime::FileTime;
+use futures::stream::{self, StreamExt};
+use futures::FutureExt;
+use rand_core::{OsRng, RngCore};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::path::{Component, Path, PathBuf};
-use tokio::fs::{self, File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
 
 /// Maximum size for delta copy operations (16MB)
 const MAX_DELTA_COPY_SIZE: usize = 16 * 1024 * 1024;
 
+/// With `--fsync batch`, sync the filesystem after this many files...
+const BATCH_SYNC_FILES: u64 = 1000;
+
+/// ...or this many bytes, whichever comes first
+const BATCH_SYNC_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Where `--atomic` stages a transfer, under the destination root: this
+/// and a random suffix, so no directory of the user's is ever taken for one
+pub const STAGE_PREFIX: &str = ".sy-stage-";
+
+/// Files hashed concurrently during the Initial Exchange
+fn checksum_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
 /// Batch size for DEST_FILE_ENTRY messages (64KB)
 /// Reduces syscalls by batching multiple encoded frames into single writes
 const DEST_ENTRY_BATCH_SIZE: usize = 64 * 1024;
@@ -47,6 +84,10 @@ fn validate_path(root: &Path, relative: &str) -> Result<PathBuf> {
             Component::Prefix(_) => {
                 anyhow::bail!("Windows prefix paths not allowed: {}", relative);
             }
+            // `\dir` on Windows: relative, but to the root of the drive
+            Component::RootDir => {
+                anyhow::bail!("Absolute paths not allowed: {}", relative);
+            }
             _ => {}
         }
     }
@@ -111,42 +152,350 @@ fn validate_symlink_target(root: &Path, link_path: &Path, target: &str) -> Resul
     Ok(())
 }
 
+/// Refuse to go through a symlinked directory on the way to `dir`
+///
+/// `validate_path` only looks at the text of a path. A hostile peer can
+/// create a harmless-looking symlink (or a chain of them) and then send
+/// entries through it, and the destination may already hold links that
+/// point elsewhere, so every existing component of `dir` under `root` is
+/// checked with lstat. Asking the filesystem also covers case-insensitive
+/// volumes, where `Link/x` goes through a symlink named `link`. Directories
+/// found to be real are remembered in `checked`.
+fn check_no_symlinks(root: &Path, dir: &Path, checked: &mut HashSet<PathBuf>) -> Result<()> {
+    let mut prefix = PathBuf::new();
+    for component in dir.components() {
+        prefix.push(component);
+        if checked.contains(&prefix) {
+            continue;
+        }
+        match std::fs::symlink_metadata(root.join(&prefix)) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                anyhow::bail!(
+                    "Refusing to write through symlinked directory: {}",
+                    prefix.display()
+                );
+            }
+            Ok(meta) if meta.is_dir() => {
+                checked.insert(prefix.clone());
+            }
+            // Missing (or not a directory): nothing below it exists yet
+            _ => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// Check where a freshly created symlink really leads
+///
+/// `validate_symlink_target` resolves the target lexically; this asks the
+/// filesystem, which catches links that only escape `root` in combination
+/// with links already there. Dangling links are left alone.
+fn check_symlink_resolves(root: &Path, link: &Path) -> Result<()> {
+    let Ok(resolved) = std::fs::canonicalize(link) else {
+        return Ok(());
+    };
+    let root = std::fs::canonicalize(root)
+        .with_context(|| format!("Failed to resolve {}", root.display()))?;
+    if !resolved.starts_with(&root) {
+        anyhow::bail!(
+            "Symlink resolves outside root: {} -> {}",
+            link.display(),
+            resolved.display()
+        );
+    }
+    Ok(())
+}
+
 /// Receiver configuration
 pub struct ReceiverConfig {
     /// Root path for writing files
     pub root: PathBuf,
-    /// Block size for checksums
-    pub block_size: u32,
+    /// zstd-compress large checksum arrays in DEST_FILE_ENTRY
+    /// (negotiated via HelloFlags::COMPRESSION)
+    pub compress_checksums: bool,
+    /// Rejects further messages once cancelled; dropping the Receiver then
+    /// removes pending temp files
+    pub cancel: CancellationToken,
+    /// How file data is written to disk
+    pub io: IoOptions,
 }
 
 /// Receiver state
 pub struct Receiver {
     config: ReceiverConfig,
-    pending_files: HashMap<String, PendingFile>,
+    pending_files: HashMap<Arc<str>, PendingFile>,
+    /// Paths of pending files, shared by their DATA and DATA_END messages
+    paths: PathTable,
+    storage: Box<dyn Storage>,
     stats: SyncStats,
+    digest: TransferDigest,
+    /// Files and bytes written since the last batch sync
+    unsynced_files: u64,
+    unsynced_bytes: u64,
+    /// Directories under the root known not to be symlinks; cleared
+    /// whenever a symlink is created or something is deleted
+    real_dirs: HashSet<PathBuf>,
+    /// Where deletes, overwrites and permission changes are recorded
+    audit: Option<AuditLog>,
+    /// What this destination refuses to receive
+    policy: ReceivePolicy,
+    /// Paths left out of the Initial Exchange (the sync's include/exclude
+    /// rules)
+    filter: FilterEngine,
+    /// Files refused by `policy` or that failed to write, still digested
+    /// until their DATA_END
+    skipped: HashMap<Arc<str>, FileDigest>,
+    /// Whether `scan_dest` hashes delta candidates
+    checksums: bool,
+    /// Keep `.sy-manifest` up to date; the id is the manifest the peer has
+    /// (0 for none)
+    manifest: Option<u128>,
+    /// Apply the owners entries carry: `-o`/`-g` and we run as root
+    chown: bool,
+    /// How those owners are mapped first (pulls; a push's sender maps them)
+    owners: OwnerMap,
+    /// Holds the transfer back until `finish` (`--atomic`)
+    staging: Option<Staging>,
+    /// How to undo the replay of a staged transfer, while it runs
+    replay: Option<Replay>,
+    /// Where old versions go before they're replaced or deleted (`--backup`)
+    backup: Option<Backup>,
+    /// Where each file that lands or fails is recorded (`--log-file`)
+    transfer_log: Option<TransferLog>,
+    /// DATA may come zstd-compressed (HelloFlags::COMPRESSION was
+    /// negotiated)
+    compression: bool,
+}
+
+/// An `--atomic` transfer, held back until all of it has arrived
+///
+/// Files are committed under a directory named [`STAGE_PREFIX`] and a
+/// random suffix; every other change is kept as the frame it came in.
+/// `Receiver::finish` replays the lot in arrival order, renaming each
+/// staged file into place where it came, so a parent directory is made
+/// before its files and an XATTR lands after its file. Whatever a step
+/// replaces or deletes is moved aside into the staging directory first,
+/// so if a later step fails the replay is undone in reverse and the
+/// destination is left as it was. Dropping the staging (an error, a FATAL,
+/// a digest mismatch) removes the directory, unless undoing a replay
+/// failed and it still holds originals. One left by a run that was killed
+/// outright is an ordinary entry to the next, which `--delete` removes.
+struct Staging {
+    /// The staging directory's name, relative to the root
+    name: String,
+    /// The staging directory's full path
+    dir: PathBuf,
+    steps: Vec<Staged>,
+    /// Something couldn't be put back; leave the directory for the user
+    keep: bool,
+}
+
+enum Staged {
+    /// A file committed under the staging directory
+    File(Arc<str>),
+    /// A change applied on replay
+    Frame(MessageType, Bytes),
+}
+
+impl Staging {
+    /// Start staging under `root`; the directory is made as files arrive
+    fn new(root: &Path) -> Self {
+        let name = format!("{}{:016x}", STAGE_PREFIX, OsRng.next_u64());
+        Self {
+            dir: root.join(&name),
+            name,
+            steps: Vec::new(),
+            keep: false,
+        }
+    }
+
+    /// Where `path` is staged, relative to the root
+    fn path_of(&self, path: &str) -> String {
+        format!("{}/new/{}", self.name, path)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// A change an `--atomic` replay made, undone if a later step fails
+enum Undo {
+    /// What was at `path` was moved to `aside`, in the staging directory or
+    /// the backup
+    Restore { path: String, aside: String },
+    /// Nothing was at `path` before
+    Remove { path: String, is_dir: bool },
+    /// A SET_ATTR changed the mode and mtime of `path`
+    Attr {
+        path: String,
+        mode: u32,
+        mtime: FileTime,
+    },
+}
+
+/// An `--atomic` transfer being moved into place
+struct Replay {
+    /// The staging directory's name, relative to the root
+    stage: String,
+    undo: Vec<Undo>,
+}
+
+impl Replay {
+    /// Where the next entry the replay displaces is kept, relative to the
+    /// root
+    fn aside(&self) -> String {
+        format!("{}/old/{}", self.stage, self.undo.len())
+    }
 }
 
 struct PendingFile {
     entry: FileEntry,
-    temp_path: PathBuf,
-    file: Option<File>,
-    /// Cached original file handle for delta sync (avoids reopening per chunk)
-    original_file: Option<File>,
+    file: Box<dyn StorageFile>,
     bytes_written: u64,
-    guard: Option<TempFileGuard>,
+    /// Length of the file written so far
+    end: u64,
+    digest: FileDigest,
+    /// Mode of the entry this file replaces (audited runs only)
+    replaces: Option<u32>,
+    /// Whether something was at the path before
+    existed: bool,
+    /// Whether any of the content differs from the previous version;
+    /// a delta made of in-place copies leaves it false
+    rewritten: bool,
+    /// Whether its DATA is a delta against the previous version
+    delta: bool,
+    /// When its FILE_ENTRY came
+    started: Instant,
+    /// Open from FILE_ENTRY until DATA_END, timing the file for trace export
+    _span: tracing::Span,
 }
 
 impl Receiver {
     pub fn new(config: ReceiverConfig) -> Self {
+        let storage = Box::new(LocalStorage::new(config.root.clone(), config.io));
+        // FAT has no owners to set
+        let chown =
+            (config.io.owner || config.io.group) && !config.io.fat && crate::fs_util::is_root();
+        let staging = (config.io.atomic && !config.io.dry_run).then(|| Staging::new(&config.root));
         Self {
             config,
             pending_files: HashMap::new(),
+            paths: PathTable::new(),
+            storage,
             stats: SyncStats::new(),
+            digest: TransferDigest::new(),
+            unsynced_files: 0,
+            unsynced_bytes: 0,
+            real_dirs: HashSet::new(),
+            audit: None,
+            policy: ReceivePolicy::default(),
+            filter: FilterEngine::new(),
+            skipped: HashMap::new(),
+            checksums: true,
+            manifest: None,
+            chown,
+            owners: OwnerMap::default(),
+            staging,
+            replay: None,
+            backup: None,
+            transfer_log: None,
+            compression: false,
         }
     }
 
+    /// Write through `storage` instead of to the local filesystem
+    ///
+    /// `scan_dest` reads `root` unless the backend lists itself (see
+    /// `Storage::list`); otherwise a backend that can't offer the previous
+    /// versions of files should be paired with an empty root.
+    pub fn with_storage(mut self, storage: impl Storage + 'static) -> Self {
+        self.storage = Box::new(storage);
+        self
+    }
+
+    /// Record deletes, overwrites and permission changes in `audit`
+    pub fn with_audit(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Map the owners and groups entries carry through `owners` before
+    /// applying them
+    pub fn with_owner_map(mut self, owners: OwnerMap) -> Self {
+        self.owners = owners;
+        self
+    }
+
+    /// Refuse entries `policy` denies, whatever the sending side asks
+    pub fn with_policy(mut self, policy: ReceivePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Leave what `filter` excludes (with everything below it) out of
+    /// `scan_dest`: the sending side neither sends nor deletes those paths,
+    /// so listing them would only cost checksums and bandwidth
+    pub fn with_filter(mut self, filter: FilterEngine) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Accept zstd-compressed DATA, once the peer has echoed
+    /// HelloFlags::COMPRESSION; without it a COMPRESSED flag is an error
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Whether `scan_dest` sends block checksums for delta candidates;
+    /// without them it never reads file contents, and the sender can only
+    /// send whole files
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Keep a manifest of the tree in the root (see `dest_manifest`), and
+    /// when the peer already has the saved one (`known`), only send what
+    /// changed since
+    pub fn with_dest_manifest(mut self, known: Option<u128>) -> Self {
+        self.manifest = known;
+        self
+    }
+
+    /// Move the old version of each file aside, as `backup` says, before
+    /// overwriting or deleting it; `scan_dest` leaves the backups out
+    pub fn with_backup(mut self, backup: Option<Backup>) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    /// Record each file that lands or fails in `log`
+    pub fn with_transfer_log(mut self, log: Option<TransferLog>) -> Self {
+        self.transfer_log = log;
+        self
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.config.root.join(MANIFEST_FILE)
+    }
+
     /// Scan destination and yield DEST_FILE_ENTRY messages for Initial Exchange.
     /// Messages are batched to reduce syscalls.
+    ///
+    /// Checksums for delta candidates are computed on up to one worker per
+    /// core, in scan order. Whenever the next entry is still hashing, the
+    /// entries already finished are sent so the exchange keeps flowing.
+    ///
+    /// With a manifest, unchanged files reuse its checksums, and if the peer
+    /// has it, entries it already knows are left out and paths that are gone
+    /// are sent as REMOVED.
+    #[tracing::instrument(name = "scan_dest", skip_all, fields(root = %self.config.root.display()))]
     pub async fn scan_dest<F>(&self, mut on_entry: F) -> Result<(u64, u64)>
     where
         F: FnMut(Bytes) -> Result<()>,
@@ -154,66 +503,96 @@ impl Receiver {
         let mut total_files = 0u64;
         let mut total_bytes = 0u64;
 
-        let scanner = crate::sync::scanner::Scanner::new(&self.config.root);
-        // Use blocking scan in spawn_blocking
-        let entries = tokio::task::spawn_blocking(move || scanner.scan()).await??;
+        // A backend that isn't a local directory lists itself
+        let listed = self.storage.list().await?;
+        let local = listed.is_none();
+        let mut entries = match listed {
+            Some(entries) => entries,
+            None => {
+                let scanner = crate::sync::scanner::Scanner::new(&self.config.root);
+                // Use blocking scan in spawn_blocking
+                let scanned = tokio::task::spawn_blocking(move || scanner.scan()).await??;
+                scanned
+                    .into_iter()
+                    .map(|entry| Listed { entry, mode: None })
+                    .collect()
+            }
+        };
+        entries.retain(|listed| {
+            !self
+                .filter
+                .excludes_tree(&listed.entry.relative_path, listed.entry.is_dir)
+        });
 
         // Batch buffer for reducing syscalls
         let mut batch = BytesMut::with_capacity(DEST_ENTRY_BATCH_SIZE);
 
-        for entry in entries {
-            let rel_path = entry.relative_path.as_ref();
-            let path_str = rel_path.to_string_lossy().to_string();
-
-            // Skip root
-            if path_str.is_empty() {
-                continue;
-            }
-
-            let mut flags = DestFileFlags::empty();
-            if entry.is_dir {
-                flags |= DestFileFlags::DIR;
+        let prev = self
+            .manifest
+            .map(|_| DestManifest::load(&self.manifest_path()).unwrap_or_default());
+        let base = match (self.manifest, &prev) {
+            (Some(known), Some(prev)) if known != 0 && known == prev.id() => known,
+            _ => 0,
+        };
+        let mut next = DestManifest::default();
+
+        let mut dest_entries = stream::iter(entries)
+            .map(|listed| self.dest_entry(listed, local, prev.as_ref()))
+            .buffered(checksum_workers());
+
+        loop {
+            let entry = match dest_entries.next().now_or_never() {
+                Some(entry) => entry,
+                None => {
+                    // Next entry is still hashing: send what's ready meanwhile
+                    if !batch.is_empty() {
+                        on_entry(batch.split().freeze())?;
+                    }
+                    dest_entries.next().await
+                }
+            };
+            let Some(dest_entry) = entry else {
+                break;
+            };
+            if self.config.cancel.is_cancelled() {
+                return Err(Cancelled.into());
             }
-
-            // Compute checksums for delta candidates
-            let (block_size, checksums) = if !entry.is_dir && entry.size >= DELTA_MIN_SIZE {
-                flags |= DestFileFlags::HAS_CHECKSUMS;
-                let cs = self.compute_checksums(&entry.path).await?;
-                (self.config.block_size, cs)
-            } else {
-                (0, vec![])
+            let Some(dest_entry) = dest_entry? else {
+                continue;
             };
 
-            let mtime = entry
-                .modified
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs() as i64;
-
-            // TODO: Scanner should provide mode. For now use 0.
-            let mode = if entry.is_dir { 0o755 } else { 0o644 };
-
-            let dest_entry = DestFileEntry {
-                path: path_str,
-                size: entry.size,
-                mtime,
-                mode,
-                flags,
-                block_size,
-                checksums,
-            };
+            total_files += 1;
+            total_bytes += dest_entry.size;
 
-            // Add to batch
-            let encoded = dest_entry.encode();
-            batch.extend_from_slice(&encoded);
+            // Add to batch, unless the peer's manifest already has it
+            let known = base != 0 && prev.as_ref().is_some_and(|m| m.contains(&dest_entry));
+            if !known {
+                dest_entry.encode_into(&mut batch);
+            }
+            if self.manifest.is_some() {
+                next.insert(dest_entry);
+            }
 
             // Flush batch when threshold reached
             if batch.len() >= DEST_ENTRY_BATCH_SIZE {
                 on_entry(batch.split().freeze())?;
             }
+        }
+        drop(dest_entries);
 
-            total_files += 1;
-            total_bytes += entry.size;
+        let mut manifest = 0;
+        if let Some(prev) = prev {
+            if base != 0 {
+                for removed in prev.removed_in(&next) {
+                    removed.encode_into(&mut batch);
+                }
+            }
+            next.seal();
+            // Without it the next session just starts over
+            match next.save(&self.manifest_path()) {
+                Ok(()) => manifest = next.id(),
+                Err(e) => tracing::warn!("Failed to save destination manifest: {}", e),
+            }
         }
 
         // Flush remaining entries
@@ -225,21 +604,171 @@ impl Receiver {
         let end = DestFileEnd {
             total_files,
             total_bytes,
+            manifest,
+            base,
         };
         on_entry(end.encode())?;
 
         Ok((total_files, total_bytes))
     }
 
+    /// Build the DEST_FILE_ENTRY for a scanned entry (None for the root itself
+    /// and the manifest); `prev` supplies the checksums of unchanged files.
+    /// Entries the storage listed (not `local`) have no file to read, only
+    /// the mode the listing carried
+    async fn dest_entry(
+        &self,
+        listed: Listed,
+        local: bool,
+        prev: Option<&DestManifest>,
+    ) -> Result<Option<DestFileEntry>> {
+        let Listed {
+            entry,
+            mode: listed_mode,
+        } = listed;
+        let rel_path = entry.relative_path.as_ref();
+        let path_str = rel_path.to_string_lossy().to_string();
+
+        // Skip root
+        if path_str.is_empty()
+            || rel_path == Path::new(MANIFEST_FILE)
+            || self
+                .staging
+                .as_ref()
+                .is_some_and(|staging| rel_path.starts_with(&staging.name))
+            || self.backup.as_ref().is_some_and(|b| b.is_backup(rel_path))
+        {
+            return Ok(None);
+        }
+
+        let since_epoch = entry
+            .modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let (mtime, mtime_nsec) = (since_epoch.as_secs() as i64, since_epoch.subsec_nanos());
+
+        // Real modes only with -p, for the peer to compare against
+        let default_mode = if entry.is_dir { 0o755 } else { 0o644 };
+        let mode = match (self.config.io.perms, local) {
+            (true, true) => std::fs::symlink_metadata(entry.path.as_ref())
+                .map_or(default_mode, |meta| crate::fs_util::permission_bits(&meta)),
+            (true, false) => listed_mode.unwrap_or(default_mode),
+            (false, _) => default_mode,
+        };
+
+        // A temp file kept by an interrupted transfer, for the peer to resume
+        let checksums = self.checksums && local;
+        if self.config.io.resume
+            && checksums
+            && !entry.is_dir
+            && !entry.is_symlink
+            && entry.size >= self.config.io.block_size(entry.size) as u64
+            && is_temp_path(rel_path)
+        {
+            let block_size = self.config.io.block_size(entry.size);
+            let mut flags = DestFileFlags::PARTIAL | self.checksum_flags();
+            let checksums = self
+                .compute_checksums(&entry.path, entry.size, block_size)
+                .await?;
+            if self.config.compress_checksums && checksums.len() >= CHECKSUM_COMPRESS_MIN {
+                flags |= DestFileFlags::CHECKSUMS_ZSTD;
+            }
+            return Ok(Some(DestFileEntry {
+                path: path_str,
+                size: entry.size,
+                mtime,
+                mtime_nsec,
+                mode,
+                flags,
+                block_size,
+                checksums,
+            }));
+        }
+
+        if let Some(saved) =
+            prev.and_then(|m| m.unchanged(&path_str, entry.size, (mtime, mtime_nsec), entry.is_dir))
+        {
+            let hashed = saved.flags.contains(DestFileFlags::HAS_CHECKSUMS);
+            // Checksums are reused only if they'd be computed the same way now
+            let same_hash = saved.flags
+                & (DestFileFlags::HAS_CHECKSUMS | DestFileFlags::STRONG_128)
+                == self.checksum_flags();
+            if hashed == (checksums && !entry.is_dir && entry.size >= DELTA_MIN_SIZE)
+                && (!hashed
+                    || saved.block_size == self.config.io.block_size(entry.size) && same_hash)
+            {
+                let mut saved = saved.clone();
+                // A chmod leaves the mtime alone, so the saved mode may be stale
+                saved.mode = mode;
+                saved.flags.set(
+                    DestFileFlags::CHECKSUMS_ZSTD,
+                    hashed
+                        && self.config.compress_checksums
+                        && saved.checksums.len() >= CHECKSUM_COMPRESS_MIN,
+                );
+                return Ok(Some(saved));
+            }
+        }
+
+        let mut flags = DestFileFlags::empty();
+        if entry.is_dir {
+            flags |= DestFileFlags::DIR;
+        }
+
+        // Compute checksums for delta candidates
+        let (block_size, checksums) = if checksums && !entry.is_dir && entry.size >= DELTA_MIN_SIZE
+        {
+            flags |= self.checksum_flags();
+            let block_size = self.config.io.block_size(entry.size);
+            let cs = self
+                .compute_checksums(&entry.path, entry.size, block_size)
+                .await?;
+            if self.config.compress_checksums && cs.len() >= CHECKSUM_COMPRESS_MIN {
+                flags |= DestFileFlags::CHECKSUMS_ZSTD;
+            }
+            (block_size, cs)
+        } else {
+            (0, vec![])
+        };
+
+        Ok(Some(DestFileEntry {
+            path: path_str,
+            size: entry.size,
+            mtime,
+            mtime_nsec,
+            mode,
+            flags,
+            block_size,
+            checksums,
+        }))
+    }
+
+    /// Flags of a DEST_FILE_ENTRY with block checksums
+    fn checksum_flags(&self) -> DestFileFlags {
+        match self.config.io.strong_hash {
+            StrongHash::Xxh3_64 => DestFileFlags::HAS_CHECKSUMS,
+            StrongHash::Xxh3_128 => DestFileFlags::HAS_CHECKSUMS | DestFileFlags::STRONG_128,
+        }
+    }
+
     async fn compute_checksums(
         &self,
         path: &Path,
+        size: u64,
+        block_size: u32,
     ) -> Result<Vec<crate::streaming::protocol::BlockChecksum>> {
         let p = path.to_path_buf();
-        let bs = self.config.block_size as usize;
-        let checksums =
-            tokio::task::spawn_blocking(move || crate::delta::checksum::compute_checksums(&p, bs))
-                .await??;
+        let bs = block_size as usize;
+        let strong = self.config.io.strong_hash;
+        let mapped = self.config.io.mmap(size);
+        let checksums = tokio::task::spawn_blocking(move || {
+            if mapped {
+                crate::delta::checksum::compute_checksums_mapped(&p, bs, strong)
+            } else {
+                crate::delta::checksum::compute_checksums_with(&p, bs, strong)
+            }
+        })
+        .await??;
 
         Ok(checksums
             .into_iter()
@@ -252,38 +781,93 @@ impl Receiver {
     }
 
     /// Process an incoming message.
+    ///
+    /// A file that can't be written (or that the sender couldn't read) is
+    /// recorded in `stats` with its path and error code and the transfer
+    /// goes on; with `--stop-on-error` the first one fails the sync
+    /// instead. Protocol violations and unsafe paths always fail it.
     pub async fn handle_message(&mut self, msg_type: MessageType, payload: Bytes) -> Result<()> {
+        let failed = self.stats.errors.len();
+        self.dispatch(msg_type, payload).await?;
+        self.stop_on_error(failed)
+    }
+
+    /// Fail with the first error recorded past `failed` (`--stop-on-error`)
+    fn stop_on_error(&self, failed: usize) -> Result<()> {
+        match self.stats.errors.get(failed) {
+            Some(error) if self.config.io.stop_on_error => anyhow::bail!(
+                "Stopping at the first error (--stop-on-error): {}: {}",
+                error.path,
+                error.message
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    async fn dispatch(&mut self, msg_type: MessageType, payload: Bytes) -> Result<()> {
+        if self.config.cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        if self.config.io.dry_run {
+            return self.plan(msg_type, payload);
+        }
         match msg_type {
             MessageType::FileEntry => {
-                let entry = FileEntry::decode(payload)?;
+                let entry = FileEntry::decode_interned(payload.clone(), &mut self.paths)?;
+                if entry.is_hardlink() {
+                    // Complete in itself: no DATA or DATA_END follows
+                    self.digest.add_frame(msg_type, &payload);
+                    match &mut self.staging {
+                        Some(staging) => staging.steps.push(Staged::Frame(msg_type, payload)),
+                        None => self.handle_hard_link(entry).await?,
+                    }
+                    return Ok(());
+                }
+                let path = entry.path.clone();
                 self.handle_file_entry(entry).await?;
+                self.update_file_digest(&path, msg_type, &payload);
             }
             MessageType::Data => {
-                let data = Data::decode(payload)?;
+                let data = Data::decode_interned(payload.clone(), &mut self.paths)?;
+                self.update_file_digest(&data.path, msg_type, &payload);
                 self.handle_data(data).await?;
             }
             MessageType::DataEnd => {
-                let end = DataEnd::decode(payload)?;
+                let end = DataEnd::decode_interned(payload.clone(), &mut self.paths)?;
+                self.update_file_digest(&end.path, msg_type, &payload);
+                if let Some(pending) = self.pending_files.get(&*end.path) {
+                    self.digest.add_file(&pending.digest);
+                } else if let Some(digest) = self.skipped.get(&*end.path) {
+                    self.digest.add_file(digest);
+                }
                 self.handle_data_end(end).await?;
             }
-            MessageType::Mkdir => {
-                let mkdir = Mkdir::decode(payload)?;
-                self.handle_mkdir(mkdir).await?;
-            }
-            MessageType::Symlink => {
-                let symlink = Symlink::decode(payload)?;
-                self.handle_symlink(symlink).await?;
-            }
-            MessageType::Delete => {
-                let delete = Delete::decode(payload)?;
-                self.handle_delete(delete).await?;
+            MessageType::Mkdir
+            | MessageType::Symlink
+            | MessageType::Delete
+            | MessageType::SetAttr
+            | MessageType::Xattr
+            | MessageType::Acl => {
+                self.digest.add_frame(msg_type, &payload);
+                match &mut self.staging {
+                    Some(staging) => staging.steps.push(Staged::Frame(msg_type, payload)),
+                    None => self.apply(msg_type, payload).await?,
+                }
             }
             MessageType::FileEnd => {
-                let _end = FileEnd::decode(payload)?;
+                let end = FileEnd::decode(payload)?;
+                self.stats.counts.skipped = end.skipped;
             }
             MessageType::DeleteEnd => {
                 let _end = DeleteEnd::decode(payload)?;
             }
+            MessageType::Error => {
+                // The sender couldn't read a file; its failed DATA_END follows
+                let error = Error::decode(payload.clone())?;
+                self.update_file_digest(&error.path, msg_type, &payload);
+                tracing::warn!("Failed to send {}: {}", error.path, error.message);
+                self.stats.errors.push(error);
+            }
             _ => {
                 // Ignore unknown messages
             }
@@ -291,119 +875,344 @@ impl Receiver {
         Ok(())
     }
 
-    async fn handle_file_entry(&mut self, entry: FileEntry) -> Result<()> {
-        let full_path = validate_path(&self.config.root, &entry.path)?;
+    /// Apply a change that comes in a single frame
+    async fn apply(&mut self, msg_type: MessageType, payload: Bytes) -> Result<()> {
+        match msg_type {
+            MessageType::FileEntry => self.handle_hard_link(FileEntry::decode(payload)?).await,
+            MessageType::Mkdir => self.handle_mkdir(Mkdir::decode(payload)?).await,
+            MessageType::Symlink => self.handle_symlink(Symlink::decode(payload)?).await,
+            MessageType::Delete => self.handle_delete(Delete::decode(payload)?).await,
+            MessageType::SetAttr => self.handle_set_attr(SetAttr::decode(payload)?).await,
+            MessageType::Xattr => self.handle_xattr(Xattr::decode(payload)?).await,
+            MessageType::Acl => self.handle_acl(Acl::decode(payload)?).await,
+            _ => Ok(()),
+        }
+    }
 
-        // Ensure parent directory exists
-        if let Some(parent) = full_path.parent() {
-            fs::create_dir_all(parent).await?;
+    /// Tally what a message would change, leaving the destination alone
+    /// (`--dry-run`)
+    ///
+    /// Every file comes as a bare FILE_ENTRY, so each message is a change
+    /// on its own. Paths are checked as for a real sync: a peer that sends
+    /// something we'd refuse fails the dry run too.
+    fn plan(&mut self, msg_type: MessageType, payload: Bytes) -> Result<()> {
+        match msg_type {
+            MessageType::FileEntry => {
+                let entry = FileEntry::decode(payload.clone())?;
+                self.validate_path(&entry.path)?;
+                self.check_parents(&entry.path)?;
+                if let Some(target) = entry.link_target.as_deref() {
+                    self.validate_path(target)?;
+                }
+                if let Some(reason) = self.policy.refuse_file(&entry.path, entry.mode) {
+                    self.refuse(&entry.path, reason);
+                    self.stats.files_err += 1;
+                } else if entry.is_hardlink() {
+                    self.stats.hardlinks_created += 1;
+                } else if self.current_mode(&entry.path).is_some() {
+                    self.stats.files_ok += 1;
+                    self.stats.counts.updated += 1;
+                    self.stats.would.change += entry.size;
+                } else {
+                    self.stats.files_ok += 1;
+                    self.stats.counts.created += 1;
+                    self.stats.would.add += entry.size;
+                }
+            }
+            MessageType::Mkdir => {
+                let mkdir = Mkdir::decode(payload.clone())?;
+                self.validate_path(&mkdir.path)?;
+                self.stats.dirs_created += 1;
+            }
+            MessageType::Symlink => {
+                let symlink = Symlink::decode(payload.clone())?;
+                let full_path = self.validate_path(&symlink.path)?;
+                validate_symlink_target(&self.config.root, &full_path, &symlink.target)?;
+                if let Some(reason) = self.policy.refuse_path(&symlink.path) {
+                    self.refuse(&symlink.path, reason);
+                    self.stats.files_err += 1;
+                } else {
+                    self.stats.symlinks_created += 1;
+                }
+            }
+            MessageType::SetAttr => {
+                let attr = SetAttr::decode(payload.clone())?;
+                self.validate_path(&attr.path)?;
+                self.stats.files_ok += 1;
+                self.stats.counts.touched += 1;
+            }
+            MessageType::Delete => {
+                let delete = Delete::decode(payload.clone())?;
+                self.validate_path(&delete.path)?;
+                let local = self.config.root.join(self.local_path(&delete.path));
+                if let Ok(meta) = std::fs::symlink_metadata(&local) {
+                    if meta.is_file() {
+                        self.stats.would.delete += meta.len();
+                    }
+                }
+                self.stats.deleted += 1;
+            }
+            MessageType::Xattr | MessageType::Acl => {}
+            MessageType::FileEnd => {
+                let end = FileEnd::decode(payload)?;
+                self.stats.counts.skipped = end.skipped;
+                return Ok(());
+            }
+            MessageType::Error => {
+                let error = Error::decode(payload)?;
+                tracing::warn!("Failed to send {}: {}", error.path, error.message);
+                self.stats.errors.push(error);
+                return Ok(());
+            }
+            _ => return Ok(()),
         }
+        self.digest.add_frame(msg_type, &payload);
+        Ok(())
+    }
 
-        // Create temp file
-        let temp_path = full_path.with_extension("sy.tmp");
-        let guard = TempFileGuard::new(&temp_path);
+    fn update_file_digest(&mut self, path: &str, msg_type: MessageType, payload: &[u8]) {
+        if let Some(pending) = self.pending_files.get_mut(path) {
+            pending.digest.update(msg_type, payload);
+        } else if let Some(digest) = self.skipped.get_mut(path) {
+            digest.update(msg_type, payload);
+        }
+    }
 
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&temp_path)
-            .await?;
+    async fn handle_file_entry(&mut self, entry: FileEntry) -> Result<()> {
+        self.validate_path(&entry.path)?;
+        self.check_parents(&entry.path)?;
+        if let Some(reason) = self.policy.refuse_file(&entry.path, entry.mode) {
+            tracing::warn!("Refusing {}: {}", entry.path, reason);
+            self.refuse(&entry.path, reason);
+            self.skipped.insert(entry.path, FileDigest::new());
+            return Ok(());
+        }
+        let existed = self.current_mode(&entry.path).is_some();
+        let replaces = self.existing_mode(&entry.path);
+        // A resumed file already holds everything before its first DATA
+        let resumed = entry.flags.contains(FileFlags::RESUME);
+        let opened = if resumed {
+            self.storage.resume(&entry).await
+        } else if let Some(staging) = &self.staging {
+            let staged = staging.path_of(&entry.path);
+            self.storage
+                .create_staged(&entry, &staged)
+                .await
+                .map(|file| (file, 0))
+        } else {
+            self.storage.create(&entry).await.map(|file| (file, 0))
+        };
+        let (file, end) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.file_error(&entry.path, e);
+                self.skipped.insert(entry.path, FileDigest::new());
+                return Ok(());
+            }
+        };
+        let span = tracing::info_span!("receive_file", path = %entry.path, size = entry.size);
 
         self.pending_files.insert(
             entry.path.clone(),
             PendingFile {
                 entry,
-                temp_path,
-                file: Some(file),
-                original_file: None, // Lazily opened on first delta chunk
+                file,
                 bytes_written: 0,
-                guard: Some(guard),
+                end,
+                digest: FileDigest::new(),
+                replaces,
+                existed,
+                rewritten: resumed,
+                delta: false,
+                started: Instant::now(),
+                _span: span,
             },
         );
 
         Ok(())
     }
 
-    async fn handle_data(&mut self, data: Data) -> Result<()> {
-        let root = self.config.root.clone();
+    async fn handle_data(&mut self, mut data: Data) -> Result<()> {
+        if self.skipped.contains_key(&*data.path) {
+            return Ok(());
+        }
+        if data.flags.contains(DataFlags::COMPRESSED) {
+            if !self.compression {
+                return Err(SyncError::Protocol(format!(
+                    "Compressed DATA for {} without negotiated compression",
+                    data.path
+                ))
+                .into());
+            }
+            // No sender produces more than a frame's worth per DATA
+            let plain =
+                zstd::bulk::decompress(&data.data, MAX_FRAME_SIZE as usize).map_err(|e| {
+                    SyncError::Protocol(format!("Corrupt compressed DATA for {}: {}", data.path, e))
+                })?;
+            data.data = Bytes::from(plain);
+        }
         let pending = self
             .pending_files
-            .get_mut(&data.path)
+            .get_mut(&*data.path)
             .ok_or_else(|| anyhow::anyhow!("No pending file for {}", data.path))?;
 
-        if let Some(ref mut file) = pending.file {
-            if data.flags.contains(DataFlags::DELTA) {
-                // Lazily open original file on first delta chunk, reuse for subsequent chunks
-                if pending.original_file.is_none() {
-                    let original_path = validate_path(&root, &data.path)?;
-                    pending.original_file = Some(
-                        File::open(&original_path)
-                            .await
-                            .context("Failed to open original file for delta application")?,
-                    );
+        let len = data.data.len() as u64;
+        let written = if data.flags.contains(DataFlags::DELTA) {
+            apply_delta(pending.file.as_mut(), pending.end, data.data)
+                .await
+                .map(|(n, in_place)| {
+                    pending.end += n;
+                    pending.rewritten |= !in_place;
+                    pending.delta = true;
+                })
+        } else {
+            // Write raw data at offset; the offset comes from the peer
+            match data.offset.checked_add(len) {
+                Some(end) => {
+                    pending.end = pending.end.max(end);
+                    pending.rewritten = true;
+                    pending.file.write_at(data.offset, data.data).await
                 }
-
-                // Apply delta using cached original file
-                let original = pending
-                    .original_file
-                    .as_mut()
-                    .expect("original_file must be set before applying delta");
-                Self::apply_delta_with_original(file, original, &data.data).await?;
-            } else {
-                // Write raw data at offset
-                file.seek(SeekFrom::Start(data.offset)).await?;
-                file.write_all(&data.data).await?;
+                None => Err(anyhow::anyhow!("DATA offset {} out of range", data.offset)),
+            }
+        };
+        if let Err(e) = written {
+            // Dropping the file discards what was written; the rest of its
+            // DATA is ignored
+            if let Some(pending) = self.pending_files.remove(&*data.path) {
+                self.skipped.insert(Arc::clone(&data.path), pending.digest);
             }
-            pending.bytes_written += data.data.len() as u64;
+            self.file_error(&data.path, e);
+            return Ok(());
         }
+        pending.bytes_written += len;
 
         Ok(())
     }
 
     async fn handle_data_end(&mut self, end: DataEnd) -> Result<()> {
-        if let Some(mut pending) = self.pending_files.remove(&end.path) {
-            if let Some(mut file) = pending.file.take() {
-                file.flush().await?;
-                file.sync_all().await?;
-            }
-
-            // Path was already validated in handle_file_entry
-            let full_path = validate_path(&self.config.root, &end.path)?;
+        let Some(log) = self.transfer_log.clone() else {
+            return self.end_file(end).await;
+        };
+        let path = Arc::clone(&end.path);
+        // Refused or failed to open: nothing was written
+        let (size, bytes, delta, started) = match self.pending_files.get(&*path) {
+            Some(p) => (p.entry.size, p.bytes_written, p.delta, Some(p.started)),
+            None => (0, 0, false, None),
+        };
+        let failed = self.stats.files_err;
+        self.end_file(end).await?;
+
+        let error = (self.stats.files_err > failed).then(|| {
+            self.stats
+                .errors
+                .iter()
+                .rev()
+                .find(|e| e.path == *path)
+                .map_or("Failed to write", |e| e.message.as_str())
+        });
+        log.record(&Transfer {
+            path: &path,
+            size,
+            bytes,
+            delta,
+            duration: started.map(|at| at.elapsed()).unwrap_or_default(),
+            error,
+        });
+        Ok(())
+    }
 
+    /// Commit or discard the file a DATA_END finishes
+    async fn end_file(&mut self, end: DataEnd) -> Result<()> {
+        self.paths.release(&end.path);
+        if self.skipped.remove(&*end.path).is_some() {
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        if let Some(pending) = self.pending_files.remove(&*end.path) {
             if end.status == DataEnd::STATUS_OK {
-                // Move temp file to final destination
-                fs::rename(&pending.temp_path, &full_path).await?;
-
-                // Defuse guard after successful rename
-                if let Some(guard) = pending.guard.take() {
-                    guard.defuse();
+                let mut pending = pending;
+                let check = self.config.io.checksum_verify
+                    || pending.delta && self.config.io.strong_hash == StrongHash::Xxh3_128;
+                if let Some(expected) = end.hash.filter(|_| check) {
+                    if !self.verify(&end.path, &mut pending, expected).await {
+                        // Dropping the file discards what was written
+                        self.stats.files_err += 1;
+                        return Ok(());
+                    }
                 }
-
-                // Set permissions
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    let perms = std::fs::Permissions::from_mode(pending.entry.mode);
-                    if let Err(e) = fs::set_permissions(&full_path, perms).await {
-                        tracing::warn!(
-                            "Failed to set permissions on {}: {}",
-                            full_path.display(),
-                            e
-                        );
+                let touched = pending.existed
+                    && !pending.rewritten
+                    && pending.file.original_len().await.ok() == Some(pending.end);
+                // A staged file is backed up when it's moved into place
+                if pending.existed && !touched && self.staging.is_none() {
+                    if let Err(e) = self.back_up(&end.path).await {
+                        self.file_error(&end.path, e);
+                        self.stats.files_err += 1;
+                        return Ok(());
                     }
                 }
-
-                // Set mtime
-                let mtime = filetime::FileTime::from_unix_time(pending.entry.mtime, 0);
-                let _ = tokio::task::spawn_blocking(move || {
-                    filetime::set_file_mtime(&full_path, mtime)
-                })
-                .await?;
+                let entry = &pending.entry;
+                let at: Cow<str> = match &self.staging {
+                    Some(staging) => Cow::Owned(staging.path_of(&end.path)),
+                    None => Cow::Borrowed(&end.path),
+                };
+                let committed = async {
+                    pending.file.commit(pending.end).await?;
+                    // Before the mode: chown clears setuid and setgid bits
+                    self.apply_owner(&at, entry.owner).await?;
+                    self.storage
+                        .set_metadata(
+                            &at,
+                            entry.mode,
+                            FileTime::from_unix_time(entry.mtime, entry.mtime_nsec),
+                        )
+                        .await?;
+                    if let Some(times) = entry.times {
+                        self.storage.set_times(&at, times).await?;
+                    }
+                    if let Some(attrs) = entry.win_attrs {
+                        self.storage.set_attributes(&at, attrs).await?;
+                    }
+                    if let Some(flags) = entry.bsd_flags {
+                        self.storage.set_file_flags(&at, flags).await?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                }
+                .await;
+                if let Err(e) = committed {
+                    self.file_error(&end.path, e);
+                    self.stats.files_err += 1;
+                    return Ok(());
+                }
+                if let Some(staging) = &mut self.staging {
+                    staging.steps.push(Staged::File(Arc::clone(&end.path)));
+                }
+                if let Some(previous) = pending.replaces {
+                    self.audit(AuditOp::Overwrite, &end.path)?;
+                    self.audit_chmod(&end.path, previous, pending.entry.mode)?;
+                }
 
                 self.stats.files_ok += 1;
+                if touched {
+                    self.stats.counts.touched += 1;
+                } else if pending.existed {
+                    self.stats.counts.updated += 1;
+                } else {
+                    self.stats.counts.created += 1;
+                }
                 self.stats.bytes_transferred += pending.bytes_written;
+
+                self.unsynced_files += 1;
+                self.unsynced_bytes += pending.bytes_written;
+                if self.fsync_policy() == FsyncPolicy::Batch
+                    && (self.unsynced_files >= BATCH_SYNC_FILES
+                        || self.unsynced_bytes >= BATCH_SYNC_BYTES)
+                {
+                    self.sync_batch().await?;
+                }
             } else {
+                // Dropping the file discards what was written
                 self.stats.files_err += 1;
             }
         }
@@ -411,157 +1220,822 @@ impl Receiver {
         Ok(())
     }
 
-    async fn handle_mkdir(&mut self, mkdir: Mkdir) -> Result<()> {
-        let full_path = validate_path(&self.config.root, &mkdir.path)?;
-        fs::create_dir_all(&full_path).await?;
-
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let perms = std::fs::Permissions::from_mode(mkdir.mode);
-            if let Err(e) = fs::set_permissions(&full_path, perms).await {
+    /// Whether what was written for `path` hashes to the sender's `expected`
+    /// (`--checksum-verify`, or a delta checked against block collisions);
+    /// a mismatch is recorded as ChecksumMismatch
+    async fn verify(&mut self, path: &str, pending: &mut PendingFile, expected: u128) -> bool {
+        match pending.file.content_hash(pending.end).await {
+            Ok(actual) if actual == expected => true,
+            Ok(actual) => {
                 tracing::warn!(
-                    "Failed to set permissions on {}: {}",
-                    full_path.display(),
-                    e
+                    "Checksum mismatch for {}: expected {:032x}, wrote {:032x}",
+                    path,
+                    expected,
+                    actual
                 );
+                let message = if pending.delta {
+                    "Checksum mismatch: the file changed while being sent, or its delta \
+                     matched the wrong blocks"
+                } else {
+                    "Checksum mismatch: the file changed in transit or while being sent"
+                };
+                self.stats.checksum_mismatches += 1;
+                self.stats.errors.push(Error {
+                    path: path.to_string(),
+                    code: ErrorCode::ChecksumMismatch as u16,
+                    message: message.to_string(),
+                });
+                false
+            }
+            Err(e) => {
+                self.file_error(path, e.context("Failed to read back for verification"));
+                false
             }
         }
-
-        self.stats.dirs_created += 1;
-        Ok(())
     }
 
-    async fn handle_symlink(&mut self, symlink: Symlink) -> Result<()> {
-        let full_path = validate_path(&self.config.root, &symlink.path)?;
-
-        // Validate symlink target
-        validate_symlink_target(&self.config.root, &full_path, &symlink.target)?;
+    /// Copy a whole local file into place without going through DATA frames
+    ///
+    /// For in-process transfers: the file lands via the same commit,
+    /// permissions and mtime handling as a streamed one, with the data
+    /// copied between descriptors in the kernel. Nothing is added to the
+    /// transfer digest, which only covers changes that arrive as frames.
+    pub async fn copy_local(&mut self, source: &Path, entry: FileEntry) -> Result<()> {
+        let failed = self.stats.errors.len();
+        self.copy_file(source, entry).await?;
+        self.stop_on_error(failed)
+    }
 
-        // Remove existing if any
-        let _ = fs::remove_file(&full_path).await;
+    async fn copy_file(&mut self, source: &Path, entry: FileEntry) -> Result<()> {
+        let path = entry.path.clone();
+        let size = entry.size;
+        self.handle_file_entry(entry).await?;
+        let Some(pending) = self.pending_files.get_mut(&*path) else {
+            // Refused by the policy
+            return self
+                .handle_data_end(DataEnd {
+                    path,
+                    status: DataEnd::STATUS_OK,
+                    hash: None,
+                })
+                .await;
+        };
 
-        #[cfg(unix)]
-        tokio::fs::symlink(&symlink.target, &full_path).await?;
+        let copied = match std::fs::File::open(source) {
+            Ok(src) => pending.file.copy_from(src, size).await,
+            Err(e) => Err(e.into()),
+        };
+        let status = match copied {
+            Ok(copied) => {
+                pending.bytes_written = copied;
+                pending.end = copied;
+                pending.rewritten = true;
+                DataEnd::STATUS_OK
+            }
+            Err(e) => {
+                self.file_error(
+                    &path,
+                    e.context(format!("Failed to copy {}", source.display())),
+                );
+                DataEnd::STATUS_ERROR
+            }
+        };
 
-        #[cfg(windows)]
-        tokio::task::spawn_blocking({
-            let target = symlink.target.clone();
-            let path = full_path.clone();
-            move || std::os::windows::fs::symlink_file(&target, &path)
+        self.handle_data_end(DataEnd {
+            path,
+            status,
+            hash: None,
         })
-        .await??;
+        .await
+    }
 
-        self.stats.symlinks_created += 1;
+    async fn handle_mkdir(&mut self, mkdir: Mkdir) -> Result<()> {
+        self.validate_path(&mkdir.path)?;
+        // The directory itself too: mkdir on a symlink would chmod its target
+        let dir = self.local_path(&mkdir.path);
+        check_no_symlinks(&self.config.root, &dir, &mut self.real_dirs)?;
+        let previous = self.existing_mode(&mkdir.path);
+        if let Err(e) = self.storage.mkdir(&mkdir.path, mkdir.mode).await {
+            self.file_error(&mkdir.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        if let Err(e) = self.apply_owner(&mkdir.path, mkdir.owner).await {
+            self.file_error(&mkdir.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        if let Some(previous) = previous {
+            self.audit_chmod(&mkdir.path, previous, mkdir.mode)?;
+        }
+        self.stats.dirs_created += 1;
         Ok(())
     }
 
-    async fn handle_delete(&mut self, delete: Delete) -> Result<()> {
-        let full_path = validate_path(&self.config.root, &delete.path)?;
+    async fn handle_symlink(&mut self, symlink: Symlink) -> Result<()> {
+        let full_path = self.validate_path(&symlink.path)?;
 
-        if delete.is_dir {
-            let _ = fs::remove_dir_all(&full_path).await;
-        } else {
-            let _ = fs::remove_file(&full_path).await;
-        }
+        // Validate symlink target
+        validate_symlink_target(&self.config.root, &full_path, &symlink.target)?;
+        self.check_parents(&symlink.path)?;
+        if let Some(reason) = self.policy.refuse_path(&symlink.path) {
+            tracing::warn!("Refusing symlink {}: {}", symlink.path, reason);
+            self.refuse(&symlink.path, reason);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+
+        let replaces = self.existing_mode(&symlink.path);
+        if let Err(e) = self.back_up(&symlink.path).await {
+            self.file_error(&symlink.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        self.real_dirs.clear();
+        if let Err(e) = self.storage.symlink(&symlink.path, &symlink.target).await {
+            self.file_error(&symlink.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        let link = self.config.root.join(self.local_path(&symlink.path));
+        if let Err(e) = check_symlink_resolves(&self.config.root, &link) {
+            let _ = self.storage.delete(&symlink.path, false).await;
+            return Err(e);
+        }
+        if let Err(e) = self.apply_owner(&symlink.path, symlink.owner).await {
+            self.file_error(&symlink.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        if replaces.is_some() {
+            self.audit(AuditOp::Overwrite, &symlink.path)?;
+        }
+        self.stats.symlinks_created += 1;
+        Ok(())
+    }
+
+    async fn handle_hard_link(&mut self, entry: FileEntry) -> Result<()> {
+        self.validate_path(&entry.path)?;
+        let Some(target) = entry.link_target.as_deref() else {
+            anyhow::bail!("Hard link without a target: {}", entry.path);
+        };
+        self.validate_path(target)?;
+        self.check_parents(&entry.path)?;
+        self.check_parents(target)?;
+        if let Some(reason) = self.policy.refuse_file(&entry.path, entry.mode) {
+            tracing::warn!("Refusing {}: {}", entry.path, reason);
+            self.refuse(&entry.path, reason);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        // Only ever to a regular file: one that failed to land isn't there
+        let local = self.config.root.join(self.local_path(target));
+        if !std::fs::symlink_metadata(&local).is_ok_and(|meta| meta.is_file()) {
+            let e = anyhow::anyhow!("Hard link target {} is not a regular file", target);
+            self.file_error(&entry.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+
+        let replaces = self.existing_mode(&entry.path);
+        if let Err(e) = self.back_up(&entry.path).await {
+            self.file_error(&entry.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        if let Err(e) = self.storage.hard_link(&entry.path, target).await {
+            self.file_error(&entry.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        if replaces.is_some() {
+            self.audit(AuditOp::Overwrite, &entry.path)?;
+        }
+        self.stats.hardlinks_created += 1;
+        Ok(())
+    }
+
+    /// Update the metadata of a file whose content is already right
+    async fn handle_set_attr(&mut self, attr: SetAttr) -> Result<()> {
+        self.validate_path(&attr.path)?;
+        self.check_parents(&attr.path)?;
+        // Only ever a regular file: chmod would follow a symlink
+        let (previous, previous_mtime) = match self.storage.stat(&attr.path).await {
+            Ok(Some(stat)) => stat,
+            Ok(None) => {
+                self.file_error(&attr.path, anyhow::anyhow!("Not a regular file"));
+                self.stats.files_err += 1;
+                return Ok(());
+            }
+            Err(e) => {
+                self.file_error(&attr.path, e);
+                self.stats.files_err += 1;
+                return Ok(());
+            }
+        };
+
+        let mode = if attr.flags.contains(SetAttrFlags::MODE) {
+            if let Some(reason) = self.policy.refuse_file(&attr.path, attr.mode) {
+                tracing::warn!("Refusing {}: {}", attr.path, reason);
+                self.refuse(&attr.path, reason);
+                self.stats.files_err += 1;
+                return Ok(());
+            }
+            attr.mode
+        } else {
+            previous
+        };
+        let mtime = if attr.flags.contains(SetAttrFlags::MTIME) {
+            FileTime::from_unix_time(attr.mtime, attr.mtime_nsec)
+        } else {
+            previous_mtime
+        };
+
+        if let Err(e) = self.storage.set_metadata(&attr.path, mode, mtime).await {
+            self.file_error(&attr.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        self.audit_chmod(&attr.path, previous, mode)?;
+        self.stats.files_ok += 1;
+        self.stats.counts.touched += 1;
+        Ok(())
+    }
+
+    /// Set the extended attributes of a file or directory sent just before
+    async fn handle_xattr(&mut self, xattr: Xattr) -> Result<()> {
+        self.validate_path(&xattr.path)?;
+        self.check_parents(&xattr.path)?;
+        if !self.config.io.xattrs || self.policy.refuse_path(&xattr.path).is_some() {
+            return Ok(());
+        }
+        // Nothing landed (its error is reported already), or it's a symlink
+        let local = self.config.root.join(self.local_path(&xattr.path));
+        match std::fs::symlink_metadata(&local) {
+            Ok(meta) if !meta.file_type().is_symlink() => {}
+            _ => return Ok(()),
+        }
+        if let Err(e) = self.storage.set_xattrs(&xattr.path, &xattr.entries).await {
+            self.file_error(&xattr.path, e);
+        }
+        Ok(())
+    }
+
+    async fn handle_acl(&mut self, acl: Acl) -> Result<()> {
+        self.validate_path(&acl.path)?;
+        self.check_parents(&acl.path)?;
+        if !self.config.io.acls || self.policy.refuse_path(&acl.path).is_some() {
+            return Ok(());
+        }
+        let local = self.config.root.join(self.local_path(&acl.path));
+        match std::fs::symlink_metadata(&local) {
+            Ok(meta) if !meta.file_type().is_symlink() => {}
+            _ => return Ok(()),
+        }
+        if let Err(e) = self.storage.set_acl(&acl.path, &acl.text).await {
+            self.file_error(&acl.path, e);
+        }
+        Ok(())
+    }
 
+    async fn handle_delete(&mut self, delete: Delete) -> Result<()> {
+        self.validate_path(&delete.path)?;
+        self.check_parents(&delete.path)?;
+        let existed = self.existing_mode(&delete.path).is_some();
+        if let Err(e) = self.back_up(&delete.path).await {
+            self.file_error(&delete.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        self.real_dirs.clear();
+        if let Err(e) = self.storage.delete(&delete.path, delete.is_dir).await {
+            self.file_error(&delete.path, e);
+            self.stats.files_err += 1;
+            return Ok(());
+        }
+        if existed {
+            self.audit(AuditOp::Delete, &delete.path)?;
+        }
         self.stats.deleted += 1;
         Ok(())
     }
 
-    /// Apply delta operations using a pre-opened original file (avoids reopening per chunk)
-    async fn apply_delta_with_original(
-        file: &mut File,
-        original: &mut File,
-        delta_data: &[u8],
-    ) -> Result<()> {
-        // Get file size for bounds checking
-        let file_size = original.metadata().await?.len();
+    /// Move what's at `path` aside before it's replaced or deleted
+    ///
+    /// With `--backup` that's a regular file, or every regular file below a
+    /// directory; symlinks and the rest aren't kept. During an `--atomic`
+    /// replay whatever is left goes into the staging directory, and each
+    /// move is recorded so it can be undone.
+    async fn back_up(&mut self, path: &str) -> Result<()> {
+        let local = self.config.root.join(self.local_path(path));
+        if let Some(backup) = self.backup.clone() {
+            let files = match std::fs::symlink_metadata(&local) {
+                Ok(meta) if meta.is_file() => vec![path.to_string()],
+                Ok(meta) if meta.is_dir() => files_below(&local, path)?,
+                _ => Vec::new(),
+            };
+            for file in files {
+                let to = backup.path_of(&file);
+                self.validate_path(&to)?;
+                self.check_parents(&to)?;
+                self.storage
+                    .rename(&file, &to)
+                    .await
+                    .with_context(|| format!("Failed to back up {} to {}", file, to))?;
+                self.stats.backed_up += 1;
+                if let Some(replay) = &mut self.replay {
+                    replay.undo.push(Undo::Restore {
+                        path: file,
+                        aside: to,
+                    });
+                }
+            }
+        }
+        let Some(replay) = &self.replay else {
+            return Ok(());
+        };
+        if std::fs::symlink_metadata(&local).is_err() {
+            return Ok(());
+        }
+        let aside = replay.aside();
+        self.storage
+            .rename(path, &aside)
+            .await
+            .with_context(|| format!("Failed to move {} aside", path))?;
+        if let Some(replay) = &mut self.replay {
+            replay.undo.push(Undo::Restore {
+                path: path.to_string(),
+                aside,
+            });
+        }
+        Ok(())
+    }
+
+    /// Give `path` the uid (`-o`) and gid (`-g`) of `owner`, mapped; a
+    /// no-op unless we run as root
+    async fn apply_owner(&self, path: &str, owner: Option<Ownership>) -> Result<()> {
+        let Some(owner) = owner.filter(|_| self.chown) else {
+            return Ok(());
+        };
+        let owner = self.owners.apply(owner);
+        let io = &self.config.io;
+        self.storage
+            .set_owner(
+                path,
+                io.owner.then_some(owner.uid),
+                io.group.then_some(owner.gid),
+            )
+            .await
+    }
 
-        // Reusable buffer for copy operations
-        let mut copy_buf = Vec::new();
+    /// [`validate_path`], and on Windows also refuse names the filesystem
+    /// would store as something else; `--fat` renames most of those instead
+    fn validate_path(&self, relative: &str) -> Result<PathBuf> {
+        let full = validate_path(&self.config.root, relative)?;
+        if cfg!(windows) {
+            if let Some(problem) = crate::fs_util::windows_name_problem(&self.local_path(relative))
+            {
+                anyhow::bail!("{}: {}", problem, relative);
+            }
+        }
+        Ok(full)
+    }
 
-        let mut reader = delta_data;
+    /// Where `path` lands under the root (storage renames it for `--fat`)
+    fn local_path<'a>(&self, path: &'a str) -> Cow<'a, Path> {
+        if self.config.io.fat {
+            crate::fs_util::fat_safe_path(Path::new(path))
+        } else {
+            Cow::Borrowed(Path::new(path))
+        }
+    }
 
-        while reader.has_remaining() {
-            let op_type = reader.get_u8();
-            match op_type {
-                0x00 => {
-                    // Copy from original file
-                    if reader.remaining() < 12 {
-                        anyhow::bail!("Delta copy op truncated");
-                    }
-                    let offset = reader.get_u64();
-                    let size = reader.get_u32() as usize;
-
-                    // Bounds validation
-                    if size > MAX_DELTA_COPY_SIZE {
-                        anyhow::bail!(
-                            "Delta copy size {} exceeds max {}",
-                            size,
-                            MAX_DELTA_COPY_SIZE
-                        );
-                    }
-                    if offset > file_size {
-                        anyhow::bail!(
-                            "Delta copy offset {} exceeds file size {}",
-                            offset,
-                            file_size
-                        );
-                    }
-                    if offset.saturating_add(size as u64) > file_size {
-                        anyhow::bail!(
-                            "Delta copy range {}..{} exceeds file size {}",
-                            offset,
-                            offset + size as u64,
-                            file_size
-                        );
-                    }
+    /// Mode of whatever is at `path` now, if the run is audited
+    fn existing_mode(&self, path: &str) -> Option<u32> {
+        self.audit.as_ref()?;
+        self.current_mode(path)
+    }
+
+    /// Mode of whatever is at `path` now
+    fn current_mode(&self, path: &str) -> Option<u32> {
+        let meta = std::fs::symlink_metadata(self.config.root.join(self.local_path(path))).ok()?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            Some(meta.permissions().mode())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = meta;
+            Some(0)
+        }
+    }
+
+    fn audit(&self, op: AuditOp, path: &str) -> Result<()> {
+        match &self.audit {
+            Some(audit) => audit.record(op, path),
+            None => Ok(()),
+        }
+    }
+
+    /// Record a permission change, if `mode` differs from `previous` and
+    /// was actually applied
+    fn audit_chmod(&self, path: &str, previous: u32, mode: u32) -> Result<()> {
+        let (from, to) = (previous & 0o7777, mode & 0o7777);
+        if cfg!(unix) && !self.config.io.fat && from != to {
+            self.audit(AuditOp::Chmod { from, to }, path)?;
+        }
+        Ok(())
+    }
+
+    /// Record that writing `path` failed; the transfer goes on without it
+    fn file_error(&mut self, path: &str, err: anyhow::Error) {
+        tracing::warn!("Failed to write {}: {:#}", path, err);
+        let message = format!("{:#}", err);
+        self.stats.errors.push(Error {
+            path: path.to_string(),
+            code: SyncError::from(err).error_code() as u16,
+            message,
+        });
+    }
+
+    /// Record that `policy` refused `path`
+    fn refuse(&mut self, path: &str, reason: String) {
+        self.stats.errors.push(Error {
+            path: path.to_string(),
+            code: ErrorCode::PermissionDenied as u16,
+            message: format!("Refused: {}", reason),
+        });
+    }
+
+    /// Refuse `path` if any directory above it is a symlink
+    fn check_parents(&mut self, path: &str) -> Result<()> {
+        let local = self.local_path(path);
+        match local.parent() {
+            Some(parent) => check_no_symlinks(&self.config.root, parent, &mut self.real_dirs),
+            None => Ok(()),
+        }
+    }
+
+    fn fsync_policy(&self) -> FsyncPolicy {
+        self.config.io.fsync_policy()
+    }
+
+    async fn sync_batch(&mut self) -> Result<()> {
+        self.storage
+            .sync()
+            .await
+            .context("Failed to sync destination filesystem")?;
+        self.unsynced_files = 0;
+        self.unsynced_bytes = 0;
+        Ok(())
+    }
+
+    /// Final durability barrier: call before reporting success to the peer
+    ///
+    /// With `--fsync batch` this syncs whatever was written since the last
+    /// periodic sync; the other policies have nothing left to do.
+    ///
+    /// An `--atomic` transfer is moved into place here, unless anything
+    /// failed: then none of it is and the error says so.
+    pub async fn finish(&mut self) -> Result<()> {
+        if let Some(staging) = self.staging.take() {
+            self.swap(staging).await?;
+        }
+        if self.fsync_policy() == FsyncPolicy::Batch && self.unsynced_files > 0 {
+            self.sync_batch().await?;
+        }
+        Ok(())
+    }
+
+    /// Replay a staged transfer onto the destination
+    async fn swap(&mut self, mut staging: Staging) -> Result<()> {
+        let failed = (self.stats.errors.len() as u64).max(self.stats.files_err);
+        if let Some(first) = self.stats.errors.first() {
+            anyhow::bail!(
+                "{} error(s), none of the transfer applied (--atomic); first: {}: {}",
+                failed,
+                first.path,
+                first.message
+            );
+        }
+        if failed > 0 {
+            anyhow::bail!(
+                "{} error(s), none of the transfer applied (--atomic)",
+                failed
+            );
+        }
+        // Everything staged has to be on disk before it goes into place
+        if self.fsync_policy() == FsyncPolicy::Batch && self.unsynced_files > 0 {
+            self.sync_batch().await?;
+        }
+        self.replay = Some(Replay {
+            stage: staging.name.clone(),
+            undo: Vec::new(),
+        });
+        let replayed = self.replay_steps(&mut staging).await;
+        let undo = self.replay.take().map(|replay| replay.undo);
+        let Err(e) = replayed else {
+            return Ok(());
+        };
+        if self.undo(undo.unwrap_or_default()).await {
+            return Err(e.context("Failed to apply the transfer; rolled back (--atomic)"));
+        }
+        staging.keep = true;
+        Err(e.context(format!(
+            "Failed to apply the transfer and to roll it back (--atomic); originals are in {}",
+            staging.dir.display()
+        )))
+    }
 
-                    // Reuse buffer
-                    copy_buf.resize(size, 0);
-                    original.seek(SeekFrom::Start(offset)).await?;
-                    original.read_exact(&mut copy_buf).await?;
-                    file.write_all(&copy_buf).await?;
+    /// Move each staged file into place and apply each staged change,
+    /// stopping at the first that fails
+    async fn replay_steps(&mut self, staging: &mut Staging) -> Result<()> {
+        let errors = self.stats.errors.len();
+        for step in std::mem::take(&mut staging.steps) {
+            let (path, created) = match step {
+                Staged::File(path) => {
+                    self.back_up(&path).await?;
+                    self.storage
+                        .rename(&staging.path_of(&path), &path)
+                        .await
+                        .with_context(|| format!("Failed to move {} into place", path))?;
+                    (path.to_string(), Some(false))
                 }
-                0x01 => {
-                    // Insert literal data
-                    if reader.remaining() < 4 {
-                        anyhow::bail!("Delta insert op truncated");
-                    }
-                    let len = reader.get_u32() as usize;
-
-                    if len > MAX_DELTA_COPY_SIZE {
-                        anyhow::bail!(
-                            "Delta insert size {} exceeds max {}",
-                            len,
-                            MAX_DELTA_COPY_SIZE
-                        );
-                    }
-                    if reader.remaining() < len {
-                        anyhow::bail!("Delta insert data truncated");
-                    }
+                Staged::Frame(msg_type, payload) => {
+                    let (path, created) = self.before_replay(msg_type, &payload).await?;
+                    self.apply(msg_type, payload).await?;
+                    (path, created)
+                }
+            };
+            if let Some(error) = self.stats.errors.get(errors) {
+                anyhow::bail!("{}: {}", error.path, error.message);
+            }
+            let exists =
+                std::fs::symlink_metadata(self.config.root.join(self.local_path(&path))).is_ok();
+            if let (Some(is_dir), true, Some(replay)) = (created, exists, &mut self.replay) {
+                replay.undo.push(Undo::Remove { path, is_dir });
+            }
+        }
+        Ok(())
+    }
+
+    /// The path a staged change applies to, and whether it's one that makes
+    /// something there (a directory or not) for the replay to undo
+    ///
+    /// A SET_ATTR's previous mode and mtime are recorded here, before it
+    /// changes them.
+    async fn before_replay(
+        &mut self,
+        msg_type: MessageType,
+        payload: &Bytes,
+    ) -> Result<(String, Option<bool>)> {
+        let payload = payload.clone();
+        Ok(match msg_type {
+            MessageType::FileEntry => (FileEntry::decode(payload)?.path.to_string(), Some(false)),
+            MessageType::Symlink => (Symlink::decode(payload)?.path, Some(false)),
+            MessageType::Mkdir => {
+                let path = Mkdir::decode(payload)?.path;
+                let local = self.config.root.join(self.local_path(&path));
+                let created = std::fs::symlink_metadata(local).is_err().then_some(true);
+                (path, created)
+            }
+            MessageType::SetAttr => {
+                let path = SetAttr::decode(payload)?.path;
+                if let (Ok(Some((mode, mtime))), Some(replay)) =
+                    (self.storage.stat(&path).await, &mut self.replay)
+                {
+                    replay.undo.push(Undo::Attr {
+                        path: path.clone(),
+                        mode,
+                        mtime,
+                    });
+                }
+                (path, None)
+            }
+            MessageType::Delete => (Delete::decode(payload)?.path, None),
+            MessageType::Xattr => (Xattr::decode(payload)?.path, None),
+            MessageType::Acl => (Acl::decode(payload)?.path, None),
+            _ => (String::new(), None),
+        })
+    }
 
-                    // Use copy_buf for insert data too
-                    copy_buf.resize(len, 0);
-                    reader.copy_to_slice(&mut copy_buf);
-                    file.write_all(&copy_buf).await?;
+    /// Undo a failed replay, last change first; false if anything couldn't
+    /// be put back
+    async fn undo(&mut self, undo: Vec<Undo>) -> bool {
+        let mut restored = true;
+        for step in undo.into_iter().rev() {
+            let (path, result) = match step {
+                Undo::Restore { path, aside } => {
+                    let result = self.storage.rename(&aside, &path).await;
+                    (path, result)
+                }
+                Undo::Remove { path, is_dir } => {
+                    let result = self.storage.delete(&path, is_dir).await;
+                    (path, result)
+                }
+                Undo::Attr { path, mode, mtime } => {
+                    let result = self.storage.set_metadata(&path, mode, mtime).await;
+                    (path, result)
                 }
-                _ => anyhow::bail!("Unknown delta op type: {}", op_type),
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to roll back {}: {:#}", path, e);
+                restored = false;
             }
         }
+        restored
+    }
 
-        Ok(())
+    /// Give up on the files still in flight, once the transfer was
+    /// cancelled or the peer stopped it
+    ///
+    /// With resume on, their temp files are kept for the next run to
+    /// continue; otherwise they're removed. With `--partial` what arrived so
+    /// far is committed under the real name instead, for the next run to
+    /// delta from. An `--atomic` transfer is dropped whole.
+    pub async fn abort(&mut self) {
+        if self.staging.is_some() {
+            // The files first: their temp files are in the staging directory
+            self.pending_files.clear();
+            self.staging = None;
+            return;
+        }
+        for (path, pending) in self.pending_files.drain() {
+            // Dropping the file removes its temp file
+            if pending.end == 0 {
+                continue;
+            }
+            if self.config.io.partial {
+                match pending.file.commit(pending.end).await {
+                    Ok(()) => tracing::info!("Kept {} bytes of interrupted {}", pending.end, path),
+                    Err(e) => tracing::warn!("Failed to keep partial {}: {:#}", path, e),
+                }
+            } else if self.config.io.resume {
+                match pending.file.keep(pending.end).await {
+                    Ok(true) => {
+                        tracing::info!(
+                            "Kept {} bytes of interrupted {} to resume",
+                            pending.end,
+                            path
+                        )
+                    }
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("Failed to keep partial {}: {:#}", path, e),
+                }
+            }
+        }
     }
 
     pub fn stats(&self) -> &SyncStats {
         &self.stats
     }
+
+    /// What has been applied so far, for a PROGRESS report; we don't know
+    /// the totals, so they're left at zero
+    pub fn progress(&self) -> Progress {
+        Progress {
+            files: self.stats.files_ok + self.stats.files_err + self.stats.hardlinks_created,
+            bytes: self.stats.bytes_transferred,
+            ..Default::default()
+        }
+    }
+
+    /// Aggregate digest of all changes applied so far
+    pub fn digest(&self) -> TransferDigest {
+        self.digest
+    }
+}
+
+/// The regular files below `dir`, whose path relative to the root is
+/// `path`, as paths relative to the root
+fn files_below(dir: &Path, path: &str) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let child = format!("{}/{}", path, name.to_string_lossy());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            files.extend(files_below(&entry.path(), &child)?);
+        } else if file_type.is_file() {
+            files.push(child);
+        }
+    }
+    Ok(files)
+}
+
+/// Apply delta operations against the file's previous version
+///
+/// Copies are done with `StorageFile::copy_original`, which locally keeps
+/// the data in the kernel (or shares the blocks) instead of reading it in
+/// and writing it back out.
+///
+/// Returns the number of bytes written to `file`, starting at `start`, and
+/// whether they were all copied from the same offset they now sit at
+async fn apply_delta(file: &mut dyn StorageFile, start: u64, delta: Bytes) -> Result<(u64, bool)> {
+    // Looked up on the first copy, for bounds checking
+    let mut file_size = None;
+
+    let mut reader = delta;
+    let mut written = 0u64;
+    let mut in_place = true;
+    // Copies of adjacent blocks go out as one (offset, len)
+    let mut run: Option<(u64, u64)> = None;
+
+    while reader.has_remaining() {
+        let op_type = reader.get_u8();
+        match op_type {
+            0x00 => {
+                // Copy from original file
+                if reader.remaining() < 12 {
+                    anyhow::bail!("Delta copy op truncated");
+                }
+                let offset = reader.get_u64();
+                let size = reader.get_u32() as usize;
+
+                // Bounds validation
+                if size > MAX_DELTA_COPY_SIZE {
+                    anyhow::bail!(
+                        "Delta copy size {} exceeds max {}",
+                        size,
+                        MAX_DELTA_COPY_SIZE
+                    );
+                }
+                let file_size = match file_size {
+                    Some(len) => len,
+                    None => *file_size.insert(file.original_len().await?),
+                };
+                if offset > file_size {
+                    anyhow::bail!(
+                        "Delta copy offset {} exceeds file size {}",
+                        offset,
+                        file_size
+                    );
+                }
+                if offset.saturating_add(size as u64) > file_size {
+                    anyhow::bail!(
+                        "Delta copy range {}..{} exceeds file size {}",
+                        offset,
+                        offset + size as u64,
+                        file_size
+                    );
+                }
+
+                in_place &= offset == start + written;
+                match &mut run {
+                    Some((from, len)) if *from + *len == offset => *len += size as u64,
+                    _ => {
+                        if let Some((from, len)) = run.take() {
+                            file.copy_original(from, start + written - len, len).await?;
+                        }
+                        run = Some((offset, size as u64));
+                    }
+                }
+                written += size as u64;
+            }
+            0x01 => {
+                // Insert literal data
+                if reader.remaining() < 4 {
+                    anyhow::bail!("Delta insert op truncated");
+                }
+                let len = reader.get_u32() as usize;
+
+                if len > MAX_DELTA_COPY_SIZE {
+                    anyhow::bail!(
+                        "Delta insert size {} exceeds max {}",
+                        len,
+                        MAX_DELTA_COPY_SIZE
+                    );
+                }
+                if reader.remaining() < len {
+                    anyhow::bail!("Delta insert data truncated");
+                }
+
+                if let Some((from, len)) = run.take() {
+                    file.copy_original(from, start + written - len, len).await?;
+                }
+                // Shares the frame's buffer
+                in_place = false;
+                file.write_at(start + written, reader.split_to(len)).await?;
+                written += len as u64;
+            }
+            _ => anyhow::bail!("Unknown delta op type: {}", op_type),
+        }
+    }
+    if let Some((from, len)) = run {
+        file.copy_original(from, start + written - len, len).await?;
+    }
+
+    Ok((written, in_place))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::streaming::channel::FileCounts;
+    use crate::streaming::protocol::Times;
+    use bytes::BufMut;
     use std::fs;
     use tempfile::TempDir;
 
@@ -570,29 +2044,42 @@ mod tests {
         let tmp = TempDir::new().unwrap();
         let config = ReceiverConfig {
             root: tmp.path().to_path_buf(),
-            block_size: 4096,
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
         };
         let mut receiver = Receiver::new(config);
 
         // Send FileEntry
         let entry = FileEntry {
-            path: "test.txt".to_string(),
+            path: "test.txt".into(),
             size: 11,
             mtime: 1234567890,
+            mtime_nsec: 0,
             mode: 0o644,
             inode: 0,
             flags: crate::streaming::protocol::FileFlags::empty(),
             symlink_target: None,
             link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: Some(Times {
+                atime: 1_500_000_000,
+                atime_nsec: 42,
+                btime: 0,
+                btime_nsec: 0,
+            }),
         };
         receiver
             .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
             .await
             .unwrap();
+        assert_eq!(receiver.paths.len(), 1);
 
         // Send Data
         let data = Data {
-            path: "test.txt".to_string(),
+            path: "test.txt".into(),
             offset: 0,
             flags: crate::streaming::protocol::DataFlags::empty(),
             data: Bytes::from("hello world"),
@@ -604,16 +2091,1947 @@ mod tests {
 
         // Send DataEnd
         let end = DataEnd {
-            path: "test.txt".to_string(),
+            path: "test.txt".into(),
             status: DataEnd::STATUS_OK,
+            hash: None,
         };
         receiver
             .handle_message(MessageType::DataEnd, end.encode().slice(5..))
             .await
             .unwrap();
+        assert!(receiver.paths.is_empty());
+
+        // The access time is set once the file is in place (checked before
+        // reading it sets a new one)
+        let meta = fs::metadata(tmp.path().join("test.txt")).unwrap();
+        let atime = FileTime::from_last_access_time(&meta);
+        assert_eq!(
+            (atime.unix_seconds(), atime.nanoseconds()),
+            (1_500_000_000, 42)
+        );
+        assert_eq!(
+            FileTime::from_last_modification_time(&meta).unix_seconds(),
+            1234567890
+        );
 
         // Check file exists and content is correct
         let content = fs::read_to_string(tmp.path().join("test.txt")).unwrap();
         assert_eq!(content, "hello world");
     }
+
+    #[tokio::test]
+    async fn test_receiver_interleaved_files() {
+        let tmp = TempDir::new().unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        let entry = |path: &str| FileEntry {
+            path: path.into(),
+            size: 10,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: crate::streaming::protocol::FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        let data = |path: &str, offset: u64, text: &'static str| Data {
+            path: path.into(),
+            offset,
+            flags: crate::streaming::protocol::DataFlags::empty(),
+            data: Bytes::from(text),
+        };
+        let end = |path: &str| DataEnd {
+            path: path.into(),
+            status: DataEnd::STATUS_OK,
+            hash: None,
+        };
+        // Two lanes of a sender: their DATA alternates, and the file started
+        // second finishes first
+        let frames = [
+            entry("a.txt").encode(),
+            entry("b.txt").encode(),
+            data("a.txt", 0, "aaaaa").encode(),
+            data("b.txt", 0, "bbbbb").encode(),
+            data("b.txt", 5, "BBBBB").encode(),
+            end("b.txt").encode(),
+            data("a.txt", 5, "AAAAA").encode(),
+            end("a.txt").encode(),
+        ];
+        for frame in frames {
+            let msg_type = MessageType::from_u8(frame[4]).unwrap();
+            receiver
+                .handle_message(msg_type, frame.slice(5..))
+                .await
+                .unwrap();
+        }
+
+        assert!(receiver.paths.is_empty() && receiver.pending_files.is_empty());
+        assert_eq!(receiver.stats().files_ok, 2);
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("a.txt")).unwrap(),
+            "aaaaaAAAAA"
+        );
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("b.txt")).unwrap(),
+            "bbbbbBBBBB"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receiver_atomic_applies_all_or_nothing() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("old.txt"), "old").unwrap();
+        let new_receiver = || {
+            Receiver::new(ReceiverConfig {
+                root: tmp.path().to_path_buf(),
+                compress_checksums: false,
+                cancel: CancellationToken::new(),
+                io: IoOptions {
+                    atomic: true,
+                    ..Default::default()
+                },
+            })
+        };
+        let frames = [
+            Mkdir {
+                path: "sub".to_string(),
+                mode: 0o755,
+                owner: None,
+            }
+            .encode(),
+            FileEntry {
+                path: "sub/new.txt".into(),
+                size: 3,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                flags: FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            }
+            .encode(),
+            Data {
+                path: "sub/new.txt".into(),
+                offset: 0,
+                flags: DataFlags::empty(),
+                data: Bytes::from("new"),
+            }
+            .encode(),
+            DataEnd {
+                path: "sub/new.txt".into(),
+                status: DataEnd::STATUS_OK,
+                hash: None,
+            }
+            .encode(),
+            Delete {
+                path: "old.txt".to_string(),
+                is_dir: false,
+            }
+            .encode(),
+        ];
+        async fn feed(receiver: &mut Receiver, frames: &[Bytes]) {
+            for frame in frames {
+                let msg_type = MessageType::from_u8(frame[4]).unwrap();
+                receiver
+                    .handle_message(msg_type, frame.slice(5..))
+                    .await
+                    .unwrap();
+            }
+        }
+        // A directory of the user's that only looks like ours is left alone
+        fs::create_dir(tmp.path().join(".sy-stage-mine")).unwrap();
+
+        // Everything arrived: nothing shows until finish
+        let mut receiver = new_receiver();
+        feed(&mut receiver, &frames).await;
+        let stage = receiver.staging.as_ref().unwrap().dir.clone();
+        assert!(stage.starts_with(tmp.path()));
+        assert!(!tmp.path().join("sub").exists());
+        assert!(tmp.path().join("old.txt").exists());
+        assert!(stage.join("new/sub/new.txt").exists());
+        receiver.finish().await.unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("sub/new.txt")).unwrap(),
+            "new"
+        );
+        assert!(!tmp.path().join("old.txt").exists());
+        assert!(!stage.exists());
+        assert!(tmp.path().join(".sy-stage-mine").exists());
+
+        // One error and none of it applies
+        fs::remove_dir_all(tmp.path().join("sub")).unwrap();
+        fs::write(tmp.path().join("old.txt"), "old").unwrap();
+        let mut receiver = new_receiver();
+        feed(&mut receiver, &frames).await;
+        let error = Error {
+            path: "other.txt".to_string(),
+            code: ErrorCode::IoError as u16,
+            message: "unreadable".to_string(),
+        };
+        receiver
+            .handle_message(MessageType::Error, error.encode().slice(5..))
+            .await
+            .unwrap();
+        let stage = receiver.staging.as_ref().unwrap().dir.clone();
+        let err = receiver.finish().await.unwrap_err();
+        assert!(err.to_string().contains("none of the transfer applied"));
+        assert!(!tmp.path().join("sub").exists());
+        assert!(tmp.path().join("old.txt").exists());
+        assert!(!stage.exists());
+
+        // Nor when the transfer is stopped halfway
+        let mut receiver = new_receiver();
+        feed(&mut receiver, &frames[..3]).await;
+        let stage = receiver.staging.as_ref().unwrap().dir.clone();
+        receiver.abort().await;
+        assert!(!tmp.path().join("sub").exists());
+        assert!(!stage.exists());
+    }
+
+    /// Local storage whose first rename onto one path fails
+    struct FailingRename {
+        inner: LocalStorage,
+        fail: &'static str,
+        failed: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl Storage for FailingRename {
+        async fn create(&self, entry: &FileEntry) -> Result<Box<dyn StorageFile>> {
+            self.inner.create(entry).await
+        }
+
+        async fn create_staged(
+            &self,
+            entry: &FileEntry,
+            staged: &str,
+        ) -> Result<Box<dyn StorageFile>> {
+            self.inner.create_staged(entry, staged).await
+        }
+
+        async fn set_metadata(&self, path: &str, mode: u32, mtime: FileTime) -> Result<()> {
+            self.inner.set_metadata(path, mode, mtime).await
+        }
+
+        async fn mkdir(&self, path: &str, mode: u32) -> Result<()> {
+            self.inner.mkdir(path, mode).await
+        }
+
+        async fn symlink(&self, path: &str, target: &str) -> Result<()> {
+            self.inner.symlink(path, target).await
+        }
+
+        async fn rename(&self, from: &str, to: &str) -> Result<()> {
+            if to == self.fail && !self.failed.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                anyhow::bail!("Injected failure");
+            }
+            self.inner.rename(from, to).await
+        }
+
+        async fn delete(&self, path: &str, is_dir: bool) -> Result<()> {
+            self.inner.delete(path, is_dir).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receiver_atomic_rolls_back_a_failed_replay() {
+        let tmp = TempDir::new().unwrap();
+        for (name, contents) in [("a.txt", "old a"), ("b.txt", "old b"), ("gone.txt", "gone")] {
+            fs::write(tmp.path().join(name), contents).unwrap();
+        }
+        let io = IoOptions {
+            atomic: true,
+            ..Default::default()
+        };
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io,
+        })
+        .with_storage(FailingRename {
+            inner: LocalStorage::new(tmp.path().to_path_buf(), io),
+            fail: "b.txt",
+            failed: Default::default(),
+        });
+
+        let file = |path: &str, contents: &'static str| {
+            [
+                FileEntry {
+                    path: path.into(),
+                    size: contents.len() as u64,
+                    mtime: 1234567890,
+                    mtime_nsec: 0,
+                    mode: 0o644,
+                    inode: 0,
+                    flags: FileFlags::empty(),
+                    symlink_target: None,
+                    link_target: None,
+                    win_attrs: None,
+                    bsd_flags: None,
+                    owner: None,
+                    times: None,
+                }
+                .encode(),
+                Data {
+                    path: path.into(),
+                    offset: 0,
+                    flags: DataFlags::empty(),
+                    data: Bytes::from(contents),
+                }
+                .encode(),
+                DataEnd {
+                    path: path.into(),
+                    status: DataEnd::STATUS_OK,
+                    hash: None,
+                }
+                .encode(),
+            ]
+        };
+        let mkdir = Mkdir {
+            path: "sub".to_string(),
+            mode: 0o755,
+            owner: None,
+        };
+        let delete = Delete {
+            path: "gone.txt".to_string(),
+            is_dir: false,
+        };
+        // The rename of b.txt, last, fails after everything else is applied
+        let frames = std::iter::once(mkdir.encode())
+            .chain(file("a.txt", "new a"))
+            .chain([delete.encode()])
+            .chain(file("sub/c.txt", "new c"))
+            .chain(file("b.txt", "new b"));
+        for frame in frames {
+            let msg_type = MessageType::from_u8(frame[4]).unwrap();
+            receiver
+                .handle_message(msg_type, frame.slice(5..))
+                .await
+                .unwrap();
+        }
+        let stage = receiver.staging.as_ref().unwrap().dir.clone();
+
+        let err = receiver.finish().await.unwrap_err();
+        assert!(format!("{:#}", err).contains("rolled back"), "{:#}", err);
+        for (name, contents) in [("a.txt", "old a"), ("b.txt", "old b"), ("gone.txt", "gone")] {
+            assert_eq!(fs::read_to_string(tmp.path().join(name)).unwrap(), contents);
+        }
+        assert!(!tmp.path().join("sub").exists());
+        assert!(!stage.exists());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_receiver_discards_checksum_mismatches() {
+        let tmp = TempDir::new().unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                checksum_verify: true,
+                ..Default::default()
+            },
+        });
+        let right = xxhash_rust::xxh3::xxh3_128(b"hello world");
+
+        for (path, hash) in [("good.txt", right), ("bad.txt", right ^ 1)] {
+            let entry = FileEntry {
+                path: path.into(),
+                size: 11,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                flags: crate::streaming::protocol::FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            };
+            let data = Data {
+                path: path.into(),
+                offset: 0,
+                flags: crate::streaming::protocol::DataFlags::empty(),
+                data: Bytes::from("hello world"),
+            };
+            let end = DataEnd {
+                path: path.into(),
+                status: DataEnd::STATUS_OK,
+                hash: Some(hash),
+            };
+            for (msg_type, frame) in [
+                (MessageType::FileEntry, entry.encode()),
+                (MessageType::Data, data.encode()),
+                (MessageType::DataEnd, end.encode()),
+            ] {
+                receiver
+                    .handle_message(msg_type, frame.slice(5..))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("good.txt")).unwrap(),
+            "hello world"
+        );
+        assert!(!tmp.path().join("bad.txt").exists());
+        assert!(!tmp.path().join("bad.sy.tmp").exists());
+        let stats = receiver.stats();
+        assert_eq!((stats.files_ok, stats.files_err), (1, 1));
+        assert_eq!(stats.checksum_mismatches, 1);
+        assert_eq!(stats.errors[0].code, ErrorCode::ChecksumMismatch as u16);
+    }
+
+    #[tokio::test]
+    async fn test_receiver_continues_after_write_errors() {
+        let tmp = TempDir::new().unwrap();
+        // A file where the peer expects a directory: nothing can go below it
+        fs::write(tmp.path().join("blocker"), "in the way").unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        let mkdir = Mkdir {
+            path: "blocker/sub".to_string(),
+            mode: 0o755,
+            owner: None,
+        };
+        receiver
+            .handle_message(MessageType::Mkdir, mkdir.encode().slice(5..))
+            .await
+            .unwrap();
+        for path in ["blocker/x", "ok.txt"] {
+            let entry = FileEntry {
+                path: path.into(),
+                size: 5,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                flags: FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            };
+            let data = Data {
+                path: path.into(),
+                offset: 0,
+                flags: DataFlags::empty(),
+                data: Bytes::from("hello"),
+            };
+            let end = DataEnd {
+                path: path.into(),
+                status: DataEnd::STATUS_OK,
+                hash: None,
+            };
+            for (msg_type, frame) in [
+                (MessageType::FileEntry, entry.encode()),
+                (MessageType::Data, data.encode()),
+                (MessageType::DataEnd, end.encode()),
+            ] {
+                receiver
+                    .handle_message(msg_type, frame.slice(5..))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let stats = receiver.stats();
+        assert_eq!((stats.files_ok, stats.files_err), (1, 2));
+        let failed: Vec<_> = stats.errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(failed, ["blocker/sub", "blocker/x"]);
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("ok.txt")).unwrap(),
+            "hello"
+        );
+        assert!(receiver.pending_files.is_empty() && receiver.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_receiver_fails_a_file_whose_data_offset_overflows() {
+        let tmp = TempDir::new().unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        let entry = FileEntry {
+            path: "big.bin".into(),
+            size: 5,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        let data = Data {
+            path: "big.bin".into(),
+            offset: u64::MAX - 2,
+            flags: DataFlags::empty(),
+            data: Bytes::from("hello"),
+        };
+        let end = DataEnd {
+            path: "big.bin".into(),
+            status: DataEnd::STATUS_OK,
+            hash: None,
+        };
+        for (msg_type, frame) in [
+            (MessageType::FileEntry, entry.encode()),
+            (MessageType::Data, data.encode()),
+            (MessageType::DataEnd, end.encode()),
+        ] {
+            receiver
+                .handle_message(msg_type, frame.slice(5..))
+                .await
+                .unwrap();
+        }
+
+        let stats = receiver.stats();
+        assert_eq!((stats.files_ok, stats.files_err), (0, 1));
+        assert!(stats.errors[0].message.contains("out of range"));
+        assert!(!tmp.path().join("big.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn test_receiver_counts_created_updated_touched() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("changed.txt"), "old content").unwrap();
+        fs::write(tmp.path().join("same.bin"), "hello world").unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        // Only the mtime of same.bin differs: its delta copies every byte
+        // from where it already is
+        let mut in_place = BytesMut::new();
+        in_place.put_u8(0x00);
+        in_place.put_u64(0);
+        in_place.put_u32(11);
+        for (path, flags, data) in [
+            ("new.txt", DataFlags::empty(), Bytes::from("hello world")),
+            (
+                "changed.txt",
+                DataFlags::empty(),
+                Bytes::from("new content"),
+            ),
+            ("same.bin", DataFlags::DELTA, in_place.freeze()),
+        ] {
+            let entry = FileEntry {
+                path: path.into(),
+                size: 11,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                flags: FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            };
+            let data = Data {
+                path: path.into(),
+                offset: 0,
+                flags,
+                data,
+            };
+            let end = DataEnd {
+                path: path.into(),
+                status: DataEnd::STATUS_OK,
+                hash: None,
+            };
+            for (msg_type, frame) in [
+                (MessageType::FileEntry, entry.encode()),
+                (MessageType::Data, data.encode()),
+                (MessageType::DataEnd, end.encode()),
+            ] {
+                receiver
+                    .handle_message(msg_type, frame.slice(5..))
+                    .await
+                    .unwrap();
+            }
+        }
+        let end = FileEnd {
+            total_files: 3,
+            total_bytes: 33,
+            skipped: 4,
+        };
+        receiver
+            .handle_message(MessageType::FileEnd, end.encode().slice(5..))
+            .await
+            .unwrap();
+
+        let stats = receiver.stats();
+        assert_eq!(stats.files_ok, 3);
+        assert_eq!(
+            stats.counts,
+            FileCounts {
+                created: 1,
+                updated: 1,
+                touched: 1,
+                skipped: 4,
+            }
+        );
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("same.bin")).unwrap(),
+            "hello world"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receiver_checks_deltas_against_the_source_hash() {
+        // A block that only matched by a hash collision: the delta copies
+        // "hello world" where the source has "hello there"
+        let source = xxhash_rust::xxh3::xxh3_128(b"hello there");
+        let mut copy = BytesMut::new();
+        copy.put_u8(0x00);
+        copy.put_u64(0);
+        copy.put_u32(11);
+        let copy = copy.freeze();
+
+        for (strong_hash, lands) in [(StrongHash::Xxh3_128, false), (StrongHash::Xxh3_64, true)] {
+            let tmp = TempDir::new().unwrap();
+            fs::write(tmp.path().join("file.bin"), "hello world").unwrap();
+            let mut receiver = Receiver::new(ReceiverConfig {
+                root: tmp.path().to_path_buf(),
+                compress_checksums: false,
+                cancel: CancellationToken::new(),
+                io: IoOptions {
+                    strong_hash,
+                    ..Default::default()
+                },
+            });
+            let entry = FileEntry {
+                path: "file.bin".into(),
+                size: 11,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                flags: FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            };
+            let data = Data {
+                path: "file.bin".into(),
+                offset: 0,
+                flags: DataFlags::DELTA,
+                data: copy.clone(),
+            };
+            let end = DataEnd {
+                path: "file.bin".into(),
+                status: DataEnd::STATUS_OK,
+                hash: Some(source),
+            };
+            for (msg_type, frame) in [
+                (MessageType::FileEntry, entry.encode()),
+                (MessageType::Data, data.encode()),
+                (MessageType::DataEnd, end.encode()),
+            ] {
+                receiver
+                    .handle_message(msg_type, frame.slice(5..))
+                    .await
+                    .unwrap();
+            }
+
+            // Only 64-bit block hashes go unchecked, as with older peers
+            let stats = receiver.stats();
+            assert_eq!(stats.files_ok, lands as u64);
+            assert_eq!(stats.checksum_mismatches, !lands as u64);
+            let meta = fs::metadata(tmp.path().join("file.bin")).unwrap();
+            let mtime = FileTime::from_last_modification_time(&meta).unix_seconds();
+            assert_eq!(mtime == 1234567890, lands);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_receiver_backs_up_overwrites_and_deletes() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("changed.txt"), "old content").unwrap();
+        fs::create_dir_all(tmp.path().join("gone/sub")).unwrap();
+        fs::write(tmp.path().join("gone/sub/a.txt"), "a").unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        })
+        .with_backup(Some(Backup::new(Some("old".into()), None, false)));
+
+        let entry = FileEntry {
+            path: "changed.txt".into(),
+            size: 11,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        let data = Data {
+            path: "changed.txt".into(),
+            offset: 0,
+            flags: DataFlags::empty(),
+            data: Bytes::from("new content"),
+        };
+        let end = DataEnd {
+            path: "changed.txt".into(),
+            status: DataEnd::STATUS_OK,
+            hash: None,
+        };
+        let delete = Delete {
+            path: "gone".into(),
+            is_dir: true,
+        };
+        for (msg_type, frame) in [
+            (MessageType::FileEntry, entry.encode()),
+            (MessageType::Data, data.encode()),
+            (MessageType::DataEnd, end.encode()),
+            (MessageType::Delete, delete.encode()),
+        ] {
+            receiver
+                .handle_message(msg_type, frame.slice(5..))
+                .await
+                .unwrap();
+        }
+
+        let read = |path: &str| fs::read_to_string(tmp.path().join(path)).unwrap();
+        assert_eq!(read("changed.txt"), "new content");
+        assert_eq!(read("old/changed.txt"), "old content");
+        assert_eq!(read("old/gone/sub/a.txt"), "a");
+        assert!(!tmp.path().join("gone").exists());
+        assert_eq!(receiver.stats().backed_up, 2);
+
+        // The backups aren't offered to the sender, which would delete them
+        let (entries, _) = scan_entries(&receiver).await;
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["changed.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_receiver_set_attr_updates_in_place() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("run.sh");
+        fs::write(&path, "#!/bin/sh").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let inode = fs::metadata(&path).unwrap().ino();
+        std::os::unix::fs::symlink(&path, tmp.path().join("link")).unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        for (path, flags) in [
+            ("run.sh", SetAttrFlags::MODE | SetAttrFlags::MTIME),
+            ("link", SetAttrFlags::MODE),
+            ("missing", SetAttrFlags::MTIME),
+        ] {
+            let attr = SetAttr {
+                path: path.to_string(),
+                mode: 0o755,
+                mtime: 1_000_000_000,
+                mtime_nsec: 250_000_000,
+                flags,
+            };
+            receiver
+                .handle_message(MessageType::SetAttr, attr.encode().slice(5..))
+                .await
+                .unwrap();
+        }
+
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!(meta.ino(), inode);
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o755);
+        assert_eq!(
+            (meta.mtime(), meta.mtime_nsec()),
+            (1_000_000_000, 250_000_000)
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "#!/bin/sh");
+
+        // Neither a symlink nor a missing file is touched
+        let stats = receiver.stats();
+        assert_eq!((stats.files_ok, stats.files_err), (1, 2));
+        assert_eq!(stats.counts.touched, 1);
+    }
+
+    #[tokio::test]
+    async fn test_receiver_goes_on_past_a_failed_path_unless_stop_on_error() {
+        let tmp = TempDir::new().unwrap();
+        // A file where a directory should go makes its mkdir fail
+        fs::write(tmp.path().join("blocked"), "file").unwrap();
+        let frames: Vec<_> = ["blocked", "open"]
+            .into_iter()
+            .map(|path| {
+                Mkdir {
+                    path: path.to_string(),
+                    mode: 0o755,
+                    owner: None,
+                }
+                .encode()
+            })
+            .collect();
+        let new_receiver = |stop_on_error| {
+            Receiver::new(ReceiverConfig {
+                root: tmp.path().to_path_buf(),
+                compress_checksums: false,
+                cancel: CancellationToken::new(),
+                io: IoOptions {
+                    stop_on_error,
+                    ..Default::default()
+                },
+            })
+        };
+
+        let mut receiver = new_receiver(false);
+        for frame in &frames {
+            receiver
+                .handle_message(MessageType::Mkdir, frame.slice(5..))
+                .await
+                .unwrap();
+        }
+        assert!(tmp.path().join("open").is_dir());
+        let stats = receiver.stats();
+        assert_eq!((stats.dirs_created, stats.files_err), (1, 1));
+        assert_eq!(stats.errors.len(), 1);
+        assert_eq!(stats.errors[0].path, "blocked");
+
+        fs::remove_dir(tmp.path().join("open")).unwrap();
+        let mut receiver = new_receiver(true);
+        let err = receiver
+            .handle_message(MessageType::Mkdir, frames[0].slice(5..))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("--stop-on-error"));
+        assert!(err.to_string().contains("blocked"));
+    }
+
+    #[tokio::test]
+    async fn test_receiver_io_uring_large_file() {
+        // Goes through io_uring when compiled in and supported, tokio::fs otherwise
+        let tmp = TempDir::new().unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                io_uring: true,
+                ..Default::default()
+            },
+        });
+
+        let content: Vec<u8> = (0..3 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let entry = FileEntry {
+            path: "big.bin".into(),
+            size: content.len() as u64,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: crate::streaming::protocol::FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        receiver
+            .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+            .await
+            .unwrap();
+
+        for (i, chunk) in content.chunks(64 * 1024).enumerate() {
+            let data = Data {
+                path: "big.bin".into(),
+                offset: (i * 64 * 1024) as u64,
+                flags: DataFlags::empty(),
+                data: Bytes::copy_from_slice(chunk),
+            };
+            receiver
+                .handle_message(MessageType::Data, data.encode().slice(5..))
+                .await
+                .unwrap();
+        }
+
+        let end = DataEnd {
+            path: "big.bin".into(),
+            status: DataEnd::STATUS_OK,
+            hash: None,
+        };
+        receiver
+            .handle_message(MessageType::DataEnd, end.encode().slice(5..))
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read(tmp.path().join("big.bin")).unwrap(), content);
+        assert_eq!(receiver.stats().bytes_transferred, content.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_receiver_batch_fsync_barrier() {
+        let tmp = TempDir::new().unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                fsync: FsyncPolicy::Batch,
+                ..Default::default()
+            },
+        });
+
+        for name in ["a.txt", "b.txt"] {
+            let entry = FileEntry {
+                path: name.into(),
+                size: 5,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                flags: crate::streaming::protocol::FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            };
+            receiver
+                .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+                .await
+                .unwrap();
+            let data = Data {
+                path: name.into(),
+                offset: 0,
+                flags: DataFlags::empty(),
+                data: Bytes::from("hello"),
+            };
+            receiver
+                .handle_message(MessageType::Data, data.encode().slice(5..))
+                .await
+                .unwrap();
+            let end = DataEnd {
+                path: name.into(),
+                status: DataEnd::STATUS_OK,
+                hash: None,
+            };
+            receiver
+                .handle_message(MessageType::DataEnd, end.encode().slice(5..))
+                .await
+                .unwrap();
+        }
+
+        // Below the batch thresholds nothing has been synced yet
+        assert_eq!(receiver.unsynced_files, 2);
+        assert_eq!(receiver.unsynced_bytes, 10);
+
+        receiver.finish().await.unwrap();
+        assert_eq!(receiver.unsynced_files, 0);
+        assert_eq!(
+            fs::read_to_string(tmp.path().join("b.txt")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receiver_preallocate() {
+        let tmp = TempDir::new().unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                preallocate: true,
+                ..Default::default()
+            },
+        });
+
+        // "short.txt" announces 100 bytes but only 5 arrive (source shrank)
+        for (name, size) in [("full.txt", 5), ("short.txt", 100)] {
+            let entry = FileEntry {
+                path: name.into(),
+                size,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                flags: crate::streaming::protocol::FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            };
+            receiver
+                .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+                .await
+                .unwrap();
+            assert_eq!(
+                fs::metadata(tmp.path().join(name).with_extension("sy.tmp"))
+                    .unwrap()
+                    .len(),
+                size
+            );
+            let data = Data {
+                path: name.into(),
+                offset: 0,
+                flags: DataFlags::empty(),
+                data: Bytes::from("hello"),
+            };
+            receiver
+                .handle_message(MessageType::Data, data.encode().slice(5..))
+                .await
+                .unwrap();
+            let end = DataEnd {
+                path: name.into(),
+                status: DataEnd::STATUS_OK,
+                hash: None,
+            };
+            receiver
+                .handle_message(MessageType::DataEnd, end.encode().slice(5..))
+                .await
+                .unwrap();
+            assert_eq!(fs::read_to_string(tmp.path().join(name)).unwrap(), "hello");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receiver_no_atomic_writes_new_files_in_place() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("old.txt"), "previous").unwrap();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                no_atomic: true,
+                ..Default::default()
+            },
+        });
+
+        // New files go straight to their final path; existing ones via a temp file
+        for (name, in_place) in [("new.txt", true), ("old.txt", false)] {
+            let entry = FileEntry {
+                path: name.into(),
+                size: 5,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                flags: crate::streaming::protocol::FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            };
+            receiver
+                .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+                .await
+                .unwrap();
+            let temp_path = tmp.path().join(name).with_extension("sy.tmp");
+            assert_eq!(temp_path.exists(), !in_place);
+
+            let data = Data {
+                path: name.into(),
+                offset: 0,
+                flags: DataFlags::empty(),
+                data: Bytes::from("hello"),
+            };
+            receiver
+                .handle_message(MessageType::Data, data.encode().slice(5..))
+                .await
+                .unwrap();
+            let end = DataEnd {
+                path: name.into(),
+                status: DataEnd::STATUS_OK,
+                hash: None,
+            };
+            receiver
+                .handle_message(MessageType::DataEnd, end.encode().slice(5..))
+                .await
+                .unwrap();
+            assert_eq!(fs::read_to_string(tmp.path().join(name)).unwrap(), "hello");
+            assert!(!temp_path.exists());
+        }
+        assert_eq!(receiver.stats().files_ok, 2);
+    }
+
+    #[tokio::test]
+    async fn test_scan_dest_stops_when_cancelled() {
+        let tmp = TempDir::new().unwrap();
+        for i in 0..4u8 {
+            fs::write(tmp.path().join(format!("f{}.bin", i)), vec![i; 8192]).unwrap();
+        }
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel,
+            io: IoOptions::default(),
+        });
+
+        let err = receiver.scan_dest(|_| Ok(())).await.unwrap_err();
+        assert!(crate::streaming::channel::is_cancelled(&err));
+    }
+
+    #[tokio::test]
+    async fn test_receiver_cancel_removes_temp_files() {
+        let tmp = TempDir::new().unwrap();
+        let cancel = CancellationToken::new();
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: cancel.clone(),
+            io: IoOptions::default(),
+        });
+
+        let entry = FileEntry {
+            path: "partial.bin".into(),
+            size: 10,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: crate::streaming::protocol::FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        receiver
+            .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+            .await
+            .unwrap();
+        let temp_path = tmp.path().join("partial.sy.tmp");
+        assert!(temp_path.exists());
+
+        cancel.cancel();
+        let data = Data {
+            path: "partial.bin".into(),
+            offset: 0,
+            flags: DataFlags::empty(),
+            data: Bytes::from("hello"),
+        };
+        let err = receiver
+            .handle_message(MessageType::Data, data.encode().slice(5..))
+            .await
+            .unwrap_err();
+        assert!(crate::streaming::channel::is_cancelled(&err));
+
+        drop(receiver);
+        assert!(!temp_path.exists());
+        assert!(!tmp.path().join("partial.bin").exists());
+    }
+
+    #[tokio::test]
+    async fn test_receiver_abort_keeps_partial() {
+        let tmp = TempDir::new().unwrap();
+        let entry = |name: &str| FileEntry {
+            path: name.into(),
+            size: 10,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: crate::streaming::protocol::FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        let data = Data {
+            path: "half.bin".into(),
+            offset: 0,
+            flags: DataFlags::empty(),
+            data: Bytes::from("hello"),
+        };
+
+        for partial in [false, true] {
+            let mut receiver = Receiver::new(ReceiverConfig {
+                root: tmp.path().to_path_buf(),
+                compress_checksums: false,
+                cancel: CancellationToken::new(),
+                io: IoOptions {
+                    partial,
+                    ..Default::default()
+                },
+            });
+            for name in ["half.bin", "empty.bin"] {
+                receiver
+                    .handle_message(MessageType::FileEntry, entry(name).encode().slice(5..))
+                    .await
+                    .unwrap();
+            }
+            receiver
+                .handle_message(MessageType::Data, data.encode().slice(5..))
+                .await
+                .unwrap();
+
+            receiver.abort().await;
+            assert!(!tmp.path().join("half.sy.tmp").exists());
+            assert!(!tmp.path().join("empty.sy.tmp").exists());
+            // Nothing arrived for empty.bin, so there's nothing to keep
+            assert!(!tmp.path().join("empty.bin").exists());
+            if partial {
+                assert_eq!(fs::read(tmp.path().join("half.bin")).unwrap(), b"hello");
+            } else {
+                assert!(!tmp.path().join("half.bin").exists());
+            }
+        }
+    }
+
+    /// Contents and mode by path
+    type MemoryFiles = HashMap<String, (Vec<u8>, u32)>;
+
+    /// Files and directories kept in memory
+    #[derive(Clone, Default)]
+    struct MemoryStorage {
+        files: Arc<std::sync::Mutex<MemoryFiles>>,
+        dirs: Arc<std::sync::Mutex<Vec<String>>>,
+        attrs: Arc<std::sync::Mutex<HashMap<String, u32>>>,
+        file_flags: Arc<std::sync::Mutex<HashMap<String, u32>>>,
+    }
+
+    struct MemoryFile {
+        path: String,
+        data: Vec<u8>,
+        store: MemoryStorage,
+    }
+
+    #[async_trait::async_trait]
+    impl Storage for MemoryStorage {
+        async fn create(&self, entry: &FileEntry) -> Result<Box<dyn StorageFile>> {
+            Ok(Box::new(MemoryFile {
+                path: entry.path.to_string(),
+                data: Vec::new(),
+                store: self.clone(),
+            }))
+        }
+
+        async fn set_metadata(&self, path: &str, mode: u32, _mtime: FileTime) -> Result<()> {
+            let mut files = self.files.lock().unwrap();
+            files.get_mut(path).unwrap().1 = mode;
+            Ok(())
+        }
+
+        async fn set_attributes(&self, path: &str, attrs: u32) -> Result<()> {
+            self.attrs.lock().unwrap().insert(path.to_string(), attrs);
+            Ok(())
+        }
+
+        async fn set_file_flags(&self, path: &str, flags: u32) -> Result<()> {
+            self.file_flags
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), flags);
+            Ok(())
+        }
+
+        async fn mkdir(&self, path: &str, _mode: u32) -> Result<()> {
+            self.dirs.lock().unwrap().push(path.to_string());
+            Ok(())
+        }
+
+        async fn symlink(&self, _path: &str, _target: &str) -> Result<()> {
+            anyhow::bail!("symlinks not supported")
+        }
+
+        async fn delete(&self, path: &str, _is_dir: bool) -> Result<()> {
+            self.files.lock().unwrap().remove(path);
+            Ok(())
+        }
+
+        async fn stat(&self, path: &str) -> Result<Option<(u32, FileTime)>> {
+            let files = self.files.lock().unwrap();
+            let (_, mode) = files.get(path).context("No such file")?;
+            Ok(Some((*mode, FileTime::zero())))
+        }
+
+        async fn list(&self) -> Result<Option<Vec<Listed>>> {
+            let files = self.files.lock().unwrap();
+            let listed = files
+                .iter()
+                .map(|(path, (data, mode))| {
+                    let path = Arc::new(PathBuf::from(path));
+                    let entry = crate::sync::scanner::FileEntry {
+                        path: Arc::clone(&path),
+                        relative_path: path,
+                        size: data.len() as u64,
+                        modified: std::time::UNIX_EPOCH,
+                        is_dir: false,
+                        is_symlink: false,
+                        symlink_target: None,
+                        is_sparse: false,
+                        allocated_size: data.len() as u64,
+                        xattrs: None,
+                        inode: None,
+                        nlink: 1,
+                        acls: None,
+                        bsd_flags: None,
+                        win_attrs: None,
+                    };
+                    Listed {
+                        entry,
+                        mode: Some(*mode),
+                    }
+                })
+                .collect();
+            Ok(Some(listed))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl StorageFile for MemoryFile {
+        async fn write_at(&mut self, offset: u64, data: Bytes) -> Result<()> {
+            let end = offset as usize + data.len();
+            if self.data.len() < end {
+                self.data.resize(end, 0);
+            }
+            self.data[offset as usize..end].copy_from_slice(&data);
+            Ok(())
+        }
+
+        async fn commit(self: Box<Self>, len: u64) -> Result<()> {
+            let mut data = self.data;
+            data.truncate(len as usize);
+            self.store
+                .files
+                .lock()
+                .unwrap()
+                .insert(self.path, (data, 0));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receiver_listed_modes_and_set_attr_go_through_storage() {
+        // The root stays empty: nothing may be read or changed there
+        let tmp = TempDir::new().unwrap();
+        let store = MemoryStorage::default();
+        store
+            .files
+            .lock()
+            .unwrap()
+            .insert("run.sh".into(), (b"#!/bin/sh".to_vec(), 0o755));
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                perms: true,
+                ..Default::default()
+            },
+        })
+        .with_storage(store.clone());
+
+        let mut frames = BytesMut::new();
+        receiver
+            .scan_dest(|bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        let mut frames = frames.freeze();
+        let (msg_type, payload) = crate::streaming::protocol::next_frame(&mut frames)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg_type, MessageType::DestFileEntry);
+        assert_eq!(DestFileEntry::decode(payload).unwrap().mode, 0o755);
+
+        let attr = SetAttr {
+            path: "run.sh".into(),
+            flags: SetAttrFlags::MODE,
+            mode: 0o700,
+            mtime: 0,
+            mtime_nsec: 0,
+        };
+        receiver
+            .handle_message(MessageType::SetAttr, attr.encode().slice(5..))
+            .await
+            .unwrap();
+        let stats = receiver.stats();
+        assert_eq!(
+            (stats.files_ok, stats.files_err),
+            (1, 0),
+            "{:?}",
+            stats.errors
+        );
+        assert_eq!(store.files.lock().unwrap()["run.sh"].1, 0o700);
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_receiver_custom_storage() {
+        let tmp = TempDir::new().unwrap();
+        let store = MemoryStorage::default();
+        store
+            .files
+            .lock()
+            .unwrap()
+            .insert("stale.txt".into(), (b"old".to_vec(), 0o644));
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        })
+        .with_storage(store.clone());
+
+        let mkdir = Mkdir {
+            path: "dir".into(),
+            mode: 0o755,
+            owner: None,
+        };
+        receiver
+            .handle_message(MessageType::Mkdir, mkdir.encode().slice(5..))
+            .await
+            .unwrap();
+
+        let entry = FileEntry {
+            path: "dir/a.txt".into(),
+            size: 11,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o640,
+            inode: 0,
+            flags: crate::streaming::protocol::FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: Some(0x2),
+            bsd_flags: Some(0x8000),
+            owner: None,
+            times: None,
+        };
+        receiver
+            .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+            .await
+            .unwrap();
+        for (offset, chunk) in [(6, "world"), (0, "hello ")] {
+            let data = Data {
+                path: "dir/a.txt".into(),
+                offset,
+                flags: DataFlags::empty(),
+                data: Bytes::from(chunk),
+            };
+            receiver
+                .handle_message(MessageType::Data, data.encode().slice(5..))
+                .await
+                .unwrap();
+        }
+        let end = DataEnd {
+            path: "dir/a.txt".into(),
+            status: DataEnd::STATUS_OK,
+            hash: None,
+        };
+        receiver
+            .handle_message(MessageType::DataEnd, end.encode().slice(5..))
+            .await
+            .unwrap();
+
+        let delete = Delete {
+            path: "stale.txt".into(),
+            is_dir: false,
+        };
+        receiver
+            .handle_message(MessageType::Delete, delete.encode().slice(5..))
+            .await
+            .unwrap();
+
+        // Traversal is rejected before the backend sees it
+        let escape = Mkdir {
+            path: "../escape".into(),
+            mode: 0o755,
+            owner: None,
+        };
+        assert!(receiver
+            .handle_message(MessageType::Mkdir, escape.encode().slice(5..))
+            .await
+            .is_err());
+
+        let files = store.files.lock().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            files.get("dir/a.txt"),
+            Some(&(b"hello world".to_vec(), 0o640))
+        );
+        assert_eq!(*store.dirs.lock().unwrap(), vec!["dir".to_string()]);
+        assert_eq!(store.attrs.lock().unwrap().get("dir/a.txt"), Some(&0x2));
+        assert_eq!(
+            store.file_flags.lock().unwrap().get("dir/a.txt"),
+            Some(&0x8000)
+        );
+        assert_eq!(receiver.stats().files_ok, 1);
+        assert_eq!(receiver.stats().deleted, 1);
+        // Nothing touched the local root
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_receiver_hostile_peer_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        let outside = tmp.path().join("outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("victim.txt"), "keep").unwrap();
+        // Already in the destination: one link out, one to the parent
+        symlink("../outside", root.join("link")).unwrap();
+        symlink("..", root.join("up")).unwrap();
+
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: root.clone(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        // Writing, creating or deleting through an existing link
+        let entry = FileEntry {
+            path: "link/evil.txt".into(),
+            size: 4,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        assert!(receiver
+            .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+            .await
+            .is_err());
+        let mkdir = Mkdir {
+            path: "link".into(),
+            mode: 0o777,
+            owner: None,
+        };
+        assert!(receiver
+            .handle_message(MessageType::Mkdir, mkdir.encode().slice(5..))
+            .await
+            .is_err());
+        let delete = Delete {
+            path: "link/victim.txt".into(),
+            is_dir: false,
+        };
+        assert!(receiver
+            .handle_message(MessageType::Delete, delete.encode().slice(5..))
+            .await
+            .is_err());
+
+        // A chain: a/b -> .. stays inside, but nothing may be created below it
+        let mkdir = Mkdir {
+            path: "a".into(),
+            mode: 0o755,
+            owner: None,
+        };
+        receiver
+            .handle_message(MessageType::Mkdir, mkdir.encode().slice(5..))
+            .await
+            .unwrap();
+        let inner = Symlink {
+            path: "a/b".into(),
+            target: "..".into(),
+            owner: None,
+        };
+        receiver
+            .handle_message(MessageType::Symlink, inner.encode().slice(5..))
+            .await
+            .unwrap();
+        let outer = Symlink {
+            path: "a/b/c".into(),
+            target: "..".into(),
+            owner: None,
+        };
+        assert!(receiver
+            .handle_message(MessageType::Symlink, outer.encode().slice(5..))
+            .await
+            .is_err());
+
+        // Looks inside on paper, resolves outside through `up`; removed again
+        let sneaky = Symlink {
+            path: "x".into(),
+            target: "up/outside".into(),
+            owner: None,
+        };
+        assert!(receiver
+            .handle_message(MessageType::Symlink, sneaky.encode().slice(5..))
+            .await
+            .is_err());
+        assert!(fs::symlink_metadata(root.join("x")).is_err());
+
+        assert_eq!(
+            fs::read_to_string(outside.join("victim.txt")).unwrap(),
+            "keep"
+        );
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 1);
+        assert!(fs::symlink_metadata(tmp.path().join("c")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_receiver_audits_destructive_changes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), "old").unwrap();
+        fs::set_permissions(root.join("a.txt"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::write(root.join("stale.txt"), "stale").unwrap();
+        let log_path = tmp.path().join("audit.log");
+
+        let audit = AuditLog::open(&log_path).unwrap().with_peer("bob@host");
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: root.clone(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        })
+        .with_audit(Some(audit));
+
+        // Overwrites a.txt with new permissions; b.txt is new
+        for (path, mode) in [("a.txt", 0o600), ("b.txt", 0o644)] {
+            let entry = FileEntry {
+                path: path.into(),
+                size: 3,
+                mtime: 1234567890,
+                mtime_nsec: 0,
+                mode,
+                inode: 0,
+                flags: FileFlags::empty(),
+                symlink_target: None,
+                link_target: None,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+            };
+            receiver
+                .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+                .await
+                .unwrap();
+            let data = Data {
+                path: path.into(),
+                offset: 0,
+                flags: DataFlags::empty(),
+                data: Bytes::from("new"),
+            };
+            receiver
+                .handle_message(MessageType::Data, data.encode().slice(5..))
+                .await
+                .unwrap();
+            let end = DataEnd {
+                path: path.into(),
+                status: DataEnd::STATUS_OK,
+                hash: None,
+            };
+            receiver
+                .handle_message(MessageType::DataEnd, end.encode().slice(5..))
+                .await
+                .unwrap();
+        }
+        // Only the delete of something that existed is recorded
+        for path in ["stale.txt", "missing.txt"] {
+            let delete = Delete {
+                path: path.into(),
+                is_dir: false,
+            };
+            receiver
+                .handle_message(MessageType::Delete, delete.encode().slice(5..))
+                .await
+                .unwrap();
+        }
+
+        let records: Vec<serde_json::Value> = fs::read_to_string(&log_path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let ops: Vec<(&str, &str)> = records
+            .iter()
+            .map(|r| (r["op"].as_str().unwrap(), r["path"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                ("overwrite", "a.txt"),
+                ("chmod", "a.txt"),
+                ("delete", "stale.txt")
+            ]
+        );
+        assert_eq!(records[1]["from"], "644");
+        assert_eq!(records[1]["to"], "600");
+        assert!(records.iter().all(|r| r["peer"] == "bob@host"));
+    }
+
+    #[tokio::test]
+    async fn test_scan_dest_parallel_checksums_keep_scan_order() {
+        let tmp = TempDir::new().unwrap();
+        for i in 0..8u8 {
+            fs::write(tmp.path().join(format!("f{}.bin", i)), vec![i; 64 * 1024]).unwrap();
+            fs::write(tmp.path().join(format!("s{}.txt", i)), "small").unwrap();
+        }
+
+        let receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        let mut frames = BytesMut::new();
+        let (total_files, _) = receiver
+            .scan_dest(|bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(total_files, 16);
+
+        let expected: Vec<String> = crate::sync::scanner::Scanner::new(tmp.path())
+            .scan()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.relative_path.to_string_lossy().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        let mut reader = &frames[..];
+        let mut paths = Vec::new();
+        loop {
+            let (msg_type, payload) = crate::streaming::protocol::read_frame(&mut reader)
+                .await
+                .unwrap();
+            if msg_type == MessageType::DestFileEnd {
+                break;
+            }
+            let entry = DestFileEntry::decode(payload).unwrap();
+            if entry.path.ends_with(".bin") {
+                let direct = crate::delta::checksum::compute_checksums_with(
+                    &tmp.path().join(&entry.path),
+                    crate::delta::calculate_block_size(64 * 1024),
+                    StrongHash::default(),
+                )
+                .unwrap();
+                assert_eq!(entry.checksums.len(), direct.len());
+                assert_eq!(entry.checksums[3].strong, direct[3].strong);
+            } else {
+                assert!(entry.checksums.is_empty());
+            }
+            paths.push(entry.path);
+        }
+        assert_eq!(paths, expected);
+    }
+
+    #[tokio::test]
+    async fn test_scan_dest_compresses_large_checksum_lists() {
+        let tmp = TempDir::new().unwrap();
+        // 1MB at a fixed 4KB block = 256 checksums, above CHECKSUM_COMPRESS_MIN
+        fs::write(tmp.path().join("big.bin"), vec![7u8; 1024 * 1024]).unwrap();
+        fs::write(tmp.path().join("small.txt"), "tiny").unwrap();
+
+        let receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: true,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                block_size: Some(4096),
+                ..Default::default()
+            },
+        });
+
+        let mut frames = BytesMut::new();
+        receiver
+            .scan_dest(|bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let mut reader = &frames[..];
+        let mut big = None;
+        loop {
+            let (msg_type, payload) = crate::streaming::protocol::read_frame(&mut reader)
+                .await
+                .unwrap();
+            if msg_type == MessageType::DestFileEnd {
+                break;
+            }
+            let entry = DestFileEntry::decode(payload).unwrap();
+            if entry.path == "big.bin" {
+                big = Some(entry);
+            }
+        }
+
+        let big = big.expect("big.bin entry");
+        assert!(big.flags.contains(DestFileFlags::CHECKSUMS_ZSTD));
+        assert_eq!(big.checksums.len(), 256);
+        assert_eq!(big.checksums[255].offset, 255 * 4096);
+    }
+
+    #[tokio::test]
+    async fn test_scan_dest_sizes_blocks_by_file() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("big.bin"), vec![7u8; 4 * 1024 * 1024]).unwrap();
+        fs::write(tmp.path().join("small.bin"), vec![7u8; 64 * 1024]).unwrap();
+
+        let receiver = Receiver::new(ReceiverConfig {
+            root: tmp.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        let (entries, _) = scan_entries(&receiver).await;
+        let block = |path: &str| {
+            let e = entries.iter().find(|e| e.path == path).unwrap();
+            e.checksums[1].offset - e.checksums[0].offset
+        };
+        // sqrt(4MB) = 2KB; 64KB files fall back to the 512-byte minimum
+        assert_eq!(block("big.bin"), 2048);
+        assert_eq!(block("small.bin"), 512);
+    }
+
+    /// Entries and DEST_FILE_END of one scan_dest run
+    async fn scan_entries(receiver: &Receiver) -> (Vec<DestFileEntry>, DestFileEnd) {
+        let mut frames = BytesMut::new();
+        receiver
+            .scan_dest(|bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let mut reader = &frames[..];
+        let mut entries = Vec::new();
+        loop {
+            let (msg_type, payload) = crate::streaming::protocol::read_frame(&mut reader)
+                .await
+                .unwrap();
+            if msg_type == MessageType::DestFileEnd {
+                return (entries, DestFileEnd::decode(payload).unwrap());
+            }
+            entries.push(DestFileEntry::decode(payload).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_dest_manifest_sends_only_changes() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("big.bin"), vec![7u8; 128 * 1024]).unwrap();
+        fs::write(tmp.path().join("gone.txt"), "old").unwrap();
+        let receiver = |known| {
+            Receiver::new(ReceiverConfig {
+                root: tmp.path().to_path_buf(),
+                compress_checksums: false,
+                cancel: CancellationToken::new(),
+                io: IoOptions::default(),
+            })
+            .with_dest_manifest(Some(known))
+        };
+
+        // First session: the client has nothing, so it gets everything
+        let (entries, end) = scan_entries(&receiver(0)).await;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(end.base, 0);
+        assert_ne!(end.manifest, 0);
+        assert!(tmp.path().join(MANIFEST_FILE).exists());
+
+        // Nothing changed: nothing to send, same manifest
+        let (entries, again) = scan_entries(&receiver(end.manifest)).await;
+        assert!(entries.is_empty());
+        assert_eq!(again.base, end.manifest);
+        assert_eq!(again.manifest, end.manifest);
+
+        fs::remove_file(tmp.path().join("gone.txt")).unwrap();
+        fs::write(tmp.path().join("new.txt"), "new").unwrap();
+        let (entries, changed) = scan_entries(&receiver(end.manifest)).await;
+        let mut paths: Vec<_> = entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.flags.contains(DestFileFlags::REMOVED)))
+            .collect();
+        paths.sort();
+        assert_eq!(paths, [("gone.txt", true), ("new.txt", false)]);
+        assert_ne!(changed.manifest, end.manifest);
+
+        // A client with a stale manifest gets the whole tree again
+        let (entries, full) = scan_entries(&receiver(end.manifest)).await;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(full.base, 0);
+        let big = entries.iter().find(|e| e.path == "big.bin").unwrap();
+        assert_eq!(big.checksums.len(), 256);
+    }
 }
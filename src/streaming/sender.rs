@@ -3,174 +3,854 @@
 //! Receives FileJobs from Generator, reads file content,
 //! computes deltas when possible, and sends Data chunks.
 
-use crate::delta::generator::{generate_delta_streaming, DeltaOp};
+use crate::delta::generator::{
+    generate_delta_mapped, generate_delta_streaming_with, Delta, DeltaOp,
+};
+use crate::delta::StrongHash;
+use crate::error::{ErrorCode, SyncError};
 use crate::streaming::channel::{
-    DeltaInfo, FileJob, FileJobReceiver, GeneratorMessage, DATA_CHUNK_SIZE, DELTA_CHUNK_SIZE,
+    is_cancelled, Cancelled, DeltaInfo, FileJob, FileJobReceiver, GeneratorMessage,
+    ProgressCounter, DATA_CHUNK_SIZE, DELTA_CHUNK_SIZE,
+};
+use crate::streaming::digest::{content_hash, split_frame, FileDigest, TransferDigest};
+use crate::streaming::io::{
+    drop_cache, open_direct, read_direct, warn_direct_unsupported, AlignedBuf, IoOptions,
 };
 use crate::streaming::protocol::{
-    Data, DataEnd, DataFlags, Delete, DeleteEnd, FileEnd, FileEntry, FileFlags, Mkdir, Symlink,
+    Acl, Data, DataEnd, DataFlags, Delete, DeleteEnd, Error, FileEnd, FileEntry, FileFlags,
+    MessageType, Mkdir, SetAttr, SetAttrFlags, Symlink, Xattr,
 };
+use crate::streaming::transfer_log::{Transfer, TransferLog};
+use crate::streaming::tuning::{AutoTune, MAX_CHUNK_SIZE};
+use crate::sync::ratelimit::RateLimiter;
 use anyhow::{Context, Result};
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::collections::HashMap;
+use std::future::Future;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, BufReader};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader, SeekFrom};
+use tokio_util::sync::CancellationToken;
+
+/// Size of the shared buffer frames are encoded into
+const FRAME_BUF_SIZE: usize = 64 * 1024;
+
+/// Grow the frame buffer when less than this is left, so small frames keep
+/// landing in one allocation instead of a fresh exact-size one each
+const FRAME_BUF_MIN_SPARE: usize = 4 * 1024;
+
+/// zstd level for DATA payloads without `--compress-level`
+pub const DEFAULT_COMPRESS_LEVEL: i32 = 3;
+
+/// Buffer that frames are encoded into and split off from
+///
+/// Split-off frames share the allocation, so a metadata-heavy transfer does
+/// one allocation per `FRAME_BUF_SIZE` bytes of frames instead of one per
+/// message.
+struct FrameBuf(BytesMut);
+
+impl FrameBuf {
+    fn new() -> Self {
+        Self(BytesMut::with_capacity(FRAME_BUF_SIZE))
+    }
+
+    fn frame(&mut self, encode: impl FnOnce(&mut BytesMut)) -> Bytes {
+        if self.0.capacity() < FRAME_BUF_MIN_SPARE {
+            self.0.reserve(FRAME_BUF_SIZE);
+        }
+        encode(&mut self.0);
+        self.0.split().freeze()
+    }
+}
 
 /// Sender configuration
+#[derive(Clone)]
 pub struct SenderConfig {
     /// Root path for reading files
     pub root: PathBuf,
-    /// Whether to compress data
+    /// zstd-compress DATA payloads (the receiver said it can take them)
     pub compress: bool,
+    /// Adaptive chunk size and in-flight window, when writing to a link
+    pub tune: Option<Arc<AutoTune>>,
+    /// Stops the transfer between DATA frames when cancelled
+    pub cancel: CancellationToken,
+    /// How file data is read from disk
+    pub io: IoOptions,
 }
 
 /// Sender state
 pub struct Sender {
     config: SenderConfig,
+    use_uring: bool,
+    frames: FrameBuf,
+    /// Compress the DATA of the file being sent
+    compress_file: bool,
+    /// DATA sent of the file being sent, before compression, for
+    /// `transfer_log`
+    data_sent: u64,
+    /// Holds DATA back to `--bwlimit`, across all lanes
+    limiter: Option<Arc<Mutex<RateLimiter>>>,
+    /// Where files and DATA sent are counted, for PROGRESS reports
+    progress: Option<Arc<ProgressCounter>>,
+    /// Where each file sent is recorded (`--log-file`)
+    transfer_log: Option<TransferLog>,
 }
 
 impl Sender {
     pub fn new(config: SenderConfig) -> Self {
-        Self { config }
+        let use_uring =
+            crate::streaming::uring::enabled(config.io.io_uring && !config.io.direct_io);
+        let limiter = config
+            .io
+            .bwlimit
+            .map(|limit| Arc::new(Mutex::new(RateLimiter::new(limit))));
+        Self {
+            config,
+            use_uring,
+            frames: FrameBuf::new(),
+            compress_file: false,
+            data_sent: 0,
+            limiter,
+            progress: None,
+            transfer_log: None,
+        }
+    }
+
+    /// Count the files and DATA bytes sent into `progress`
+    pub fn with_progress(mut self, progress: Arc<ProgressCounter>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Record each file sent, or that failed to send, in `log`
+    pub fn with_transfer_log(mut self, log: Option<TransferLog>) -> Self {
+        self.transfer_log = log;
+        self
     }
 
     /// Run the sender, processing FileJobs and outputting Data messages.
-    /// Returns encoded Data messages via callback.
-    pub async fn run<F>(self, mut rx: FileJobReceiver, mut on_data: F) -> Result<()>
+    /// Returns encoded Data messages via callback, and the aggregate digest
+    /// of everything sent (for comparison with the receiver's DONE).
+    ///
+    /// With `--transfers N`, up to N files are read and sent at once, each
+    /// on its own lane, their frames interleaving on the way out. Other
+    /// messages still go out in the order they came, except that an XATTR
+    /// or ACL waits for its file to finish, and a hard link or FILE_END
+    /// waits for every file before it.
+    #[tracing::instrument(name = "sender", skip_all)]
+    pub async fn run<F>(mut self, mut rx: FileJobReceiver, mut on_data: F) -> Result<TransferDigest>
     where
         F: FnMut(Bytes) -> Result<()>,
     {
-        while let Some(msg) = rx.recv().await {
-            match msg {
-                GeneratorMessage::File(job) => {
-                    self.process_file(job, &mut on_data).await?;
+        let mut digest = TransferDigest::new();
+        let progress = self.progress.clone();
+        // Lanes only hold it while handing over a frame, never across an
+        // await
+        let out = Mutex::new(move |frame: Bytes| {
+            if let Some(progress) = &progress {
+                if frame.get(4) == Some(&(MessageType::Data as u8)) {
+                    progress.sent(frame.len() as u64);
                 }
-                GeneratorMessage::Mkdir { path, mode } => {
-                    let msg = Mkdir {
-                        path: path.to_string_lossy().to_string(),
-                        mode,
-                    };
-                    on_data(msg.encode())?;
+            }
+            on_data(frame)
+        });
+        let mut emit = |frame: Bytes| (*out.lock().unwrap())(frame);
+
+        let mut idle: Vec<Sender> = (0..self.config.io.transfers())
+            .map(|_| self.lane())
+            .collect();
+        let mut in_flight = FuturesUnordered::new();
+        // XATTRs and ACLs that came in while their file was still being sent
+        let mut held: HashMap<Arc<PathBuf>, Vec<GeneratorMessage>> = HashMap::new();
+        // A message that goes out once no file is in flight
+        let mut barrier: Option<GeneratorMessage> = None;
+        let mut closed = false;
+
+        loop {
+            if in_flight.is_empty() {
+                if let Some(msg) = barrier.take() {
+                    self.send_message(msg, &mut digest, &mut emit)?;
                 }
-                GeneratorMessage::Symlink { path, target } => {
-                    let msg = Symlink {
-                        path: path.to_string_lossy().to_string(),
-                        target,
-                    };
-                    on_data(msg.encode())?;
+                if closed {
+                    break;
                 }
-                GeneratorMessage::Delete { path, is_dir } => {
-                    let msg = Delete {
-                        path: path.to_string_lossy().to_string(),
-                        is_dir,
-                    };
-                    on_data(msg.encode())?;
+            }
+            tokio::select! {
+                Some((lane, path, sent)) = in_flight.next() => {
+                    idle.push(lane);
+                    digest.add_file(&sent?);
+                    for msg in held.remove(&path).unwrap_or_default() {
+                        self.send_message(msg, &mut digest, &mut emit)?;
+                    }
                 }
-                GeneratorMessage::FileEnd {
-                    total_files,
-                    total_bytes,
-                } => {
-                    let msg = FileEnd {
-                        total_files,
-                        total_bytes,
+                msg = rx.recv(), if !closed && barrier.is_none() && !idle.is_empty() => {
+                    let Some(msg) = msg else {
+                        closed = true;
+                        continue;
                     };
-                    on_data(msg.encode())?;
+                    if self.config.cancel.is_cancelled() {
+                        return Err(Cancelled.into());
+                    }
+                    if let GeneratorMessage::Xattr { path, .. }
+                    | GeneratorMessage::Acl { path, .. } = &msg
+                    {
+                        if let Some(after) = held.get_mut(path) {
+                            after.push(msg);
+                            continue;
+                        }
+                    }
+                    match msg {
+                        GeneratorMessage::File(job) if job.link_target.is_some() => {
+                            // The receiver links to a target it has committed
+                            barrier = Some(GeneratorMessage::File(job));
+                        }
+                        GeneratorMessage::File(job) if !self.config.io.dry_run => {
+                            let lane = idle.pop().expect("a lane is idle");
+                            held.insert(Arc::clone(&job.path), Vec::new());
+                            in_flight.push(lane.send_file(job, &out));
+                        }
+                        msg @ GeneratorMessage::FileEnd { .. } => barrier = Some(msg),
+                        msg => self.send_message(msg, &mut digest, &mut emit)?,
+                    }
                 }
-                GeneratorMessage::DeleteEnd { count } => {
-                    let msg = DeleteEnd { count };
-                    on_data(msg.encode())?;
+            }
+        }
+        Ok(digest)
+    }
+
+    /// Another sender sharing this one's config, `--bwlimit` and progress,
+    /// to carry one more file at a time
+    fn lane(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            use_uring: self.use_uring,
+            frames: FrameBuf::new(),
+            compress_file: false,
+            data_sent: 0,
+            limiter: self.limiter.clone(),
+            progress: self.progress.clone(),
+            transfer_log: self.transfer_log.clone(),
+        }
+    }
+
+    /// Send one file on this lane, handing the lane back with the file's
+    /// path and digest
+    async fn send_file<F>(
+        mut self,
+        job: FileJob,
+        out: &Mutex<F>,
+    ) -> (Self, Arc<PathBuf>, Result<FileDigest>)
+    where
+        F: FnMut(Bytes) -> Result<()>,
+    {
+        let path = Arc::clone(&job.path);
+        let sent = self
+            .process_file(job, &mut |frame: Bytes| (*out.lock().unwrap())(frame))
+            .await;
+        self.file_done();
+        (self, path, sent)
+    }
+
+    /// Send a message that isn't a file's content: a directory, symlink,
+    /// attribute change, hard link, delete or end marker
+    fn send_message<F>(
+        &mut self,
+        msg: GeneratorMessage,
+        digest: &mut TransferDigest,
+        on_data: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(Bytes) -> Result<()>,
+    {
+        match msg {
+            GeneratorMessage::File(job) => {
+                // A change on its own: no DATA or DATA_END follows
+                let frame = self
+                    .frames
+                    .frame(|buf| standalone_entry(&job).encode_into(buf));
+                if let Some((msg_type, payload)) = split_frame(&frame) {
+                    digest.add_frame(msg_type, payload);
                 }
+                on_data(frame)?;
+                self.file_done();
+            }
+            GeneratorMessage::Mkdir { path, mode, owner } => {
+                let msg = Mkdir {
+                    path: path.to_string_lossy().to_string(),
+                    mode,
+                    owner,
+                };
+                let frame = self.frames.frame(|buf| msg.encode_into(buf));
+                digest.add_encoded(&frame);
+                on_data(frame)?;
+            }
+            GeneratorMessage::Symlink {
+                path,
+                target,
+                owner,
+            } => {
+                let msg = Symlink {
+                    path: path.to_string_lossy().to_string(),
+                    target,
+                    owner,
+                };
+                let frame = self.frames.frame(|buf| msg.encode_into(buf));
+                digest.add_encoded(&frame);
+                on_data(frame)?;
+            }
+            GeneratorMessage::Xattr { path, entries } => {
+                let msg = Xattr {
+                    path: path.to_string_lossy().to_string(),
+                    entries,
+                };
+                let frame = self.frames.frame(|buf| msg.encode_into(buf));
+                digest.add_encoded(&frame);
+                on_data(frame)?;
+            }
+            GeneratorMessage::Acl { path, text } => {
+                let msg = Acl {
+                    path: path.to_string_lossy().to_string(),
+                    text,
+                };
+                let frame = self.frames.frame(|buf| msg.encode_into(buf));
+                digest.add_encoded(&frame);
+                on_data(frame)?;
+            }
+            GeneratorMessage::SetAttr {
+                path,
+                mode,
+                mtime,
+                mtime_nsec,
+                flags,
+            } => {
+                let msg = SetAttr {
+                    path: path.to_string_lossy().to_string(),
+                    mode,
+                    mtime,
+                    mtime_nsec,
+                    flags,
+                };
+                let frame = self.frames.frame(|buf| msg.encode_into(buf));
+                digest.add_encoded(&frame);
+                on_data(frame)?;
+            }
+            GeneratorMessage::Delete { path, is_dir } => {
+                let msg = Delete {
+                    path: path.to_string_lossy().to_string(),
+                    is_dir,
+                };
+                let frame = self.frames.frame(|buf| msg.encode_into(buf));
+                digest.add_encoded(&frame);
+                on_data(frame)?;
+            }
+            GeneratorMessage::FileEnd {
+                total_files,
+                total_bytes,
+                skipped,
+            } => {
+                let msg = FileEnd {
+                    total_files,
+                    total_bytes,
+                    skipped,
+                };
+                on_data(self.frames.frame(|buf| msg.encode_into(buf)))?;
+            }
+            GeneratorMessage::DeleteEnd { count } => {
+                let msg = DeleteEnd { count };
+                on_data(self.frames.frame(|buf| msg.encode_into(buf)))?;
             }
         }
         Ok(())
     }
 
-    async fn process_file<F>(&self, job: FileJob, on_data: &mut F) -> Result<()>
+    fn file_done(&self) {
+        if let Some(progress) = &self.progress {
+            progress.file_done();
+        }
+    }
+
+    #[tracing::instrument(
+        name = "send_file",
+        skip_all,
+        fields(path = %job.path.display(), size = job.size, delta = job.need_delta)
+    )]
+    async fn process_file<F>(&mut self, job: FileJob, on_data: &mut F) -> Result<FileDigest>
     where
         F: FnMut(Bytes) -> Result<()>,
     {
-        let path_str = job.path.to_string_lossy().to_string();
+        // One allocation per file, shared by all of its messages
+        let path_str: Arc<str> = Arc::from(job.path.to_string_lossy());
         let full_path = self.config.root.join(job.path.as_ref());
+        // Already-compressed formats would only cost CPU
+        self.compress_file =
+            self.config.compress && !crate::compress::is_compressed_extension(&path_str);
+
+        // Every frame of this file feeds its digest on the way out
+        let mut file_digest = FileDigest::new();
+        let started = Instant::now();
+        self.data_sent = 0;
+        let mut on_data = |frame: Bytes| {
+            if let Some((msg_type, payload)) = split_frame(&frame) {
+                file_digest.update(msg_type, payload);
+            }
+            on_data(frame)
+        };
+        let on_data = &mut on_data;
+
+        // A partial temp file left by an interrupted transfer is continued
+        // after the blocks of it that still match
+        let resume_from = match job.resume {
+            Some(partial) => self.matching_prefix(&full_path, partial, job.size).await,
+            None => 0,
+        };
+
+        // Delta candidates are compared first: when the delta only copies
+        // every block in place, the destination already has this content
+        // and only its metadata is updated
+        let same_size = job
+            .checksums
+            .as_ref()
+            .is_some_and(|dest| dest.file_size == job.size);
+        let delta = match job.checksums {
+            Some(checksums) if job.need_delta && resume_from == 0 => {
+                Some(self.compute_delta(&full_path, checksums).await)
+            }
+            _ => None,
+        };
+        if let Some(Ok(delta)) = &delta {
+            if same_size && is_unchanged(delta, job.size) {
+                let mut flags = SetAttrFlags::MTIME;
+                if self.config.io.perms {
+                    flags |= SetAttrFlags::MODE;
+                }
+                let attr = SetAttr {
+                    path: path_str.to_string(),
+                    mode: job.mode,
+                    mtime: job.mtime,
+                    mtime_nsec: job.mtime_nsec,
+                    flags,
+                };
+                on_data(self.frames.frame(|buf| attr.encode_into(buf)))?;
+                return Ok(file_digest);
+            }
+        }
 
         // Send FILE_ENTRY first
+        let mut flags = if job.sparse {
+            FileFlags::SPARSE
+        } else {
+            FileFlags::empty()
+        };
+        flags.set(FileFlags::RESUME, resume_from > 0);
         let entry = FileEntry {
-            path: path_str.clone(),
+            path: Arc::clone(&path_str),
             size: job.size,
             mtime: job.mtime,
+            mtime_nsec: job.mtime_nsec,
             mode: job.mode,
             inode: job.inode,
-            flags: FileFlags::empty(),
+            flags,
             symlink_target: None,
             link_target: None,
+            win_attrs: job.win_attrs,
+            bsd_flags: job.bsd_flags,
+            owner: job.owner,
+            times: job.times,
         };
-        on_data(entry.encode())?;
+        on_data(self.frames.frame(|buf| entry.encode_into(buf)))?;
 
         // Read and send data chunks
-        if job.need_delta && job.checksums.is_some() {
+        let is_delta = matches!(delta, Some(Ok(_)));
+        let sent = match delta {
             // Delta transfer
-            self.send_delta(&full_path, &path_str, job.checksums.unwrap(), on_data)
-                .await?;
-        } else {
-            // Full transfer
-            self.send_full(&full_path, &path_str, on_data).await?;
-        }
+            Some(Ok(delta)) => self.send_delta(&full_path, &path_str, delta, on_data).await,
+            Some(Err(e)) => Err(e),
+            _ if resume_from > 0 => {
+                self.send_full(&full_path, &path_str, resume_from, on_data)
+                    .await
+            }
+            _ if self.config.io.direct_io => {
+                self.send_full_direct(&full_path, &path_str, on_data).await
+            }
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            _ if self.use_uring && job.size > crate::streaming::uring::MIN_FILE_SIZE => {
+                self.send_full_uring(&full_path, &path_str, on_data).await
+            }
+            _ => {
+                // Full transfer
+                self.send_full(&full_path, &path_str, 0, on_data).await
+            }
+        };
+
+        // A file that can't be read fails on its own: ERROR says why and the
+        // failed DATA_END makes the receiver discard what it got so far.
+        // If the link itself is gone, sending those fails too.
+        let mut failure = None;
+        let status = match sent {
+            Ok(()) => DataEnd::STATUS_OK,
+            Err(e) if is_cancelled(&e) => return Err(e),
+            Err(e) => {
+                let message = format!("{:#}", e);
+                // Everything here reads the source, so a missing file was
+                // deleted since the scan
+                let code = match SyncError::from(e).error_code() {
+                    ErrorCode::NotFound => {
+                        tracing::warn!("File vanished: {}", path_str);
+                        ErrorCode::Vanished
+                    }
+                    code => {
+                        tracing::warn!("Failed to send {}: {}", path_str, message);
+                        code
+                    }
+                };
+                let error = Error {
+                    path: path_str.to_string(),
+                    code: code as u16,
+                    message,
+                };
+                on_data(self.frames.frame(|buf| error.encode_into(buf)))?;
+                failure = Some(error.message);
+                DataEnd::STATUS_ERROR
+            }
+        };
+
+        // Hashed as it is once sent, so a file that changed meanwhile
+        // fails verification instead of landing torn. A delta is always
+        // checked with 128-bit block hashes: a block confirmed by a hash
+        // collision would otherwise land unnoticed
+        let check_delta = is_delta && self.config.io.strong_hash == StrongHash::Xxh3_128;
+        let hash =
+            if status == DataEnd::STATUS_OK && (self.config.io.checksum_verify || check_delta) {
+                let path = full_path.clone();
+                match tokio::task::spawn_blocking(move || content_hash(&path, u64::MAX)).await? {
+                    Ok(hash) => Some(hash),
+                    Err(e) => {
+                        tracing::warn!("Failed to hash {} for verification: {}", path_str, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
 
         // Send DATA_END
         let end = DataEnd {
-            path: path_str,
-            status: DataEnd::STATUS_OK,
+            path: Arc::clone(&path_str),
+            status,
+            hash,
         };
-        on_data(end.encode())?;
+        on_data(self.frames.frame(|buf| end.encode_into(buf)))?;
 
-        Ok(())
+        if let Some(log) = &self.transfer_log {
+            log.record(&Transfer {
+                path: &path_str,
+                size: job.size,
+                bytes: self.data_sent,
+                delta: is_delta,
+                duration: started.elapsed(),
+                error: failure.as_deref(),
+            });
+        }
+        Ok(file_digest)
     }
 
-    async fn send_full<F>(&self, path: &Path, path_str: &str, on_data: &mut F) -> Result<()>
+    /// Send the file from `start` on as plain DATA
+    async fn send_full<F>(
+        &mut self,
+        path: &Path,
+        path_str: &Arc<str>,
+        start: u64,
+        on_data: &mut F,
+    ) -> Result<()>
     where
         F: FnMut(Bytes) -> Result<()>,
     {
-        let file = File::open(path)
-            .await
-            .context("Failed to open file for full transfer")?;
+        let mut file = self
+            .read_or_stall(async {
+                File::open(path)
+                    .await
+                    .context("Failed to open file for full transfer")
+            })
+            .await?;
+        if start > 0 {
+            file.seek(SeekFrom::Start(start)).await?;
+        }
         let mut reader = BufReader::new(file);
-        let mut offset = 0u64;
-        let mut buf = vec![0u8; DATA_CHUNK_SIZE];
+        let mut offset = start;
+        let max_chunk = if self.config.tune.is_some() {
+            MAX_CHUNK_SIZE
+        } else {
+            DATA_CHUNK_SIZE
+        };
+        let mut buf = vec![0u8; max_chunk];
 
         loop {
-            let n = reader.read(&mut buf).await?;
+            let chunk_size = self.chunk_size();
+            let n = self
+                .read_or_stall(read_full(&mut reader, &mut buf[..chunk_size]))
+                .await?;
             if n == 0 {
                 break;
             }
 
-            let mut flags = DataFlags::empty();
-            if self.config.compress {
-                flags |= DataFlags::COMPRESSED;
+            let frame = self.data_frame(
+                path_str,
+                offset,
+                DataFlags::empty(),
+                Bytes::copy_from_slice(&buf[..n]),
+            );
+            self.pace(frame.len()).await?;
+            on_data(frame)?;
+
+            if self.config.io.drop_cache {
+                drop_cache(reader.get_ref(), offset, n as u64);
+            }
+            offset += n as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Wait for one read of a file, failing the file if it makes no
+    /// progress within `--stall-timeout`
+    ///
+    /// A read stuck in the kernel can't be cancelled: its blocking thread
+    /// is left behind and the transfer goes on without it.
+    async fn read_or_stall<T>(&self, read: impl Future<Output = Result<T>>) -> Result<T> {
+        let Some(limit) = self.config.io.stall_timeout else {
+            return read.await;
+        };
+        match tokio::time::timeout(limit, read).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("Read made no progress for {:?}", limit),
+            )
+            .into()),
+        }
+    }
+
+    /// Encode a DATA frame, zstd-compressing `payload` (and setting
+    /// COMPRESSED) when this file is compressed and it actually shrinks
+    fn data_frame(
+        &mut self,
+        path: &Arc<str>,
+        offset: u64,
+        flags: DataFlags,
+        payload: Bytes,
+    ) -> Bytes {
+        self.data_sent += payload.len() as u64;
+        let level = self
+            .config
+            .io
+            .compress_level
+            .unwrap_or(DEFAULT_COMPRESS_LEVEL);
+        let compressed = self
+            .compress_file
+            .then(|| zstd::bulk::compress(&payload, level));
+        let (flags, payload) = match compressed {
+            Some(Ok(compressed)) if compressed.len() < payload.len() => {
+                (flags | DataFlags::COMPRESSED, Bytes::from(compressed))
+            }
+            _ => (flags, payload),
+        };
+        let data = Data {
+            path: Arc::clone(path),
+            offset,
+            flags,
+            data: payload,
+        };
+        self.frames.frame(|buf| data.encode_into(buf))
+    }
+
+    /// Size of the next full-transfer chunk
+    fn chunk_size(&self) -> usize {
+        self.config
+            .tune
+            .as_ref()
+            .map_or(DATA_CHUNK_SIZE, |tune| tune.chunk_size())
+    }
+
+    /// Wait for room in the in-flight window, and under `--bwlimit` for
+    /// the rate to allow `len` more bytes, before emitting a DATA frame;
+    /// stops there if the transfer is cancelled
+    async fn pace(&mut self, len: usize) -> Result<()> {
+        if let Some(tune) = &self.config.tune {
+            tokio::select! {
+                _ = tune.acquire() => {}
+                _ = self.config.cancel.cancelled() => {}
+            }
+        }
+        if let Some(limiter) = &self.limiter {
+            let wait = limiter.lock().unwrap().consume(len as u64);
+            if !wait.is_zero() {
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = self.config.cancel.cancelled() => {}
+                }
+            }
+        }
+        if self.config.cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Full transfer reading through O_DIRECT, bypassing the page cache
+    async fn send_full_direct<F>(
+        &mut self,
+        path: &Path,
+        path_str: &Arc<str>,
+        on_data: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(Bytes) -> Result<()>,
+    {
+        let file = match open_direct(path, false) {
+            Ok(file) => Arc::new(file),
+            Err(e) => {
+                warn_direct_unsupported(path, &e);
+                return self.send_full(path, path_str, 0, on_data).await;
+            }
+        };
+
+        let mut buf = AlignedBuf::new(DATA_CHUNK_SIZE);
+        let mut offset = 0u64;
+        loop {
+            let f = Arc::clone(&file);
+            let read = tokio::task::spawn_blocking(move || {
+                let n = read_direct(&f, &mut buf, offset);
+                (buf, n)
+            });
+            let (b, n) = self
+                .read_or_stall(async { Ok::<_, anyhow::Error>(read.await?) })
+                .await?;
+            buf = b;
+
+            let n = n.context("Failed to read file for full transfer")?;
+            if n == 0 {
+                break;
             }
 
-            let data = Data {
-                path: path_str.to_string(),
+            let frame = self.data_frame(
+                path_str,
                 offset,
-                flags,
-                data: Bytes::copy_from_slice(&buf[..n]),
-            };
-            on_data(data.encode())?;
+                DataFlags::empty(),
+                Bytes::copy_from_slice(&buf[..n]),
+            );
+            self.pace(frame.len()).await?;
+            on_data(frame)?;
 
             offset += n as u64;
+            if n < buf.len() {
+                break;
+            }
         }
 
         Ok(())
     }
 
-    async fn send_delta<F>(
-        &self,
+    /// Full transfer reading a batch of chunks per io_uring submission
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    async fn send_full_uring<F>(
+        &mut self,
         path: &Path,
-        path_str: &str,
-        delta_info: DeltaInfo,
+        path_str: &Arc<str>,
         on_data: &mut F,
     ) -> Result<()>
     where
         F: FnMut(Bytes) -> Result<()>,
     {
+        use crate::streaming::uring::UringReader;
+
+        let file = std::fs::File::open(path).context("Failed to open file for full transfer")?;
+        let mut reader = match UringReader::new(file, DATA_CHUNK_SIZE) {
+            Ok(reader) => reader,
+            Err(e) => {
+                tracing::debug!("io_uring setup failed, using tokio::fs: {}", e);
+                return self.send_full(path, path_str, 0, on_data).await;
+            }
+        };
+
+        let mut offset = 0u64;
+        loop {
+            let read = tokio::task::spawn_blocking(move || {
+                let chunks = reader.read_batch();
+                (reader, chunks)
+            });
+            let (r, chunks) = self
+                .read_or_stall(async { Ok::<_, anyhow::Error>(read.await?) })
+                .await?;
+            reader = r;
+
+            let chunks = chunks.context("Failed to read file for full transfer")?;
+            if chunks.is_empty() {
+                break;
+            }
+            for chunk in chunks {
+                let len = chunk.len() as u64;
+                let frame = self.data_frame(path_str, offset, DataFlags::empty(), chunk);
+                self.pace(frame.len()).await?;
+                on_data(frame)?;
+                offset += len;
+            }
+            if self.config.io.drop_cache {
+                drop_cache(reader.file(), 0, offset);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How much of the file at `path` the destination's partial temp file
+    /// already holds: the leading whole blocks whose checksums still match
+    ///
+    /// Anything unreadable counts as nothing matched; the transfer that
+    /// follows reports it.
+    async fn matching_prefix(&self, path: &Path, partial: DeltaInfo, size: u64) -> u64 {
+        let block_size = partial.block_size as u64;
+        if block_size == 0 {
+            return 0;
+        }
+        let p = path.to_path_buf();
+        let strong = self.config.io.strong_hash;
+        let matched = tokio::task::spawn_blocking(move || {
+            use std::io::Read as _;
+            let mut file = std::io::BufReader::new(std::fs::File::open(&p)?);
+            let mut buf = vec![0u8; block_size as usize];
+            let mut matched = 0u64;
+            // The temp file's last block may be short; it's sent again
+            let whole_blocks = (partial.file_size.min(size) / block_size) as usize;
+            for block in partial.checksums.iter().take(whole_blocks) {
+                if block.offset != matched {
+                    break;
+                }
+                file.read_exact(&mut buf)?;
+                if crate::delta::Adler32::hash(&buf) != block.weak
+                    || strong.hash(&buf) != block.strong
+                {
+                    break;
+                }
+                matched += block_size;
+            }
+            Ok::<_, std::io::Error>(matched)
+        })
+        .await;
+        match matched {
+            Ok(Ok(matched)) => matched,
+            Ok(Err(e)) => {
+                tracing::debug!("Not resuming {}: {}", path.display(), e);
+                0
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Delta of the file at `path` against the destination's blocks
+    async fn compute_delta(&self, path: &Path, delta_info: DeltaInfo) -> Result<Delta> {
         // Convert protocol checksums to delta engine checksums
         let block_size = delta_info.block_size as usize;
         let file_size = delta_info.file_size;
@@ -200,19 +880,38 @@ impl Sender {
             })
             .collect();
 
-        // generate_delta_streaming is blocking
+        // Delta generation is blocking
         let p = path.to_path_buf();
+        let io = self.config.io;
         let delta = tokio::task::spawn_blocking(move || {
-            generate_delta_streaming(&p, &dest_checksums, block_size)
+            if io.mmap(std::fs::metadata(&p)?.len()) {
+                generate_delta_mapped(&p, &dest_checksums, block_size, io.strong_hash)
+            } else {
+                generate_delta_streaming_with(&p, &dest_checksums, block_size, io.strong_hash)
+            }
         })
         .await??;
+        Ok(delta)
+    }
 
-        // Encode delta ops into DATA messages, chunking to avoid frame size limits
-        let mut flags = DataFlags::DELTA;
-        if self.config.compress {
-            flags |= DataFlags::COMPRESSED;
+    async fn send_delta<F>(
+        &mut self,
+        path: &Path,
+        path_str: &Arc<str>,
+        delta: Delta,
+        on_data: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(Bytes) -> Result<()>,
+    {
+        if self.config.io.drop_cache {
+            if let Ok(file) = std::fs::File::open(path) {
+                drop_cache(&file, 0, 0);
+            }
         }
 
+        // Encode delta ops into DATA messages, chunking to avoid frame size limits
+
         // Serialize delta ops, chunking into multiple messages if needed.
         // Note: For delta operations, the receiver ignores the offset field (it processes
         // ops sequentially), so we always use 0. The receiver appends all delta chunks
@@ -241,13 +940,14 @@ impl Sender {
             // Check if adding this op would exceed chunk size
             if !delta_bytes.is_empty() && delta_bytes.len() + op_bytes.len() > DELTA_CHUNK_SIZE {
                 // Flush current chunk
-                let data = Data {
-                    path: path_str.to_string(),
-                    offset: 0, // Unused for delta - receiver processes ops sequentially
-                    flags,
-                    data: Bytes::from(std::mem::take(&mut delta_bytes)),
-                };
-                on_data(data.encode())?;
+                let frame = self.data_frame(
+                    path_str,
+                    0, // Unused for delta - receiver processes ops sequentially
+                    DataFlags::DELTA,
+                    Bytes::from(std::mem::take(&mut delta_bytes)),
+                );
+                self.pace(frame.len()).await?;
+                on_data(frame)?;
             }
 
             delta_bytes.extend(op_bytes);
@@ -255,19 +955,75 @@ impl Sender {
 
         // Flush remaining ops
         if !delta_bytes.is_empty() {
-            let data = Data {
-                path: path_str.to_string(),
-                offset: 0, // Unused for delta
-                flags,
-                data: Bytes::from(delta_bytes),
-            };
-            on_data(data.encode())?;
+            let frame = self.data_frame(
+                path_str,
+                0, // Unused for delta
+                DataFlags::DELTA,
+                Bytes::from(delta_bytes),
+            );
+            self.pace(frame.len()).await?;
+            on_data(frame)?;
         }
 
         Ok(())
     }
 }
 
+/// Whether `delta` rebuilds a file of `size` bytes by copying every block of
+/// the destination's copy to the offset it's already at
+fn is_unchanged(delta: &Delta, size: u64) -> bool {
+    let mut pos = 0u64;
+    for op in &delta.ops {
+        match op {
+            DeltaOp::Copy { offset, size } if *offset == pos => pos += *size as u64,
+            _ => return false,
+        }
+    }
+    pos == size
+}
+
+/// Read until `buf` is full or EOF, so chunks match the tuned size
+async fn read_full(reader: &mut BufReader<File>, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// The FILE_ENTRY that stands for a whole file when the destination links
+/// it to the path of `job.link_target` instead, or in a dry run only
+/// tallies it
+fn standalone_entry(job: &FileJob) -> FileEntry {
+    let flags = if job.link_target.is_some() {
+        FileFlags::HARDLINK
+    } else {
+        FileFlags::empty()
+    };
+    FileEntry {
+        path: Arc::from(job.path.to_string_lossy()),
+        size: job.size,
+        mtime: job.mtime,
+        mtime_nsec: job.mtime_nsec,
+        mode: job.mode,
+        inode: job.inode,
+        flags,
+        symlink_target: None,
+        link_target: job
+            .link_target
+            .as_ref()
+            .map(|target| target.to_string_lossy().to_string()),
+        win_attrs: None,
+        bsd_flags: None,
+        owner: None,
+        times: None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -285,6 +1041,9 @@ mod tests {
         let config = SenderConfig {
             root: tmp.path().to_path_buf(),
             compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
         };
 
         let (tx, rx) = crate::streaming::channel::file_job_channel();
@@ -295,10 +1054,19 @@ mod tests {
             path: Arc::new(PathBuf::from("test.txt")),
             size: 11,
             mtime: 0,
+            mtime_nsec: 0,
             mode: 0o644,
             inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: false,
             need_delta: false,
             checksums: None,
+            resume: None,
+            link_target: None,
         }))
         .await
         .unwrap();
@@ -306,6 +1074,7 @@ mod tests {
         tx.send(GeneratorMessage::FileEnd {
             total_files: 1,
             total_bytes: 11,
+            skipped: 0,
         })
         .await
         .unwrap();
@@ -326,56 +1095,332 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_sender_delta_file() {
+    async fn test_sender_holds_data_to_bwlimit() {
         let tmp = TempDir::new().unwrap();
-        let file_path = tmp.path().join("test.txt");
-
-        // Create a file that will differ from the "destination"
-        let content = "new content that differs from original";
-        fs::write(&file_path, content).unwrap();
+        let size = 6 * DATA_CHUNK_SIZE;
+        fs::write(tmp.path().join("big.bin"), vec![7u8; size]).unwrap();
 
-        let config = SenderConfig {
+        let sender = Sender::new(SenderConfig {
             root: tmp.path().to_path_buf(),
             compress: false,
-        };
-
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                bwlimit: Some(1024 * 1024),
+                ..Default::default()
+            },
+        });
         let (tx, rx) = crate::streaming::channel::file_job_channel();
-        let sender = Sender::new(config);
-
-        // Create fake destination checksums that won't match source
-        // This forces all source data to be sent as DeltaOp::Data
-        let delta_info = DeltaInfo {
-            block_size: 16,
-            file_size: 32,
-            checksums: vec![
-                BlockChecksum {
-                    offset: 0,
-                    weak: 0xDEADBEEF,
-                    strong: 0x0,
-                },
-                BlockChecksum {
-                    offset: 16,
-                    weak: 0xCAFEBABE,
-                    strong: 0x1,
-                },
-            ],
-        };
-
         tx.send(GeneratorMessage::File(FileJob {
-            path: Arc::new(PathBuf::from("test.txt")),
-            size: content.len() as u64,
+            path: Arc::new(PathBuf::from("big.bin")),
+            size: size as u64,
             mtime: 0,
+            mtime_nsec: 0,
             mode: 0o644,
             inode: 0,
-            need_delta: true,
-            checksums: Some(delta_info),
-        }))
-        .await
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: false,
+            need_delta: false,
+            checksums: None,
+            resume: None,
+            link_target: None,
+        }))
+        .await
+        .unwrap();
+        drop(tx);
+
+        // 1.5MB at 1MB/s: the first second's worth goes out at once, the
+        // rest takes another half second
+        let start = std::time::Instant::now();
+        sender.run(rx, |_| Ok(())).await.unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_sender_reports_unreadable_files_and_goes_on() {
+        use crate::streaming::protocol::next_frame;
+        use crate::streaming::{Receiver, ReceiverConfig};
+
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        fs::write(src.path().join("ok.txt"), "fine").unwrap();
+
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        for (name, size) in [("gone.txt", 5), ("ok.txt", 4)] {
+            tx.send(GeneratorMessage::File(FileJob {
+                path: Arc::new(PathBuf::from(name)),
+                size,
+                mtime: 0,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                sparse: false,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+                exists: false,
+                need_delta: false,
+                checksums: None,
+                resume: None,
+                link_target: None,
+            }))
+            .await
+            .unwrap();
+        }
+        drop(tx);
+
+        let sender = Sender::new(SenderConfig {
+            root: src.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+        let mut frames = BytesMut::new();
+        let digest = sender
+            .run(rx, |bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let mut receiver = Receiver::new(ReceiverConfig {
+            root: dst.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+        let mut frames = frames.freeze();
+        while let Some((msg_type, payload)) = next_frame(&mut frames).unwrap() {
+            receiver.handle_message(msg_type, payload).await.unwrap();
+        }
+        receiver.digest().verify(digest.value()).unwrap();
+
+        let stats = receiver.stats();
+        assert_eq!((stats.files_ok, stats.files_err), (1, 1));
+        assert_eq!(stats.errors.len(), 1);
+        assert_eq!(stats.errors[0].path, "gone.txt");
+        assert_eq!(
+            stats.errors[0].code,
+            crate::error::ErrorCode::Vanished as u16
+        );
+        assert!(!dst.path().join("gone.txt").exists());
+        assert_eq!(
+            fs::read_to_string(dst.path().join("ok.txt")).unwrap(),
+            "fine"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sender_gives_up_on_stalled_files() {
+        use crate::streaming::protocol::{next_frame, MessageType};
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = TempDir::new().unwrap();
+        // Opening a FIFO for reading blocks until a writer shows up
+        let fifo = tmp.path().join("stuck");
+        let c_path = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) }, 0);
+        fs::write(tmp.path().join("ok.txt"), "fine").unwrap();
+
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        for (name, size) in [("stuck", 5), ("ok.txt", 4)] {
+            tx.send(GeneratorMessage::File(FileJob {
+                path: Arc::new(PathBuf::from(name)),
+                size,
+                mtime: 0,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                sparse: false,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+                exists: false,
+                need_delta: false,
+                checksums: None,
+                resume: None,
+                link_target: None,
+            }))
+            .await
+            .unwrap();
+        }
+        drop(tx);
+
+        let sender = Sender::new(SenderConfig {
+            root: tmp.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                stall_timeout: Some(std::time::Duration::from_millis(200)),
+                ..Default::default()
+            },
+        });
+        let mut frames = BytesMut::new();
+        sender
+            .run(rx, |bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        // Let the abandoned open return
+        let _writer = fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+
+        let mut frames = frames.freeze();
+        let mut errors = Vec::new();
+        let mut ends = Vec::new();
+        while let Some((msg_type, payload)) = next_frame(&mut frames).unwrap() {
+            match msg_type {
+                MessageType::Error => errors.push(Error::decode(payload).unwrap()),
+                MessageType::DataEnd => {
+                    let end = DataEnd::decode(payload).unwrap();
+                    ends.push((end.path.to_string(), end.status));
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "stuck");
+        assert!(
+            errors[0].message.contains("no progress"),
+            "{}",
+            errors[0].message
+        );
+        assert_eq!(
+            ends,
+            [
+                ("stuck".to_string(), DataEnd::STATUS_ERROR),
+                ("ok.txt".to_string(), DataEnd::STATUS_OK)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sender_stops_when_cancelled() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("big.bin"), vec![7u8; 4 * DATA_CHUNK_SIZE]).unwrap();
+
+        let cancel = CancellationToken::new();
+        let sender = Sender::new(SenderConfig {
+            root: tmp.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: cancel.clone(),
+            io: IoOptions::default(),
+        });
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        tx.send(GeneratorMessage::File(FileJob {
+            path: Arc::new(PathBuf::from("big.bin")),
+            size: 4 * DATA_CHUNK_SIZE as u64,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: false,
+            need_delta: false,
+            checksums: None,
+            resume: None,
+            link_target: None,
+        }))
+        .await
+        .unwrap();
+        drop(tx);
+
+        // Cancel as soon as the first chunk goes out
+        let mut data_frames = 0;
+        let err = sender
+            .run(rx, |frame| {
+                if frame[4] == crate::streaming::protocol::MessageType::Data as u8 {
+                    data_frames += 1;
+                    cancel.cancel();
+                }
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(crate::streaming::channel::is_cancelled(&err));
+        assert_eq!(data_frames, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sender_delta_file() {
+        let tmp = TempDir::new().unwrap();
+        let file_path = tmp.path().join("test.txt");
+
+        // Create a file that will differ from the "destination"
+        let content = "new content that differs from original";
+        fs::write(&file_path, content).unwrap();
+
+        let config = SenderConfig {
+            root: tmp.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        };
+
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        let sender = Sender::new(config);
+
+        // Create fake destination checksums that won't match source
+        // This forces all source data to be sent as DeltaOp::Data
+        let delta_info = DeltaInfo {
+            block_size: 16,
+            file_size: 32,
+            checksums: vec![
+                BlockChecksum {
+                    offset: 0,
+                    weak: 0xDEADBEEF,
+                    strong: 0x0,
+                },
+                BlockChecksum {
+                    offset: 16,
+                    weak: 0xCAFEBABE,
+                    strong: 0x1,
+                },
+            ],
+        };
+
+        tx.send(GeneratorMessage::File(FileJob {
+            path: Arc::new(PathBuf::from("test.txt")),
+            size: content.len() as u64,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: true,
+            need_delta: true,
+            checksums: Some(delta_info),
+            resume: None,
+            link_target: None,
+        }))
+        .await
         .unwrap();
 
         tx.send(GeneratorMessage::FileEnd {
             total_files: 1,
             total_bytes: content.len() as u64,
+            skipped: 0,
         })
         .await
         .unwrap();
@@ -409,6 +1454,87 @@ mod tests {
             flags & DataFlags::DELTA.bits() != 0,
             "Expected DELTA flag to be set"
         );
+
+        // With 128-bit block hashes a delta ends with the source's hash
+        let end = messages
+            .iter()
+            .find(|m| m[4] == MessageType::DataEnd as u8)
+            .unwrap();
+        let end = DataEnd::decode(end.slice(5..)).unwrap();
+        assert_eq!(
+            end.hash,
+            Some(xxhash_rust::xxh3::xxh3_128(content.as_bytes()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sender_unchanged_content_only_sets_attributes() {
+        let tmp = TempDir::new().unwrap();
+        let file_path = tmp.path().join("same.bin");
+        let content: Vec<u8> = (0..100u8).collect();
+        fs::write(&file_path, &content).unwrap();
+
+        // The destination has the same bytes, under a different mtime
+        let checksums =
+            crate::delta::checksum::compute_checksums_with(&file_path, 16, StrongHash::default())
+                .unwrap()
+                .into_iter()
+                .map(|c| BlockChecksum {
+                    offset: c.offset,
+                    weak: c.weak,
+                    strong: c.strong,
+                })
+                .collect();
+        let sender = Sender::new(SenderConfig {
+            root: tmp.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        tx.send(GeneratorMessage::File(FileJob {
+            path: Arc::new(PathBuf::from("same.bin")),
+            size: 100,
+            mtime: 1_700_000_000,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: true,
+            need_delta: true,
+            checksums: Some(DeltaInfo {
+                block_size: 16,
+                file_size: 100,
+                checksums,
+            }),
+            resume: None,
+            link_target: None,
+        }))
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut frames = Vec::new();
+        sender
+            .run(rx, |frame| {
+                frames.push(frame);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0][4], MessageType::SetAttr as u8);
+        let attr = SetAttr::decode(frames[0].slice(5..)).unwrap();
+        assert_eq!(attr.path, "same.bin");
+        assert_eq!(attr.mtime, 1_700_000_000);
+        // Without -p the destination keeps its mode
+        assert_eq!(attr.flags, SetAttrFlags::MTIME);
     }
 
     #[tokio::test]
@@ -425,6 +1551,9 @@ mod tests {
         let config = SenderConfig {
             root: tmp.path().to_path_buf(),
             compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
         };
 
         let (tx, rx) = crate::streaming::channel::file_job_channel();
@@ -452,10 +1581,19 @@ mod tests {
             path: Arc::new(PathBuf::from("large.txt")),
             size: content.len() as u64,
             mtime: 0,
+            mtime_nsec: 0,
             mode: 0o644,
             inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: true,
             need_delta: true,
             checksums: Some(delta_info),
+            resume: None,
+            link_target: None,
         }))
         .await
         .unwrap();
@@ -463,6 +1601,7 @@ mod tests {
         tx.send(GeneratorMessage::FileEnd {
             total_files: 1,
             total_bytes: content.len() as u64,
+            skipped: 0,
         })
         .await
         .unwrap();
@@ -500,4 +1639,570 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_compressed_data_round_trips() {
+        use crate::streaming::protocol::next_frame;
+        use crate::streaming::{Receiver, ReceiverConfig};
+
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        let text = "all work and no play makes jack a dull boy\n".repeat(20_000);
+        fs::write(src.path().join("log.txt"), &text).unwrap();
+        // Already compressed by its extension: sent as is
+        fs::write(src.path().join("photo.jpg"), &text).unwrap();
+
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        for name in ["log.txt", "photo.jpg"] {
+            tx.send(GeneratorMessage::File(FileJob {
+                path: Arc::new(PathBuf::from(name)),
+                size: text.len() as u64,
+                mtime: 0,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                sparse: false,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+                exists: false,
+                need_delta: false,
+                checksums: None,
+                resume: None,
+                link_target: None,
+            }))
+            .await
+            .unwrap();
+        }
+        drop(tx);
+
+        let sender = Sender::new(SenderConfig {
+            root: src.path().to_path_buf(),
+            compress: true,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+        let mut frames = BytesMut::new();
+        let digest = sender
+            .run(rx, |bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let receiver = || {
+            Receiver::new(ReceiverConfig {
+                root: dst.path().to_path_buf(),
+                compress_checksums: false,
+                cancel: CancellationToken::new(),
+                io: IoOptions::default(),
+            })
+        };
+        let frames = frames.freeze();
+
+        // Compressed DATA is refused unless compression was negotiated
+        let mut plain = receiver();
+        let mut replay = frames.clone();
+        let mut refused = false;
+        while let Some((msg_type, payload)) = next_frame(&mut replay).unwrap() {
+            if plain.handle_message(msg_type, payload).await.is_err() {
+                refused = true;
+                break;
+            }
+        }
+        assert!(refused);
+        drop(plain);
+
+        let mut receiver = receiver().with_compression(true);
+        let mut wire = 0;
+        let mut compressed = Vec::new();
+        let mut frames = frames;
+        while let Some((msg_type, payload)) = next_frame(&mut frames).unwrap() {
+            if msg_type == MessageType::Data {
+                let data = Data::decode(payload.clone()).unwrap();
+                wire += data.data.len();
+                if data.flags.contains(DataFlags::COMPRESSED) {
+                    compressed.push(data.path.to_string());
+                }
+            }
+            receiver.handle_message(msg_type, payload).await.unwrap();
+        }
+        receiver.digest().verify(digest.value()).unwrap();
+
+        compressed.dedup();
+        assert_eq!(compressed, ["log.txt"]);
+        assert!(wire < text.len() * 2 - text.len() / 2);
+        for name in ["log.txt", "photo.jpg"] {
+            assert_eq!(fs::read_to_string(dst.path().join(name)).unwrap(), text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_continues_kept_temp_file() {
+        use crate::streaming::protocol::{next_frame, DestFileEntry, DestFileFlags};
+        use crate::streaming::{Receiver, ReceiverConfig};
+
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..65536u32).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(src.path().join("big.bin"), &content).unwrap();
+        let io = IoOptions {
+            resume: true,
+            ..Default::default()
+        };
+        let receiver = || {
+            Receiver::new(ReceiverConfig {
+                root: dst.path().to_path_buf(),
+                compress_checksums: false,
+                cancel: CancellationToken::new(),
+                io,
+            })
+        };
+        let job = |resume| FileJob {
+            path: Arc::new(PathBuf::from("big.bin")),
+            size: content.len() as u64,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: false,
+            need_delta: false,
+            checksums: None,
+            resume,
+            link_target: None,
+        };
+
+        // A transfer that dies 20000 bytes in keeps its temp file
+        let mut first = receiver();
+        let entry = FileEntry {
+            path: "big.bin".into(),
+            size: content.len() as u64,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        let data = Data {
+            path: "big.bin".into(),
+            offset: 0,
+            flags: DataFlags::empty(),
+            data: Bytes::copy_from_slice(&content[..20000]),
+        };
+        first
+            .handle_message(MessageType::FileEntry, entry.encode().slice(5..))
+            .await
+            .unwrap();
+        first
+            .handle_message(MessageType::Data, data.encode().slice(5..))
+            .await
+            .unwrap();
+        first.abort().await;
+        let temp = dst.path().join("big.sy.tmp");
+        assert_eq!(fs::metadata(&temp).unwrap().len(), 20000);
+
+        // The next Initial Exchange reports it
+        let mut frames = BytesMut::new();
+        receiver()
+            .scan_dest(|bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        let mut frames = frames.freeze();
+        let (_, payload) = next_frame(&mut frames).unwrap().unwrap();
+        let partial = DestFileEntry::decode(payload).unwrap();
+        assert_eq!(partial.path, "big.sy.tmp");
+        assert!(partial.flags.contains(DestFileFlags::PARTIAL));
+
+        // Only what follows its whole matching blocks is sent again
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        tx.send(GeneratorMessage::File(job(Some(DeltaInfo {
+            block_size: partial.block_size,
+            file_size: partial.size,
+            checksums: partial.checksums,
+        }))))
+        .await
+        .unwrap();
+        drop(tx);
+        let sender = Sender::new(SenderConfig {
+            root: src.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io,
+        });
+        let mut frames = BytesMut::new();
+        let digest = sender
+            .run(rx, |bytes| {
+                frames.extend_from_slice(&bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let mut second = receiver();
+        let mut frames = frames.freeze();
+        let mut offsets = Vec::new();
+        while let Some((msg_type, payload)) = next_frame(&mut frames).unwrap() {
+            match msg_type {
+                MessageType::FileEntry => {
+                    let entry = FileEntry::decode(payload.clone()).unwrap();
+                    assert!(entry.flags.contains(FileFlags::RESUME));
+                }
+                MessageType::Data => offsets.push(Data::decode(payload.clone()).unwrap().offset),
+                _ => {}
+            }
+            second.handle_message(msg_type, payload).await.unwrap();
+        }
+        second.digest().verify(digest.value()).unwrap();
+
+        // 39 whole 512-byte blocks of the 20000 bytes kept
+        assert_eq!(offsets.first(), Some(&19968));
+        assert_eq!(second.stats().bytes_transferred, 65536 - 19968);
+        assert_eq!(fs::read(dst.path().join("big.bin")).unwrap(), content);
+        assert!(!temp.exists());
+    }
+
+    #[tokio::test]
+    async fn test_sender_digest_matches_receiver() {
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        fs::write(src.path().join("a.txt"), "digest me").unwrap();
+
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        let sender = Sender::new(SenderConfig {
+            root: src.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+
+        tx.send(GeneratorMessage::Mkdir {
+            path: Arc::new(PathBuf::from("sub")),
+            mode: 0o755,
+            owner: None,
+        })
+        .await
+        .unwrap();
+        tx.send(GeneratorMessage::File(FileJob {
+            path: Arc::new(PathBuf::from("a.txt")),
+            size: 9,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: false,
+            need_delta: false,
+            checksums: None,
+            resume: None,
+            link_target: None,
+        }))
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut frames = Vec::new();
+        let sent = sender
+            .run(rx, |bytes| {
+                frames.push(bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let mut receiver = crate::streaming::Receiver::new(crate::streaming::ReceiverConfig {
+            root: dst.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+        for frame in &frames {
+            let (msg_type, payload) = crate::streaming::protocol::read_frame(&mut &frame[..])
+                .await
+                .unwrap();
+            receiver.handle_message(msg_type, payload).await.unwrap();
+        }
+        assert_eq!(receiver.digest(), sent);
+        assert_ne!(sent, TransferDigest::new());
+
+        // A duplicated frame makes the digests disagree
+        let (msg_type, payload) = crate::streaming::protocol::read_frame(&mut &frames[0][..])
+            .await
+            .unwrap();
+        receiver.handle_message(msg_type, payload).await.unwrap();
+        assert!(receiver.digest().verify(sent.value()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sender_lanes_interleave_files() {
+        use crate::streaming::protocol::XattrEntry;
+
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        let size = 3 * DATA_CHUNK_SIZE;
+        let names = ["a.bin", "b.bin", "c.bin", "d.bin"];
+        for (i, name) in names.iter().enumerate() {
+            fs::write(src.path().join(name), vec![i as u8; size]).unwrap();
+        }
+
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        let sender = Sender::new(SenderConfig {
+            root: src.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                transfers: Some(4),
+                ..Default::default()
+            },
+        });
+        for name in names {
+            tx.send(GeneratorMessage::File(FileJob {
+                path: Arc::new(PathBuf::from(name)),
+                size: size as u64,
+                mtime: 0,
+                mtime_nsec: 0,
+                mode: 0o644,
+                inode: 0,
+                sparse: false,
+                win_attrs: None,
+                bsd_flags: None,
+                owner: None,
+                times: None,
+                exists: false,
+                need_delta: false,
+                checksums: None,
+                resume: None,
+                link_target: None,
+            }))
+            .await
+            .unwrap();
+            if name == "a.bin" {
+                tx.send(GeneratorMessage::Xattr {
+                    path: Arc::new(PathBuf::from(name)),
+                    entries: vec![XattrEntry {
+                        name: "user.lane".to_string(),
+                        value: Bytes::from_static(b"1"),
+                    }],
+                })
+                .await
+                .unwrap();
+            }
+        }
+        tx.send(GeneratorMessage::FileEnd {
+            total_files: 4,
+            total_bytes: 4 * size as u64,
+            skipped: 0,
+        })
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut frames = Vec::new();
+        let sent = sender
+            .run(rx, |bytes| {
+                frames.push(bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let types: Vec<u8> = frames.iter().map(|frame| frame[4]).collect();
+        let position = |msg_type: MessageType| types.iter().position(|&t| t == msg_type as u8);
+        // How the files' frames interleave depends on when their reads
+        // complete, but the XATTR waits for its file and FILE_END for all
+        // of them
+        let a_end = frames
+            .iter()
+            .position(|frame| {
+                frame[4] == MessageType::DataEnd as u8
+                    && &*DataEnd::decode(frame.slice(5..)).unwrap().path == "a.bin"
+            })
+            .unwrap();
+        assert!(a_end < position(MessageType::Xattr).unwrap());
+        assert_eq!(types.last(), Some(&(MessageType::FileEnd as u8)));
+
+        let mut receiver = crate::streaming::Receiver::new(crate::streaming::ReceiverConfig {
+            root: dst.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions::default(),
+        });
+        for frame in &frames {
+            let (msg_type, payload) = crate::streaming::protocol::read_frame(&mut &frame[..])
+                .await
+                .unwrap();
+            receiver.handle_message(msg_type, payload).await.unwrap();
+        }
+        assert_eq!(receiver.digest(), sent);
+        assert_eq!(receiver.stats().files_ok, 4);
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(
+                fs::read(dst.path().join(name)).unwrap(),
+                vec![i as u8; size]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sender_io_uring_large_file_roundtrip() {
+        // Exercises the batched read path when io_uring is available
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..(DATA_CHUNK_SIZE * 40 + 123))
+            .map(|i| (i % 249) as u8)
+            .collect();
+        fs::write(src.path().join("big.bin"), &content).unwrap();
+
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        let sender = Sender::new(SenderConfig {
+            root: src.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                io_uring: true,
+                ..Default::default()
+            },
+        });
+        tx.send(GeneratorMessage::File(FileJob {
+            path: Arc::new(PathBuf::from("big.bin")),
+            size: content.len() as u64,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: false,
+            need_delta: false,
+            checksums: None,
+            resume: None,
+            link_target: None,
+        }))
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut frames = Vec::new();
+        sender
+            .run(rx, |bytes| {
+                frames.push(bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let mut receiver = crate::streaming::Receiver::new(crate::streaming::ReceiverConfig {
+            root: dst.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io: IoOptions {
+                io_uring: true,
+                ..Default::default()
+            },
+        });
+        for frame in &frames {
+            let (msg_type, payload) = crate::streaming::protocol::read_frame(&mut &frame[..])
+                .await
+                .unwrap();
+            receiver.handle_message(msg_type, payload).await.unwrap();
+        }
+        assert_eq!(fs::read(dst.path().join("big.bin")).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn test_sender_direct_io_roundtrip() {
+        // O_DIRECT where the filesystem supports it, buffered fallback otherwise;
+        // the unaligned tail always takes the buffered path
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..(DATA_CHUNK_SIZE * 3 + 1234))
+            .map(|i| (i % 247) as u8)
+            .collect();
+        fs::write(src.path().join("big.bin"), &content).unwrap();
+
+        let io = IoOptions {
+            drop_cache: true,
+            direct_io: true,
+            ..Default::default()
+        };
+        let (tx, rx) = crate::streaming::channel::file_job_channel();
+        let sender = Sender::new(SenderConfig {
+            root: src.path().to_path_buf(),
+            compress: false,
+            tune: None,
+            cancel: CancellationToken::new(),
+            io,
+        });
+        tx.send(GeneratorMessage::File(FileJob {
+            path: Arc::new(PathBuf::from("big.bin")),
+            size: content.len() as u64,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists: false,
+            need_delta: false,
+            checksums: None,
+            resume: None,
+            link_target: None,
+        }))
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut frames = Vec::new();
+        sender
+            .run(rx, |bytes| {
+                frames.push(bytes);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let mut receiver = crate::streaming::Receiver::new(crate::streaming::ReceiverConfig {
+            root: dst.path().to_path_buf(),
+            compress_checksums: false,
+            cancel: CancellationToken::new(),
+            io,
+        });
+        for frame in &frames {
+            let (msg_type, payload) = crate::streaming::protocol::read_frame(&mut &frame[..])
+                .await
+                .unwrap();
+            receiver.handle_message(msg_type, payload).await.unwrap();
+        }
+        assert_eq!(fs::read(dst.path().join("big.bin")).unwrap(), content);
+    }
 }
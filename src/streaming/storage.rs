@@ -0,0 +1,1130 @@
+//! Where the Receiver puts what it receives.
+//!
+//! The Receiver handles the protocol: decoding frames, validating paths,
+//! applying deltas, digests and stats. Everything that touches the
+//! destination goes through a [`Storage`] backend, so object stores, archive
+//! writers or in-memory test stores can be plugged in with
+//! `Receiver::with_storage` instead of forking receiver.rs.
+//!
+//! [`LocalStorage`] is the default: a directory on the local filesystem,
+//! written through temp files that are renamed into place.
+//!
+//! Paths handed to a backend are relative to the destination root and have
+//! already been checked for traversal and absolute components.
+
+use crate::streaming::channel::DATA_CHUNK_SIZE;
+use crate::streaming::io::{self, is_aligned, write_direct, FsyncPolicy, IoOptions, DIRECT_ALIGN};
+use crate::streaming::protocol::{FileEntry, FileFlags, Times, XattrEntry};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::streaming::uring::UringWriter;
+use crate::sync::scanner::FileEntry as ScanEntry;
+use crate::temp_file::TempFileGuard;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use filetime::FileTime;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter, SeekFrom};
+
+/// Write buffer per pending file (matches the sender's DATA chunk size)
+const WRITE_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Extension of the temp files `LocalStorage` writes through
+pub const TEMP_EXTENSION: &str = "sy.tmp";
+
+/// The temp file `path` is written to until it's committed
+pub fn temp_path(path: &Path) -> PathBuf {
+    path.with_extension(TEMP_EXTENSION)
+}
+
+/// Whether `path` names one of our temp files
+pub fn is_temp_path(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(".sy.tmp")
+}
+
+/// Remove the file or symlink at `path`. On Windows a symlink to a
+/// directory is a directory itself, which remove_file refuses
+async fn remove_file(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path).await {
+        #[cfg(windows)]
+        Err(e) if is_dir_symlink(path) => fs::remove_dir(path).await.map_err(|_| e),
+        result => result,
+    }
+}
+
+#[cfg(windows)]
+fn is_dir_symlink(path: &Path) -> bool {
+    use std::os::windows::fs::FileTypeExt;
+    std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink_dir())
+}
+
+/// Destination backend for the Receiver
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Start writing a file; nothing appears at `entry.path` until it's committed
+    async fn create(&self, entry: &FileEntry) -> Result<Box<dyn StorageFile>>;
+
+    /// Continue the partial file an interrupted transfer of `entry` kept
+    /// (see `StorageFile::keep`), returning it with the length it has
+    async fn resume(&self, _entry: &FileEntry) -> Result<(Box<dyn StorageFile>, u64)> {
+        anyhow::bail!("Storage backend doesn't keep partial files")
+    }
+
+    /// Apply mode and mtime to a committed file
+    async fn set_metadata(&self, path: &str, mode: u32, mtime: FileTime) -> Result<()>;
+
+    /// Mode and mtime of the regular file at `path`, without following a
+    /// symlink, for a SET_ATTR to update in place; `None` if something else
+    /// is there
+    async fn stat(&self, _path: &str) -> Result<Option<(u32, FileTime)>> {
+        anyhow::bail!("Storage backend can't update files in place (SET_ATTR)")
+    }
+
+    /// Apply access and (where the platform can set one) creation times to
+    /// a committed file, after its mtime
+    async fn set_times(&self, _path: &str, _times: Times) -> Result<()> {
+        Ok(())
+    }
+
+    /// Apply Windows FILE_ATTRIBUTE bits to a committed file, after its mtime
+    async fn set_attributes(&self, _path: &str, _attrs: u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Apply BSD file flags to a committed file, last: `uchg` locks it
+    async fn set_file_flags(&self, _path: &str, _flags: u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Change the owner and/or group of whatever is at `path`, without
+    /// following a symlink; `None` leaves that id alone
+    async fn set_owner(&self, _path: &str, _uid: Option<u32>, _gid: Option<u32>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Set extended attributes on a committed file or a directory, after
+    /// its owner: chown drops file capabilities
+    async fn set_xattrs(&self, _path: &str, _entries: &[XattrEntry]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Set the POSIX ACL (text form) of a committed file or a directory
+    async fn set_acl(&self, _path: &str, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Create a directory and any missing parents
+    async fn mkdir(&self, path: &str, mode: u32) -> Result<()>;
+
+    /// Replace whatever is at `path` with a symlink to `target`
+    async fn symlink(&self, path: &str, target: &str) -> Result<()>;
+
+    /// Like `create`, but the file is written and committed at `staged`;
+    /// deltas still copy from what's at `entry.path` (`--atomic`)
+    async fn create_staged(
+        &self,
+        _entry: &FileEntry,
+        _staged: &str,
+    ) -> Result<Box<dyn StorageFile>> {
+        anyhow::bail!("Storage backend can't stage files (--atomic)")
+    }
+
+    /// Move the committed file at `from` onto `to`, creating its parents
+    /// (`--atomic` moves staged files into place, `--backup` old versions
+    /// out of the way)
+    async fn rename(&self, _from: &str, _to: &str) -> Result<()> {
+        anyhow::bail!("Storage backend can't move files (--atomic, --backup)")
+    }
+
+    /// Replace whatever file is at `path` with a hard link to the committed
+    /// file at `target`
+    async fn hard_link(&self, _path: &str, _target: &str) -> Result<()> {
+        anyhow::bail!("Storage backend doesn't support hard links")
+    }
+
+    /// Remove a file, or a directory and its contents; missing paths are fine
+    async fn delete(&self, path: &str, is_dir: bool) -> Result<()>;
+
+    /// Durability barrier for `--fsync batch`
+    async fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// What's at the destination, for the Initial Exchange of a backend
+    /// the Receiver can't scan as a local directory; `None` scans the root
+    ///
+    /// Listed files are never hashed, so the peer sends them whole.
+    async fn list(&self) -> Result<Option<Vec<Listed>>> {
+        Ok(None)
+    }
+}
+
+/// An entry a backend listed (see [`Storage::list`])
+pub struct Listed {
+    pub entry: ScanEntry,
+    /// Permission bits, if the backend keeps any; `-p` compares them
+    pub mode: Option<u32>,
+}
+
+/// A file being written; dropping it without `commit` discards it
+#[async_trait]
+pub trait StorageFile: Send + Sync {
+    /// Write full-file data at `offset`
+    async fn write_at(&mut self, offset: u64, data: Bytes) -> Result<()>;
+
+    /// Length of the version being replaced, the source of delta copies
+    async fn original_len(&mut self) -> Result<u64> {
+        anyhow::bail!("Storage backend doesn't keep previous versions for delta transfers")
+    }
+
+    /// Fill `buf` from the version being replaced, starting at `offset`
+    async fn read_original(&mut self, _offset: u64, _buf: &mut [u8]) -> Result<()> {
+        anyhow::bail!("Storage backend doesn't keep previous versions for delta transfers")
+    }
+
+    /// Copy `len` bytes of the version being replaced, from `offset`, to
+    /// `at` in the new one (a run of delta copies)
+    async fn copy_original(&mut self, offset: u64, at: u64, len: u64) -> Result<()> {
+        copy_through_buffer(self, offset, at, len).await
+    }
+
+    /// Fill the file from a local one (in-process transfers), returning
+    /// the bytes copied
+    async fn copy_from(&mut self, source: std::fs::File, len: u64) -> Result<u64> {
+        let mut source = File::from_std(source);
+        let mut copied = 0u64;
+        while copied < len {
+            let mut buf = vec![0u8; (len - copied).min(DATA_CHUNK_SIZE as u64) as usize];
+            let n = source.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            buf.truncate(n);
+            self.write_at(copied, Bytes::from(buf)).await?;
+            copied += n as u64;
+        }
+        Ok(copied)
+    }
+
+    /// Hash of the first `len` bytes as written (see `digest::content_hash`),
+    /// read back before the file is committed
+    async fn content_hash(&mut self, _len: u64) -> Result<u128> {
+        anyhow::bail!("Storage backend can't read back what it wrote")
+    }
+
+    /// Make the file visible at its path, `len` bytes long
+    async fn commit(self: Box<Self>, len: u64) -> Result<()>;
+
+    /// Leave the first `len` bytes where `Storage::resume` finds them, for
+    /// a later transfer to continue; returns false if it's discarded instead
+    async fn keep(self: Box<Self>, _len: u64) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// `StorageFile::copy_original` with `read_original` and `write_at`, a chunk
+/// at a time
+async fn copy_through_buffer<F: StorageFile + ?Sized>(
+    file: &mut F,
+    offset: u64,
+    at: u64,
+    len: u64,
+) -> Result<()> {
+    let mut copied = 0u64;
+    while copied < len {
+        let mut buf = BytesMut::zeroed((len - copied).min(DATA_CHUNK_SIZE as u64) as usize);
+        file.read_original(offset + copied, &mut buf).await?;
+        copied += buf.len() as u64;
+        file.write_at(at + copied - buf.len() as u64, buf.freeze())
+            .await?;
+    }
+    Ok(())
+}
+
+/// A directory on the local filesystem
+pub struct LocalStorage {
+    root: PathBuf,
+    io: IoOptions,
+    use_uring: bool,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf, io: IoOptions) -> Self {
+        let use_uring = crate::streaming::uring::enabled(io.io_uring && !io.direct_io);
+        Self {
+            root,
+            io,
+            use_uring,
+        }
+    }
+
+    /// Where `path` lives under the root; FAT destinations get its FAT-safe name
+    fn full_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        if self.io.fat {
+            self.root.join(crate::fs_util::fat_safe_path(path))
+        } else {
+            self.root.join(path)
+        }
+    }
+
+    /// Start writing `entry` so that it's committed at `at`
+    async fn create_at(&self, entry: &FileEntry, at: &str) -> Result<Box<dyn StorageFile>> {
+        let full_path = self.full_path(at);
+
+        // Ensure parent directory exists
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // With --no-atomic, a file with no previous version is written at its
+        // final path. create_new makes the existence check and creation one
+        // step; if anything is in the way, fall back to a temp file.
+        let direct = if self.io.no_atomic {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&full_path)
+                .await
+            {
+                Ok(file) => Some(file),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => None,
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            None
+        };
+        let in_place = direct.is_some();
+
+        // Temp file, unless writing in place
+        let temp_path = if in_place {
+            full_path.clone()
+        } else {
+            temp_path(&full_path)
+        };
+        let guard = TempFileGuard::new(&temp_path);
+
+        let file = match direct {
+            Some(file) => file,
+            None => {
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&temp_path)
+                    .await?
+            }
+        };
+        let file = if self.io.preallocate {
+            let file = file.into_std().await;
+            let sparse = entry.flags.contains(FileFlags::SPARSE);
+            let size = entry.size;
+            let file = tokio::task::spawn_blocking(move || {
+                io::preallocate(&file, size, sparse).map(|_| file)
+            })
+            .await?
+            .with_context(|| format!("Failed to preallocate {}", temp_path.display()))?;
+            File::from_std(file)
+        } else {
+            file
+        };
+        let output = self.open_output(file, entry.size, &temp_path).await;
+
+        Ok(Box::new(LocalFile {
+            output: Some(output),
+            temp_path,
+            path: full_path,
+            original_path: self.full_path(&*entry.path),
+            size: entry.size,
+            original: None, // Lazily opened on first delta copy
+            original_raw: None,
+            copy_in_kernel: true,
+            in_place,
+            guard: Some(guard),
+            io: self.io,
+        }))
+    }
+
+    /// Use O_DIRECT or io_uring for large files when enabled, falling back to tokio::fs
+    async fn open_output(&self, file: File, size: u64, temp_path: &Path) -> Output {
+        if self.io.direct_io && size >= DIRECT_ALIGN as u64 {
+            match io::open_direct(temp_path, true) {
+                Ok(direct) => {
+                    return Output::Direct {
+                        direct: Arc::new(direct),
+                        buffered: BufferedFile::new(file),
+                    }
+                }
+                Err(e) => io::warn_direct_unsupported(temp_path, &e),
+            }
+        }
+
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if self.use_uring && size > crate::streaming::uring::MIN_FILE_SIZE {
+            let std_file = file.into_std().await;
+            match std_file.try_clone().and_then(UringWriter::new) {
+                Ok(writer) => return Output::Uring(Some(Box::new(writer))),
+                Err(e) => {
+                    tracing::debug!("io_uring setup failed, using tokio::fs: {}", e);
+                    return Output::Tokio(BufferedFile::new(File::from_std(std_file)));
+                }
+            }
+        }
+        let _ = size;
+        Output::Tokio(BufferedFile::new(file))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn create(&self, entry: &FileEntry) -> Result<Box<dyn StorageFile>> {
+        self.create_at(entry, &entry.path).await
+    }
+
+    async fn create_staged(&self, entry: &FileEntry, staged: &str) -> Result<Box<dyn StorageFile>> {
+        self.create_at(entry, staged).await
+    }
+
+    async fn resume(&self, entry: &FileEntry) -> Result<(Box<dyn StorageFile>, u64)> {
+        let full_path = self.full_path(&*entry.path);
+        let temp_path = temp_path(&full_path);
+        let file = OpenOptions::new()
+            .write(true)
+            .open(&temp_path)
+            .await
+            .with_context(|| format!("Failed to reopen {}", temp_path.display()))?;
+        let guard = TempFileGuard::new(&temp_path);
+
+        // Whatever lies past the file's new size is stale
+        let len = file.metadata().await?.len().min(entry.size);
+        file.set_len(len).await?;
+        let output = self.open_output(file, entry.size, &temp_path).await;
+
+        let file = LocalFile {
+            output: Some(output),
+            temp_path,
+            original_path: full_path.clone(),
+            path: full_path,
+            size: entry.size,
+            original: None,
+            original_raw: None,
+            copy_in_kernel: true,
+            in_place: false,
+            guard: Some(guard),
+            io: self.io,
+        };
+        Ok((Box::new(file), len))
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<(u32, FileTime)>> {
+        let meta = fs::symlink_metadata(self.full_path(path)).await?;
+        Ok(meta.is_file().then(|| {
+            (
+                crate::fs_util::permission_bits(&meta),
+                FileTime::from_last_modification_time(&meta),
+            )
+        }))
+    }
+
+    async fn set_metadata(&self, path: &str, mode: u32, mtime: FileTime) -> Result<()> {
+        let full_path = self.full_path(path);
+
+        // FAT has no Unix permissions; chmod there fails or is ignored
+        #[cfg(unix)]
+        if !self.io.fat {
+            use std::os::unix::fs::PermissionsExt;
+            let perms = std::fs::Permissions::from_mode(mode);
+            if let Err(e) = fs::set_permissions(&full_path, perms).await {
+                tracing::warn!(
+                    "Failed to set permissions on {}: {}",
+                    full_path.display(),
+                    e
+                );
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        let _ = tokio::task::spawn_blocking(move || crate::fs_util::set_mtime(&full_path, mtime))
+            .await?;
+        Ok(())
+    }
+
+    async fn set_attributes(&self, path: &str, attrs: u32) -> Result<()> {
+        let full_path = self.full_path(path);
+        let result = tokio::task::spawn_blocking({
+            let full_path = full_path.clone();
+            move || crate::fs_util::set_windows_attributes(&full_path, attrs)
+        })
+        .await?;
+        if let Err(e) = result {
+            tracing::warn!("Failed to set attributes on {}: {}", full_path.display(), e);
+        }
+        Ok(())
+    }
+
+    async fn set_times(&self, path: &str, times: Times) -> Result<()> {
+        let full_path = self.full_path(path);
+        let atime = FileTime::from_unix_time(times.atime, times.atime_nsec);
+        let btime = times
+            .birth()
+            .map(|(secs, nsec)| FileTime::from_unix_time(secs, nsec));
+        let result = tokio::task::spawn_blocking({
+            let full_path = full_path.clone();
+            move || crate::fs_util::set_times(&full_path, atime, btime)
+        })
+        .await?;
+        if let Err(e) = result {
+            tracing::warn!("Failed to set times on {}: {}", full_path.display(), e);
+        }
+        Ok(())
+    }
+
+    async fn set_file_flags(&self, path: &str, flags: u32) -> Result<()> {
+        let full_path = self.full_path(path);
+        let result = tokio::task::spawn_blocking({
+            let full_path = full_path.clone();
+            move || crate::fs_util::set_bsd_flags(&full_path, flags)
+        })
+        .await?;
+        if let Err(e) = result {
+            tracing::warn!("Failed to set file flags on {}: {}", full_path.display(), e);
+        }
+        Ok(())
+    }
+
+    async fn set_owner(&self, path: &str, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        let full_path = self.full_path(path);
+        let result = tokio::task::spawn_blocking({
+            let full_path = full_path.clone();
+            move || crate::fs_util::set_ownership(&full_path, uid, gid)
+        })
+        .await?;
+        if let Err(e) = result {
+            tracing::warn!("Failed to set owner of {}: {}", full_path.display(), e);
+        }
+        Ok(())
+    }
+
+    async fn set_xattrs(&self, path: &str, entries: &[XattrEntry]) -> Result<()> {
+        #[cfg(unix)]
+        {
+            let full_path = self.full_path(path);
+            let entries = entries.to_vec();
+            tokio::task::spawn_blocking(move || {
+                for entry in entries {
+                    if let Err(e) = crate::fs_util::set_xattr(&full_path, &entry.name, &entry.value)
+                    {
+                        tracing::warn!(
+                            "Failed to set xattr {} on {}: {}",
+                            entry.name,
+                            full_path.display(),
+                            e
+                        );
+                    }
+                }
+            })
+            .await?;
+        }
+        #[cfg(not(unix))]
+        let _ = (path, entries);
+        Ok(())
+    }
+
+    async fn set_acl(&self, path: &str, text: &str) -> Result<()> {
+        let full_path = self.full_path(path);
+        let result = tokio::task::spawn_blocking({
+            let full_path = full_path.clone();
+            let text = text.to_string();
+            move || crate::fs_util::set_acl(&full_path, &text)
+        })
+        .await?;
+        if let Err(e) = result {
+            tracing::warn!("Failed to set ACL on {}: {}", full_path.display(), e);
+        }
+        Ok(())
+    }
+
+    async fn mkdir(&self, path: &str, mode: u32) -> Result<()> {
+        let full_path = self.full_path(path);
+        fs::create_dir_all(&full_path).await?;
+
+        // FAT has no Unix permissions; chmod there fails or is ignored
+        #[cfg(unix)]
+        if !self.io.fat {
+            use std::os::unix::fs::PermissionsExt;
+            let perms = std::fs::Permissions::from_mode(mode);
+            if let Err(e) = fs::set_permissions(&full_path, perms).await {
+                tracing::warn!(
+                    "Failed to set permissions on {}: {}",
+                    full_path.display(),
+                    e
+                );
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        Ok(())
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> Result<()> {
+        let full_path = self.full_path(path);
+
+        // Remove existing if any
+        let _ = remove_file(&full_path).await;
+
+        let target = PathBuf::from(target);
+        let links = self.io.links;
+        tokio::task::spawn_blocking(move || {
+            crate::fs_util::create_symlink(&target, &full_path, links)
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn hard_link(&self, path: &str, target: &str) -> Result<()> {
+        let full_path = self.full_path(path);
+        // Linked under the temp name first, so a failure keeps the old file
+        let temp = temp_path(&full_path);
+        let _ = fs::remove_file(&temp).await;
+        fs::hard_link(self.full_path(target), &temp).await?;
+        let renamed = fs::rename(&temp, &full_path).await;
+        // Renaming onto another link to the same file does nothing at all,
+        // leaving the temp link behind
+        let _ = fs::remove_file(&temp).await;
+        renamed?;
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let to = self.full_path(to);
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(self.full_path(from), &to).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str, is_dir: bool) -> Result<()> {
+        let full_path = self.full_path(path);
+        if is_dir {
+            let _ = fs::remove_dir_all(&full_path).await;
+        } else {
+            let _ = remove_file(&full_path).await;
+        }
+        Ok(())
+    }
+
+    async fn sync(&self) -> Result<()> {
+        let root = self.root.clone();
+        tokio::task::spawn_blocking(move || io::sync_filesystem(&root)).await??;
+        Ok(())
+    }
+}
+
+/// A file being written under a `LocalStorage` root
+struct LocalFile {
+    output: Option<Output>,
+    temp_path: PathBuf,
+    /// Final path
+    path: PathBuf,
+    /// The previous version deltas copy from: the final path, unless the
+    /// file is staged (`--atomic`)
+    original_path: PathBuf,
+    /// Size announced in the FILE_ENTRY
+    size: u64,
+    /// Cached original file handle for delta sync (avoids reopening per chunk)
+    original: Option<File>,
+    /// The same, for copies in the kernel
+    original_raw: Option<Arc<std::fs::File>>,
+    /// Delta copies go through `io::copy_file_range_at` until the
+    /// filesystem turns one down
+    copy_in_kernel: bool,
+    /// Written at its final path (`--no-atomic`), so there's nothing to rename
+    in_place: bool,
+    guard: Option<TempFileGuard>,
+    io: IoOptions,
+}
+
+impl LocalFile {
+    async fn original(&mut self) -> Result<&mut File> {
+        if self.original.is_none() {
+            self.original = Some(
+                File::open(&self.original_path)
+                    .await
+                    .context("Failed to open original file for delta application")?,
+            );
+        }
+        Ok(self
+            .original
+            .as_mut()
+            .expect("original file was just opened"))
+    }
+}
+
+#[async_trait]
+impl StorageFile for LocalFile {
+    async fn write_at(&mut self, offset: u64, data: Bytes) -> Result<()> {
+        self.output
+            .as_mut()
+            .expect("output is open until commit")
+            .write_at(offset, data)
+            .await
+    }
+
+    async fn original_len(&mut self) -> Result<u64> {
+        Ok(self.original().await?.metadata().await?.len())
+    }
+
+    async fn read_original(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let original = self.original().await?;
+        original.seek(SeekFrom::Start(offset)).await?;
+        original.read_exact(buf).await?;
+        Ok(())
+    }
+
+    async fn copy_original(&mut self, offset: u64, at: u64, len: u64) -> Result<()> {
+        if self.copy_in_kernel {
+            let original = match &self.original_raw {
+                Some(original) => Arc::clone(original),
+                None => {
+                    let dup = self.original().await?.try_clone().await?;
+                    Arc::clone(self.original_raw.insert(Arc::new(dup.into_std().await)))
+                }
+            };
+            let copied = match self.output.as_mut().expect("output is open until commit") {
+                Output::Tokio(file) | Output::Direct { buffered: file, .. } => {
+                    file.copy_range(original, offset, at, len).await?
+                }
+                #[cfg(all(target_os = "linux", feature = "io-uring"))]
+                Output::Uring(_) => false,
+            };
+            if copied {
+                return Ok(());
+            }
+            self.copy_in_kernel = false;
+        }
+        copy_through_buffer(self, offset, at, len).await
+    }
+
+    async fn copy_from(&mut self, source: std::fs::File, len: u64) -> Result<u64> {
+        // A whole file on APFS can share the source's blocks instead
+        let source = if cfg!(target_os = "macos") && source.metadata()?.len() == len {
+            let temp_path = self.temp_path.clone();
+            let fsync = self.io.fsync_policy() == FsyncPolicy::PerFile;
+            let (cloned, source) = tokio::task::spawn_blocking(move || {
+                (io::clone_file(&source, &temp_path, fsync), source)
+            })
+            .await?;
+            match cloned {
+                Ok(()) => {
+                    // Nothing was written through it, and it's the replaced file
+                    self.output = None;
+                    return Ok(len);
+                }
+                Err(e) => {
+                    tracing::debug!("clonefile failed, copying instead: {}", e);
+                    source
+                }
+            }
+        } else {
+            source
+        };
+
+        let dst = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.temp_path)?;
+        let drop_cache = self.io.drop_cache;
+        let copied = tokio::task::spawn_blocking(move || {
+            let copied = io::copy_range(&source, &dst, len)?;
+            if drop_cache {
+                io::drop_cache(&source, 0, 0);
+            }
+            Ok::<_, std::io::Error>(copied)
+        })
+        .await??;
+        Ok(copied)
+    }
+
+    async fn content_hash(&mut self, len: u64) -> Result<u128> {
+        // Everything buffered has to be on disk first; commit then finds
+        // the output finished
+        let fsync = self.io.fsync_policy() == FsyncPolicy::PerFile;
+        if let Some(output) = self.output.take() {
+            output.finish(fsync, self.io.drop_cache).await?;
+        }
+        let written = if self.in_place {
+            self.path.clone()
+        } else {
+            self.temp_path.clone()
+        };
+        let hash = tokio::task::spawn_blocking(move || {
+            crate::streaming::digest::content_hash(&written, len)
+        })
+        .await??;
+        Ok(hash)
+    }
+
+    async fn commit(mut self: Box<Self>, len: u64) -> Result<()> {
+        let fsync = self.io.fsync_policy() == FsyncPolicy::PerFile;
+        if let Some(output) = self.output.take() {
+            output.finish(fsync, self.io.drop_cache).await?;
+        }
+        if self.io.preallocate && len < self.size {
+            // The source shrank mid-transfer: drop the preallocated tail
+            let file = OpenOptions::new().write(true).open(&self.temp_path).await?;
+            file.set_len(len).await?;
+            if fsync {
+                file.sync_all().await?;
+            }
+        }
+        if self.io.drop_cache {
+            if let Some(original) = self.original.take() {
+                io::drop_cache(&original, 0, 0);
+            }
+        }
+
+        // Windows won't replace a read-only file; the source's attributes
+        // are applied again once the new one is in place
+        #[cfg(windows)]
+        if !self.in_place {
+            if let Ok(meta) = fs::symlink_metadata(&self.path).await {
+                let mut perms = meta.permissions();
+                if perms.readonly() {
+                    perms.set_readonly(false);
+                    let _ = fs::set_permissions(&self.path, perms).await;
+                }
+            }
+        }
+
+        // Nor will macOS replace an immutable (uchg) one; flags are applied
+        // again after the metadata too
+        #[cfg(target_os = "macos")]
+        if !self.in_place {
+            use std::os::darwin::fs::MetadataExt;
+            if let Ok(meta) = fs::symlink_metadata(&self.path).await {
+                if meta.st_flags() != 0 {
+                    let path = self.path.clone();
+                    let _ = tokio::task::spawn_blocking(move || {
+                        crate::fs_util::set_bsd_flags(&path, 0)
+                    })
+                    .await;
+                }
+            }
+        }
+
+        // Move temp file to final destination, copying it when the two
+        // resolve to different filesystems
+        if !self.in_place {
+            if let Err(e) = fs::rename(&self.temp_path, &self.path).await {
+                if !io::is_cross_device(&e) {
+                    return Err(e.into());
+                }
+                tracing::debug!(
+                    "{} is on another filesystem, copying it into place",
+                    self.temp_path.display()
+                );
+                let (temp_path, path) = (self.temp_path.clone(), self.path.clone());
+                tokio::task::spawn_blocking(move || io::move_across_filesystems(&temp_path, &path))
+                    .await?
+                    .with_context(|| {
+                        format!("Failed to move {} into place", self.path.display())
+                    })?;
+            }
+        }
+
+        // Defuse guard after successful rename
+        if let Some(guard) = self.guard.take() {
+            guard.defuse();
+        }
+        Ok(())
+    }
+
+    async fn keep(mut self: Box<Self>, len: u64) -> Result<bool> {
+        // A file written in place has no temp file to come back to
+        if self.in_place {
+            return Ok(false);
+        }
+        let fsync = self.io.fsync_policy() == FsyncPolicy::PerFile;
+        if let Some(output) = self.output.take() {
+            output.finish(fsync, self.io.drop_cache).await?;
+        }
+        // A preallocated tail would pass for data
+        let file = OpenOptions::new().write(true).open(&self.temp_path).await?;
+        file.set_len(len).await?;
+        if fsync {
+            file.sync_all().await?;
+        }
+        if let Some(guard) = self.guard.take() {
+            guard.defuse();
+        }
+        Ok(true)
+    }
+}
+
+/// Buffered writer that only seeks when the write offset jumps
+///
+/// Full transfers arrive as in-order chunks and delta output is written
+/// sequentially, so coalescing writes in a `BufWriter` and skipping redundant
+/// seeks turns many small writes into few large ones.
+struct BufferedFile {
+    writer: BufWriter<File>,
+    /// Current write position
+    pos: u64,
+    /// The file, for copies in the kernel
+    raw: Option<Arc<std::fs::File>>,
+}
+
+impl BufferedFile {
+    fn new(file: File) -> Self {
+        Self {
+            writer: BufWriter::with_capacity(WRITE_BUFFER_SIZE, file),
+            pos: 0,
+            raw: None,
+        }
+    }
+
+    /// Copy `len` bytes of `src` from `src_offset` to `offset` in the kernel
+    /// (see `io::copy_file_range_at`); false, with nothing copied, if the
+    /// filesystem can't
+    async fn copy_range(
+        &mut self,
+        src: Arc<std::fs::File>,
+        src_offset: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<bool> {
+        // What's buffered lands first; the file's own offset stays at `pos`
+        self.writer.flush().await?;
+        let dst = match &self.raw {
+            Some(raw) => Arc::clone(raw),
+            None => {
+                let dup = self.writer.get_ref().try_clone().await?;
+                Arc::clone(self.raw.insert(Arc::new(dup.into_std().await)))
+            }
+        };
+        let copied = tokio::task::spawn_blocking(move || {
+            io::copy_file_range_at(&src, src_offset, &dst, offset, len)
+        })
+        .await?;
+        match copied {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                tracing::debug!("Copying delta blocks through a buffer: {}", e);
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        if self.pos != offset {
+            // Seeking flushes the buffer first
+            self.writer.seek(SeekFrom::Start(offset)).await?;
+        }
+        self.writer.write_all(data).await?;
+        self.pos = offset + data.len() as u64;
+        Ok(())
+    }
+
+    async fn finish(mut self, fsync: bool, drop_cache: bool) -> Result<()> {
+        self.writer.flush().await?;
+        let file = self.writer.into_inner();
+        if fsync {
+            file.sync_all().await?;
+        }
+        if drop_cache {
+            io::drop_cache(&file, 0, 0);
+        }
+        Ok(())
+    }
+}
+
+/// Destination of a pending file's data
+enum Output {
+    Tokio(BufferedFile),
+    /// Always `Some` outside of a drain on the blocking pool
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    Uring(Option<Box<UringWriter>>),
+    /// Aligned chunks go through the O_DIRECT handle, the rest (a file's
+    /// tail, delta output) through the buffered one
+    Direct {
+        direct: Arc<std::fs::File>,
+        buffered: BufferedFile,
+    },
+}
+
+impl Output {
+    /// Write a chunk of data at `offset`
+    async fn write_at(&mut self, offset: u64, data: Bytes) -> Result<()> {
+        match self {
+            Output::Tokio(file) => file.write_at(offset, &data).await?,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Output::Uring(slot) => {
+                let mut writer = slot.take().expect("io_uring writer");
+                if writer.is_full() {
+                    // Wait for completions off the runtime
+                    writer = tokio::task::spawn_blocking(move || writer.drain().map(|_| writer))
+                        .await??;
+                }
+                let result = writer.write_at(offset, data);
+                *slot = Some(writer);
+                result?;
+            }
+            Output::Direct { direct, buffered } => {
+                if is_aligned(offset, data.len()) {
+                    let direct = Arc::clone(direct);
+                    tokio::task::spawn_blocking(move || write_direct(&direct, &data, offset))
+                        .await??;
+                } else {
+                    buffered.write_at(offset, &data).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush (and fsync if asked), then optionally drop the file from the page cache
+    async fn finish(self, fsync: bool, drop_cache: bool) -> Result<()> {
+        match self {
+            Output::Tokio(file) | Output::Direct { buffered: file, .. } => {
+                file.finish(fsync, drop_cache).await?;
+            }
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Output::Uring(slot) => {
+                let mut writer = slot.expect("io_uring writer");
+                tokio::task::spawn_blocking(move || {
+                    if fsync {
+                        writer.sync_all()?;
+                    } else {
+                        writer.drain()?;
+                    }
+                    if drop_cache {
+                        io::drop_cache(writer.file(), 0, 0);
+                    }
+                    Ok::<_, std::io::Error>(())
+                })
+                .await??;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, size: u64) -> FileEntry {
+        FileEntry {
+            path: path.into(),
+            size,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o600,
+            inode: 0,
+            flags: FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buffered_file_coalesces_and_seeks_on_jump() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("out.bin");
+        let file = File::create(&path).await.unwrap();
+        let mut buffered = BufferedFile::new(file);
+
+        // Small in-order chunks stay in the buffer without seeking
+        for i in 0..10u64 {
+            buffered.write_at(i * 3, b"abc").await.unwrap();
+        }
+        assert_eq!(buffered.pos, 30);
+        assert_eq!(buffered.writer.buffer().len(), 30);
+
+        // A jump backwards flushes and seeks
+        buffered.write_at(3, b"XYZ").await.unwrap();
+        assert_eq!(buffered.pos, 6);
+        buffered.finish(false, false).await.unwrap();
+
+        let content = std::fs::read(&path).unwrap();
+        assert_eq!(&content[..9], b"abcXYZabc");
+        assert_eq!(content.len(), 30);
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_copies_original_ranges() {
+        let tmp = TempDir::new().unwrap();
+        let old: Vec<u8> = (0..64 * 1024u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(tmp.path().join("f.bin"), &old).unwrap();
+        let storage = LocalStorage::new(tmp.path().to_path_buf(), IoOptions::default());
+
+        // Written data and copies interleave: aligned blocks, then an
+        // unaligned run after buffered bytes
+        let mut file = storage.create(&entry("f.bin", 20_000)).await.unwrap();
+        file.copy_original(8192, 0, 8192).await.unwrap();
+        file.write_at(8192, Bytes::from_static(b"new"))
+            .await
+            .unwrap();
+        file.copy_original(100, 8195, 11_805).await.unwrap();
+        file.commit(20_000).await.unwrap();
+
+        let mut expected = old[8192..16384].to_vec();
+        expected.extend_from_slice(b"new");
+        expected.extend_from_slice(&old[100..11_905]);
+        assert_eq!(std::fs::read(tmp.path().join("f.bin")).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_commit_replaces_original() {
+        let tmp = TempDir::new().unwrap();
+        std::fs::create_dir(tmp.path().join("dir")).unwrap();
+        std::fs::write(tmp.path().join("dir/f.txt"), "old contents").unwrap();
+        let storage = LocalStorage::new(tmp.path().to_path_buf(), IoOptions::default());
+
+        let mut file = storage.create(&entry("dir/f.txt", 3)).await.unwrap();
+        assert!(tmp.path().join("dir/f.sy.tmp").exists());
+
+        // The previous version stays readable until commit
+        assert_eq!(file.original_len().await.unwrap(), 12);
+        let mut buf = [0u8; 3];
+        file.read_original(4, &mut buf).await.unwrap();
+        assert_eq!(&buf, b"con");
+
+        file.write_at(0, Bytes::from_static(b"new")).await.unwrap();
+        file.commit(3).await.unwrap();
+        storage
+            .set_metadata(
+                "dir/f.txt",
+                0o600,
+                FileTime::from_unix_time(1234567890, 250),
+            )
+            .await
+            .unwrap();
+
+        let path = tmp.path().join("dir/f.txt");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!tmp.path().join("dir/f.sy.tmp").exists());
+        let mtime = FileTime::from_last_modification_time(&path.metadata().unwrap());
+        assert_eq!(
+            (mtime.unix_seconds(), mtime.nanoseconds()),
+            (1234567890, 250)
+        );
+
+        // Dropping an uncommitted file discards it
+        let file = storage.create(&entry("dir/g.txt", 3)).await.unwrap();
+        drop(file);
+        assert!(!tmp.path().join("dir/g.sy.tmp").exists());
+        assert!(!tmp.path().join("dir/g.txt").exists());
+    }
+}
@@ -0,0 +1,221 @@
+//! Transfer log (`--log-file`)
+//!
+//! Every file a sync sends or receives is appended to the log, one JSON
+//! object per line, once it has landed or failed:
+//!
+//! ```text
+//! {"time":"2026-03-01T12:00:00Z","path":"big.iso","size":1048576,"bytes":4096,"delta_ratio":0.0039,"duration_ms":12,"result":"ok"}
+//! {"time":"2026-03-01T12:00:01Z","path":"gone.txt","size":10,"bytes":0,"duration_ms":0,"result":"failed","error":"File vanished"}
+//! ```
+//!
+//! `bytes` is the DATA sent for the file, before compression: all of it,
+//! or for a delta transfer the delta, whose share of the size
+//! `delta_ratio` gives. Once the log would grow past its size limit it's
+//! renamed to `<log>.1`, an older `<log>.1` to `<log>.2` and so on up to
+//! [`LOG_KEEP`], and a new one is started.
+//!
+//! Unlike the audit log, the transfer log is a record rather than a
+//! safeguard: a line that can't be written is warned about and the
+//! transfer goes on.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Size a log grows to before it's rotated (`--log-file-size`)
+pub const DEFAULT_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Rotated logs kept beside the current one
+pub const LOG_KEEP: usize = 5;
+
+/// How one file's transfer went
+#[derive(Debug, Clone, Default)]
+pub struct Transfer<'a> {
+    /// Relative to the sync root
+    pub path: &'a str,
+    /// Size of the file
+    pub size: u64,
+    /// DATA sent or received for it, uncompressed
+    pub bytes: u64,
+    /// Whether it went as a delta against the destination's version
+    pub delta: bool,
+    /// From its FILE_ENTRY to its DATA_END
+    pub duration: Duration,
+    /// Why it failed, if it did
+    pub error: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    path: &'a str,
+    size: u64,
+    bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta_ratio: Option<f64>,
+    duration_ms: u64,
+    result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+#[derive(Debug)]
+struct Sink {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_size: u64,
+}
+
+/// Size-rotated log shared by everything that sends or receives files in
+/// this process; clones share the file
+#[derive(Debug, Clone)]
+pub struct TransferLog {
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl TransferLog {
+    /// Open (or create) the log at `path` for appending, rotating it once
+    /// it would grow past `max_size` bytes
+    pub fn open(path: &Path, max_size: u64) -> Result<Self> {
+        let file = open_append(path)?;
+        let len = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        Ok(Self {
+            sink: Arc::new(Mutex::new(Sink {
+                path: path.to_path_buf(),
+                file,
+                len,
+                max_size,
+            })),
+        })
+    }
+
+    /// Append the line for one file
+    pub fn record(&self, transfer: &Transfer) {
+        let record = Record {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            path: transfer.path,
+            size: transfer.size,
+            bytes: transfer.bytes,
+            delta_ratio: (transfer.delta && transfer.size > 0)
+                .then(|| transfer.bytes as f64 / transfer.size as f64),
+            duration_ms: transfer.duration.as_millis() as u64,
+            result: if transfer.error.is_some() {
+                "failed"
+            } else {
+                "ok"
+            },
+            error: transfer.error,
+        };
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = sink.write(&record) {
+            tracing::warn!("Failed to write to transfer log: {:#}", e);
+        }
+    }
+}
+
+impl Sink {
+    fn write(&mut self, record: &Record) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        if self.len > 0 && self.len + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(&line)?;
+        self.len += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `<log>.N` to `<log>.N+1`, dropping the oldest, and start over
+    fn rotate(&mut self) -> Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        for n in (1..LOG_KEEP).rev() {
+            let from = numbered(n);
+            if from.exists() {
+                std::fs::rename(&from, numbered(n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, numbered(1))
+            .with_context(|| format!("Failed to rotate {}", self.path.display()))?;
+        self.file = open_append(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open transfer log {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn read_lines(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_transfer_log_records_and_rotates() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("transfers.log");
+
+        let log = TransferLog::open(&path, DEFAULT_LOG_SIZE).unwrap();
+        log.record(&Transfer {
+            path: "big.iso",
+            size: 1000,
+            bytes: 100,
+            delta: true,
+            duration: Duration::from_millis(12),
+            error: None,
+        });
+        log.record(&Transfer {
+            path: "gone.txt",
+            size: 10,
+            error: Some("File vanished"),
+            ..Default::default()
+        });
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["path"], "big.iso");
+        assert_eq!(lines[0]["bytes"], 100);
+        assert_eq!(lines[0]["delta_ratio"], 0.1);
+        assert_eq!(lines[0]["duration_ms"], 12);
+        assert_eq!(lines[0]["result"], "ok");
+        assert!(lines[0].get("error").is_none());
+        assert!(lines[1].get("delta_ratio").is_none());
+        assert_eq!(lines[1]["result"], "failed");
+        assert_eq!(lines[1]["error"], "File vanished");
+
+        // Small enough that every line starts a new file
+        let log = TransferLog::open(&path, 10).unwrap();
+        for n in 0..LOG_KEEP + 2 {
+            log.record(&Transfer {
+                path: &n.to_string(),
+                ..Default::default()
+            });
+        }
+        let numbered = |n: usize| tmp.path().join(format!("transfers.log.{}", n));
+        assert_eq!(read_lines(&path)[0]["path"], (LOG_KEEP + 1).to_string());
+        assert_eq!(read_lines(&numbered(1))[0]["path"], LOG_KEEP.to_string());
+        assert_eq!(read_lines(&numbered(LOG_KEEP))[0]["path"], "1");
+        assert!(!numbered(LOG_KEEP + 1).exists());
+    }
+}
@@ -0,0 +1,238 @@
+//! Adaptive DATA chunk size and in-flight window.
+//!
+//! A single chunk size can't suit every link: 256KB frames on a thin,
+//! high-latency pipe mean coarse progress and long head-of-line waits, while a
+//! fast LAN wants bigger frames and more of them queued. The first few
+//! megabytes of a transfer are a probe: the side writing frames to the wire
+//! measures throughput, then sizes chunks to roughly `CHUNK_INTERVAL` of
+//! transfer time and the window to roughly `WINDOW_INTERVAL`.
+//!
+//! The window bounds DATA frames the Sender has produced that haven't been
+//! written yet, so it also caps how much file data sits in memory when the
+//! link is slower than the disk.
+
+use crate::streaming::channel::DATA_CHUNK_SIZE;
+use crate::streaming::protocol::MessageType;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Smallest chunk the tuner picks
+pub const MIN_CHUNK_SIZE: usize = 32 * 1024;
+
+/// Largest chunk the tuner picks (well under the 64MB frame limit)
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Window bounds, in DATA frames
+const MIN_WINDOW: usize = 4;
+const MAX_WINDOW: usize = 64;
+
+/// Window while probing
+const PROBE_WINDOW: usize = 16;
+
+/// The probe ends after this many bytes or this long, whichever comes first
+const PROBE_BYTES: u64 = 8 * 1024 * 1024;
+const PROBE_TIME: Duration = Duration::from_millis(500);
+
+/// Transfer time one chunk should take
+const CHUNK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Transfer time the in-flight window should cover
+const WINDOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Chunk size and window for a measured throughput (bytes/sec)
+pub fn tune_for(throughput: f64) -> (usize, usize) {
+    let chunk = (throughput * CHUNK_INTERVAL.as_secs_f64()) as usize;
+    let chunk = chunk
+        .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+        .next_power_of_two()
+        .min(MAX_CHUNK_SIZE);
+    let window = (throughput * WINDOW_INTERVAL.as_secs_f64()) as usize / chunk;
+    (chunk, window.clamp(MIN_WINDOW, MAX_WINDOW))
+}
+
+#[derive(Default)]
+struct Probe {
+    start: Option<Instant>,
+    bytes: u64,
+    done: bool,
+}
+
+/// Shared between the Sender (which paces DATA frames) and the task that
+/// writes frames to the wire (which measures)
+pub struct AutoTune {
+    chunk_size: AtomicUsize,
+    window: AtomicUsize,
+    in_flight: AtomicUsize,
+    released: Notify,
+    probe: Mutex<Probe>,
+}
+
+impl Default for AutoTune {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AutoTune {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: AtomicUsize::new(DATA_CHUNK_SIZE),
+            window: AtomicUsize::new(PROBE_WINDOW),
+            in_flight: AtomicUsize::new(0),
+            released: Notify::new(),
+            probe: Mutex::new(Probe::default()),
+        }
+    }
+
+    /// Size to read for the next full-transfer DATA chunk
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size.load(Ordering::Relaxed)
+    }
+
+    /// Current in-flight window, in DATA frames
+    pub fn window(&self) -> usize {
+        self.window.load(Ordering::Relaxed)
+    }
+
+    /// Wait for room in the window before producing a DATA frame
+    pub async fn acquire(&self) {
+        loop {
+            // Registered before the check, so a release in between still wakes us
+            let released = self.released.notified();
+            let current = self.in_flight.load(Ordering::Acquire);
+            if current < self.window()
+                && self
+                    .in_flight
+                    .compare_exchange(current, current + 1, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    /// Record a frame written to the wire, freeing its window slot if it was DATA
+    pub fn written(&self, frame: &[u8]) {
+        if frame.get(4) == Some(&(MessageType::Data as u8)) {
+            let _ = self
+                .in_flight
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+            self.released.notify_waiters();
+        }
+        self.measure(frame.len() as u64);
+    }
+
+    fn measure(&self, bytes: u64) {
+        let mut probe = self.probe.lock().unwrap_or_else(|e| e.into_inner());
+        if probe.done {
+            return;
+        }
+        let Some(start) = probe.start else {
+            // The first write only starts the clock
+            probe.start = Some(Instant::now());
+            return;
+        };
+        probe.bytes += bytes;
+        let elapsed = start.elapsed();
+        if probe.bytes < PROBE_BYTES && elapsed < PROBE_TIME {
+            return;
+        }
+
+        probe.done = true;
+        let throughput = probe.bytes as f64 / elapsed.as_secs_f64().max(1e-3);
+        let (chunk, window) = tune_for(throughput);
+        self.chunk_size.store(chunk, Ordering::Relaxed);
+        self.window.store(window, Ordering::Relaxed);
+        self.released.notify_waiters();
+        tracing::debug!(
+            "Auto-tuned transfer: {:.1} MB/s, {} KB chunks, window {}",
+            throughput / (1024.0 * 1024.0),
+            chunk / 1024,
+            window
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::protocol::{Data, DataFlags, Mkdir};
+    use bytes::Bytes;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tune_for_link_speeds() {
+        // Thin pipe (~1 MB/s): smallest chunks, few in flight
+        let (chunk, window) = tune_for(1024.0 * 1024.0);
+        assert_eq!(chunk, MIN_CHUNK_SIZE);
+        assert_eq!(window, 8);
+
+        // ~100 MB/s
+        let (chunk, window) = tune_for(100.0 * 1024.0 * 1024.0);
+        assert_eq!(chunk, 2 * 1024 * 1024);
+        assert_eq!(window, 12);
+
+        // Very fast local link: capped
+        let (chunk, window) = tune_for(10.0 * 1024.0 * 1024.0 * 1024.0);
+        assert_eq!(chunk, MAX_CHUNK_SIZE);
+        assert_eq!(window, MAX_WINDOW);
+
+        // Stalled link still gets a usable window
+        assert_eq!(tune_for(0.0), (MIN_CHUNK_SIZE, MIN_WINDOW));
+    }
+
+    #[tokio::test]
+    async fn test_window_blocks_until_written() {
+        let tune = Arc::new(AutoTune::new());
+        for _ in 0..PROBE_WINDOW {
+            tune.acquire().await;
+        }
+
+        let blocked = {
+            let tune = Arc::clone(&tune);
+            tokio::spawn(async move { tune.acquire().await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!blocked.is_finished());
+
+        // Non-DATA frames don't free a slot
+        tune.written(
+            &Mkdir {
+                path: "d".into(),
+                mode: 0o755,
+                owner: None,
+            }
+            .encode(),
+        );
+        tokio::task::yield_now().await;
+        assert!(!blocked.is_finished());
+
+        let data = Data {
+            path: "f".into(),
+            offset: 0,
+            flags: DataFlags::empty(),
+            data: Bytes::from_static(b"x"),
+        }
+        .encode();
+        tune.written(&data);
+        tokio::time::timeout(Duration::from_secs(5), blocked)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_probe_retunes_after_enough_bytes() {
+        let tune = AutoTune::new();
+        let frame = vec![0u8; 1024 * 1024];
+        for _ in 0..=(PROBE_BYTES / frame.len() as u64) {
+            tune.written(&frame);
+        }
+        // Memory-speed "link": top of the range
+        assert_eq!(tune.chunk_size(), MAX_CHUNK_SIZE);
+        assert_eq!(tune.window(), MAX_WINDOW);
+    }
+}
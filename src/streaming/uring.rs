@@ -0,0 +1,424 @@
+//! io_uring file I/O for the streaming pipeline (`--io-uring`).
+//!
+//! The default path costs a `seek` + `write` per DATA chunk on the receiver
+//! and a `read` per chunk on the sender, each a round trip through tokio's
+//! blocking pool. With io_uring the receiver queues writes on a ring and only
+//! waits when the queue is full or the file is finished, and the sender reads
+//! a batch of consecutive chunks per submission.
+//!
+//! Only available on Linux with the `io-uring` cargo feature. Rings are set up
+//! per file, so callers fall back to tokio::fs whenever the kernel refuses
+//! (too old, disabled via sysctl, blocked by seccomp).
+
+use std::sync::Once;
+
+/// Operations kept in flight per file
+pub const QUEUE_DEPTH: u32 = 32;
+
+/// Files at or below this size go through tokio::fs - a single chunk gains
+/// nothing from batching and doesn't justify setting up a ring
+pub const MIN_FILE_SIZE: u64 = crate::streaming::channel::DATA_CHUNK_SIZE as u64;
+
+/// Whether this build includes the io_uring backend
+pub const COMPILED: bool = cfg!(all(target_os = "linux", feature = "io-uring"));
+
+/// Whether the kernel accepts io_uring (probed once)
+pub fn is_supported() -> bool {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+        static SUPPORTED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+        *SUPPORTED.get_or_init(|| io_uring::IoUring::new(2).is_ok())
+    }
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    {
+        false
+    }
+}
+
+/// Resolve a `--io-uring` request, warning once if it can't be honoured
+pub fn enabled(requested: bool) -> bool {
+    if !requested {
+        return false;
+    }
+    if is_supported() {
+        return true;
+    }
+
+    static WARN: Once = Once::new();
+    WARN.call_once(|| {
+        if COMPILED {
+            tracing::warn!("io_uring is not available on this kernel, using standard file I/O");
+        } else {
+            tracing::warn!("sy was built without the io-uring feature, using standard file I/O");
+        }
+    });
+    false
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use imp::{UringReader, UringWriter};
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod imp {
+    use super::QUEUE_DEPTH;
+    use bytes::Bytes;
+    use io_uring::{opcode, squeue, types, IoUring};
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
+
+    /// user_data for the fsync that closes out a file
+    const FSYNC_TAG: u64 = u64::MAX;
+
+    /// Push an entry, submitting first if the submission queue is full
+    ///
+    /// # Safety
+    /// Buffers referenced by `entry` must stay valid until its completion is reaped.
+    unsafe fn push(ring: &mut IoUring, entry: &squeue::Entry) -> io::Result<()> {
+        if ring.submission().push(entry).is_err() {
+            ring.submit()?;
+            ring.submission()
+                .push(entry)
+                .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        }
+        Ok(())
+    }
+
+    /// Submit and wait for `want` completions, retrying on EINTR
+    fn wait(ring: &mut IoUring, want: usize) -> io::Result<()> {
+        loop {
+            match ring.submit_and_wait(want) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    struct PendingWrite {
+        offset: u64,
+        data: Bytes,
+    }
+
+    /// Queued positional writes to a single file
+    pub struct UringWriter {
+        ring: IoUring,
+        file: File,
+        /// Buffers owned until their write completes, indexed by user_data
+        slots: Vec<Option<PendingWrite>>,
+        free: Vec<usize>,
+    }
+
+    impl UringWriter {
+        pub fn new(file: File) -> io::Result<Self> {
+            let ring = IoUring::new(QUEUE_DEPTH)?;
+            let depth = QUEUE_DEPTH as usize;
+            Ok(Self {
+                ring,
+                file,
+                slots: (0..depth).map(|_| None).collect(),
+                free: (0..depth).rev().collect(),
+            })
+        }
+
+        pub fn file(&self) -> &File {
+            &self.file
+        }
+
+        /// Whether every slot is in flight; `drain` before writing more to avoid blocking
+        pub fn is_full(&self) -> bool {
+            self.free.is_empty()
+        }
+
+        fn in_flight(&self) -> usize {
+            self.slots.len() - self.free.len()
+        }
+
+        /// Queue a write of `data` at `offset` without waiting for it
+        ///
+        /// Blocks only if the queue is full and nothing has completed yet.
+        pub fn write_at(&mut self, offset: u64, data: Bytes) -> io::Result<()> {
+            self.reap()?;
+            while self.free.is_empty() {
+                wait(&mut self.ring, 1)?;
+                self.reap()?;
+            }
+
+            let slot = self.free.pop().expect("free slot");
+            let entry = opcode::Write::new(
+                types::Fd(self.file.as_raw_fd()),
+                data.as_ptr(),
+                data.len() as u32,
+            )
+            .offset(offset)
+            .build()
+            .user_data(slot as u64);
+            self.slots[slot] = Some(PendingWrite { offset, data });
+
+            // SAFETY: the buffer is held in `slots[slot]` until the completion is reaped
+            unsafe { push(&mut self.ring, &entry)? };
+            self.ring.submit()?;
+            Ok(())
+        }
+
+        /// Collect finished writes without waiting
+        fn reap(&mut self) -> io::Result<()> {
+            let done: Vec<(u64, i32)> = self
+                .ring
+                .completion()
+                .map(|cqe| (cqe.user_data(), cqe.result()))
+                .collect();
+
+            let mut result = Ok(());
+            for (user_data, res) in done {
+                let Some(write) = self
+                    .slots
+                    .get_mut(user_data as usize)
+                    .and_then(Option::take)
+                else {
+                    continue;
+                };
+                self.free.push(user_data as usize);
+                if let Err(e) = self.complete(write, res) {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+            result
+        }
+
+        fn complete(&self, write: PendingWrite, res: i32) -> io::Result<()> {
+            if res < 0 {
+                return Err(io::Error::from_raw_os_error(-res));
+            }
+            // Short write: finish the remainder synchronously
+            let written = res as usize;
+            if written < write.data.len() {
+                self.file
+                    .write_all_at(&write.data[written..], write.offset + written as u64)?;
+            }
+            Ok(())
+        }
+
+        /// Wait for all queued writes
+        pub fn drain(&mut self) -> io::Result<()> {
+            let mut result = Ok(());
+            while self.in_flight() > 0 {
+                wait(&mut self.ring, 1)?;
+                if let Err(e) = self.reap() {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+            result
+        }
+
+        /// Wait for all queued writes and fsync the file
+        pub fn sync_all(&mut self) -> io::Result<()> {
+            self.drain()?;
+
+            let entry = opcode::Fsync::new(types::Fd(self.file.as_raw_fd()))
+                .build()
+                .user_data(FSYNC_TAG);
+            // SAFETY: fsync references no buffers
+            unsafe { push(&mut self.ring, &entry)? };
+            wait(&mut self.ring, 1)?;
+
+            let res = self
+                .ring
+                .completion()
+                .find(|cqe| cqe.user_data() == FSYNC_TAG)
+                .map(|cqe| cqe.result())
+                .ok_or_else(|| io::Error::other("missing io_uring fsync completion"))?;
+            if res < 0 {
+                return Err(io::Error::from_raw_os_error(-res));
+            }
+            Ok(())
+        }
+
+        /// Wait for queued writes and hand back the file (for sequential writes)
+        pub fn into_file(mut self) -> io::Result<File> {
+            self.drain()?;
+            self.file.try_clone()
+        }
+    }
+
+    impl Drop for UringWriter {
+        fn drop(&mut self) {
+            if self.drain().is_err() && self.in_flight() > 0 {
+                // The kernel may still write from these buffers; never free them
+                std::mem::forget(std::mem::take(&mut self.slots));
+            }
+        }
+    }
+
+    /// Sequential reader that issues a batch of chunk reads per submission
+    pub struct UringReader {
+        ring: IoUring,
+        file: File,
+        offset: u64,
+        size: u64,
+        chunk_size: usize,
+    }
+
+    impl UringReader {
+        /// Read `file` up to its current size in `chunk_size` pieces
+        pub fn new(file: File, chunk_size: usize) -> io::Result<Self> {
+            let ring = IoUring::new(QUEUE_DEPTH)?;
+            let size = file.metadata()?.len();
+            Ok(Self {
+                ring,
+                file,
+                offset: 0,
+                size,
+                chunk_size,
+            })
+        }
+
+        pub fn file(&self) -> &File {
+            &self.file
+        }
+
+        /// Read the next (up to `QUEUE_DEPTH`) chunks in order; empty at EOF
+        pub fn read_batch(&mut self) -> io::Result<Vec<Bytes>> {
+            let fd = types::Fd(self.file.as_raw_fd());
+            let mut bufs: Vec<Vec<u8>> = Vec::new();
+            let mut offset = self.offset;
+
+            while bufs.len() < QUEUE_DEPTH as usize && offset < self.size {
+                let len = (self.size - offset).min(self.chunk_size as u64) as usize;
+                let mut buf = vec![0u8; len];
+                let entry = opcode::Read::new(fd, buf.as_mut_ptr(), len as u32)
+                    .offset(offset)
+                    .build()
+                    .user_data(bufs.len() as u64);
+                // The Vec's heap buffer doesn't move when `bufs` grows
+                bufs.push(buf);
+                // SAFETY: `bufs` outlives the wait below (or is leaked on failure)
+                if let Err(e) = unsafe { push(&mut self.ring, &entry) } {
+                    std::mem::forget(bufs);
+                    return Err(e);
+                }
+                offset += len as u64;
+            }
+
+            if bufs.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let mut results = vec![None; bufs.len()];
+            let mut remaining = bufs.len();
+            while remaining > 0 {
+                if let Err(e) = wait(&mut self.ring, 1) {
+                    std::mem::forget(bufs);
+                    return Err(e);
+                }
+                for cqe in self.ring.completion() {
+                    if let Some(slot) = results.get_mut(cqe.user_data() as usize) {
+                        *slot = Some(cqe.result());
+                        remaining -= 1;
+                    }
+                }
+            }
+
+            let mut chunks = Vec::with_capacity(bufs.len());
+            for (mut buf, res) in bufs.into_iter().zip(results) {
+                let res = res.expect("all reads completed");
+                if res < 0 {
+                    return Err(io::Error::from_raw_os_error(-res));
+                }
+
+                // Short read: fill the rest synchronously, stopping at EOF
+                let mut filled = res as usize;
+                while filled < buf.len() {
+                    let n = self
+                        .file
+                        .read_at(&mut buf[filled..], self.offset + filled as u64)?;
+                    if n == 0 {
+                        break;
+                    }
+                    filled += n;
+                }
+
+                self.offset += filled as u64;
+                if filled < buf.len() {
+                    // File shrank since open; nothing after this is valid
+                    buf.truncate(filled);
+                    if filled > 0 {
+                        chunks.push(Bytes::from(buf));
+                    }
+                    self.size = self.offset;
+                    break;
+                }
+                chunks.push(Bytes::from(buf));
+            }
+            Ok(chunks)
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux", feature = "io-uring"))]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_writer_out_of_order_chunks() {
+        if !is_supported() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("out.bin");
+        let file = fs::File::create(&path).unwrap();
+
+        let expected: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = UringWriter::new(file).unwrap();
+        // More chunks than the queue holds, written back to front
+        let chunks: Vec<_> = expected.chunks(1000).enumerate().collect();
+        for (i, chunk) in chunks.into_iter().rev() {
+            writer
+                .write_at(i as u64 * 1000, Bytes::copy_from_slice(chunk))
+                .unwrap();
+        }
+        writer.sync_all().unwrap();
+        drop(writer);
+
+        assert_eq!(fs::read(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_reader_batches_whole_file() {
+        if !is_supported() {
+            return;
+        }
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("in.bin");
+        // Spans several batches with a partial last chunk
+        let expected: Vec<u8> = (0..(QUEUE_DEPTH as usize * 3 * 100 + 37))
+            .map(|i| (i % 253) as u8)
+            .collect();
+        fs::write(&path, &expected).unwrap();
+
+        let mut reader = UringReader::new(fs::File::open(&path).unwrap(), 100).unwrap();
+        let mut read = Vec::new();
+        let mut batches = 0;
+        loop {
+            let chunks = reader.read_batch().unwrap();
+            if chunks.is_empty() {
+                break;
+            }
+            batches += 1;
+            for chunk in chunks {
+                read.extend_from_slice(&chunk);
+            }
+        }
+
+        assert_eq!(batches, 4);
+        assert_eq!(read, expected);
+    }
+}
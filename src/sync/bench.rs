@@ -0,0 +1,376 @@
+//! Synthetic transfers over the real protocol (`sy bench`)
+//!
+//! Each workload is a streaming push from a temporary directory to
+//! `<target>/.sy-bench` through `sy --server`, exactly as a sync would run,
+//! so the numbers cover ssh, the protocol and both disks. Comparing the
+//! workloads separates a slow link (the large file is slow) from a slow
+//! round trip (small files are slow) from slow handshakes (the empty push is
+//! slow). The scratch directory is emptied afterwards; the directory itself
+//! is left behind.
+
+use super::server_mode;
+use crate::config::SshSettings;
+use crate::filter::FilterEngine;
+use crate::path::SyncPath;
+use crate::streaming::channel::PhaseTimes;
+use crate::streaming::{tuning, IoOptions, OwnerMap};
+use anyhow::{Context as _, Result};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio_util::sync::CancellationToken;
+
+/// Scratch directory under the target
+pub const SCRATCH_DIR: &str = ".sy-bench";
+
+/// Size of each small file
+const SMALL_FILE_SIZE: usize = 4 * 1024;
+
+/// The delta workload rewrites this much of every `DELTA_STRIDE` bytes
+const DELTA_PATCH: usize = 4 * 1024;
+const DELTA_STRIDE: u64 = 4 * 1024 * 1024;
+
+/// Connecting slower than this points at ssh setup rather than sy
+const SLOW_CONNECT: Duration = Duration::from_secs(2);
+
+/// Below this the large file is limited by the link (100 Mbit/s)
+const SLOW_LINK: f64 = 12_500_000.0;
+
+/// Small files this many times slower than the large file are limited by
+/// round trips
+const LATENCY_BOUND_RATIO: f64 = 10.0;
+
+/// What to transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// Size of the large file
+    pub size: u64,
+    /// Number of small files
+    pub files: usize,
+}
+
+/// One timed push
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    pub name: &'static str,
+    /// Files in the source
+    pub files: usize,
+    /// Bytes in the source
+    pub bytes: u64,
+    /// Bytes that went over the wire
+    pub sent: u64,
+    pub duration: Duration,
+    pub phases: Option<PhaseTimes>,
+}
+
+impl Workload {
+    /// Source bytes per second
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.duration.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Files per second
+    pub fn file_rate(&self) -> f64 {
+        self.files as f64 / self.duration.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Outcome of `sy bench`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    /// An empty push: connecting, starting the server and the handshake
+    pub connect: Duration,
+    pub workloads: Vec<Workload>,
+}
+
+impl BenchReport {
+    fn workload(&self, name: &str) -> Option<&Workload> {
+        self.workloads.iter().find(|w| w.name == name)
+    }
+
+    /// DATA chunk size and window the measured throughput calls for
+    pub fn tuning(&self) -> Option<(usize, usize)> {
+        self.workload("large file")
+            .map(|w| tuning::tune_for(w.throughput()))
+    }
+
+    /// What the numbers suggest, most important first
+    pub fn suggestions(&self) -> Vec<String> {
+        let mut suggestions = Vec::new();
+        if self.connect > SLOW_CONNECT {
+            suggestions.push(
+                "Connecting is slow: enable ssh connection sharing (ControlMaster) or check DNS"
+                    .to_string(),
+            );
+        }
+        let large = self.workload("large file");
+        if let Some(large) = large {
+            if large.throughput() < SLOW_LINK {
+                suggestions.push(
+                    "Throughput is network-bound: --compress helps with compressible data"
+                        .to_string(),
+                );
+            }
+        }
+        if let (Some(large), Some(small)) = (large, self.workload("small files")) {
+            if small.throughput() * LATENCY_BOUND_RATIO < large.throughput() {
+                suggestions.push(
+                    "Small files are latency-bound: --dest-manifest skips the destination scan \
+                     on repeat pushes"
+                        .to_string(),
+                );
+            }
+        }
+        suggestions
+    }
+}
+
+/// `target` with the scratch directory as its path
+fn scratch(target: &SyncPath) -> SyncPath {
+    let mut scratch = target.clone();
+    match &mut scratch {
+        SyncPath::Local { path, .. } | SyncPath::Remote { path, .. } => {
+            *path = path.join(SCRATCH_DIR)
+        }
+        SyncPath::S3 { .. } | SyncPath::Gcs { .. } => {}
+    }
+    scratch
+}
+
+/// Deterministic incompressible bytes
+fn fill(seed: &str, buf: &mut [u8]) {
+    blake3::Hasher::new()
+        .update(seed.as_bytes())
+        .finalize_xof()
+        .fill(buf);
+}
+
+/// Write `size` bytes of pseudo-random data to `path`
+fn write_random(path: &Path, seed: &str, size: u64) -> Result<()> {
+    use std::io::Write as _;
+    let mut reader = blake3::Hasher::new().update(seed.as_bytes()).finalize_xof();
+    let mut file = std::io::BufWriter::new(
+        std::fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?,
+    );
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut left = size;
+    while left > 0 {
+        let n = left.min(buf.len() as u64) as usize;
+        reader.fill(&mut buf[..n]);
+        file.write_all(&buf[..n])?;
+        left -= n as u64;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Rewrite a few KB of every few MB of `path`, as an edited file would be
+fn patch(path: &Path, size: u64) -> Result<()> {
+    use std::io::{Seek as _, SeekFrom, Write as _};
+    let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+    let mut buf = vec![0u8; DELTA_PATCH];
+    let mut offset = 0;
+    while offset + DELTA_PATCH as u64 <= size {
+        fill(&format!("patch {}", offset), &mut buf);
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&buf)?;
+        offset += DELTA_STRIDE;
+    }
+    // Make sure the size+mtime check sees the change
+    let mtime =
+        filetime::FileTime::from_system_time(std::time::SystemTime::now() + Duration::from_secs(2));
+    filetime::set_file_mtime(path, mtime)?;
+    Ok(())
+}
+
+/// Push `source` to the scratch directory and time it
+async fn push(
+    name: &'static str,
+    source: &Path,
+    dest: &SyncPath,
+    io: &IoOptions,
+    ssh: &SshSettings,
+    cancel: &CancellationToken,
+    (files, bytes): (usize, u64),
+) -> Result<Workload> {
+    let start = Instant::now();
+    let stats = server_mode::sync_push(
+        source,
+        dest,
+        true,
+        false,
+        *io,
+        FilterEngine::new(),
+        OwnerMap::default(),
+        None,
+        None,
+        ssh,
+        false,
+        None,
+        None,
+        cancel.clone(),
+    )
+    .await
+    .with_context(|| format!("Benchmark '{}' failed", name))?;
+    if stats.cancelled {
+        anyhow::bail!("Benchmark interrupted");
+    }
+    if let Some(error) = stats.errors.first() {
+        anyhow::bail!(
+            "Benchmark '{}' failed: {}: {}",
+            name,
+            error.path.display(),
+            error.error
+        );
+    }
+    Ok(Workload {
+        name,
+        files,
+        bytes,
+        sent: stats.bytes_transferred,
+        duration: start.elapsed(),
+        phases: stats.phases,
+    })
+}
+
+/// Run every workload against `target`
+pub async fn run(
+    target: &SyncPath,
+    options: BenchOptions,
+    io: IoOptions,
+    ssh: &SshSettings,
+    cancel: CancellationToken,
+) -> Result<BenchReport> {
+    if target.is_s3() || target.is_gcs() {
+        anyhow::bail!("sy bench needs an ssh or local target, not cloud storage");
+    }
+    let dest = scratch(target);
+    let local = TempDir::new().context("Failed to create benchmark directory")?;
+    let source = local.path();
+
+    // Also clears whatever an interrupted run left in the scratch directory
+    let connect = push("connect", source, &dest, &io, ssh, &cancel, (0, 0))
+        .await?
+        .duration;
+
+    let mut workloads = Vec::new();
+    let result = async {
+        let large = source.join("large.bin");
+        write_random(&large, "large", options.size)?;
+        workloads.push(
+            push(
+                "large file",
+                source,
+                &dest,
+                &io,
+                ssh,
+                &cancel,
+                (1, options.size),
+            )
+            .await?,
+        );
+
+        patch(&large, options.size)?;
+        workloads.push(
+            push(
+                "delta update",
+                source,
+                &dest,
+                &io,
+                ssh,
+                &cancel,
+                (1, options.size),
+            )
+            .await?,
+        );
+
+        std::fs::remove_file(&large)?;
+        let mut buf = vec![0u8; SMALL_FILE_SIZE];
+        for i in 0..options.files {
+            // 100 files per directory, like a source tree
+            let dir = source.join(format!("d{:04}", i / 100));
+            if i % 100 == 0 {
+                std::fs::create_dir(&dir)?;
+            }
+            fill(&format!("small {}", i), &mut buf);
+            std::fs::write(dir.join(format!("f{:06}", i)), &buf)?;
+        }
+        workloads.push(
+            push(
+                "small files",
+                source,
+                &dest,
+                &io,
+                ssh,
+                &cancel,
+                (options.files, (options.files * SMALL_FILE_SIZE) as u64),
+            )
+            .await?,
+        );
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+
+    // Empty the scratch directory even when a workload failed
+    let empty = TempDir::new()?;
+    let cleanup = push("cleanup", empty.path(), &dest, &io, ssh, &cancel, (0, 0)).await;
+    result?;
+    cleanup?;
+
+    Ok(BenchReport { connect, workloads })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workload(name: &'static str, files: usize, bytes: u64, secs: u64) -> Workload {
+        Workload {
+            name,
+            files,
+            bytes,
+            sent: bytes,
+            duration: Duration::from_secs(secs),
+            phases: None,
+        }
+    }
+
+    #[test]
+    fn test_suggestions_follow_the_bottleneck() {
+        // Fast link, quick connect, small files keep up: nothing to suggest
+        let mut report = BenchReport {
+            connect: Duration::from_millis(300),
+            workloads: vec![
+                workload("large file", 1, 1_000_000_000, 4),
+                workload("small files", 10_000, 40_000_000, 1),
+            ],
+        };
+        assert!(report.suggestions().is_empty());
+        let (chunk, window) = report.tuning().unwrap();
+        assert_eq!(chunk, tuning::MAX_CHUNK_SIZE);
+        assert!(window >= 4);
+
+        // Small files crawl while the large file flies
+        report.workloads[1] = workload("small files", 10_000, 40_000_000, 60);
+        let suggestions = report.suggestions();
+        assert_eq!(suggestions.len(), 1);
+        assert!(suggestions[0].contains("latency-bound"));
+
+        // Slow handshake and a slow link
+        report.connect = Duration::from_secs(5);
+        report.workloads[0] = workload("large file", 1, 100_000_000, 100);
+        report.workloads[1] = workload("small files", 10_000, 40_000_000, 40);
+        let suggestions = report.suggestions();
+        assert_eq!(suggestions.len(), 2);
+        assert!(suggestions[0].contains("ControlMaster"));
+        assert!(suggestions[1].contains("network-bound"));
+    }
+
+    #[test]
+    fn test_scratch_is_under_target() {
+        let scratch = scratch(&SyncPath::parse("backup:/srv"));
+        assert_eq!(scratch.path(), Path::new("/srv/.sy-bench"));
+        assert!(scratch.is_remote());
+    }
+}
@@ -33,7 +33,7 @@ pub struct ChecksumDatabase {
 #[allow(dead_code)] // Integration with SyncEngine pending
 impl ChecksumDatabase {
     /// Database directory name in destination directory
-    const DB_DIR: &'static str = ".sy-checksums";
+    pub const DB_DIR: &'static str = ".sy-checksums";
 
     /// Partition name for checksums
     const PARTITION_NAME: &'static str = "checksums";
@@ -131,6 +131,39 @@ impl ChecksumDatabase {
         Ok(Some(checksum))
     }
 
+    /// Get the stored entry for a path regardless of current file metadata
+    ///
+    /// Unlike `get_checksum`, this doesn't treat a metadata change as a miss,
+    /// so callers can tell "file changed" apart from "content changed".
+    pub fn lookup(&self, path: &Path) -> Result<Option<StoredChecksum>> {
+        let key = Self::path_to_key(path);
+        let value = match self.partition.get(&key)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let entry: ChecksumEntry = bincode::deserialize(&value).map_err(|e| {
+            crate::error::SyncError::Database(format!(
+                "Failed to deserialize checksum entry for {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        let checksum = match entry.checksum_type.as_str() {
+            "fast" => Checksum::Fast(entry.checksum),
+            "cryptographic" => Checksum::Cryptographic(entry.checksum),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(StoredChecksum {
+            mtime_secs: entry.mtime_secs,
+            mtime_nanos: entry.mtime_nanos,
+            size: entry.size,
+            checksum,
+        }))
+    }
+
     /// Store checksum after successful transfer
     pub fn store_checksum(
         &self,
@@ -258,6 +291,22 @@ impl ChecksumDatabase {
     }
 }
 
+/// A checksum entry as stored in the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredChecksum {
+    pub mtime_secs: i64,
+    pub mtime_nanos: i32,
+    pub size: u64,
+    pub checksum: Checksum,
+}
+
+impl StoredChecksum {
+    /// Whether the entry was recorded for a file with this mtime and size
+    pub fn matches_metadata(&self, mtime: SystemTime, size: u64) -> bool {
+        (self.mtime_secs, self.mtime_nanos) == system_time_to_parts(mtime) && self.size == size
+    }
+}
+
 /// Database statistics
 #[derive(Debug, Clone)]
 #[allow(dead_code)] // Integration with SyncEngine pending
@@ -404,6 +453,23 @@ mod tests {
         assert_eq!(db.stats().unwrap().total_entries, 2);
     }
 
+    #[test]
+    fn test_lookup_ignores_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = ChecksumDatabase::open(temp_dir.path()).unwrap();
+
+        let path = PathBuf::from("test/file.txt");
+        let mtime = SystemTime::now();
+        let checksum = Checksum::Fast(vec![9, 8, 7, 6]);
+        db.store_checksum(&path, mtime, 1024, &checksum).unwrap();
+
+        let stored = db.lookup(&path).unwrap().unwrap();
+        assert_eq!(stored.checksum, checksum);
+        assert!(stored.matches_metadata(mtime, 1024));
+        assert!(!stored.matches_metadata(mtime, 2048));
+        assert!(db.lookup(Path::new("other.txt")).unwrap().is_none());
+    }
+
     #[test]
     fn test_cryptographic_checksum_storage() {
         let temp_dir = TempDir::new().unwrap();
@@ -0,0 +1,559 @@
+//! Content-addressed snapshot store (`sy push --dedup-store`, `sy restore`)
+//!
+//! Instead of mirroring the source, each push records a snapshot: file
+//! contents are cut into content-defined chunks, each stored once under its
+//! BLAKE3 hash, and a JSON tree manifest lists every entry with the chunks
+//! that make it up. A chunk shared by two files, or by two snapshots, is
+//! stored once, so keeping many snapshots of a slowly changing tree costs
+//! little more than keeping one.
+//!
+//! Layout of a store:
+//!
+//! ```text
+//! DIR/chunks/ab/abcdef...   zstd-compressed chunk, named by the BLAKE3 of its plain bytes
+//! DIR/snapshots/<id>.json   tree manifest, <id> is the UTC start time
+//! ```
+//!
+//! Chunk boundaries come from the rsync rolling checksum over a small window,
+//! so an insertion only changes the chunks around it. Files whose size and
+//! mtime match the previous snapshot reuse its chunk list without being read.
+
+use crate::compress::{self, Compression};
+use crate::delta::Adler32;
+use crate::manifest::{manifest_path, unix_mtime};
+use crate::sync::scanner::Scanner;
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Current snapshot format version
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// No chunk boundary before this many bytes
+const MIN_CHUNK: usize = 256 * 1024;
+
+/// Forced boundary after this many bytes
+const MAX_CHUNK: usize = 4 * 1024 * 1024;
+
+/// Bytes the rolling checksum covers
+const WINDOW: usize = 64;
+
+/// A boundary is one position in 2^AVG_BITS past `MIN_CHUNK` (~1MB chunks)
+const AVG_BITS: u32 = 20;
+
+const READ_SIZE: usize = 1024 * 1024;
+
+/// What an entry in a snapshot is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// One entry of a snapshot's tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// Path relative to the source, always `/`-separated
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: i64,
+    #[serde(default)]
+    pub mtime_nsec: u32,
+    /// Unix permission bits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Target of a symlink
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Hex BLAKE3 of each chunk of a file, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<String>,
+}
+
+/// A tree manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub id: String,
+    /// Creation time (RFC 3339)
+    pub created: String,
+    pub source: String,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+/// Outcome of a push into a store
+#[derive(Debug, Default)]
+pub struct PushReport {
+    pub snapshot: String,
+    pub files: usize,
+    /// Files reused from the previous snapshot by size and mtime
+    pub files_unchanged: usize,
+    pub bytes_read: u64,
+    pub chunks_new: usize,
+    /// Chunks already in the store
+    pub chunks_reused: usize,
+    /// Compressed bytes added to the store
+    pub bytes_stored: u64,
+    pub duration: Duration,
+}
+
+/// Outcome of a restore
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    pub snapshot: String,
+    pub files: usize,
+    pub dirs: usize,
+    pub symlinks: usize,
+    pub bytes_written: u64,
+    pub duration: Duration,
+}
+
+/// Cut `data` at the first content-defined boundary
+///
+/// `data` must hold at least `MAX_CHUNK` bytes unless it is the end of the
+/// input, so boundaries don't depend on how the input was read.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK);
+    let mut hash = Adler32::new(WINDOW);
+    hash.update_block(&data[MIN_CHUNK - WINDOW..MIN_CHUNK]);
+    for i in MIN_CHUNK..end {
+        // Adler-32's low bits are weak; mix before testing
+        if hash.digest().wrapping_mul(0x9E37_79B1) >> (32 - AVG_BITS) == 0 {
+            return i;
+        }
+        hash.roll(data[i - WINDOW], data[i]);
+    }
+    end
+}
+
+/// Split everything `reader` yields into content-defined chunks
+fn for_each_chunk(mut reader: impl Read, mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    let mut buf = Vec::with_capacity(MAX_CHUNK + READ_SIZE);
+    let mut eof = false;
+    loop {
+        while !eof && buf.len() < MAX_CHUNK {
+            let len = buf.len();
+            buf.resize(len + READ_SIZE, 0);
+            let n = reader.read(&mut buf[len..])?;
+            buf.truncate(len + n);
+            eof = n == 0;
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let len = cut_point(&buf);
+        f(&buf[..len])?;
+        buf.drain(..len);
+    }
+}
+
+/// A store directory
+pub struct Store {
+    root: PathBuf,
+}
+
+impl Store {
+    /// Open the store at `root`, creating it if needed
+    pub fn open(root: &Path) -> Result<Self> {
+        for dir in ["chunks", "snapshots"] {
+            std::fs::create_dir_all(root.join(dir))
+                .with_context(|| format!("Failed to create store {}", root.display()))?;
+        }
+        Ok(Self {
+            root: root.to_path_buf(),
+        })
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.root.join("chunks").join(&hash[..2]).join(hash)
+    }
+
+    fn snapshot_path(&self, id: &str) -> PathBuf {
+        self.root.join("snapshots").join(format!("{}.json", id))
+    }
+
+    /// Snapshot ids, oldest first
+    pub fn snapshots(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(self.root.join("snapshots"))? {
+            let name = entry?.file_name();
+            if let Some(id) = name.to_string_lossy().strip_suffix(".json") {
+                ids.push(id.to_string());
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Load snapshot `id`, or the latest one
+    pub fn snapshot(&self, id: Option<&str>) -> Result<Snapshot> {
+        let id = match id {
+            Some(id) => id.to_string(),
+            None => self
+                .snapshots()?
+                .pop()
+                .with_context(|| format!("No snapshots in {}", self.root.display()))?,
+        };
+        let path = self.snapshot_path(&id);
+        let json = std::fs::read(&path)
+            .with_context(|| format!("No snapshot '{}' in {}", id, self.root.display()))?;
+        serde_json::from_slice(&json)
+            .with_context(|| format!("Corrupt snapshot {}", path.display()))
+    }
+
+    /// Store one chunk unless it's already there; returns its hash and the
+    /// compressed bytes written (0 if it was there)
+    fn put_chunk(&self, data: &[u8]) -> Result<(String, u64)> {
+        let hash = blake3::hash(data).to_hex().to_string();
+        let path = self.chunk_path(&hash);
+        if path.exists() {
+            return Ok((hash, 0));
+        }
+        let dir = path.parent().expect("chunk path has a parent");
+        std::fs::create_dir_all(dir)?;
+        let compressed = compress::compress(data, Compression::Zstd)?;
+        // Write aside and rename, so a chunk that exists is complete
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        tmp.write_all(&compressed)?;
+        tmp.persist(&path)?;
+        Ok((hash, compressed.len() as u64))
+    }
+
+    /// Read chunk `hash`, checking its contents against the hash
+    fn get_chunk(&self, hash: &str) -> Result<Vec<u8>> {
+        let path = self.chunk_path(hash);
+        let compressed = std::fs::read(&path).with_context(|| format!("Missing chunk {}", hash))?;
+        let data = compress::decompress(&compressed, Compression::Zstd)
+            .with_context(|| format!("Corrupt chunk {}", hash))?;
+        if blake3::hash(&data).to_hex().as_str() != hash {
+            anyhow::bail!("Corrupt chunk {}: contents don't match its hash", hash);
+        }
+        Ok(data)
+    }
+
+    /// Record a snapshot of `source`
+    pub fn push(&self, source: &Path) -> Result<PushReport> {
+        let start = Instant::now();
+        if !source.is_dir() {
+            anyhow::bail!("Source {} is not a directory", source.display());
+        }
+        let previous: HashMap<String, SnapshotEntry> = match self.snapshots()?.last() {
+            Some(id) => self
+                .snapshot(Some(id))?
+                .entries
+                .into_iter()
+                .filter(|e| e.kind == EntryKind::File)
+                .map(|e| (e.path.clone(), e))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let mut report = PushReport::default();
+        let mut entries = Vec::new();
+        for entry in Scanner::new(source).scan()? {
+            let path = manifest_path(&entry.relative_path);
+            let mtime_nsec = entry
+                .modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.subsec_nanos());
+            let mut item = SnapshotEntry {
+                path,
+                kind: EntryKind::File,
+                size: 0,
+                mtime: unix_mtime(entry.modified),
+                mtime_nsec,
+                mode: mode(&entry.path),
+                target: None,
+                chunks: Vec::new(),
+            };
+            if entry.is_dir {
+                item.kind = EntryKind::Dir;
+            } else if entry.is_symlink {
+                item.kind = EntryKind::Symlink;
+                item.target = entry
+                    .symlink_target
+                    .as_ref()
+                    .map(|t| t.to_string_lossy().into_owned());
+            } else {
+                item.size = entry.size;
+                report.files += 1;
+                match previous.get(&item.path) {
+                    Some(prev)
+                        if prev.size == item.size
+                            && prev.mtime == item.mtime
+                            && prev.mtime_nsec == item.mtime_nsec =>
+                    {
+                        item.chunks = prev.chunks.clone();
+                        report.files_unchanged += 1;
+                    }
+                    _ => {
+                        let file = File::open(entry.path.as_path())
+                            .with_context(|| format!("Failed to read {}", entry.path.display()))?;
+                        for_each_chunk(file, |data| {
+                            let (hash, stored) = self.put_chunk(data)?;
+                            if stored > 0 {
+                                report.chunks_new += 1;
+                                report.bytes_stored += stored;
+                            } else {
+                                report.chunks_reused += 1;
+                            }
+                            report.bytes_read += data.len() as u64;
+                            item.chunks.push(hash);
+                            Ok(())
+                        })?;
+                    }
+                }
+            }
+            entries.push(item);
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let now = SystemTime::now();
+        let created = chrono::DateTime::<chrono::Utc>::from(now);
+        let mut id = created.format("%Y%m%dT%H%M%SZ").to_string();
+        // Two pushes in the same second
+        let base = id.clone();
+        let mut n = 1;
+        while self.snapshot_path(&id).exists() {
+            n += 1;
+            id = format!("{}-{}", base, n);
+        }
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            id: id.clone(),
+            created: created.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            source: source.display().to_string(),
+            entries,
+        };
+        let mut tmp = tempfile::NamedTempFile::new_in(self.root.join("snapshots"))?;
+        serde_json::to_writer_pretty(&mut tmp, &snapshot)?;
+        tmp.persist(self.snapshot_path(&id))?;
+
+        report.snapshot = id;
+        report.duration = start.elapsed();
+        Ok(report)
+    }
+
+    /// Write snapshot `id` (or the latest) out as a normal tree at `target`
+    pub fn restore(&self, id: Option<&str>, target: &Path) -> Result<RestoreReport> {
+        let start = Instant::now();
+        let snapshot = self.snapshot(id)?;
+        let mut report = RestoreReport {
+            snapshot: snapshot.id.clone(),
+            ..Default::default()
+        };
+        std::fs::create_dir_all(target)
+            .with_context(|| format!("Failed to create {}", target.display()))?;
+
+        // Entries are sorted, so a directory comes before what it holds
+        for entry in &snapshot.entries {
+            let relative = Path::new(&entry.path);
+            if relative
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                anyhow::bail!("Refusing to restore unsafe path '{}'", entry.path);
+            }
+            let path = target.join(relative);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            match entry.kind {
+                EntryKind::Dir => {
+                    std::fs::create_dir_all(&path)?;
+                    report.dirs += 1;
+                }
+                EntryKind::Symlink => {
+                    let target = entry.target.as_deref().unwrap_or_default();
+                    restore_symlink(target, &path)?;
+                    report.symlinks += 1;
+                }
+                EntryKind::File => {
+                    let mut file = std::io::BufWriter::new(
+                        File::create(&path)
+                            .with_context(|| format!("Failed to create {}", path.display()))?,
+                    );
+                    for hash in &entry.chunks {
+                        let data = self.get_chunk(hash)?;
+                        file.write_all(&data)?;
+                        report.bytes_written += data.len() as u64;
+                    }
+                    file.flush()?;
+                    report.files += 1;
+                }
+            }
+        }
+
+        // Modes and mtimes last, children first: filling a directory bumps
+        // its mtime, and a read-only directory can't be filled
+        for entry in snapshot.entries.iter().rev() {
+            if entry.kind == EntryKind::Symlink {
+                continue;
+            }
+            if let Some(mode) = entry.mode {
+                set_mode(&target.join(&entry.path), mode)?;
+            }
+            let mtime = filetime::FileTime::from_unix_time(entry.mtime, entry.mtime_nsec);
+            filetime::set_file_mtime(target.join(&entry.path), mtime)?;
+        }
+
+        report.duration = start.elapsed();
+        Ok(report)
+    }
+}
+
+#[cfg(unix)]
+fn mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::symlink_metadata(path)
+        .ok()
+        .map(|m| m.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_path: &Path) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restore_symlink(target: &str, path: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, path)
+        .with_context(|| format!("Failed to create symlink {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn restore_symlink(_target: &str, path: &Path) -> Result<()> {
+    tracing::warn!("Skipping symlink {} (not supported here)", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn random(seed: &str, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        blake3::Hasher::new()
+            .update(seed.as_bytes())
+            .finalize_xof()
+            .fill(&mut data);
+        data
+    }
+
+    fn chunk_hashes(data: &[u8]) -> Vec<blake3::Hash> {
+        let mut hashes = Vec::new();
+        for_each_chunk(data, |chunk| {
+            assert!(chunk.len() <= MAX_CHUNK);
+            hashes.push(blake3::hash(chunk));
+            Ok(())
+        })
+        .unwrap();
+        hashes
+    }
+
+    #[test]
+    fn test_chunk_boundaries_survive_an_insertion() {
+        let data = random("data", 16 * 1024 * 1024);
+        let chunks = chunk_hashes(&data);
+        assert!(chunks.len() >= 4);
+
+        // Insert a few bytes near the start: only the chunks around the
+        // insertion change
+        let mut edited = data[..1000].to_vec();
+        edited.extend_from_slice(b"inserted");
+        edited.extend_from_slice(&data[1000..]);
+        let edited_chunks = chunk_hashes(&edited);
+        assert_ne!(edited_chunks[0], chunks[0]);
+        assert!(edited_chunks[2..].iter().all(|c| chunks.contains(c)));
+    }
+
+    #[test]
+    fn test_push_dedups_and_restore_round_trips() {
+        let source = TempDir::new().unwrap();
+        let store_dir = TempDir::new().unwrap();
+        let big = random("big", 3 * 1024 * 1024);
+        std::fs::create_dir(source.path().join("sub")).unwrap();
+        std::fs::write(source.path().join("a.bin"), &big).unwrap();
+        std::fs::write(source.path().join("sub/copy.bin"), &big).unwrap();
+        std::fs::write(source.path().join("sub/small.txt"), b"hello").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("small.txt", source.path().join("sub/link")).unwrap();
+
+        let store = Store::open(store_dir.path()).unwrap();
+        let first = store.push(source.path()).unwrap();
+        assert_eq!(first.files, 3);
+        assert!(first.chunks_reused > 0, "identical files share chunks");
+
+        // Nothing changed: the second snapshot reads nothing
+        let second = store.push(source.path()).unwrap();
+        assert_eq!(second.files_unchanged, 3);
+        assert_eq!(second.bytes_read, 0);
+        assert_ne!(first.snapshot, second.snapshot);
+        assert_eq!(store.snapshots().unwrap().len(), 2);
+
+        let target = TempDir::new().unwrap();
+        let report = store.restore(Some(&first.snapshot), target.path()).unwrap();
+        assert_eq!(report.files, 3);
+        assert_eq!(
+            std::fs::read(target.path().join("sub/copy.bin")).unwrap(),
+            big
+        );
+        assert_eq!(
+            std::fs::read(target.path().join("sub/small.txt")).unwrap(),
+            b"hello"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(target.path().join("sub/link")).unwrap(),
+            Path::new("small.txt")
+        );
+        let mtime = |root: &Path| {
+            std::fs::metadata(root.join("a.bin"))
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+        assert_eq!(mtime(target.path()), mtime(source.path()));
+    }
+
+    #[test]
+    fn test_restore_detects_corrupt_chunks() {
+        let source = TempDir::new().unwrap();
+        let store_dir = TempDir::new().unwrap();
+        std::fs::write(source.path().join("file"), b"precious").unwrap();
+        let store = Store::open(store_dir.path()).unwrap();
+        store.push(source.path()).unwrap();
+
+        let hash = blake3::hash(b"precious").to_hex().to_string();
+        let corrupt = compress::compress(b"tampered", Compression::Zstd).unwrap();
+        std::fs::write(store.chunk_path(&hash), corrupt).unwrap();
+
+        let target = TempDir::new().unwrap();
+        let err = store.restore(None, target.path()).unwrap_err();
+        assert!(err.to_string().contains("Corrupt chunk"));
+    }
+}
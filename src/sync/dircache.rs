@@ -17,6 +17,14 @@ pub struct CachedFile {
     pub modified: SystemTime,
     /// Whether this is a directory
     pub is_dir: bool,
+    /// Whether this is a symlink, and where it points
+    pub is_symlink: bool,
+    pub symlink_target: Option<PathBuf>,
+    pub is_sparse: bool,
+    pub allocated_size: u64,
+    /// Inode and link count, for hardlink detection
+    pub inode: Option<u64>,
+    pub nlink: u64,
 }
 
 impl CachedFile {
@@ -27,11 +35,17 @@ impl CachedFile {
             size: file.size,
             modified: file.modified,
             is_dir: file.is_dir,
+            is_symlink: file.is_symlink,
+            symlink_target: file.symlink_target.as_deref().cloned(),
+            is_sparse: file.is_sparse,
+            allocated_size: file.allocated_size,
+            inode: file.inode,
+            nlink: file.nlink,
         }
     }
 
     /// Convert back to FileEntry
-    /// Note: Some fields have default values for simplicity
+    /// Note: xattrs, ACLs and file flags aren't cached
     pub fn to_file_entry(&self, source_root: &Path) -> FileEntry {
         FileEntry {
             path: Arc::new(source_root.join(&self.path)),
@@ -39,15 +53,16 @@ impl CachedFile {
             size: self.size,
             modified: self.modified,
             is_dir: self.is_dir,
-            is_symlink: false, // Not cached
-            symlink_target: None,
-            is_sparse: false,
-            allocated_size: self.size,
+            is_symlink: self.is_symlink,
+            symlink_target: self.symlink_target.clone().map(Arc::new),
+            is_sparse: self.is_sparse,
+            allocated_size: self.allocated_size,
             xattrs: None,
-            inode: None,
-            nlink: 1,
+            inode: self.inode,
+            nlink: self.nlink,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         }
     }
 }
@@ -58,11 +73,20 @@ impl CachedFile {
 /// incremental scanning. When a directory's mtime hasn't changed, we can use the
 /// cached file list instead of re-scanning, dramatically speeding up re-syncs.
 ///
+/// Each directory's cached listing also carries a content hash (names, kinds,
+/// sizes, mtimes and symlink targets of its files). A listing is only reused
+/// when the directory's mtime is exactly as recorded and the listing still
+/// hashes to what was recorded, so a stale or damaged entry means a rescan.
+///
 /// # Performance Impact
 /// - Initial sync: No overhead (cache is empty)
-/// - Re-sync with no changes: ~100x faster (skips all directory scans)
+/// - Re-sync with no changes: only directories are statted, not their files
 /// - Re-sync with changes: Only scans changed directories
 ///
+/// Editing a file in place doesn't change its directory's mtime, so such an
+/// edit goes unseen until something else in the directory changes; run
+/// without the cache (or with `--clear-cache`) now and then to catch these.
+///
 /// # Cache File Format
 /// - Location: `<dest>/.sy-dir-cache.json`
 /// - Format: JSON (human-readable, debuggable)
@@ -83,6 +107,10 @@ pub struct DirectoryCache {
     #[serde(rename = "files", default)]
     file_entries: HashMap<PathBuf, Vec<CachedFile>>,
 
+    /// Content hash of each directory's cached file list
+    #[serde(default)]
+    hashes: HashMap<PathBuf, String>,
+
     /// Version number for cache format changes
     #[serde(default = "default_version")]
     version: u32,
@@ -97,7 +125,7 @@ fn default_version() -> u32 {
 }
 
 impl DirectoryCache {
-    const CURRENT_VERSION: u32 = 2; // Bumped for new cache format
+    const CURRENT_VERSION: u32 = 3; // Bumped for per-directory hashes
     const CACHE_FILENAME: &'static str = ".sy-dir-cache.json";
 
     /// Create a new empty cache
@@ -105,6 +133,7 @@ impl DirectoryCache {
         Self {
             dir_entries: HashMap::new(),
             file_entries: HashMap::new(),
+            hashes: HashMap::new(),
             version: Self::CURRENT_VERSION,
             last_updated: SystemTime::now(),
         }
@@ -207,6 +236,7 @@ impl DirectoryCache {
     /// - Parent directory was modified (might affect this directory)
     ///
     /// Returns false if directory mtime matches cache (can skip scan)
+    #[allow(dead_code)] // Used in tests
     pub fn needs_rescan(&self, dir_path: &Path, current_mtime: SystemTime) -> bool {
         match self.dir_entries.get(dir_path) {
             Some(&cached_mtime) => {
@@ -223,7 +253,57 @@ impl DirectoryCache {
         }
     }
 
+    /// Cached files of directory `dir_path` (relative, empty for the root),
+    /// if its mtime is exactly `current_mtime` and the listing is intact
+    pub fn unchanged_files(
+        &self,
+        dir_path: &Path,
+        current_mtime: SystemTime,
+    ) -> Option<&[CachedFile]> {
+        if self.dir_entries.get(dir_path) != Some(&current_mtime) {
+            return None;
+        }
+        let files = self.file_entries.get(dir_path)?;
+        (self.hashes.get(dir_path) == Some(&listing_hash(files))).then_some(files.as_slice())
+    }
+
+    /// Replace the cache with the result of a full scan: the root's mtime and
+    /// every entry under it
+    pub fn record_scan(&mut self, root_mtime: SystemTime, files: &[FileEntry]) {
+        self.dir_entries.clear();
+        self.file_entries.clear();
+        self.hashes.clear();
+
+        self.dir_entries.insert(PathBuf::new(), root_mtime);
+        self.file_entries.insert(PathBuf::new(), Vec::new());
+        for file in files {
+            if file.is_dir {
+                self.dir_entries
+                    .insert((*file.relative_path).clone(), file.modified);
+                self.file_entries
+                    .entry((*file.relative_path).clone())
+                    .or_default();
+            } else {
+                let dir = file
+                    .relative_path
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                self.file_entries
+                    .entry(dir)
+                    .or_default()
+                    .push(CachedFile::from_file_entry(file));
+            }
+        }
+
+        for (dir, files) in &mut self.file_entries {
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            self.hashes.insert(dir.clone(), listing_hash(files));
+        }
+    }
+
     /// Update cache entry for a directory
+    #[allow(dead_code)] // Used in tests
     pub fn update(&mut self, dir_path: PathBuf, mtime: SystemTime) {
         self.dir_entries.insert(dir_path, mtime);
     }
@@ -235,6 +315,7 @@ impl DirectoryCache {
     }
 
     /// Store cached files for a directory
+    #[allow(dead_code)] // Used in tests
     pub fn cache_files(&mut self, dir_path: PathBuf, files: Vec<CachedFile>) {
         self.file_entries.insert(dir_path, files);
     }
@@ -244,6 +325,7 @@ impl DirectoryCache {
     pub fn remove(&mut self, dir_path: &Path) -> bool {
         let dir_removed = self.dir_entries.remove(dir_path).is_some();
         let files_removed = self.file_entries.remove(dir_path).is_some();
+        self.hashes.remove(dir_path);
         dir_removed || files_removed
     }
 
@@ -252,6 +334,7 @@ impl DirectoryCache {
     pub fn clear(&mut self) {
         self.dir_entries.clear();
         self.file_entries.clear();
+        self.hashes.clear();
         self.last_updated = SystemTime::now();
     }
 
@@ -273,6 +356,26 @@ impl DirectoryCache {
     }
 }
 
+/// Hash of a directory's cached files, which are sorted by path
+fn listing_hash(files: &[CachedFile]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for file in files {
+        hasher.update(file.path.as_os_str().as_encoded_bytes());
+        hasher.update(&[0, file.is_symlink as u8]);
+        hasher.update(&file.size.to_le_bytes());
+        let mtime = file
+            .modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        hasher.update(&mtime.to_le_bytes());
+        if let Some(target) = &file.symlink_target {
+            hasher.update(target.as_os_str().as_encoded_bytes());
+        }
+        hasher.update(&[0]);
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
 impl Default for DirectoryCache {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,158 @@
+//! What a sync would transfer (`sy --estimate`)
+//!
+//! Only the scan and the comparison run: files are matched by size and
+//! mtime, so no file contents are read on either side. Byte counts are
+//! full file sizes, an upper bound when deltas or compression would apply.
+
+use super::output::SyncEvent;
+use super::SyncStats;
+use crate::streaming::GeneratorMessage;
+use std::time::Duration;
+
+/// Rate the time estimate assumes without `--estimate-rate` or `--bwlimit`
+/// (1 Gbit/s)
+pub const DEFAULT_ESTIMATE_RATE: u64 = 125_000_000;
+
+/// Outcome of comparing source and destination
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Estimate {
+    /// Files the destination doesn't have yet
+    pub files_new: u64,
+    pub bytes_new: u64,
+    /// Files the destination has an outdated copy of
+    pub files_changed: u64,
+    pub bytes_changed: u64,
+    /// Files already up to date
+    pub files_unchanged: u64,
+    /// Destination entries a `--delete` run would remove
+    pub files_deleted: u64,
+    /// How long the comparison took
+    pub duration: Duration,
+}
+
+impl Estimate {
+    /// Tally one message of a streaming Generator
+    pub fn record(&mut self, msg: &GeneratorMessage) {
+        match msg {
+            GeneratorMessage::File(job) if job.exists => {
+                self.files_changed += 1;
+                self.bytes_changed += job.size;
+            }
+            GeneratorMessage::File(job) => {
+                self.files_new += 1;
+                self.bytes_new += job.size;
+            }
+            GeneratorMessage::FileEnd { skipped, .. } => self.files_unchanged += skipped,
+            GeneratorMessage::Delete { .. } => self.files_deleted += 1,
+            _ => {}
+        }
+    }
+
+    /// Estimate from the stats of a dry run
+    pub fn from_dry_run(stats: &SyncStats) -> Self {
+        Self {
+            files_new: stats.files_created,
+            bytes_new: stats.bytes_would_add,
+            files_changed: stats.files_updated,
+            bytes_changed: stats.bytes_would_change,
+            files_unchanged: stats.files_skipped as u64,
+            files_deleted: stats.files_deleted as u64,
+            duration: stats.duration,
+        }
+    }
+
+    /// Bytes that would be sent at most
+    pub fn bytes(&self) -> u64 {
+        self.bytes_new + self.bytes_changed
+    }
+
+    /// How long sending them takes at `rate` bytes per second
+    pub fn transfer_time(&self, rate: u64) -> Duration {
+        Duration::from_secs_f64(self.bytes() as f64 / rate.max(1) as f64)
+    }
+
+    pub fn to_event(&self, rate: u64) -> SyncEvent {
+        SyncEvent::Estimate {
+            files_new: self.files_new,
+            bytes_new: self.bytes_new,
+            files_changed: self.files_changed,
+            bytes_changed: self.bytes_changed,
+            files_unchanged: self.files_unchanged,
+            files_deleted: self.files_deleted,
+            rate,
+            transfer_secs: self.transfer_time(rate).as_secs_f64(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::FileJob;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn job(size: u64, exists: bool) -> GeneratorMessage {
+        GeneratorMessage::File(FileJob {
+            path: Arc::new(PathBuf::from("f")),
+            size,
+            mtime: 0,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            sparse: false,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+            exists,
+            need_delta: false,
+            checksums: None,
+            resume: None,
+            link_target: None,
+        })
+    }
+
+    #[test]
+    fn test_record_generator_messages() {
+        let mut estimate = Estimate::default();
+        for msg in [
+            job(100, false),
+            job(50, true),
+            job(25, false),
+            GeneratorMessage::FileEnd {
+                total_files: 3,
+                total_bytes: 175,
+                skipped: 7,
+            },
+            GeneratorMessage::Delete {
+                path: Arc::new(PathBuf::from("old")),
+                is_dir: false,
+            },
+        ] {
+            estimate.record(&msg);
+        }
+
+        assert_eq!(estimate.files_new, 2);
+        assert_eq!(estimate.bytes_new, 125);
+        assert_eq!(estimate.files_changed, 1);
+        assert_eq!(estimate.bytes_changed, 50);
+        assert_eq!(estimate.files_unchanged, 7);
+        assert_eq!(estimate.files_deleted, 1);
+        assert_eq!(estimate.bytes(), 175);
+    }
+
+    #[test]
+    fn test_transfer_time() {
+        let estimate = Estimate {
+            bytes_new: 250_000_000,
+            bytes_changed: 250_000_000,
+            ..Default::default()
+        };
+        assert_eq!(
+            estimate.transfer_time(DEFAULT_ESTIMATE_RATE),
+            Duration::from_secs(4)
+        );
+        assert_eq!(Estimate::default().transfer_time(0), Duration::ZERO);
+    }
+}
@@ -1,11 +1,16 @@
+pub mod bench;
 pub mod checksumdb;
+pub mod dedup;
 pub mod dircache;
+pub mod estimate;
+pub mod namecrypt;
 pub mod output;
 pub mod progress;
 pub mod ratelimit;
 pub mod resume;
 pub mod scale;
 pub mod scanner;
+pub mod scrub;
 pub mod server_mode;
 pub mod strategy;
 pub mod transfer;
@@ -14,23 +19,29 @@ pub mod watch;
 
 use crate::cli::SymlinkMode;
 use crate::error::Result;
-use crate::filter::FilterEngine;
-use crate::integrity::{ChecksumType, IntegrityVerifier};
+use crate::filter::{EntryMetadata, FilterCallback, FilterDecision, FilterEngine};
+use crate::integrity::{ChecksumType, IntegrityVerifier, VerifySample};
+use crate::metrics::{self, MetricsCallback, MetricsSnapshot};
 use crate::perf::{PerformanceMetrics, PerformanceMonitor};
 use crate::resource;
 use crate::transport::Transport;
 use dircache::DirectoryCache;
 use futures::{stream::StreamExt, FutureExt};
 use indicatif::{ProgressBar, ProgressStyle};
+use namecrypt::NameCrypt;
 use output::SyncEvent;
+use progress::{EventSender, Progress, ProgressCallback};
 use ratelimit::RateLimiter;
 use resume::{ResumeState, SyncFlags};
 use scale::FileSetBloom;
 use scanner::FileEntry;
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 use strategy::{StrategyPlanner, SyncAction};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 use transfer::Transferrer;
 
 #[derive(Debug, Clone)]
@@ -40,20 +51,71 @@ pub struct SyncError {
     pub action: String,
 }
 
+/// Print every failed path with its error to stderr
+pub fn print_error_report(errors: &[SyncError]) {
+    use colored::Colorize;
+    eprintln!("\n{}", "⚠️  Errors occurred during sync:".red().bold());
+    eprintln!();
+
+    for (i, err) in errors.iter().enumerate() {
+        eprintln!(
+            "  {}. {} {}",
+            (i + 1).to_string().bright_black(),
+            format!("[{}]", err.action).yellow(),
+            err.path.display().to_string().white()
+        );
+        eprintln!("     {}", err.error.bright_black());
+        if i < errors.len() - 1 {
+            eprintln!();
+        }
+    }
+
+    eprintln!();
+    eprintln!("{}", format!("Total errors: {}", errors.len()).red());
+    eprintln!();
+}
+
+/// Exit status of a sync that was only partial because source files
+/// vanished while it ran (rsync's status for the same)
+pub const EXIT_VANISHED: i32 = 24;
+
+/// Whether `err` from copying `source` means it was deleted after the scan
+fn source_vanished(source: &FileEntry, err: &crate::error::SyncError) -> bool {
+    err.error_code() == crate::error::ErrorCode::NotFound
+        && std::fs::symlink_metadata(&*source.path)
+            .is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct SyncStats {
     pub files_scanned: u64,
     pub files_created: u64,
     pub files_updated: u64,
+    /// Existing files whose content was already right, so only their
+    /// metadata was applied (server mode)
+    pub files_touched: u64,
     pub files_skipped: usize,
     pub files_deleted: usize,
+    /// Old versions moved aside before being overwritten or deleted
+    /// (`--backup`, server mode)
+    pub files_backed_up: u64,
+    /// Source files deleted between the scan and the read; not errors, but
+    /// the sync is only partial
+    pub files_vanished: usize,
     pub bytes_transferred: u64,
     pub files_delta_synced: usize,
     pub delta_bytes_saved: u64,
     pub files_compressed: usize,
     pub compression_bytes_saved: u64,
+    /// Sparse files copied hole-aware, and the bytes of holes that were
+    /// recreated rather than copied; `bytes_transferred` counts logical
+    /// sizes, holes included
+    pub files_sparse: usize,
+    pub sparse_bytes_skipped: u64,
     pub files_verified: usize,
     pub verification_failures: usize,
+    /// Transferred files not selected by --verify-sample
+    pub files_verify_skipped: usize,
     pub duration: Duration,
     // Dry-run statistics
     pub bytes_would_add: u64,
@@ -64,10 +126,32 @@ pub struct SyncStats {
     pub dirs_created: u64,
     #[allow(dead_code)]
     pub symlinks_created: u64,
+    /// Stopped early by a cancellation (SIGINT/SIGTERM); the stats cover
+    /// what finished before
+    pub cancelled: bool,
+    /// Per-phase timing (server mode)
+    pub phases: Option<crate::streaming::PhaseTimes>,
     // Error tracking
     pub errors: Vec<SyncError>,
 }
 
+impl SyncStats {
+    /// Bytes of file data actually read and written: `bytes_transferred`
+    /// without the holes of sparse files
+    pub fn data_bytes(&self) -> u64 {
+        self.bytes_transferred
+            .saturating_sub(self.sparse_bytes_skipped)
+    }
+
+    /// Count `result` towards the sparse totals if it was a sparse copy
+    fn add_sparse(&mut self, result: &crate::transport::TransferResult) {
+        if let Some(holes) = result.sparse_bytes_skipped {
+            self.files_sparse += 1;
+            self.sparse_bytes_skipped += holes;
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct VerificationResult {
     pub files_matched: usize,
@@ -100,6 +184,8 @@ pub struct SyncEngine<T: Transport> {
     json: bool,
     verification_mode: ChecksumType,
     verify_on_write: bool,
+    verify_retries: u32,
+    verify_sample: Option<VerifySample>,
     symlink_mode: SymlinkMode,
     preserve_xattrs: bool,
     preserve_hardlinks: bool,
@@ -119,6 +205,13 @@ pub struct SyncEngine<T: Transport> {
     prune_checksum_db: bool,
     dest_is_remote: bool,
     perf_monitor: Option<Arc<Mutex<PerformanceMonitor>>>,
+    on_progress: Option<ProgressCallback>,
+    events: Option<EventSender>,
+    filter_callback: Option<FilterCallback>,
+    on_metrics: Option<MetricsCallback>,
+    fat: bool,
+    name_crypt: Option<NameCrypt>,
+    cancel: CancellationToken,
 }
 
 impl<T: Transport + 'static> SyncEngine<T> {
@@ -144,6 +237,8 @@ impl<T: Transport + 'static> SyncEngine<T> {
         json: bool,
         verification_mode: ChecksumType,
         verify_on_write: bool,
+        verify_retries: u32,
+        verify_sample: Option<VerifySample>,
         symlink_mode: SymlinkMode,
         preserve_xattrs: bool,
         preserve_hardlinks: bool,
@@ -190,6 +285,8 @@ impl<T: Transport + 'static> SyncEngine<T> {
             json,
             verification_mode,
             verify_on_write,
+            verify_retries,
+            verify_sample,
             symlink_mode,
             preserve_xattrs,
             preserve_hardlinks,
@@ -208,6 +305,132 @@ impl<T: Transport + 'static> SyncEngine<T> {
             prune_checksum_db,
             dest_is_remote,
             perf_monitor,
+            on_progress: None,
+            events: None,
+            filter_callback: None,
+            on_metrics: None,
+            fat: false,
+            name_crypt: None,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Report progress to `callback` after each file of a directory sync
+    #[allow(dead_code)] // Library API (sy::Sync::builder); the CLI draws progress bars
+    pub fn with_progress(mut self, callback: ProgressCallback) -> Self {
+        self.on_progress = Some(callback);
+        self
+    }
+
+    /// Send per-file events of a directory sync to `events`
+    #[allow(dead_code)] // Library API (sy::Sync::run_with_events) and --tui
+    pub fn with_events(mut self, events: EventSender) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Also skip scanned entries `callback` excludes
+    #[allow(dead_code)] // Library API (sy::Sync::builder().filter)
+    pub fn with_filter(mut self, callback: FilterCallback) -> Self {
+        self.filter_callback = Some(callback);
+        self
+    }
+
+    /// Pass the metrics of each finished sync to `callback`
+    ///
+    /// Turns on phase timing (the `--perf` monitor) so the snapshot has it.
+    #[allow(dead_code)] // Library API (sy::Sync::builder().on_metrics)
+    pub fn with_metrics(mut self, callback: MetricsCallback) -> Self {
+        self.on_metrics = Some(callback);
+        if self.perf_monitor.is_none() {
+            self.perf_monitor = Some(Arc::new(Mutex::new(PerformanceMonitor::new(self.bwlimit))));
+        }
+        self
+    }
+
+    /// Treat the destination as FAT/exFAT (`--fat`)
+    ///
+    /// Mtimes are compared with a 2-second window (falling back to size when
+    /// they're off by a time zone shift), and names FAT forbids are rewritten
+    /// so planning, transfers and deletions all agree on the stored name.
+    pub fn with_fat(mut self, fat: bool) -> Self {
+        self.fat = fat;
+        self
+    }
+
+    /// Encrypt (or decrypt) names on the way to the destination
+    /// (`--encrypt-names`, `--decrypt-names`)
+    pub fn with_name_crypt(mut self, name_crypt: Option<NameCrypt>) -> Self {
+        self.name_crypt = name_crypt;
+        self
+    }
+
+    /// Stop a directory sync when `cancel` is cancelled
+    ///
+    /// No new transfers start, the ones in flight are dropped (their temp
+    /// files with them), the resume state is saved so the next run skips
+    /// what finished, and the sync returns `Ok` with `SyncStats::cancelled`.
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Give `file` the name it will have on the destination, or None if it
+    /// can't have one there
+    fn dest_name(&self, mut file: FileEntry) -> Option<FileEntry> {
+        if let Some(name_crypt) = &self.name_crypt {
+            match name_crypt.dest_path(&file.relative_path) {
+                Ok(path) => file.relative_path = Arc::new(path),
+                Err(e) => {
+                    tracing::warn!("Skipping {}: {}", file.relative_path.display(), e);
+                    return None;
+                }
+            }
+        }
+        if self.fat {
+            if let Cow::Owned(safe) = crate::fs_util::fat_safe_path(&file.relative_path) {
+                tracing::debug!(
+                    "Renaming {} to {} for FAT",
+                    file.relative_path.display(),
+                    safe.display()
+                );
+                file.relative_path = Arc::new(safe);
+            }
+        }
+        Some(file)
+    }
+
+    /// Start of a run, for `record_metrics`
+    fn start_metrics(&self) -> (std::time::Instant, u64) {
+        (std::time::Instant::now(), metrics::global().retries())
+    }
+
+    /// Record a finished run into the global registry and the callback
+    fn record_metrics(
+        &self,
+        (start, retries): (std::time::Instant, u64),
+        result: &Result<SyncStats>,
+    ) {
+        let mut run = match result {
+            Ok(stats) => MetricsSnapshot::from_sync(
+                stats,
+                self.get_performance_metrics().as_ref(),
+                self.dest_is_remote,
+            ),
+            Err(_) => MetricsSnapshot::failed(start.elapsed()),
+        };
+        // Retries are counted process-wide; concurrent syncs share them
+        run.retries = metrics::global().retries().saturating_sub(retries);
+        metrics::global().record(&run);
+        if let Some(callback) = &self.on_metrics {
+            callback(&run);
+        }
+    }
+
+    fn emit(&self, event: progress::SyncEvent) {
+        if let Some(events) = &self.events {
+            // The receiver going away just means nobody is listening
+            let _ = events.send(event);
         }
     }
 
@@ -225,11 +448,27 @@ impl<T: Transport + 'static> SyncEngine<T> {
         false
     }
 
-    fn should_exclude(&self, relative_path: &Path, is_dir: bool) -> bool {
-        self.filter_engine.should_exclude(relative_path, is_dir)
+    fn should_exclude(&self, file: &FileEntry) -> bool {
+        if self
+            .filter_engine
+            .should_exclude(&file.relative_path, file.is_dir)
+        {
+            return true;
+        }
+        self.filter_callback.as_ref().is_some_and(|callback| {
+            callback(&file.relative_path, &EntryMetadata::from(file)) == FilterDecision::Exclude
+        })
     }
 
+    #[tracing::instrument(name = "sync", skip_all, fields(source = %source.display(), dest = %destination.display()))]
     pub async fn sync(&self, source: &Path, destination: &Path) -> Result<SyncStats> {
+        let run = self.start_metrics();
+        let result = self.sync_dir(source, destination).await;
+        self.record_metrics(run, &result);
+        result
+    }
+
+    async fn sync_dir(&self, source: &Path, destination: &Path) -> Result<SyncStats> {
         let start_time = std::time::Instant::now();
 
         tracing::info!(
@@ -301,109 +540,54 @@ impl<T: Transport + 'static> SyncEngine<T> {
             None
         };
 
-        // Check if we can use cached scan results (incremental scanning)
-        let can_use_cache = if let Some(ref cache) = dir_cache {
-            // Check source directory mtime
-            if let Ok(source_meta) = std::fs::metadata(source) {
-                if let Ok(source_mtime) = source_meta.modified() {
-                    let source_path = PathBuf::from(".");
-                    !cache.needs_rescan(&source_path, source_mtime)
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        } else {
-            false
-        };
-
         // Start scan timing
+        let scan_span = tracing::info_span!("scan");
         if let Some(ref monitor) = self.perf_monitor {
             monitor.lock().unwrap().start_scan();
         }
 
-        // Scan source directory (or use cache)
-        let all_files = if can_use_cache {
-            // Use cached files for incremental scan
-            if let Some(ref cache) = dir_cache {
-                if let Some(cached_files) = cache.get_cached_files(&PathBuf::from(".")) {
-                    let file_count = cached_files.len();
-                    tracing::info!(
-                        "Using cached scan results ({} files) - source unchanged",
-                        file_count
-                    );
-
-                    // Convert cached files back to FileEntry
-                    cached_files
-                        .iter()
-                        .map(|cf| cf.to_file_entry(source))
-                        .collect()
-                } else {
-                    // Cache exists but no files cached for root directory
-                    tracing::debug!("No cached files found, performing full scan");
-                    self.transport.scan(source).await?
-                }
-            } else {
-                // This shouldn't happen, but fall back to full scan
-                self.transport.scan(source).await?
-            }
+        // Scan source directory, taking unchanged directories' files from
+        // the cache (if enabled). xattrs, ACLs and file flags aren't cached,
+        // so a cached listing can't stand in for a scan that preserves them.
+        // The root's mtime is taken first: a change made mid-scan then shows
+        // up as a changed root next time
+        let root_mtime = std::fs::metadata(source).and_then(|meta| meta.modified());
+        let can_prune = !self.preserve_xattrs && !self.preserve_acls && !self.preserve_flags;
+        let all_files = if let Some(cache) = dir_cache.take_if(|_| can_prune) {
+            let cache = Arc::new(cache);
+            let (files, unchanged_dirs) = self
+                .transport
+                .scan_pruned(source, Arc::clone(&cache))
+                .await?;
+            tracing::info!(
+                "Found {} items in source ({} of {} cached directories unchanged)",
+                files.len(),
+                unchanged_dirs,
+                cache.len()
+            );
+            dir_cache = Some(Arc::unwrap_or_clone(cache));
+            files
         } else {
-            tracing::debug!("Scanning source directory (cache miss or disabled)...");
-            self.transport.scan(source).await?
+            tracing::debug!("Scanning source directory...");
+            let files = self.transport.scan(source).await?;
+            tracing::info!("Found {} items in source", files.len());
+            files
         };
-
         let total_scanned = all_files.len();
-        if can_use_cache {
-            tracing::info!("Retrieved {} items from cache", total_scanned);
-        } else {
-            tracing::info!("Found {} items in source", total_scanned);
-        }
 
         // Prepare transport for the workload (e.g., expand SSH connection pool)
         self.transport.prepare_for_transfer(total_scanned).await?;
 
-        // Update cache with scanned directory mtimes and file entries (for future incremental scans)
-        if let Some(ref mut cache) = dir_cache {
-            use crate::sync::dircache::CachedFile;
-            use std::collections::HashMap;
-
-            // Group files by their parent directory
-            let mut files_by_dir: HashMap<PathBuf, Vec<CachedFile>> = HashMap::new();
-
-            for file in &all_files {
-                // Update directory mtimes
-                if file.is_dir {
-                    cache.update((*file.relative_path).clone(), file.modified);
+        // Record what was found for the next run's scan; without the root's
+        // mtime there's nothing to vouch for its listing with
+        match root_mtime {
+            Ok(root_mtime) => {
+                if let Some(cache) = dir_cache.as_mut() {
+                    cache.record_scan(root_mtime, &all_files);
+                    tracing::debug!("Updated directory cache with {} directories", cache.len());
                 }
-
-                // Group files by directory for caching
-                let dir_path = if file.is_dir {
-                    (*file.relative_path).clone()
-                } else {
-                    file.relative_path
-                        .parent()
-                        .map(|p| p.to_path_buf())
-                        .unwrap_or_else(|| PathBuf::from("."))
-                };
-
-                files_by_dir
-                    .entry(dir_path)
-                    .or_default()
-                    .push(CachedFile::from_file_entry(file));
-            }
-
-            // Cache files for each directory
-            let total_files: usize = files_by_dir.values().map(|v| v.len()).sum();
-            for (dir_path, files) in files_by_dir {
-                cache.cache_files(dir_path, files);
             }
-
-            tracing::debug!(
-                "Updated directory cache with {} directories, {} files",
-                cache.len(),
-                total_files
-            );
+            Err(_) => dir_cache = None,
         }
 
         // Filter files by size and exclude patterns
@@ -425,7 +609,7 @@ impl<T: Transport + 'static> SyncEngine<T> {
                 }
 
                 // Apply exclude patterns
-                if self.should_exclude(&file.relative_path, file.is_dir) {
+                if self.should_exclude(file) {
                     tracing::debug!("Filtering out (excluded): {}", file.relative_path.display());
 
                     // If this is a directory, track it to exclude its children
@@ -447,6 +631,7 @@ impl<T: Transport + 'static> SyncEngine<T> {
                 }
                 true
             })
+            .filter_map(|file| self.dest_name(file))
             .collect();
 
         if source_files.len() < total_scanned {
@@ -455,6 +640,7 @@ impl<T: Transport + 'static> SyncEngine<T> {
         }
 
         // End scan timing
+        drop(scan_span);
         if let Some(ref monitor) = self.perf_monitor {
             monitor.lock().unwrap().end_scan();
         }
@@ -543,6 +729,7 @@ impl<T: Transport + 'static> SyncEngine<T> {
         tracing::debug!("Resume state loaded, starting plan timing");
 
         // Start plan timing
+        let plan_span = tracing::info_span!("plan");
         if let Some(ref monitor) = self.perf_monitor {
             monitor.lock().unwrap().start_plan();
         }
@@ -554,7 +741,8 @@ impl<T: Transport + 'static> SyncEngine<T> {
             self.checksum,
             self.update_only,
             self.ignore_existing,
-        );
+        )
+        .with_fat(self.fat);
 
         tracing::debug!("Starting to plan {} tasks", source_files.len());
 
@@ -765,6 +953,7 @@ impl<T: Transport + 'static> SyncEngine<T> {
         }
 
         // End plan timing
+        drop(plan_span);
         if let Some(ref monitor) = self.perf_monitor {
             monitor.lock().unwrap().end_plan();
         }
@@ -790,6 +979,9 @@ impl<T: Transport + 'static> SyncEngine<T> {
             files_scanned: source_files.len() as u64,
             files_created: 0,
             files_updated: 0,
+            files_touched: 0,
+            files_backed_up: 0,
+            files_vanished: 0,
             files_skipped: 0,
             files_deleted: 0,
             bytes_transferred: 0,
@@ -797,14 +989,19 @@ impl<T: Transport + 'static> SyncEngine<T> {
             delta_bytes_saved: 0,
             files_compressed: 0,
             compression_bytes_saved: 0,
+            files_sparse: 0,
+            sparse_bytes_skipped: 0,
             files_verified: 0,
             verification_failures: 0,
+            files_verify_skipped: 0,
             duration: Duration::ZERO,
             bytes_would_add: 0,
             bytes_would_change: 0,
             bytes_would_delete: 0,
             dirs_created: 0,
             symlinks_created: 0,
+            cancelled: false,
+            phases: None,
             errors: Vec::new(),
         }));
 
@@ -837,6 +1034,16 @@ impl<T: Transport + 'static> SyncEngine<T> {
             pb
         };
 
+        // Counters for the progress callback
+        let files_total = tasks.len() as u64;
+        let files_done = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let bytes_done = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        self.emit(progress::SyncEvent::Scanned {
+            files_scanned: source_files.len() as u64,
+            files_total,
+            bytes_total: total_bytes,
+        });
+
         // Create rate limiter if bandwidth limit is set
         let rate_limiter = self
             .bwlimit
@@ -846,6 +1053,7 @@ impl<T: Transport + 'static> SyncEngine<T> {
         let hardlink_map = Arc::new(Mutex::new(std::collections::HashMap::new()));
 
         // Start transfer timing
+        let transfer_span = tracing::info_span!("transfer");
         if let Some(ref monitor) = self.perf_monitor {
             monitor.lock().unwrap().start_transfer();
         }
@@ -879,15 +1087,22 @@ impl<T: Transport + 'static> SyncEngine<T> {
 
         // Use stream-based execution (buffer_unordered) instead of join_all
         // This allows processing results as they complete and enabling periodic checkpointing
+        let transfer_id = transfer_span.id();
         let transfer_futures = tasks.into_iter().map(|task| {
             let transport = Arc::clone(&self.transport);
             let dry_run = self.dry_run;
             let diff_mode = self.diff_mode;
             let _json = self.json;
             let pb = pb.clone();
+            let on_progress = self.on_progress.clone();
+            let events = self.events.clone();
+            let files_done = Arc::clone(&files_done);
+            let bytes_done = Arc::clone(&bytes_done);
             let rate_limiter = rate_limiter.clone();
             let verification_mode = self.verification_mode;
             let verify_on_write = self.verify_on_write;
+            let verify_retries = self.verify_retries;
+            let verify_sample = self.verify_sample;
             let symlink_mode = self.symlink_mode;
             let preserve_xattrs = self.preserve_xattrs;
             let preserve_hardlinks = self.preserve_hardlinks;
@@ -896,6 +1111,12 @@ impl<T: Transport + 'static> SyncEngine<T> {
             let per_file_progress = self.per_file_progress && !self.quiet;
             let hardlink_map = Arc::clone(&hardlink_map);
             let _perf_monitor = self.perf_monitor.clone();
+            let file_span = tracing::info_span!(
+                parent: transfer_id.clone(),
+                "file",
+                path = %task.dest_path.display(),
+                action = ?task.action,
+            );
 
             // Clone stats for error reporting inside the task (if needed)
             // But we mainly return results to the main loop
@@ -932,6 +1153,13 @@ impl<T: Transport + 'static> SyncEngine<T> {
                 if !matches!(task.action, SyncAction::Skip) {
                     pb.set_message(msg);
                 }
+                let transfers = matches!(task.action, SyncAction::Create | SyncAction::Update);
+                if let (Some(events), true) = (&events, transfers) {
+                    let _ = events.send(progress::SyncEvent::FileStarted {
+                        path: task.dest_path.clone(),
+                        size: task.source.as_ref().map(|f| f.size).unwrap_or(0),
+                    });
+                }
 
                 // Return a struct with all info needed for stats and checkpointing
                 struct TaskResult {
@@ -964,42 +1192,24 @@ impl<T: Transport + 'static> SyncEngine<T> {
                                         }
                                     }
 
-                                    // Verify
-                                    let mut verified = true;
-                                    if verification_mode != ChecksumType::None
+                                    // Verify, re-sending on mismatch
+                                    let verified = if verification_mode != ChecksumType::None
                                         && !dry_run
                                         && !source.is_dir
+                                        && verify_sample
+                                            .is_none_or(|s| s.selects(&source.relative_path))
                                     {
-                                        // Verification logic...
-                                        // For brevity, duplicating verification logic from original code
-                                        let source_path = &source.path;
-                                        let dest_path = &task.dest_path;
-                                        let verification_result = if !dest_path.exists() {
-                                            // Remote/S3
-                                            match transport
-                                                .compute_checksum(source_path, &verifier)
-                                                .await
-                                            {
-                                                Ok(sc) => match transport
-                                                    .compute_checksum(dest_path, &verifier)
-                                                    .await
-                                                {
-                                                    Ok(dc) => Ok(sc == dc),
-                                                    Err(e) => Err(e),
-                                                },
-                                                Err(e) => Err(e),
-                                            }
-                                        } else {
-                                            verifier.verify_transfer(source_path, dest_path)
-                                        };
-
-                                        if let Ok(v) = verification_result {
-                                            verified = v;
-                                        } else {
-                                            verified = false;
-                                            // We should capture the error, but for now boolean is enough for stats
-                                        }
-                                    }
+                                        transferrer
+                                            .verify_with_retry(
+                                                source,
+                                                &task.dest_path,
+                                                &verifier,
+                                                verify_retries,
+                                            )
+                                            .await
+                                    } else {
+                                        true
+                                    };
 
                                     Ok(TaskResult {
                                         task: task.clone(),
@@ -1041,38 +1251,24 @@ impl<T: Transport + 'static> SyncEngine<T> {
                                         }
                                     }
 
-                                    // Verify
-                                    let mut verified = true;
-                                    if verification_mode != ChecksumType::None
+                                    // Verify, re-sending on mismatch
+                                    let verified = if verification_mode != ChecksumType::None
                                         && !dry_run
                                         && !source.is_dir
+                                        && verify_sample
+                                            .is_none_or(|s| s.selects(&source.relative_path))
                                     {
-                                        let source_path = &source.path;
-                                        let dest_path = &task.dest_path;
-                                        let verification_result = if !dest_path.exists() {
-                                            match transport
-                                                .compute_checksum(source_path, &verifier)
-                                                .await
-                                            {
-                                                Ok(sc) => match transport
-                                                    .compute_checksum(dest_path, &verifier)
-                                                    .await
-                                                {
-                                                    Ok(dc) => Ok(sc == dc),
-                                                    Err(e) => Err(e),
-                                                },
-                                                Err(e) => Err(e),
-                                            }
-                                        } else {
-                                            verifier.verify_transfer(source_path, dest_path)
-                                        };
-
-                                        if let Ok(v) = verification_result {
-                                            verified = v;
-                                        } else {
-                                            verified = false;
-                                        }
-                                    }
+                                        transferrer
+                                            .verify_with_retry(
+                                                source,
+                                                &task.dest_path,
+                                                &verifier,
+                                                verify_retries,
+                                            )
+                                            .await
+                                    } else {
+                                        true
+                                    };
 
                                     Ok(TaskResult {
                                         task: task.clone(),
@@ -1125,15 +1321,46 @@ impl<T: Transport + 'static> SyncEngine<T> {
                 };
                 pb.inc(bytes_for_progress);
 
+                if on_progress.is_some() || events.is_some() {
+                    use std::sync::atomic::Ordering;
+                    let progress = Progress {
+                        path: task.dest_path.clone(),
+                        files_done: files_done.fetch_add(1, Ordering::Relaxed) + 1,
+                        files_total,
+                        bytes_done: bytes_done.fetch_add(bytes_for_progress, Ordering::Relaxed)
+                            + bytes_for_progress,
+                        bytes_total: total_bytes,
+                    };
+                    if let Some(callback) = on_progress {
+                        callback(&progress);
+                    }
+                    if let (Some(events), true) = (&events, transfers && result.is_ok()) {
+                        let _ = events.send(progress::SyncEvent::FileDone(progress));
+                    }
+                }
+
                 result
             }
+            .instrument(file_span)
         });
 
         // Process results as they stream in
         let mut stream =
             futures::stream::iter(transfer_futures).buffer_unordered(self.max_concurrent);
 
-        while let Some(result) = stream.next().await {
+        let mut interrupted = false;
+        loop {
+            let result = tokio::select! {
+                biased;
+                _ = self.cancel.cancelled() => {
+                    interrupted = true;
+                    break;
+                }
+                result = stream.next() => match result {
+                    Some(result) => result,
+                    None => break,
+                },
+            };
             match result {
                 Ok(res) => {
                     // Successful task
@@ -1144,6 +1371,9 @@ impl<T: Transport + 'static> SyncEngine<T> {
                         SyncAction::Create => {
                             s.files_created += 1;
                             s.bytes_transferred += res.bytes_written;
+                            if let Some(tr) = &res.transfer_result {
+                                s.add_sparse(tr);
+                            }
 
                             if self.dry_run {
                                 if let Some(src) = &task.source {
@@ -1155,10 +1385,10 @@ impl<T: Transport + 'static> SyncEngine<T> {
 
                             if let Some(monitor) = &self.perf_monitor {
                                 monitor.lock().unwrap().add_file_created();
-                                monitor
-                                    .lock()
-                                    .unwrap()
-                                    .add_bytes_transferred(res.bytes_written);
+                                monitor.lock().unwrap().add_bytes_transferred(
+                                    res.transfer_result
+                                        .map_or(res.bytes_written, |tr| tr.data_bytes()),
+                                );
                                 if !task.source.as_ref().map(|s| s.is_dir).unwrap_or(false) {
                                     monitor.lock().unwrap().add_bytes_read(
                                         task.source.as_ref().map(|s| s.size).unwrap_or(0),
@@ -1190,6 +1420,9 @@ impl<T: Transport + 'static> SyncEngine<T> {
                         SyncAction::Update => {
                             s.files_updated += 1;
                             s.bytes_transferred += res.bytes_written;
+                            if let Some(tr) = &res.transfer_result {
+                                s.add_sparse(tr);
+                            }
 
                             if self.dry_run {
                                 if let Some(src) = &task.source {
@@ -1201,10 +1434,10 @@ impl<T: Transport + 'static> SyncEngine<T> {
 
                             if let Some(monitor) = &self.perf_monitor {
                                 monitor.lock().unwrap().add_file_updated();
-                                monitor
-                                    .lock()
-                                    .unwrap()
-                                    .add_bytes_transferred(res.bytes_written);
+                                monitor.lock().unwrap().add_bytes_transferred(
+                                    res.transfer_result
+                                        .map_or(res.bytes_written, |tr| tr.data_bytes()),
+                                );
                                 if !task.source.as_ref().map(|s| s.is_dir).unwrap_or(false) {
                                     monitor.lock().unwrap().add_bytes_read(
                                         task.source.as_ref().map(|s| s.size).unwrap_or(0),
@@ -1273,6 +1506,9 @@ impl<T: Transport + 'static> SyncEngine<T> {
                                 }
                                 .emit();
                             }
+                            self.emit(progress::SyncEvent::Deleted {
+                                path: task.dest_path.clone(),
+                            });
                         }
                     }
 
@@ -1283,9 +1519,14 @@ impl<T: Transport + 'static> SyncEngine<T> {
                     } else if self.verification_mode != ChecksumType::None
                         && !self.dry_run
                         && matches!(task.action, SyncAction::Create | SyncAction::Update)
-                        && task.source.as_ref().map(|s| !s.is_dir).unwrap_or(false)
                     {
-                        s.files_verified += 1;
+                        if let Some(source) = task.source.as_ref().filter(|s| !s.is_dir) {
+                            if self.is_sampled_for_verify(&source.relative_path) {
+                                s.files_verified += 1;
+                            } else {
+                                s.files_verify_skipped += 1;
+                            }
+                        }
                     }
 
                     // Resume State Update & Periodic Checkpointing
@@ -1343,6 +1584,15 @@ impl<T: Transport + 'static> SyncEngine<T> {
                         }
                     }
                 }
+                Err((task, e))
+                    if task
+                        .source
+                        .as_ref()
+                        .is_some_and(|source| source_vanished(source, &e)) =>
+                {
+                    tracing::warn!("File vanished: {}", task.dest_path.display());
+                    stats.lock().unwrap().files_vanished += 1;
+                }
                 Err((task, e)) => {
                     // Error handling
                     let mut s = stats.lock().unwrap();
@@ -1357,6 +1607,10 @@ impl<T: Transport + 'static> SyncEngine<T> {
                         },
                     });
                     tracing::error!("Sync error for {}: {}", task.dest_path.display(), e);
+                    self.emit(progress::SyncEvent::Error {
+                        path: task.dest_path.clone(),
+                        message: e.to_string(),
+                    });
 
                     // Check max errors
                     if self.max_errors > 0 && s.errors.len() >= self.max_errors {
@@ -1370,12 +1624,21 @@ impl<T: Transport + 'static> SyncEngine<T> {
             }
         }
 
+        // Transfers still in flight stop here, removing their temp files
+        drop(stream);
+
         // End transfer timing
+        drop(transfer_span);
         if let Some(ref monitor) = self.perf_monitor {
             monitor.lock().unwrap().end_transfer();
         }
 
-        pb.finish_with_message("Sync complete");
+        if interrupted {
+            tracing::warn!("Sync interrupted");
+            pb.finish_with_message("Interrupted");
+        } else {
+            pb.finish_with_message("Sync complete");
+        }
 
         // Extract final stats before reporting errors
         let mut final_stats = Arc::try_unwrap(stats).unwrap().into_inner().unwrap();
@@ -1385,34 +1648,13 @@ impl<T: Transport + 'static> SyncEngine<T> {
             tracing::warn!("Sync completed with {} errors", final_stats.errors.len());
 
             if !self.quiet && !self.json {
-                use colored::Colorize;
-                eprintln!("\n{}", "⚠️  Errors occurred during sync:".red().bold());
-                eprintln!();
-
-                for (i, err) in final_stats.errors.iter().enumerate() {
-                    eprintln!(
-                        "  {}. {} {}",
-                        (i + 1).to_string().bright_black(),
-                        format!("[{}]", err.action).yellow(),
-                        err.path.display().to_string().white()
-                    );
-                    eprintln!("     {}", err.error.bright_black());
-                    if i < final_stats.errors.len() - 1 {
-                        eprintln!();
-                    }
-                }
-
-                eprintln!();
-                eprintln!(
-                    "{}",
-                    format!("Total errors: {}", final_stats.errors.len()).red()
-                );
-                eprintln!();
+                print_error_report(&final_stats.errors);
             }
         }
 
         // Add duration after extracting stats
         final_stats.duration = start_time.elapsed();
+        final_stats.cancelled = interrupted;
 
         tracing::info!(
             "Sync complete: {} created, {} updated, {} skipped, {} deleted, took {:.2}s",
@@ -1431,9 +1673,11 @@ impl<T: Transport + 'static> SyncEngine<T> {
                 files_skipped: final_stats.files_skipped,
                 files_deleted: final_stats.files_deleted,
                 bytes_transferred: final_stats.bytes_transferred,
+                sparse_bytes_skipped: final_stats.sparse_bytes_skipped,
                 duration_secs: final_stats.duration.as_secs_f64(),
                 files_verified: final_stats.files_verified,
                 verification_failures: final_stats.verification_failures,
+                files_verify_skipped: final_stats.files_verify_skipped,
             }
             .emit();
 
@@ -1460,9 +1704,18 @@ impl<T: Transport + 'static> SyncEngine<T> {
             }
         }
 
-        // Clean up resume state on successful completion
+        // Clean up resume state on successful completion; an interrupted
+        // sync saves it instead, so the next run skips what finished
         if let Ok(mut state_guard) = resume_state.lock() {
-            if state_guard.is_some() {
+            if interrupted {
+                if let Some(state) = state_guard.as_ref() {
+                    if !self.dry_run && !self.dest_is_remote {
+                        if let Err(e) = state.save(destination) {
+                            tracing::warn!("Failed to save resume state: {}", e);
+                        }
+                    }
+                }
+            } else if state_guard.is_some() {
                 // Only clean up if this was an actual resume operation
                 // (Don't clean up if we just created a new state that was never saved)
                 if ResumeState::load(destination)?.is_some() {
@@ -1476,8 +1729,9 @@ impl<T: Transport + 'static> SyncEngine<T> {
             *state_guard = None;
         }
 
-        // Save directory cache if enabled
-        if self.use_cache && !self.dry_run {
+        // Save directory cache if enabled (not after an interrupt: it would
+        // vouch for directories that weren't finished)
+        if self.use_cache && !self.dry_run && !interrupted {
             if let Some(ref cache) = dir_cache {
                 // Only save cache if destination is local
                 if !self.dest_is_remote {
@@ -1494,7 +1748,7 @@ impl<T: Transport + 'static> SyncEngine<T> {
 
         // Store checksums in database if enabled
         if let Some(ref db) = checksum_db {
-            if !self.dry_run {
+            if !self.dry_run && !interrupted {
                 let mut stored_count = 0;
                 let verifier = IntegrityVerifier::new(
                     if self.checksum {
@@ -1562,7 +1816,15 @@ impl<T: Transport + 'static> SyncEngine<T> {
     ///
     /// This pipeline avoids loading all files into memory by processing them in stages:
     /// Scan -> Filter -> Plan -> Execute
+    #[tracing::instrument(name = "sync", skip_all, fields(source = %source.display(), dest = %destination.display()))]
     pub async fn sync_streaming(&self, source: &Path, destination: &Path) -> Result<SyncStats> {
+        let run = self.start_metrics();
+        let result = self.sync_dir_streaming(source, destination).await;
+        self.record_metrics(run, &result);
+        result
+    }
+
+    async fn sync_dir_streaming(&self, source: &Path, destination: &Path) -> Result<SyncStats> {
         let start_time = std::time::Instant::now();
 
         tracing::info!(
@@ -1611,6 +1873,9 @@ impl<T: Transport + 'static> SyncEngine<T> {
             files_scanned: 0,
             files_created: 0,
             files_updated: 0,
+            files_touched: 0,
+            files_backed_up: 0,
+            files_vanished: 0,
             files_skipped: 0,
             files_deleted: 0,
             bytes_transferred: 0,
@@ -1618,25 +1883,33 @@ impl<T: Transport + 'static> SyncEngine<T> {
             delta_bytes_saved: 0,
             files_compressed: 0,
             compression_bytes_saved: 0,
+            files_sparse: 0,
+            sparse_bytes_skipped: 0,
             files_verified: 0,
             verification_failures: 0,
+            files_verify_skipped: 0,
             duration: Duration::ZERO,
             bytes_would_add: 0,
             bytes_would_change: 0,
             bytes_would_delete: 0,
             dirs_created: 0,
             symlinks_created: 0,
+            cancelled: false,
+            phases: None,
             errors: Vec::new(),
         }));
 
         // Strategy Planner
-        let planner = Arc::new(StrategyPlanner::with_comparison_flags(
-            self.ignore_times,
-            self.size_only,
-            self.checksum,
-            self.update_only,
-            self.ignore_existing,
-        ));
+        let planner = Arc::new(
+            StrategyPlanner::with_comparison_flags(
+                self.ignore_times,
+                self.size_only,
+                self.checksum,
+                self.update_only,
+                self.ignore_existing,
+            )
+            .with_fat(self.fat),
+        );
 
         // Create hardlink map for tracking inodes (shared across all parallel transfers)
         let hardlink_map = Arc::new(Mutex::new(std::collections::HashMap::new()));
@@ -1662,11 +1935,12 @@ impl<T: Transport + 'static> SyncEngine<T> {
                 // Filter and update stats
                 match entry_result {
                     Ok(file) => {
+                        let file = self.dest_name(file)?;
                         // Update Bloom filter for deletions later
                         bloom_filter.lock().unwrap().insert(&file.relative_path);
 
                         // Filter exclusion logic
-                        if self.should_exclude(&file.relative_path, file.is_dir) {
+                        if self.should_exclude(&file) {
                             // Update excluded stats if we tracked them, but for now just skip
                             return None;
                         }
@@ -1808,13 +2082,15 @@ impl<T: Transport + 'static> SyncEngine<T> {
                                             let mut stats = stats.lock().unwrap();
                                             stats.bytes_transferred += bytes_written;
                                             stats.files_created += 1;
+                                            if let Some(ref result) = transfer_result {
+                                                stats.add_sparse(result);
+                                            }
 
                                             if let Some(monitor) = &perf_monitor {
                                                 monitor.lock().unwrap().add_file_created();
-                                                monitor
-                                                    .lock()
-                                                    .unwrap()
-                                                    .add_bytes_transferred(bytes_written);
+                                                monitor.lock().unwrap().add_bytes_transferred(
+                                                    transfer_result.map_or(0, |r| r.data_bytes()),
+                                                );
                                                 if !source.is_dir {
                                                     monitor
                                                         .lock()
@@ -1848,6 +2124,14 @@ impl<T: Transport + 'static> SyncEngine<T> {
                                         }
                                         Ok(())
                                     }
+                                    Err(e) if source_vanished(source, &e) => {
+                                        tracing::warn!(
+                                            "File vanished: {}",
+                                            task.dest_path.display()
+                                        );
+                                        stats.lock().unwrap().files_vanished += 1;
+                                        Ok(())
+                                    }
                                     Err(e) => {
                                         let mut stats = stats.lock().unwrap();
                                         stats.errors.push(SyncError {
@@ -1877,6 +2161,7 @@ impl<T: Transport + 'static> SyncEngine<T> {
                                             let mut stats = stats.lock().unwrap();
                                             if let Some(ref result) = transfer_result {
                                                 stats.bytes_transferred += result.bytes_written;
+                                                stats.add_sparse(result);
                                                 if result.used_delta() {
                                                     stats.files_delta_synced += 1;
                                                     if let Some(literal) = result.literal_bytes {
@@ -1890,10 +2175,9 @@ impl<T: Transport + 'static> SyncEngine<T> {
 
                                             if let Some(monitor) = &perf_monitor {
                                                 monitor.lock().unwrap().add_file_updated();
-                                                monitor
-                                                    .lock()
-                                                    .unwrap()
-                                                    .add_bytes_transferred(bytes_written);
+                                                monitor.lock().unwrap().add_bytes_transferred(
+                                                    transfer_result.map_or(0, |r| r.data_bytes()),
+                                                );
                                                 if !source.is_dir {
                                                     monitor
                                                         .lock()
@@ -1932,6 +2216,14 @@ impl<T: Transport + 'static> SyncEngine<T> {
                                         }
                                         Ok(())
                                     }
+                                    Err(e) if source_vanished(source, &e) => {
+                                        tracing::warn!(
+                                            "File vanished: {}",
+                                            task.dest_path.display()
+                                        );
+                                        stats.lock().unwrap().files_vanished += 1;
+                                        Ok(())
+                                    }
                                     Err(e) => {
                                         let mut stats = stats.lock().unwrap();
                                         stats.errors.push(SyncError {
@@ -2232,7 +2524,15 @@ impl<T: Transport + 'static> SyncEngine<T> {
     }
 
     /// Sync a single file (source is a file, not a directory)
+    #[tracing::instrument(name = "sync", skip_all, fields(source = %source.display(), dest = %destination.display()))]
     pub async fn sync_single_file(&self, source: &Path, destination: &Path) -> Result<SyncStats> {
+        let run = self.start_metrics();
+        let result = self.sync_file(source, destination).await;
+        self.record_metrics(run, &result);
+        result
+    }
+
+    async fn sync_file(&self, source: &Path, destination: &Path) -> Result<SyncStats> {
         let start_time = std::time::Instant::now();
 
         tracing::info!(
@@ -2245,6 +2545,9 @@ impl<T: Transport + 'static> SyncEngine<T> {
             files_scanned: 1,
             files_created: 0,
             files_updated: 0,
+            files_touched: 0,
+            files_backed_up: 0,
+            files_vanished: 0,
             files_skipped: 0,
             files_deleted: 0,
             bytes_transferred: 0,
@@ -2252,14 +2555,19 @@ impl<T: Transport + 'static> SyncEngine<T> {
             delta_bytes_saved: 0,
             files_compressed: 0,
             compression_bytes_saved: 0,
+            files_sparse: 0,
+            sparse_bytes_skipped: 0,
             files_verified: 0,
             verification_failures: 0,
+            files_verify_skipped: 0,
             duration: Duration::ZERO,
             bytes_would_add: 0,
             bytes_would_change: 0,
             bytes_would_delete: 0,
             dirs_created: 0,
             symlinks_created: 0,
+            cancelled: false,
+            phases: None,
             errors: Vec::new(),
         };
 
@@ -2298,29 +2606,26 @@ impl<T: Transport + 'static> SyncEngine<T> {
                     )))
                 })?
                 .to_owned();
-            if let Some(result) = transferrer
-                .create(
-                    &FileEntry {
-                        path: Arc::new(source.to_path_buf()),
-                        relative_path: Arc::new(PathBuf::from(filename)),
-                        size: metadata.len(),
-                        modified: metadata.modified()?,
-                        is_dir: false,
-                        is_symlink: false,
-                        symlink_target: None,
-                        is_sparse: false,
-                        allocated_size: metadata.len(),
-                        xattrs: None,
-                        inode: None,
-                        nlink: 1,
-                        acls: None,
-                        bsd_flags: None,
-                    },
-                    destination,
-                )
-                .await?
-            {
+            let entry = FileEntry {
+                path: Arc::new(source.to_path_buf()),
+                relative_path: Arc::new(PathBuf::from(filename)),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+                is_dir: false,
+                is_symlink: false,
+                symlink_target: None,
+                is_sparse: false,
+                allocated_size: metadata.len(),
+                xattrs: None,
+                inode: None,
+                nlink: 1,
+                acls: None,
+                bsd_flags: None,
+                win_attrs: None,
+            };
+            if let Some(result) = transferrer.create(&entry, destination).await? {
                 stats.bytes_transferred = result.bytes_written;
+                stats.add_sparse(&result);
 
                 // Track compression if used
                 if result.compression_used {
@@ -2333,25 +2638,18 @@ impl<T: Transport + 'static> SyncEngine<T> {
             }
             stats.files_created = 1;
 
-            // Verify transfer if verification is enabled
+            // Verify transfer if verification is enabled, re-sending on mismatch
             if self.verification_mode != ChecksumType::None && !self.dry_run {
                 let verifier = IntegrityVerifier::new(self.verification_mode, self.verify_on_write);
-                match verifier.verify_transfer(source, destination) {
-                    Ok(verified) => {
-                        if verified {
-                            stats.files_verified = 1;
-                        } else {
-                            stats.verification_failures = 1;
-                            tracing::warn!(
-                                "Verification failed for {}: checksums do not match",
-                                destination.display()
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!("Verification error for {}: {}", destination.display(), e);
-                        stats.verification_failures = 1;
-                    }
+                if !self.is_sampled_for_verify(&entry.relative_path) {
+                    stats.files_verify_skipped = 1;
+                } else if transferrer
+                    .verify_with_retry(&entry, destination, &verifier, self.verify_retries)
+                    .await
+                {
+                    stats.files_verified = 1;
+                } else {
+                    stats.verification_failures = 1;
                 }
             }
         } else {
@@ -2367,29 +2665,26 @@ impl<T: Transport + 'static> SyncEngine<T> {
                     )))
                 })?
                 .to_owned();
-            if let Some(result) = transferrer
-                .update(
-                    &FileEntry {
-                        path: Arc::new(source.to_path_buf()),
-                        relative_path: Arc::new(PathBuf::from(filename)),
-                        size: metadata.len(),
-                        modified: metadata.modified()?,
-                        is_dir: false,
-                        is_symlink: false,
-                        symlink_target: None,
-                        is_sparse: false,
-                        allocated_size: metadata.len(),
-                        xattrs: None,
-                        inode: None,
-                        nlink: 1,
-                        acls: None,
-                        bsd_flags: None,
-                    },
-                    destination,
-                )
-                .await?
-            {
+            let entry = FileEntry {
+                path: Arc::new(source.to_path_buf()),
+                relative_path: Arc::new(PathBuf::from(filename)),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+                is_dir: false,
+                is_symlink: false,
+                symlink_target: None,
+                is_sparse: false,
+                allocated_size: metadata.len(),
+                xattrs: None,
+                inode: None,
+                nlink: 1,
+                acls: None,
+                bsd_flags: None,
+                win_attrs: None,
+            };
+            if let Some(result) = transferrer.update(&entry, destination).await? {
                 stats.bytes_transferred = result.bytes_written;
+                stats.add_sparse(&result);
 
                 // Track delta sync if used
                 if result.used_delta() {
@@ -2411,25 +2706,18 @@ impl<T: Transport + 'static> SyncEngine<T> {
             }
             stats.files_updated = 1;
 
-            // Verify transfer if verification is enabled
+            // Verify transfer if verification is enabled, re-sending on mismatch
             if self.verification_mode != ChecksumType::None && !self.dry_run {
                 let verifier = IntegrityVerifier::new(self.verification_mode, self.verify_on_write);
-                match verifier.verify_transfer(source, destination) {
-                    Ok(verified) => {
-                        if verified {
-                            stats.files_verified = 1;
-                        } else {
-                            stats.verification_failures = 1;
-                            tracing::warn!(
-                                "Verification failed for {}: checksums do not match",
-                                destination.display()
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!("Verification error for {}: {}", destination.display(), e);
-                        stats.verification_failures = 1;
-                    }
+                if !self.is_sampled_for_verify(&entry.relative_path) {
+                    stats.files_verify_skipped = 1;
+                } else if transferrer
+                    .verify_with_retry(&entry, destination, &verifier, self.verify_retries)
+                    .await
+                {
+                    stats.files_verified = 1;
+                } else {
+                    stats.verification_failures = 1;
                 }
             }
         }
@@ -2439,6 +2727,13 @@ impl<T: Transport + 'static> SyncEngine<T> {
     }
 
     /// Get performance metrics (if performance monitoring is enabled)
+    /// Whether a transferred file is covered by verification (always true
+    /// unless --verify-sample restricts it to a subset)
+    fn is_sampled_for_verify(&self, relative_path: &Path) -> bool {
+        self.verify_sample
+            .is_none_or(|sample| sample.selects(relative_path))
+    }
+
     pub fn get_performance_metrics(&self) -> Option<PerformanceMetrics> {
         self.perf_monitor
             .as_ref()
@@ -2477,6 +2772,8 @@ mod tests {
             false,               // json
             ChecksumType::Fast,
             false, // verify_on_write
+            0,     // verify_retries
+            None,  // verify_sample
             SymlinkMode::Preserve,
             false, // preserve_xattrs
             false, // preserve_hardlinks
@@ -2522,6 +2819,39 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_sync_counts_sparse_holes_separately() {
+        use std::os::unix::fs::MetadataExt;
+
+        let source_dir = TempDir::new().unwrap();
+        let dest_dir = TempDir::new().unwrap();
+
+        // 8MB logical, nothing allocated
+        let image = source_dir.path().join("disk.img");
+        let file = fs::File::create(&image).unwrap();
+        file.set_len(8 * 1024 * 1024).unwrap();
+        drop(file);
+        fs::write(source_dir.path().join("small.txt"), "content").unwrap();
+
+        let engine = create_test_engine();
+        let stats = engine
+            .sync(source_dir.path(), dest_dir.path())
+            .await
+            .unwrap();
+
+        assert_eq!(stats.bytes_transferred, 8 * 1024 * 1024 + 7);
+        let meta = fs::metadata(&image).unwrap();
+        if meta.blocks() * 512 < meta.len() {
+            assert_eq!(stats.files_sparse, 1);
+            assert!(stats.sparse_bytes_skipped > 0);
+            assert_eq!(
+                stats.data_bytes(),
+                stats.bytes_transferred - stats.sparse_bytes_skipped
+            );
+        }
+    }
+
     #[tokio::test]
     async fn test_sync_with_subdirectories() {
         let source_dir = TempDir::new().unwrap();
@@ -2589,6 +2919,8 @@ mod tests {
             false,               // json
             ChecksumType::Fast,
             false, // verify_on_write
+            0,     // verify_retries
+            None,  // verify_sample
             SymlinkMode::Preserve,
             false, // preserve_xattrs
             false, // preserve_hardlinks
@@ -2900,6 +3232,49 @@ mod tests {
         assert_eq!(stats2.files_created, 0);
     }
 
+    #[tokio::test]
+    async fn test_sync_encrypted_names() {
+        use namecrypt::NameCipher;
+
+        let source_dir = TempDir::new().unwrap();
+        let dest_dir = TempDir::new().unwrap();
+        let restore_dir = TempDir::new().unwrap();
+        fs::create_dir(source_dir.path().join("photos")).unwrap();
+        fs::write(source_dir.path().join("photos/beach.jpg"), "waves").unwrap();
+
+        let cipher = NameCipher::from_secret(b"secret", &[7; namecrypt::SALT_LEN]);
+        let engine = create_test_engine().with_name_crypt(Some(NameCrypt::Encrypt(cipher.clone())));
+        let stats = engine
+            .sync(source_dir.path(), dest_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(stats.files_created, 2); // photos/ and beach.jpg
+
+        let stored = dest_dir
+            .path()
+            .join(cipher.encrypt_path(Path::new("photos/beach.jpg")).unwrap());
+        assert_eq!(fs::read_to_string(stored).unwrap(), "waves");
+        assert!(!dest_dir.path().join("photos").exists());
+
+        // The stored names are stable, so nothing is copied again
+        let stats = engine
+            .sync(source_dir.path(), dest_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(stats.files_skipped, 2);
+        assert_eq!(stats.files_created, 0);
+
+        let restore = create_test_engine().with_name_crypt(Some(NameCrypt::Decrypt(cipher)));
+        restore
+            .sync(dest_dir.path(), restore_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(restore_dir.path().join("photos/beach.jpg")).unwrap(),
+            "waves"
+        );
+    }
+
     // === Error Collection and max_errors Threshold Tests ===
 
     #[tokio::test]
@@ -2941,6 +3316,8 @@ mod tests {
             false,               // json
             ChecksumType::Fast,
             false, // verify_on_write
+            0,     // verify_retries
+            None,  // verify_sample
             SymlinkMode::Preserve,
             false, // preserve_xattrs
             false, // preserve_hardlinks
@@ -3022,6 +3399,8 @@ mod tests {
             false,               // json
             ChecksumType::Fast,
             false, // verify_on_write
+            0,     // verify_retries
+            None,  // verify_sample
             SymlinkMode::Preserve,
             false, // preserve_xattrs
             false, // preserve_hardlinks
@@ -3105,6 +3484,8 @@ mod tests {
             false,               // json
             ChecksumType::Fast,
             false, // verify_on_write
+            0,     // verify_retries
+            None,  // verify_sample
             SymlinkMode::Preserve,
             false, // preserve_xattrs
             false, // preserve_hardlinks
@@ -3185,6 +3566,8 @@ mod tests {
             false, // json
             ChecksumType::Fast,
             false, // verify_on_write
+            0,     // verify_retries
+            None,  // verify_sample
             SymlinkMode::Preserve,
             false, // preserve_xattrs
             false, // preserve_hardlinks
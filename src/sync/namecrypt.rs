@@ -0,0 +1,394 @@
+//! Deterministic filename encryption (`--encrypt-names`)
+//!
+//! Every path component is encrypted on its own with AES-SIV (RFC 5297) and
+//! stored as unpadded lowercase base32, which is safe on case-insensitive
+//! filesystems and object stores. SIV needs no nonce: the same name under the
+//! same key always encrypts to the same stored name, so a re-sync finds the
+//! previous copy and compares size and mtime as usual, with no mapping table
+//! to keep. The storage provider still sees how many entries each directory
+//! has and how deep the tree is, but not what anything is called.
+//!
+//! The key is stretched from the key file with Argon2id under a random salt
+//! kept next to it (`<key file>.salt`), created by the first `--encrypt-names`
+//! and needed again to restore.
+//!
+//! File contents are left as they are.
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes256;
+use argon2::Argon2;
+use cmac::{Cmac, Mac};
+use data_encoding::BASE32_NOPAD;
+use rand_core::{OsRng, RngCore};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use subtle::ConstantTimeEq;
+
+/// Length of the random salt stored next to the key file
+pub const SALT_LEN: usize = 16;
+
+/// AES block size; also the length of the synthetic IV
+const BLOCK_LEN: usize = 16;
+
+/// Longest file name most filesystems accept (in bytes)
+const MAX_NAME_LEN: usize = 255;
+
+/// AES-SIV-512 under a key derived from a user secret
+#[derive(Clone)]
+pub struct NameCipher {
+    /// S2V half of the key, as a ready CMAC state
+    mac: Cmac<Aes256>,
+    /// CTR half of the key
+    ctr: Aes256,
+}
+
+impl NameCipher {
+    /// Derive the key from a secret (a passphrase or random bytes) with
+    /// Argon2id
+    pub fn from_secret(secret: &[u8], salt: &[u8; SALT_LEN]) -> Self {
+        let mut key = [0u8; 64];
+        Argon2::default()
+            .hash_password_into(secret, salt, &mut key)
+            .expect("a 16-byte salt and 64-byte key are within Argon2's limits");
+        Self {
+            mac: <Cmac<Aes256> as KeyInit>::new(GenericArray::from_slice(&key[..32])),
+            ctr: Aes256::new(GenericArray::from_slice(&key[32..])),
+        }
+    }
+
+    /// Derive the key from the contents of `path` and its salt file
+    ///
+    /// A trailing newline is ignored, so `echo passphrase > key` works. With
+    /// `create_salt` a missing salt file is generated; otherwise it's an error.
+    pub fn from_key_file(path: &Path, create_salt: bool) -> std::io::Result<Self> {
+        let secret = std::fs::read(path)?;
+        let secret = secret
+            .strip_suffix(b"\n")
+            .map(|s| s.strip_suffix(b"\r").unwrap_or(s))
+            .unwrap_or(&secret);
+        if secret.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("name encryption key file {} is empty", path.display()),
+            ));
+        }
+        let salt = load_salt(&salt_path(path), create_salt)?;
+        Ok(Self::from_secret(secret, &salt))
+    }
+
+    /// The stored name for `name`
+    pub fn encrypt_name(&self, name: &str) -> Result<String, String> {
+        let sealed = siv_encrypt(&self.mac, &self.ctr, &[], name.as_bytes());
+        let encoded = BASE32_NOPAD.encode(&sealed).to_ascii_lowercase();
+        if encoded.len() > MAX_NAME_LEN {
+            return Err(format!(
+                "name is too long to encrypt ({} bytes, at most {})",
+                name.len(),
+                max_plaintext_len()
+            ));
+        }
+        Ok(encoded)
+    }
+
+    /// The original name behind a stored `name`
+    pub fn decrypt_name(&self, name: &str) -> Result<String, String> {
+        let sealed = BASE32_NOPAD
+            .decode(name.to_ascii_uppercase().as_bytes())
+            .map_err(|_| "not an encrypted name".to_string())?;
+        let plain = siv_decrypt(&self.mac, &self.ctr, &[], &sealed)
+            .ok_or_else(|| "name doesn't decrypt with this key".to_string())?;
+        String::from_utf8(plain).map_err(|_| "decrypted name isn't UTF-8".to_string())
+    }
+
+    /// Encrypt every component of a relative path
+    pub fn encrypt_path(&self, path: &Path) -> Result<PathBuf, String> {
+        self.map_path(path, |name| self.encrypt_name(name))
+    }
+
+    /// Decrypt every component of a relative path
+    pub fn decrypt_path(&self, path: &Path) -> Result<PathBuf, String> {
+        self.map_path(path, |name| self.decrypt_name(name))
+    }
+
+    fn map_path(
+        &self,
+        path: &Path,
+        map: impl Fn(&str) -> Result<String, String>,
+    ) -> Result<PathBuf, String> {
+        let mut mapped = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => {
+                    let name = name
+                        .to_str()
+                        .ok_or_else(|| "name isn't UTF-8".to_string())?;
+                    mapped.push(map(name)?);
+                }
+                other => mapped.push(other.as_os_str()),
+            }
+        }
+        Ok(mapped)
+    }
+}
+
+/// Which way `--encrypt-names` / `--decrypt-names` maps source names
+#[derive(Clone)]
+pub enum NameCrypt {
+    /// Plain source, encrypted destination (backing up)
+    Encrypt(NameCipher),
+    /// Encrypted source, plain destination (restoring)
+    Decrypt(NameCipher),
+}
+
+impl NameCrypt {
+    /// The destination's name for a source-relative path
+    pub fn dest_path(&self, path: &Path) -> Result<PathBuf, String> {
+        match self {
+            NameCrypt::Encrypt(cipher) => cipher.encrypt_path(path),
+            NameCrypt::Decrypt(cipher) => cipher.decrypt_path(path),
+        }
+    }
+}
+
+/// Where the salt for the key in `key_file` is kept
+pub fn salt_path(key_file: &Path) -> PathBuf {
+    let mut path = key_file.as_os_str().to_owned();
+    path.push(".salt");
+    PathBuf::from(path)
+}
+
+/// Read the salt at `path`, writing a fresh one first if it's missing and
+/// `create` is set
+fn load_salt(path: &Path, create: bool) -> std::io::Result<[u8; SALT_LEN]> {
+    match std::fs::read(path) {
+        Ok(salt) => salt.try_into().map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("name encryption salt {} is damaged", path.display()),
+            )
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && create => {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)?;
+            file.write_all(&salt)?;
+            file.sync_all()?;
+            Ok(salt)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(std::io::Error::new(
+            e.kind(),
+            format!(
+                "name encryption salt {} is missing; it was created next to the key by --encrypt-names",
+                path.display()
+            ),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// Longest name (in bytes) whose encryption still fits in [`MAX_NAME_LEN`]
+fn max_plaintext_len() -> usize {
+    MAX_NAME_LEN * 5 / 8 - BLOCK_LEN
+}
+
+/// RFC 5297 doubling in GF(2^128)
+fn dbl(block: [u8; BLOCK_LEN]) -> [u8; BLOCK_LEN] {
+    let value = u128::from_be_bytes(block);
+    let doubled = (value << 1) ^ if value >> 127 == 1 { 0x87 } else { 0 };
+    doubled.to_be_bytes()
+}
+
+fn cmac<M: Mac + Clone>(mac: &M, data: &[u8]) -> [u8; BLOCK_LEN] {
+    let mut mac = mac.clone();
+    mac.update(data);
+    let mut out = [0u8; BLOCK_LEN];
+    out.copy_from_slice(&mac.finalize().into_bytes());
+    out
+}
+
+fn xor(a: [u8; BLOCK_LEN], b: [u8; BLOCK_LEN]) -> [u8; BLOCK_LEN] {
+    (u128::from_ne_bytes(a) ^ u128::from_ne_bytes(b)).to_ne_bytes()
+}
+
+/// S2V over the associated data and then the plaintext
+fn s2v<M: Mac + Clone>(mac: &M, associated: &[&[u8]], plaintext: &[u8]) -> [u8; BLOCK_LEN] {
+    let mut d = cmac(mac, &[0u8; BLOCK_LEN]);
+    for data in associated {
+        d = xor(dbl(d), cmac(mac, data));
+    }
+    if plaintext.len() >= BLOCK_LEN {
+        // xorend: fold D into the last block
+        let mut t = plaintext.to_vec();
+        let tail = t.len() - BLOCK_LEN;
+        for (byte, d) in t[tail..].iter_mut().zip(d) {
+            *byte ^= d;
+        }
+        cmac(mac, &t)
+    } else {
+        let mut padded = [0u8; BLOCK_LEN];
+        padded[..plaintext.len()].copy_from_slice(plaintext);
+        padded[plaintext.len()] = 0x80;
+        cmac(mac, &xor(dbl(d), padded))
+    }
+}
+
+/// AES-CTR keyed off the synthetic IV (with bits 31 and 63 cleared)
+fn ctr<C: BlockEncrypt>(cipher: &C, iv: [u8; BLOCK_LEN], data: &mut [u8]) {
+    let mut q = iv;
+    q[8] &= 0x7f;
+    q[12] &= 0x7f;
+    let mut counter = u128::from_be_bytes(q);
+    for chunk in data.chunks_mut(BLOCK_LEN) {
+        let mut keystream = counter.to_be_bytes();
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut keystream));
+        for (byte, k) in chunk.iter_mut().zip(keystream) {
+            *byte ^= k;
+        }
+        counter = counter.wrapping_add(1);
+    }
+}
+
+/// IV || ciphertext
+fn siv_encrypt<M: Mac + Clone, C: BlockEncrypt>(
+    mac: &M,
+    cipher: &C,
+    associated: &[&[u8]],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let iv = s2v(mac, associated, plaintext);
+    let mut out = Vec::with_capacity(BLOCK_LEN + plaintext.len());
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(plaintext);
+    ctr(cipher, iv, &mut out[BLOCK_LEN..]);
+    out
+}
+
+/// The plaintext, or None if `sealed` wasn't produced under this key
+fn siv_decrypt<M: Mac + Clone, C: BlockEncrypt>(
+    mac: &M,
+    cipher: &C,
+    associated: &[&[u8]],
+    sealed: &[u8],
+) -> Option<Vec<u8>> {
+    if sealed.len() < BLOCK_LEN {
+        return None;
+    }
+    let (iv, ciphertext) = sealed.split_at(BLOCK_LEN);
+    let iv: [u8; BLOCK_LEN] = iv.try_into().ok()?;
+    let mut plaintext = ciphertext.to_vec();
+    ctr(cipher, iv, &mut plaintext);
+    let tag = s2v(mac, associated, &plaintext);
+    bool::from(tag.ct_eq(&iv)).then_some(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::Aes128;
+
+    const SALT: [u8; SALT_LEN] = *b"0123456789abcdef";
+
+    #[test]
+    fn test_rfc5297_deterministic_vector() {
+        // RFC 5297 appendix A.1
+        let key = hex::decode("fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff")
+            .unwrap();
+        let ad = hex::decode("101112131415161718191a1b1c1d1e1f2021222324252627").unwrap();
+        let plaintext = hex::decode("112233445566778899aabbccddee").unwrap();
+
+        let mac = <Cmac<Aes128> as KeyInit>::new(GenericArray::from_slice(&key[..16]));
+        let cipher = Aes128::new(GenericArray::from_slice(&key[16..]));
+        let sealed = siv_encrypt(&mac, &cipher, &[&ad], &plaintext);
+        assert_eq!(
+            hex::encode(&sealed),
+            "85632d07c6e8f37f950acd320a2ecc9340c02b9690c4dc04daef7f6afe5c"
+        );
+        assert_eq!(
+            siv_decrypt(&mac, &cipher, &[&ad], &sealed).unwrap(),
+            plaintext
+        );
+
+        let mut tampered = sealed.clone();
+        tampered[20] ^= 1;
+        assert!(siv_decrypt(&mac, &cipher, &[&ad], &tampered).is_none());
+    }
+
+    #[test]
+    fn test_encrypt_path_roundtrip() {
+        let cipher = NameCipher::from_secret(b"correct horse", &SALT);
+        let path = Path::new("Photos/2024/Beach day.jpg");
+
+        let stored = cipher.encrypt_path(path).unwrap();
+        assert_eq!(stored.components().count(), 3);
+        for component in stored.components() {
+            let name = component.as_os_str().to_str().unwrap();
+            assert!(name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c)));
+        }
+        assert_eq!(cipher.decrypt_path(&stored).unwrap(), path);
+
+        // Same name, same stored name - in any directory, on every run
+        let again = NameCipher::from_secret(b"correct horse", &SALT);
+        assert_eq!(again.encrypt_path(path).unwrap(), stored);
+        assert_eq!(
+            cipher.encrypt_name("2024").unwrap(),
+            stored
+                .components()
+                .nth(1)
+                .unwrap()
+                .as_os_str()
+                .to_str()
+                .unwrap()
+        );
+
+        let other = NameCipher::from_secret(b"battery staple", &SALT);
+        assert_ne!(other.encrypt_path(path).unwrap(), stored);
+        assert!(other.decrypt_path(&stored).is_err());
+        let resalted = NameCipher::from_secret(b"correct horse", b"fedcba9876543210");
+        assert_ne!(resalted.encrypt_path(path).unwrap(), stored);
+        assert!(cipher.decrypt_name(".DS_Store").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_name_length_limit() {
+        let cipher = NameCipher::from_secret(b"key", &SALT);
+        let longest = "a".repeat(max_plaintext_len());
+        assert!(cipher.encrypt_name(&longest).unwrap().len() <= MAX_NAME_LEN);
+        assert!(cipher.encrypt_name(&format!("{}a", longest)).is_err());
+        assert_eq!(cipher.encrypt_name("").unwrap().len(), 26);
+    }
+
+    #[test]
+    fn test_key_file() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let key = temp.path().join("key");
+        std::fs::write(&key, "correct horse\n").unwrap();
+
+        // Restoring needs the salt the first encrypting run created
+        assert!(NameCipher::from_key_file(&key, false).is_err());
+        let from_file = NameCipher::from_key_file(&key, true).unwrap();
+        let salt: [u8; SALT_LEN] = std::fs::read(salt_path(&key)).unwrap().try_into().unwrap();
+        assert_eq!(
+            from_file.encrypt_name("a").unwrap(),
+            NameCipher::from_secret(b"correct horse", &salt)
+                .encrypt_name("a")
+                .unwrap()
+        );
+        let restore = NameCipher::from_key_file(&key, false).unwrap();
+        assert_eq!(
+            restore.encrypt_name("a").unwrap(),
+            from_file.encrypt_name("a").unwrap()
+        );
+
+        std::fs::write(salt_path(&key), "short").unwrap();
+        assert!(NameCipher::from_key_file(&key, false).is_err());
+
+        std::fs::write(&key, "\n").unwrap();
+        assert!(NameCipher::from_key_file(&key, true).is_err());
+    }
+}
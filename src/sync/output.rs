@@ -40,9 +40,23 @@ pub enum SyncEvent {
         files_skipped: usize,
         files_deleted: usize,
         bytes_transferred: u64,
+        /// Holes of sparse files, counted in `bytes_transferred` but not copied
+        sparse_bytes_skipped: u64,
         duration_secs: f64,
         files_verified: usize,
         verification_failures: usize,
+        files_verify_skipped: usize,
+    },
+    /// `--estimate`: what a sync would transfer and how long at `rate`
+    Estimate {
+        files_new: u64,
+        bytes_new: u64,
+        files_changed: u64,
+        bytes_changed: u64,
+        files_unchanged: u64,
+        files_deleted: u64,
+        rate: u64,
+        transfer_secs: f64,
     },
     #[allow(dead_code)] // Event for verify-only mode (Phase 5c)
     VerificationResult {
@@ -141,9 +155,11 @@ mod tests {
             files_skipped: 20,
             files_deleted: 2,
             bytes_transferred: 123456,
+            sparse_bytes_skipped: 0,
             duration_secs: 12.5,
             files_verified: 15,
             verification_failures: 0,
+            files_verify_skipped: 0,
         };
 
         let json = serde_json::to_string(&event).unwrap();
@@ -12,8 +12,9 @@
 //   sy /source /dest --per-file-progress  # Show progress for large files
 //   sy /source /dest --quiet               # Hide all progress
 
-use indicatif::{ProgressBar, ProgressStyle};
-use std::path::Path;
+use crate::streaming::ProgressReport;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -70,6 +71,74 @@ pub fn create_progress_callback(
     })
 }
 
+/// Live bar for a server-mode push or pull, fed by the server's PROGRESS
+/// reports
+///
+/// It counts files: the totals grow while the source is still being
+/// walked, and bytes on the wire shrink with deltas and compression.
+pub fn create_remote_progress_bar() -> (ProgressBar, ProgressReport) {
+    let pb = ProgressBar::new(0);
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{spinner:.green} [{wide_bar:.cyan/blue}] {pos}/{len} files, {msg} sent ({elapsed})",
+        )
+        .unwrap()
+        .progress_chars("#>-"),
+    );
+    pb.enable_steady_tick(Duration::from_millis(100));
+
+    let bar = pb.clone();
+    let report: ProgressReport = Arc::new(move |progress: crate::streaming::Progress| {
+        bar.set_length(progress.files_total.max(progress.files));
+        bar.set_position(progress.files);
+        bar.set_message(HumanBytes(progress.bytes).to_string());
+    });
+    (pb, report)
+}
+
+/// Overall progress of a sync, reported to library callers after each file
+#[derive(Debug, Clone)]
+#[allow(dead_code)] // Read by library callers, not the CLI
+pub struct Progress {
+    /// Destination path of the file just handled
+    pub path: PathBuf,
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Callback for `SyncEngine::with_progress`; called from transfer tasks, so
+/// it must be cheap and thread-safe
+pub type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// What happened during a sync, for `Sync::run_with_events`
+#[derive(Debug)]
+#[allow(dead_code)] // Read by library callers, not the CLI
+#[allow(clippy::large_enum_variant)] // Done is sent once, at the end
+pub enum SyncEvent {
+    /// Scanning and planning are done; the transfer is about to start
+    Scanned {
+        files_scanned: u64,
+        /// Files, directories and deletions to handle
+        files_total: u64,
+        bytes_total: u64,
+    },
+    /// A file is about to be created or updated
+    FileStarted { path: PathBuf, size: u64 },
+    /// A file was created or updated
+    FileDone(Progress),
+    /// A destination file was deleted
+    Deleted { path: PathBuf },
+    /// A file failed; the sync carries on until `max_errors`
+    Error { path: PathBuf, message: String },
+    /// Always the last event
+    Done(crate::error::Result<super::SyncStats>),
+}
+
+/// Channel for `SyncEngine::with_events`
+pub type EventSender = tokio::sync::mpsc::UnboundedSender<SyncEvent>;
+
 /// Finish a progress bar
 ///
 /// Call this after the transfer completes to finalize the display
@@ -81,7 +150,6 @@ pub fn finish_progress_bar(pb: Arc<ProgressBar>) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
 
     #[test]
     fn test_min_size_constant() {
@@ -38,10 +38,10 @@ impl RateLimiter {
             self.available_tokens -= bytes_f64;
             Duration::ZERO
         } else {
-            // Not enough tokens, calculate sleep time
-            let deficit = bytes_f64 - self.available_tokens;
-            let sleep_secs = deficit / self.bytes_per_second as f64;
-            self.available_tokens = 0.0;
+            // Not enough tokens: go into debt, which the sleep pays off
+            // (refilling to zero would count the sleep twice)
+            self.available_tokens -= bytes_f64;
+            let sleep_secs = -self.available_tokens / self.bytes_per_second as f64;
             Duration::from_secs_f64(sleep_secs)
         }
     }
@@ -81,6 +81,19 @@ mod tests {
         assert_eq!(sleep, Duration::ZERO);
     }
 
+    #[test]
+    fn test_rate_limiter_sustained_rate() {
+        let mut limiter = RateLimiter::new(1024); // 1 KB/s
+        limiter.consume(1024);
+
+        // Each later KB waits a second more than the one before, as the
+        // time slept for one doesn't pay for the next
+        let first = limiter.consume(1024);
+        let second = limiter.consume(1024);
+        assert!(first.as_secs_f64() > 0.9 && first.as_secs_f64() < 1.1);
+        assert!(second.as_secs_f64() > 1.9 && second.as_secs_f64() < 2.1);
+    }
+
     #[test]
     fn test_rate_limiter_small_transfers() {
         let mut limiter = RateLimiter::new(1024 * 1024); // 1 MB/s
@@ -1,9 +1,10 @@
 use crate::error::{Result, SyncError};
+use crate::sync::dircache::DirectoryCache;
 use crossbeam_channel::{bounded, Receiver};
 use ignore::{WalkBuilder, WalkState};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
 #[cfg(unix)]
@@ -21,7 +22,6 @@ pub struct FileEntry {
     pub is_dir: bool,
     pub is_symlink: bool,
     pub symlink_target: Option<Arc<PathBuf>>,
-    #[allow(dead_code)] // Used for sparse file detection
     pub is_sparse: bool,
     #[allow(dead_code)] // Used for sparse file optimization
     pub allocated_size: u64, // Actual bytes allocated on disk
@@ -31,6 +31,7 @@ pub struct FileEntry {
     pub acls: Option<Vec<u8>>,                    // Serialized ACLs (if enabled)
     #[cfg_attr(not(target_os = "macos"), allow(dead_code))] // Only read on macOS
     pub bsd_flags: Option<u32>, // BSD file flags (hidden, immutable, etc.) - macOS only, None on other platforms
+    pub win_attrs: Option<u32>, // FILE_ATTRIBUTE bits (hidden, readonly, ...) - Windows only, None on other platforms
 }
 
 /// Detect if a file is sparse and get its allocated size
@@ -159,6 +160,19 @@ fn read_bsd_flags(_metadata: &std::fs::Metadata) -> Option<u32> {
     None
 }
 
+/// Read the FILE_ATTRIBUTE bits sy preserves (Windows only)
+#[cfg(windows)]
+fn read_win_attrs(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::windows::fs::MetadataExt;
+    Some(metadata.file_attributes() & crate::fs_util::WINDOWS_ATTRIBUTE_MASK)
+}
+
+/// Non-Windows platforms don't have FILE_ATTRIBUTE bits
+#[cfg(not(windows))]
+fn read_win_attrs(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ScanOptions {
     pub respect_gitignore: bool,
@@ -210,7 +224,13 @@ fn should_use_parallel(root: &Path) -> bool {
 /// Process a directory entry into a FileEntry
 /// Extracted to share between sequential and parallel scanners
 fn process_dir_entry(root: &Path, entry: ignore::DirEntry) -> Result<FileEntry> {
-    let path = entry.path().to_path_buf();
+    scan_entry(root, entry.path())
+}
+
+/// Stat a single `path` below `root` into the FileEntry a scan would report
+/// for it (for rescanning just the paths that changed)
+pub fn scan_entry(root: &Path, path: &Path) -> Result<FileEntry> {
+    let path = path.to_path_buf();
 
     // Use symlink_metadata to properly detect symlinks
     // entry.metadata() follows symlinks by default, making is_symlink() always false
@@ -251,6 +271,9 @@ fn process_dir_entry(root: &Path, entry: ignore::DirEntry) -> Result<FileEntry>
     // Read BSD file flags (macOS only, None on other platforms)
     let bsd_flags = read_bsd_flags(&metadata);
 
+    // Read Windows file attributes (Windows only, None on other platforms)
+    let win_attrs = read_win_attrs(&metadata);
+
     let modified = metadata.modified().map_err(|e| SyncError::ReadDirError {
         path: path.clone(),
         source: e,
@@ -271,6 +294,7 @@ fn process_dir_entry(root: &Path, entry: ignore::DirEntry) -> Result<FileEntry>
         nlink,
         acls,
         bsd_flags,
+        win_attrs,
     })
 }
 
@@ -373,6 +397,80 @@ impl Scanner {
     /// }
     /// ```
     pub fn scan_streaming(&self) -> Result<Box<dyn Iterator<Item = Result<FileEntry>> + Send>> {
+        let mut walker = self.walk_builder();
+        if !self.options.include_git_dir {
+            walker.filter_entry(|entry| {
+                // Skip .git directories
+                entry.file_name() != ".git"
+            });
+        }
+        Ok(self.walk(walker))
+    }
+
+    /// Scan like [`scan`](Self::scan), but take the files of directories
+    /// whose mtime matches `cache` from the cache instead of statting them
+    ///
+    /// Every directory is still statted, so changes further down an
+    /// unchanged directory are found. Returns the entries and how many
+    /// directories' files came from the cache.
+    pub fn scan_pruned(&self, cache: Arc<DirectoryCache>) -> Result<(Vec<FileEntry>, usize)> {
+        // An ignore file edited in place would leave its directory's mtime
+        // alone but change what belongs in the listing
+        if self.options.respect_gitignore {
+            return Ok((self.scan()?, 0));
+        }
+
+        let unchanged = Arc::new(Mutex::new(HashSet::new()));
+        let root_mtime = std::fs::metadata(&self.root)
+            .and_then(|meta| meta.modified())
+            .map_err(|e| SyncError::ReadDirError {
+                path: self.root.clone(),
+                source: e,
+            })?;
+        if cache.unchanged_files(Path::new(""), root_mtime).is_some() {
+            unchanged.lock().unwrap().insert(PathBuf::new());
+        }
+
+        let mut walker = self.walk_builder();
+        let include_git_dir = self.options.include_git_dir;
+        let (root, filter_cache, filter_unchanged) = (
+            self.root.clone(),
+            Arc::clone(&cache),
+            Arc::clone(&unchanged),
+        );
+        walker.filter_entry(move |entry| {
+            if !include_git_dir && entry.file_name() == ".git" {
+                return false;
+            }
+            let Ok(rel) = entry.path().strip_prefix(&root) else {
+                return true;
+            };
+            if entry.file_type().is_some_and(|t| t.is_dir()) {
+                // Seen before any of its entries, so they can be left out
+                let mtime = std::fs::symlink_metadata(entry.path()).and_then(|m| m.modified());
+                if mtime.is_ok_and(|mtime| filter_cache.unchanged_files(rel, mtime).is_some()) {
+                    filter_unchanged.lock().unwrap().insert(rel.to_path_buf());
+                }
+                true
+            } else {
+                // The cached listing stands in for the files of an
+                // unchanged directory
+                !rel.parent()
+                    .is_some_and(|dir| filter_unchanged.lock().unwrap().contains(dir))
+            }
+        });
+
+        let mut entries = self.walk(walker).collect::<Result<Vec<_>>>()?;
+        let unchanged = std::mem::take(&mut *unchanged.lock().unwrap());
+        for dir in &unchanged {
+            if let Some(files) = cache.get_cached_files(dir) {
+                entries.extend(files.iter().map(|file| file.to_file_entry(&self.root)));
+            }
+        }
+        Ok((entries, unchanged.len()))
+    }
+
+    fn walk_builder(&self) -> WalkBuilder {
         let mut walker = WalkBuilder::new(&self.root);
         walker
             .hidden(false) // Don't skip hidden files by default
@@ -382,13 +480,6 @@ impl Scanner {
             .threads(self.threads) // Parallel walking if threads > 1
             .follow_links(self.follow_links); // Follow symlinks with automatic loop detection
 
-        if !self.options.include_git_dir {
-            walker.filter_entry(|entry| {
-                // Skip .git directories
-                entry.file_name() != ".git"
-            });
-        }
-
         // Also respect .gitignore files even outside git repos
         // This allows .gitignore to work in non-git directories
         if self.options.respect_gitignore {
@@ -397,7 +488,10 @@ impl Scanner {
                 walker.add_ignore(&gitignore_path);
             }
         }
+        walker
+    }
 
+    fn walk(&self, walker: WalkBuilder) -> Box<dyn Iterator<Item = Result<FileEntry>> + Send> {
         // Determine whether to use parallel scanning
         // - If auto_select: check directory size (parallel has overhead for small dirs)
         // - If explicit threads: respect user's choice
@@ -411,15 +505,15 @@ impl Scanner {
         };
 
         if use_parallel {
-            Ok(Box::new(ParallelStreamingScanner::new(
+            Box::new(ParallelStreamingScanner::new(
                 self.root.clone(),
                 walker.build_parallel(),
-            )))
+            ))
         } else {
-            Ok(Box::new(StreamingScanner {
+            Box::new(StreamingScanner {
                 root: self.root.clone(),
                 walker: walker.build(),
-            }))
+            })
         }
     }
 }
@@ -1478,4 +1572,42 @@ mod tests {
         let entries = scanner.scan().unwrap();
         assert_eq!(entries.len(), 1000);
     }
+
+    #[test]
+    fn test_scan_pruned_reuses_unchanged_directories() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("d/e")).unwrap();
+        fs::write(root.join("r.txt"), "r").unwrap();
+        fs::write(root.join("d/a.txt"), "a").unwrap();
+        fs::write(root.join("d/e/c.txt"), "c").unwrap();
+
+        let root_mtime = fs::metadata(root).unwrap().modified().unwrap();
+        let mut cache = DirectoryCache::new();
+        cache.record_scan(root_mtime, &Scanner::new(root).scan().unwrap());
+        let cache = Arc::new(cache);
+
+        // Rewriting a file leaves its directory's mtime alone, so the cached
+        // entry stands in for it
+        fs::write(root.join("d/a.txt"), "longer").unwrap();
+        let (entries, unchanged) = Scanner::new(root).scan_pruned(Arc::clone(&cache)).unwrap();
+        assert_eq!(unchanged, 3);
+        assert_eq!(entries.len(), 5);
+        let a = entries
+            .iter()
+            .find(|e| e.relative_path.as_path() == Path::new("d/a.txt"))
+            .unwrap();
+        assert_eq!(a.size, 1);
+
+        // A new file changes its directory; the others are still reused
+        fs::write(root.join("d/e/new.txt"), "new").unwrap();
+        let later = filetime::FileTime::from_unix_time(2_000_000_000, 0);
+        filetime::set_file_mtime(root.join("d/e"), later).unwrap();
+        let (entries, unchanged) = Scanner::new(root).scan_pruned(cache).unwrap();
+        assert_eq!(unchanged, 2);
+        assert_eq!(entries.len(), 6);
+        assert!(entries
+            .iter()
+            .any(|e| e.relative_path.as_path() == Path::new("d/e/new.txt")));
+    }
 }
@@ -0,0 +1,206 @@
+//! Bitrot scrubbing (`sy scrub DIR`)
+//!
+//! Re-reads every file under a directory and checks it against the checksum
+//! database stored alongside it. A file whose size and mtime are unchanged but
+//! whose content hash differs has silently changed on disk - that's bitrot.
+//! Files that are new or were legitimately modified get their checksum
+//! (re)recorded, so running scrub periodically keeps the database current.
+
+use super::checksumdb::ChecksumDatabase;
+use super::ratelimit::RateLimiter;
+use super::scanner::Scanner;
+use crate::error::Result;
+use crate::integrity::Checksum;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Read buffer size for hashing
+const SCRUB_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Scrub configuration
+#[derive(Debug, Clone, Default)]
+pub struct ScrubOptions {
+    /// Maximum read rate in bytes per second (None = unlimited)
+    pub bwlimit: Option<u64>,
+}
+
+/// Outcome of a scrub run
+#[derive(Debug, Default)]
+pub struct ScrubReport {
+    pub files_scanned: usize,
+    /// Files whose content matched the recorded checksum
+    pub files_verified: usize,
+    /// New or modified files whose checksum was (re)recorded
+    pub files_recorded: usize,
+    pub bytes_read: u64,
+    /// Files whose content changed while size and mtime stayed identical
+    pub corrupted: Vec<PathBuf>,
+    pub errors: Vec<(PathBuf, String)>,
+    pub duration: Duration,
+}
+
+/// Hash a file with xxHash3, sleeping as needed to respect the rate limit
+fn hash_file_throttled(path: &Path, limiter: &mut Option<RateLimiter>) -> std::io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0u8; SCRUB_CHUNK_SIZE];
+
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+
+        if let Some(limiter) = limiter.as_mut() {
+            let delay = limiter.consume(n as u64);
+            if !delay.is_zero() {
+                std::thread::sleep(delay);
+            }
+        }
+    }
+
+    Ok(hasher.digest())
+}
+
+/// Scrub all regular files under `root` against its checksum database
+///
+/// Files are read sequentially so that, combined with `bwlimit`, a scrub
+/// can run in the background without starving other I/O.
+pub fn scrub(root: &Path, options: &ScrubOptions) -> Result<ScrubReport> {
+    let start = Instant::now();
+    let db = ChecksumDatabase::open(root)?;
+    let mut limiter = options.bwlimit.map(RateLimiter::new);
+    let mut report = ScrubReport::default();
+
+    for entry in Scanner::new(root).scan_streaming()? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.errors.push((root.to_path_buf(), e.to_string()));
+                continue;
+            }
+        };
+
+        if entry.is_dir
+            || entry.is_symlink
+            || entry.relative_path.starts_with(ChecksumDatabase::DB_DIR)
+        {
+            continue;
+        }
+        report.files_scanned += 1;
+
+        let path = entry.path.as_path();
+        let hash = match hash_file_throttled(path, &mut limiter) {
+            Ok(hash) => hash,
+            Err(e) => {
+                report.errors.push((path.to_path_buf(), e.to_string()));
+                continue;
+            }
+        };
+        report.bytes_read += entry.size;
+        let checksum = Checksum::Fast(hash.to_le_bytes().to_vec());
+
+        // A file written to while we were reading isn't bitrot; record it next time
+        let unchanged = std::fs::symlink_metadata(path)
+            .and_then(|m| Ok(m.len() == entry.size && m.modified()? == entry.modified))
+            .unwrap_or(false);
+        if !unchanged {
+            tracing::debug!("{} changed during scrub, skipping", path.display());
+            continue;
+        }
+
+        let stored = db.lookup(path)?;
+        match stored {
+            Some(stored)
+                if stored.matches_metadata(entry.modified, entry.size)
+                    && matches!(stored.checksum, Checksum::Fast(_)) =>
+            {
+                if stored.checksum == checksum {
+                    report.files_verified += 1;
+                } else {
+                    // Keep the known-good checksum so the file stays flagged
+                    tracing::warn!(
+                        "Content changed without metadata change: {}",
+                        path.display()
+                    );
+                    report.corrupted.push(entry.relative_path.to_path_buf());
+                }
+            }
+            _ => {
+                db.store_checksum(path, entry.modified, entry.size, &checksum)?;
+                report.files_recorded += 1;
+            }
+        }
+    }
+
+    report.duration = start.elapsed();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::FileTime;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_scrub_records_then_verifies() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), b"hello").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/b.txt"), b"world").unwrap();
+
+        let first = scrub(dir.path(), &ScrubOptions::default()).unwrap();
+        assert_eq!(first.files_scanned, 2);
+        assert_eq!(first.files_recorded, 2);
+        assert_eq!(first.files_verified, 0);
+
+        let second = scrub(dir.path(), &ScrubOptions::default()).unwrap();
+        assert_eq!(second.files_scanned, 2);
+        assert_eq!(second.files_verified, 2);
+        assert!(second.corrupted.is_empty());
+    }
+
+    #[test]
+    fn test_scrub_detects_silent_change() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("data.bin");
+        fs::write(&file, b"original").unwrap();
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&file).unwrap());
+
+        scrub(dir.path(), &ScrubOptions::default()).unwrap();
+
+        // Same size, same mtime, different bytes
+        fs::write(&file, b"Original").unwrap();
+        filetime::set_file_mtime(&file, mtime).unwrap();
+
+        let report = scrub(dir.path(), &ScrubOptions::default()).unwrap();
+        assert_eq!(report.corrupted, vec![PathBuf::from("data.bin")]);
+
+        // Still flagged on the next run
+        let report = scrub(dir.path(), &ScrubOptions::default()).unwrap();
+        assert_eq!(report.corrupted.len(), 1);
+    }
+
+    #[test]
+    fn test_scrub_rerecords_modified_file() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, b"v1").unwrap();
+        filetime::set_file_mtime(&file, FileTime::from_unix_time(1_000_000, 0)).unwrap();
+
+        scrub(dir.path(), &ScrubOptions::default()).unwrap();
+
+        fs::write(&file, b"v2 longer").unwrap();
+        filetime::set_file_mtime(&file, FileTime::from_unix_time(2_000_000, 0)).unwrap();
+
+        let report = scrub(dir.path(), &ScrubOptions::default()).unwrap();
+        assert!(report.corrupted.is_empty());
+        assert_eq!(report.files_recorded, 1);
+    }
+}
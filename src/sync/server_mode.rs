@@ -1,117 +1,491 @@
 //! Server mode sync - uses subprocess protocol for remote operations.
 //!
-//! Supports both SSH (remote) and local subprocess for testing.
+//! Supports both SSH (remote) and local subprocess for testing, plus an
+//! in-process pipeline for local-to-local transfers and pushes to S3.
 
 use anyhow::Result;
-use std::path::Path;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
+use tokio_util::sync::CancellationToken;
 
+use crate::config::SshSettings;
+use crate::error::ErrorCode;
+use crate::filter::FilterEngine;
+use crate::metrics::{self, MetricsSnapshot};
 use crate::path::SyncPath;
+use crate::retry::RetryConfig;
 use crate::ssh::config::SshConfig;
-use crate::streaming::StreamingSync;
-use crate::sync::SyncStats;
+use crate::streaming::channel::SyncStats as StreamingStats;
+use crate::streaming::{
+    AuditLog, Backup, IoOptions, OwnerMap, ProgressReport, ReceivePolicy, RemoteTree,
+    StreamingSync, TransferLog,
+};
+use crate::sync::estimate::Estimate;
+use crate::sync::{SyncError, SyncStats};
 use crate::transport::server::ServerSession;
 
 /// Sync from local source to remote destination (push)
+///
+/// With `dest_manifest`, the server keeps a manifest of the destination and
+/// we cache a copy, so later pushes skip unchanged files in the Initial
+/// Exchange. `filter` is applied on both ends, `owners` maps what `-o`/`-g`
+/// send, and the server moves old versions aside as `backup` says. With
+/// `scope`, only those
+/// source paths are synced (see [`StreamingSync::with_scope`]). `progress`
+/// gets the server's PROGRESS reports. Cancelling `cancel` stops the push
+/// and tells the server, which cleans up before the session ends.
+#[allow(clippy::too_many_arguments)]
 pub async fn sync_push(
     source: &Path,
     dest: &SyncPath,
     delete: bool,
     compress: bool,
+    io: IoOptions,
+    filter: FilterEngine,
+    owners: OwnerMap,
+    backup: Option<Backup>,
+    transfer_log: Option<TransferLog>,
+    ssh: &SshSettings,
+    dest_manifest: bool,
+    scope: Option<Vec<PathBuf>>,
+    progress: Option<ProgressReport>,
+    cancel: CancellationToken,
 ) -> Result<SyncStats> {
-    let session = match dest {
-        SyncPath::Remote { host, user, .. } => {
-            let config = if let Some(user) = user {
-                SshConfig {
-                    hostname: host.clone(),
-                    user: user.clone(),
-                    ..Default::default()
-                }
-            } else {
-                crate::ssh::config::parse_ssh_config(host)?
-            };
-            ServerSession::connect_ssh(&config, dest.path()).await?
+    #[cfg(feature = "s3")]
+    if dest.is_s3() {
+        return push_s3(source, dest, delete, io, filter, scope, cancel).await;
+    }
+    let start = Instant::now();
+    let mut server_args = io.server_args();
+    if let Some(backup) = &backup {
+        server_args.extend(backup.server_args());
+    }
+
+    // Every session, and the SFTP fallback, gets a sync of its own
+    let new_sync = |scope: Option<Vec<PathBuf>>| {
+        let sync = StreamingSync::new(
+            source.to_path_buf(),
+            dest.path().to_path_buf(),
+            delete,
+            compress,
+        )
+        .with_io(io)
+        .with_filter(filter.clone())
+        .with_owner_map(owners.clone())
+        .with_scope(scope)
+        .with_transfer_log(transfer_log.clone())
+        .with_cancel(cancel.clone());
+        with_progress(sync, progress.clone())
+    };
+    let manifest = dest_manifest.then(|| manifest_cache(dest)).flatten();
+
+    // A retry sends only the files that failed
+    let result = with_retries(&io, |failed| {
+        let scope = failed.or_else(|| scope.clone());
+        let sync = new_sync(scope.clone()).with_dest_manifest(manifest.clone());
+        // SFTP has no server to keep a manifest
+        let sftp = || new_sync(scope);
+        let server_args = &server_args;
+        async move { push_session(&sync, sftp, dest, server_args, ssh).await }
+    })
+    .await;
+    Ok(record_metrics(result.map(make_sync_stats), start, true)?)
+}
+
+/// One push over a fresh connection, or over SFTP with the sync `sftp`
+/// builds when the destination has no `sy`
+async fn push_session(
+    sync: &StreamingSync,
+    sftp: impl FnOnce() -> StreamingSync,
+    dest: &SyncPath,
+    server_args: &[String],
+    ssh: &SshSettings,
+) -> crate::error::Result<StreamingStats> {
+    let session = connect(dest, server_args, ssh).await?;
+    let exit = session.exit();
+    let (mut stdin, mut stdout) = session.split();
+
+    let mut result = sync.push(&mut stdout, &mut stdin).await;
+    // With our end closed the server winds down, e.g. removes its temp
+    // files after our FATAL, and ssh exits
+    drop((stdin, stdout));
+    if result.is_err() && exit.clone().command_not_found().await {
+        result = push_sftp(&sftp(), dest, ssh).await;
+    }
+    exit.reap().await;
+    result
+}
+
+/// Run `session` and then, up to `io.retries` times with backoff, run it
+/// again: all of it if the connection dropped, or just for the files that
+/// failed with a transient error, which it gets as paths relative to the
+/// source (a pull's server can't narrow its scan, so it sends everything)
+async fn with_retries<F, Fut>(
+    io: &IoOptions,
+    mut session: F,
+) -> crate::error::Result<StreamingStats>
+where
+    F: FnMut(Option<Vec<PathBuf>>) -> Fut,
+    Fut: Future<Output = crate::error::Result<StreamingStats>>,
+{
+    let backoff = RetryConfig::new(io.retries, io.retry_delay);
+    let mut attempt = 0;
+    let mut stats = loop {
+        match session(None).await {
+            Err(e) if connection_dropped(&e) && attempt < io.retries => {
+                back_off(&backoff, &mut attempt, &e.to_string()).await
+            }
+            result => break result?,
         }
-        SyncPath::Local { path, .. } => ServerSession::connect_local(path).await?,
-        SyncPath::S3 { .. } | SyncPath::Gcs { .. } => {
-            anyhow::bail!("Cloud storage paths not supported in server mode")
+    };
+    while attempt < io.retries && !stats.cancelled {
+        let failed = stats.transient_failures();
+        if failed.is_empty() {
+            break;
         }
+        let why = format!("{} file(s) failed with a transient error", failed.len());
+        back_off(&backoff, &mut attempt, &why).await;
+        let paths = failed.iter().map(PathBuf::from).collect();
+        match session(Some(paths)).await {
+            Ok(again) => stats.absorb_retry(&failed, again),
+            Err(e) if connection_dropped(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(stats)
+}
+
+/// Wait out the backoff before retry `attempt + 1`, saying why
+async fn back_off(backoff: &RetryConfig, attempt: &mut u32, why: &str) {
+    let delay = backoff.calculate_delay(*attempt);
+    *attempt += 1;
+    tracing::warn!(
+        "{}; retrying in {:?} ({}/{})",
+        why,
+        delay,
+        attempt,
+        backoff.max_attempts
+    );
+    crate::metrics::global().record_retry();
+    tokio::time::sleep(delay).await;
+}
+
+/// Whether `e` says the connection went away rather than the sync failing
+fn connection_dropped(e: &crate::error::SyncError) -> bool {
+    use std::io::ErrorKind;
+
+    match e {
+        crate::error::SyncError::Io(io) => matches!(
+            io.kind(),
+            ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::TimedOut
+        ),
+        e => e.is_retryable(),
+    }
+}
+
+/// Push `sync` to a host without `sy` over plain SFTP (see [`SftpStorage`]):
+/// whole files, no delta transfers
+///
+/// [`SftpStorage`]: crate::transport::sftp::SftpStorage
+async fn push_sftp(
+    sync: &StreamingSync,
+    dest: &SyncPath,
+    ssh: &SshSettings,
+) -> crate::error::Result<crate::streaming::channel::SyncStats> {
+    use crate::transport::sftp::SftpStorage;
+
+    let SyncPath::Remote { host, user, .. } = dest else {
+        return Err(anyhow::anyhow!("Not a remote path: {}", dest).into());
     };
+    tracing::warn!(
+        "sy isn't installed on {}; falling back to SFTP (whole files, no delta transfers)",
+        host
+    );
+    let config = ssh_config(host, user.as_deref(), ssh)?;
+    let storage = SftpStorage::connect(&config, dest.path()).await?;
+    sync.local_to(storage).await
+}
 
+/// Push into an S3 bucket: with no server at the other end, the whole
+/// pipeline runs here and writes objects (see [`S3Storage`])
+///
+/// [`S3Storage`]: crate::transport::s3_storage::S3Storage
+#[cfg(feature = "s3")]
+async fn push_s3(
+    source: &Path,
+    dest: &SyncPath,
+    delete: bool,
+    io: IoOptions,
+    filter: FilterEngine,
+    scope: Option<Vec<PathBuf>>,
+    cancel: CancellationToken,
+) -> Result<SyncStats> {
+    use crate::transport::s3_storage::S3Storage;
+
+    let SyncPath::S3 {
+        bucket,
+        key,
+        region,
+        endpoint,
+        ..
+    } = dest
+    else {
+        anyhow::bail!("Not an S3 path: {}", dest);
+    };
+    let start = Instant::now();
+    let storage = S3Storage::new(
+        bucket,
+        key.trim_matches('/'),
+        region.clone(),
+        endpoint.clone(),
+    )?;
+    let sync = StreamingSync::new(source.to_path_buf(), PathBuf::from(key), delete, false)
+        .with_io(io)
+        .with_filter(filter)
+        .with_scope(scope)
+        .with_cancel(cancel);
+
+    let result = sync.local_to(storage).await.map(make_sync_stats);
+    Ok(record_metrics(result, start, true)?)
+}
+
+/// Compare a local source with a remote destination without transferring
+/// anything (`--estimate`)
+pub async fn estimate_push(
+    source: &Path,
+    dest: &SyncPath,
+    delete: bool,
+    io: IoOptions,
+    filter: FilterEngine,
+    ssh: &SshSettings,
+) -> Result<Estimate> {
+    let session = connect(dest, &io.server_args(), ssh).await?;
     let (mut stdin, mut stdout) = session.split();
 
     let sync = StreamingSync::new(
         source.to_path_buf(),
         dest.path().to_path_buf(),
         delete,
-        compress,
-    );
+        false,
+    )
+    .with_io(io)
+    .with_filter(filter);
 
-    let stats = sync.push(&mut stdout, &mut stdin).await?;
+    Ok(sync.estimate_push(&mut stdout, &mut stdin).await?)
+}
 
-    Ok(make_sync_stats(stats))
+/// Open a read-only browse session on a remote tree (`sy mount`)
+#[allow(dead_code)] // Only with the mount feature
+pub async fn browse(
+    remote: &SyncPath,
+    ssh: &SshSettings,
+) -> Result<RemoteTree<tokio::process::ChildStdout, tokio::process::ChildStdin>> {
+    let session = connect(remote, &[], ssh).await?;
+    let (stdin, stdout) = session.split();
+    RemoteTree::open(stdout, stdin).await
 }
 
 /// Sync from remote source to local destination (pull)
+///
+/// The server applies `filter` to what it sends; `owners` maps the owners it
+/// sends under `-o`/`-g`, and `backup` says where old versions of local
+/// files go. `progress` gets the server's PROGRESS reports.
+/// Cancelling `cancel` stops the pull and tells the server, which cleans up
+/// before the session ends.
+#[allow(clippy::too_many_arguments)]
 pub async fn sync_pull(
     source: &SyncPath,
     dest: &Path,
     delete: bool,
     compress: bool,
+    io: IoOptions,
+    filter: FilterEngine,
+    owners: OwnerMap,
+    backup: Option<Backup>,
+    transfer_log: Option<TransferLog>,
+    ssh: &SshSettings,
+    audit: Option<AuditLog>,
+    policy: ReceivePolicy,
+    progress: Option<ProgressReport>,
+    cancel: CancellationToken,
 ) -> Result<SyncStats> {
-    let session = match source {
-        SyncPath::Remote { host, user, .. } => {
-            let config = if let Some(user) = user {
-                SshConfig {
-                    hostname: host.clone(),
-                    user: user.clone(),
-                    ..Default::default()
-                }
-            } else {
-                crate::ssh::config::parse_ssh_config(host)?
-            };
-            ServerSession::connect_ssh(&config, source.path()).await?
-        }
-        SyncPath::Local { path, .. } => ServerSession::connect_local(path).await?,
-        SyncPath::S3 { .. } | SyncPath::Gcs { .. } => {
-            anyhow::bail!("Cloud storage paths not supported in server mode")
-        }
-    };
-
-    let (mut stdin, mut stdout) = session.split();
+    let start = Instant::now();
+    let server_args = io.server_args();
 
     let sync = StreamingSync::new(
         dest.to_path_buf(),
         source.path().to_path_buf(),
         delete,
         compress,
-    );
+    )
+    .with_io(io)
+    .with_filter(filter)
+    .with_owner_map(owners)
+    .with_audit(audit.map(|log| log.with_peer(source.to_string())))
+    .with_policy(policy)
+    .with_backup(backup)
+    .with_transfer_log(transfer_log)
+    .with_cancel(cancel);
+    let sync = with_progress(sync, progress);
+
+    let result = with_retries(&io, |_| pull_session(&sync, source, &server_args, ssh)).await;
+    Ok(record_metrics(result.map(make_sync_stats), start, false)?)
+}
+
+/// One pull over a fresh connection
+async fn pull_session(
+    sync: &StreamingSync,
+    source: &SyncPath,
+    server_args: &[String],
+    ssh: &SshSettings,
+) -> crate::error::Result<StreamingStats> {
+    let session = connect(source, server_args, ssh).await?;
+    let exit = session.exit();
+    let (mut stdin, mut stdout) = session.split();
+
+    let mut result = sync.pull(&mut stdout, &mut stdin).await;
+    drop((stdin, stdout));
+    if result.is_err() && exit.clone().command_not_found().await {
+        // A pull's source has to run sy; only pushes fall back to SFTP
+        result = Err(anyhow::anyhow!(
+            "sy isn't installed on {}; pulls need it there (pushes fall back to SFTP)",
+            source
+        )
+        .into());
+    }
+    exit.reap().await;
+    result
+}
+
+/// Sync between two local directories through the streaming pipeline, in-process
+///
+/// No subprocess or framing for whole files: they're copied in the kernel.
+#[allow(dead_code)] // Library API; CLI local-to-local syncs still use SyncEngine
+pub async fn sync_local(
+    source: &Path,
+    dest: &Path,
+    delete: bool,
+    io: IoOptions,
+    audit: Option<AuditLog>,
+) -> Result<SyncStats> {
+    let start = Instant::now();
+    let sync = StreamingSync::new(source.to_path_buf(), dest.to_path_buf(), delete, false)
+        .with_io(io)
+        .with_audit(audit);
 
-    let stats = sync.pull(&mut stdout, &mut stdin).await?;
+    let result = sync.local().await.map(make_sync_stats);
+    Ok(record_metrics(result, start, false)?)
+}
 
-    Ok(make_sync_stats(stats))
+fn with_progress(sync: StreamingSync, progress: Option<ProgressReport>) -> StreamingSync {
+    match progress {
+        Some(report) => sync.with_progress(move |p| report(p)),
+        None => sync,
+    }
+}
+
+/// Where the manifest of `dest` is cached on this machine
+fn manifest_cache(dest: &SyncPath) -> Option<PathBuf> {
+    let key = blake3::hash(dest.to_string().as_bytes()).to_hex();
+    dirs::cache_dir().map(|dir| dir.join("sy").join("manifests").join(&key[..32]))
+}
+
+/// Start `sy --server` for the remote end of a sync
+async fn connect(
+    remote: &SyncPath,
+    server_args: &[String],
+    ssh: &SshSettings,
+) -> Result<ServerSession> {
+    Ok(match remote {
+        SyncPath::Remote { host, user, .. } => {
+            let config = ssh_config(host, user.as_deref(), ssh)?;
+            ServerSession::connect_ssh(&config, remote.path(), server_args).await?
+        }
+        SyncPath::Local { path, .. } => ServerSession::connect_local(path, server_args).await?,
+        SyncPath::S3 { .. } | SyncPath::Gcs { .. } => {
+            anyhow::bail!(
+                "Of cloud storage, only S3 destinations are supported (with the s3 feature)"
+            )
+        }
+    })
+}
+
+/// How to reach `host`, as `user` when given
+fn ssh_config(host: &str, user: Option<&str>, ssh: &SshSettings) -> Result<SshConfig> {
+    let config = if let Some(user) = user {
+        SshConfig {
+            hostname: host.to_string(),
+            user: user.to_string(),
+            ..Default::default()
+        }
+    } else {
+        crate::ssh::config::parse_ssh_config(host)?
+    };
+    Ok(config.with_host_keys(ssh, host))
+}
+
+/// Time the run and record it into the global metrics
+fn record_metrics(
+    result: crate::error::Result<SyncStats>,
+    start: Instant,
+    dest_is_remote: bool,
+) -> crate::error::Result<SyncStats> {
+    let result = result.map(|stats| SyncStats {
+        duration: start.elapsed(),
+        ..stats
+    });
+    let run = match &result {
+        Ok(stats) => MetricsSnapshot::from_sync(stats, None, dest_is_remote),
+        Err(_) => MetricsSnapshot::failed(start.elapsed()),
+    };
+    metrics::global().record(&run);
+    result
 }
 
 fn make_sync_stats(stats: crate::streaming::channel::SyncStats) -> SyncStats {
+    let (vanished, errors): (Vec<_>, Vec<_>) = stats
+        .errors
+        .into_iter()
+        .partition(|e| e.code == ErrorCode::Vanished as u16);
     SyncStats {
-        files_scanned: stats.files_ok,
-        files_created: stats.files_ok,
-        files_updated: 0,
+        files_scanned: stats.files_ok + stats.files_err + stats.counts.skipped,
+        files_created: stats.counts.created,
+        files_updated: stats.counts.updated,
+        files_touched: stats.counts.touched,
+        files_vanished: vanished.len(),
         files_deleted: stats.deleted as usize,
-        files_skipped: 0,
+        files_backed_up: stats.backed_up,
+        files_skipped: stats.counts.skipped as usize,
         bytes_transferred: stats.bytes_transferred,
         files_delta_synced: stats.delta_files as usize,
         delta_bytes_saved: stats.delta_bytes_saved,
         files_compressed: 0,
         compression_bytes_saved: 0,
+        files_sparse: 0,
+        sparse_bytes_skipped: 0,
         files_verified: 0,
-        verification_failures: 0,
-        duration: Instant::now().elapsed(),
-        bytes_would_add: 0,
-        bytes_would_change: 0,
-        bytes_would_delete: 0,
+        verification_failures: stats.checksum_mismatches as usize,
+        files_verify_skipped: 0,
+        duration: stats.duration,
+        bytes_would_add: stats.would.add,
+        bytes_would_change: stats.would.change,
+        bytes_would_delete: stats.would.delete,
         dirs_created: stats.dirs_created,
         symlinks_created: stats.symlinks_created,
-        errors: vec![],
+        cancelled: stats.cancelled,
+        phases: Some(stats.phases),
+        errors: errors
+            .into_iter()
+            .map(|e| SyncError {
+                path: PathBuf::from(e.path),
+                error: e.message,
+                action: "transfer".to_string(),
+            })
+            .collect(),
     }
 }
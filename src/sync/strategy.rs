@@ -48,6 +48,8 @@ pub struct StrategyPlanner {
     ignore_existing: bool,
     /// Integrity verifier for checksum computation
     verifier: Option<IntegrityVerifier>,
+    /// Destination is FAT/exFAT: 2-second mtimes kept in local time (`--fat`)
+    fat: bool,
 }
 
 impl StrategyPlanner {
@@ -60,6 +62,7 @@ impl StrategyPlanner {
             update_only: false,
             ignore_existing: false,
             verifier: None,
+            fat: false,
         }
     }
 
@@ -87,9 +90,19 @@ impl StrategyPlanner {
             update_only,
             ignore_existing,
             verifier,
+            fat: false,
         }
     }
 
+    /// Compare mtimes the way a FAT destination stores them
+    pub fn with_fat(mut self, fat: bool) -> Self {
+        self.fat = fat;
+        if fat {
+            self.mtime_tolerance = crate::fs_util::FAT_MODIFY_WINDOW;
+        }
+        self
+    }
+
     /// Determine sync action for a source file (async version using transport)
     pub async fn plan_file_async<T: Transport>(
         &self,
@@ -518,6 +531,13 @@ impl StrategyPlanner {
 
     /// Check if mtimes match within tolerance
     fn mtime_matches(&self, source_mtime: &SystemTime, dest_mtime: &SystemTime) -> bool {
+        if self.fat {
+            let secs = |t: &SystemTime| match t.duration_since(SystemTime::UNIX_EPOCH) {
+                Ok(d) => d.as_secs() as i64,
+                Err(e) => -(e.duration().as_secs() as i64),
+            };
+            return crate::fs_util::fat_mtime_matches(secs(source_mtime), secs(dest_mtime));
+        }
         match source_mtime.duration_since(*dest_mtime) {
             Ok(duration) => duration.as_secs() <= self.mtime_tolerance,
             Err(e) => e.duration().as_secs() <= self.mtime_tolerance,
@@ -634,6 +654,7 @@ mod tests {
     use std::fs;
     use std::path::PathBuf;
     use std::sync::Arc;
+    use std::time::Duration;
     use tempfile::TempDir;
 
     #[test]
@@ -656,6 +677,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let planner = StrategyPlanner::new();
@@ -687,6 +709,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let planner = StrategyPlanner::new();
@@ -718,6 +741,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let planner = StrategyPlanner::new();
@@ -726,6 +750,56 @@ mod tests {
         assert_eq!(task.action, SyncAction::Update);
     }
 
+    #[test]
+    fn test_plan_fat_mtimes() {
+        let temp = TempDir::new().unwrap();
+        let dest_root = temp.path();
+        fs::write(dest_root.join("file.txt"), "content").unwrap();
+        let dest_mtime = fs::metadata(dest_root.join("file.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+
+        let source_at = |modified: SystemTime| FileEntry {
+            path: Arc::new(PathBuf::from("/source/file.txt")),
+            relative_path: Arc::new(PathBuf::from("file.txt")),
+            size: 7,
+            modified,
+            is_dir: false,
+            is_symlink: false,
+            symlink_target: None,
+            is_sparse: false,
+            allocated_size: 7,
+            xattrs: None,
+            inode: None,
+            nlink: 1,
+            acls: None,
+            bsd_flags: None,
+            win_attrs: None,
+        };
+        let planner = StrategyPlanner::new();
+        let fat = StrategyPlanner::new().with_fat(true);
+
+        // FAT rounds to 2 seconds
+        let rounded = source_at(dest_mtime - Duration::from_secs(2));
+        assert_eq!(
+            planner.plan_file(&rounded, dest_root).action,
+            SyncAction::Update
+        );
+        assert_eq!(fat.plan_file(&rounded, dest_root).action, SyncAction::Skip);
+
+        // A time zone shift leaves only the size to go by
+        let shifted = source_at(dest_mtime + Duration::from_secs(3600));
+        assert_eq!(
+            planner.plan_file(&shifted, dest_root).action,
+            SyncAction::Update
+        );
+        assert_eq!(fat.plan_file(&shifted, dest_root).action, SyncAction::Skip);
+
+        let edited = source_at(dest_mtime + Duration::from_secs(600));
+        assert_eq!(fat.plan_file(&edited, dest_root).action, SyncAction::Update);
+    }
+
     #[test]
     fn test_plan_deletions_small_set() {
         let temp_dest = TempDir::new().unwrap();
@@ -752,6 +826,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         }];
 
         let planner = StrategyPlanner::new();
@@ -802,6 +877,7 @@ mod tests {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             });
         }
 
@@ -864,6 +940,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         // Create planner with checksum mode enabled
@@ -906,6 +983,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         // Create planner with checksum mode enabled
@@ -947,6 +1025,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         // Create planner with checksum mode enabled
@@ -986,6 +1065,7 @@ mod tests {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             },
             FileEntry {
                 path: Arc::new(PathBuf::from("/source/file2.txt")),
@@ -1002,6 +1082,7 @@ mod tests {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             },
         ];
 
@@ -1,5 +1,6 @@
 use crate::cli::SymlinkMode;
 use crate::error::Result;
+use crate::integrity::IntegrityVerifier;
 use crate::sync::scanner::FileEntry;
 use crate::transport::{TransferResult, Transport};
 use std::collections::HashMap;
@@ -116,6 +117,7 @@ impl<'a, T: Transport> Transferrer<'a, T> {
                                     transferred_bytes: Some(0),
                                     delta_operations: None,
                                     literal_bytes: None,
+                                    sparse_bytes_skipped: None,
                                 }));
                             }
                             Some(InodeState::InProgress(notify)) => {
@@ -243,6 +245,81 @@ impl<'a, T: Transport> Transferrer<'a, T> {
         }
     }
 
+    /// Verify a transferred file, re-sending it whole-file on mismatch
+    ///
+    /// A mismatch after a successful write points at corruption in flight
+    /// (bad RAM, flaky link), so the file is copied again from scratch - no
+    /// delta, since the destination content can't be trusted as a basis.
+    /// Returns true once source and destination agree, false if they still
+    /// differ after `retries` re-sends.
+    pub async fn verify_with_retry(
+        &self,
+        source: &FileEntry,
+        dest_path: &Path,
+        verifier: &IntegrityVerifier,
+        retries: u32,
+    ) -> bool {
+        let mut attempt = 0;
+        loop {
+            match self.verify(&source.path, dest_path, verifier).await {
+                Ok(true) => {
+                    if attempt > 0 {
+                        tracing::info!(
+                            "Verified {} after {} re-send(s)",
+                            dest_path.display(),
+                            attempt
+                        );
+                    }
+                    return true;
+                }
+                Ok(false) => tracing::warn!(
+                    "Checksum mismatch for {} (attempt {}/{})",
+                    dest_path.display(),
+                    attempt + 1,
+                    retries + 1
+                ),
+                Err(e) => tracing::warn!("Verification error for {}: {}", dest_path.display(), e),
+            }
+
+            if attempt >= retries {
+                return false;
+            }
+            attempt += 1;
+
+            if let Err(e) = self.resend(source, dest_path).await {
+                tracing::warn!("Re-send of {} failed: {}", dest_path.display(), e);
+                return false;
+            }
+        }
+    }
+
+    /// Compare source and destination checksums
+    async fn verify(
+        &self,
+        source: &Path,
+        dest_path: &Path,
+        verifier: &IntegrityVerifier,
+    ) -> Result<bool> {
+        if dest_path.exists() {
+            verifier.verify_transfer(source, dest_path)
+        } else {
+            // Remote/S3 destination
+            let source_checksum = self.transport.compute_checksum(source, verifier).await?;
+            let dest_checksum = self.transport.compute_checksum(dest_path, verifier).await?;
+            Ok(source_checksum == dest_checksum)
+        }
+    }
+
+    /// Copy a regular file again, whole-file, and reapply its metadata
+    async fn resend(&self, source: &FileEntry, dest_path: &Path) -> Result<TransferResult> {
+        let result = self.copy_file(&source.path, dest_path, source.size).await?;
+        self.write_mtime_and_mode(source, dest_path)?;
+        self.write_xattrs(source, dest_path).await?;
+        self.write_acls(source, dest_path).await?;
+        self.write_bsd_flags(source, dest_path).await?;
+        Ok(result)
+    }
+
     /// Delete a file or directory
     pub async fn delete(&self, dest_path: &Path, is_dir: bool) -> Result<()> {
         if self.dry_run {
@@ -288,6 +365,24 @@ impl<'a, T: Transport> Transferrer<'a, T> {
         Ok(result)
     }
 
+    /// Give a local destination the source's permission bits and mtime,
+    /// so a re-sent file isn't seen as changed next run
+    ///
+    /// Remote destinations get both from the transport's copy.
+    fn write_mtime_and_mode(&self, source: &FileEntry, dest_path: &Path) -> Result<()> {
+        if !dest_path.exists() {
+            return Ok(());
+        }
+        if let Ok(metadata) = std::fs::metadata(&*source.path) {
+            std::fs::set_permissions(dest_path, metadata.permissions())?;
+        }
+        filetime::set_file_mtime(
+            dest_path,
+            filetime::FileTime::from_system_time(source.modified),
+        )?;
+        Ok(())
+    }
+
     /// Write extended attributes to a file
     async fn write_xattrs(&self, file_entry: &FileEntry, dest_path: &Path) -> Result<()> {
         if !self.preserve_xattrs {
@@ -505,6 +600,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -551,6 +647,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -593,6 +690,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -648,6 +746,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -705,6 +804,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -762,6 +862,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -822,6 +923,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -883,6 +985,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -948,6 +1051,7 @@ mod tests {
             nlink: 2,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let link_entry = FileEntry {
@@ -965,6 +1069,7 @@ mod tests {
             nlink: 2,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         // Transfer with preserve_hardlinks = true
@@ -1057,6 +1162,7 @@ mod tests {
             nlink: 2,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let link_entry = FileEntry {
@@ -1074,6 +1180,7 @@ mod tests {
             nlink: 2,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         // Transfer with preserve_hardlinks = false
@@ -1158,6 +1265,7 @@ mod tests {
             nlink: 3,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let entry2 = FileEntry {
@@ -1175,6 +1283,7 @@ mod tests {
             nlink: 3,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let entry3 = FileEntry {
@@ -1192,6 +1301,7 @@ mod tests {
             nlink: 3,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         // Transfer with preserve_hardlinks = true
@@ -1261,6 +1371,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1318,6 +1429,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1403,6 +1515,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1455,6 +1568,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1503,6 +1617,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1551,6 +1666,7 @@ mod tests {
             nlink: 1,
             acls: Some(acls_text.into_bytes()),
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1599,6 +1715,7 @@ mod tests {
             nlink: 1,
             acls: Some(acls_text.into_bytes()),
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1646,6 +1763,7 @@ mod tests {
             nlink: 1,
             acls: Some(Vec::new()), // Empty ACLs
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1708,6 +1826,7 @@ mod tests {
             nlink: 1,
             acls: Some(acls_bytes),
             bsd_flags: None,
+            win_attrs: None,
         };
 
         // Transfer with preserve_acls = true
@@ -1767,6 +1886,7 @@ mod tests {
             nlink: 1,
             acls: Some(acls_text.into_bytes()),
             bsd_flags: None,
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1832,6 +1952,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: Some(flags),
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1898,6 +2019,7 @@ mod tests {
             nlink: 1,
             acls: None,
             bsd_flags: Some(flags),
+            win_attrs: None,
         };
 
         let transport = LocalTransport::new();
@@ -1928,4 +2050,130 @@ mod tests {
             "Hidden flag should not be preserved when preserve_flags=false"
         );
     }
+
+    /// Local transport whose first `corrupt` copies write flipped bytes
+    struct FlakyTransport {
+        inner: LocalTransport,
+        corrupt: std::sync::atomic::AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for FlakyTransport {
+        async fn scan(&self, path: &Path) -> Result<Vec<FileEntry>> {
+            self.inner.scan(path).await
+        }
+        async fn exists(&self, path: &Path) -> Result<bool> {
+            self.inner.exists(path).await
+        }
+        async fn metadata(&self, path: &Path) -> Result<std::fs::Metadata> {
+            self.inner.metadata(path).await
+        }
+        async fn create_dir_all(&self, path: &Path) -> Result<()> {
+            self.inner.create_dir_all(path).await
+        }
+        async fn copy_file(&self, source: &Path, dest: &Path) -> Result<TransferResult> {
+            let result = self.inner.copy_file(source, dest).await?;
+            let left = self.corrupt.load(std::sync::atomic::Ordering::SeqCst);
+            if left > 0 {
+                self.corrupt
+                    .store(left - 1, std::sync::atomic::Ordering::SeqCst);
+                let mut data = fs::read(dest).unwrap();
+                data[0] ^= 0xFF;
+                fs::write(dest, data).unwrap();
+            }
+            Ok(result)
+        }
+        async fn remove(&self, path: &Path, is_dir: bool) -> Result<()> {
+            self.inner.remove(path, is_dir).await
+        }
+        async fn create_hardlink(&self, source: &Path, dest: &Path) -> Result<()> {
+            self.inner.create_hardlink(source, dest).await
+        }
+        async fn create_symlink(&self, target: &Path, dest: &Path) -> Result<()> {
+            self.inner.create_symlink(target, dest).await
+        }
+    }
+
+    async fn run_flaky_verify(corrupt: u32, retries: u32) -> bool {
+        let source_dir = TempDir::new().unwrap();
+        let dest_dir = TempDir::new().unwrap();
+        let source_file = source_dir.path().join("data.bin");
+        fs::write(&source_file, b"important payload").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&source_file, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        filetime::set_file_mtime(&source_file, filetime::FileTime::from_system_time(modified))
+            .unwrap();
+
+        let file_entry = FileEntry {
+            path: Arc::new(source_file),
+            relative_path: Arc::new(PathBuf::from("data.bin")),
+            size: 17,
+            modified,
+            is_dir: false,
+            is_symlink: false,
+            symlink_target: None,
+            is_sparse: false,
+            allocated_size: 17,
+            xattrs: None,
+            inode: None,
+            nlink: 1,
+            acls: None,
+            bsd_flags: None,
+            win_attrs: None,
+        };
+
+        let transport = FlakyTransport {
+            inner: LocalTransport::new(),
+            corrupt: std::sync::atomic::AtomicU32::new(corrupt),
+        };
+        let transferrer = Transferrer::new(
+            &transport,
+            false,
+            false,
+            SymlinkMode::Preserve,
+            false,
+            false,
+            false,
+            false,
+            false,
+            Arc::new(Mutex::new(std::collections::HashMap::new())),
+        );
+        let dest_path = dest_dir.path().join("data.bin");
+        transferrer.create(&file_entry, &dest_path).await.unwrap();
+
+        let verifier = IntegrityVerifier::new(crate::integrity::ChecksumType::Fast, false);
+        let verified = transferrer
+            .verify_with_retry(&file_entry, &dest_path, &verifier, retries)
+            .await;
+
+        // A re-sent file keeps the source's mtime and mode
+        if verified {
+            let metadata = fs::metadata(&dest_path).unwrap();
+            assert_eq!(metadata.modified().unwrap(), modified);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+            }
+        }
+        verified
+    }
+
+    #[tokio::test]
+    async fn test_verify_with_retry_recovers_from_corruption() {
+        // First copy corrupted, re-send succeeds
+        assert!(run_flaky_verify(1, 2).await);
+    }
+
+    #[tokio::test]
+    async fn test_verify_with_retry_gives_up() {
+        // Every attempt (initial + 2 re-sends) corrupted
+        assert!(!run_flaky_verify(3, 2).await);
+        // No retries configured
+        assert!(!run_flaky_verify(1, 0).await);
+    }
 }
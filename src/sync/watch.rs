@@ -17,6 +17,8 @@ pub struct WatchMode<T: Transport> {
     source: PathBuf,
     destination: PathBuf,
     debounce: Duration,
+    /// Leave the terminal alone (`--tui` draws on it)
+    quiet: bool,
 }
 
 impl<T: Transport + 'static> WatchMode<T> {
@@ -31,9 +33,16 @@ impl<T: Transport + 'static> WatchMode<T> {
             source,
             destination,
             debounce,
+            quiet: false,
         }
     }
 
+    /// Report through `tracing` only, not on stdout
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
     pub async fn watch(&self) -> Result<()> {
         // Initial sync
         tracing::info!("Running initial sync...");
@@ -44,10 +53,12 @@ impl<T: Transport + 'static> WatchMode<T> {
         let mut watcher: RecommendedWatcher = notify::recommended_watcher(tx)?;
         watcher.watch(&self.source, RecursiveMode::Recursive)?;
 
-        println!(
-            "\n🔍 Watching {} for changes (Ctrl+C to stop)...\n",
-            self.source.display()
-        );
+        if !self.quiet {
+            println!(
+                "\n🔍 Watching {} for changes (Ctrl+C to stop)...\n",
+                self.source.display()
+            );
+        }
 
         // Event loop with debouncing
         let mut pending_changes = Vec::new();
@@ -61,7 +72,9 @@ impl<T: Transport + 'static> WatchMode<T> {
             // Check for Ctrl+C
             tokio::select! {
                 _ = &mut ctrl_c => {
-                    println!("\n⏹️  Stopping watch mode...");
+                    if !self.quiet {
+                        println!("\n⏹️  Stopping watch mode...");
+                    }
                     break;
                 }
                 _ = tokio::time::sleep(Duration::from_millis(10)) => {
@@ -86,12 +99,16 @@ impl<T: Transport + 'static> WatchMode<T> {
                     // Check if we should sync (debounce timeout reached)
                     if !pending_changes.is_empty() && last_sync.elapsed() >= self.debounce {
                         tracing::info!("Detected {} changes, syncing...", pending_changes.len());
-                        println!("📝 Changes detected, syncing...");
+                        if !self.quiet {
+                            println!("📝 Changes detected, syncing...");
+                        }
 
                         match self.engine.sync(&self.source, &self.destination).await {
+                            Ok(_) if self.quiet => {}
                             Ok(_) => {
                                 println!("✓ Sync complete\n");
                             }
+                            Err(e) if self.quiet => tracing::error!("Sync failed: {}", e),
                             Err(e) => {
                                 eprintln!("✗ Sync failed: {}\n", e);
                             }
@@ -164,6 +181,8 @@ mod tests {
             false,                              // json
             ChecksumType::None,                 // verification_mode
             false,                              // verify_on_write
+            0,                                  // verify_retries
+            None,                               // verify_sample
             SymlinkMode::Preserve,              // symlink_mode
             false,                              // preserve_xattrs
             false,                              // preserve_hardlinks
@@ -228,6 +247,8 @@ mod tests {
             false,
             ChecksumType::None,
             false,
+            0,
+            None,
             SymlinkMode::Preserve,
             false,
             false,
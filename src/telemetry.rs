@@ -0,0 +1,136 @@
+//! Logging and trace export setup for the CLI
+//!
+//! Log output goes to stderr through the usual `fmt` layer. With
+//! `--otlp-endpoint` (and the `otel` feature), the spans around sync phases,
+//! pipeline tasks and individual files are also exported over OTLP/HTTP, so
+//! slow syncs can be profiled in Jaeger, Tempo, Honeycomb, ...
+
+use anyhow::Result;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+/// Keeps the trace exporter alive; dropping it flushes pending spans
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+/// Install the global subscriber: `filter` applies to log output, while the
+/// OTLP exporter (if any) records every span at INFO and above
+pub fn init(filter: EnvFilter, otlp_endpoint: Option<&str>) -> Result<Telemetry> {
+    let log_layer = fmt::layer()
+        .with_target(false)
+        .with_thread_ids(false)
+        .with_file(false)
+        .with_line_number(false)
+        .compact()
+        .with_filter(filter);
+
+    #[cfg(feature = "otel")]
+    {
+        let (otel_layer, provider) = match otlp_endpoint {
+            Some(endpoint) => {
+                let (layer, provider) = otel::layer(endpoint)?;
+                (Some(layer), Some(provider))
+            }
+            None => (None, None),
+        };
+        tracing_subscriber::registry()
+            .with(log_layer)
+            .with(otel_layer)
+            .init();
+        Ok(Telemetry { provider })
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        if otlp_endpoint.is_some() {
+            anyhow::bail!(
+                r#"--otlp-endpoint requires the 'otel' feature.
+Install sy from crates.io with: cargo install sy --features otel
+Or install from local source with: cargo install --path . --features otel"#
+            );
+        }
+        tracing_subscriber::registry().with(log_layer).init();
+        Ok(Telemetry {})
+    }
+}
+
+#[cfg(feature = "otel")]
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush traces: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use anyhow::{Context, Result};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use tracing::Subscriber;
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    /// OTLP/HTTP traces path; the collector base URL (`http://host:4318`) is
+    /// what users usually have at hand
+    const TRACES_PATH: &str = "/v1/traces";
+
+    pub(super) fn layer<S>(endpoint: &str) -> Result<(impl Layer<S>, SdkTracerProvider)>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let endpoint = traces_url(endpoint);
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&endpoint)
+            .build()
+            .with_context(|| format!("Failed to set up OTLP export to {}", endpoint))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("sy").build())
+            .build();
+        let layer = tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("sy"))
+            .with_filter(LevelFilter::INFO);
+        Ok((layer, provider))
+    }
+
+    pub(super) fn traces_url(endpoint: &str) -> String {
+        let endpoint = endpoint.trim_end_matches('/');
+        if endpoint.ends_with(TRACES_PATH) {
+            endpoint.to_string()
+        } else {
+            format!("{}{}", endpoint, TRACES_PATH)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_traces_url() {
+            assert_eq!(
+                traces_url("http://localhost:4318"),
+                "http://localhost:4318/v1/traces"
+            );
+            assert_eq!(
+                traces_url("http://localhost:4318/"),
+                "http://localhost:4318/v1/traces"
+            );
+            assert_eq!(
+                traces_url("https://otel.example.com/v1/traces"),
+                "https://otel.example.com/v1/traces"
+            );
+        }
+    }
+}
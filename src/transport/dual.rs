@@ -42,6 +42,14 @@ impl Transport for DualTransport {
         self.source.scan(path).await
     }
 
+    async fn scan_pruned(
+        &self,
+        path: &Path,
+        cache: std::sync::Arc<crate::sync::dircache::DirectoryCache>,
+    ) -> Result<(Vec<FileEntry>, usize)> {
+        self.source.scan_pruned(path, cache).await
+    }
+
     async fn scan_streaming(
         &self,
         path: &Path,
@@ -112,6 +112,7 @@ impl Transport for GcsTransport {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             });
         }
 
@@ -172,6 +173,7 @@ impl Transport for GcsTransport {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             })
         });
 
@@ -1,8 +1,12 @@
 use super::{TransferResult, Transport};
+use crate::cli::{ReflinkMode, SymlinkMode};
 use crate::error::{Result, SyncError};
-use crate::fs_util::{has_hard_links, same_filesystem, supports_cow_reflinks};
+use crate::fs_util::{
+    create_symlink, has_hard_links, same_filesystem, supports_cow_reflinks, SymlinkOutcome,
+};
 use crate::integrity::{ChecksumType, IntegrityVerifier};
 use crate::resource::format_bytes;
+use crate::sync::dircache::DirectoryCache;
 use crate::sync::scanner::{FileEntry, ScanOptions, Scanner};
 use crate::temp_file::TempFileGuard;
 use async_trait::async_trait;
@@ -10,6 +14,7 @@ use futures::stream::{BoxStream, Stream, StreamExt};
 use std::fs::{self, File};
 use std::path::Path;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::sync::mpsc;
 
@@ -46,6 +51,17 @@ fn is_file_sparse(_metadata: &std::fs::Metadata) -> bool {
     false // Non-Unix platforms don't support sparse detection
 }
 
+/// Bytes of a sparse file's holes: its logical size beyond what's allocated
+#[cfg(unix)]
+fn hole_bytes(metadata: &std::fs::Metadata) -> u64 {
+    metadata.len().saturating_sub(metadata.blocks() * 512)
+}
+
+#[cfg(not(unix))]
+fn hole_bytes(_metadata: &std::fs::Metadata) -> u64 {
+    0
+}
+
 /// Copy a sparse file while preserving holes
 ///
 /// Tries to use SEEK_HOLE/SEEK_DATA for efficiency, falls back to block-based
@@ -193,6 +209,37 @@ fn copy_sparse_file(source: &Path, dest: &Path) -> std::io::Result<u64> {
     fs::copy(source, dest)
 }
 
+/// Replace `dest` with a clone of `source` if `mode` allows, returning whether
+/// it did
+///
+/// `auto` only tries when both sit on the same Btrfs, XFS or APFS filesystem
+/// and copies instead if the clone fails; `always` makes that failure an error.
+fn clone_into(source: &Path, dest: &Path, mode: ReflinkMode) -> Result<bool> {
+    let dir = dest
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    match mode {
+        ReflinkMode::Never => return Ok(false),
+        ReflinkMode::Auto if !(supports_cow_reflinks(dir) && same_filesystem(source, dir)) => {
+            return Ok(false)
+        }
+        _ => {}
+    }
+
+    match File::open(source).and_then(|src| crate::streaming::io::clone_file(&src, dest, false)) {
+        Ok(()) => Ok(true),
+        Err(e) if mode == ReflinkMode::Always => Err(SyncError::CopyError {
+            path: source.to_path_buf(),
+            source: std::io::Error::new(e.kind(), format!("--reflink=always: {}", e)),
+        }),
+        Err(e) => {
+            tracing::debug!("Clone of {} failed, copying: {}", source.display(), e);
+            Ok(false)
+        }
+    }
+}
+
 /// Local filesystem transport
 ///
 /// Implements the Transport trait for local filesystem operations.
@@ -200,6 +247,7 @@ fn copy_sparse_file(source: &Path, dest: &Path) -> std::io::Result<u64> {
 pub struct LocalTransport {
     verifier: IntegrityVerifier,
     scan_options: ScanOptions,
+    reflink: ReflinkMode,
 }
 
 impl LocalTransport {
@@ -208,6 +256,7 @@ impl LocalTransport {
         Self {
             verifier: IntegrityVerifier::new(ChecksumType::None, false),
             scan_options: ScanOptions::default(),
+            reflink: ReflinkMode::default(),
         }
     }
 
@@ -215,6 +264,7 @@ impl LocalTransport {
         Self {
             verifier,
             scan_options: ScanOptions::default(),
+            reflink: ReflinkMode::default(),
         }
     }
 
@@ -223,6 +273,12 @@ impl LocalTransport {
         self.scan_options = options;
         self
     }
+
+    /// Set when whole-file copies are cloned instead (`--reflink`)
+    pub fn with_reflink(mut self, mode: ReflinkMode) -> Self {
+        self.reflink = mode;
+        self
+    }
 }
 
 impl Default for LocalTransport {
@@ -237,6 +293,7 @@ impl Transport for LocalTransport {
         self.scan_options = options;
     }
 
+    #[tracing::instrument(name = "local.scan", skip_all, fields(path = %path.display()))]
     async fn scan(&self, path: &Path) -> Result<Vec<FileEntry>> {
         // Use existing scanner (runs synchronously, wrapped in async)
         let path = path.to_path_buf();
@@ -249,6 +306,21 @@ impl Transport for LocalTransport {
         .map_err(|e| SyncError::Io(std::io::Error::other(e.to_string())))?
     }
 
+    async fn scan_pruned(
+        &self,
+        path: &Path,
+        cache: Arc<DirectoryCache>,
+    ) -> Result<(Vec<FileEntry>, usize)> {
+        let path = path.to_path_buf();
+        let options = self.scan_options;
+        tokio::task::spawn_blocking(move || {
+            let scanner = Scanner::new(&path).with_options(options);
+            scanner.scan_pruned(cache)
+        })
+        .await
+        .map_err(|e| SyncError::Io(std::io::Error::other(e.to_string())))?
+    }
+
     async fn scan_streaming(&self, path: &Path) -> Result<BoxStream<'static, Result<FileEntry>>> {
         let path = path.to_path_buf();
         let options = self.scan_options;
@@ -291,6 +363,7 @@ impl Transport for LocalTransport {
         tokio::fs::create_dir_all(path).await.map_err(SyncError::Io)
     }
 
+    #[tracing::instrument(name = "local.copy_file", skip_all, fields(path = %dest.display()))]
     async fn copy_file(&self, source: &Path, dest: &Path) -> Result<TransferResult> {
         // Ensure parent directory exists
         if let Some(parent) = dest.parent() {
@@ -300,6 +373,7 @@ impl Transport for LocalTransport {
         // Copy file with checksum verification using spawn_blocking
         let source = source.to_path_buf();
         let dest = dest.to_path_buf();
+        let reflink = self.reflink;
 
         tokio::task::spawn_blocking(move || {
             // Check if source is sparse
@@ -310,7 +384,11 @@ impl Transport for LocalTransport {
 
             let is_sparse = is_file_sparse(&source_meta);
 
-            if is_sparse {
+            // On Btrfs, XFS and APFS a clone shares the source's blocks, holes
+            // included, so it's instant whatever the size
+            let cloned = clone_into(&source, &dest, reflink)?;
+
+            if is_sparse && !cloned {
                 // For sparse files, use std::fs::copy() which preserves sparseness on Unix
                 tracing::debug!(
                     "Sparse file detected ({}), using sparse-aware copy",
@@ -345,18 +423,25 @@ impl Transport for LocalTransport {
                     bytes_written
                 );
 
-                return Ok(bytes_written);
+                return Ok(TransferResult::with_sparse(
+                    bytes_written,
+                    hole_bytes(&source_meta),
+                ));
             }
 
-            // Use fs::copy() which is optimized per-platform:
-            // - macOS: clonefile() for COW reflinks on APFS (100x+ faster)
+            // Otherwise use fs::copy() which is optimized per-platform:
+            // - macOS: clonefile() for new files on APFS, fcopyfile() otherwise
             // - Linux: copy_file_range() for zero-copy (kernel-side)
             // - Fallback: sendfile() or read/write
             // This is MUCH faster than manual read/write loop
-            let bytes_written = fs::copy(&source, &dest).map_err(|e| SyncError::CopyError {
-                path: source.clone(),
-                source: e,
-            })?;
+            let bytes_written = if cloned {
+                source_meta.len()
+            } else {
+                fs::copy(&source, &dest).map_err(|e| SyncError::CopyError {
+                    path: source.clone(),
+                    source: e,
+                })?
+            };
 
             // fs::copy() may preserve xattrs on some platforms (e.g., macOS).
             // Strip all xattrs so that Transferrer can selectively re-add them
@@ -382,14 +467,14 @@ impl Transport for LocalTransport {
                     filetime::set_file_mtime(&dest, filetime::FileTime::from_system_time(mtime));
             }
 
-            Ok(bytes_written)
+            Ok(TransferResult::new(bytes_written))
         })
         .await
         .map_err(|e| SyncError::Io(std::io::Error::other(e.to_string())))
         .and_then(|r| r)
-        .map(TransferResult::new)
     }
 
+    #[tracing::instrument(name = "local.sync_file_with_delta", skip_all, fields(path = %dest.display()))]
     async fn sync_file_with_delta(&self, source: &Path, dest: &Path) -> Result<TransferResult> {
         // Check if destination exists
         if !self.exists(dest).await? {
@@ -434,6 +519,7 @@ impl Transport for LocalTransport {
         let source = source.to_path_buf();
         let dest = dest.to_path_buf();
         let verifier = self.verifier.clone();
+        let reflink = self.reflink;
 
         tokio::task::spawn_blocking(move || {
             use crate::delta::estimate_change_ratio;
@@ -466,7 +552,10 @@ impl Transport for LocalTransport {
                     bytes_written
                 );
 
-                return Ok(TransferResult::new(bytes_written));
+                return Ok(TransferResult::with_sparse(
+                    bytes_written,
+                    hole_bytes(&source_meta),
+                ));
             }
 
             // Sample blocks to estimate change ratio
@@ -551,7 +640,12 @@ impl Transport for LocalTransport {
 
             let (bytes_written, literal_bytes, changed_blocks) = if use_cow_strategy {
                 // COW Strategy: Clone file (instant), then selectively overwrite changed blocks
-                fs::copy(&dest, &temp_dest).map_err(|e| SyncError::DeltaSyncError {
+                let cloned = reflink != ReflinkMode::Never
+                    && File::open(&dest)
+                        .and_then(|d| crate::streaming::io::clone_file(&d, &temp_dest, false))
+                        .is_ok();
+                if !cloned {
+                    fs::copy(&dest, &temp_dest).map_err(|e| SyncError::DeltaSyncError {
                     path: temp_dest.clone(),
                     strategy: "COW (clone + selective writes)".to_string(),
                     source: e,
@@ -561,6 +655,7 @@ impl Transport for LocalTransport {
                            - Insufficient disk space\n  \
                            Falling back to in-place strategy may help.".to_string(),
                 })?;
+                }
 
                 // Strip xattrs from clone (fs::copy may preserve them)
                 #[cfg(unix)]
@@ -914,31 +1009,18 @@ impl Transport for LocalTransport {
             tokio::fs::remove_file(dest).await.ok(); // Ignore errors
         }
 
-        // Create the symbolic link
-        #[cfg(unix)]
-        {
-            tokio::fs::symlink(target, dest)
-                .await
-                .map_err(SyncError::Io)?;
-        }
-
-        #[cfg(windows)]
-        {
-            // Windows requires different symlink APIs for files vs directories
-            if tokio::fs::metadata(target)
-                .await
-                .ok()
-                .map(|m| m.is_dir())
-                .unwrap_or(false)
-            {
-                tokio::fs::symlink_dir(target, dest)
-                    .await
-                    .map_err(SyncError::Io)?;
-            } else {
-                tokio::fs::symlink_file(target, dest)
-                    .await
-                    .map_err(SyncError::Io)?;
-            }
+        // Create the symbolic link. Only `--links preserve` gets here; where
+        // the platform refuses (Windows without the privilege), the target is
+        // copied instead
+        let (link_target, link) = (target.to_path_buf(), dest.to_path_buf());
+        let outcome = tokio::task::spawn_blocking(move || {
+            create_symlink(&link_target, &link, SymlinkMode::Preserve)
+        })
+        .await
+        .map_err(|e| SyncError::Io(std::io::Error::other(e.to_string())))?
+        .map_err(SyncError::Io)?;
+        if outcome != SymlinkOutcome::Linked {
+            return Ok(());
         }
 
         tracing::debug!(
@@ -1199,6 +1281,11 @@ mod tests {
         // Verify copy succeeded
         assert!(dest_file.exists());
         assert_eq!(result.bytes_written, 10 * 1024 * 1024);
+        let source_meta = fs::metadata(&source_file).unwrap();
+        if is_file_sparse(&source_meta) {
+            assert_eq!(result.sparse_bytes_skipped, Some(hole_bytes(&source_meta)));
+            assert!(hole_bytes(&source_meta) > 9 * 1024 * 1024);
+        }
 
         // Verify destination is also sparse (or at least has same size)
         let dest_meta = fs::metadata(&dest_file).unwrap();
@@ -1237,6 +1324,33 @@ mod tests {
         assert!(result.is_err(), "Should fail when source doesn't exist");
     }
 
+    #[tokio::test]
+    async fn test_copy_file_reflink_modes() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("source.bin");
+        fs::write(&source, vec![7u8; 64 * 1024]).unwrap();
+        let can_clone = supports_cow_reflinks(dir.path());
+
+        for mode in [ReflinkMode::Auto, ReflinkMode::Never] {
+            let dest = dir.path().join(format!("{:?}.bin", mode));
+            let transport = LocalTransport::new().with_reflink(mode);
+            let result = transport.copy_file(&source, &dest).await.unwrap();
+            assert_eq!(result.bytes_written, 64 * 1024);
+            assert_eq!(fs::read(&dest).unwrap(), fs::read(&source).unwrap());
+        }
+
+        // `always` only succeeds where the filesystem can clone
+        let dest = dir.path().join("always.bin");
+        let transport = LocalTransport::new().with_reflink(ReflinkMode::Always);
+        let result = transport.copy_file(&source, &dest).await;
+        assert_eq!(result.is_ok(), can_clone);
+        if can_clone {
+            assert_eq!(fs::read(&dest).unwrap(), fs::read(&source).unwrap());
+        } else {
+            assert!(!dir.path().join("always.bin.clone").exists());
+        }
+    }
+
     #[tokio::test]
     #[cfg(unix)] // Permission tests work differently on Windows
     async fn test_copy_file_permission_denied_destination() {
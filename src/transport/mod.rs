@@ -5,8 +5,12 @@ pub mod local;
 pub mod router;
 #[cfg(feature = "s3")]
 pub mod s3;
+#[cfg(feature = "s3")]
+pub mod s3_storage;
 pub mod server;
 #[cfg(feature = "ssh")]
+pub mod sftp;
+#[cfg(feature = "ssh")]
 pub mod ssh;
 
 use crate::error::Result;
@@ -38,6 +42,9 @@ pub struct TransferResult {
     pub transferred_bytes: Option<u64>,
     /// Whether compression was used
     pub compression_used: bool,
+    /// Bytes of a sparse source's holes that were recreated at the
+    /// destination instead of copied (None unless copied sparse)
+    pub sparse_bytes_skipped: Option<u64>,
 }
 
 impl TransferResult {
@@ -48,6 +55,7 @@ impl TransferResult {
             literal_bytes: None,
             transferred_bytes: None,
             compression_used: false,
+            sparse_bytes_skipped: None,
         }
     }
 
@@ -58,6 +66,7 @@ impl TransferResult {
             literal_bytes: Some(literal_bytes),
             transferred_bytes: None,
             compression_used: false,
+            sparse_bytes_skipped: None,
         }
     }
 
@@ -68,9 +77,26 @@ impl TransferResult {
             literal_bytes: None,
             transferred_bytes: Some(transferred_bytes),
             compression_used: true,
+            sparse_bytes_skipped: None,
         }
     }
 
+    /// A sparse copy of `bytes_written` logical bytes that skipped
+    /// `holes` bytes of holes
+    pub fn with_sparse(bytes_written: u64, holes: u64) -> Self {
+        Self {
+            sparse_bytes_skipped: Some(holes),
+            ..Self::new(bytes_written)
+        }
+    }
+
+    /// Logical bytes less the holes a sparse copy skipped: the data that
+    /// was actually read and written
+    pub fn data_bytes(&self) -> u64 {
+        self.bytes_written
+            .saturating_sub(self.sparse_bytes_skipped.unwrap_or(0))
+    }
+
     /// Returns true if this transfer used delta sync
     pub fn used_delta(&self) -> bool {
         self.delta_operations.is_some()
@@ -123,6 +149,19 @@ pub trait Transport: Send + Sync {
     /// - With archive mode: includes all files including .git
     async fn scan(&self, path: &Path) -> Result<Vec<FileEntry>>;
 
+    /// Scan a directory, reusing `cache` for directories that haven't changed
+    ///
+    /// Returns the entries and how many directories' files came from the
+    /// cache. Default implementation does a full scan (for transports that
+    /// can't stat a directory on its own cheaply).
+    async fn scan_pruned(
+        &self,
+        path: &Path,
+        _cache: std::sync::Arc<crate::sync::dircache::DirectoryCache>,
+    ) -> Result<(Vec<FileEntry>, usize)> {
+        Ok((self.scan(path).await?, 0))
+    }
+
     /// Scan a directory and return a stream of entries
     ///
     /// This recursively scans the directory, respecting .gitignore patterns
@@ -333,7 +372,7 @@ pub trait Transport: Send + Sync {
 
             tokio::task::spawn_blocking(move || {
                 for (name, value) in xattrs {
-                    if let Err(e) = xattr::set(&path, &name, &value) {
+                    if let Err(e) = crate::fs_util::set_xattr(&path, &name, &value) {
                         tracing::warn!("Failed to set xattr {} on {}: {}", name, path.display(), e);
                     }
                 }
@@ -405,25 +444,11 @@ pub trait Transport: Send + Sync {
         // Default implementation: use local libc chflags
         #[cfg(target_os = "macos")]
         {
-            use std::ffi::CString;
             let path = path.to_path_buf();
 
             tokio::task::spawn_blocking(move || {
-                let c_path = match CString::new(path.to_str().unwrap_or("")) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        tracing::warn!("Failed to create C string for {}: {}", path.display(), e);
-                        return;
-                    }
-                };
-
-                let result = unsafe { libc::chflags(c_path.as_ptr(), flags as _) };
-                if result != 0 {
-                    tracing::warn!(
-                        "Failed to set BSD flags on {}: {}",
-                        path.display(),
-                        std::io::Error::last_os_error()
-                    );
+                if let Err(e) = crate::fs_util::set_bsd_flags(&path, flags) {
+                    tracing::warn!("Failed to set BSD flags on {}: {}", path.display(), e);
                 }
             })
             .await
@@ -480,6 +505,14 @@ impl<T: Transport + ?Sized> Transport for std::sync::Arc<T> {
         (**self).scan(path).await
     }
 
+    async fn scan_pruned(
+        &self,
+        path: &Path,
+        cache: std::sync::Arc<crate::sync::dircache::DirectoryCache>,
+    ) -> Result<(Vec<FileEntry>, usize)> {
+        (**self).scan_pruned(path, cache).await
+    }
+
     async fn scan_streaming(&self, path: &Path) -> Result<BoxStream<'static, Result<FileEntry>>> {
         (**self).scan_streaming(path).await
     }
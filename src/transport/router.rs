@@ -5,6 +5,8 @@ use super::s3::S3Transport;
 #[cfg(feature = "ssh")]
 use super::ssh::SshTransport;
 use super::{dual::DualTransport, local::LocalTransport, TransferResult, Transport};
+use crate::cli::ReflinkMode;
+use crate::config::SshSettings;
 use crate::error::Result;
 use crate::integrity::{ChecksumType, IntegrityVerifier};
 use crate::path::SyncPath;
@@ -40,6 +42,8 @@ impl TransportRouter {
     /// Should typically match the number of parallel workers.
     ///
     /// `retry_config` configures network interruption recovery behavior for SSH operations.
+    ///
+    /// `host_keys` decides how SSH host keys are checked.
     pub async fn new(
         source: &SyncPath,
         destination: &SyncPath,
@@ -47,7 +51,10 @@ impl TransportRouter {
         verify_on_write: bool,
         pool_size: usize,
         retry_config: RetryConfig,
+        host_keys: &SshSettings,
     ) -> Result<Self> {
+        #[cfg(not(feature = "ssh"))]
+        let _ = host_keys;
         let verifier = IntegrityVerifier::new(checksum_type, verify_on_write);
 
         match (source, destination) {
@@ -68,7 +75,8 @@ impl TransportRouter {
                     }
                 } else {
                     parse_ssh_config(host)?
-                };
+                }
+                .with_host_keys(host_keys, host);
 
                 let source_transport = Box::new(LocalTransport::with_verifier(verifier.clone()));
                 let dest_transport = Box::new(
@@ -89,7 +97,8 @@ impl TransportRouter {
                     }
                 } else {
                     parse_ssh_config(host)?
-                };
+                }
+                .with_host_keys(host_keys, host);
 
                 let source_transport = Box::new(
                     SshTransport::with_retry_config(&config, pool_size, retry_config.clone())
@@ -121,7 +130,8 @@ impl TransportRouter {
                     }
                 } else {
                     parse_ssh_config(source_host)?
-                };
+                }
+                .with_host_keys(host_keys, source_host);
 
                 let dest_config = if let Some(user) = dest_user {
                     SshConfig {
@@ -131,7 +141,8 @@ impl TransportRouter {
                     }
                 } else {
                     parse_ssh_config(dest_host)?
-                };
+                }
+                .with_host_keys(host_keys, dest_host);
 
                 let source_transport = Box::new(
                     SshTransport::with_retry_config(
@@ -289,6 +300,14 @@ impl TransportRouter {
             }
         }
     }
+
+    /// Set when local → local copies are cloned instead (`--reflink`)
+    pub fn with_reflink(self, mode: ReflinkMode) -> Self {
+        match self {
+            TransportRouter::Local(t) => TransportRouter::Local(t.with_reflink(mode)),
+            other => other,
+        }
+    }
 }
 
 #[async_trait]
@@ -326,6 +345,21 @@ impl Transport for TransportRouter {
         }
     }
 
+    async fn scan_pruned(
+        &self,
+        path: &Path,
+        cache: std::sync::Arc<crate::sync::dircache::DirectoryCache>,
+    ) -> Result<(Vec<crate::sync::scanner::FileEntry>, usize)> {
+        match self {
+            TransportRouter::Local(t) => t.scan_pruned(path, cache).await,
+            TransportRouter::Dual(t) => t.scan_pruned(path, cache).await,
+            #[cfg(feature = "s3")]
+            TransportRouter::S3(t) => t.scan_pruned(path, cache).await,
+            #[cfg(feature = "gcs")]
+            TransportRouter::Gcs(t) => t.scan_pruned(path, cache).await,
+        }
+    }
+
     async fn scan_streaming(
         &self,
         path: &Path,
@@ -33,24 +33,7 @@ impl S3Transport {
         region: Option<String>,
         endpoint: Option<String>,
     ) -> Result<Self> {
-        // Build S3 store with object_store
-        let mut builder = AmazonS3Builder::new().with_bucket_name(&bucket);
-
-        if let Some(r) = region {
-            builder = builder.with_region(&r);
-        }
-
-        if let Some(ep) = endpoint {
-            builder = builder.with_endpoint(&ep);
-        }
-
-        let store = Arc::new(builder.build().map_err(|e| {
-            SyncError::Io(std::io::Error::other(format!(
-                "Failed to create S3 client: {}",
-                e
-            )))
-        })?);
-
+        let store = build_store(&bucket, region, endpoint)?;
         Ok(Self { store, prefix })
     }
 
@@ -82,6 +65,32 @@ impl S3Transport {
     }
 }
 
+/// Client for `bucket`, in `region` or at `endpoint` when given
+pub(crate) fn build_store(
+    bucket: &str,
+    region: Option<String>,
+    endpoint: Option<String>,
+) -> Result<Arc<dyn ObjectStore>> {
+    // Build S3 store with object_store
+    let mut builder = AmazonS3Builder::new().with_bucket_name(bucket);
+
+    if let Some(r) = region {
+        builder = builder.with_region(&r);
+    }
+
+    if let Some(ep) = endpoint {
+        builder = builder.with_endpoint(&ep);
+    }
+
+    let store = builder.build().map_err(|e| {
+        SyncError::Io(std::io::Error::other(format!(
+            "Failed to create S3 client: {}",
+            e
+        )))
+    })?;
+    Ok(Arc::new(store))
+}
+
 #[async_trait]
 impl Transport for S3Transport {
     fn set_scan_options(&mut self, _options: ScanOptions) {
@@ -130,6 +139,7 @@ impl Transport for S3Transport {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             });
         }
 
@@ -190,6 +200,7 @@ impl Transport for S3Transport {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             })
         });
 
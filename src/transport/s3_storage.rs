@@ -0,0 +1,485 @@
+//! S3 as a destination of the streaming pipeline.
+//!
+//! [`S3Storage`] is a [`Storage`] backend over a bucket and key prefix, so a
+//! push to `s3://` runs the same Generator/Receiver pipeline as a local sync
+//! (`StreamingSync::local_to`):
+//!
+//! - the Initial Exchange lists the objects under the prefix
+//! - DATA is uploaded as it arrives, as a multipart upload once a file
+//!   outgrows one part; nothing appears until the file is committed
+//! - DELETE removes objects (on a versioned bucket, behind a delete marker)
+//!
+//! Objects have no modes, owners or links and can't be rewritten in place,
+//! so directories and metadata are no-ops, symlinks and hard links fail per
+//! entry, and files are always sent whole. Each object keeps its source
+//! mtime in `sy-mtime` metadata, which later listings compare against.
+
+use crate::streaming::protocol::FileEntry;
+use crate::streaming::storage::{Listed, Storage, StorageFile};
+use crate::sync::scanner::FileEntry as ScanEntry;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use filetime::FileTime;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use object_store::path::Path as ObjectPath;
+use object_store::{
+    Attribute, Attributes, GetOptions, ObjectMeta, ObjectStore, PutMultipartOptions, PutOptions,
+    WriteMultipart,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Files up to this size are uploaded with one PUT, larger ones in parts
+/// of this size
+const PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Parts of one file uploading at once
+const PARTS_IN_FLIGHT: usize = 4;
+
+/// Objects whose metadata is fetched at once while listing
+const HEADS_IN_FLIGHT: usize = 32;
+
+/// Object metadata holding the source mtime (seconds since the epoch)
+const MTIME_KEY: &str = "sy-mtime";
+
+/// Objects under a key prefix of a bucket
+pub struct S3Storage {
+    store: Arc<dyn ObjectStore>,
+    root: ObjectPath,
+}
+
+impl S3Storage {
+    /// Storage under `prefix` of `bucket`, in `region` or at `endpoint`
+    /// (R2, B2, MinIO...) when given
+    pub fn new(
+        bucket: &str,
+        prefix: &str,
+        region: Option<String>,
+        endpoint: Option<String>,
+    ) -> Result<Self> {
+        let store = super::s3::build_store(bucket, region, endpoint)?;
+        Self::with_store(store, prefix)
+    }
+
+    /// Storage under `prefix` of an existing client
+    pub fn with_store(store: Arc<dyn ObjectStore>, prefix: &str) -> Result<Self> {
+        let root = ObjectPath::parse(prefix)
+            .with_context(|| format!("Invalid S3 key prefix: {}", prefix))?;
+        Ok(Self { store, root })
+    }
+
+    /// The object at `path`, relative to the prefix
+    fn location(&self, path: &str) -> Result<ObjectPath> {
+        let key = match self.root.as_ref() {
+            "" => path.to_string(),
+            root => format!("{}/{}", root, path),
+        };
+        ObjectPath::parse(&key).with_context(|| format!("Invalid S3 key: {}", key))
+    }
+
+    /// `location` relative to the prefix
+    fn relative<'a>(&self, location: &'a ObjectPath) -> &'a str {
+        let key = location.as_ref();
+        key.strip_prefix(self.root.as_ref())
+            .unwrap_or(key)
+            .trim_start_matches('/')
+    }
+
+    /// The listed object `meta`, with the mtime of its source file: its
+    /// `sy-mtime`, or when it was uploaded if something else put it there
+    async fn file_entry(&self, meta: ObjectMeta) -> Result<ScanEntry> {
+        let options = GetOptions {
+            head: true,
+            ..Default::default()
+        };
+        let head = self
+            .store
+            .get_opts(&meta.location, options)
+            .await
+            .with_context(|| format!("Failed to read metadata of {}", meta.location))?;
+        let saved = head
+            .attributes
+            .get(&Attribute::Metadata(MTIME_KEY.into()))
+            .and_then(|value| value.parse::<i64>().ok());
+        let modified = match saved {
+            Some(secs) if secs >= 0 => UNIX_EPOCH + Duration::from_secs(secs as u64),
+            Some(secs) => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+            None => meta.last_modified.into(),
+        };
+        let relative = self.relative(&meta.location);
+        Ok(scan_entry(relative, meta.size, modified, false))
+    }
+}
+
+/// A listed object or key prefix, as the scanner would have found it
+fn scan_entry(relative: &str, size: u64, modified: SystemTime, is_dir: bool) -> ScanEntry {
+    let path = Arc::new(PathBuf::from(relative));
+    ScanEntry {
+        path: Arc::clone(&path),
+        relative_path: path,
+        size,
+        modified,
+        is_dir,
+        is_symlink: false,
+        symlink_target: None,
+        is_sparse: false,
+        allocated_size: size,
+        xattrs: None,
+        inode: None,
+        nlink: 1,
+        acls: None,
+        bsd_flags: None,
+        win_attrs: None,
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn create(&self, entry: &FileEntry) -> Result<Box<dyn StorageFile>> {
+        let mut attributes = Attributes::new();
+        attributes.insert(
+            Attribute::Metadata(MTIME_KEY.into()),
+            entry.mtime.to_string().into(),
+        );
+        Ok(Box::new(S3File {
+            store: Arc::clone(&self.store),
+            location: self.location(&entry.path)?,
+            attributes,
+            buffer: Vec::new(),
+            len: 0,
+            ahead: BTreeMap::new(),
+            upload: None,
+        }))
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<(u32, FileTime)>> {
+        let location = self.location(path)?;
+        let meta = self
+            .store
+            .head(&location)
+            .await
+            .with_context(|| format!("Failed to read metadata of {}", location))?;
+        // Objects have no mode; the listing reported them all as 0644
+        let modified = FileTime::from_system_time(meta.last_modified.into());
+        Ok(Some((0o644, modified)))
+    }
+
+    async fn set_metadata(&self, _path: &str, _mode: u32, _mtime: FileTime) -> Result<()> {
+        // The mtime went up with the object; there's no mode to set
+        Ok(())
+    }
+
+    async fn mkdir(&self, _path: &str, _mode: u32) -> Result<()> {
+        // Key prefixes exist as long as there are objects under them
+        Ok(())
+    }
+
+    async fn symlink(&self, path: &str, _target: &str) -> Result<()> {
+        anyhow::bail!("S3 has no symlinks: {}", path)
+    }
+
+    async fn delete(&self, path: &str, is_dir: bool) -> Result<()> {
+        let location = self.location(path)?;
+        if !is_dir {
+            return match self.store.delete(&location).await {
+                Err(object_store::Error::NotFound { .. }) => Ok(()),
+                result => result.with_context(|| format!("Failed to delete {}", location)),
+            };
+        }
+        let below = self
+            .store
+            .list(Some(&location))
+            .map_ok(|meta| meta.location)
+            .boxed();
+        self.store
+            .delete_stream(below)
+            .try_for_each(|_| async { Ok(()) })
+            .await
+            .with_context(|| format!("Failed to delete {}", location))
+    }
+
+    async fn list(&self) -> Result<Option<Vec<Listed>>> {
+        let objects: Vec<ObjectMeta> = self
+            .store
+            .list(Some(&self.root))
+            .try_collect()
+            .await
+            .with_context(|| format!("Failed to list {}", self.root))?;
+
+        // Key prefixes stand in for directories, so a directory the source
+        // lost is deleted as one
+        let mut dirs = BTreeSet::new();
+        for meta in &objects {
+            let mut relative = self.relative(&meta.location);
+            while let Some((parent, _)) = relative.rsplit_once('/') {
+                if !dirs.insert(parent) {
+                    break;
+                }
+                relative = parent;
+            }
+        }
+        let mut entries: Vec<ScanEntry> = dirs
+            .into_iter()
+            .map(|dir| scan_entry(dir, 0, UNIX_EPOCH, true))
+            .collect();
+
+        let files: Vec<ScanEntry> =
+            stream::iter(objects.into_iter().map(|meta| self.file_entry(meta)))
+                .buffered(HEADS_IN_FLIGHT)
+                .try_collect()
+                .await?;
+        entries.extend(files);
+        // Objects have no modes to report
+        let listed = entries
+            .into_iter()
+            .map(|entry| Listed { entry, mode: None })
+            .collect();
+        Ok(Some(listed))
+    }
+}
+
+/// An object being uploaded
+struct S3File {
+    store: Arc<dyn ObjectStore>,
+    location: ObjectPath,
+    attributes: Attributes,
+    /// The file so far, until it outgrows one part
+    buffer: Vec<u8>,
+    /// Bytes received in order
+    len: u64,
+    /// Chunks that arrived before the ones in front of them
+    ahead: BTreeMap<u64, Bytes>,
+    /// The multipart upload, once the file outgrew `buffer` (locked only
+    /// to be Sync: `get_mut` reaches it)
+    upload: Option<Mutex<WriteMultipart>>,
+}
+
+impl S3File {
+    /// Upload `data`, which goes at `self.len`
+    async fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.len += data.len() as u64;
+        if let Some(upload) = &mut self.upload {
+            let upload = upload.get_mut().unwrap_or_else(PoisonError::into_inner);
+            upload
+                .wait_for_capacity(PARTS_IN_FLIGHT)
+                .await
+                .with_context(|| format!("Failed to upload {}", self.location))?;
+            upload.write(data);
+            return Ok(());
+        }
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= PART_SIZE {
+            let options = PutMultipartOptions {
+                attributes: self.attributes.clone(),
+                ..Default::default()
+            };
+            let upload = self
+                .store
+                .put_multipart_opts(&self.location, options)
+                .await
+                .with_context(|| format!("Failed to start uploading {}", self.location))?;
+            let mut upload = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+            upload.write(&std::mem::take(&mut self.buffer));
+            self.upload = Some(Mutex::new(upload));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageFile for S3File {
+    async fn write_at(&mut self, offset: u64, data: Bytes) -> Result<()> {
+        if offset < self.len {
+            anyhow::bail!(
+                "Can't rewrite {} at {}: S3 objects are uploaded in order",
+                self.location,
+                offset
+            );
+        }
+        if offset > self.len {
+            self.ahead.insert(offset, data);
+            return Ok(());
+        }
+        self.append(&data).await?;
+        while let Some(next) = self.ahead.remove(&self.len) {
+            self.append(&next).await?;
+        }
+        Ok(())
+    }
+
+    async fn commit(mut self: Box<Self>, len: u64) -> Result<()> {
+        if self.len != len || !self.ahead.is_empty() {
+            anyhow::bail!(
+                "Incomplete upload of {}: {} of {} bytes",
+                self.location,
+                self.len,
+                len
+            );
+        }
+        match self.upload.take() {
+            Some(upload) => {
+                let upload = upload.into_inner().unwrap_or_else(PoisonError::into_inner);
+                upload.finish().await
+            }
+            None => {
+                let options = PutOptions {
+                    attributes: std::mem::take(&mut self.attributes),
+                    ..Default::default()
+                };
+                let data = Bytes::from(std::mem::take(&mut self.buffer));
+                self.store
+                    .put_opts(&self.location, data.into(), options)
+                    .await
+            }
+        }
+        .with_context(|| format!("Failed to upload {}", self.location))?;
+        Ok(())
+    }
+}
+
+impl Drop for S3File {
+    fn drop(&mut self) {
+        // Uncommitted: don't leave the parts uploaded so far to be billed
+        if let Some(upload) = self.upload.take() {
+            let upload = upload.into_inner().unwrap_or_else(PoisonError::into_inner);
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                runtime.spawn(async move {
+                    let _ = upload.abort().await;
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::io::IoOptions;
+    use crate::streaming::StreamingSync;
+    use object_store::memory::InMemory;
+    use std::fs;
+    use tempfile::TempDir;
+
+    async fn read(store: &InMemory, key: &str) -> Option<Vec<u8>> {
+        let data = store.get(&ObjectPath::from(key)).await.ok()?;
+        Some(data.bytes().await.unwrap().to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_s3_storage_push() {
+        let tmp = TempDir::new().unwrap();
+        let source = tmp.path().join("source");
+        fs::create_dir_all(source.join("dir")).unwrap();
+        fs::write(source.join("a.txt"), "hello").unwrap();
+        fs::write(source.join("dir/b.txt"), "world").unwrap();
+        // Large enough for a multipart upload
+        let big: Vec<u8> = (0..PART_SIZE * 2 + 123).map(|i| (i % 251) as u8).collect();
+        fs::write(source.join("dir/big.bin"), &big).unwrap();
+
+        let store = Arc::new(InMemory::new());
+        for key in ["backup/stale.txt", "backup/old/c.txt", "other/keep.txt"] {
+            store
+                .put(&ObjectPath::from(key), Bytes::from("old").into())
+                .await
+                .unwrap();
+        }
+
+        let sync = StreamingSync::new(source.clone(), PathBuf::from("backup"), true, false);
+        let storage = S3Storage::with_store(store.clone(), "backup").unwrap();
+        let stats = sync.local_to(storage).await.unwrap();
+        assert_eq!(stats.files_ok, 3);
+
+        assert_eq!(read(&store, "backup/a.txt").await.unwrap(), b"hello");
+        assert_eq!(read(&store, "backup/dir/b.txt").await.unwrap(), b"world");
+        assert_eq!(read(&store, "backup/dir/big.bin").await.unwrap(), big);
+        assert!(read(&store, "backup/stale.txt").await.is_none());
+        assert!(read(&store, "backup/old/c.txt").await.is_none());
+        // Outside the prefix
+        assert!(read(&store, "other/keep.txt").await.is_some());
+
+        // The source mtimes went up with the objects: nothing to send again
+        let storage = S3Storage::with_store(store.clone(), "backup").unwrap();
+        let stats = sync.local_to(storage).await.unwrap();
+        assert_eq!(stats.files_ok, 0);
+        assert_eq!(stats.deleted, 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_s3_storage_push_with_perms_twice() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let source = TempDir::new().unwrap();
+        let script = source.path().join("run.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let store = Arc::new(InMemory::new());
+        let sync = StreamingSync::new(
+            source.path().to_path_buf(),
+            PathBuf::from("backup"),
+            true,
+            false,
+        )
+        .with_io(IoOptions {
+            perms: true,
+            ..Default::default()
+        });
+        for _ in 0..2 {
+            let storage = S3Storage::with_store(store.clone(), "backup").unwrap();
+            let stats = sync.local_to(storage).await.unwrap();
+            assert_eq!(stats.files_err, 0, "{:?}", stats.errors);
+            assert!(stats.errors.is_empty(), "{:?}", stats.errors);
+        }
+        assert_eq!(read(&store, "backup/run.sh").await.unwrap(), b"#!/bin/sh\n");
+    }
+
+    #[tokio::test]
+    async fn test_s3_file_reorders_chunks() {
+        let store = Arc::new(InMemory::new());
+        let storage = S3Storage::with_store(store.clone(), "").unwrap();
+        let entry = FileEntry {
+            path: "a.txt".into(),
+            size: 11,
+            mtime: 1234567890,
+            mtime_nsec: 0,
+            mode: 0o644,
+            inode: 0,
+            flags: crate::streaming::protocol::FileFlags::empty(),
+            symlink_target: None,
+            link_target: None,
+            win_attrs: None,
+            bsd_flags: None,
+            owner: None,
+            times: None,
+        };
+        let mut file = storage.create(&entry).await.unwrap();
+        file.write_at(6, Bytes::from("world")).await.unwrap();
+        file.write_at(0, Bytes::from("hello ")).await.unwrap();
+        // Already uploaded
+        assert!(file.write_at(0, Bytes::from("x")).await.is_err());
+        file.commit(11).await.unwrap();
+        assert_eq!(read(&store, "a.txt").await.unwrap(), b"hello world");
+
+        let listed = storage.list().await.unwrap().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(
+            listed[0].entry.modified,
+            UNIX_EPOCH + Duration::from_secs(1234567890)
+        );
+
+        // Nothing appears without a commit
+        let entry = FileEntry {
+            path: "b.txt".into(),
+            ..entry
+        };
+        let mut file = storage.create(&entry).await.unwrap();
+        file.write_at(0, Bytes::from("partial")).await.unwrap();
+        drop(file);
+        storage.delete("a.txt", false).await.unwrap();
+        assert!(storage.list().await.unwrap().unwrap().is_empty());
+    }
+}
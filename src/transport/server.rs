@@ -6,22 +6,78 @@
 
 use anyhow::{Context, Result};
 use std::path::Path;
-use std::process::Stdio;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
 use tokio::process::{Child, Command};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
 
 use crate::ssh::config::SshConfig;
 
+/// How long a failed handshake waits for the process to report its exit
+const EXIT_WAIT: Duration = Duration::from_secs(2);
+
+/// How long a finished session waits for the server to wind down (e.g.
+/// remove its temp files after a FATAL) before killing the process
+const REAP_WAIT: Duration = Duration::from_secs(10);
+
 /// Manages connection to a remote sy --server instance
 pub struct ServerSession {
-    #[allow(dead_code)]
-    child: Child,
     stdin: tokio::process::ChildStdin,
     stdout: tokio::process::ChildStdout,
+    exit: ServerExit,
+}
+
+/// How the process behind a session ended, once it has
+#[derive(Clone)]
+pub struct ServerExit {
+    status: watch::Receiver<Option<ExitStatus>>,
+    kill: CancellationToken,
+}
+
+impl ServerExit {
+    /// Whether the remote shell couldn't find `sy` (exit status 127,
+    /// "command not found"); ask once the handshake has failed
+    pub async fn command_not_found(mut self) -> bool {
+        let exited = tokio::time::timeout(EXIT_WAIT, self.status.wait_for(Option::is_some)).await;
+        match exited {
+            Ok(Ok(status)) => status.and_then(|status| status.code()) == Some(127),
+            _ => false,
+        }
+    }
+
+    /// Wait for the process to exit once the session is over and its pipes
+    /// are closed, killing it if it's still there after REAP_WAIT, so no
+    /// ssh (or server) outlives the sync
+    pub async fn reap(self) -> Option<ExitStatus> {
+        self.reap_within(REAP_WAIT).await
+    }
+
+    async fn reap_within(mut self, wait: Duration) -> Option<ExitStatus> {
+        let exited = tokio::time::timeout(wait, self.status.wait_for(Option::is_some)).await;
+        if let Ok(Ok(status)) = exited {
+            return *status;
+        }
+        drop(exited);
+        tracing::warn!("The server didn't exit after the session; killing it");
+        self.kill.cancel();
+        let status = self.status.wait_for(Option::is_some).await.ok()?;
+        *status
+    }
 }
 
 impl ServerSession {
     /// Connect to remote server via SSH
-    pub async fn connect_ssh(config: &SshConfig, remote_path: &Path) -> Result<Self> {
+    ///
+    /// `server_args` are extra flags for the remote `sy --server` (e.g. `--io-uring`).
+    /// With `config.remote_sudo`, the server is started through that command,
+    /// which has to run it without prompting (`sudo -n`).
+    #[tracing::instrument(name = "ssh.connect", skip_all, fields(host = %config.hostname))]
+    pub async fn connect_ssh(
+        config: &SshConfig,
+        remote_path: &Path,
+        server_args: &[String],
+    ) -> Result<Self> {
         let mut cmd = Command::new("ssh");
 
         cmd.arg(&config.hostname);
@@ -38,52 +94,129 @@ impl ServerSession {
             cmd.arg("-i").arg(key);
         }
 
-        // Remote command: sy --server <remote_path>
+        // Remote command: [sudo -n] sy --server [args] <remote_path>
+        if let Some(elevate) = &config.remote_sudo {
+            cmd.args(elevate.split_whitespace());
+        }
         cmd.arg("sy");
         cmd.arg("--server");
+        cmd.args(server_args);
         cmd.arg(remote_path);
 
         cmd.stdin(Stdio::piped());
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::inherit());
+        ignore_interrupts(&mut cmd);
 
-        let mut child = cmd.spawn().context("Failed to spawn SSH process")?;
-
-        let stdin = child.stdin.take().context("Failed to open stdin")?;
-        let stdout = child.stdout.take().context("Failed to open stdout")?;
-
-        Ok(Self {
-            child,
-            stdin,
-            stdout,
-        })
+        let child = cmd.spawn().context("Failed to spawn SSH process")?;
+        Self::with_child(child)
     }
 
     /// Connect to local server (for testing)
-    pub async fn connect_local(remote_path: &Path) -> Result<Self> {
+    pub async fn connect_local(remote_path: &Path, server_args: &[String]) -> Result<Self> {
         let exe = std::env::current_exe()?;
         let mut cmd = Command::new(exe);
         cmd.arg("--server");
+        cmd.args(server_args);
         cmd.arg(remote_path);
 
         cmd.stdin(Stdio::piped());
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::inherit());
 
-        let mut child = cmd.spawn().context("Failed to spawn sy process")?;
+        let child = cmd.spawn().context("Failed to spawn sy process")?;
+        Self::with_child(child)
+    }
 
+    /// Talk to `child` over its stdin and stdout, and reap it when it exits
+    fn with_child(mut child: Child) -> Result<Self> {
         let stdin = child.stdin.take().context("Failed to open stdin")?;
         let stdout = child.stdout.take().context("Failed to open stdout")?;
 
+        let (exited, status) = watch::channel(None);
+        let kill = CancellationToken::new();
+        let killed = kill.clone();
+        tokio::spawn(async move {
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = killed.cancelled() => match child.kill().await {
+                    Ok(()) => child.wait().await,
+                    Err(e) => Err(e),
+                },
+            };
+            if let Ok(status) = status {
+                let _ = exited.send(Some(status));
+            }
+        });
+
         Ok(Self {
-            child,
             stdin,
             stdout,
+            exit: ServerExit { status, kill },
         })
     }
 
+    /// How the process ends; keep it before `split`
+    pub fn exit(&self) -> ServerExit {
+        self.exit.clone()
+    }
+
     /// Split into stdin/stdout for protocol handling
     pub fn split(self) -> (tokio::process::ChildStdin, tokio::process::ChildStdout) {
         (self.stdin, self.stdout)
     }
 }
+
+/// Leave Ctrl-C to us: the terminal sends SIGINT to ssh as well, which
+/// would drop the connection before the server hears we stopped. ssh keeps
+/// an ignored SIGINT ignored, and still quits when we close its stdin
+fn ignore_interrupts(cmd: &mut Command) {
+    #[cfg(unix)]
+    // SAFETY: signal() is async-signal-safe, so it may run between fork and exec
+    unsafe {
+        cmd.pre_exec(|| {
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+            Ok(())
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn shell(script: &str) -> ServerSession {
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        ServerSession::with_child(child).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_exit_command_not_found() {
+        let session = shell("exit 127");
+        assert!(session.exit().command_not_found().await);
+
+        let session = shell("exit 1");
+        assert!(!session.exit().command_not_found().await);
+    }
+
+    #[tokio::test]
+    async fn test_reap_kills_a_server_that_stays() {
+        let session = shell("exit 3");
+        let status = session.exit().reap_within(Duration::from_secs(5)).await;
+        assert_eq!(status.and_then(|status| status.code()), Some(3));
+
+        let session = shell("sleep 30");
+        let started = std::time::Instant::now();
+        let status = session.exit().reap_within(Duration::from_millis(100)).await;
+        assert!(status.is_some_and(|status| !status.success()));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
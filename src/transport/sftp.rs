@@ -0,0 +1,351 @@
+//! Plain SFTP as a destination of the streaming pipeline.
+//!
+//! For hosts without `sy` installed: [`SftpStorage`] is a [`Storage`]
+//! backend that only needs the SSH server's SFTP subsystem, so a push
+//! there runs the Generator/Receiver pipeline in-process
+//! (`StreamingSync::local_to`) instead of talking to `sy --server`.
+//!
+//! The Initial Exchange walks the destination with readdir, and nothing
+//! reads the files back, so every changed file is sent whole; the listing
+//! carries modes for `-p` to compare, and SET_ATTR is a setstat. Files are
+//! written to a temp file next to their path and renamed into place.
+//! Ownership, xattrs, ACLs, hard links and `--atomic` need `sy` on the host.
+
+use crate::ssh::config::SshConfig;
+use crate::streaming::protocol::FileEntry;
+use crate::streaming::storage::{temp_path, Listed, Storage, StorageFile};
+use crate::sync::scanner::FileEntry as ScanEntry;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use filetime::FileTime;
+use ssh2::{ErrorCode, File, FileStat, FileType, Sftp};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// A directory on a host reached over SFTP
+pub struct SftpStorage {
+    sftp: Arc<Sftp>,
+    root: PathBuf,
+}
+
+impl SftpStorage {
+    /// Open an SFTP session to `config`'s host and use `root` there,
+    /// creating it if it's missing
+    pub async fn connect(config: &SshConfig, root: &Path) -> Result<Self> {
+        let session = crate::ssh::connect::connect(config).await?;
+        let sftp = tokio::task::spawn_blocking(move || session.sftp())
+            .await?
+            .context("Failed to start SFTP session")?;
+        // SFTP resolves relative paths against the home directory itself
+        let root = root.strip_prefix("~").unwrap_or(root).to_path_buf();
+        let storage = Self {
+            sftp: Arc::new(sftp),
+            root,
+        };
+        storage.mkdir("", 0o755).await?;
+        Ok(storage)
+    }
+
+    /// Run `op` on the blocking pool: every SFTP call waits on the network
+    async fn run<T, F>(&self, op: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Sftp) -> Result<T> + Send + 'static,
+    {
+        let sftp = Arc::clone(&self.sftp);
+        tokio::task::spawn_blocking(move || op(&sftp)).await?
+    }
+
+    /// Where `path` (relative to the root) is on the host
+    fn full(&self, path: &str) -> PathBuf {
+        if path.is_empty() {
+            self.root.clone()
+        } else {
+            self.root.join(path)
+        }
+    }
+}
+
+/// Whether `e` says there's no such file (LIBSSH2_FX_NO_SUCH_FILE or
+/// LIBSSH2_FX_NO_SUCH_PATH)
+fn is_missing(e: &ssh2::Error) -> bool {
+    matches!(e.code(), ErrorCode::SFTP(2) | ErrorCode::SFTP(10))
+}
+
+/// What's at `path`, without following a symlink; None if nothing is
+fn lstat(sftp: &Sftp, path: &Path) -> Result<Option<FileStat>> {
+    match sftp.lstat(path) {
+        Ok(stat) => Ok(Some(stat)),
+        Err(e) if is_missing(&e) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to stat {}", path.display())),
+    }
+}
+
+/// Create `dir` and any missing parents
+fn mkdir_all(sftp: &Sftp, dir: &Path, mode: u32) -> Result<()> {
+    let mut current = PathBuf::new();
+    for component in dir.components() {
+        current.push(component);
+        match lstat(sftp, &current)? {
+            Some(stat) if stat.is_dir() => continue,
+            Some(_) => anyhow::bail!("Not a directory: {}", current.display()),
+            None => sftp
+                .mkdir(&current, mode as i32)
+                .with_context(|| format!("Failed to create {}", current.display()))?,
+        }
+    }
+    Ok(())
+}
+
+/// Remove whatever is at `path`, a directory with its contents
+fn remove_all(sftp: &Sftp, path: &Path) -> Result<()> {
+    let Some(stat) = lstat(sftp, path)? else {
+        return Ok(());
+    };
+    if stat.file_type() != FileType::Directory {
+        return sftp
+            .unlink(path)
+            .with_context(|| format!("Failed to remove {}", path.display()));
+    }
+    let children = sftp
+        .readdir(path)
+        .with_context(|| format!("Failed to list {}", path.display()))?;
+    for (child, _) in children {
+        remove_all(sftp, &child)?;
+    }
+    sftp.rmdir(path)
+        .with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+/// Move `from` onto `to`
+///
+/// Servers that only speak SFTP v3 (OpenSSH) won't rename over an existing
+/// file and fail with a bare SSH_FX_FAILURE. Only then, and only if `to` is
+/// a file or symlink and `from` is still there, is `to` unlinked for a
+/// second try; a directory is never removed, and any other failure leaves
+/// `to` as it was.
+fn replace(sftp: &Sftp, from: &Path, to: &Path) -> Result<()> {
+    let failed = || format!("Failed to rename {} to {}", from.display(), to.display());
+    let Err(e) = sftp.rename(from, to, None) else {
+        return Ok(());
+    };
+    let target_exists = matches!(e.code(), ErrorCode::SFTP(4))
+        && lstat(sftp, from)?.is_some()
+        && lstat(sftp, to)?.is_some_and(|stat| {
+            matches!(stat.file_type(), FileType::RegularFile | FileType::Symlink)
+        });
+    if !target_exists {
+        return Err(e).with_context(failed);
+    }
+    sftp.unlink(to)
+        .with_context(|| format!("Failed to remove {}", to.display()))?;
+    sftp.rename(from, to, None).with_context(failed)
+}
+
+/// Everything below `dir`, as the scanner would have found it
+fn walk(sftp: &Sftp, root: &Path, dir: &Path, entries: &mut Vec<Listed>) -> Result<()> {
+    let children = sftp
+        .readdir(dir)
+        .with_context(|| format!("Failed to list {}", dir.display()))?;
+    for (path, stat) in children {
+        let file_type = stat.file_type();
+        let is_dir = file_type == FileType::Directory;
+        let is_symlink = file_type == FileType::Symlink;
+        let symlink_target = if is_symlink {
+            sftp.readlink(&path).ok().map(Arc::new)
+        } else {
+            None
+        };
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        let size = stat.size.unwrap_or(0);
+        let entry = ScanEntry {
+            path: Arc::new(path.clone()),
+            relative_path: Arc::new(relative),
+            size,
+            modified: UNIX_EPOCH + Duration::from_secs(stat.mtime.unwrap_or(0)),
+            is_dir,
+            is_symlink,
+            symlink_target,
+            is_sparse: false,
+            allocated_size: size,
+            xattrs: None,
+            inode: None,
+            nlink: 1,
+            acls: None,
+            bsd_flags: None,
+            win_attrs: None,
+        };
+        entries.push(Listed {
+            entry,
+            mode: stat.perm.map(|perm| perm & 0o7777),
+        });
+        if is_dir {
+            walk(sftp, root, &path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SftpStorage {
+    async fn create(&self, entry: &FileEntry) -> Result<Box<dyn StorageFile>> {
+        let path = self.full(&entry.path);
+        let temp = temp_path(&path);
+        let opened = temp.clone();
+        let file = self
+            .run(move |sftp| {
+                sftp.create(&opened)
+                    .with_context(|| format!("Failed to create {}", opened.display()))
+            })
+            .await?;
+        Ok(Box::new(SftpFile {
+            sftp: Arc::clone(&self.sftp),
+            file: Some(file),
+            temp: Some(temp),
+            path,
+        }))
+    }
+
+    async fn stat(&self, path: &str) -> Result<Option<(u32, FileTime)>> {
+        let path = self.full(path);
+        self.run(move |sftp| {
+            let stat =
+                lstat(sftp, &path)?.with_context(|| format!("No such file: {}", path.display()))?;
+            if stat.file_type() != FileType::RegularFile {
+                return Ok(None);
+            }
+            let mode = stat.perm.unwrap_or(0o644) & 0o7777;
+            let mtime = FileTime::from_unix_time(stat.mtime.unwrap_or(0) as i64, 0);
+            Ok(Some((mode, mtime)))
+        })
+        .await
+    }
+
+    async fn set_metadata(&self, path: &str, mode: u32, mtime: FileTime) -> Result<()> {
+        let path = self.full(path);
+        // SFTP v3 times are whole seconds
+        let mtime = mtime.unix_seconds().max(0) as u64;
+        self.run(move |sftp| {
+            let stat = FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: Some(mode),
+                atime: Some(mtime),
+                mtime: Some(mtime),
+            };
+            sftp.setstat(&path, stat)
+                .with_context(|| format!("Failed to set metadata of {}", path.display()))
+        })
+        .await
+    }
+
+    async fn mkdir(&self, path: &str, mode: u32) -> Result<()> {
+        let dir = self.full(path);
+        self.run(move |sftp| mkdir_all(sftp, &dir, mode)).await
+    }
+
+    async fn symlink(&self, path: &str, target: &str) -> Result<()> {
+        let link = self.full(path);
+        let target = PathBuf::from(target);
+        self.run(move |sftp| {
+            remove_all(sftp, &link)?;
+            // OpenSSH takes the target first, whatever the draft says
+            sftp.symlink(&target, &link)
+                .with_context(|| format!("Failed to create symlink {}", link.display()))
+        })
+        .await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let (from, to) = (self.full(from), self.full(to));
+        self.run(move |sftp| {
+            if let Some(parent) = to.parent() {
+                mkdir_all(sftp, parent, 0o755)?;
+            }
+            replace(sftp, &from, &to)
+        })
+        .await
+    }
+
+    async fn delete(&self, path: &str, _is_dir: bool) -> Result<()> {
+        let path = self.full(path);
+        self.run(move |sftp| remove_all(sftp, &path)).await
+    }
+
+    async fn list(&self) -> Result<Option<Vec<Listed>>> {
+        let root = self.root.clone();
+        let entries = self
+            .run(move |sftp| {
+                let mut entries = Vec::new();
+                walk(sftp, &root, &root, &mut entries)?;
+                Ok(entries)
+            })
+            .await?;
+        Ok(Some(entries))
+    }
+}
+
+/// A file being written to its temp path
+struct SftpFile {
+    sftp: Arc<Sftp>,
+    /// Taken while a write is on the blocking pool
+    file: Option<File>,
+    /// Removed on drop until the file is committed
+    temp: Option<PathBuf>,
+    path: PathBuf,
+}
+
+#[async_trait]
+impl StorageFile for SftpFile {
+    async fn write_at(&mut self, offset: u64, data: Bytes) -> Result<()> {
+        let mut file = self
+            .file
+            .take()
+            .with_context(|| format!("{} failed earlier", self.path.display()))?;
+        let file = tokio::task::spawn_blocking(move || -> std::io::Result<File> {
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&data)?;
+            Ok(file)
+        })
+        .await?
+        .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    async fn commit(mut self: Box<Self>, _len: u64) -> Result<()> {
+        let file = self
+            .file
+            .take()
+            .with_context(|| format!("{} failed earlier", self.path.display()))?;
+        let temp = self.temp.clone().context("Already committed")?;
+        let (sftp, path) = (Arc::clone(&self.sftp), self.path.clone());
+        tokio::task::spawn_blocking(move || {
+            // Closed before it's moved
+            drop(file);
+            replace(&sftp, &temp, &path)
+        })
+        .await??;
+        self.temp = None;
+        Ok(())
+    }
+}
+
+impl Drop for SftpFile {
+    fn drop(&mut self) {
+        let Some(temp) = self.temp.take() else {
+            return;
+        };
+        let (sftp, file) = (Arc::clone(&self.sftp), self.file.take());
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn_blocking(move || {
+                drop(file);
+                let _ = sftp.unlink(&temp);
+            });
+        }
+    }
+}
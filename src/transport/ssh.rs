@@ -794,6 +794,9 @@ impl SshTransport {
                 literal_bytes: None,
                 transferred_bytes: Some(response.bytes_written),
                 compression_used: false,
+                sparse_bytes_skipped: Some(
+                    response.file_size.saturating_sub(response.bytes_written),
+                ),
             })
             }
                 })
@@ -1130,6 +1133,7 @@ impl Transport for SshTransport {
         self.connection_pool.expand_to(optimal_connections).await
     }
 
+    #[tracing::instrument(name = "ssh.scan", skip_all, fields(path = %path.display()))]
     async fn scan(&self, path: &Path) -> Result<Vec<FileEntry>> {
         let path_str = path.to_string_lossy();
         let mut command = format!("{} scan {}", self.remote_binary_path, path_str);
@@ -1198,6 +1202,7 @@ impl Transport for SshTransport {
                     nlink: e.nlink,
                     acls,
                     bsd_flags: None, // TODO: Serialize BSD flags in SSH protocol
+                    win_attrs: None,
                 })
             })
             .collect();
@@ -1344,6 +1349,7 @@ impl Transport for SshTransport {
         Ok(())
     }
 
+    #[tracing::instrument(name = "ssh.copy_file", skip_all, fields(path = %dest.display()))]
     async fn copy_file(&self, source: &Path, dest: &Path) -> Result<TransferResult> {
         // Check if file is sparse and try sparse transfer first
         #[cfg(unix)]
@@ -1798,6 +1804,7 @@ impl Transport for SshTransport {
         .await
     }
 
+    #[tracing::instrument(name = "ssh.sync_file_with_delta", skip_all, fields(path = %dest.display()))]
     async fn sync_file_with_delta(&self, source: &Path, dest: &Path) -> Result<TransferResult> {
         // Check if remote destination exists
         if !self.exists(dest).await? {
@@ -2780,28 +2787,24 @@ impl Transport for SshTransport {
         let path_str = path.to_string_lossy();
 
         for (name, value) in xattrs {
-            // Encode value as base64 for safe shell transmission
-            use base64::{engine::general_purpose, Engine as _};
-            let value_b64 = general_purpose::STANDARD.encode(value);
-
-            // Try Linux setfattr first, fallback to macOS xattr
-            // Linux: setfattr -n name -v value path
-            // macOS: xattr -w name value path (but xattr expects text, not binary)
+            // The value goes over stdin: binary-safe, and resource forks are
+            // far beyond command line limits. sy-remote maps the name and
+            // checks the size for the remote platform
             let command = format!(
-                "if command -v setfattr >/dev/null 2>&1; then \
-                    echo '{}' | base64 -d | setfattr -n '{}' -v - '{}'; \
-                 elif command -v xattr >/dev/null 2>&1; then \
-                    echo '{}' | base64 -d | xattr -w '{}' - '{}'; \
-                 else \
-                    echo 'No xattr tool found' >&2; exit 1; \
-                 fi",
-                value_b64, name, path_str, value_b64, name, path_str
+                "{} set-xattr --name '{}' '{}'",
+                self.remote_binary_path,
+                name.replace('\'', "'\\''"),
+                path_str.replace('\'', "'\\''")
             );
+            let session = self.connection_pool.get_session();
+            let value = value.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                Self::execute_command_with_stdin(session, &command, &value)
+            })
+            .await
+            .map_err(|e| SyncError::Io(std::io::Error::other(e.to_string())))?;
 
-            match self
-                .execute_command_with_retry(self.connection_pool.get_session(), &command)
-                .await
-            {
+            match result {
                 Ok(_) => {
                     tracing::debug!("Set remote xattr {} on {}", name, path.display());
                 }
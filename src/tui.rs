@@ -0,0 +1,494 @@
+//! Live dashboard for long transfers (`--tui`)
+//!
+//! Driven by the engine's event stream (`SyncEngine::with_events`): the
+//! files in flight, a throughput graph, what's left in the queue, the
+//! errors so far and an ETA. Drawn on the alternate screen from a thread of
+//! its own, which gives the terminal back once the sync is done. `q` and
+//! Ctrl+C interrupt the sync the way Ctrl+C would without the dashboard.
+
+use crate::resource::format_bytes;
+use crate::sync::progress::{EventSender, SyncEvent};
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, Sparkline};
+use ratatui::Frame;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// How often the screen is redrawn
+const TICK: Duration = Duration::from_millis(200);
+
+/// Throughput samples kept for the graph, one per second
+const SAMPLES: usize = 120;
+
+/// Errors kept for display; older ones are only counted
+const MAX_ERRORS: usize = 100;
+
+/// A running dashboard; [`Dashboard::finish`] closes it
+pub struct Dashboard {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl Dashboard {
+    /// Take over the terminal; returns the sender to hand to
+    /// `SyncEngine::with_events`
+    pub fn start(workers: usize) -> Result<(EventSender, Self)> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut terminal = ratatui::try_init()?;
+        // Whatever was printed before shouldn't show through blank cells
+        terminal.clear()?;
+        let thread = std::thread::spawn({
+            let stop = Arc::clone(&stop);
+            move || {
+                let result = run(&mut terminal, rx, workers, &stop);
+                ratatui::restore();
+                result
+            }
+        });
+        Ok((
+            tx,
+            Self {
+                stop,
+                thread: Some(thread),
+            },
+        ))
+    }
+
+    /// Draw what's left to draw and give the terminal back
+    pub fn finish(mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => anyhow::bail!("Dashboard thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    /// A sync that fails still gets its terminal back
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+fn run(
+    terminal: &mut ratatui::DefaultTerminal,
+    mut rx: UnboundedReceiver<SyncEvent>,
+    workers: usize,
+    stop: &AtomicBool,
+) -> Result<()> {
+    let mut state = State::new(workers, Instant::now());
+    loop {
+        let stopping = stop.load(Ordering::Relaxed);
+        let now = Instant::now();
+        while let Ok(event) = rx.try_recv() {
+            state.apply(event, now);
+        }
+        state.tick(now);
+        terminal.draw(|frame| state.render(frame, now))?;
+        if stopping {
+            return Ok(());
+        }
+
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (key.code == KeyCode::Char('q') || ctrl_c) {
+                    ratatui::restore();
+                    interrupt();
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Raw mode keeps Ctrl+C from reaching us as a signal; send it ourselves,
+/// so watch mode stops the way it always does
+fn interrupt() {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(libc::getpid(), libc::SIGINT);
+    }
+    #[cfg(not(unix))]
+    std::process::exit(130);
+}
+
+/// A file being transferred
+struct Worker {
+    path: PathBuf,
+    size: u64,
+    since: Instant,
+}
+
+/// What the dashboard knows, built up from events
+struct State {
+    workers: Vec<Option<Worker>>,
+    files_total: u64,
+    files_done: u64,
+    bytes_total: u64,
+    bytes_done: u64,
+    errors: VecDeque<(PathBuf, String)>,
+    error_count: u64,
+    /// Bytes per second, newest last
+    samples: VecDeque<u64>,
+    last_sample: Instant,
+    bytes_at_sample: u64,
+}
+
+impl State {
+    fn new(workers: usize, now: Instant) -> Self {
+        Self {
+            workers: (0..workers.max(1)).map(|_| None).collect(),
+            files_total: 0,
+            files_done: 0,
+            bytes_total: 0,
+            bytes_done: 0,
+            errors: VecDeque::new(),
+            error_count: 0,
+            samples: VecDeque::new(),
+            last_sample: now,
+            bytes_at_sample: 0,
+        }
+    }
+
+    fn apply(&mut self, event: SyncEvent, now: Instant) {
+        match event {
+            // A new run (watch mode syncs again on every change)
+            SyncEvent::Scanned {
+                files_total,
+                bytes_total,
+                ..
+            } => {
+                self.workers.iter_mut().for_each(|w| *w = None);
+                self.files_total = files_total;
+                self.bytes_total = bytes_total;
+                self.files_done = 0;
+                self.bytes_done = 0;
+                self.bytes_at_sample = 0;
+            }
+            SyncEvent::FileStarted { path, size } => {
+                let worker = Worker {
+                    path,
+                    size,
+                    since: now,
+                };
+                match self.workers.iter_mut().find(|w| w.is_none()) {
+                    Some(slot) => *slot = Some(worker),
+                    None => self.workers.push(Some(worker)),
+                }
+            }
+            SyncEvent::FileDone(progress) => {
+                self.release(&progress.path);
+                self.files_total = progress.files_total;
+                self.files_done = progress.files_done;
+                self.bytes_total = progress.bytes_total;
+                self.bytes_done = progress.bytes_done;
+            }
+            SyncEvent::Error { path, message } => {
+                self.release(&path);
+                self.error_count += 1;
+                if self.errors.len() == MAX_ERRORS {
+                    self.errors.pop_front();
+                }
+                self.errors.push_back((path, message));
+            }
+            SyncEvent::Deleted { .. } | SyncEvent::Done(_) => {}
+        }
+    }
+
+    fn release(&mut self, path: &PathBuf) {
+        if let Some(slot) = self
+            .workers
+            .iter_mut()
+            .find(|w| w.as_ref().is_some_and(|w| &w.path == path))
+        {
+            *slot = None;
+        }
+    }
+
+    /// Take a throughput sample once a second has gone by
+    fn tick(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_sample);
+        if elapsed < Duration::from_secs(1) {
+            return;
+        }
+        let bytes = self.bytes_done.saturating_sub(self.bytes_at_sample);
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples
+            .push_back((bytes as f64 / elapsed.as_secs_f64()) as u64);
+        self.last_sample = now;
+        self.bytes_at_sample = self.bytes_done;
+    }
+
+    fn in_flight(&self) -> u64 {
+        self.workers.iter().flatten().count() as u64
+    }
+
+    /// Entries neither done nor being transferred
+    fn queued(&self) -> u64 {
+        self.files_total
+            .saturating_sub(self.files_done + self.in_flight())
+    }
+
+    /// Bytes per second over the last few samples
+    fn rate(&self) -> u64 {
+        let recent: Vec<_> = self.samples.iter().rev().take(5).collect();
+        if recent.is_empty() {
+            return 0;
+        }
+        recent.iter().copied().sum::<u64>() / recent.len() as u64
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        let rate = self.rate();
+        let left = self.bytes_total.saturating_sub(self.bytes_done);
+        (rate > 0 && left > 0).then(|| Duration::from_secs(left / rate))
+    }
+
+    fn render(&self, frame: &mut Frame, now: Instant) {
+        let errors_height = if self.errors.is_empty() { 3 } else { 8 };
+        let [overall, workers, graph, errors] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(4),
+            Constraint::Length(7),
+            Constraint::Length(errors_height),
+        ])
+        .areas(frame.area());
+
+        let ratio = if self.bytes_total > 0 {
+            (self.bytes_done as f64 / self.bytes_total as f64).min(1.0)
+        } else {
+            0.0
+        };
+        let eta = self
+            .eta()
+            .map(|eta| format!("ETA {}", format_eta(eta)))
+            .unwrap_or_default();
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                    " sy: {}/{} files, {} queued ",
+                    self.files_done,
+                    self.files_total,
+                    self.queued()
+                )))
+                .gauge_style(Style::default().fg(Color::Cyan))
+                .ratio(ratio)
+                .label(format!(
+                    "{} / {}  {}/s  {}",
+                    format_bytes(self.bytes_done),
+                    format_bytes(self.bytes_total),
+                    format_bytes(self.rate()),
+                    eta
+                )),
+            overall,
+        );
+
+        let items: Vec<ListItem> = self
+            .workers
+            .iter()
+            .enumerate()
+            .map(|(i, worker)| match worker {
+                Some(w) => ListItem::new(format!(
+                    "{:>3}  {}  ({}, {}s)",
+                    i + 1,
+                    w.path.display(),
+                    format_bytes(w.size),
+                    now.duration_since(w.since).as_secs()
+                )),
+                None => ListItem::new(format!("{:>3}  idle", i + 1))
+                    .style(Style::default().fg(Color::DarkGray)),
+            })
+            .collect();
+        frame.render_widget(
+            List::new(items).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" Workers ({} busy) ", self.in_flight())),
+            ),
+            workers,
+        );
+
+        let data: Vec<u64> = self.samples.iter().copied().collect();
+        let peak = data.iter().copied().max().unwrap_or(0);
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                    " Throughput, last {}s (peak {}/s) ",
+                    SAMPLES,
+                    format_bytes(peak)
+                )))
+                .style(Style::default().fg(Color::Green))
+                .data(&data),
+            graph,
+        );
+
+        let shown = errors.height.saturating_sub(2) as usize;
+        let items: Vec<ListItem> = self
+            .errors
+            .iter()
+            .rev()
+            .take(shown)
+            .map(|(path, message)| {
+                ListItem::new(Line::from(format!("{}: {}", path.display(), message)))
+            })
+            .collect();
+        let title_style = if self.error_count > 0 {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default()
+        };
+        frame.render_widget(
+            List::new(items).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(" Errors ({}) ", self.error_count))
+                    .title_style(title_style),
+            ),
+            errors,
+        );
+    }
+}
+
+fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::progress::Progress;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn done(path: &str, files_done: u64, bytes_done: u64) -> SyncEvent {
+        SyncEvent::FileDone(Progress {
+            path: PathBuf::from(path),
+            files_done,
+            files_total: 4,
+            bytes_done,
+            bytes_total: 4000,
+        })
+    }
+
+    #[test]
+    fn test_state_follows_events() {
+        let start = Instant::now();
+        let mut state = State::new(2, start);
+        state.apply(
+            SyncEvent::Scanned {
+                files_scanned: 10,
+                files_total: 4,
+                bytes_total: 4000,
+            },
+            start,
+        );
+        for path in ["a", "b", "c"] {
+            state.apply(
+                SyncEvent::FileStarted {
+                    path: PathBuf::from(path),
+                    size: 1000,
+                },
+                start,
+            );
+        }
+        // More files in flight than workers: the list grows
+        assert_eq!(state.workers.len(), 3);
+        assert_eq!(state.queued(), 1);
+
+        state.apply(done("a", 1, 1000), start);
+        state.apply(
+            SyncEvent::Error {
+                path: PathBuf::from("b"),
+                message: "Permission denied".to_string(),
+            },
+            start,
+        );
+        assert_eq!(state.in_flight(), 1);
+        assert_eq!(state.error_count, 1);
+        // "a" freed the first slot
+        state.apply(
+            SyncEvent::FileStarted {
+                path: PathBuf::from("d"),
+                size: 1000,
+            },
+            start,
+        );
+        assert_eq!(state.workers[0].as_ref().unwrap().path, PathBuf::from("d"));
+
+        // 1000 bytes in two seconds: 500 B/s, 3000 bytes to go
+        state.tick(start + Duration::from_secs(2));
+        assert_eq!(state.rate(), 500);
+        assert_eq!(state.eta(), Some(Duration::from_secs(6)));
+
+        state.apply(done("c", 2, 2000), start);
+        state.apply(done("d", 3, 4000), start);
+        assert_eq!(state.eta(), None);
+        assert_eq!(state.queued(), 1);
+    }
+
+    #[test]
+    fn test_render_shows_workers_and_errors() {
+        let now = Instant::now();
+        let mut state = State::new(2, now);
+        state.apply(
+            SyncEvent::FileStarted {
+                path: PathBuf::from("videos/big.mkv"),
+                size: 5 << 30,
+            },
+            now,
+        );
+        state.apply(
+            SyncEvent::Error {
+                path: PathBuf::from("locked.db"),
+                message: "Permission denied".to_string(),
+            },
+            now,
+        );
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal.draw(|frame| state.render(frame, now)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("videos/big.mkv"));
+        assert!(screen.contains("5.00 GB"));
+        assert!(screen.contains("Errors (1)"));
+        assert!(screen.contains("locked.db: Permission denied"));
+    }
+
+    #[test]
+    fn test_format_eta() {
+        assert_eq!(format_eta(Duration::from_secs(42)), "42s");
+        assert_eq!(format_eta(Duration::from_secs(125)), "2m 05s");
+        assert_eq!(format_eta(Duration::from_secs(7260)), "2h 01m");
+    }
+}
@@ -0,0 +1,243 @@
+//! Turning filesystem events into batches of dirty paths
+
+use notify::{Event, EventKind};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// More dirty paths than this and a batch rescans the whole tree instead:
+/// one walk beats thousands of small ones
+pub const MAX_BATCH_PATHS: usize = 1024;
+
+/// What a sync triggered by the watcher has to look at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Batch {
+    /// The whole tree (the first sync, or events were lost)
+    All,
+    /// Just these paths, relative to the root, none below another
+    Paths(Vec<PathBuf>),
+}
+
+impl Batch {
+    /// The scope to sync, `None` meaning everything
+    pub fn into_scope(self) -> Option<Vec<PathBuf>> {
+        match self {
+            Batch::All => None,
+            Batch::Paths(paths) => Some(paths),
+        }
+    }
+}
+
+/// Collects the paths events touch until things have been quiet for the
+/// debounce delay, or changes have kept coming for `max_delay`
+pub struct Batcher {
+    root: PathBuf,
+    debounce: Duration,
+    max_delay: Duration,
+    dirty: BTreeSet<PathBuf>,
+    /// Rescan everything, whatever is in `dirty`
+    all: bool,
+    /// When the first and the latest event of the pending batch came
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Batcher {
+    pub fn new(root: impl Into<PathBuf>, debounce: Duration, max_delay: Duration) -> Self {
+        Self {
+            root: root.into(),
+            debounce,
+            max_delay,
+            dirty: BTreeSet::new(),
+            all: false,
+            first: None,
+            last: None,
+        }
+    }
+
+    /// Note what `event` touched
+    pub fn add(&mut self, event: &Event, now: Instant) {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        if event.need_rescan() || event.paths.is_empty() {
+            self.rescan_all(now);
+            return;
+        }
+        let mut touched = false;
+        for path in &event.paths {
+            // The root's own events come with events for what changed in it
+            match path.strip_prefix(&self.root) {
+                Ok(rel) if !rel.as_os_str().is_empty() => {
+                    self.dirty.insert(rel.to_path_buf());
+                    touched = true;
+                }
+                _ => {}
+            }
+        }
+        if touched {
+            self.touch(now);
+        }
+    }
+
+    /// Rescan the whole tree in the next batch (the watcher lost events or
+    /// failed)
+    pub fn rescan_all(&mut self, now: Instant) {
+        self.all = true;
+        self.touch(now);
+    }
+
+    /// Put back a batch whose sync failed, to go out with the next one
+    ///
+    /// Doesn't start the timer: a sync that keeps failing is retried when
+    /// something changes again, not in a loop.
+    pub fn requeue(&mut self, batch: Batch) {
+        match batch {
+            Batch::All => self.all = true,
+            Batch::Paths(paths) => self.dirty.extend(paths),
+        }
+    }
+
+    /// When the pending batch is due, if there is one
+    pub fn deadline(&self) -> Option<Instant> {
+        let (first, last) = (self.first?, self.last?);
+        Some((last + self.debounce).min(first + self.max_delay))
+    }
+
+    /// Take the pending batch, whether or not it's due
+    pub fn take(&mut self) -> Option<Batch> {
+        self.first.take()?;
+        self.last = None;
+        let dirty = std::mem::take(&mut self.dirty);
+        if std::mem::take(&mut self.all) {
+            return Some(Batch::All);
+        }
+        let paths = collapse(dirty);
+        if paths.len() > MAX_BATCH_PATHS {
+            return Some(Batch::All);
+        }
+        Some(Batch::Paths(paths))
+    }
+
+    fn touch(&mut self, now: Instant) {
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+}
+
+/// Drop the paths below another one in the set, which rescans them anyway
+fn collapse(dirty: BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::with_capacity(dirty.len());
+    for path in dirty {
+        // Paths sort by component, so what's below a directory comes right
+        // after it
+        if !paths.last().is_some_and(|kept| path.starts_with(kept)) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RemoveKind, RenameMode};
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        paths.iter().fold(Event::new(kind), |event, path| {
+            event.add_path(PathBuf::from(path))
+        })
+    }
+
+    #[test]
+    fn test_batcher_collapses_paths_below_directories() {
+        let now = Instant::now();
+        let mut batcher = Batcher::new("/src", Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(batcher.take(), None);
+
+        batcher.add(
+            &event(EventKind::Create(CreateKind::Folder), &["/src/a"]),
+            now,
+        );
+        batcher.add(
+            &event(EventKind::Create(CreateKind::File), &["/src/a/b/c.txt"]),
+            now,
+        );
+        batcher.add(
+            &event(EventKind::Create(CreateKind::File), &["/src/a.txt"]),
+            now,
+        );
+        batcher.add(
+            &event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &["/src/x/old", "/src/y/new"],
+            ),
+            now,
+        );
+        batcher.add(&event(EventKind::Remove(RemoveKind::File), &["/src"]), now);
+        batcher.add(
+            &event(EventKind::Access(AccessKind::Any), &["/src/read"]),
+            now,
+        );
+        batcher.add(
+            &event(EventKind::Modify(ModifyKind::Any), &["/elsewhere/f"]),
+            now,
+        );
+
+        assert_eq!(
+            batcher.take(),
+            Some(Batch::Paths(
+                ["a", "a.txt", "x/old", "y/new"].map(PathBuf::from).to_vec()
+            ))
+        );
+        assert_eq!(batcher.take(), None);
+    }
+
+    #[test]
+    fn test_batcher_debounces_up_to_max_delay() {
+        let start = Instant::now();
+        let debounce = Duration::from_millis(100);
+        let mut batcher = Batcher::new("/src", debounce, Duration::from_millis(250));
+        let modify = event(EventKind::Modify(ModifyKind::Any), &["/src/log"]);
+
+        assert_eq!(batcher.deadline(), None);
+        batcher.add(&modify, start);
+        assert_eq!(batcher.deadline(), Some(start + debounce));
+
+        // Each event pushes it back, but never past max_delay
+        batcher.add(&modify, start + Duration::from_millis(90));
+        assert_eq!(batcher.deadline(), Some(start + Duration::from_millis(190)));
+        batcher.add(&modify, start + Duration::from_millis(200));
+        assert_eq!(batcher.deadline(), Some(start + Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn test_batcher_falls_back_to_full_rescan() {
+        let now = Instant::now();
+        let mut batcher = Batcher::new("/src", Duration::ZERO, Duration::ZERO);
+
+        batcher.add(&event(EventKind::Other, &[]), now);
+        assert_eq!(batcher.take(), Some(Batch::All));
+
+        for i in 0..=MAX_BATCH_PATHS {
+            let path = format!("/src/f{}", i);
+            batcher.add(&event(EventKind::Create(CreateKind::File), &[&path]), now);
+        }
+        assert_eq!(batcher.take(), Some(Batch::All));
+
+        // A failed batch waits for the next change
+        batcher.requeue(Batch::Paths(vec![PathBuf::from("retry")]));
+        assert_eq!(batcher.deadline(), None);
+        batcher.add(
+            &event(EventKind::Create(CreateKind::File), &["/src/new"]),
+            now,
+        );
+        assert_eq!(
+            batcher.take(),
+            Some(Batch::Paths(vec![
+                PathBuf::from("new"),
+                PathBuf::from("retry")
+            ]))
+        );
+    }
+}
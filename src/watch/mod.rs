@@ -0,0 +1,174 @@
+//! Continuous sync over the streaming pipeline (`sy --watch` to a
+//! server-mode destination)
+//!
+//! A [`Watch`] runs one full sync, then follows notify events on the source
+//! and batches the paths they touch ([`Batcher`]). Each batch runs the same
+//! sync again with the batch as its scope, so the Generator rescans only
+//! those paths and deletes only below them. Batches go out once events have
+//! been quiet for the debounce delay, or after `max_delay` when they keep
+//! coming. Lost events (queue overflow, watcher errors) turn the next batch
+//! into a full rescan.
+
+mod batch;
+
+pub use batch::{Batch, Batcher};
+
+use anyhow::{Context as _, Result};
+use notify::{RecursiveMode, Watcher};
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// How long events have to stop before a batch is synced
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How long a batch waits at most while events keep coming
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Keeps a destination in sync with a local source as it changes
+pub struct Watch {
+    root: PathBuf,
+    debounce: Duration,
+    max_delay: Duration,
+    cancel: CancellationToken,
+}
+
+impl Watch {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            debounce: DEFAULT_DEBOUNCE,
+            max_delay: DEFAULT_MAX_DELAY,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Shorter delays than the defaults, so tests don't wait on them
+    #[cfg(test)]
+    pub fn with_debounce(mut self, debounce: Duration, max_delay: Duration) -> Self {
+        self.debounce = debounce;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Stop watching when `cancel` is cancelled (hand the same token to the
+    /// syncs, so one in progress stops too)
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Run `sync` for [`Batch::All`], then for every batch of changes until
+    /// cancelled
+    ///
+    /// The watcher starts before the first sync, so nothing that changes
+    /// during it is missed. A failed sync is logged and its batch is synced
+    /// again with the next change.
+    pub async fn run<F, Fut>(&self, mut sync: F) -> Result<()>
+    where
+        F: FnMut(Batch) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        // Events name paths the way the OS resolves them
+        let root = self
+            .root
+            .canonicalize()
+            .with_context(|| format!("Cannot watch {}", self.root.display()))?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver only goes away when we stop watching
+            let _ = tx.send(event);
+        })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        let mut batcher = Batcher::new(&root, self.debounce, self.max_delay);
+        let mut batch = Some(Batch::All);
+        loop {
+            if let Some(batch) = batch.take() {
+                if let Err(e) = sync(batch.clone()).await {
+                    tracing::error!("Sync failed: {:#}", e);
+                    batcher.requeue(batch);
+                }
+                if self.cancel.is_cancelled() {
+                    break;
+                }
+            }
+
+            let deadline = batcher.deadline();
+            tokio::select! {
+                _ = self.cancel.cancelled() => break,
+                event = rx.recv() => match event {
+                    Some(Ok(event)) => batcher.add(&event, Instant::now()),
+                    Some(Err(e)) => {
+                        tracing::warn!("Watch error, rescanning everything: {}", e);
+                        batcher.rescan_all(Instant::now());
+                    }
+                    None => anyhow::bail!("File watcher stopped"),
+                },
+                _ = sleep_until(deadline), if deadline.is_some() => batch = batcher.take(),
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_watch_syncs_batches_of_changes() {
+        let source = TempDir::new().unwrap();
+        std::fs::create_dir(source.path().join("dir")).unwrap();
+
+        let cancel = CancellationToken::new();
+        let watch = Watch::new(source.path())
+            .with_debounce(Duration::from_millis(50), Duration::from_secs(1))
+            .with_cancel(cancel.clone());
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&batches);
+        let stop = cancel.clone();
+        let run = watch.run(move |batch| {
+            let seen = Arc::clone(&seen);
+            let stop = stop.clone();
+            async move {
+                let mut seen = seen.lock().unwrap();
+                seen.push(batch);
+                if seen.len() == 2 {
+                    stop.cancel();
+                }
+                Ok(())
+            }
+        });
+
+        let changes = async {
+            // Give the initial sync time to run
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            std::fs::write(source.path().join("dir/a.txt"), "a").unwrap();
+            std::fs::write(source.path().join("dir/b.txt"), "b").unwrap();
+        };
+        let (result, ()) = tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::join!(run, changes)
+        })
+        .await
+        .expect("no batch for the changes");
+        result.unwrap();
+
+        let batches = batches.lock().unwrap();
+        assert_eq!(batches[0], Batch::All);
+        assert_eq!(
+            batches[1],
+            Batch::Paths(vec![PathBuf::from("dir/a.txt"), PathBuf::from("dir/b.txt")])
+        );
+    }
+}
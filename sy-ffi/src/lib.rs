@@ -0,0 +1,333 @@
+//! C interface to sy, for frontends (Electron, Python, Go, ...) that drive
+//! syncs in-process.
+//!
+//! A sync runs on a shared background runtime and is driven through an
+//! opaque handle:
+//!
+//! ```c
+//! SySync *sync = sy_sync_start("/data/photos/", "/backup/photos", SY_DELETE);
+//! SyProgress progress;
+//! while (sy_sync_poll(sync, &progress) == SY_RUNNING) {
+//!     printf("%llu/%llu files\n", progress.files_done, progress.files_total);
+//!     sleep(1);
+//! }
+//! if (sy_sync_poll(sync, NULL) == SY_FAILED) {
+//!     fprintf(stderr, "%s\n", sy_sync_error(sync));
+//! }
+//! sy_sync_free(sync);
+//! ```
+//!
+//! `include/sy.h` is generated from this file:
+//! `cbindgen --config cbindgen.toml --output include/sy.h`
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Delete destination files that aren't in the source (`--delete`)
+pub const SY_DELETE: u32 = 1 << 0;
+/// Plan the sync without changing anything (`--dry-run`)
+pub const SY_DRY_RUN: u32 = 1 << 1;
+/// Compare files by checksum instead of size and mtime (`--checksum`)
+pub const SY_CHECKSUM: u32 = 1 << 2;
+/// Read back and verify each file after writing it (`--verify`)
+pub const SY_VERIFY: u32 = 1 << 3;
+
+/// The sync is still running
+pub const SY_RUNNING: i32 = 0;
+/// The sync finished
+pub const SY_DONE: i32 = 1;
+/// The sync failed; see `sy_sync_error`
+pub const SY_FAILED: i32 = 2;
+/// The sync was stopped by `sy_sync_cancel`
+pub const SY_CANCELLED: i32 = 3;
+
+/// Overall progress of a sync
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyProgress {
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Opaque handle to a running or finished sync
+pub struct SySync {
+    shared: Arc<Shared>,
+    cancel: CancellationToken,
+    /// Taken by the first cancel, which waits for it
+    task: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    status: AtomicI32,
+    progress: Mutex<SyProgress>,
+    /// Set once, before the status becomes `SY_FAILED`
+    error: OnceLock<CString>,
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to start the sy runtime")
+    })
+}
+
+unsafe fn to_string(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok().map(str::to_string)
+}
+
+/// Start syncing `source` to `dest` in the background
+///
+/// Paths take the same forms as on the command line (`user@host:/path`, ...),
+/// with rsync trailing slash semantics. `flags` is a combination of the
+/// `SY_*` option flags. Returns NULL if either path is NULL or not UTF-8.
+///
+/// # Safety
+///
+/// `source` and `dest` must be NULL or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn sy_sync_start(
+    source: *const c_char,
+    dest: *const c_char,
+    flags: u32,
+) -> *mut SySync {
+    let (Some(source), Some(dest)) = (to_string(source), to_string(dest)) else {
+        return ptr::null_mut();
+    };
+
+    let shared = Arc::new(Shared::default());
+    let cancel = CancellationToken::new();
+    let progress = Arc::clone(&shared);
+    let builder = sy::Sync::builder()
+        .source(source)
+        .dest(dest)
+        .delete(flags & SY_DELETE != 0)
+        .dry_run(flags & SY_DRY_RUN != 0)
+        .checksum(flags & SY_CHECKSUM != 0)
+        .verify(flags & SY_VERIFY != 0)
+        .cancel(cancel.clone())
+        .on_progress(move |p| {
+            *progress.progress.lock().unwrap_or_else(|e| e.into_inner()) = SyProgress {
+                files_done: p.files_done,
+                files_total: p.files_total,
+                bytes_done: p.bytes_done,
+                bytes_total: p.bytes_total,
+            };
+        });
+
+    let done = Arc::clone(&shared);
+    let task = runtime().spawn(async move {
+        let status = match builder.run().await {
+            Ok(_) => SY_DONE,
+            Err(e) => {
+                let message = CString::new(e.to_string().replace('\0', " "))
+                    .expect("NUL bytes were replaced");
+                let _ = done.error.set(message);
+                SY_FAILED
+            }
+        };
+        // A cancel that got in first wins
+        let _ =
+            done.status
+                .compare_exchange(SY_RUNNING, status, Ordering::AcqRel, Ordering::Acquire);
+    });
+
+    Box::into_raw(Box::new(SySync {
+        shared,
+        cancel,
+        task: Some(task),
+    }))
+}
+
+/// Stop a running sync; files already written stay, partial ones are removed
+///
+/// Blocks until the sync has stopped. Must not be called from a callback of
+/// the sync itself.
+///
+/// # Safety
+///
+/// `sync` must be NULL or a handle from `sy_sync_start` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn sy_sync_cancel(sync: *mut SySync) {
+    let Some(sync) = sync.as_mut() else {
+        return;
+    };
+    if sync
+        .shared
+        .status
+        .compare_exchange(
+            SY_RUNNING,
+            SY_CANCELLED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_ok()
+    {
+        sync.cancel.cancel();
+        if let Some(task) = sync.task.take() {
+            let _ = runtime().block_on(task);
+        }
+    }
+}
+
+/// Current status (`SY_RUNNING`, `SY_DONE`, `SY_FAILED` or `SY_CANCELLED`),
+/// copying the latest progress into `progress` unless it is NULL
+///
+/// Returns `SY_FAILED` for a NULL handle.
+///
+/// # Safety
+///
+/// `sync` must be NULL or a live handle; `progress` must be NULL or point to
+/// writable memory for an `SyProgress`.
+#[no_mangle]
+pub unsafe extern "C" fn sy_sync_poll(sync: *const SySync, progress: *mut SyProgress) -> i32 {
+    let Some(sync) = sync.as_ref() else {
+        return SY_FAILED;
+    };
+    if let Some(out) = progress.as_mut() {
+        *out = *sync
+            .shared
+            .progress
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+    }
+    sync.shared.status.load(Ordering::Acquire)
+}
+
+/// Error message of a failed sync, or NULL
+///
+/// The string belongs to the handle and stays valid until `sy_sync_free`.
+///
+/// # Safety
+///
+/// `sync` must be NULL or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn sy_sync_error(sync: *const SySync) -> *const c_char {
+    match sync.as_ref().and_then(|sync| sync.shared.error.get()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Release a handle, cancelling the sync if it is still running
+///
+/// # Safety
+///
+/// `sync` must be NULL or a handle from `sy_sync_start`; it must not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sy_sync_free(sync: *mut SySync) {
+    if sync.is_null() {
+        return;
+    }
+    sy_sync_cancel(sync);
+    drop(Box::from_raw(sync));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    fn wait(sync: *mut SySync) -> (i32, SyProgress) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut progress = SyProgress::default();
+        loop {
+            let status = unsafe { sy_sync_poll(sync, &mut progress) };
+            if status != SY_RUNNING || Instant::now() > deadline {
+                return (status, progress);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_sync_start_poll_free() {
+        let src = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        std::fs::write(src.path().join("a.txt"), "alpha").unwrap();
+        std::fs::write(src.path().join("b.txt"), "beta").unwrap();
+
+        let source = CString::new(format!("{}/", src.path().display())).unwrap();
+        let target = CString::new(dest.path().display().to_string()).unwrap();
+        let sync = unsafe { sy_sync_start(source.as_ptr(), target.as_ptr(), 0) };
+        assert!(!sync.is_null());
+
+        let (status, progress) = wait(sync);
+        assert_eq!(status, SY_DONE);
+        assert_eq!(progress.files_done, progress.files_total);
+        assert_eq!(progress.bytes_done, 9);
+        assert!(unsafe { sy_sync_error(sync) }.is_null());
+        assert_eq!(
+            std::fs::read_to_string(dest.path().join("b.txt")).unwrap(),
+            "beta"
+        );
+
+        // Cancelling a finished sync changes nothing
+        unsafe { sy_sync_cancel(sync) };
+        assert_eq!(unsafe { sy_sync_poll(sync, ptr::null_mut()) }, SY_DONE);
+        unsafe { sy_sync_free(sync) };
+    }
+
+    #[test]
+    fn test_sync_cancel_waits_for_the_sync() {
+        let src = TempDir::new().unwrap();
+        let dest = TempDir::new().unwrap();
+        for i in 0..200 {
+            std::fs::write(src.path().join(format!("{}.txt", i)), "data").unwrap();
+        }
+
+        let source = CString::new(format!("{}/", src.path().display())).unwrap();
+        let target = CString::new(dest.path().display().to_string()).unwrap();
+        let sync = unsafe { sy_sync_start(source.as_ptr(), target.as_ptr(), 0) };
+        unsafe { sy_sync_cancel(sync) };
+
+        let status = unsafe { sy_sync_poll(sync, ptr::null_mut()) };
+        assert!(status == SY_CANCELLED || status == SY_DONE);
+        // Nothing is left running to write after the cancel returned
+        let written = std::fs::read_dir(dest.path()).unwrap().count();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(std::fs::read_dir(dest.path()).unwrap().count(), written);
+        unsafe { sy_sync_free(sync) };
+    }
+
+    #[test]
+    fn test_sync_failure_reports_error() {
+        let dest = TempDir::new().unwrap();
+        let source = CString::new("/nonexistent/sy-ffi-source/").unwrap();
+        let target = CString::new(dest.path().display().to_string()).unwrap();
+        let sync = unsafe { sy_sync_start(source.as_ptr(), target.as_ptr(), SY_DELETE) };
+
+        let (status, _) = wait(sync);
+        assert_eq!(status, SY_FAILED);
+        let message = unsafe { CStr::from_ptr(sy_sync_error(sync)) };
+        assert!(!message.to_bytes().is_empty());
+        unsafe { sy_sync_free(sync) };
+    }
+
+    #[test]
+    fn test_null_arguments() {
+        let dest = CString::new("/tmp").unwrap();
+        unsafe {
+            assert!(sy_sync_start(ptr::null(), dest.as_ptr(), 0).is_null());
+            assert_eq!(sy_sync_poll(ptr::null(), ptr::null_mut()), SY_FAILED);
+            assert!(sy_sync_error(ptr::null()).is_null());
+            sy_sync_cancel(ptr::null_mut());
+            sy_sync_free(ptr::null_mut());
+        }
+    }
+}
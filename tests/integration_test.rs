@@ -679,3 +679,45 @@ fn test_directory_cache_updates_on_new_directories() {
         updated_count
     );
 }
+
+#[test]
+fn test_history_env_override() {
+    let source = TempDir::new().unwrap();
+    let dest = TempDir::new().unwrap();
+    let state = TempDir::new().unwrap();
+    let history = state.path().join("history.jsonl");
+    fs::write(source.path().join("file.txt"), "content").unwrap();
+
+    let output = Command::new(sy_bin())
+        .env("SY_HISTORY", &history)
+        .args([
+            &format!("{}/", source.path().display()),
+            dest.path().to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let output = Command::new(sy_bin())
+        .env("SY_HISTORY", &history)
+        .args(["history", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let runs: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(runs.as_array().unwrap().len(), 1);
+    assert_eq!(runs[0]["status"], "ok");
+    assert_eq!(runs[0]["files_created"], 1);
+
+    // Empty turns recording off
+    let output = Command::new(sy_bin())
+        .env("SY_HISTORY", "")
+        .args([
+            &format!("{}/", source.path().display()),
+            dest.path().to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&history).unwrap().lines().count(), 1);
+}
@@ -36,6 +36,7 @@ impl MockRemoteTransport {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             },
             FileEntry {
                 path: Arc::new(PathBuf::from("/remote/utimer/Cargo.toml")),
@@ -52,6 +53,7 @@ impl MockRemoteTransport {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             },
             FileEntry {
                 path: Arc::new(PathBuf::from("/remote/utimer/src/main.rs")),
@@ -68,6 +70,7 @@ impl MockRemoteTransport {
                 nlink: 1,
                 acls: None,
                 bsd_flags: None,
+                win_attrs: None,
             },
         ];
 
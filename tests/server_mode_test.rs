@@ -1,9 +1,13 @@
 #[cfg(test)]
 mod tests {
     use std::fs;
+    use sy::config::SshSettings;
+    use sy::filter::FilterEngine;
     use sy::path::SyncPath;
-    use sy::sync::server_mode::{sync_pull, sync_push};
+    use sy::streaming::{IoOptions, OwnerMap, ReceivePolicy};
+    use sy::sync::server_mode::{estimate_push, sync_local, sync_pull, sync_push};
     use tempfile::TempDir;
+    use tokio_util::sync::CancellationToken;
 
     #[tokio::test]
     async fn test_server_mode_push_local() -> anyhow::Result<()> {
@@ -45,7 +49,23 @@ mod tests {
             has_trailing_slash: false,
         };
 
-        sync_push(&source, &dest_sync_path, false, false).await?;
+        sync_push(
+            &source,
+            &dest_sync_path,
+            false,
+            false,
+            IoOptions::default(),
+            FilterEngine::new(),
+            OwnerMap::default(),
+            None,
+            None,
+            &SshSettings::default(),
+            false,
+            None,
+            None,
+            CancellationToken::new(),
+        )
+        .await?;
 
         // Verify
         assert!(dest.join("file1.txt").exists());
@@ -96,7 +116,23 @@ mod tests {
             has_trailing_slash: false,
         };
 
-        sync_pull(&source_sync_path, &dest, false, false).await?;
+        sync_pull(
+            &source_sync_path,
+            &dest,
+            false,
+            false,
+            IoOptions::default(),
+            FilterEngine::new(),
+            OwnerMap::default(),
+            None,
+            None,
+            &SshSettings::default(),
+            None,
+            ReceivePolicy::default(),
+            None,
+            CancellationToken::new(),
+        )
+        .await?;
 
         // Verify
         assert!(dest.join("file1.txt").exists());
@@ -117,4 +153,220 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_streaming_local_in_process() -> anyhow::Result<()> {
+        let temp = TempDir::new()?;
+        let source = temp.path().join("src");
+        let dest = temp.path().join("dest");
+
+        fs::create_dir_all(source.join("subdir"))?;
+        fs::create_dir(&dest)?;
+
+        // New files take the copy_file_range path
+        let big: Vec<u8> = (0..1_000_000u32).map(|i| (i % 253) as u8).collect();
+        fs::write(source.join("big.bin"), &big)?;
+        fs::write(source.join("subdir/small.txt"), "Nested file")?;
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        fs::write(source.join("noise.bin"), &noise)?;
+
+        // An existing, changed file goes through delta frames
+        let mut changed = big.clone();
+        changed[500_000..500_100].fill(0xAA);
+        fs::write(source.join("changed.bin"), &changed)?;
+        fs::write(dest.join("changed.bin"), &big)?;
+        filetime::set_file_mtime(
+            dest.join("changed.bin"),
+            filetime::FileTime::from_unix_time(1_000_000_000, 0),
+        )?;
+
+        fs::write(dest.join("stale.txt"), "delete me")?;
+
+        let stats = sync_local(&source, &dest, true, IoOptions::default(), None).await?;
+
+        assert_eq!(fs::read(dest.join("big.bin"))?, big);
+        assert_eq!(
+            fs::read_to_string(dest.join("subdir/small.txt"))?,
+            "Nested file"
+        );
+        assert_eq!(fs::read(dest.join("changed.bin"))?, changed);
+        assert!(!dest.join("stale.txt").exists());
+        assert_eq!(stats.files_created, 3);
+        assert_eq!(stats.files_updated, 1);
+        assert_eq!(stats.files_deleted, 1);
+
+        // Phases follow each other, so they fit in the run's wall-clock time
+        let phases = stats.phases.expect("streaming syncs time their phases");
+        assert!(stats.duration > std::time::Duration::ZERO);
+        assert!(phases.scan + phases.exchange + phases.transfer + phases.delete <= stats.duration);
+
+        // Touching a file only brings its new mtime over; the rest is skipped
+        filetime::set_file_mtime(
+            source.join("noise.bin"),
+            filetime::FileTime::from_unix_time(1_500_000_000, 0),
+        )?;
+        let stats = sync_local(&source, &dest, true, IoOptions::default(), None).await?;
+        assert_eq!(fs::read(dest.join("noise.bin"))?, noise);
+        assert_eq!(
+            (
+                stats.files_created,
+                stats.files_updated,
+                stats.files_touched
+            ),
+            (0, 0, 1)
+        );
+        assert_eq!(stats.files_skipped, 3);
+
+        Ok(())
+    }
+
+    /// Push `source` into a `sy --server --sandbox dest` child process
+    #[cfg(target_os = "linux")]
+    async fn push_to_sandboxed_server(
+        source: &std::path::Path,
+        dest: &std::path::Path,
+    ) -> anyhow::Result<()> {
+        use std::process::Stdio;
+        use sy::streaming::StreamingSync;
+
+        let mut child = tokio::process::Command::new(env!("CARGO_BIN_EXE_sy"))
+            .args(["--server", "--sandbox"])
+            .arg(dest)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        let result = StreamingSync::new(source.to_path_buf(), dest.to_path_buf(), false, false)
+            .push(&mut stdout, &mut stdin)
+            .await;
+        drop(stdin);
+        child.wait().await?;
+        Ok(result.map(|_| ())?)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_sandboxed_server_push() -> anyhow::Result<()> {
+        let temp = TempDir::new()?;
+        let source = temp.path().join("src");
+        let dest = temp.path().join("dest");
+        let outside = temp.path().join("outside");
+        fs::create_dir_all(source.join("sub"))?;
+        fs::create_dir(&outside)?;
+        fs::write(source.join("file.txt"), "inside")?;
+        fs::write(source.join("sub/nested.txt"), "nested")?;
+
+        // The allowlisted syscalls cover a whole session
+        push_to_sandboxed_server(&source, &dest).await?;
+        assert_eq!(fs::read_to_string(dest.join("file.txt"))?, "inside");
+        assert_eq!(fs::read_to_string(dest.join("sub/nested.txt"))?, "nested");
+
+        // A symlink already in the root must not lead the server out of it
+        std::os::unix::fs::symlink(&outside, dest.join("escape"))?;
+        fs::create_dir(source.join("escape"))?;
+        fs::write(source.join("escape/planted.txt"), "outside")?;
+        let result = push_to_sandboxed_server(&source, &dest).await;
+
+        let landlock =
+            unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, 0usize, 0usize, 1u32) > 0 };
+        if landlock {
+            assert!(result.is_err());
+            assert!(!outside.join("planted.txt").exists());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_mode_estimate_push() -> anyhow::Result<()> {
+        let temp = TempDir::new()?;
+        let source = temp.path().join("src");
+        let dest = temp.path().join("dest");
+        fs::create_dir(&source)?;
+        fs::create_dir(&dest)?;
+
+        fs::write(source.join("new.txt"), "brand new")?;
+        fs::write(source.join("changed.txt"), "longer than before")?;
+        fs::write(dest.join("changed.txt"), "short")?;
+        fs::write(dest.join("stale.txt"), "gone from source")?;
+
+        let sy_bin = std::env::current_exe()?
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("sy");
+        if !sy_bin.exists() {
+            eprintln!("Skipping test: sy binary not found at {}", sy_bin.display());
+            return Ok(());
+        }
+        let path_env = std::env::var("PATH").unwrap_or_default();
+        std::env::set_var(
+            "PATH",
+            format!("{}:{}", sy_bin.parent().unwrap().display(), path_env),
+        );
+
+        let dest_sync_path = SyncPath::Local {
+            path: dest.clone(),
+            has_trailing_slash: false,
+        };
+        let estimate = estimate_push(
+            &source,
+            &dest_sync_path,
+            true,
+            IoOptions::default(),
+            FilterEngine::new(),
+            &SshSettings::default(),
+        )
+        .await?;
+
+        assert_eq!(estimate.files_new, 1);
+        assert_eq!(estimate.bytes_new, 9);
+        assert_eq!(estimate.files_changed, 1);
+        assert_eq!(estimate.bytes_changed, 18);
+        assert_eq!(estimate.files_deleted, 1);
+
+        // Nothing was sent
+        assert!(!dest.join("new.txt").exists());
+        assert_eq!(fs::read_to_string(dest.join("changed.txt"))?, "short");
+        assert!(dest.join("stale.txt").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_streaming_local_cancelled_before_start() -> anyhow::Result<()> {
+        use sy::streaming::StreamingSync;
+
+        let temp = TempDir::new()?;
+        let source = temp.path().join("src");
+        let dest = temp.path().join("dest");
+        fs::create_dir(&source)?;
+        fs::create_dir(&dest)?;
+        fs::write(source.join("file.txt"), "never copied")?;
+        fs::write(dest.join("stale.txt"), "kept")?;
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let stats = StreamingSync::new(source, dest.clone(), true, false)
+            .with_cancel(cancel)
+            .local()
+            .await?;
+
+        assert!(stats.cancelled);
+        assert!(!dest.join("file.txt").exists());
+        // Deletes never run for a cancelled sync
+        assert!(dest.join("stale.txt").exists());
+
+        Ok(())
+    }
 }
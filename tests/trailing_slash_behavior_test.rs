@@ -6,7 +6,7 @@
 
 use std::path::PathBuf;
 
-/// Helper function that mirrors the logic from compute_destination_path in main.rs
+/// Helper function that mirrors the logic of SyncPath::effective_destination
 /// This duplication is intentional to document the expected behavior independently
 fn compute_test_destination(source: &sy::path::SyncPath, dest: &sy::path::SyncPath) -> PathBuf {
     let source_path = source.path();